@@ -0,0 +1,4 @@
+<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.8" tiledversion="1.8.0" name="quit_button" tilewidth="394" tileheight="145" tilecount="1" columns="1">
+ <image source="quit_button.png" width="394" height="145"/>
+</tileset>
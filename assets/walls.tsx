@@ -0,0 +1,24 @@
+<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.8" tiledversion="1.8.0" name="walls" tilewidth="64" tileheight="64" tilecount="4" columns="4">
+ <image source="walls.png" width="256" height="64"/>
+ <tile id="0">
+  <properties>
+   <property name="texture" type="int" value="0"/>
+  </properties>
+ </tile>
+ <tile id="1">
+  <properties>
+   <property name="texture" type="int" value="1"/>
+  </properties>
+ </tile>
+ <tile id="2">
+  <properties>
+   <property name="texture" type="int" value="2"/>
+  </properties>
+ </tile>
+ <tile id="3">
+  <properties>
+   <property name="texture" type="int" value="3"/>
+  </properties>
+ </tile>
+</tileset>
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::hash::Hasher;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use crate::billboard::Billboard;
+use crate::constants::FRAME_RATE;
+use crate::replay::StateHash;
+
+/// How far a dash carries its user each frame it lasts, in tiles.
+pub const DASH_SPEED: f32 = 0.25;
+/// How many frames a dash lasts.
+const DASH_FRAMES: u32 = 8;
+/// How many frames after a dash starts before another one can.
+const DASH_COOLDOWN_FRAMES: u32 = 2 * FRAME_RATE;
+/// How close the player has to get to a pickup to pick it up, in tiles.
+const PICKUP_RADIUS: f32 = 0.5;
+
+/// A movement ability the player has to find and pick up before they can use it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ability {
+    Dash,
+}
+
+impl FromStr for Ability {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "dash" => Ability::Dash,
+            _ => bail!("invalid ability: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for Ability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Ability::Dash => "dash",
+        })
+    }
+}
+
+/// The abilities the player has picked up.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Abilities {
+    unlocked: Vec<Ability>,
+}
+
+impl Abilities {
+    /// Unlocks an ability, returning false if it was already unlocked.
+    pub fn unlock(&mut self, ability: Ability) -> bool {
+        if self.has(ability) {
+            return false;
+        }
+        self.unlocked.push(ability);
+        true
+    }
+
+    pub fn has(&self, ability: Ability) -> bool {
+        self.unlocked.contains(&ability)
+    }
+
+    /// Adds the unlocked abilities, in the order they were unlocked, to a replay's state hash.
+    pub fn hash_state(&self, hasher: &mut dyn Hasher) {
+        hasher.hash_usize(self.unlocked.len());
+        for ability in self.unlocked.iter() {
+            hasher.hash_u32(*ability as u32);
+        }
+    }
+}
+
+/// A short burst of speed in the direction its user is facing, which has to cool down before
+/// it can be used again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Dash {
+    /// How many more frames the current dash moves its user.
+    frames_left: u32,
+    /// How many more frames until another dash can start.
+    cooldown: u32,
+}
+
+impl Dash {
+    /// Starts a dash, returning false if the last one hasn't cooled down yet.
+    pub fn start(&mut self) -> bool {
+        if !self.is_ready() {
+            return false;
+        }
+        self.frames_left = DASH_FRAMES;
+        self.cooldown = DASH_COOLDOWN_FRAMES;
+        true
+    }
+
+    /// Advances a frame, returning whether the dash moves its user this frame.
+    pub fn update(&mut self) -> bool {
+        self.cooldown = self.cooldown.saturating_sub(1);
+        if self.frames_left == 0 {
+            return false;
+        }
+        self.frames_left -= 1;
+        true
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.cooldown == 0
+    }
+
+    /// Adds how far along the dash and its cooldown are to a replay's state hash.
+    pub fn hash_state(&self, hasher: &mut dyn Hasher) {
+        hasher.hash_u32(self.frames_left);
+        hasher.hash_u32(self.cooldown);
+    }
+
+    /// How much of the cooldown is left, from 1 right after a dash starts to 0 once it's ready.
+    pub fn cooldown_fraction(&self) -> f32 {
+        self.cooldown as f32 / DASH_COOLDOWN_FRAMES as f32
+    }
+}
+
+/// What the player gets from a pickup.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PickupKind {
+    Ability(Ability),
+    /// An item for the inventory, by its name in the items file.
+    Item(String),
+}
+
+impl fmt::Display for PickupKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PickupKind::Ability(ability) => ability.fmt(f),
+            PickupKind::Item(name) => f.write_str(name),
+        }
+    }
+}
+
+/// An ability or item lying in a level, which the player picks up by walking into it.
+#[derive(Debug, Clone)]
+pub struct Pickup {
+    pub kind: PickupKind,
+    pub billboard: Billboard,
+}
+
+impl Pickup {
+    /// Whether someone at a point on a floor, in tiles, is close enough to pick it up.
+    pub fn reached_by(&self, floor: usize, x: f32, y: f32) -> bool {
+        let (dx, dy) = (x - self.billboard.x, y - self.billboard.y);
+        floor == self.billboard.floor && dx * dx + dy * dy <= PICKUP_RADIUS * PICKUP_RADIUS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dash_cooldown() {
+        let mut dash = Dash::default();
+        assert!(dash.is_ready());
+        assert!(dash.start());
+        assert_eq!(dash.cooldown_fraction(), 1.0);
+
+        let moving = (0..DASH_COOLDOWN_FRAMES).filter(|_| dash.update()).count();
+        assert_eq!(moving as u32, DASH_FRAMES);
+        assert!(dash.is_ready());
+        assert!(!dash.update());
+
+        // A dash can't start again until it's cooled down.
+        assert!(dash.start());
+        dash.update();
+        assert!(!dash.start());
+        assert!(dash.cooldown_fraction() > 0.0 && dash.cooldown_fraction() < 1.0);
+    }
+
+    #[test]
+    fn unlock() {
+        let mut abilities = Abilities::default();
+        assert!(!abilities.has(Ability::Dash));
+        assert!(abilities.unlock("dash".parse().unwrap()));
+        assert!(!abilities.unlock(Ability::Dash));
+        assert!(abilities.has(Ability::Dash));
+        assert_eq!(Ability::Dash.to_string(), "dash");
+        assert!("grapple".parse::<Ability>().is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use std::f32::consts::{PI, TAU};
+
+use crate::cvars::Cvars;
+
+/// How much shots bend toward enemies, from 0 for not at all to 1 for the most.
+pub const AIM_ASSIST_CVAR: &str = "aim_assist";
+
+/// How far off, in radians, a shot can be and still go at an enemy, at full strength.
+const MAX_ASSIST_ANGLE: f32 = 0.15;
+
+/// Bends shots toward nearby enemies, for players who have trouble aiming precisely.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AimAssist {
+    strength: f32,
+}
+
+impl AimAssist {
+    pub fn new(strength: f32) -> AimAssist {
+        AimAssist {
+            strength: strength.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn from_cvars(cvars: &Cvars) -> AimAssist {
+        AimAssist::new(cvars.get_parsed(AIM_ASSIST_CVAR).unwrap_or(0.0))
+    }
+
+    /// How far off, in radians, a shot can be and still go at an enemy.
+    pub fn cone(&self) -> f32 {
+        MAX_ASSIST_ANGLE * self.strength
+    }
+
+    /// The direction to fire in, when aiming at angle from a point. If any of the targets is
+    /// within the cone, it's the direction to whichever one is closest to the aim.
+    pub fn aim(
+        &self,
+        from: (f32, f32),
+        angle: f32,
+        targets: impl IntoIterator<Item = (f32, f32)>,
+    ) -> f32 {
+        let cone = self.cone();
+        if cone <= 0.0 {
+            return angle;
+        }
+        targets
+            .into_iter()
+            .map(|(x, y)| (y - from.1).atan2(x - from.0).rem_euclid(TAU))
+            .map(|target| (target, (target - angle + PI).rem_euclid(TAU) - PI))
+            .filter(|(_, off)| off.abs() <= cone)
+            .min_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+            .map_or(angle, |(target, _)| target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapping() {
+        let targets = [(10.0, 1.0), (10.0, -0.5), (0.0, 10.0)];
+        assert_eq!(AimAssist::default().aim((0.0, 0.0), 0.0, targets), 0.0);
+
+        // Only the closest target in the cone counts, even across angle 0.
+        let assist = AimAssist::new(1.0);
+        let aimed = assist.aim((0.0, 0.0), 0.0, targets);
+        assert!((aimed - (-0.5f32).atan2(10.0).rem_euclid(TAU)).abs() < 1e-5);
+
+        // A weaker assist has a narrower cone.
+        let weak = AimAssist::new(0.2);
+        assert_eq!(weak.aim((0.0, 0.0), 0.0, [(10.0, 1.0)]), 0.0);
+    }
+}
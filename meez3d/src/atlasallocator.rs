@@ -0,0 +1,205 @@
+use crate::geometry::Rect;
+
+/// Whether two rects share any pixels, unlike Rect::intersects, which counts touching edges.
+fn overlaps(a: Rect<i32>, b: Rect<i32>) -> bool {
+    a.x < b.right() && b.x < a.right() && a.y < b.bottom() && b.y < a.bottom()
+}
+
+fn contains(outer: Rect<i32>, inner: Rect<i32>) -> bool {
+    inner.x >= outer.x
+        && inner.y >= outer.y
+        && inner.right() <= outer.right()
+        && inner.bottom() <= outer.bottom()
+}
+
+/// Keeps track of the unused space in a texture atlas, so regions can be added at runtime.
+///
+/// The free space is a list of the largest rects that fit in it, which may overlap each other.
+/// Released regions are merged back with free rects they line up with exactly, so space can
+/// fragment if regions of many different sizes come and go.
+#[derive(Debug, Clone)]
+pub struct AtlasAllocator {
+    free: Vec<Rect<i32>>,
+}
+
+impl AtlasAllocator {
+    pub fn new(width: i32, height: i32) -> AtlasAllocator {
+        AtlasAllocator {
+            free: vec![Rect {
+                x: 0,
+                y: 0,
+                w: width,
+                h: height,
+            }],
+        }
+    }
+
+    /// Marks an area as used, e.g. by an image that was packed into the atlas ahead of time.
+    pub fn reserve(&mut self, area: Rect<i32>) {
+        if area.w <= 0 || area.h <= 0 {
+            return;
+        }
+        let mut free = Vec::with_capacity(self.free.len() + 4);
+        for rect in self.free.drain(..) {
+            if !overlaps(rect, area) {
+                free.push(rect);
+                continue;
+            }
+            // Keep the parts of the rect on each side of the area.
+            if area.x > rect.x {
+                free.push(Rect {
+                    w: area.x - rect.x,
+                    ..rect
+                });
+            }
+            if area.right() < rect.right() {
+                free.push(Rect {
+                    x: area.right(),
+                    w: rect.right() - area.right(),
+                    ..rect
+                });
+            }
+            if area.y > rect.y {
+                free.push(Rect {
+                    h: area.y - rect.y,
+                    ..rect
+                });
+            }
+            if area.bottom() < rect.bottom() {
+                free.push(Rect {
+                    y: area.bottom(),
+                    h: rect.bottom() - area.bottom(),
+                    ..rect
+                });
+            }
+        }
+        self.free = free;
+        self.prune();
+    }
+
+    /// Finds room for a region of the given size, in the free rect it fits most snugly.
+    pub fn allocate(&mut self, width: i32, height: i32) -> Option<Rect<i32>> {
+        if width <= 0 || height <= 0 {
+            return None;
+        }
+        let best = self
+            .free
+            .iter()
+            .filter(|rect| rect.w >= width && rect.h >= height)
+            .min_by_key(|rect| ((rect.w - width).min(rect.h - height), rect.y, rect.x))?;
+        let area = Rect {
+            x: best.x,
+            y: best.y,
+            w: width,
+            h: height,
+        };
+        self.reserve(area);
+        Some(area)
+    }
+
+    /// Returns a region from allocate to the free space.
+    pub fn release(&mut self, area: Rect<i32>) {
+        if area.w <= 0 || area.h <= 0 {
+            return;
+        }
+        self.free.push(area);
+        self.merge();
+        self.prune();
+    }
+
+    /// The free rects, for debugging.
+    pub fn free_rects(&self) -> &[Rect<i32>] {
+        &self.free
+    }
+
+    /// Combines free rects that line up exactly along a shared edge, until none do.
+    fn merge(&mut self) {
+        'restart: loop {
+            for i in 0..self.free.len() {
+                for j in 0..self.free.len() {
+                    let (a, b) = (self.free[i], self.free[j]);
+                    if i == j {
+                        continue;
+                    }
+                    let merged = if a.x == b.x && a.w == b.w && a.bottom() == b.y {
+                        Rect { h: a.h + b.h, ..a }
+                    } else if a.y == b.y && a.h == b.h && a.right() == b.x {
+                        Rect { w: a.w + b.w, ..a }
+                    } else {
+                        continue;
+                    };
+                    self.free[i] = merged;
+                    self.free.swap_remove(j);
+                    continue 'restart;
+                }
+            }
+            return;
+        }
+    }
+
+    /// Removes free rects that are inside other ones, since they'd never be the better choice.
+    fn prune(&mut self) {
+        let mut i = 0;
+        while i < self.free.len() {
+            let rect = self.free[i];
+            let redundant =
+                self.free.iter().enumerate().any(|(j, other)| {
+                    j != i && contains(*other, rect) && (rect != *other || j < i)
+                });
+            if redundant {
+                self.free.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocate_and_release() {
+        let mut atlas = AtlasAllocator::new(64, 64);
+        // The left half is already taken by images from the atlas index.
+        atlas.reserve(Rect {
+            x: 0,
+            y: 0,
+            w: 32,
+            h: 64,
+        });
+
+        let mut regions = Vec::new();
+        for _ in 0..8 {
+            regions.push(atlas.allocate(16, 16).unwrap());
+        }
+        assert!(atlas.allocate(16, 16).is_none());
+        assert!(atlas.allocate(1, 1).is_none());
+        for (i, a) in regions.iter().enumerate() {
+            assert!(a.x >= 32 && a.right() <= 64 && a.y >= 0 && a.bottom() <= 64);
+            for b in regions[i + 1..].iter() {
+                assert!(!overlaps(*a, *b));
+            }
+        }
+
+        // Releasing two neighbours leaves room for something bigger than either.
+        let mut released: Vec<Rect<i32>> = regions.iter().copied().filter(|r| r.y == 0).collect();
+        released.sort_by_key(|r| r.x);
+        for region in released {
+            atlas.release(region);
+        }
+        let wide = atlas.allocate(32, 16).unwrap();
+        assert_eq!(
+            wide,
+            Rect {
+                x: 32,
+                y: 0,
+                w: 32,
+                h: 16
+            }
+        );
+
+        assert!(AtlasAllocator::new(8, 8).allocate(16, 4).is_none());
+    }
+}
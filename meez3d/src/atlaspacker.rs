@@ -0,0 +1,217 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use image::{imageops, RgbaImage};
+
+use crate::geometry::Rect;
+
+/// A texture made by pack_images, and where each image ended up in it.
+pub struct PackedAtlas {
+    pub image: RgbaImage,
+    pub entries: Vec<(PathBuf, Rect<i32>)>,
+}
+
+/// Where pack_rects put everything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packing {
+    /// How wide every page is.
+    pub width: i32,
+    /// How much of each page is used, from the top.
+    pub page_heights: Vec<i32>,
+    /// For each rect, in the order they were given, which page it's on and where.
+    pub placements: Vec<(usize, Rect<i32>)>,
+}
+
+/// A row across a page, as tall as the first rect put in it.
+struct Shelf {
+    page: usize,
+    y: i32,
+    height: i32,
+    /// How far across the row is filled.
+    used: i32,
+}
+
+/// Packs rects onto pages width across and at most max_height down, returning the shelves and
+/// where each rect went.
+///
+/// The tallest rects go first, each in the first shelf with room for it, so shorter ones fill
+/// in the gaps at the ends of taller shelves.
+fn pack_shelves(
+    sizes: &[(i32, i32)],
+    width: i32,
+    max_height: i32,
+) -> (Vec<Shelf>, Vec<(usize, Rect<i32>)>) {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| (-sizes[i].1, -sizes[i].0));
+
+    let mut shelves: Vec<Shelf> = Vec::new();
+    let empty = Rect {
+        x: 0,
+        y: 0,
+        w: 0,
+        h: 0,
+    };
+    let mut placements = vec![(0, empty); sizes.len()];
+    for i in order {
+        let (w, h) = sizes[i];
+        let fits = |shelf: &&mut Shelf| shelf.used + w <= width && h <= shelf.height;
+        let shelf = match shelves.iter_mut().find(fits) {
+            Some(shelf) => shelf,
+            None => {
+                let (page, y) = match shelves.last() {
+                    Some(last) if last.y + last.height + h <= max_height => {
+                        (last.page, last.y + last.height)
+                    }
+                    Some(last) => (last.page + 1, 0),
+                    None => (0, 0),
+                };
+                shelves.push(Shelf {
+                    page,
+                    y,
+                    height: h,
+                    used: 0,
+                });
+                shelves.last_mut().unwrap()
+            }
+        };
+        placements[i] = (
+            shelf.page,
+            Rect {
+                x: shelf.used,
+                y: shelf.y,
+                w,
+                h,
+            },
+        );
+        shelf.used += w;
+    }
+    (shelves, placements)
+}
+
+/// Arranges rects of the given sizes onto as few pages as it can, none more than max_size
+/// across or down.
+///
+/// Pages are as narrow as they can be, in powers of two, while still fitting everything on one.
+/// If nothing narrower than max_size does, everything is spread across pages max_size wide.
+pub fn pack_rects(sizes: &[(i32, i32)], max_size: i32) -> Result<Packing> {
+    for &(w, h) in sizes {
+        if w <= 0 || h <= 0 || w > max_size || h > max_size {
+            bail!(
+                "invalid size to fit in a {}x{} atlas: {}x{}",
+                max_size,
+                max_size,
+                w,
+                h
+            );
+        }
+    }
+    let widest = sizes.iter().map(|&(w, _)| w).max().unwrap_or(1);
+    let mut width = (widest as u32).next_power_of_two().min(max_size as u32) as i32;
+    loop {
+        let (shelves, placements) = pack_shelves(sizes, width, max_size);
+        let pages = shelves.last().map_or(0, |shelf| shelf.page + 1);
+        if pages <= 1 || width == max_size {
+            let mut page_heights = vec![0; pages];
+            for shelf in shelves.iter() {
+                page_heights[shelf.page] = shelf.y + shelf.height;
+            }
+            return Ok(Packing {
+                width,
+                page_heights,
+                placements,
+            });
+        }
+        width = (width * 2).min(max_size);
+    }
+}
+
+/// Packs images into as few atlases as it can, none more than max_size across or down.
+pub fn pack_images(images: Vec<(PathBuf, RgbaImage)>, max_size: i32) -> Result<Vec<PackedAtlas>> {
+    let sizes: Vec<(i32, i32)> = images
+        .iter()
+        .map(|(_, image)| (image.width() as i32, image.height() as i32))
+        .collect();
+    let packing = pack_rects(&sizes, max_size)?;
+    let mut atlases: Vec<PackedAtlas> = packing
+        .page_heights
+        .iter()
+        .map(|&height| PackedAtlas {
+            image: RgbaImage::new(packing.width as u32, height as u32),
+            entries: Vec::new(),
+        })
+        .collect();
+    for ((path, image), (page, area)) in images.into_iter().zip(packing.placements) {
+        let atlas = &mut atlases[page];
+        imageops::replace(&mut atlas.image, &image, area.x as i64, area.y as i64);
+        atlas.entries.push((path, area));
+    }
+    Ok(atlases)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    fn overlaps(a: Rect<i32>, b: Rect<i32>) -> bool {
+        a.x < b.right() && b.x < a.right() && a.y < b.bottom() && b.y < a.bottom()
+    }
+
+    #[test]
+    fn shelves() {
+        let sizes = [(100, 60), (20, 20), (30, 60), (50, 40), (64, 10)];
+        let packing = pack_rects(&sizes, 256).unwrap();
+        // The widest rect needs 128 across, and everything fits in that.
+        assert_eq!(packing.width, 128);
+        assert_eq!(packing.page_heights, [130]);
+        let areas: Vec<Rect<i32>> = packing.placements.iter().map(|(_, area)| *area).collect();
+        for (i, a) in areas.iter().enumerate() {
+            assert_eq!((a.w, a.h), sizes[i]);
+            assert!(a.x >= 0 && a.right() <= 128 && a.y >= 0 && a.bottom() <= 130);
+            for b in areas[i + 1..].iter() {
+                assert!(!overlaps(*a, *b));
+            }
+        }
+        // The small square fills in the end of the top shelf.
+        assert_eq!((areas[0].y, areas[2].y), (0, 60));
+        assert_eq!((areas[1].x, areas[1].y), (100, 0));
+
+        assert!(pack_rects(&[(300, 10)], 256).is_err());
+    }
+
+    #[test]
+    fn pages() {
+        let packing = pack_rects(&[(64, 48); 5], 128).unwrap();
+        // Only four fit on a page, two to a shelf.
+        assert_eq!(packing.width, 128);
+        assert_eq!(packing.page_heights, [96, 48]);
+        assert_eq!(packing.placements[4].0, 1);
+    }
+
+    #[test]
+    fn copies_pixels() {
+        let red = RgbaImage::from_pixel(2, 3, Rgba([255, 0, 0, 255]));
+        let blue = RgbaImage::from_pixel(4, 1, Rgba([0, 0, 255, 255]));
+        let images = vec![
+            (PathBuf::from("red.png"), red),
+            (PathBuf::from("blue.png"), blue),
+        ];
+        let atlases = pack_images(images, 16).unwrap();
+        assert_eq!(atlases.len(), 1);
+        let atlas = &atlases[0];
+        // The strip doesn't fit next to the taller image, so it goes under it.
+        assert_eq!(atlas.image.dimensions(), (4, 4));
+        for (path, area) in atlas.entries.iter() {
+            let expected = if path == &PathBuf::from("red.png") {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            };
+            let pixel = atlas
+                .image
+                .get_pixel(area.x as u32, area.bottom() as u32 - 1);
+            assert_eq!(*pixel, expected);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::fmt;
+use std::io::Cursor;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use image::{ImageOutputFormat, Rgba, RgbaImage};
+
+use crate::filemanager::FileManager;
+use crate::geometry::Rect;
+
+/// How many rows of empty pixels separate the atlases in the occupancy image.
+const GAP: u32 = 4;
+
+const FREE_COLOR: Rgba<u8> = Rgba([0x22, 0x22, 0x22, 0xff]);
+const GAP_COLOR: Rgba<u8> = Rgba([0xff, 0x00, 0xff, 0xff]);
+/// The color of regions from allocate_atlas_region, like cached text.
+const REGION_COLOR: Rgba<u8> = Rgba([0xee, 0xee, 0xee, 0xff]);
+/// The colors of the images, taken in turn, so images next to each other stand apart.
+const IMAGE_COLORS: &[Rgba<u8>] = &[
+    Rgba([0xcc, 0x44, 0x44, 0xff]),
+    Rgba([0x44, 0xaa, 0x44, 0xff]),
+    Rgba([0x44, 0x66, 0xcc, 0xff]),
+    Rgba([0xcc, 0xaa, 0x33, 0xff]),
+    Rgba([0x99, 0x44, 0xbb, 0xff]),
+    Rgba([0x33, 0xaa, 0xaa, 0xff]),
+];
+
+/// Something that went wrong putting an image in the texture atlas, or finding it there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AtlasProblem {
+    /// A sprite was asked for that isn't in any atlas.
+    Missing { path: PathBuf },
+    /// An image was too big to pack into an atlas at all, so it was left out.
+    Oversized {
+        path: PathBuf,
+        width: i32,
+        height: i32,
+        max_size: i32,
+    },
+    /// An entry in an atlas index is partly or all outside the atlas image.
+    OutOfBounds { path: PathBuf, area: Rect<i32> },
+    /// A region was asked for that didn't fit in any atlas.
+    NoRoom { width: u32, height: u32 },
+}
+
+impl fmt::Display for AtlasProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AtlasProblem::Missing { path } => write!(f, "missing: {:?}", path),
+            AtlasProblem::Oversized {
+                path,
+                width,
+                height,
+                max_size,
+            } => write!(
+                f,
+                "oversized: {:?} is {}x{}, but atlases are at most {}x{}",
+                path, width, height, max_size, max_size
+            ),
+            AtlasProblem::OutOfBounds { path, area } => write!(
+                f,
+                "out of bounds: {:?} at {},{} {}x{}",
+                path, area.x, area.y, area.w, area.h
+            ),
+            AtlasProblem::NoRoom { width, height } => {
+                write!(f, "no room: a {}x{} region", width, height)
+            }
+        }
+    }
+}
+
+/// What's in one texture atlas.
+#[derive(Debug, Clone)]
+pub struct AtlasUsage {
+    pub width: i32,
+    pub height: i32,
+    /// Where each image is in the atlas.
+    pub images: Vec<Rect<i32>>,
+    /// The regions handed out by allocate_atlas_region that are still in use.
+    pub regions: Vec<Rect<i32>>,
+}
+
+impl AtlasUsage {
+    /// How many of the atlas's pixels are covered by images and regions.
+    pub fn used_pixels(&self) -> i64 {
+        self.images
+            .iter()
+            .chain(self.regions.iter())
+            .filter_map(|area| clip(*area, self.width, self.height))
+            .map(|area| area.w as i64 * area.h as i64)
+            .sum()
+    }
+
+    /// The fraction of the atlas that's in use, from 0 to 1.
+    pub fn utilization(&self) -> f32 {
+        let total = self.width as i64 * self.height as i64;
+        if total <= 0 {
+            return 0.0;
+        }
+        self.used_pixels() as f32 / total as f32
+    }
+}
+
+/// How full the texture atlases are, and the problems found since diagnostics were turned on.
+#[derive(Debug, Clone, Default)]
+pub struct AtlasReport {
+    pub atlases: Vec<AtlasUsage>,
+    pub problems: Vec<AtlasProblem>,
+}
+
+impl AtlasReport {
+    /// Draws where everything is in each atlas, with each atlas below the one before it. Images
+    /// are in a rotating set of colors, regions are white, and free space is dark.
+    pub fn to_image(&self) -> RgbaImage {
+        let width = self.atlases.iter().map(|atlas| atlas.width).max();
+        let heights = self.atlases.iter().map(|atlas| atlas.height as u32);
+        let gaps = self.atlases.len().saturating_sub(1) as u32 * GAP;
+        let mut img = RgbaImage::from_pixel(
+            width.unwrap_or(0).max(0) as u32,
+            heights.sum::<u32>() + gaps,
+            GAP_COLOR,
+        );
+        let mut top = 0;
+        for atlas in self.atlases.iter() {
+            let bounds = Rect {
+                x: 0,
+                y: 0,
+                w: atlas.width,
+                h: atlas.height,
+            };
+            let images = atlas.images.iter().zip(IMAGE_COLORS.iter().cycle());
+            let regions = atlas.regions.iter().zip(iter::repeat(&REGION_COLOR));
+            let areas = iter::once((&bounds, &FREE_COLOR))
+                .chain(images)
+                .chain(regions);
+            for (area, color) in areas {
+                if let Some(area) = clip(*area, atlas.width, atlas.height) {
+                    fill(&mut img, area, top, *color);
+                }
+            }
+            top += atlas.height as u32 + GAP;
+        }
+        img
+    }
+
+    /// Saves the occupancy image from to_image as a PNG.
+    pub fn save_image(&self, path: &Path, files: &FileManager) -> Result<()> {
+        let mut png = Vec::new();
+        self.to_image()
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+        files.write(path, &png)
+    }
+}
+
+impl fmt::Display for AtlasReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, atlas) in self.atlases.iter().enumerate() {
+            writeln!(
+                f,
+                "atlas {}: {}x{}, {} images, {} regions, {:.1}% used",
+                i,
+                atlas.width,
+                atlas.height,
+                atlas.images.len(),
+                atlas.regions.len(),
+                atlas.utilization() * 100.0
+            )?;
+        }
+        if self.problems.is_empty() {
+            return writeln!(f, "no problems");
+        }
+        for problem in self.problems.iter() {
+            writeln!(f, "{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// The part of an area that's inside a width x height atlas, if any of it is.
+pub(crate) fn clip(area: Rect<i32>, width: i32, height: i32) -> Option<Rect<i32>> {
+    let (left, top) = (area.x.max(0), area.y.max(0));
+    let (right, bottom) = (area.right().min(width), area.bottom().min(height));
+    (right > left && bottom > top).then_some(Rect {
+        x: left,
+        y: top,
+        w: right - left,
+        h: bottom - top,
+    })
+}
+
+/// Fills an area of an atlas whose top is at the given row of the image.
+fn fill(img: &mut RgbaImage, area: Rect<i32>, top: u32, color: Rgba<u8>) {
+    for y in area.y..area.bottom() {
+        for x in area.x..area.right() {
+            img.put_pixel(x as u32, top + y as u32, color);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Serialize;
+
+/// The seed benchmarks start from, so every run plays out the same way.
+pub const BENCHMARK_SEED: u64 = 0;
+/// How many frames a benchmark runs if it isn't told, which is 10 seconds of play.
+pub const DEFAULT_BENCHMARK_FRAMES: u64 = 600;
+
+/// What Engine::run_benchmark plays.
+#[derive(Debug, Clone)]
+pub struct Benchmark {
+    /// The Tiled map to play, like "assets/levels/corridors.tmx".
+    pub level: PathBuf,
+    /// Inputs recorded with --record, or None to stand still.
+    pub inputs: Option<PathBuf>,
+    pub frames: u64,
+}
+
+/// How long a benchmark's frames took to update, draw, and render, in milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BenchmarkReport {
+    pub level: String,
+    /// How many frames ran, which is fewer than asked for if the game ended.
+    pub frames: usize,
+    pub total_seconds: f64,
+    pub fps: f64,
+    pub mean_millis: f64,
+    pub min_millis: f64,
+    pub p50_millis: f64,
+    pub p90_millis: f64,
+    pub p95_millis: f64,
+    pub p99_millis: f64,
+    pub max_millis: f64,
+}
+
+/// The value at or below which p percent of the sorted values fall, by nearest rank.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl BenchmarkReport {
+    pub fn new(level: &str, frame_times: &[Duration]) -> BenchmarkReport {
+        let mut millis: Vec<f64> = frame_times
+            .iter()
+            .map(|time| time.as_secs_f64() * 1000.0)
+            .collect();
+        millis.sort_by(f64::total_cmp);
+        let total_seconds = millis.iter().sum::<f64>() / 1000.0;
+        BenchmarkReport {
+            level: level.to_string(),
+            frames: millis.len(),
+            total_seconds,
+            fps: if total_seconds > 0.0 {
+                millis.len() as f64 / total_seconds
+            } else {
+                0.0
+            },
+            mean_millis: total_seconds * 1000.0 / millis.len().max(1) as f64,
+            min_millis: millis.first().copied().unwrap_or_default(),
+            p50_millis: percentile(&millis, 50.0),
+            p90_millis: percentile(&millis, 90.0),
+            p95_millis: percentile(&millis, 95.0),
+            p99_millis: percentile(&millis, 99.0),
+            max_millis: millis.last().copied().unwrap_or_default(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::engine::Engine;
+    use crate::filemanager::FileManager;
+    use crate::imagemanager::ImageManager;
+    use crate::soundmanager::SoundManager;
+
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        // 1 to 100 milliseconds, out of order.
+        let times: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+        let report = BenchmarkReport::new("level.tmx", &times);
+        assert_eq!(report.frames, 100);
+        assert_eq!(report.min_millis, 1.0);
+        assert_eq!(report.p50_millis, 50.0);
+        assert_eq!(report.p90_millis, 90.0);
+        assert_eq!(report.p99_millis, 99.0);
+        assert_eq!(report.max_millis, 100.0);
+        assert!((report.mean_millis - 50.5).abs() < 1e-9);
+        assert!((report.total_seconds - 5.05).abs() < 1e-9);
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["level"], "level.tmx");
+        assert_eq!(json["p90_millis"], 90.0);
+
+        let empty = BenchmarkReport::new("level.tmx", &[]);
+        assert_eq!((empty.frames, empty.fps, empty.p99_millis), (0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn runs_level() {
+        let mut map = HashMap::new();
+        map.insert(
+            PathBuf::from("assets/8bitfont.tsx"),
+            include_bytes!("../../assets/8bitfont.tsx").to_vec(),
+        );
+        map.insert(
+            PathBuf::from("assets/walls.tsx"),
+            include_bytes!("../../assets/walls.tsx").to_vec(),
+        );
+        map.insert(
+            PathBuf::from("assets/levels/corridors.tmx"),
+            include_bytes!("../../assets/levels/corridors.tmx").to_vec(),
+        );
+        // Walk forward for a second, then stop.
+        map.insert(PathBuf::from("walk.txt"), b"0,8\n60,0".to_vec());
+        let files = FileManager::from_memory(map).unwrap();
+        let mut images = ImageManager::null_manager();
+        let font = images.load_font(&files).unwrap();
+        let sounds = SoundManager::noop_manager();
+        let mut engine =
+            Engine::with_seed(files, &mut images, font, sounds, BENCHMARK_SEED).unwrap();
+
+        let benchmark = Benchmark {
+            level: PathBuf::from("assets/levels/corridors.tmx"),
+            inputs: Some(PathBuf::from("walk.txt")),
+            frames: 90,
+        };
+        let report = engine.run_benchmark(&mut images, &benchmark).unwrap();
+        assert_eq!(report.frames, 90);
+        assert_eq!(report.level, "assets/levels/corridors.tmx");
+        assert!(report.p50_millis <= report.p99_millis);
+        assert_eq!(engine.frame(), 90);
+        assert_eq!(engine.stage_manager().scene_names(), ["level"]);
+        // The level starts the player at (1.5, 1.5), facing down the corridor.
+        let player = engine.stage_manager().save_state().unwrap().player;
+        assert!(player.y > 2.0, "{:?}", player);
+
+        let missing = Benchmark {
+            inputs: Some(PathBuf::from("missing.txt")),
+            ..benchmark
+        };
+        assert!(engine.run_benchmark(&mut images, &missing).is_err());
+    }
+}
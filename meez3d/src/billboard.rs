@@ -0,0 +1,402 @@
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use anyhow::Result;
+
+use crate::constants::{RENDER_HEIGHT, RENDER_WIDTH};
+use crate::faction::Faction;
+use crate::geometry::Rect;
+use crate::rendercontext::SpriteBatch;
+use crate::sprite::Sprite;
+use crate::tilemap::{MapObject, TileMap};
+use crate::utils::Color;
+
+/// Billboards closer than this, in tiles, are inside the camera, so they aren't drawn.
+const NEAR_DISTANCE: f32 = 0.2;
+
+/// A sprite standing in the raycast world, like a pickup, a decoration, or an enemy. It always
+/// faces the camera, and gets smaller with distance like the walls do.
+#[derive(Debug, Clone, Copy)]
+pub struct Billboard {
+    pub floor: usize,
+    /// Where it stands, in tiles.
+    pub x: f32,
+    pub y: f32,
+    /// How tall it is, in tiles. It's as wide as the sprite's proportions make it.
+    pub size: f32,
+    pub sprite: Sprite,
+    pub source: Rect<i32>,
+    pub reversed: bool,
+    /// The side it's on, if it's a character that moves around and fights, rather than scenery.
+    pub faction: Option<Faction>,
+    /// Which of the level's spawners made it, if one did.
+    pub spawner: Option<usize>,
+    /// Multiplied with the sprite's colors, e.g. to recolor a type of enemy without new art.
+    pub tint: Color,
+    /// Mixed over the sprite by its alpha, e.g. to flash an enemy white when it's hit.
+    pub flash: Color,
+}
+
+impl Billboard {
+    /// A billboard for each tile object in a Tiled map, other than spawners, along with the
+    /// object it's for.
+    pub fn from_tilemap(tilemap: &TileMap) -> Result<Vec<(&MapObject, Billboard)>> {
+        let mut billboards = Vec::new();
+        let objects = tilemap.objects.iter();
+        for obj in objects.filter(|obj| !obj.properties.spawn && obj.properties.spawner.is_none()) {
+            if let Some(billboard) = Billboard::from_object(tilemap, obj)? {
+                billboards.push((obj, billboard));
+            }
+        }
+        Ok(billboards)
+    }
+
+    /// A billboard for a tile object, standing where the middle of its bottom edge is, and as
+    /// tall in tiles as it is in the map. Returns None if the object isn't a tile.
+    pub fn from_object(tilemap: &TileMap, obj: &MapObject) -> Result<Option<Billboard>> {
+        let Some(gid) = obj.gid else {
+            return Ok(None);
+        };
+        let (sprite, source) = tilemap.get_tile_sprite(gid)?;
+        let position = obj.position;
+        Ok(Some(Billboard {
+            floor: 0,
+            x: (position.x as f32 + position.w as f32 / 2.0) / tilemap.tilewidth as f32,
+            y: (position.y + position.h) as f32 / tilemap.tileheight as f32,
+            size: position.h as f32 / tilemap.tileheight as f32,
+            sprite,
+            source,
+            reversed: obj.flip_horizontal,
+            faction: obj
+                .properties
+                .faction
+                .or(obj.properties.enemy.then_some(Faction::Monsters)),
+            spawner: None,
+            tint: obj.properties.tint.unwrap_or(Color::WHITE),
+            flash: Color::TRANSPARENT,
+        }))
+    }
+}
+
+/// How far away the wall is in each column of the screen, with the same fisheye correction the
+/// walls are drawn with, so sprites and effects can be hidden behind walls a column at a time.
+#[derive(Debug, Clone, Default)]
+pub struct DepthBuffer {
+    depths: Vec<f32>,
+}
+
+impl DepthBuffer {
+    /// A buffer with each column's wall distance, or None for columns with no wall.
+    pub fn new(depths: impl IntoIterator<Item = Option<f32>>) -> DepthBuffer {
+        let mut buffer = DepthBuffer::default();
+        buffer.update(depths);
+        buffer
+    }
+
+    /// Replaces every column's wall distance, reusing the buffer's space.
+    pub fn update(&mut self, depths: impl IntoIterator<Item = Option<f32>>) {
+        self.depths.clear();
+        self.depths.extend(
+            depths
+                .into_iter()
+                .map(|depth| depth.unwrap_or(f32::INFINITY)),
+        );
+    }
+
+    /// How far away the wall in a column is, or infinity if there's no wall, or the column is
+    /// off the screen.
+    pub fn depth(&self, column: i32) -> f32 {
+        usize::try_from(column)
+            .ok()
+            .and_then(|column| self.depths.get(column))
+            .copied()
+            .unwrap_or(f32::INFINITY)
+    }
+
+    /// Whether something at the given distance in a column is behind the wall there.
+    pub fn is_hidden(&self, column: i32, distance: f32) -> bool {
+        self.depth(column) < distance
+    }
+}
+
+/// Where a billboard shows up on the screen.
+pub(crate) struct Placement<'a> {
+    billboard: &'a Billboard,
+    /// How far away it is, with the same fisheye correction as the walls.
+    pub distance: f32,
+    dest: Rect<i32>,
+}
+
+/// Where a point in the world shows up, as seen from a camera at (x, y) facing angle, as the
+/// screen column it's in and how far away it is, with the same fisheye correction as the walls.
+/// Returns None if it's behind the camera or too close to draw.
+pub(crate) fn project(x: f32, y: f32, camera: (f32, f32, f32)) -> Option<(i32, f32)> {
+    let (camera_x, camera_y, angle) = camera;
+    let (dx, dy) = (x - camera_x, y - camera_y);
+    let angle = (dy.atan2(dx) - angle + PI).rem_euclid(TAU) - PI;
+    if angle.abs() > FRAC_PI_2 {
+        return None;
+    }
+    let distance = (dx * dx + dy * dy).sqrt() * angle.cos();
+    if distance < NEAR_DISTANCE {
+        return None;
+    }
+    let column = ((angle + PI / 4.0) / FRAC_PI_2 * RENDER_WIDTH as f32) as i32;
+    Some((column, distance))
+}
+
+/// Whether anything of a rect that's width wide, starting at x, is on the screen.
+pub(crate) fn is_on_screen(x: i32, width: i32) -> bool {
+    width > 0 && x + width >= 0 && x < RENDER_WIDTH as i32
+}
+
+/// Where something size tiles tall and aspect times as wide, standing on the floor at (x, y),
+/// shows up, as seen from a camera at (x, y) facing angle, with the horizon at the given row.
+/// Returns the area it covers on screen and how far away it is, or None if it's behind the
+/// camera or off the side of the screen.
+pub(crate) fn place(
+    x: f32,
+    y: f32,
+    size: f32,
+    aspect: f32,
+    camera: (f32, f32, f32),
+    horizon: i32,
+) -> Option<(Rect<i32>, f32)> {
+    let (column, distance) = project(x, y, camera)?;
+    // The floor at a distance is where the bottom of a wall at that distance would be.
+    let bottom = horizon + (RENDER_HEIGHT as f32 / distance / 2.0) as i32;
+    let height = (RENDER_HEIGHT as f32 * size / distance) as i32;
+    let width = (height as f32 * aspect) as i32;
+    let dest = Rect {
+        x: column - width / 2,
+        y: bottom - height,
+        w: width,
+        h: height,
+    };
+    if !is_on_screen(dest.x, width) {
+        return None;
+    }
+    Some((dest, distance))
+}
+
+/// Draws the source area of a sprite stretched over dest, but only in the columns where
+/// something at the given distance is in front of the wall, shifted right by shift.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn draw_unoccluded(
+    batch: &mut SpriteBatch,
+    sprite: Sprite,
+    source: Rect<i32>,
+    dest: Rect<i32>,
+    reversed: bool,
+    distance: f32,
+    shift: i32,
+    depth: &DepthBuffer,
+) {
+    let first = dest.x.max(0);
+    let last = (dest.x + dest.w).min(RENDER_WIDTH as i32);
+    // Draw each run of visible columns as one slice of the sprite.
+    let mut column = first;
+    while column < last {
+        if depth.is_hidden(column, distance) {
+            column += 1;
+            continue;
+        }
+        let start = column;
+        while column < last && !depth.is_hidden(column, distance) {
+            column += 1;
+        }
+        let from = (start - dest.x) * source.w / dest.w;
+        let to = ((column - dest.x) * source.w / dest.w).max(from + 1);
+        // A reversed sprite is flipped, so its left edge on screen comes from the right.
+        let x = if reversed {
+            source.x + source.w - to
+        } else {
+            source.x + from
+        };
+        let slice = Rect {
+            x,
+            y: source.y,
+            w: to - from,
+            h: source.h,
+        };
+        let slice_dest = Rect {
+            x: start + shift,
+            y: dest.y,
+            w: column - start,
+            h: dest.h,
+        };
+        batch.draw(sprite, slice_dest, slice, reversed);
+    }
+}
+
+/// Where each billboard shows up, as seen from a camera at (x, y) facing angle, with the
+/// horizon at the given row, sorted from farthest to nearest so nearer ones are drawn over
+/// farther ones. Billboards that are off the screen are left out.
+pub(crate) fn place_billboards<'a>(
+    billboards: impl IntoIterator<Item = &'a Billboard>,
+    camera: (f32, f32, f32),
+    horizon: i32,
+) -> Vec<Placement<'a>> {
+    let mut placements: Vec<Placement> = billboards
+        .into_iter()
+        .filter_map(|billboard| {
+            let aspect = billboard.source.w as f32 / billboard.source.h.max(1) as f32;
+            let (x, y, size) = (billboard.x, billboard.y, billboard.size);
+            let (dest, distance) = place(x, y, size, aspect, camera, horizon)?;
+            Some(Placement {
+                billboard,
+                distance,
+                dest,
+            })
+        })
+        .collect();
+    placements.sort_by(|a, b| b.distance.total_cmp(&a.distance));
+    placements
+}
+
+impl Placement<'_> {
+    /// Draws the billboard, but only in the columns where it's in front of the wall, shifted
+    /// right by shift.
+    pub(crate) fn draw(&self, batch: &mut SpriteBatch, shift: i32, depth: &DepthBuffer) {
+        let (tint, flash) = (batch.tint, batch.flash);
+        batch.tint = tint.multiply(self.billboard.tint);
+        batch.flash = self.billboard.flash;
+        draw_unoccluded(
+            batch,
+            self.billboard.sprite,
+            self.billboard.source,
+            self.dest,
+            self.billboard.reversed,
+            self.distance,
+            shift,
+            depth,
+        );
+        batch.tint = tint;
+        batch.flash = flash;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rendercontext::{RenderContext, SpriteBatchEntry};
+
+    fn billboard(x: f32, id: usize) -> Billboard {
+        Billboard {
+            floor: 0,
+            x,
+            y: 0.0,
+            size: 1.0,
+            sprite: Sprite {
+                id,
+                area: Rect {
+                    x: 0,
+                    y: 0,
+                    w: 64,
+                    h: 64,
+                },
+            },
+            source: Rect {
+                x: 0,
+                y: 0,
+                w: 64,
+                h: 64,
+            },
+            reversed: false,
+            faction: None,
+            spawner: None,
+            tint: Color::WHITE,
+            flash: Color::TRANSPARENT,
+        }
+    }
+
+    /// Draws the billboards the way the level does when there's no text in the world.
+    fn draw_billboards<'a>(
+        batch: &mut SpriteBatch,
+        billboards: impl IntoIterator<Item = &'a Billboard>,
+        camera: (f32, f32, f32),
+        horizon: i32,
+        shift: i32,
+        depth: &DepthBuffer,
+    ) {
+        for placement in place_billboards(billboards, camera, horizon) {
+            placement.draw(batch, shift, depth);
+        }
+    }
+
+    #[test]
+    fn depth_sorting() {
+        let mut context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let horizon = RENDER_HEIGHT as i32 / 2;
+        let near = billboard(2.0, 1);
+        let far = billboard(4.0, 2);
+        let hidden = billboard(10.0, 3);
+        let center = RENDER_WIDTH as i32 / 2;
+        // A wall 5 tiles away covers the right half of the screen.
+        let depth = DepthBuffer::new(
+            (0..RENDER_WIDTH as i32).map(|column| (column >= center).then_some(5.0)),
+        );
+        draw_billboards(
+            &mut context.player_batch,
+            [&near, &hidden, &far],
+            (0.0, 0.0, 0.0),
+            horizon,
+            0,
+            &depth,
+        );
+
+        // The far one is drawn first, so the near one covers it, and the one behind the wall is
+        // only drawn in the columns to the left of the wall.
+        let drawn: Vec<(usize, Rect<i32>)> = context
+            .player_batch
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                SpriteBatchEntry::Sprite {
+                    sprite,
+                    destination,
+                    ..
+                } => (sprite.id, *destination),
+                _ => panic!("expected a sprite"),
+            })
+            .collect();
+        let ids: Vec<usize> = drawn.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec![3, 2, 1]);
+        assert_eq!(drawn[0].1.x + drawn[0].1.w, center);
+        assert_eq!(drawn[1].1.h, RENDER_HEIGHT as i32 / 4);
+        assert_eq!(drawn[2].1.h, RENDER_HEIGHT as i32 / 2);
+        // Both stand on the floor.
+        assert_eq!(
+            drawn[2].1.y + drawn[2].1.h,
+            horizon + RENDER_HEIGHT as i32 / 4
+        );
+    }
+
+    #[test]
+    fn tints() {
+        let mut context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let red = Color {
+            g: 0,
+            b: 0,
+            ..Color::WHITE
+        };
+        let tinted = Billboard {
+            tint: red,
+            ..billboard(2.0, 1)
+        };
+        draw_billboards(
+            &mut context.player_batch,
+            [&tinted],
+            (0.0, 0.0, 0.0),
+            RENDER_HEIGHT as i32 / 2,
+            0,
+            &DepthBuffer::default(),
+        );
+        let Some(SpriteBatchEntry::Sprite { tint, .. }) = context.player_batch.entries.first()
+        else {
+            panic!("expected a sprite");
+        };
+        assert_eq!(*tint, red);
+        // Whatever's drawn next isn't tinted.
+        assert_eq!(context.player_batch.tint, Color::WHITE);
+    }
+}
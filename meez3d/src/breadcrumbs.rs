@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+/// Whether the level records the player's trail and shows it on the automap, "true" or "false".
+pub const BREADCRUMBS_CVAR: &str = "breadcrumbs";
+/// Where the automap draws a route to: "off", "start", or "exit".
+pub const ROUTE_GUIDE_CVAR: &str = "route_guide";
+
+/// How many crumbs the trail keeps before it starts dropping the oldest ones.
+const BREADCRUMB_CAPACITY: usize = 1000;
+/// How far the player has to move before another crumb is dropped, in tiles.
+const BREADCRUMB_SPACING: f32 = 0.5;
+
+/// What the automap shows a route back to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RouteGuide {
+    #[default]
+    Off,
+    /// Where the player started the level.
+    Start,
+    /// The nearest way off the current floor, toward the top of the level.
+    Exit,
+}
+
+impl FromStr for RouteGuide {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "off" => RouteGuide::Off,
+            "start" => RouteGuide::Start,
+            "exit" => RouteGuide::Exit,
+            _ => bail!("invalid route guide: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for RouteGuide {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RouteGuide::Off => "off",
+            RouteGuide::Start => "start",
+            RouteGuide::Exit => "exit",
+        })
+    }
+}
+
+/// A place the player has been.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crumb {
+    pub floor: usize,
+    pub x: f32,
+    pub y: f32,
+}
+
+/// The path the player has taken through a level, oldest first, up to a fixed number of crumbs.
+#[derive(Debug, Clone)]
+pub struct Breadcrumbs {
+    crumbs: VecDeque<Crumb>,
+    capacity: usize,
+}
+
+impl Default for Breadcrumbs {
+    fn default() -> Self {
+        Breadcrumbs::new(BREADCRUMB_CAPACITY)
+    }
+}
+
+impl Breadcrumbs {
+    pub fn new(capacity: usize) -> Breadcrumbs {
+        Breadcrumbs {
+            crumbs: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Drops a crumb where the player is, if they've moved far enough from the last one.
+    pub fn record(&mut self, floor: usize, x: f32, y: f32) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(last) = self.crumbs.back() {
+            let distance = ((x - last.x).powi(2) + (y - last.y).powi(2)).sqrt();
+            if last.floor == floor && distance < BREADCRUMB_SPACING {
+                return;
+            }
+        }
+        if self.crumbs.len() == self.capacity {
+            self.crumbs.pop_front();
+        }
+        self.crumbs.push_back(Crumb { floor, x, y });
+    }
+
+    /// The crumbs, from oldest to newest.
+    pub fn crumbs(&self) -> impl DoubleEndedIterator<Item = &Crumb> + ExactSizeIterator {
+        self.crumbs.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.crumbs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.crumbs.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.crumbs.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spacing_and_capacity() {
+        let mut trail = Breadcrumbs::new(3);
+        trail.record(0, 1.0, 1.0);
+        trail.record(0, 1.2, 1.0);
+        assert_eq!(trail.len(), 1);
+
+        // Changing floors always drops a crumb, even without moving.
+        trail.record(1, 1.2, 1.0);
+        trail.record(1, 2.0, 1.0);
+        trail.record(1, 3.0, 1.0);
+        assert_eq!(trail.len(), 3);
+        assert_eq!(trail.crumbs().next().unwrap().floor, 1);
+        let last = trail.crumbs().last().unwrap();
+        assert_eq!((last.x, last.y), (3.0, 1.0));
+
+        assert_eq!("exit".parse::<RouteGuide>().unwrap(), RouteGuide::Exit);
+        assert!("home".parse::<RouteGuide>().is_err());
+    }
+}
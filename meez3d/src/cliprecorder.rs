@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+use image::{imageops, RgbaImage};
+use log::info;
+
+use crate::constants::FRAME_RATE;
+use crate::cvars::Cvars;
+use crate::filemanager::FileManager;
+
+/// The cvar for how many seconds of recent frames are kept for clips. Recording is off at 0, the
+/// default, since frames have to be read back from the renderer.
+pub const CLIP_SECONDS_CVAR: &str = "clip_seconds";
+/// The cvar that saves a clip when it's set to a file name in the user data directory, e.g. from
+/// the debug server. It's cleared once the clip is saved.
+pub const SAVE_CLIP_CVAR: &str = "save_clip";
+/// Clips are kept at this many frames a second, skipping the frames in between.
+pub const CLIP_FRAME_RATE: u32 = 15;
+/// Frames are shrunk to this width for clips, keeping their proportions.
+pub const CLIP_WIDTH: u32 = 320;
+/// The most seconds a clip can hold, so a typo can't use up all the memory.
+pub const MAX_CLIP_SECONDS: f32 = 60.0;
+
+/// Keeps the last few seconds of rendered frames, shrunk down, so they can be saved as an
+/// animated PNG, e.g. to show a bug.
+#[derive(Debug, Clone, Default)]
+pub struct ClipRecorder {
+    frames: VecDeque<RgbaImage>,
+    /// How many frames the clip holds, or 0 if it isn't recording.
+    max_frames: usize,
+    /// How many frames have been offered, to keep only every few.
+    offered: u64,
+}
+
+impl ClipRecorder {
+    pub fn new() -> ClipRecorder {
+        ClipRecorder::default()
+    }
+
+    /// Keeps the given number of seconds from now on, dropping older frames if it's shorter.
+    pub fn set_seconds(&mut self, seconds: f32) {
+        let seconds = if seconds.is_finite() {
+            seconds.clamp(0.0, MAX_CLIP_SECONDS)
+        } else {
+            0.0
+        };
+        self.max_frames = (seconds * CLIP_FRAME_RATE as f32).round() as usize;
+        while self.frames.len() > self.max_frames {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Sets the clip length from the cvar.
+    pub fn apply_cvars(&mut self, cvars: &Cvars) {
+        self.set_seconds(cvars.get_parsed(CLIP_SECONDS_CVAR).unwrap_or(0.0));
+    }
+
+    /// Whether it's keeping frames for a clip at all.
+    pub fn is_recording(&self) -> bool {
+        self.max_frames > 0
+    }
+
+    /// How many frames the clip has so far.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Whether the next frame will be kept, so the renderer only has to read back the frames
+    /// that are. Only enough are kept for CLIP_FRAME_RATE frames a second.
+    pub fn wants_frame(&self) -> bool {
+        self.is_recording()
+            && self
+                .offered
+                .is_multiple_of((FRAME_RATE / CLIP_FRAME_RATE) as u64)
+    }
+
+    /// Offers a frame as it was rendered. The host does this, or skip_frame if the frame wasn't
+    /// read back, once for each frame of the game. The frame is only kept if wants_frame asked
+    /// for it.
+    pub fn add_frame(&mut self, frame: &RgbaImage) {
+        let wanted = self.wants_frame();
+        self.skip_frame();
+        if !wanted || frame.width() == 0 || frame.height() == 0 {
+            return;
+        }
+        let width = CLIP_WIDTH.min(frame.width());
+        let height = (frame.height() * width / frame.width()).max(1);
+        let frame = imageops::resize(frame, width, height, imageops::FilterType::Triangle);
+        // Frames can change size with the window, but every frame of a clip has to match.
+        if self
+            .frames
+            .back()
+            .is_some_and(|last| last.dimensions() != frame.dimensions())
+        {
+            self.frames.clear();
+        }
+        if self.frames.len() >= self.max_frames {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    /// Counts a frame that wasn't read back, so the ones that are keep to CLIP_FRAME_RATE.
+    pub fn skip_frame(&mut self) {
+        if self.is_recording() {
+            self.offered += 1;
+        }
+    }
+
+    /// Encodes the clip as an animated PNG that loops forever.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let Some(first) = self.frames.front() else {
+            bail!("no frames have been recorded for a clip");
+        };
+        let to_error = |e: png::EncodingError| anyhow!("unable to encode clip: {}", e);
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, first.width(), first.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .set_animated(self.frames.len() as u32, 0)
+            .map_err(to_error)?;
+        encoder
+            .set_frame_delay(1, CLIP_FRAME_RATE as u16)
+            .map_err(to_error)?;
+        let mut writer = encoder.write_header().map_err(to_error)?;
+        for frame in self.frames.iter() {
+            writer.write_image_data(frame.as_raw()).map_err(to_error)?;
+        }
+        writer.finish().map_err(to_error)?;
+        Ok(bytes)
+    }
+
+    /// Saves the clip with the given name in the user data directory.
+    pub fn save(&self, files: &FileManager, name: &str) -> Result<()> {
+        files.write_user_data(name, &self.encode()?)?;
+        info!("saved {} frame clip to {}", self.frames.len(), name);
+        Ok(())
+    }
+}
+
+/// The name a clip is saved under from the save clip cvar, which is cleared so it's only saved
+/// once, or None if it isn't set.
+pub fn take_clip_request(cvars: &mut Cvars) -> Option<String> {
+    let name = cvars.get(SAVE_CLIP_CVAR).filter(|name| !name.is_empty())?;
+    let name = name.to_string();
+    cvars.set(SAVE_CLIP_CVAR, "");
+    Some(name)
+}
+
+/// A name for a clip saved with the hotkey, from the time, so clips don't overwrite each other.
+pub fn timestamped_clip_name() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    format!("clip-{}.png", seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn keeps_recent_frames() {
+        let mut recorder = ClipRecorder::new();
+        let frame = RgbaImage::from_pixel(640, 400, Rgba([255, 0, 0, 255]));
+        recorder.add_frame(&frame);
+        assert!(recorder.is_empty());
+
+        let mut cvars = Cvars::new();
+        cvars.set(CLIP_SECONDS_CVAR, "2");
+        recorder.apply_cvars(&cvars);
+        for _ in 0..FRAME_RATE * 5 {
+            recorder.add_frame(&frame);
+        }
+        assert_eq!(recorder.len(), 2 * CLIP_FRAME_RATE as usize);
+
+        // Only the frames it wants have to be read back, like a host that skips the rest.
+        let mut wanted = 0;
+        for _ in 0..FRAME_RATE {
+            if recorder.wants_frame() {
+                wanted += 1;
+                recorder.add_frame(&frame);
+            } else {
+                recorder.skip_frame();
+            }
+        }
+        assert_eq!(wanted, CLIP_FRAME_RATE);
+        assert_eq!(recorder.frames[0].dimensions(), (CLIP_WIDTH, 200));
+
+        recorder.set_seconds(1.0);
+        assert_eq!(recorder.len(), CLIP_FRAME_RATE as usize);
+
+        let bytes = recorder.encode().unwrap();
+        let reader = png::Decoder::new(Cursor::new(bytes)).read_info().unwrap();
+        let animation = reader.info().animation_control.unwrap();
+        assert_eq!(animation.num_frames, CLIP_FRAME_RATE);
+        assert_eq!(reader.info().width, CLIP_WIDTH);
+
+        assert!(ClipRecorder::new().encode().is_err());
+    }
+
+    #[test]
+    fn clip_requests() {
+        let mut cvars = Cvars::new();
+        assert_eq!(take_clip_request(&mut cvars), None);
+        cvars.set(SAVE_CLIP_CVAR, "bug.png");
+        assert_eq!(take_clip_request(&mut cvars), Some("bug.png".to_string()));
+        assert_eq!(take_clip_request(&mut cvars), None);
+    }
+}
@@ -2,7 +2,8 @@
 pub const RENDER_WIDTH: u32 = 640;
 pub const RENDER_HEIGHT: u32 = 400;
 pub const FRAME_RATE: u32 = 60;
-pub const CIRCLE_STEPS: u32 = 50;
 
 // Rendering details.
 pub const MAX_LIGHTS: usize = 32;
+/// The most segments a full circle is drawn with, however big it is.
+pub const MAX_CIRCLE_STEPS: u32 = 128;
@@ -34,7 +34,8 @@ impl Cursor {
         context.draw(self.sprite, layer, dest, src);
     }
 
-    pub fn update(&mut self, input: &InputSnapshot) {
-        self.position = input.mouse_position;
+    /// The cursor is drawn on the HUD, so it follows the mouse in HUD units.
+    pub fn update(&mut self, context: &RenderContext, input: &InputSnapshot) {
+        self.position = context.to_ui_point(input.mouse_position);
     }
 }
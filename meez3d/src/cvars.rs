@@ -0,0 +1,31 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Named console variables that can be tweaked while the game is running.
+#[derive(Debug, Clone, Default)]
+pub struct Cvars {
+    values: BTreeMap<String, String>,
+}
+
+impl Cvars {
+    pub fn new() -> Cvars {
+        Cvars::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    /// Returns the value parsed as T, or None if it's missing or doesn't parse.
+    pub fn get_parsed<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name).and_then(|value| value.parse().ok())
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.values.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
@@ -0,0 +1,63 @@
+use crate::cvars::Cvars;
+
+/// Whether the player takes damage, "true" or "false".
+pub const GOD_CVAR: &str = "god";
+/// Whether the player walks through walls, "true" or "false".
+pub const NOCLIP_CVAR: &str = "noclip";
+
+/// The cheat codes for each flag, which toggle the cvar with the same name.
+pub const DEFAULT_CHEAT_CODES: [(&str, &str); 2] = [
+    (GOD_CVAR, "up up down down left right left right"),
+    (NOCLIP_CVAR, "left right left right up down up down"),
+];
+
+/// Debugging flags that bend the rules of the game, set from the console or with cheat codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DebugFlags {
+    /// The player ignores damage.
+    pub god: bool,
+    /// The player ignores collision with walls.
+    pub noclip: bool,
+}
+
+impl DebugFlags {
+    pub fn from_cvars(cvars: &Cvars) -> DebugFlags {
+        DebugFlags {
+            god: cvars.get_parsed(GOD_CVAR).unwrap_or(false),
+            noclip: cvars.get_parsed(NOCLIP_CVAR).unwrap_or(false),
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        self.god || self.noclip
+    }
+
+    /// A line listing the flags that are on, to show on the HUD, or None if none are.
+    pub fn watermark(&self) -> Option<String> {
+        let names: Vec<&str> = [(self.god, "god"), (self.noclip, "noclip")]
+            .into_iter()
+            .filter_map(|(on, name)| on.then_some(name))
+            .collect();
+        if names.is_empty() {
+            return None;
+        }
+        Some(format!("debug {}", names.join(" ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watermark() {
+        let mut cvars = Cvars::new();
+        assert_eq!(DebugFlags::from_cvars(&cvars).watermark(), None);
+
+        cvars.set(GOD_CVAR, "true");
+        cvars.set(NOCLIP_CVAR, "true");
+        let flags = DebugFlags::from_cvars(&cvars);
+        assert!(flags.any());
+        assert_eq!(flags.watermark().unwrap(), "debug god noclip");
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::engine::EnginePlugin;
+use crate::font::Font;
+use crate::rendercontext::RenderContext;
+use crate::renderer::RendererStats;
+use crate::savestate::PlayerState;
+use crate::scene::EntityInfo;
+use crate::stagemanager::StageManager;
+
+/// The live state of the game, as served at /state.
+#[derive(Debug, Default, Serialize)]
+struct DebugSnapshot {
+    frame: u64,
+    scenes: Vec<String>,
+    player: Option<PlayerState>,
+    /// The enemies, pickups, and billboards in the level, with where they are.
+    entities: Vec<EntityInfo>,
+    update_millis: f64,
+    frame_millis: f64,
+    renderer: RendererStats,
+    /// Entries dropped because they were entirely offscreen.
+    culled_entries: u32,
+    cvars: BTreeMap<String, String>,
+}
+
+#[derive(Default)]
+struct SharedState {
+    snapshot: DebugSnapshot,
+    pending_cvars: Vec<(String, String)>,
+}
+
+fn lock(shared: &Mutex<SharedState>) -> MutexGuard<'_, SharedState> {
+    shared
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// An HTTP server for inspecting a running game, registered with the Engine as a plugin.
+///
+///   GET /state: the current frame, scene stack, player position, entities, timings, renderer
+///               stats, culled entries, and cvars.
+///   GET /cvars: just the cvars.
+///   PUT /cvars/<name>: sets a cvar to the request body, starting with the next frame.
+///
+pub struct DebugServer {
+    shared: Arc<Mutex<SharedState>>,
+    update_start: Instant,
+    update_millis: f64,
+    last_frame: Option<Instant>,
+}
+
+impl DebugServer {
+    /// Starts serving in a background thread, on an address like "127.0.0.1:7878".
+    pub fn start(addr: &str) -> Result<DebugServer> {
+        let server = Server::http(addr)
+            .map_err(|e| anyhow!("unable to start debug server on {}: {}", addr, e))?;
+        info!("debug server listening on {}", addr);
+
+        let shared = Arc::new(Mutex::new(SharedState::default()));
+        let thread_shared = shared.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                if let Err(e) = handle_request(request, &thread_shared) {
+                    error!("debug server error: {}", e);
+                }
+            }
+        });
+
+        Ok(DebugServer {
+            shared,
+            update_start: Instant::now(),
+            update_millis: 0.0,
+            last_frame: None,
+        })
+    }
+}
+
+impl EnginePlugin for DebugServer {
+    fn pre_update(&mut self, _context: &RenderContext, game: &mut StageManager) {
+        let pending = std::mem::take(&mut lock(&self.shared).pending_cvars);
+        for (name, value) in pending {
+            info!("debug server set cvar {} = {:?}", name, value);
+            game.cvars_mut().set(&name, &value);
+        }
+        self.update_start = Instant::now();
+    }
+
+    fn post_update(&mut self, _context: &RenderContext, _game: &mut StageManager) {
+        self.update_millis = self.update_start.elapsed().as_secs_f64() * 1000.0;
+    }
+
+    fn pre_render(&mut self, context: &mut RenderContext, _font: &Font, game: &StageManager) {
+        let now = Instant::now();
+        let frame_millis = self
+            .last_frame
+            .map(|last| (now - last).as_secs_f64() * 1000.0)
+            .unwrap_or(0.0);
+        self.last_frame = Some(now);
+
+        lock(&self.shared).snapshot = DebugSnapshot {
+            frame: context.frame,
+            scenes: game.scene_names().into_iter().map(String::from).collect(),
+            player: game.save_state().map(|state| state.player),
+            entities: game.entities().unwrap_or_default(),
+            update_millis: self.update_millis,
+            frame_millis,
+            renderer: context.renderer_stats,
+            culled_entries: context.culled(),
+            cvars: game
+                .cvars()
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        };
+    }
+}
+
+fn json_response<T: Serialize>(value: &T) -> Result<Response<Cursor<Vec<u8>>>> {
+    let body = serde_json::to_string(value)?;
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .map_err(|_| anyhow!("invalid content type header"))?;
+    Ok(Response::from_string(body).with_header(header))
+}
+
+fn handle_request(mut request: Request, shared: &Mutex<SharedState>) -> Result<()> {
+    let url = request.url().to_string();
+    let response = match (request.method(), url.as_str()) {
+        (Method::Get, "/state") => json_response(&lock(shared).snapshot)?,
+        (Method::Get, "/cvars") => json_response(&lock(shared).snapshot.cvars)?,
+        (Method::Put | Method::Post, path) if path.starts_with("/cvars/") => {
+            let name = path["/cvars/".len()..].to_string();
+            let mut value = String::new();
+            request.as_reader().read_to_string(&mut value)?;
+            lock(shared)
+                .pending_cvars
+                .push((name, value.trim().to_string()));
+            Response::from_string("").with_status_code(202)
+        }
+        _ => Response::from_string("not found").with_status_code(404),
+    };
+    request
+        .respond(response)
+        .map_err(|e| anyhow!("unable to respond to {}: {}", url, e))
+}
@@ -0,0 +1,112 @@
+use std::sync::Mutex;
+
+/// Decodes assets, like images and Tiled files, on a pool of worker threads while loading, so
+/// loading a level uses every core. Reading the files and uploading to the GPU stay on the main
+/// thread, since neither the file manager nor the renderer can be shared between threads.
+///
+/// There are no threads in the browser, so on wasm everything is decoded in order instead.
+#[derive(Debug, Clone, Copy)]
+pub struct DecodePool {
+    threads: usize,
+}
+
+impl DecodePool {
+    /// A pool with a thread for each core.
+    pub fn new() -> DecodePool {
+        let threads = if cfg!(target_arch = "wasm32") {
+            1
+        } else {
+            std::thread::available_parallelism().map_or(1, |n| n.get())
+        };
+        DecodePool::with_threads(threads)
+    }
+
+    /// A pool with the given number of threads, where 1 decodes everything on the calling thread.
+    pub fn with_threads(threads: usize) -> DecodePool {
+        DecodePool {
+            threads: threads.max(1),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Decodes every input, and returns the outputs in the same order as the inputs.
+    pub fn decode_all<I, O, F>(&self, inputs: Vec<I>, decode: F) -> Vec<O>
+    where
+        I: Send,
+        O: Send,
+        F: Fn(I) -> O + Sync,
+    {
+        let threads = self.threads.min(inputs.len());
+        if cfg!(target_arch = "wasm32") || threads <= 1 {
+            return inputs.into_iter().map(decode).collect();
+        }
+        profile_scope!("decode_all", inputs = inputs.len(), threads = threads);
+
+        // Each worker takes the next input as soon as it's done with the last one, so one big
+        // image doesn't hold up a whole share of the others.
+        let count = inputs.len();
+        let queue = Mutex::new(inputs.into_iter().enumerate());
+        let next = || queue.lock().unwrap_or_else(|e| e.into_inner()).next();
+        let mut decoded: Vec<(usize, O)> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut outputs = Vec::new();
+                        while let Some((i, input)) = next() {
+                            outputs.push((i, decode(input)));
+                        }
+                        outputs
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| match worker.join() {
+                    Ok(outputs) => outputs,
+                    Err(panic) => std::panic::resume_unwind(panic),
+                })
+                .collect()
+        });
+        debug_assert_eq!(decoded.len(), count);
+        decoded.sort_by_key(|(i, _)| *i);
+        decoded.into_iter().map(|(_, output)| output).collect()
+    }
+}
+
+impl Default for DecodePool {
+    fn default() -> Self {
+        DecodePool::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn keeps_order() {
+        let inputs: Vec<u64> = (0..100).collect();
+        let pool = DecodePool::with_threads(4);
+        let outputs = pool.decode_all(inputs.clone(), |n| {
+            // The early inputs take the longest, so they finish out of order.
+            thread::sleep(std::time::Duration::from_micros(100 - n));
+            (n * n, thread::current().id())
+        });
+        let squares: Vec<u64> = outputs.iter().map(|(square, _)| *square).collect();
+        assert_eq!(squares, inputs.iter().map(|n| n * n).collect::<Vec<_>>());
+        assert!(outputs.iter().all(|(_, id)| *id != thread::current().id()));
+
+        // With one thread, it all happens on this one.
+        let outputs =
+            DecodePool::with_threads(1).decode_all(vec![1, 2], |_| thread::current().id());
+        assert_eq!(outputs, vec![thread::current().id(); 2]);
+        assert!(DecodePool::new()
+            .decode_all(Vec::<u8>::new(), |n| n)
+            .is_empty());
+    }
+}
@@ -0,0 +1,104 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+use log::error;
+
+use crate::cvars::Cvars;
+
+/// The cvar that picks the window mode, as windowed, borderless, or exclusive.
+pub const WINDOW_MODE_CVAR: &str = "window_mode";
+/// The cvar that picks which monitor to show the window on, counting from zero.
+pub const DISPLAY_CVAR: &str = "display";
+
+/// How the game's window is shown.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WindowMode {
+    #[default]
+    Windowed,
+    /// A window without decorations that covers the whole display, at the desktop resolution.
+    Borderless,
+    /// Takes over the display, which can change its video mode.
+    Exclusive,
+}
+
+impl FromStr for WindowMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "windowed" => WindowMode::Windowed,
+            "borderless" => WindowMode::Borderless,
+            "exclusive" => WindowMode::Exclusive,
+            _ => bail!("invalid window mode: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for WindowMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WindowMode::Windowed => "windowed",
+            WindowMode::Borderless => "borderless",
+            WindowMode::Exclusive => "exclusive",
+        })
+    }
+}
+
+/// Which monitor the window is on, and how it's shown.
+///
+/// Hosts apply these at startup, and then watch the cvars for changes, so they can be changed
+/// from inside the game, e.g. from a settings menu.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DisplaySettings {
+    pub mode: WindowMode,
+    pub display: usize,
+}
+
+impl DisplaySettings {
+    /// Returns these settings updated from the cvars, keeping any that are missing or invalid.
+    pub fn with_cvars(&self, cvars: &Cvars) -> DisplaySettings {
+        let mut settings = *self;
+        if let Some(mode) = cvars.get(WINDOW_MODE_CVAR) {
+            match mode.parse() {
+                Ok(mode) => settings.mode = mode,
+                Err(e) => error!("{}", e),
+            }
+        }
+        if let Some(display) = cvars.get_parsed(DISPLAY_CVAR) {
+            settings.display = display;
+        }
+        settings
+    }
+
+    /// Writes the settings to the cvars, so the game can show and change them.
+    pub fn write_cvars(&self, cvars: &mut Cvars) {
+        cvars.set(WINDOW_MODE_CVAR, &self.mode.to_string());
+        cvars.set(DISPLAY_CVAR, &self.display.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cvars() {
+        let settings = DisplaySettings {
+            mode: WindowMode::Borderless,
+            display: 1,
+        };
+        let mut cvars = Cvars::new();
+        settings.write_cvars(&mut cvars);
+        assert_eq!(DisplaySettings::default().with_cvars(&cvars), settings);
+
+        cvars.set(WINDOW_MODE_CVAR, "exclusive");
+        cvars.set(DISPLAY_CVAR, "second");
+        let changed = settings.with_cvars(&cvars);
+        assert_eq!(changed.mode, WindowMode::Exclusive);
+        assert_eq!(changed.display, 1);
+
+        cvars.set(WINDOW_MODE_CVAR, "maximized");
+        assert_eq!(settings.with_cvars(&cvars).mode, WindowMode::Borderless);
+    }
+}
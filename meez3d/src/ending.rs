@@ -0,0 +1,243 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::filemanager::FileManager;
+
+/// The game data file listing the endings, in the order they're checked.
+pub const ENDINGS_PATH: &str = "assets/endings.json";
+/// The user data file the endings the player has reached are kept in.
+const ENDING_RECORD_PATH: &str = "endings.json";
+
+/// How the player did on the way to the final exit, which decides which ending they get.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RunSummary {
+    pub seconds: u64,
+    pub health: f32,
+    /// How many enemies the player's rockets or barrels killed.
+    pub kills: u32,
+    /// How many times the player went back to a checkpoint.
+    pub respawns: u32,
+}
+
+/// What a run has to look like to get an ending. Anything left out doesn't matter.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EndingConditions {
+    pub max_seconds: Option<u64>,
+    pub min_health: Option<f32>,
+    pub min_kills: Option<u32>,
+    pub max_kills: Option<u32>,
+    pub max_respawns: Option<u32>,
+}
+
+impl EndingConditions {
+    pub fn matches(&self, run: &RunSummary) -> bool {
+        self.max_seconds.is_none_or(|max| run.seconds <= max)
+            && self.min_health.is_none_or(|min| run.health >= min)
+            && self.min_kills.is_none_or(|min| run.kills >= min)
+            && self.max_kills.is_none_or(|max| run.kills <= max)
+            && self.max_respawns.is_none_or(|max| run.respawns <= max)
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == EndingConditions::default()
+    }
+}
+
+/// One way the game can end, with the epilogue shown for it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ending {
+    /// The name the ending is saved under.
+    pub name: String,
+    pub title: String,
+    /// The epilogue's text, shown a line at a time.
+    pub lines: Vec<String>,
+    #[serde(default)]
+    pub when: EndingConditions,
+}
+
+/// Every ending, checked in order at the final exit. The last one has no conditions, so there's
+/// always an ending to pick.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EndingTable {
+    endings: Vec<Ending>,
+}
+
+impl Default for EndingTable {
+    fn default() -> Self {
+        EndingTable {
+            endings: vec![Ending {
+                name: "escape".to_string(),
+                title: "escape".to_string(),
+                lines: vec!["you made it out".to_string()],
+                when: EndingConditions::default(),
+            }],
+        }
+    }
+}
+
+impl EndingTable {
+    pub fn from_json(text: &str) -> Result<EndingTable> {
+        let endings: Vec<Ending> = serde_json::from_str(text)
+            .map_err(|e| anyhow!("unable to deserialize endings: {}", e))?;
+        let Some(last) = endings.last() else {
+            bail!("no endings");
+        };
+        if !last.when.is_empty() {
+            bail!("invalid last ending: {} has conditions", last.name);
+        }
+        let mut names = BTreeSet::new();
+        for ending in endings.iter() {
+            if !names.insert(ending.name.as_str()) {
+                bail!("duplicate ending: {}", ending.name);
+            }
+        }
+        Ok(EndingTable { endings })
+    }
+
+    /// Loads the endings, or a single built-in one if the file is missing or invalid.
+    pub fn load(files: &FileManager) -> EndingTable {
+        match files
+            .read_to_string(Path::new(ENDINGS_PATH))
+            .and_then(|text| EndingTable::from_json(&text))
+        {
+            Ok(table) => table,
+            Err(e) => {
+                info!("using the built-in ending: {}", e);
+                EndingTable::default()
+            }
+        }
+    }
+
+    /// The first ending the run qualifies for.
+    pub fn choose(&self, run: &RunSummary) -> &Ending {
+        self.endings
+            .iter()
+            .find(|ending| ending.when.matches(run))
+            .unwrap_or_else(|| self.endings.last().expect("there's always an ending"))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Ending> {
+        self.endings.iter().find(|ending| ending.name == name)
+    }
+}
+
+/// Which endings the player has reached, across sessions, for the title screen.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct EndingRecord {
+    /// The ending the player reached most recently.
+    last: Option<String>,
+    seen: BTreeSet<String>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl EndingRecord {
+    /// Loads the endings the player has reached, or starts over if nothing's been saved yet.
+    pub fn load(files: &FileManager) -> EndingRecord {
+        match files
+            .read_user_data(ENDING_RECORD_PATH)
+            .and_then(|text| EndingRecord::from_json(&text))
+        {
+            Ok(record) => record,
+            Err(e) => {
+                info!("no endings reached yet: {}", e);
+                EndingRecord::default()
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|e| anyhow!("unable to serialize endings: {}", e))
+    }
+
+    pub fn from_json(text: &str) -> Result<EndingRecord> {
+        serde_json::from_str(text).map_err(|e| anyhow!("unable to deserialize endings: {}", e))
+    }
+
+    pub fn record(&mut self, name: &str) {
+        info!("reached the {} ending", name);
+        self.last = Some(name.to_string());
+        self.seen.insert(name.to_string());
+        self.dirty = true;
+    }
+
+    pub fn last(&self) -> Option<&str> {
+        self.last.as_deref()
+    }
+
+    pub fn has_seen(&self, name: &str) -> bool {
+        self.seen.contains(name)
+    }
+
+    /// How many different endings the player has reached.
+    pub fn seen_count(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Saves the record, if anything has changed since the last time.
+    pub fn flush(&mut self, files: &FileManager) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        // A failed save isn't retried until another ending is seen, since this is called every
+        // frame, and the files might be read-only.
+        self.dirty = false;
+        files.write_user_data(ENDING_RECORD_PATH, self.to_json()?.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const ENDINGS: &str = r#"[
+        {"name": "speedrun", "title": "in a hurry", "lines": ["gone"], "when": {"max_seconds": 60}},
+        {"name": "pacifist", "title": "no harm done", "lines": ["peace"], "when": {"max_kills": 0}},
+        {"name": "escape", "title": "escape", "lines": ["out"]}
+    ]"#;
+
+    #[test]
+    fn choose_and_record() {
+        let table = EndingTable::from_json(ENDINGS).unwrap();
+        let run = |seconds, kills| RunSummary {
+            seconds,
+            health: 50.0,
+            kills,
+            respawns: 0,
+        };
+        assert_eq!(table.choose(&run(30, 4)).name, "speedrun");
+        assert_eq!(table.choose(&run(90, 0)).name, "pacifist");
+        assert_eq!(table.choose(&run(90, 4)).name, "escape");
+
+        let shipped = EndingTable::from_json(include_str!("../../assets/endings.json")).unwrap();
+        assert!(shipped.get(&shipped.choose(&run(999, 9)).name).is_some());
+
+        assert!(EndingTable::from_json("[]").is_err());
+        let conditional_last = ENDINGS.replace(
+            r#""lines": ["out"]"#,
+            r#""lines": [], "when": {"min_kills": 1}"#,
+        );
+        assert!(EndingTable::from_json(&conditional_last).is_err());
+        let duplicate = ENDINGS.replace("pacifist", "speedrun");
+        assert!(EndingTable::from_json(&duplicate).is_err());
+
+        let mut record = EndingRecord::default();
+        record.record("pacifist");
+        record.record("escape");
+        let copy = EndingRecord::from_json(&record.to_json().unwrap()).unwrap();
+        assert_eq!(copy.last(), Some("escape"));
+        assert!(copy.has_seen("pacifist"));
+        assert_eq!(copy.seen_count(), 2);
+
+        let files = FileManager::from_memory(HashMap::new()).unwrap();
+        assert!(record.flush(&files).is_err());
+        assert!(record.flush(&files).is_ok());
+    }
+}
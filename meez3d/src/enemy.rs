@@ -0,0 +1,348 @@
+use std::collections::VecDeque;
+use std::f32::consts::FRAC_PI_2;
+use std::fmt;
+
+use crate::billboard::Billboard;
+use crate::constants::FRAME_RATE;
+use crate::enemystats::EnemyStats;
+use crate::faction::Faction;
+use crate::utils::Color;
+
+/// How close, in tiles, an enemy has to be to fire at what it's after.
+const FIRE_RANGE: f32 = 8.0;
+/// How long an enemy waits between shots.
+const RELOAD_FRAMES: u32 = FRAME_RATE * 2;
+/// How close an enemy has to get to the player to catch them, in tiles.
+const CONTACT_DISTANCE: f32 = 0.5;
+/// How far ahead of itself an enemy checks for walls, in tiles.
+const ENEMY_RADIUS: f32 = 0.3;
+/// How long an enemy follows a path before finding a new one, so it keeps up with the player.
+const REPATH_FRAMES: u32 = FRAME_RATE / 2;
+/// How long an enemy flashes white after it's hurt.
+const HURT_FLASH_FRAMES: u32 = FRAME_RATE / 6;
+/// How long a spawned enemy takes to fade in out of a white flash.
+const SPAWN_FLASH_FRAMES: u32 = FRAME_RATE / 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EnemyState {
+    /// Walking straight ahead, and turning at walls.
+    Patrol,
+    /// Heading for where it last saw what it's after.
+    Chase,
+}
+
+impl fmt::Display for EnemyState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EnemyState::Patrol => "patrol",
+            EnemyState::Chase => "chase",
+        })
+    }
+}
+
+/// A character in the level that moves around on its own, and hunts whoever its faction is
+/// hostile to.
+#[derive(Debug, Clone)]
+pub struct Enemy {
+    /// How it looks and where it is.
+    pub billboard: Billboard,
+    /// Which type of enemy it is, which decides its stats.
+    pub kind: String,
+    pub stats: EnemyStats,
+    /// How much more blast damage it can take.
+    pub health: f32,
+    pub state: EnemyState,
+    /// Which way it's walking while it patrols.
+    heading: f32,
+    /// The cell it last saw its target in, as (row, column).
+    target: Option<(usize, usize)>,
+    /// The cells it's walking through to get to the target, not counting the one it's in.
+    path: VecDeque<(usize, usize)>,
+    /// How many more frames until it finds a new path.
+    repath: u32,
+    /// How many more frames until it can fire again.
+    reload: u32,
+    /// How many more frames it flashes for, out of how many the flash lasts.
+    flash: (u32, u32),
+    /// The tint it was placed with, which its type's tint is multiplied with.
+    own_tint: Color,
+}
+
+impl Enemy {
+    pub fn new(billboard: Billboard, kind: &str, stats: EnemyStats) -> Enemy {
+        Enemy {
+            billboard,
+            kind: kind.to_string(),
+            stats,
+            health: stats.health,
+            state: EnemyState::Patrol,
+            heading: 0.0,
+            target: None,
+            path: VecDeque::new(),
+            repath: 0,
+            reload: RELOAD_FRAMES,
+            flash: (0, 0),
+            own_tint: billboard.tint,
+        }
+    }
+
+    /// Recolors it with its type's tint, on top of the tint it was placed with.
+    pub fn set_type_tint(&mut self, tint: Color) {
+        self.billboard.tint = self.own_tint.multiply(tint);
+    }
+
+    /// Takes damage, flashing white for a moment.
+    pub fn hurt(&mut self, damage: f32) {
+        self.health -= damage;
+        self.start_flash(HURT_FLASH_FRAMES);
+    }
+
+    /// Starts fading in out of a white flash, like when a spawner makes it.
+    pub fn fade_in(&mut self) {
+        self.start_flash(SPAWN_FLASH_FRAMES);
+    }
+
+    fn start_flash(&mut self, frames: u32) {
+        self.flash = (frames, frames);
+        self.update_flash();
+    }
+
+    /// Fades the billboard's flash out as the flash runs down.
+    fn update_flash(&mut self) {
+        let (left, length) = self.flash;
+        self.billboard.flash = Color {
+            a: (255 * left).checked_div(length).unwrap_or(0) as u8,
+            ..Color::WHITE
+        };
+    }
+
+    /// Switches to new stats, like after a change in difficulty, keeping the same fraction of
+    /// its health.
+    pub fn set_stats(&mut self, stats: EnemyStats) {
+        self.health *= stats.health / self.stats.health;
+        self.stats = stats;
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.health <= 0.0
+    }
+
+    /// The side it's on. Anything without one is a monster.
+    pub fn faction(&self) -> Faction {
+        self.billboard.faction.unwrap_or(Faction::Monsters)
+    }
+
+    pub fn position(&self) -> (f32, f32) {
+        (self.billboard.x, self.billboard.y)
+    }
+
+    /// The cell it's in, as (row, column).
+    pub fn cell(&self) -> (usize, usize) {
+        (self.billboard.y as usize, self.billboard.x as usize)
+    }
+
+    fn distance_to(&self, x: f32, y: f32) -> f32 {
+        let (dx, dy) = (x - self.billboard.x, y - self.billboard.y);
+        (dx * dx + dy * dy).sqrt()
+    }
+
+    /// Whether it's close enough to a point, like where the player is, to catch them.
+    pub fn touches(&self, point: (f32, f32)) -> bool {
+        self.distance_to(point.0, point.1) < CONTACT_DISTANCE
+    }
+
+    /// Whether a point is close enough to see, if nothing's in the way.
+    pub fn in_sight_range(&self, point: (f32, f32)) -> bool {
+        self.distance_to(point.0, point.1) < self.stats.sight_radius
+    }
+
+    /// Starts chasing toward a cell, like where it saw its target.
+    pub fn alert(&mut self, cell: (usize, usize)) {
+        if self.target != Some(cell) {
+            self.repath = 0;
+        }
+        self.state = EnemyState::Chase;
+        self.target = Some(cell);
+    }
+
+    /// Advances it by a frame. target is where whatever it's after is, if it can see them,
+    /// find_path finds a route between two cells, including both ends, and blocked is whether a
+    /// point is inside a wall. Returns the direction to fire a rocket in, if it fires one.
+    pub fn update<F, B>(
+        &mut self,
+        target: Option<(f32, f32)>,
+        find_path: F,
+        blocked: B,
+    ) -> Option<f32>
+    where
+        F: FnOnce((usize, usize), (usize, usize)) -> Option<Vec<(usize, usize)>>,
+        B: Fn(f32, f32) -> bool,
+    {
+        self.reload = self.reload.saturating_sub(1);
+        if self.flash.0 > 0 {
+            self.flash.0 -= 1;
+            self.update_flash();
+        }
+        if let Some((x, y)) = target {
+            self.alert((y as usize, x as usize));
+        }
+        match self.state {
+            EnemyState::Patrol => {
+                if !self.step(self.heading, self.stats.patrol_speed(), &blocked) {
+                    self.heading = (self.heading + FRAC_PI_2) % (4.0 * FRAC_PI_2);
+                }
+            }
+            EnemyState::Chase => self.chase(target, find_path, &blocked),
+        }
+        let (x, y) = target?;
+        if self.reload > 0 || self.distance_to(x, y) > FIRE_RANGE {
+            return None;
+        }
+        self.reload = RELOAD_FRAMES;
+        Some((y - self.billboard.y).atan2(x - self.billboard.x))
+    }
+
+    fn chase<F, B>(&mut self, target: Option<(f32, f32)>, find_path: F, blocked: &B)
+    where
+        F: FnOnce((usize, usize), (usize, usize)) -> Option<Vec<(usize, usize)>>,
+        B: Fn(f32, f32) -> bool,
+    {
+        // With nothing in the way, go straight for them.
+        if let Some((x, y)) = target {
+            let angle = (y - self.billboard.y).atan2(x - self.billboard.x);
+            if self.step(angle, self.stats.chase_speed(), blocked) {
+                self.path.clear();
+                return;
+            }
+        }
+        let Some(target) = self.target else {
+            self.state = EnemyState::Patrol;
+            return;
+        };
+        self.repath = self.repath.saturating_sub(1);
+        if self.repath == 0 || self.path.is_empty() {
+            self.repath = REPATH_FRAMES;
+            self.path = find_path(self.cell(), target).unwrap_or_default().into();
+            // The first cell is the one it's already in.
+            self.path.pop_front();
+        }
+        let Some(&(row, column)) = self.path.front() else {
+            // It got where its target was, and they're gone.
+            self.state = EnemyState::Patrol;
+            self.target = None;
+            return;
+        };
+        let (x, y) = (column as f32 + 0.5, row as f32 + 0.5);
+        let distance = self.distance_to(x, y);
+        if distance <= self.stats.chase_speed() {
+            (self.billboard.x, self.billboard.y) = (x, y);
+            self.path.pop_front();
+            return;
+        }
+        let angle = (y - self.billboard.y).atan2(x - self.billboard.x);
+        self.step(angle, self.stats.chase_speed(), blocked);
+    }
+
+    /// Moves in a direction, unless that would walk into a wall. Returns whether it moved.
+    fn step<B: Fn(f32, f32) -> bool>(&mut self, angle: f32, speed: f32, blocked: &B) -> bool {
+        let (dx, dy) = (angle.cos(), angle.sin());
+        let (x, y) = (self.billboard.x + dx * speed, self.billboard.y + dy * speed);
+        if blocked(x + dx * ENEMY_RADIUS, y + dy * ENEMY_RADIUS) {
+            return false;
+        }
+        (self.billboard.x, self.billboard.y) = (x, y);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enemystats::DEFAULT_ENEMY_TYPE;
+    use crate::geometry::Rect;
+    use crate::pathfinder::find_path;
+    use crate::sprite::Sprite;
+
+    fn enemy(x: f32, y: f32) -> Enemy {
+        let area = Rect {
+            x: 0,
+            y: 0,
+            w: 64,
+            h: 64,
+        };
+        let billboard = Billboard {
+            floor: 0,
+            x,
+            y,
+            size: 1.0,
+            sprite: Sprite { id: 0, area },
+            source: area,
+            reversed: false,
+            faction: Some(Faction::Monsters),
+            spawner: None,
+            tint: Color::WHITE,
+            flash: Color::TRANSPARENT,
+        };
+        Enemy::new(billboard, DEFAULT_ENEMY_TYPE, EnemyStats::default())
+    }
+
+    #[test]
+    fn patrols_and_chases() {
+        let rows = ["#####", "#...#", "#.#.#", "#...#", "#####"];
+        let wall = |row: usize, column: usize| rows[row].as_bytes()[column] == b'#';
+        let blocked = |x: f32, y: f32| x < 0.0 || y < 0.0 || wall(y as usize, x as usize);
+        let path = |from, to| find_path(5, 5, from, |r, c| (r, c) == to, |r, c| !wall(r, c));
+
+        // Patrolling turns at walls instead of walking into them.
+        let mut patrol = enemy(1.5, 1.5);
+        for _ in 0..200 {
+            assert_eq!(patrol.update(None, path, blocked), None);
+            assert!(!wall(patrol.cell().0, patrol.cell().1));
+        }
+        assert_eq!(patrol.state, EnemyState::Patrol);
+
+        // Having seen the player across the map, it goes around the pillar to where they were.
+        let mut chaser = enemy(1.5, 1.5);
+        let player = (3.5, 3.5);
+        chaser.update(Some(player), path, blocked);
+        assert_eq!(chaser.state, EnemyState::Chase);
+        let mut frames = 0;
+        while !chaser.touches(player) && frames < 500 {
+            chaser.update(None, path, blocked);
+            assert!(!wall(chaser.cell().0, chaser.cell().1));
+            frames += 1;
+        }
+        assert!(chaser.touches(player));
+
+        // Once it's where the player was, and they're not, it goes back to patrolling.
+        for _ in 0..100 {
+            chaser.update(None, path, blocked);
+        }
+        assert_eq!(chaser.state, EnemyState::Patrol);
+
+        // It fires at what it can see, each time it's reloaded, even when it's stuck.
+        let mut shooter = enemy(1.5, 1.5);
+        let shots: Vec<f32> = (0..RELOAD_FRAMES * 2)
+            .filter_map(|_| shooter.update(Some((1.5, 3.5)), path, |_, _| true))
+            .collect();
+        assert_eq!(shots.len(), 2);
+        assert!((shots[0] - FRAC_PI_2).abs() < 0.01);
+    }
+
+    #[test]
+    fn flashes_when_hurt() {
+        let mut enemy = enemy(1.5, 1.5);
+        assert_eq!(enemy.billboard.flash.a, 0);
+        enemy.hurt(5.0);
+        assert_eq!(enemy.health, EnemyStats::default().health - 5.0);
+        assert_eq!(enemy.billboard.flash, Color::WHITE);
+
+        let mut alphas = Vec::new();
+        for _ in 0..HURT_FLASH_FRAMES {
+            enemy.update(None, |_, _| None, |_, _| true);
+            alphas.push(enemy.billboard.flash.a);
+        }
+        assert!(alphas.windows(2).all(|pair| pair[1] < pair[0]));
+        assert_eq!(alphas.last(), Some(&0));
+    }
+}
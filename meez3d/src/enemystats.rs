@@ -0,0 +1,308 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Error, Result};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::constants::FRAME_RATE;
+use crate::filemanager::FileManager;
+use crate::utils::Color;
+
+/// How tough enemies are, "easy", "normal", or "hard".
+pub const DIFFICULTY_CVAR: &str = "difficulty";
+/// The data file with the stats for every type of enemy, at every difficulty.
+pub const ENEMY_STATS_PATH: &str = "assets/enemies.json";
+/// The type of enemies that don't say which type they are, which every stats file has to have.
+pub const DEFAULT_ENEMY_TYPE: &str = "default";
+
+/// Enemies patrol at this fraction of the speed they chase at.
+const PATROL_FRACTION: f32 = 0.6;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+}
+
+impl FromStr for Difficulty {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "easy" => Difficulty::Easy,
+            "normal" => Difficulty::Normal,
+            "hard" => Difficulty::Hard,
+            _ => bail!("invalid difficulty: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Normal => "normal",
+            Difficulty::Hard => "hard",
+        })
+    }
+}
+
+/// How tough one type of enemy is at one difficulty.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnemyStats {
+    /// How much blast damage it takes to kill it.
+    pub health: f32,
+    /// How fast it chases, in tiles per second.
+    pub speed: f32,
+    /// The damage its rockets do at the center of the blast.
+    pub damage: f32,
+    /// How far away it can see, in tiles.
+    pub sight_radius: f32,
+}
+
+impl Default for EnemyStats {
+    fn default() -> Self {
+        EnemyStats {
+            health: 30.0,
+            speed: 2.1,
+            damage: 20.0,
+            sight_radius: 10.0,
+        }
+    }
+}
+
+impl EnemyStats {
+    /// How far it moves each frame while chasing, in tiles.
+    pub fn chase_speed(&self) -> f32 {
+        self.speed / FRAME_RATE as f32
+    }
+
+    /// How far it moves each frame while patrolling, in tiles.
+    pub fn patrol_speed(&self) -> f32 {
+        self.chase_speed() * PATROL_FRACTION
+    }
+
+    fn validate(&self) -> Result<()> {
+        let fields = [
+            ("health", self.health),
+            ("speed", self.speed),
+            ("damage", self.damage),
+            ("sight_radius", self.sight_radius),
+        ];
+        for (name, value) in fields {
+            if !value.is_finite() || value <= 0.0 {
+                bail!("invalid {}: {}", name, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One type of enemy in the stats file.
+#[derive(Debug, Deserialize)]
+struct EnemyTypeJson {
+    /// Multiplied with the sprite's colors, like "#ff8080", so a tougher type can reuse another
+    /// type's art in a different color.
+    #[serde(default)]
+    tint: Option<String>,
+    #[serde(flatten)]
+    stats: BTreeMap<Difficulty, EnemyStats>,
+}
+
+/// The stats for one type of enemy, at every difficulty, and how it's recolored.
+#[derive(Debug, Clone, PartialEq)]
+struct EnemyType {
+    stats: BTreeMap<Difficulty, EnemyStats>,
+    tint: Color,
+}
+
+/// The stats for every type of enemy, at every difficulty, so they can be balanced without
+/// rebuilding the game. The file is checked for changes while the game runs.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct EnemyStatsTable {
+    types: BTreeMap<String, EnemyType>,
+    /// The text it was last loaded from, to tell when the file has changed.
+    source: String,
+}
+
+impl EnemyStatsTable {
+    /// Parses a JSON object with an entry for each enemy type, each with an entry for each
+    /// difficulty and an optional tint, like
+    /// `{"default": {"tint": "#ff8080", "easy": {"health": 20, ...}, ...}}`.
+    pub fn from_json(text: &str) -> Result<EnemyStatsTable> {
+        let json: BTreeMap<String, EnemyTypeJson> = serde_json::from_str(text)
+            .map_err(|e| anyhow!("unable to deserialize enemy stats: {}", e))?;
+        if !json.contains_key(DEFAULT_ENEMY_TYPE) {
+            bail!("missing enemy type: {}", DEFAULT_ENEMY_TYPE);
+        }
+        let mut types = BTreeMap::new();
+        for (kind, json) in json.into_iter() {
+            for difficulty in Difficulty::ALL {
+                let Some(stats) = json.stats.get(&difficulty) else {
+                    bail!("missing {} stats for enemy type {}", difficulty, kind);
+                };
+                stats
+                    .validate()
+                    .map_err(|e| anyhow!("{} stats for enemy type {}: {}", difficulty, kind, e))?;
+            }
+            let tint = match json.tint {
+                Some(tint) => tint
+                    .parse()
+                    .map_err(|e| anyhow!("tint for enemy type {}: {}", kind, e))?,
+                None => Color::WHITE,
+            };
+            let stats = json.stats;
+            types.insert(kind, EnemyType { stats, tint });
+        }
+        Ok(EnemyStatsTable {
+            types,
+            source: text.to_string(),
+        })
+    }
+
+    /// Loads the stats file, or falls back to built-in stats if it's missing or invalid.
+    pub fn load(files: &FileManager) -> EnemyStatsTable {
+        let table = files
+            .read_to_string(Path::new(ENEMY_STATS_PATH))
+            .and_then(|text| EnemyStatsTable::from_json(&text));
+        match table {
+            Ok(table) => table,
+            Err(e) => {
+                info!("using built-in enemy stats: {}", e);
+                EnemyStatsTable::default()
+            }
+        }
+    }
+
+    /// Loads the stats file again if it's changed, returning whether the stats did. If the new
+    /// file is invalid, the old stats are kept.
+    pub fn reload(&mut self, files: &FileManager) -> bool {
+        let Ok(text) = files.read_to_string(Path::new(ENEMY_STATS_PATH)) else {
+            return false;
+        };
+        if text == self.source {
+            return false;
+        }
+        match EnemyStatsTable::from_json(&text) {
+            Ok(table) => {
+                info!("reloaded enemy stats");
+                *self = table;
+                true
+            }
+            Err(e) => {
+                error!("not reloading enemy stats: {}", e);
+                // Don't complain again until it changes.
+                self.source = text;
+                false
+            }
+        }
+    }
+
+    /// The type of enemy with the given name, or the default type if there's no such type.
+    fn enemy_type(&self, kind: &str) -> Option<&EnemyType> {
+        self.types
+            .get(kind)
+            .or_else(|| self.types.get(DEFAULT_ENEMY_TYPE))
+    }
+
+    /// The stats for a type of enemy, or for the default type if there's no such type.
+    pub fn get(&self, kind: &str, difficulty: Difficulty) -> EnemyStats {
+        self.enemy_type(kind)
+            .and_then(|enemy_type| enemy_type.stats.get(&difficulty))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The tint for a type of enemy's sprite, which is white unless the type is recolored.
+    pub fn tint(&self, kind: &str) -> Color {
+        self.enemy_type(kind)
+            .map_or(Color::WHITE, |enemy_type| enemy_type.tint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    const STATS: &str = r#"{
+        "default": {
+            "easy": {"health": 20, "speed": 1.5, "damage": 10, "sight_radius": 7},
+            "normal": {"health": 30, "speed": 2.1, "damage": 20, "sight_radius": 10},
+            "hard": {"health": 50, "speed": 2.7, "damage": 30, "sight_radius": 12}
+        }
+    }"#;
+
+    #[test]
+    fn load_and_validate() {
+        let table = EnemyStatsTable::from_json(STATS).unwrap();
+        assert_eq!(table.get("default", Difficulty::Hard).health, 50.0);
+        assert_eq!(table.get("brute", Difficulty::Easy).sight_radius, 7.0);
+        assert_eq!(
+            EnemyStatsTable::default().get("default", Difficulty::Normal),
+            EnemyStats::default()
+        );
+
+        // The stats the game ships with have to load, too.
+        let shipped = EnemyStatsTable::from_json(include_str!("../../assets/enemies.json"));
+        assert!(shipped.unwrap().get("brute", Difficulty::Hard).health > 0.0);
+
+        let missing_difficulty = STATS.replace(r#""easy""#, r#""medium""#);
+        assert!(EnemyStatsTable::from_json(&missing_difficulty).is_err());
+        let negative = STATS.replace(r#""health": 20"#, r#""health": -20"#);
+        assert!(EnemyStatsTable::from_json(&negative).is_err());
+        let unknown_field = STATS.replace(r#""damage": 10"#, r#""armor": 10"#);
+        assert!(EnemyStatsTable::from_json(&unknown_field).is_err());
+        let no_default = STATS.replace(r#""default""#, r#""brute""#);
+        assert!(EnemyStatsTable::from_json(&no_default).is_err());
+        let bad_tint = STATS.replace(r#""default": {"#, r#""default": {"tint": "red","#);
+        assert!(EnemyStatsTable::from_json(&bad_tint).is_err());
+        assert!("medium".parse::<Difficulty>().is_err());
+    }
+
+    #[test]
+    fn tints() {
+        let tinted = STATS.replace(r#""default": {"#, r##""default": {"tint": "#ff8080","##);
+        let table = EnemyStatsTable::from_json(&tinted).unwrap();
+        let pink = "#ff8080".parse().unwrap();
+        assert_eq!(table.tint("default"), pink);
+        assert_eq!(table.tint("brute"), pink);
+        assert_eq!(table.get("default", Difficulty::Easy).health, 20.0);
+        assert_eq!(EnemyStatsTable::default().tint("default"), Color::WHITE);
+        let plain = EnemyStatsTable::from_json(STATS).unwrap();
+        assert_eq!(plain.tint("default"), Color::WHITE);
+    }
+
+    #[test]
+    fn reload() {
+        let files = |text: &str| {
+            let mut map = HashMap::new();
+            map.insert(PathBuf::from(ENEMY_STATS_PATH), text.as_bytes().to_vec());
+            FileManager::from_memory(map).unwrap()
+        };
+        let mut table = EnemyStatsTable::load(&files(STATS));
+        assert!(!table.reload(&files(STATS)));
+
+        // Changes get picked up, but a broken file doesn't replace good stats.
+        let faster = STATS.replace(r#""speed": 2.1"#, r#""speed": 3.0"#);
+        assert!(table.reload(&files(&faster)));
+        assert_eq!(table.get("default", Difficulty::Normal).speed, 3.0);
+        assert!(!table.reload(&files("{")));
+        assert_eq!(table.get("default", Difficulty::Normal).speed, 3.0);
+    }
+}
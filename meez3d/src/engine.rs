@@ -0,0 +1,336 @@
+use std::mem;
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use log::{error, warn};
+use rand::random;
+
+use crate::benchmark::{Benchmark, BenchmarkReport};
+use crate::cliprecorder::{take_clip_request, timestamped_clip_name, ClipRecorder};
+use crate::constants::{RENDER_HEIGHT, RENDER_WIDTH};
+use crate::filemanager::FileManager;
+use crate::font::Font;
+use crate::imagemanager::{ImageLoader, ImageManager};
+use crate::inputmanager::{InputRecorder, InputSnapshot};
+use crate::rendercontext::{
+    RenderContext, MAX_SAFE_AREA, MAX_UI_SCALE, MIN_UI_SCALE, SAFE_AREA_CVAR, UI_SCALE_CVAR,
+};
+use crate::renderer::Renderer;
+use crate::replay::Replay;
+use crate::session::{Session, WindowGeometry};
+use crate::soundmanager::{SoundManager, SOUND_MANIFEST_PATH, VOLUME_CVAR};
+use crate::stagemanager::StageManager;
+use crate::windowconfig::WindowConfig;
+
+/// Callbacks a host can register with the Engine to run code every frame.
+///
+/// This is useful for overlays, analytics, and tools that need to see the game without being
+/// built into it. All of the methods have empty default implementations.
+pub trait EnginePlugin {
+    /// Called once, before the first frame is updated.
+    fn on_start(&mut self, _game: &mut StageManager) {}
+
+    /// Called every frame, before the scenes are updated.
+    fn pre_update(&mut self, _context: &RenderContext, _game: &mut StageManager) {}
+
+    /// Called every frame, after the scenes are updated, as long as the game is still running.
+    fn post_update(&mut self, _context: &RenderContext, _game: &mut StageManager) {}
+
+    /// Called every frame, after the scenes are drawn, but before the context is rendered.
+    fn pre_render(&mut self, _context: &mut RenderContext, _font: &Font, _game: &StageManager) {}
+}
+
+/// Ties together the pieces of the game that every host needs to run a frame.
+pub struct Engine {
+    stage_manager: StageManager,
+    files: FileManager,
+    sounds: SoundManager,
+    font: Font,
+    frame: u64,
+    plugins: Vec<Box<dyn EnginePlugin>>,
+    /// Reset every frame rather than recreated, so its batches keep their memory.
+    context: RenderContext,
+    /// The frame before this one, swapped with the context every frame, so it's still around if
+    /// a transition needs the outgoing scene's last frame.
+    last_context: RenderContext,
+    clips: ClipRecorder,
+    /// The settings from the last run, which are saved again when the host exits.
+    session: Session,
+    /// How the host's window is presented.
+    window_config: WindowConfig,
+    /// The seed the random levels come from, for replays.
+    seed: u64,
+    /// The game so far, if the host is recording it to check with --verify-replay.
+    replay: Option<Replay>,
+}
+
+impl Engine {
+    /// An engine that starts with the settings the player had when the game last exited.
+    pub fn new(
+        files: FileManager,
+        images: &mut dyn ImageLoader,
+        font: Font,
+        sounds: SoundManager,
+    ) -> Result<Engine> {
+        let mut engine = Engine::with_seed(files, images, font, sounds, random())?;
+        engine.session = Session::load(&engine.files);
+        engine.session.apply_cvars(engine.stage_manager.cvars_mut());
+        Ok(engine)
+    }
+
+    /// An engine whose random levels come from the given seed, for replays.
+    ///
+    /// Every sound in the sound manifest is registered with the sound manager. The last
+    /// session's settings aren't restored, so replays and benchmarks play out the same.
+    pub fn with_seed(
+        files: FileManager,
+        images: &mut dyn ImageLoader,
+        font: Font,
+        mut sounds: SoundManager,
+        seed: u64,
+    ) -> Result<Engine> {
+        if let Err(e) = sounds.load_manifest(&files, Path::new(SOUND_MANIFEST_PATH)) {
+            error!("unable to load sounds: {}", e);
+        }
+        let stage_manager = StageManager::with_seed(&files, images, seed)?;
+        Ok(Engine {
+            stage_manager,
+            files,
+            sounds,
+            font,
+            frame: 0,
+            plugins: Vec::new(),
+            context: RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0)?,
+            last_context: RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0)?,
+            clips: ClipRecorder::new(),
+            session: Session::new(),
+            window_config: WindowConfig::default(),
+            seed,
+            replay: None,
+        })
+    }
+
+    pub fn add_plugin(&mut self, mut plugin: Box<dyn EnginePlugin>) {
+        if self.frame > 0 {
+            plugin.on_start(&mut self.stage_manager);
+        }
+        self.plugins.push(plugin);
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn files(&self) -> &FileManager {
+        &self.files
+    }
+
+    pub fn stage_manager(&self) -> &StageManager {
+        &self.stage_manager
+    }
+
+    pub fn stage_manager_mut(&mut self) -> &mut StageManager {
+        &mut self.stage_manager
+    }
+
+    /// The title, icon, and minimum size of the window.
+    pub fn window_config(&self) -> &WindowConfig {
+        &self.window_config
+    }
+
+    /// Changes the title, icon, or minimum size of the window. It takes effect the next time
+    /// the host configures its window.
+    pub fn set_window_config(&mut self, window_config: WindowConfig) {
+        self.window_config = window_config;
+    }
+
+    /// Gives an SDL window the title, icon, and minimum size from the window config, loading the
+    /// icon through the engine's files.
+    #[cfg(feature = "sdl2")]
+    pub fn configure_sdl_window(&self, window: &mut sdl2::video::Window) -> Result<()> {
+        self.window_config.apply_to_sdl(window, &self.files)
+    }
+
+    /// Gives a winit window the title, icon, and minimum size from the window config, loading
+    /// the icon through the engine's files.
+    #[cfg(feature = "winit")]
+    pub fn configure_winit_window(&self, window: &winit::window::Window) -> Result<()> {
+        self.window_config.apply_to_winit(window, &self.files)
+    }
+
+    /// Where the window was when the game last exited, if it was windowed, so the host can put
+    /// it back.
+    pub fn last_window(&self) -> Option<WindowGeometry> {
+        self.session.window
+    }
+
+    /// Saves the current settings, and the window if it's given, for the next run. Hosts call
+    /// this as they exit. Without a window, the last one saved is kept.
+    pub fn save_session(&mut self, window: Option<WindowGeometry>) {
+        self.session.read_cvars(self.stage_manager.cvars());
+        if window.is_some() {
+            self.session.window = window;
+        }
+        if let Err(e) = self.session.save(&self.files) {
+            error!("unable to save session: {}", e);
+        }
+    }
+
+    /// Starts recording a replay of the game, with the settings it's played with, so it can be
+    /// checked later. It has to start before the first frame, since replays play from the seed.
+    pub fn record_replay(&mut self) -> Result<()> {
+        if self.frame != 0 {
+            bail!("replays have to be recorded from the first frame");
+        }
+        self.replay = Some(Replay::new(self.seed));
+        Ok(())
+    }
+
+    /// The replay recorded so far, if record_replay was called.
+    pub fn replay(&self) -> Option<&Replay> {
+        self.replay.as_ref()
+    }
+
+    /// The context drawn by the last call to run_one_frame, ready to be rendered.
+    pub fn context(&self) -> &RenderContext {
+        &self.context
+    }
+
+    /// Keeps the frame the renderer just drew for clips, while they're being recorded, and saves
+    /// a clip if the save clip cvar asks for one, or if save_clip is set, like when the host's
+    /// hotkey is pressed. Hosts call this after each render.
+    pub fn record_clip<R: Renderer + ?Sized>(&mut self, renderer: &mut R, save_clip: bool) {
+        self.clips.apply_cvars(self.stage_manager.cvars());
+        match renderer.take_captured_frame() {
+            Some(frame) => self.clips.add_frame(&frame),
+            None => self.clips.skip_frame(),
+        }
+        // Only the next frame the clip keeps is read back, since reading back can stall.
+        renderer.set_capturing(self.clips.wants_frame());
+        let name = take_clip_request(self.stage_manager.cvars_mut())
+            .or_else(|| save_clip.then(timestamped_clip_name));
+        if let Some(name) = name {
+            if let Err(e) = self.clips.save(&self.files, &name) {
+                error!("unable to save clip: {}", e);
+            }
+        }
+    }
+
+    /// Plays the benchmark's level with its inputs, updating, drawing, and rendering every frame
+    /// as fast as it can, and reports how long each frame took.
+    ///
+    /// The host should turn off vsync first, and start the engine from BENCHMARK_SEED, so runs
+    /// can be compared.
+    pub fn run_benchmark<R: Renderer>(
+        &mut self,
+        images: &mut ImageManager<R>,
+        benchmark: &Benchmark,
+    ) -> Result<BenchmarkReport> {
+        let mut inputs = match &benchmark.inputs {
+            Some(path) => {
+                let text = self
+                    .files
+                    .read_to_string(path)
+                    .with_context(|| format!("unable to load benchmark inputs at {:?}", path))?;
+                InputRecorder::from_text(&text)?
+            }
+            None => InputRecorder::new(),
+        };
+        self.stage_manager
+            .load_level(&benchmark.level, &self.files, images)?;
+
+        let mut frame_times = Vec::with_capacity(benchmark.frames as usize);
+        for _ in 0..benchmark.frames {
+            let start = Instant::now();
+            let frame_inputs = inputs.playback(self.frame);
+            if !self.run_one_frame(&frame_inputs, images)? {
+                warn!("benchmark ended early at frame {}", self.frame);
+                break;
+            }
+            images.render(&self.context)?;
+            frame_times.push(start.elapsed());
+        }
+        Ok(BenchmarkReport::new(
+            &benchmark.level.to_string_lossy(),
+            &frame_times,
+        ))
+    }
+
+    /// Updates and draws one frame into the context, returning false if the game is over.
+    pub fn run_one_frame(
+        &mut self,
+        inputs: &InputSnapshot,
+        images: &mut dyn ImageLoader,
+    ) -> Result<bool> {
+        profile_scope!("frame", frame = self.frame);
+        if self.frame == 0 {
+            for plugin in self.plugins.iter_mut() {
+                plugin.on_start(&mut self.stage_manager);
+            }
+        }
+
+        mem::swap(&mut self.context, &mut self.last_context);
+        let outgoing = self.last_context.outgoing.take();
+        let context = &mut self.context;
+        context.reset(self.frame);
+        let ui_scale = self
+            .stage_manager
+            .cvars()
+            .get_parsed::<f32>(UI_SCALE_CVAR)
+            .filter(|scale| scale.is_finite())
+            .map_or(1.0, |scale| scale.clamp(MIN_UI_SCALE, MAX_UI_SCALE));
+        context.set_ui_scale(ui_scale);
+        let safe_area_inset = self
+            .stage_manager
+            .cvars()
+            .get_parsed::<f32>(SAFE_AREA_CVAR)
+            .filter(|inset| inset.is_finite())
+            .map_or(0.0, |inset| inset.clamp(0.0, MAX_SAFE_AREA));
+        context.set_safe_area_inset(safe_area_inset);
+        let volume = self.stage_manager.cvars().get_parsed(VOLUME_CVAR);
+        self.sounds.set_volume(volume.unwrap_or(1.0));
+        context.renderer_stats = images.renderer_stats();
+
+        for plugin in self.plugins.iter_mut() {
+            plugin.pre_update(context, &mut self.stage_manager);
+        }
+        if let Some(replay) = &mut self.replay {
+            replay.record_cvars(self.stage_manager.cvars());
+        }
+        if !self
+            .stage_manager
+            .update(context, inputs, &self.files, images, &mut self.sounds)?
+        {
+            return Ok(false);
+        }
+        if let Some(replay) = &mut self.replay {
+            replay.record_frame(inputs, self.stage_manager.state_hash());
+        }
+        for plugin in self.plugins.iter_mut() {
+            plugin.post_update(context, &mut self.stage_manager);
+        }
+
+        context.outgoing = match self.stage_manager.transition() {
+            Some(transition) if transition.is_starting() => {
+                // The last frame was the outgoing scene's, so it's kept for the whole transition.
+                let blank = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, self.frame)?;
+                let mut last = mem::replace(&mut self.last_context, blank);
+                last.transition = None;
+                last.outgoing = None;
+                Some(Box::new(last))
+            }
+            Some(_) => outgoing,
+            None => None,
+        };
+
+        self.stage_manager.draw(context, &self.font);
+        for plugin in self.plugins.iter_mut() {
+            plugin.pre_render(context, &self.font, &self.stage_manager);
+        }
+        images.resolve_sprites(context);
+
+        self.frame += 1;
+        Ok(true)
+    }
+}
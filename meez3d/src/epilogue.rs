@@ -0,0 +1,128 @@
+use crate::constants::FRAME_RATE;
+use crate::ending::Ending;
+use crate::font::Font;
+use crate::geometry::Point;
+use crate::inputmanager::InputSnapshot;
+use crate::narration::Narrator;
+use crate::rendercontext::{PostprocessProfile, RenderContext, RenderLayer};
+use crate::scene::{Scene, SceneResult};
+use crate::soundmanager::SoundManager;
+use crate::utils::Color;
+
+const MARGIN: i32 = 16;
+/// How long each line is on screen before the next one appears.
+const LINE_FRAMES: u32 = 2 * FRAME_RATE;
+const BACKGROUND_COLOR: Color = Color {
+    r: 0,
+    g: 0,
+    b: 0,
+    a: 0xff,
+};
+const TITLE_COLOR: Color = Color {
+    r: 0xff,
+    g: 0xdd,
+    b: 0x44,
+    a: 0xff,
+};
+
+/// The text for the ending the player reached, shown a line at a time after the final exit.
+///
+/// Ok shows the rest of the text at once, and once it's all shown, ok or cancel goes to the title
+/// screen.
+pub struct Epilogue {
+    title: String,
+    lines: Vec<String>,
+    /// How many lines are showing.
+    shown: usize,
+    /// How many frames the newest line has been showing.
+    frame: u32,
+    /// How many lines have been narrated, or None if the title hasn't been yet.
+    narrated: Option<usize>,
+}
+
+impl Epilogue {
+    pub fn new(ending: &Ending) -> Epilogue {
+        Epilogue {
+            title: ending.title.clone(),
+            lines: ending.lines.clone(),
+            shown: 0,
+            frame: 0,
+            narrated: None,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.shown >= self.lines.len()
+    }
+}
+
+impl Scene for Epilogue {
+    fn update(
+        &mut self,
+        _context: &RenderContext,
+        inputs: &InputSnapshot,
+        _sounds: &mut SoundManager,
+    ) -> SceneResult {
+        if self.is_done() {
+            if inputs.ok_clicked || inputs.cancel_clicked {
+                return SceneResult::PushMenu;
+            }
+            return SceneResult::Continue;
+        }
+        if inputs.ok_clicked {
+            self.shown = self.lines.len();
+            return SceneResult::Continue;
+        }
+        self.frame += 1;
+        if self.shown == 0 || self.frame >= LINE_FRAMES {
+            self.shown += 1;
+            self.frame = 0;
+        }
+        SceneResult::Continue
+    }
+
+    fn name(&self) -> &str {
+        "epilogue"
+    }
+
+    fn postprocess(&self) -> PostprocessProfile {
+        PostprocessProfile::CLEAN
+    }
+
+    fn flush_narration(&mut self, narrator: &mut dyn Narrator) {
+        let narrated = match self.narrated {
+            Some(narrated) => narrated,
+            None => {
+                narrator.narrate(&self.title);
+                0
+            }
+        };
+        for line in self.lines[narrated..self.shown].iter() {
+            narrator.narrate(line);
+        }
+        self.narrated = Some(self.shown);
+    }
+
+    fn draw(&self, context: &mut RenderContext, font: &Font, _previous: Option<&dyn Scene>) {
+        let area = context.ui_area();
+        context.fill_rect(area, RenderLayer::Hud, BACKGROUND_COLOR);
+        let centered = |text: &str| (area.w - font.text_width(text)) / 2;
+
+        let mut y = area.h / 3;
+        context.set_tint(RenderLayer::Hud, TITLE_COLOR);
+        let position = Point::new(centered(&self.title), y);
+        font.draw_string(context, RenderLayer::Hud, position, &self.title);
+        context.set_tint(RenderLayer::Hud, Color::WHITE);
+
+        y += font.char_height + MARGIN * 2;
+        for line in self.lines.iter().take(self.shown) {
+            font.draw_string(
+                context,
+                RenderLayer::Hud,
+                Point::new(centered(line), y),
+                line,
+            );
+            y += font.char_height + MARGIN;
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use crate::constants::FRAME_RATE;
+use crate::faction::Faction;
+use crate::utils::Color;
+
+/// How long an explosion's fireball lasts.
+const EXPLOSION_FRAMES: u32 = FRAME_RATE / 2;
+/// How long the screen shakes after an explosion.
+const SHAKE_FRAMES: u32 = FRAME_RATE / 3;
+/// How far the screen shakes from an explosion right on top of the player, in pixels.
+const MAX_SHAKE: f32 = 12.0;
+/// How long the light from an explosion lasts.
+const FLASH_FRAMES: u32 = FRAME_RATE / 6;
+/// Shake and flash fade out over this many explosion radii from the player.
+const FEEL_RADII: f32 = 4.0;
+
+/// The colors of the fireball, from when it starts to when it's just smoke.
+const FIREBALL_COLORS: [Color; 4] = [
+    Color {
+        r: 0xff,
+        g: 0xff,
+        b: 0xc0,
+        a: 0xff,
+    },
+    Color {
+        r: 0xff,
+        g: 0xb0,
+        b: 0x30,
+        a: 0xff,
+    },
+    Color {
+        r: 0xd0,
+        g: 0x40,
+        b: 0x10,
+        a: 0xe0,
+    },
+    Color {
+        r: 0x50,
+        g: 0x50,
+        b: 0x50,
+        a: 0x80,
+    },
+];
+
+/// A blast at a point in the map, which damages things around it and then plays out its fireball.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Explosion {
+    pub x: f32,
+    pub y: f32,
+    /// How far the blast reaches, in tiles.
+    pub radius: f32,
+    /// The damage done at the center, which falls off to nothing at the radius.
+    pub damage: f32,
+    /// Whose rocket it was, if anybody's, which decides who it can hurt.
+    pub source: Option<Faction>,
+    frame: u32,
+}
+
+impl Explosion {
+    pub fn new(x: f32, y: f32, radius: f32, damage: f32) -> Explosion {
+        Explosion {
+            x,
+            y,
+            radius,
+            damage,
+            source: None,
+            frame: 0,
+        }
+    }
+
+    /// The same explosion, set off by a faction.
+    pub fn with_source(self, source: Faction) -> Explosion {
+        Explosion {
+            source: Some(source),
+            ..self
+        }
+    }
+
+    /// Advances the explosion by a frame, returning false once it's over.
+    pub fn update(&mut self) -> bool {
+        self.frame = (self.frame + 1).min(EXPLOSION_FRAMES);
+        !self.is_done()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.frame >= EXPLOSION_FRAMES
+    }
+
+    pub fn distance_to(&self, x: f32, y: f32) -> f32 {
+        ((x - self.x).powi(2) + (y - self.y).powi(2)).sqrt()
+    }
+
+    /// The damage done to something at the given distance, which has to be in line of sight.
+    pub fn damage_at(&self, distance: f32) -> f32 {
+        if distance >= self.radius {
+            return 0.0;
+        }
+        self.damage * (1.0 - distance / self.radius)
+    }
+
+    /// How strongly the player feels the explosion at a distance, from 0 to 1.
+    fn nearness(&self, distance: f32) -> f32 {
+        (1.0 - distance / (self.radius * FEEL_RADII)).clamp(0.0, 1.0)
+    }
+
+    /// How far to shake the screen, in pixels, for a player at the given distance.
+    ///
+    /// The shake alternates direction every frame, and dies down over SHAKE_FRAMES.
+    pub fn shake(&self, distance: f32) -> (i32, i32) {
+        if self.frame >= SHAKE_FRAMES {
+            return (0, 0);
+        }
+        let fade = 1.0 - self.frame as f32 / SHAKE_FRAMES as f32;
+        let amount = MAX_SHAKE * fade * self.nearness(distance);
+        // A fixed pattern, so replays shake the same way.
+        let (dx, dy) =
+            [(1.0, -0.5), (-0.75, 1.0), (0.5, 0.75), (-1.0, -0.75)][self.frame as usize % 4];
+        ((dx * amount) as i32, (dy * amount) as i32)
+    }
+
+    /// How bright the flash of light is, from 0 to 1, for a player at the given distance.
+    pub fn flash(&self, distance: f32) -> f32 {
+        if self.frame >= FLASH_FRAMES {
+            return 0.0;
+        }
+        (1.0 - self.frame as f32 / FLASH_FRAMES as f32) * self.nearness(distance)
+    }
+
+    /// How big the fireball is, as a fraction of the radius, and its color right now.
+    pub fn fireball(&self) -> (f32, Color) {
+        let t = self.frame as f32 / EXPLOSION_FRAMES as f32;
+        let index = ((t * FIREBALL_COLORS.len() as f32) as usize).min(FIREBALL_COLORS.len() - 1);
+        // Grow quickly, then hang in the air as it turns to smoke.
+        let size = 0.3 + 0.7 * (1.0 - (1.0 - t).powi(3));
+        (size, FIREBALL_COLORS[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falloff_and_lifetime() {
+        let mut explosion = Explosion::new(5.0, 5.0, 2.0, 100.0);
+        assert_eq!(explosion.damage_at(0.0), 100.0);
+        assert_eq!(explosion.damage_at(1.0), 50.0);
+        assert_eq!(explosion.damage_at(2.5), 0.0);
+        assert_eq!(explosion.distance_to(8.0, 9.0), 5.0);
+
+        // Nearby players feel it more, and far away ones don't feel it at all.
+        assert!(explosion.flash(0.0) > explosion.flash(4.0));
+        assert_eq!(explosion.flash(100.0), 0.0);
+        assert_eq!(explosion.shake(100.0), (0, 0));
+        assert_ne!(explosion.shake(1.0), (0, 0));
+
+        let (start_size, _) = explosion.fireball();
+        let mut frames = 1;
+        while explosion.update() {
+            frames += 1;
+        }
+        assert_eq!(frames, EXPLOSION_FRAMES);
+        assert!(explosion.fireball().0 > start_size);
+        assert_eq!(explosion.shake(0.0), (0, 0));
+        assert_eq!(explosion.flash(0.0), 0.0);
+    }
+}
@@ -0,0 +1,161 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use crate::cvars::Cvars;
+
+/// Who's hostile to whom, as a list of pairs like "player>monsters monsters>player", where "a>b"
+/// means a goes after b, and a's rockets hurt b.
+pub const HOSTILITY_CVAR: &str = "hostility";
+
+const DEFAULT_HOSTILITY: &str = "player>monsters monsters>player";
+
+/// Which side a character is on, which decides who fights whom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Faction {
+    Player,
+    Monsters,
+    /// Bystanders, who nobody goes after by default.
+    Neutral,
+}
+
+impl Faction {
+    const ALL: [Faction; 3] = [Faction::Player, Faction::Monsters, Faction::Neutral];
+
+    fn index(self) -> usize {
+        match self {
+            Faction::Player => 0,
+            Faction::Monsters => 1,
+            Faction::Neutral => 2,
+        }
+    }
+}
+
+impl FromStr for Faction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "player" => Faction::Player,
+            "monsters" => Faction::Monsters,
+            "neutral" => Faction::Neutral,
+            _ => bail!("invalid faction: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for Faction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Faction::Player => "player",
+            Faction::Monsters => "monsters",
+            Faction::Neutral => "neutral",
+        })
+    }
+}
+
+/// Which factions are hostile to which. It doesn't have to be symmetric, and a faction can be
+/// hostile to itself, so monsters caught in each other's blasts fight among themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hostility {
+    /// Indexed by attacker, then target.
+    hostile: [[bool; 3]; 3],
+}
+
+impl Default for Hostility {
+    fn default() -> Self {
+        DEFAULT_HOSTILITY.parse().expect("default hostility")
+    }
+}
+
+impl Hostility {
+    /// Nobody is hostile to anybody.
+    pub fn peaceful() -> Hostility {
+        Hostility {
+            hostile: [[false; 3]; 3],
+        }
+    }
+
+    pub fn from_cvars(cvars: &Cvars) -> Hostility {
+        cvars.get_parsed(HOSTILITY_CVAR).unwrap_or_default()
+    }
+
+    pub fn set(&mut self, attacker: Faction, target: Faction, hostile: bool) {
+        self.hostile[attacker.index()][target.index()] = hostile;
+    }
+
+    pub fn is_hostile(&self, attacker: Faction, target: Faction) -> bool {
+        self.hostile[attacker.index()][target.index()]
+    }
+
+    /// Whether a faction goes after anybody at all.
+    pub fn is_aggressive(&self, faction: Faction) -> bool {
+        Faction::ALL
+            .iter()
+            .any(|target| self.is_hostile(faction, *target))
+    }
+
+    /// Whether damage from something a faction did, like a rocket it fired, hurts a target.
+    /// Damage from nobody in particular, like a barrel going off, hurts everyone.
+    pub fn can_hurt(&self, source: Option<Faction>, target: Faction) -> bool {
+        source.is_none_or(|source| self.is_hostile(source, target))
+    }
+}
+
+impl FromStr for Hostility {
+    type Err = Error;
+
+    /// Parses a list of "attacker>target" pairs, separated by spaces.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut hostility = Hostility::peaceful();
+        for pair in s.split_whitespace() {
+            let Some((attacker, target)) = pair.split_once('>') else {
+                bail!("invalid hostility: {}", s);
+            };
+            hostility.set(attacker.parse()?, target.parse()?, true);
+        }
+        Ok(hostility)
+    }
+}
+
+impl fmt::Display for Hostility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pairs: Vec<String> = Faction::ALL
+            .iter()
+            .flat_map(|attacker| Faction::ALL.iter().map(move |target| (attacker, target)))
+            .filter(|(attacker, target)| self.is_hostile(**attacker, **target))
+            .map(|(attacker, target)| format!("{}>{}", attacker, target))
+            .collect();
+        f.write_str(&pairs.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hostility() {
+        let hostility = Hostility::default();
+        assert_eq!(hostility.to_string(), DEFAULT_HOSTILITY);
+        assert!(hostility.is_hostile(Faction::Player, Faction::Monsters));
+        assert!(!hostility.is_hostile(Faction::Monsters, Faction::Monsters));
+        assert!(!hostility.is_aggressive(Faction::Neutral));
+        assert!(hostility.can_hurt(None, Faction::Neutral));
+        assert!(!hostility.can_hurt(Some(Faction::Player), Faction::Neutral));
+        assert!(!hostility.can_hurt(Some(Faction::Player), Faction::Player));
+
+        let mut cvars = Cvars::new();
+        cvars.set(
+            HOSTILITY_CVAR,
+            "player>monsters monsters>player monsters>monsters",
+        );
+        let infighting = Hostility::from_cvars(&cvars);
+        assert!(infighting.can_hurt(Some(Faction::Monsters), Faction::Monsters));
+
+        assert_eq!("".parse::<Hostility>().unwrap(), Hostility::peaceful());
+        assert!("player".parse::<Hostility>().is_err());
+        assert!("player>aliens".parse::<Hostility>().is_err());
+    }
+}
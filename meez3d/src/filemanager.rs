@@ -1,14 +1,18 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::Read;
 use std::path::Path;
+use std::time::SystemTime;
 use std::{fs, path::PathBuf};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use flate2::read::GzDecoder;
 use log::{error, info, warn};
 
 use crate::utils::normalize_path;
 
+/// Where files the game saves for the player, like ghosts and high scores, are kept.
+pub const USER_DATA_DIR: &str = "userdata";
+
 pub enum DirEntryType {
     Directory,
     File,
@@ -20,10 +24,22 @@ pub struct DirEntry {
     pub file_type: DirEntryType,
 }
 
-trait FileManagerImpl {
+/// A backend for FileManager, so tests can provide files without touching the disk.
+pub trait FileManagerImpl {
     fn read(&self, path: &Path) -> Result<Vec<u8>>;
     fn read_to_string(&self, path: &Path) -> Result<String>;
     fn read_dir(&self, dir_path: &Path) -> Result<Vec<DirEntry>>;
+
+    /// Writes a whole file, e.g. a log or capture. Archives are read-only, so this fails by default.
+    fn write(&self, path: &Path, _data: &[u8]) -> Result<()> {
+        bail!("unable to write {:?}: files are read-only", path);
+    }
+
+    /// When a file last changed, for hot reloading. Archives never change, so by default there's
+    /// no time.
+    fn modified(&self, _path: &Path) -> Option<SystemTime> {
+        None
+    }
 }
 
 struct DefaultFileManagerImpl {}
@@ -72,6 +88,22 @@ impl FileManagerImpl for DefaultFileManagerImpl {
         }
         Ok(entries)
     }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let path = normalize_path(path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| anyhow!("unable to create directory {:?}: {}", parent, e))?;
+        }
+        fs::write(&path, data).map_err(|e| anyhow!("unable to write {:?}: {}", &path, e))
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        let path = normalize_path(path).ok()?;
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
 }
 
 struct ArchiveFileManager {
@@ -117,6 +149,14 @@ impl ArchiveFileManager {
 
         Ok(ArchiveFileManager { files })
     }
+
+    pub fn from_map(map: HashMap<PathBuf, Vec<u8>>) -> Result<ArchiveFileManager> {
+        let mut files = BTreeMap::new();
+        for (path, data) in map {
+            files.insert(normalize_path(&path)?, data);
+        }
+        Ok(ArchiveFileManager { files })
+    }
 }
 
 impl FileManagerImpl for ArchiveFileManager {
@@ -184,6 +224,10 @@ pub struct FileManager {
 }
 
 impl FileManager {
+    pub fn with_internal(internal: Box<dyn FileManagerImpl>) -> FileManager {
+        Self { internal }
+    }
+
     pub fn from_fs() -> Result<Self> {
         Ok(Self {
             internal: Box::new(DefaultFileManagerImpl {}),
@@ -202,6 +246,13 @@ impl FileManager {
         })
     }
 
+    /// Serves files from memory, e.g. from include_bytes!, as if they had been in an archive.
+    pub fn from_memory(map: HashMap<PathBuf, Vec<u8>>) -> Result<Self> {
+        Ok(Self {
+            internal: Box::new(ArchiveFileManager::from_map(map)?),
+        })
+    }
+
     pub fn read(&self, path: &Path) -> Result<Vec<u8>> {
         self.internal.read(path)
     }
@@ -213,4 +264,121 @@ impl FileManager {
     pub fn read_dir(&self, dir_path: &Path) -> Result<Vec<DirEntry>> {
         self.internal.read_dir(dir_path)
     }
+
+    pub fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        self.internal.write(path, data)
+    }
+
+    /// When a file last changed, if the files can change while the game runs, like when they're
+    /// read straight from the assets directory.
+    pub fn modified(&self, path: &Path) -> Option<SystemTime> {
+        self.internal.modified(path)
+    }
+
+    /// Reads a file the game saved for the player, by its name within the user data directory.
+    pub fn read_user_data(&self, name: &str) -> Result<String> {
+        self.read_to_string(&Path::new(USER_DATA_DIR).join(name))
+    }
+
+    /// Saves a file for the player, by its name within the user data directory.
+    pub fn write_user_data(&self, name: &str, data: &[u8]) -> Result<()> {
+        self.write(&Path::new(USER_DATA_DIR).join(name), data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_memory() {
+        let mut map = HashMap::new();
+        map.insert(PathBuf::from("assets/menus/../a.txt"), b"a".to_vec());
+        map.insert(PathBuf::from("assets/sounds/b.wav"), b"b".to_vec());
+        let files = FileManager::from_memory(map).unwrap();
+
+        assert_eq!(
+            files.read_to_string(Path::new("assets/a.txt")).unwrap(),
+            "a"
+        );
+        assert_eq!(
+            files
+                .read(Path::new("assets/levels/../sounds/b.wav"))
+                .unwrap(),
+            b"b"
+        );
+        assert!(files.read(Path::new("assets/c.txt")).is_err());
+        assert!(files.write(Path::new("assets/c.txt"), b"c").is_err());
+        assert!(files.write_user_data("ghosts/level.txt", b"c").is_err());
+
+        let mut names: Vec<String> = files
+            .read_dir(Path::new("assets"))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["a.txt", "sounds"]);
+    }
+
+    /// Whether a path under assets matches a line of the manifest, like "levels/**/*.tmx",
+    /// "*.tsx", or "tutorial.json".
+    fn in_manifest(pattern: &str, path: &str) -> bool {
+        let matches_name = |glob: &str, name: &str| match glob.strip_prefix('*') {
+            Some(suffix) => name.ends_with(suffix),
+            None => name == glob,
+        };
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        match pattern.split_once("/**/") {
+            Some((root, glob)) => {
+                (dir == root || dir.starts_with(&format!("{}/", root))) && matches_name(glob, name)
+            }
+            None => {
+                let (pattern_dir, glob) = pattern.rsplit_once('/').unwrap_or(("", pattern));
+                dir == pattern_dir && matches_name(glob, name)
+            }
+        }
+    }
+
+    fn files_under(dir: &Path, out: &mut Vec<PathBuf>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files_under(&path, out);
+            } else {
+                out.push(path);
+            }
+        }
+    }
+
+    /// The wasm build embeds the archive, so it has to be rebuilt with `make assets_archive`
+    /// whenever a file in the manifest changes.
+    #[test]
+    fn archive_matches_manifest() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+        let archive = FileManager::from_archive_file(&root.join("assets.tar.gz")).unwrap();
+        let manifest = fs::read_to_string(root.join("assets/manifest.txt")).unwrap();
+        let mut files = Vec::new();
+        files_under(&root.join("assets"), &mut files);
+        let mut packed = 0;
+        for file in files {
+            let path = file.strip_prefix(root.join("assets")).unwrap();
+            let path = path.to_str().unwrap().replace('\\', "/");
+            if !manifest
+                .lines()
+                .any(|pattern| in_manifest(pattern.trim(), &path))
+            {
+                continue;
+            }
+            let in_archive = archive.read(&Path::new("assets").join(&path));
+            let in_archive = in_archive.unwrap_or_else(|_| panic!("{} isn't in the archive", path));
+            assert!(
+                in_archive == fs::read(&file).unwrap(),
+                "{} is out of date",
+                path
+            );
+            packed += 1;
+        }
+        assert!(packed > 0);
+    }
 }
@@ -1,30 +1,110 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use std::path::Path;
 
 use crate::filemanager::FileManager;
 use crate::geometry::{Point, Rect};
 use crate::imagemanager::ImageLoader;
 use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::sprite::Sprite;
 use crate::tilemap::TileIndex;
 use crate::tileset::TileSet;
 
+/// The tile drawn for characters that no font in the chain has, which is DEL in an ASCII font.
+const MISSING_TILE: usize = 127;
+
+/// How many fonts can follow the first one in a chain, so a tileset that falls back to itself
+/// isn't loaded forever.
+const MAX_FALLBACKS: usize = 8;
+
+/// A font drawn from a tileset with one tile per character.
+///
+/// A font's tileset can have a first_char property, which is the character its first tile is,
+/// for tilesets of characters past ASCII. It can also have a fallback property, which is the
+/// path of another font tileset, relative to this one, to draw the characters this one doesn't
+/// have. That way, text translated into a language the main font can't draw still reads.
 pub struct Font {
     tileset: TileSet,
+    /// The character the first tile is drawn for.
+    first_char: u32,
+    /// The font to try for characters this one doesn't have.
+    fallback: Option<Box<Font>>,
     pub char_width: i32,
     pub char_height: i32,
 }
 
 impl Font {
     pub fn new(path: &Path, files: &FileManager, images: &mut dyn ImageLoader) -> Result<Font> {
+        Font::load(path, files, images, 0)
+    }
+
+    fn load(
+        path: &Path,
+        files: &FileManager,
+        images: &mut dyn ImageLoader,
+        depth: usize,
+    ) -> Result<Font> {
         // It doesn't actually matter what the global id is, since there is no map.
         let firstgid: TileIndex = 0.into();
+        let tileset = TileSet::from_file(path, firstgid, files, images)?;
+        let properties = &tileset.properties.raw;
+        let first_char = properties.get_int("first_char")?.unwrap_or(0);
+        let first_char = u32::try_from(first_char)
+            .map_err(|_| anyhow!("invalid first_char in {:?}: {}", path, first_char))?;
+        let fallback = match properties.get_string("fallback")? {
+            Some(_) if depth >= MAX_FALLBACKS => {
+                bail!("too many fallback fonts after {:?}", path)
+            }
+            Some(fallback) => {
+                let fallback = path
+                    .parent()
+                    .context(anyhow!("font path is root"))?
+                    .join(fallback);
+                Some(Box::new(Font::load(&fallback, files, images, depth + 1)?))
+            }
+            None => None,
+        };
         Ok(Font {
-            tileset: TileSet::from_file(path, firstgid, files, images)?,
+            tileset,
+            first_char,
+            fallback,
             char_width: 64,
             char_height: 64,
         })
     }
 
+    /// Adds a font to the end of the chain, for the characters none of the others have.
+    pub fn with_fallback(mut self, fallback: Font) -> Font {
+        self.fallback = Some(Box::new(match self.fallback.take() {
+            Some(next) => next.with_fallback(fallback),
+            None => fallback,
+        }));
+        self
+    }
+
+    /// The tile for a character in this font alone, if it has one.
+    fn tile(&self, c: char) -> Option<usize> {
+        let index = (c as u32).checked_sub(self.first_char)? as usize;
+        (index < self.tileset.tilecount() as usize).then_some(index)
+    }
+
+    /// The sprite a character is drawn from, and where it is in the sprite. It's drawn from the
+    /// first font in the chain that has it, or as the missing tile of this one if none do.
+    pub fn glyph(&self, c: char) -> (Sprite, Rect<i32>) {
+        let mut font = Some(self);
+        while let Some(current) = font {
+            if let Some(index) = current.tile(c) {
+                let tileset = &current.tileset;
+                return (tileset.sprite, tileset.get_source_rect(index.into()));
+            }
+            font = current.fallback.as_deref();
+        }
+        let index = MISSING_TILE.min(self.tileset.tilecount().max(1) as usize - 1);
+        (
+            self.tileset.sprite,
+            self.tileset.get_source_rect(index.into()),
+        )
+    }
+
     pub fn draw_string(
         &self,
         context: &mut RenderContext,
@@ -34,8 +114,7 @@ impl Font {
     ) {
         let mut pos = pos;
         for c in s.chars() {
-            let c = (c as usize).min(127).into();
-            let area = self.tileset.get_source_rect(c);
+            let (sprite, area) = self.glyph(c);
             let dest = Rect {
                 x: pos.x,
                 y: pos.y,
@@ -45,8 +124,132 @@ impl Font {
             if dest.bottom() <= 0 || dest.right() <= 0 {
                 continue;
             }
-            context.draw(self.tileset.sprite, layer, dest, area);
+            context.draw(sprite, layer, dest, area);
             pos = Point::new(pos.x + self.char_width, pos.y);
         }
     }
+
+    /// How wide text is when it's drawn, which is the same for every character, however many
+    /// bytes it takes.
+    pub fn text_width(&self, text: &str) -> i32 {
+        text.chars().count() as i32 * self.char_width
+    }
+
+    /// Draws text broken into lines no wider than width, returning how tall it was.
+    pub fn draw_wrapped(
+        &self,
+        context: &mut RenderContext,
+        layer: RenderLayer,
+        pos: Point<i32>,
+        width: i32,
+        s: &str,
+    ) -> i32 {
+        let columns = (width / self.char_width).max(1) as usize;
+        let lines = wrap_text(s, columns);
+        for (i, line) in lines.iter().enumerate() {
+            let y = pos.y + i as i32 * self.char_height;
+            self.draw_string(context, layer, Point::new(pos.x, y), line);
+        }
+        lines.len() as i32 * self.char_height
+    }
+}
+
+/// Breaks text into lines of at most the given number of characters, between words where it
+/// can. Words longer than a line are split.
+pub fn wrap_text(s: &str, columns: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in s.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        let length = line.chars().count();
+        if length > 0 && length + 1 + word.len() <= columns {
+            line.push(' ');
+            line.extend(word);
+            continue;
+        }
+        if length > 0 {
+            lines.push(std::mem::take(&mut line));
+        }
+        while word.len() > columns {
+            lines.push(word.drain(..columns).collect());
+        }
+        line.extend(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::imagemanager::ImageManager;
+
+    /// A font tileset like the 8-bit one, with the given tileset properties.
+    fn font_tsx(properties: &str) -> Vec<u8> {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.8" tiledversion="1.8.0" name="font" tilewidth="8" tileheight="8" tilecount="144" columns="12">
+ <properties>{}</properties>
+ <image source="8bitfont.png" width="96" height="96"/>
+</tileset>"#,
+            properties
+        )
+        .into_bytes()
+    }
+
+    fn load(files: Vec<(&str, Vec<u8>)>) -> Result<Font> {
+        let map: HashMap<PathBuf, Vec<u8>> = files
+            .into_iter()
+            .map(|(path, data)| (PathBuf::from(path), data))
+            .collect();
+        let files = FileManager::from_memory(map)?;
+        let mut images = ImageManager::null_manager();
+        Font::new(Path::new("assets/main.tsx"), &files, &mut images)
+    }
+
+    #[test]
+    fn fallback() {
+        let font = load(vec![
+            (
+                "assets/main.tsx",
+                font_tsx(r#"<property name="fallback" value="fonts/cyrillic.tsx"/>"#),
+            ),
+            (
+                "assets/fonts/cyrillic.tsx",
+                font_tsx(r#"<property name="first_char" type="int" value="1024"/>"#),
+            ),
+        ])
+        .unwrap();
+        let rect = |x, y| Rect { x, y, w: 8, h: 8 };
+
+        // 'A' is 65, in the main font. 'Б' is 1041, the 17th character of the fallback.
+        assert_eq!(font.glyph('A').1, rect(40, 40));
+        assert_eq!(font.glyph('Б').1, rect(40, 8));
+        // Neither has the euro sign, so it's drawn as DEL.
+        assert_eq!(font.glyph('€').1, rect(56, 80));
+        // Characters that take more than one byte are still one character wide.
+        assert_eq!(font.text_width("AБ€"), 3 * font.char_width);
+
+        // A font that falls back to itself is an error, not a hang.
+        let looped = load(vec![(
+            "assets/main.tsx",
+            font_tsx(r#"<property name="fallback" value="main.tsx"/>"#),
+        )]);
+        assert!(looped.is_err());
+    }
+
+    #[test]
+    fn wrap() {
+        assert_eq!(
+            wrap_text("patches you  up a little", 10),
+            vec!["patches", "you up a", "little"]
+        );
+        assert_eq!(wrap_text("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        assert!(wrap_text("  ", 4).is_empty());
+    }
 }
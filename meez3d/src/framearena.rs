@@ -0,0 +1,112 @@
+use std::vec;
+
+/// A bump arena for scratch data that only has to live for one frame.
+///
+/// Everything allocated from it goes on the end of one buffer, and reset empties the buffer
+/// without freeing it. After the first few frames it's big enough for a whole frame's worth of
+/// scratch data, so nothing allocated from it touches the system allocator.
+#[derive(Debug)]
+pub struct FrameArena<T> {
+    items: Vec<T>,
+}
+
+/// Where an allocation is in a FrameArena. It's only meaningful until the arena is reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArenaSlice {
+    start: usize,
+    end: usize,
+}
+
+impl ArenaSlice {
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// The first len items of the allocation. The rest stay in the arena until it's reset.
+    pub fn truncated(self, len: usize) -> ArenaSlice {
+        ArenaSlice {
+            start: self.start,
+            end: self.start + len.min(self.len()),
+        }
+    }
+}
+
+impl<T> FrameArena<T> {
+    pub fn new() -> Self {
+        FrameArena { items: Vec::new() }
+    }
+
+    /// Adds one item to the end of the arena.
+    pub fn push(&mut self, item: T) {
+        self.items.push(item);
+    }
+
+    /// Copies items into the arena, returning where they went.
+    pub fn alloc(&mut self, items: impl IntoIterator<Item = T>) -> ArenaSlice {
+        let start = self.items.len();
+        self.items.extend(items);
+        ArenaSlice {
+            start,
+            end: self.items.len(),
+        }
+    }
+
+    pub fn get(&self, slice: ArenaSlice) -> &[T] {
+        &self.items[slice.start..slice.end]
+    }
+
+    pub fn get_mut(&mut self, slice: ArenaSlice) -> &mut [T] {
+        &mut self.items[slice.start..slice.end]
+    }
+
+    /// Everything allocated since the arena was last reset, in order.
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    /// Frees everything in the arena at once, keeping its memory for the next frame.
+    pub fn reset(&mut self) {
+        self.items.clear();
+    }
+}
+
+impl<T> Default for FrameArena<T> {
+    fn default() -> Self {
+        FrameArena::new()
+    }
+}
+
+impl<T> IntoIterator for FrameArena<T> {
+    type Item = T;
+    type IntoIter = vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alloc_and_reset() {
+        let mut arena = FrameArena::new();
+        let first = arena.alloc([1, 2, 3]);
+        arena.push(4);
+        let second = arena.alloc([5, 6]);
+        assert_eq!(arena.get(first), &[1, 2, 3]);
+        assert_eq!(arena.get(second), &[5, 6]);
+        assert_eq!(arena.get(first.truncated(2)), &[1, 2]);
+        arena.get_mut(second)[0] = 7;
+        assert_eq!(arena.items(), &[1, 2, 3, 4, 7, 6]);
+
+        // Resetting keeps the memory, so the next frame doesn't have to allocate it again.
+        let capacity = arena.items.capacity();
+        arena.reset();
+        assert!(arena.items().is_empty());
+        let third = arena.alloc([8]);
+        assert_eq!(arena.get(third), &[8]);
+        assert_eq!(arena.items.capacity(), capacity);
+    }
+}
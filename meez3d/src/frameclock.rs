@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use log::warn;
+
+use crate::constants::FRAME_RATE;
+
+/// The most updates to run before drawing, when the game has fallen behind.
+const DEFAULT_MAX_CATCH_UP_FRAMES: u32 = 5;
+
+/// A hitch longer than this is treated as if it were this long, e.g. after loading assets or
+/// while the window was being dragged, so the game doesn't try to make up for all of it.
+const DEFAULT_MAX_DELTA: Duration = Duration::from_millis(250);
+
+/// Decides how many fixed-length updates to run each time through a host's loop.
+///
+/// The game only advances in whole frames of 1 / FRAME_RATE seconds, so when a frame takes too
+/// long, the next call runs extra updates to catch up, and only the last one needs to be
+/// rendered. Both the number of extra updates and the time made up for are capped, so a long
+/// hitch just slows the game down instead of making it lurch forward.
+pub struct FrameClock {
+    step: Duration,
+    accumulated: Duration,
+    pub max_catch_up_frames: u32,
+    pub max_delta: Duration,
+}
+
+impl Default for FrameClock {
+    fn default() -> Self {
+        FrameClock {
+            step: Duration::from_secs(1) / FRAME_RATE,
+            accumulated: Duration::ZERO,
+            max_catch_up_frames: DEFAULT_MAX_CATCH_UP_FRAMES,
+            max_delta: DEFAULT_MAX_DELTA,
+        }
+    }
+}
+
+impl FrameClock {
+    pub fn new() -> FrameClock {
+        FrameClock::default()
+    }
+
+    /// The length of one update.
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    /// Adds the time since the last call, and returns how many updates to run now.
+    ///
+    /// This can be zero, e.g. when the display refreshes faster than the game updates.
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        if elapsed > self.max_delta {
+            warn!(
+                "frame took {:?}, only catching up {:?}",
+                elapsed, self.max_delta
+            );
+        }
+        self.accumulated += elapsed.min(self.max_delta);
+
+        let frames = (self.accumulated.as_nanos() / self.step.as_nanos()) as u32;
+        if frames > self.max_catch_up_frames {
+            // Drop the rest of the backlog, rather than carrying it into the next frames.
+            self.accumulated = Duration::ZERO;
+            return self.max_catch_up_frames;
+        }
+        self.accumulated -= self.step * frames;
+        frames
+    }
+
+    /// How long until the next update is due, for hosts that sleep between frames.
+    pub fn time_until_next(&self) -> Duration {
+        self.step.saturating_sub(self.accumulated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catch_up() {
+        let mut clock = FrameClock::new();
+        let step = clock.step();
+
+        // Running on time is one update per frame, and a fast display sometimes gets none.
+        assert_eq!(clock.advance(step), 1);
+        assert_eq!(clock.advance(step / 2), 0);
+        assert_eq!(clock.time_until_next(), step - step / 2);
+        assert_eq!(clock.advance(step / 2), 1);
+
+        // A short hitch is made up for exactly.
+        assert_eq!(clock.advance(step * 3), 3);
+        assert_eq!(clock.time_until_next(), step);
+
+        // A long hitch is capped, and the rest is dropped.
+        assert_eq!(clock.advance(Duration::from_secs(5)), 5);
+        assert_eq!(clock.advance(step), 1);
+
+        clock.max_catch_up_frames = 100;
+        assert_eq!(clock.advance(Duration::from_secs(5)), 15);
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::constants::FRAME_RATE;
+use crate::properties::PropertyMap;
+use crate::tilemap::TileMapProperties;
+use crate::utils::Color;
+
+/// How long a day lasts when the level doesn't say, in seconds of real time.
+const DEFAULT_DAY_LENGTH: u32 = 10 * 60;
+const DEFAULT_START_HOUR: u32 = 8;
+
+/// The time in the game world, which advances once per simulation tick.
+///
+/// It only moves while the level it belongs to is updating, so it stops while the game is paused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GameClock {
+    ticks: u64,
+    /// How many ticks a whole day takes.
+    pub ticks_per_day: u64,
+    /// The time of day when the clock started, from 0 to 1, where 0 is midnight.
+    pub start: f32,
+}
+
+impl Default for GameClock {
+    fn default() -> Self {
+        GameClock::new(DEFAULT_DAY_LENGTH, DEFAULT_START_HOUR)
+    }
+}
+
+impl GameClock {
+    /// A clock where a day lasts the given number of seconds, starting at the given hour.
+    pub fn new(day_length: u32, start_hour: u32) -> GameClock {
+        GameClock {
+            ticks: 0,
+            ticks_per_day: (day_length.max(1) * FRAME_RATE) as u64,
+            start: (start_hour % 24) as f32 / 24.0,
+        }
+    }
+
+    /// The clock for a map with "day_length" and "start_hour" properties, or the defaults.
+    pub fn from_properties(properties: &TileMapProperties) -> GameClock {
+        GameClock::new(
+            properties.day_length.unwrap_or(DEFAULT_DAY_LENGTH),
+            properties.start_hour.unwrap_or(DEFAULT_START_HOUR),
+        )
+    }
+
+    pub fn tick(&mut self) {
+        self.ticks += 1;
+    }
+
+    /// How many ticks have passed since the clock started.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// How many whole days have passed since midnight before the clock started.
+    pub fn day(&self) -> u64 {
+        (self.start as f64 + self.ticks as f64 / self.ticks_per_day as f64) as u64
+    }
+
+    /// How far through the current day it is, from 0 to 1, where 0 is midnight.
+    pub fn time_of_day(&self) -> f32 {
+        let days = self.start as f64 + self.ticks as f64 / self.ticks_per_day as f64;
+        days.fract() as f32
+    }
+
+    pub fn phase(&self) -> DayPhase {
+        DayPhase::at(self.time_of_day())
+    }
+}
+
+/// Shows the time like a 24-hour clock, e.g. "06:30".
+impl fmt::Display for GameClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let minutes = (self.time_of_day() * 24.0 * 60.0) as u32;
+        write!(f, "{:02}:{:02}", minutes / 60, minutes % 60)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DayPhase {
+    Dawn,
+    Day,
+    Dusk,
+    Night,
+}
+
+impl DayPhase {
+    /// Which part of the day it is at a time of day from 0 to 1.
+    pub fn at(time_of_day: f32) -> DayPhase {
+        let hour = time_of_day * 24.0;
+        if hour < 5.0 || hour >= 20.0 {
+            DayPhase::Night
+        } else if hour < 8.0 {
+            DayPhase::Dawn
+        } else if hour < 17.0 {
+            DayPhase::Day
+        } else {
+            DayPhase::Dusk
+        }
+    }
+}
+
+/// The color the lighting is multiplied by at each part of the day.
+///
+/// The colors blend into each other around the edges of each phase, so there's no sudden change.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmbientLight {
+    pub dawn: Color,
+    pub day: Color,
+    pub dusk: Color,
+    pub night: Color,
+}
+
+impl Default for AmbientLight {
+    fn default() -> Self {
+        AmbientLight {
+            dawn: Color {
+                r: 0xff,
+                g: 0xc0,
+                b: 0xa0,
+                a: 0xff,
+            },
+            day: Color::WHITE,
+            dusk: Color {
+                r: 0xe0,
+                g: 0x90,
+                b: 0x90,
+                a: 0xff,
+            },
+            night: Color {
+                r: 0x50,
+                g: 0x60,
+                b: 0xa0,
+                a: 0xff,
+            },
+        }
+    }
+}
+
+impl AmbientLight {
+    /// The lighting for a map, if it has "ambient_light" set or any of the phase colors, like
+    /// "night_color". Colors that aren't set use the defaults.
+    pub fn from_properties(properties: &PropertyMap) -> Result<Option<AmbientLight>> {
+        let mut light = AmbientLight::default();
+        let mut enabled = properties.get_bool("ambient_light")?.unwrap_or(false);
+        for (name, color) in [
+            ("dawn_color", &mut light.dawn),
+            ("day_color", &mut light.day),
+            ("dusk_color", &mut light.dusk),
+            ("night_color", &mut light.night),
+        ] {
+            if let Some(value) = properties.get_string(name)? {
+                *color = value.parse()?;
+                enabled = true;
+            }
+        }
+        Ok(enabled.then_some(light))
+    }
+
+    /// The color at a time of day from 0 to 1.
+    pub fn color_at(&self, time_of_day: f32) -> Color {
+        // The hours each color is at its fullest, wrapping around at midnight.
+        let keys = [
+            (4.0, self.night),
+            (6.0, self.dawn),
+            (9.0, self.day),
+            (16.0, self.day),
+            (18.5, self.dusk),
+            (21.0, self.night),
+            (28.0, self.night),
+        ];
+        let mut hour = time_of_day.rem_euclid(1.0) * 24.0;
+        if hour < keys[0].0 {
+            hour += 24.0;
+        }
+        for pair in keys.windows(2) {
+            let ((start, from), (end, to)) = (pair[0], pair[1]);
+            if hour <= end {
+                return from.lerp(to, (hour - start) / (end - start));
+            }
+        }
+        self.night
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_of_day() {
+        let mut clock = GameClock::new(24, 6);
+        assert_eq!(clock.to_string(), "06:00");
+        assert_eq!(clock.phase(), DayPhase::Dawn);
+
+        // Each second of real time is an hour of game time.
+        for _ in 0..6 * FRAME_RATE {
+            clock.tick();
+        }
+        assert_eq!(clock.to_string(), "12:00");
+        assert_eq!(clock.phase(), DayPhase::Day);
+        for _ in 0..12 * FRAME_RATE {
+            clock.tick();
+        }
+        assert_eq!(clock.to_string(), "00:00");
+        assert_eq!(clock.phase(), DayPhase::Night);
+        assert_eq!(clock.day(), 1);
+
+        let light = AmbientLight::default();
+        assert_eq!(light.color_at(12.5 / 24.0), Color::WHITE);
+        assert_eq!(light.color_at(0.0), light.night);
+        let evening = light.color_at(20.0 / 24.0);
+        assert!(evening.b > light.dusk.b && evening.b < light.night.b);
+    }
+}
@@ -4,7 +4,7 @@ use num_traits::Zero;
 
 // Points
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct Point<T> {
     pub x: T,
     pub y: T,
@@ -106,7 +106,7 @@ where
 
 // Rect
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Rect<T> {
     pub x: T,
     pub y: T,
@@ -182,6 +182,89 @@ where
     }
 }
 
+// Polygons
+
+/// Twice the signed area of a triangle, which is positive when a, b, c turn counterclockwise
+/// with y pointing up.
+fn cross(a: Point<i32>, b: Point<i32>, c: Point<i32>) -> i64 {
+    (b.x - a.x) as i64 * (c.y - a.y) as i64 - (c.x - a.x) as i64 * (b.y - a.y) as i64
+}
+
+fn in_triangle(p: Point<i32>, a: Point<i32>, b: Point<i32>, c: Point<i32>) -> bool {
+    let d1 = cross(a, b, p);
+    let d2 = cross(b, c, p);
+    let d3 = cross(c, a, p);
+    let has_negative = d1 < 0 || d2 < 0 || d3 < 0;
+    let has_positive = d1 > 0 || d2 > 0 || d3 > 0;
+    !(has_negative && has_positive)
+}
+
+/// Splits a simple polygon into triangles by ear clipping.
+///
+/// The polygon can be in either winding, but every triangle comes out in the same winding as it.
+/// Self-intersecting polygons are triangulated as far as possible, and the rest is dropped.
+pub fn triangulate(points: &[Point<i32>]) -> Vec<[Point<i32>; 3]> {
+    let mut remaining: Vec<Point<i32>> = points.to_vec();
+    remaining.dedup();
+    if remaining.len() > 1 && remaining.first() == remaining.last() {
+        remaining.pop();
+    }
+
+    let mut area = 0;
+    for i in 0..remaining.len() {
+        let a = remaining[i];
+        let b = remaining[(i + 1) % remaining.len()];
+        area += a.x as i64 * b.y as i64 - b.x as i64 * a.y as i64;
+    }
+    let winding = area.signum();
+
+    let mut triangles = Vec::new();
+    if winding == 0 {
+        return triangles;
+    }
+
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let a = remaining[(i + n - 1) % n];
+            let b = remaining[i];
+            let c = remaining[(i + 1) % n];
+            if cross(a, b, c) * winding <= 0 {
+                return false;
+            }
+            remaining
+                .iter()
+                .all(|&p| p == a || p == b || p == c || !in_triangle(p, a, b, c))
+        });
+        // Points in a straight line aren't ears, but they can be dropped without changing the shape.
+        let straight = || {
+            (0..n).find(|&i| {
+                cross(
+                    remaining[(i + n - 1) % n],
+                    remaining[i],
+                    remaining[(i + 1) % n],
+                ) == 0
+            })
+        };
+        if let Some(i) = ear {
+            triangles.push([
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            ]);
+            remaining.remove(i);
+        } else if let Some(i) = straight() {
+            remaining.remove(i);
+        } else {
+            return triangles;
+        }
+    }
+    if remaining.len() == 3 && cross(remaining[0], remaining[1], remaining[2]) != 0 {
+        triangles.push([remaining[0], remaining[1], remaining[2]]);
+    }
+    triangles
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -223,4 +306,31 @@ mod tests {
         assert_eq!(r.right(), 113);
         assert_eq!(r.bottom(), 224);
     }
+
+    #[test]
+    fn triangulate_concave() {
+        // An L shape, which has one reflex corner.
+        let points = [
+            Point::new(0, 0),
+            Point::new(20, 0),
+            Point::new(20, 10),
+            Point::new(10, 10),
+            Point::new(10, 20),
+            Point::new(0, 20),
+        ];
+        let triangles = triangulate(&points);
+        assert_eq!(triangles.len(), 4);
+        let area: i64 = triangles
+            .iter()
+            .map(|[a, b, c]| cross(*a, *b, *c).abs())
+            .sum();
+        assert_eq!(area, 2 * 300);
+        for [a, b, c] in triangles.iter() {
+            assert!(cross(*a, *b, *c) > 0);
+        }
+
+        assert!(triangulate(&points[0..2]).is_empty());
+        let line = [Point::new(0, 0), Point::new(1, 1), Point::new(2, 2)];
+        assert!(triangulate(&line).is_empty());
+    }
 }
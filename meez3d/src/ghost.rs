@@ -0,0 +1,167 @@
+use anyhow::{bail, Context, Result};
+
+use crate::ability::Dash;
+use crate::inputmanager::{InputRecorder, InputSnapshot};
+use crate::savestate::PlayerState;
+use crate::utils::level_file_name;
+
+/// Whether levels show a ghost of the best previous run, "true" or "false".
+pub const GHOST_CVAR: &str = "ghost";
+
+/// The user data file a level's best run is kept in.
+pub fn ghost_path(level: &str) -> String {
+    format!("ghosts/{}.txt", level_file_name(level))
+}
+
+/// Only the inputs that move the player, which are all a ghost needs to retrace a run.
+pub fn movement_inputs(inputs: &InputSnapshot) -> InputSnapshot {
+    InputSnapshot {
+        player_forward_down: inputs.player_forward_down,
+        player_backward_down: inputs.player_backward_down,
+        player_strafe_left_down: inputs.player_strafe_left_down,
+        player_strafe_right_down: inputs.player_strafe_right_down,
+        player_turn_left_down: inputs.player_turn_left_down,
+        player_turn_right_down: inputs.player_turn_right_down,
+        player_dash_clicked: inputs.player_dash_clicked,
+        move_axis: inputs.move_axis,
+        strafe_axis: inputs.strafe_axis,
+        turn_axis: inputs.turn_axis,
+        mouse_delta: inputs.mouse_delta,
+        ..Default::default()
+    }
+}
+
+/// A whole run through a level, from where the player started to where they finished.
+#[derive(Debug, Clone)]
+pub struct GhostRun {
+    /// How many frames the run took.
+    pub frames: u64,
+    pub start: PlayerState,
+    /// The mouse sensitivity the run was played at, so the ghost turns as far as the player did.
+    pub mouse_sensitivity: f32,
+    pub inputs: InputRecorder,
+}
+
+impl GhostRun {
+    /// The run as text, with a "frames,x,y,angle,sensitivity" line followed by the recorded
+    /// inputs.
+    pub fn to_text(&self) -> String {
+        format!(
+            "{},{},{},{},{}\n{}",
+            self.frames,
+            self.start.x,
+            self.start.y,
+            self.start.angle,
+            self.mouse_sensitivity,
+            self.inputs.to_text()
+        )
+    }
+
+    pub fn from_text(text: &str) -> Result<GhostRun> {
+        let (header, inputs) = text.split_once('\n').unwrap_or((text, ""));
+        let fields: Vec<&str> = header.trim().split(',').collect();
+        // Runs saved before the sensitivity was kept were played at the default.
+        let (frames, x, y, angle, mouse_sensitivity) = match fields[..] {
+            [frames, x, y, angle] => (frames, x, y, angle, "1"),
+            [frames, x, y, angle, mouse_sensitivity] => (frames, x, y, angle, mouse_sensitivity),
+            _ => bail!("invalid ghost header: {}", header),
+        };
+        Ok(GhostRun {
+            frames: frames.parse().context("invalid ghost frames")?,
+            start: PlayerState {
+                x: x.parse().context("invalid ghost x")?,
+                y: y.parse().context("invalid ghost y")?,
+                angle: angle.parse().context("invalid ghost angle")?,
+            },
+            mouse_sensitivity: mouse_sensitivity
+                .parse()
+                .context("invalid ghost mouse sensitivity")?,
+            inputs: InputRecorder::from_text(inputs)?,
+        })
+    }
+}
+
+/// A previous run being played back alongside the player.
+pub struct Ghost {
+    pub run: GhostRun,
+    /// The inputs still to play back.
+    playback: InputRecorder,
+    frame: u64,
+    pub body: PlayerState,
+    pub floor: usize,
+    /// Whether the ghost is standing on the portal or stairs it arrived by.
+    pub arrived: bool,
+    pub dash: Dash,
+}
+
+impl Ghost {
+    pub fn new(run: GhostRun) -> Ghost {
+        Ghost {
+            playback: run.inputs.clone(),
+            frame: 0,
+            body: run.start,
+            floor: 0,
+            arrived: true,
+            dash: Dash::default(),
+            run,
+        }
+    }
+
+    /// The inputs for the ghost's next frame, or None once the run is over.
+    pub fn next_inputs(&mut self) -> Option<InputSnapshot> {
+        if self.is_done() {
+            return None;
+        }
+        let inputs = self.playback.playback(self.frame);
+        self.frame += 1;
+        Some(inputs)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.frame >= self.run.frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_round_trip() {
+        assert_eq!(ghost_path("Sector 7"), "ghosts/sector_7.txt");
+
+        let forward = InputSnapshot {
+            player_forward_down: true,
+            ok_clicked: true,
+            ..Default::default()
+        };
+        let mut inputs = InputRecorder::new();
+        inputs.record(2, &movement_inputs(&forward));
+        let run = GhostRun {
+            frames: 4,
+            start: PlayerState {
+                x: 1.5,
+                y: 2.5,
+                angle: 0.25,
+            },
+            mouse_sensitivity: 0.5,
+            inputs,
+        };
+
+        let mut ghost = Ghost::new(GhostRun::from_text(&run.to_text()).unwrap());
+        assert_eq!(ghost.body, run.start);
+        assert_eq!(ghost.run.mouse_sensitivity, 0.5);
+        let moving: Vec<bool> = std::iter::from_fn(|| ghost.next_inputs())
+            .map(|inputs| {
+                assert!(!inputs.ok_clicked);
+                inputs.player_forward_down
+            })
+            .collect();
+        assert_eq!(moving, vec![false, false, true, true]);
+        assert!(ghost.is_done());
+
+        assert!(GhostRun::from_text("1,2\n").is_err());
+        let old = GhostRun::from_text("4,1.5,2.5,0.25\n").unwrap();
+        assert_eq!(old.mouse_sensitivity, 1.0);
+    }
+}
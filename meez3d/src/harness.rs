@@ -0,0 +1,176 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::Result;
+use rand::random;
+
+use crate::engine::Engine;
+use crate::filemanager::FileManager;
+use crate::imagemanager::ImageManager;
+use crate::inputmanager::InputSnapshot;
+use crate::renderer::NullRenderer;
+use crate::savestate::PlayerState;
+use crate::soundmanager::{RecordingSoundPlayer, SoundManager};
+
+/// Runs the Engine with no window or audio, so tests can feed it inputs and check the results.
+///
+/// Every frame is updated and drawn exactly as it would be in a real host, but nothing is
+/// rendered, so a test can step through hundreds of frames in a few milliseconds.
+pub struct TestHarness {
+    engine: Engine,
+    images: ImageManager<NullRenderer>,
+    sounds: Rc<RefCell<Vec<String>>>,
+    running: bool,
+}
+
+impl TestHarness {
+    pub fn new(files: FileManager) -> Result<TestHarness> {
+        TestHarness::with_seed(files, random())
+    }
+
+    /// A harness whose random levels come from the given seed, so a run can be repeated exactly.
+    pub fn with_seed(files: FileManager, seed: u64) -> Result<TestHarness> {
+        let mut images = ImageManager::null_manager();
+        let font = images.load_font(&files)?;
+        let player = RecordingSoundPlayer::new();
+        let played = player.played();
+        let sounds = SoundManager::with_internal(Box::new(player));
+        let engine = Engine::with_seed(files, &mut images, font, sounds, seed)?;
+        Ok(TestHarness {
+            engine,
+            images,
+            sounds: played,
+            running: true,
+        })
+    }
+
+    /// Runs a single frame with the given inputs, returning false once the game has exited.
+    pub fn step(&mut self, inputs: &InputSnapshot) -> Result<bool> {
+        if self.running {
+            self.running = self.engine.run_one_frame(inputs, &mut self.images)?;
+        }
+        Ok(self.running)
+    }
+
+    /// Runs n frames, all with the same inputs.
+    pub fn step_n(&mut self, n: usize, inputs: &InputSnapshot) -> Result<bool> {
+        for _ in 0..n {
+            self.step(inputs)?;
+        }
+        Ok(self.running)
+    }
+
+    /// Runs one frame for each of the given inputs, in order.
+    pub fn play(&mut self, inputs: &[InputSnapshot]) -> Result<bool> {
+        for input in inputs {
+            self.step(input)?;
+        }
+        Ok(self.running)
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.engine.frame()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// The player in the topmost scene that has one.
+    pub fn player(&self) -> Option<PlayerState> {
+        self.engine
+            .stage_manager()
+            .save_state()
+            .map(|state| state.player)
+    }
+
+    /// The names of the scenes on the stack, from the bottom up to the current scene.
+    pub fn scene_names(&self) -> Vec<&str> {
+        self.engine.stage_manager().scene_names()
+    }
+
+    /// A hash of everything the simulation decides, for checking that it's deterministic.
+    pub fn state_hash(&self) -> u64 {
+        self.engine.stage_manager().state_hash()
+    }
+
+    /// Returns the names of every sound played since the last call.
+    pub fn take_sounds(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.sounds.borrow_mut())
+    }
+}
+
+/// The assets a test needs to run the Engine: the font, and the kill screen a test can die into.
+#[cfg(test)]
+pub(crate) fn test_files() -> FileManager {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    let mut map = HashMap::new();
+    map.insert(
+        PathBuf::from("assets/8bitfont.tsx"),
+        include_bytes!("../../assets/8bitfont.tsx").to_vec(),
+    );
+    map.insert(
+        PathBuf::from("assets/menus/kill.tmx"),
+        include_bytes!("../../assets/menus/kill.tmx").to_vec(),
+    );
+    map.insert(
+        PathBuf::from("assets/retry_button.tsx"),
+        include_bytes!("../../assets/retry_button.tsx").to_vec(),
+    );
+    map.insert(
+        PathBuf::from("assets/quit_button.tsx"),
+        include_bytes!("../../assets/quit_button.tsx").to_vec(),
+    );
+    FileManager::from_memory(map).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turning() {
+        let mut harness = TestHarness::new(test_files()).unwrap();
+        let inputs = InputSnapshot {
+            player_turn_right_down: true,
+            ..Default::default()
+        };
+        assert!(harness.step_n(10, &inputs).unwrap());
+        assert_eq!(harness.frame(), 10);
+        let player = harness.player().unwrap();
+        assert!((player.angle - 0.2).abs() < 0.0001);
+
+        // The same context is drawn into every frame, so its batches keep their memory.
+        let context = harness.engine().context();
+        assert_eq!(context.frame, 9);
+        let capacity = context.player_batch.entries.capacity();
+        assert!(capacity >= context.player_batch.entries.len());
+        harness.step(&inputs).unwrap();
+        let context = harness.engine().context();
+        assert_eq!(context.frame, 10);
+        assert_eq!(context.player_batch.entries.capacity(), capacity);
+    }
+
+    #[test]
+    fn kill_screen() {
+        let mut harness = TestHarness::new(test_files()).unwrap();
+        assert_eq!(harness.scene_names(), vec!["level"]);
+        let inputs = InputSnapshot {
+            ok_clicked: true,
+            ..Default::default()
+        };
+        assert!(harness.step(&inputs).unwrap());
+        assert_eq!(harness.scene_names(), vec!["level", "menu"]);
+        assert!(harness.take_sounds().is_empty());
+    }
+}
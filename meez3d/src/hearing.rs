@@ -0,0 +1,119 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// How much farther a noise has to travel to get through a wall, in tiles, on top of the step
+/// into it.
+const WALL_ATTENUATION: u32 = 4;
+
+/// A loud sound in the level, like a rocket launch or an explosion, which enemies can hear even
+/// when they can't see where it came from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Noise {
+    pub x: f32,
+    pub y: f32,
+    /// How far it carries through open space, in tiles.
+    pub radius: f32,
+}
+
+impl Noise {
+    pub fn new(x: f32, y: f32, radius: f32) -> Noise {
+        Noise { x, y, radius }
+    }
+
+    /// The cell it came from, as (row, column).
+    pub fn cell(&self) -> (usize, usize) {
+        (self.y as usize, self.x as usize)
+    }
+
+    /// Spreads the noise across a grid, stepping between edge-adjacent cells. Going through a
+    /// solid cell costs extra, so walls muffle it, but don't stop it outright.
+    pub fn propagate<S>(&self, width: usize, height: usize, is_solid: S) -> NoiseField
+    where
+        S: Fn(usize, usize) -> bool,
+    {
+        let mut distances = vec![u32::MAX; width * height];
+        let (row, column) = self.cell();
+        let mut queue = BinaryHeap::new();
+        if self.x >= 0.0 && self.y >= 0.0 && row < height && column < width {
+            distances[row * width + column] = 0;
+            queue.push(Reverse((0, row, column)));
+        }
+        while let Some(Reverse((distance, row, column))) = queue.pop() {
+            if distance > distances[row * width + column] {
+                continue;
+            }
+            let neighbors = [
+                (row.wrapping_sub(1), column),
+                (row + 1, column),
+                (row, column.wrapping_sub(1)),
+                (row, column + 1),
+            ];
+            for (next_row, next_column) in neighbors {
+                if next_row >= height || next_column >= width {
+                    continue;
+                }
+                let cost = if is_solid(next_row, next_column) {
+                    1 + WALL_ATTENUATION
+                } else {
+                    1
+                };
+                let next = distance + cost;
+                let index = next_row * width + next_column;
+                if next as f32 <= self.radius && next < distances[index] {
+                    distances[index] = next;
+                    queue.push(Reverse((next, next_row, next_column)));
+                }
+            }
+        }
+        NoiseField { width, distances }
+    }
+}
+
+/// How far a noise had to travel to get to each cell it reached.
+#[derive(Debug, Clone)]
+pub struct NoiseField {
+    width: usize,
+    /// Indexed by row * width + column, with u32::MAX for cells it didn't reach.
+    distances: Vec<u32>,
+}
+
+impl NoiseField {
+    /// How far the noise traveled to get to a cell, in tiles, or None if it didn't reach it.
+    pub fn distance(&self, row: usize, column: usize) -> Option<u32> {
+        if column >= self.width {
+            return None;
+        }
+        self.distances
+            .get(row * self.width + column)
+            .copied()
+            .filter(|distance| *distance != u32::MAX)
+    }
+
+    /// Whether someone standing in a cell can hear the noise.
+    pub fn is_heard(&self, row: usize, column: usize) -> bool {
+        self.distance(row, column).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walls_muffle() {
+        let rows = ["....#....", "....#....", "....#...."];
+        let is_solid = |row: usize, column: usize| rows[row].as_bytes()[column] == b'#';
+        let field = Noise::new(0.5, 1.5, 6.0).propagate(9, 3, is_solid);
+        assert_eq!(field.distance(1, 0), Some(0));
+        assert_eq!(field.distance(1, 3), Some(3));
+        // Through the wall is 4 steps and the wall's attenuation.
+        assert_eq!(field.distance(1, 5), None);
+        assert!(!field.is_heard(0, 8));
+
+        let louder = Noise::new(0.5, 1.5, 10.0).propagate(9, 3, is_solid);
+        assert_eq!(louder.distance(1, 5), Some(9));
+        assert!(louder.is_heard(1, 6));
+        assert!(!louder.is_heard(1, 7));
+        assert!(!louder.is_heard(5, 0));
+    }
+}
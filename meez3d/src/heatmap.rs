@@ -0,0 +1,275 @@
+use std::io::Cursor;
+
+use anyhow::{anyhow, Result};
+use image::{ImageOutputFormat, Rgba, RgbaImage};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::filemanager::FileManager;
+use crate::utils::{level_file_name, Color};
+
+/// Whether the automap shows where players have walked and died, "true" or "false".
+pub const HEATMAP_CVAR: &str = "heatmap";
+
+/// How many pixels across each tile is in an exported heatmap image.
+const EXPORT_SCALE: u32 = 8;
+
+const COLD_COLOR: Color = Color {
+    r: 0x10,
+    g: 0x20,
+    b: 0x80,
+    a: 0xff,
+};
+const WARM_COLOR: Color = Color {
+    r: 0xff,
+    g: 0x20,
+    b: 0x20,
+    a: 0xff,
+};
+const HOT_COLOR: Color = Color {
+    r: 0xff,
+    g: 0xff,
+    b: 0x60,
+    a: 0xff,
+};
+/// Cells where anyone has died are marked in this color.
+pub const DEATH_COLOR: Color = Color::WHITE;
+
+/// The user data file a level's heatmap is kept in.
+pub fn heatmap_path(level: &str) -> String {
+    format!("heatmaps/{}.json", level_file_name(level))
+}
+
+/// The user data file a level's heatmap is exported to as an image.
+pub fn heatmap_image_path(level: &str) -> String {
+    format!("heatmaps/{}.png", level_file_name(level))
+}
+
+/// The color for a cell with the given heat, from blue for cold through red to yellow for hot.
+pub fn heat_color(heat: f32) -> Color {
+    if heat < 0.5 {
+        COLD_COLOR.lerp(WARM_COLOR, heat * 2.0)
+    } else {
+        WARM_COLOR.lerp(HOT_COLOR, heat * 2.0 - 1.0)
+    }
+}
+
+/// Where players have walked and died in a level, over every session, so level designers can
+/// see which parts of a layout get used and which are too dangerous.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heatmap {
+    width: usize,
+    height: usize,
+    /// For each floor, how many times players have walked into each cell, row by row.
+    walks: Vec<Vec<u32>>,
+    /// For each floor, how many times players have died in each cell, row by row.
+    deaths: Vec<Vec<u32>>,
+    /// The cell the player was last in, as (floor, row, column), so standing still doesn't count
+    /// as walking.
+    #[serde(skip)]
+    last: Option<(usize, usize, usize)>,
+    /// Whether there's anything that hasn't been saved yet.
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl Heatmap {
+    pub fn new(floors: usize, width: usize, height: usize) -> Heatmap {
+        Heatmap {
+            width,
+            height,
+            walks: vec![vec![0; width * height]; floors],
+            deaths: vec![vec![0; width * height]; floors],
+            last: None,
+            dirty: false,
+        }
+    }
+
+    /// Loads the saved heatmap for a level, or starts a new one if there isn't one yet, or the
+    /// level's layout has changed size since it was saved.
+    pub fn load(
+        files: &FileManager,
+        level: &str,
+        floors: usize,
+        width: usize,
+        height: usize,
+    ) -> Heatmap {
+        let heatmap = files
+            .read_user_data(&heatmap_path(level))
+            .and_then(|text| Heatmap::from_json(&text));
+        match heatmap {
+            Ok(heatmap) if heatmap.floors() == floors && heatmap.size() == (width, height) => {
+                heatmap
+            }
+            Ok(_) => {
+                info!("starting a new heatmap: the level has changed size");
+                Heatmap::new(floors, width, height)
+            }
+            Err(e) => {
+                info!("starting a new heatmap: {}", e);
+                Heatmap::new(floors, width, height)
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|e| anyhow!("unable to serialize heatmap: {}", e))
+    }
+
+    pub fn from_json(text: &str) -> Result<Heatmap> {
+        serde_json::from_str(text).map_err(|e| anyhow!("unable to deserialize heatmap: {}", e))
+    }
+
+    pub fn floors(&self) -> usize {
+        self.walks.len()
+    }
+
+    /// The width and height of each floor, in tiles.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn index(&self, floor: usize, row: usize, column: usize) -> Option<usize> {
+        (floor < self.floors() && row < self.height && column < self.width)
+            .then_some(row * self.width + column)
+    }
+
+    /// Notes that the player is in a cell, counting it as a walk if they just got there.
+    pub fn record_walk(&mut self, floor: usize, row: usize, column: usize) {
+        if self.last == Some((floor, row, column)) {
+            return;
+        }
+        self.last = Some((floor, row, column));
+        if let Some(i) = self.index(floor, row, column) {
+            self.walks[floor][i] += 1;
+            self.dirty = true;
+        }
+    }
+
+    pub fn record_death(&mut self, floor: usize, row: usize, column: usize) {
+        if let Some(i) = self.index(floor, row, column) {
+            self.deaths[floor][i] += 1;
+            self.dirty = true;
+        }
+    }
+
+    pub fn walks(&self, floor: usize, row: usize, column: usize) -> u32 {
+        self.index(floor, row, column)
+            .map_or(0, |i| self.walks[floor][i])
+    }
+
+    pub fn deaths(&self, floor: usize, row: usize, column: usize) -> u32 {
+        self.index(floor, row, column)
+            .map_or(0, |i| self.deaths[floor][i])
+    }
+
+    /// How much a cell has been walked through, from 0 for never to 1 for the most walked cell
+    /// on its floor.
+    pub fn heat(&self, floor: usize, row: usize, column: usize) -> f32 {
+        let most = self
+            .walks
+            .get(floor)
+            .and_then(|walks| walks.iter().max())
+            .copied()
+            .unwrap_or(0);
+        if most == 0 {
+            return 0.0;
+        }
+        self.walks(floor, row, column) as f32 / most as f32
+    }
+
+    /// Draws the heatmap as an image, with each floor below the one above it. Cells nobody has
+    /// walked through are black, and cells where anyone has died have a mark in the middle.
+    pub fn to_image(&self) -> RgbaImage {
+        let mut img = RgbaImage::new(
+            self.width as u32 * EXPORT_SCALE,
+            (self.height * self.floors()) as u32 * EXPORT_SCALE,
+        );
+        for floor in 0..self.floors() {
+            for row in 0..self.height {
+                for column in 0..self.width {
+                    let color = if self.walks(floor, row, column) == 0 {
+                        Color {
+                            r: 0,
+                            g: 0,
+                            b: 0,
+                            a: 0xff,
+                        }
+                    } else {
+                        heat_color(self.heat(floor, row, column))
+                    };
+                    let died = self.deaths(floor, row, column) > 0;
+                    let left = column as u32 * EXPORT_SCALE;
+                    let top = (floor * self.height + row) as u32 * EXPORT_SCALE;
+                    for y in 0..EXPORT_SCALE {
+                        for x in 0..EXPORT_SCALE {
+                            let middle = EXPORT_SCALE / 4..EXPORT_SCALE * 3 / 4;
+                            let color = if died && middle.contains(&x) && middle.contains(&y) {
+                                DEATH_COLOR
+                            } else {
+                                color
+                            };
+                            img.put_pixel(
+                                left + x,
+                                top + y,
+                                Rgba([color.r, color.g, color.b, color.a]),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        img
+    }
+
+    /// Saves the heatmap for a level and exports it as an image, if anything has changed since
+    /// the last time.
+    pub fn flush(&mut self, files: &FileManager, level: &str) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        files.write_user_data(&heatmap_path(level), self.to_json()?.as_bytes())?;
+        let mut png = Vec::new();
+        self.to_image()
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+        files.write_user_data(&heatmap_image_path(level), &png)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walks_and_deaths() {
+        let mut heatmap = Heatmap::new(2, 3, 2);
+        for _ in 0..3 {
+            heatmap.record_walk(0, 1, 2);
+        }
+        heatmap.record_walk(0, 0, 0);
+        heatmap.record_walk(0, 1, 2);
+        heatmap.record_walk(1, 1, 2);
+        heatmap.record_walk(1, 5, 5);
+        heatmap.record_death(0, 0, 0);
+        assert_eq!(heatmap.walks(0, 1, 2), 2);
+        assert_eq!(heatmap.walks(1, 1, 2), 1);
+        assert_eq!(heatmap.heat(0, 0, 0), 0.5);
+        assert_eq!(heatmap.heat(0, 1, 1), 0.0);
+        assert_eq!(heatmap.deaths(0, 0, 0), 1);
+
+        let copy = Heatmap::from_json(&heatmap.to_json().unwrap()).unwrap();
+        assert_eq!(copy.walks(0, 1, 2), 2);
+        assert_eq!(copy.deaths(0, 0, 0), 1);
+
+        let img = heatmap.to_image();
+        assert_eq!(img.dimensions(), (3 * EXPORT_SCALE, 4 * EXPORT_SCALE));
+        assert_eq!(img.get_pixel(0, 0).0, [0xff, 0x20, 0x20, 0xff]);
+        assert_eq!(
+            img.get_pixel(EXPORT_SCALE / 2, EXPORT_SCALE / 2).0,
+            [0xff; 4]
+        );
+        assert_eq!(img.get_pixel(EXPORT_SCALE, 0).0, [0, 0, 0, 0xff]);
+    }
+}
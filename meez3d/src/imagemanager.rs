@@ -1,14 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{BufRead, BufReader};
+use std::iter;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use anyhow::{anyhow, bail, Result};
-use log::info;
+use image::RgbaImage;
+use log::{error, info, warn};
 
-use crate::filemanager::FileManager;
+use crate::atlasallocator::AtlasAllocator;
+use crate::atlaspacker::pack_images;
+use crate::atlasreport::{clip, AtlasProblem, AtlasReport, AtlasUsage};
+use crate::constants::FRAME_RATE;
+use crate::decodepool::DecodePool;
+use crate::filemanager::{DirEntryType, FileManager};
 use crate::font::Font;
 use crate::geometry::Rect;
-use crate::renderer::Renderer;
+use crate::rendercontext::RenderContext;
+use crate::renderer::{NullRenderer, Renderer, RendererStats};
 use crate::sprite::{Animation, Sprite, SpriteSheet};
 use crate::utils::normalize_path;
 
@@ -28,12 +37,113 @@ pub trait ImageLoader {
         sprite_width: i32,
         sprite_height: i32,
     ) -> Result<Animation>;
+
+    /// Stats from the last frame the renderer drew, so the engine can pass them to the game.
+    fn renderer_stats(&self) -> RendererStats {
+        RendererStats::default()
+    }
+
+    /// Points the sprites drawn this frame at where their images are in the atlas now, if it's
+    /// been reloaded since they were handed out. The engine calls this after drawing each frame.
+    fn resolve_sprites(&self, _context: &mut RenderContext) {}
+}
+
+/// How often hot reloading checks whether the texture atlas has changed, in calls to
+/// reload_changed_atlas, which hosts make once a frame.
+const HOT_RELOAD_CHECK_FRAMES: u32 = FRAME_RATE;
+
+/// The widest or tallest a packed texture atlas can be, which is the most wgpu allows by default.
+const MAX_ATLAS_SIZE: i32 = 8192;
+
+/// The files the texture atlas is made from.
+#[derive(Debug, Clone)]
+enum AtlasFiles {
+    /// An atlas packed ahead of time, with an index of where each image is in it.
+    Baked {
+        image_path: PathBuf,
+        index_path: PathBuf,
+    },
+    /// A directory of images, packed as they're loaded.
+    Packed { dir: PathBuf },
+}
+
+/// Where the texture atlas was loaded from, so it can be loaded again when it changes.
+struct AtlasSource {
+    files: AtlasFiles,
+    /// When any of the files last changed, as of the last load.
+    modified: Option<SystemTime>,
+}
+
+impl AtlasSource {
+    fn latest_modified(&self, files: &FileManager) -> Option<SystemTime> {
+        match &self.files {
+            AtlasFiles::Baked {
+                image_path,
+                index_path,
+            } => files.modified(image_path).max(files.modified(index_path)),
+            AtlasFiles::Packed { dir } => {
+                // The directory itself changes when images are added or removed.
+                let images = packable_images(dir, files).unwrap_or_default();
+                images
+                    .iter()
+                    .map(|path| files.modified(path))
+                    .chain(iter::once(files.modified(dir)))
+                    .max()
+                    .flatten()
+            }
+        }
+    }
+}
+
+/// The images in a directory that go in a packed atlas. An image with an index next to it, like
+/// textures.png with textures_index.txt, is an atlas itself, so it's left out.
+fn packable_images(dir: &Path, files: &FileManager) -> Result<Vec<PathBuf>> {
+    let entries = files.read_dir(dir)?;
+    let names: HashSet<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+    let mut paths: Vec<PathBuf> = entries
+        .iter()
+        .filter(|entry| matches!(entry.file_type, DirEntryType::File))
+        .filter_map(|entry| {
+            let stem = entry.name.strip_suffix(".png")?;
+            if names.contains(format!("{}_index.txt", stem).as_str()) {
+                return None;
+            }
+            Some(entry.full_path.clone())
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// A texture atlas, and what's been put in it.
+struct Atlas {
+    /// The sprite covering the whole atlas.
+    sprite: Sprite,
+    /// The space in it that's still free.
+    space: AtlasAllocator,
+    /// Where each image is in it.
+    images: Vec<Rect<i32>>,
+    /// The regions from allocate_atlas_region that haven't been released.
+    regions: Vec<Rect<i32>>,
 }
 
 pub struct ImageManager<T: Renderer> {
+    /// The sprite handed out for each image, which stays the same when the atlas is reloaded.
     path_to_sprite: HashMap<PathBuf, Sprite>,
+    /// Where the images that moved when the atlas was reloaded are now, by the id and area of
+    /// the sprites handed out for them.
+    moved: HashMap<(usize, Rect<i32>), Sprite>,
     renderer: T,
     locked: bool, // once it's locked, it can't read more images
+    atlases: Vec<Atlas>,
+    atlas_source: Option<AtlasSource>,
+    /// Whether the texture atlas is loaded again when its files change, while iterating on art.
+    hot_reload: bool,
+    /// How many calls to reload_changed_atlas since the files were last checked.
+    frames_since_check: u32,
+    /// Whether atlas problems are kept for atlas_report, instead of just failing.
+    diagnostics: bool,
+    atlas_problems: Vec<AtlasProblem>,
 }
 
 impl<T> ImageManager<T>
@@ -45,8 +155,15 @@ where
         let locked = false;
         Ok(ImageManager {
             path_to_sprite,
+            moved: HashMap::new(),
             renderer,
             locked,
+            atlases: Vec::new(),
+            atlas_source: None,
+            hot_reload: false,
+            frames_since_check: 0,
+            diagnostics: false,
+            atlas_problems: Vec::new(),
         })
     }
 
@@ -68,13 +185,178 @@ where
         index_path: &Path,
         files: &FileManager,
     ) -> Result<()> {
+        profile_scope!("load_texture_atlas", path = ?image_path);
         info!("loading texture atlas from {image_path:?} with index {index_path:?}");
-        let base_path = index_path.parent().unwrap();
-        let base_sprite = self.load_sprite(image_path)?;
+        let atlas_files = AtlasFiles::Baked {
+            image_path: image_path.to_owned(),
+            index_path: index_path.to_owned(),
+        };
+        self.load_atlas(atlas_files, files)
+    }
+
+    /// Packs every image in a directory into the texture atlas as it's loaded, instead of using
+    /// one packed ahead of time, so there's no atlas to rebuild after changing the art.
+    pub fn pack_texture_atlas(&mut self, dir: &Path, files: &FileManager) -> Result<()> {
+        profile_scope!("pack_texture_atlas", path = ?dir);
+        info!("packing texture atlas from {dir:?}");
+        let atlas_files = AtlasFiles::Packed {
+            dir: dir.to_owned(),
+        };
+        self.load_atlas(atlas_files, files)
+    }
+
+    fn load_atlas(&mut self, atlas_files: AtlasFiles, files: &FileManager) -> Result<()> {
+        if self.locked {
+            bail!("image manager is locked while loading: {:?}", atlas_files);
+        }
+        let mut source = AtlasSource {
+            files: atlas_files,
+            modified: None,
+        };
+        source.modified = source.latest_modified(files);
+        self.read_atlas(&source.files, files)?;
+        self.atlas_source = Some(source);
+        self.locked = true;
+        Ok(())
+    }
+
+    /// Turns hot reloading of the texture atlas on or off. Hosts turn it on when the assets are
+    /// read straight from disk, so art can be changed without restarting the game.
+    pub fn set_hot_reload(&mut self, hot_reload: bool) {
+        self.hot_reload = hot_reload;
+    }
+
+    /// With hot reloading on, loads the texture atlas and its index again if either has changed
+    /// since they were last loaded, returning whether they were. Hosts call this once a frame,
+    /// but the files are only checked about once a second.
+    ///
+    /// Sprites that were already handed out, like the font's, are still used for their images,
+    /// and resolve_sprites points them at wherever the images are now. Regions from
+    /// allocate_atlas_region are forgotten, since the new image doesn't have their pixels.
+    pub fn reload_changed_atlas(&mut self, files: &FileManager) -> bool {
+        if !self.hot_reload {
+            return false;
+        }
+        self.frames_since_check += 1;
+        if self.frames_since_check < HOT_RELOAD_CHECK_FRAMES {
+            return false;
+        }
+        self.frames_since_check = 0;
+        let Some(source) = &mut self.atlas_source else {
+            return false;
+        };
+        let modified = source.latest_modified(files);
+        if modified.is_none() || modified == source.modified {
+            return false;
+        }
+        // Don't try again until it changes, even if it's broken.
+        source.modified = modified;
+        let atlas_files = source.files.clone();
+        let handed_out = self.path_to_sprite.clone();
+        match self.read_atlas(&atlas_files, files) {
+            Ok(()) => {
+                info!("reloaded texture atlas from {:?}", atlas_files);
+                self.keep_handed_out(handed_out);
+                true
+            }
+            Err(e) => {
+                error!("not reloading texture atlas: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Puts back the sprites handed out before a reload, keeping track of where the ones whose
+    /// images moved are now.
+    fn keep_handed_out(&mut self, handed_out: HashMap<PathBuf, Sprite>) {
+        let key = |sprite: Sprite| (sprite.id, sprite.area);
+        let mut added = self.path_to_sprite.clone();
+        for (path, sprite) in handed_out {
+            let Some(current) = added.remove(&path) else {
+                continue;
+            };
+            if key(current) == key(sprite) {
+                self.moved.remove(&key(sprite));
+            } else {
+                self.moved.insert(key(sprite), current);
+            }
+            self.path_to_sprite.insert(path, sprite);
+        }
+        for (path, sprite) in added {
+            if self.moved.contains_key(&key(sprite)) {
+                warn!(
+                    "{:?} was added where another image used to be, so it needs a restart",
+                    path
+                );
+            }
+        }
+    }
+
+    /// Turns atlas diagnostics on or off. Turn them on before loading the texture atlas.
+    ///
+    /// With diagnostics on, problems with the atlas are kept for atlas_report, and loading
+    /// carries on past them, so one run finds all of them. Images too big for a packed atlas are
+    /// left out of it, and sprites that aren't in the atlas are loaded as empty placeholders,
+    /// which draw whatever is at the atlas's top left.
+    pub fn set_atlas_diagnostics(&mut self, diagnostics: bool) {
+        self.diagnostics = diagnostics;
+    }
+
+    /// How full each texture atlas is, and the problems found while diagnostics were on.
+    pub fn atlas_report(&self) -> AtlasReport {
+        AtlasReport {
+            atlases: self
+                .atlases
+                .iter()
+                .map(|atlas| AtlasUsage {
+                    width: atlas.sprite.area.w,
+                    height: atlas.sprite.area.h,
+                    images: atlas.images.clone(),
+                    regions: atlas.regions.clone(),
+                })
+                .collect(),
+            problems: self.atlas_problems.clone(),
+        }
+    }
+
+    /// Logs a problem with the atlas that loading carries on past, and keeps it for
+    /// atlas_report if diagnostics are on.
+    fn atlas_problem(&mut self, problem: AtlasProblem) {
+        warn!("texture atlas problem: {}", problem);
+        self.record_atlas_problem(problem);
+    }
+
+    /// Keeps a problem for atlas_report if diagnostics are on, without logging it, for problems
+    /// that fail with an error saying what went wrong.
+    fn record_atlas_problem(&mut self, problem: AtlasProblem) {
+        if self.diagnostics {
+            self.atlas_problems.push(problem);
+        }
+    }
 
+    fn read_atlas(&mut self, atlas_files: &AtlasFiles, files: &FileManager) -> Result<()> {
+        match atlas_files {
+            AtlasFiles::Baked {
+                image_path,
+                index_path,
+            } => self.read_texture_atlas(image_path, index_path, files),
+            AtlasFiles::Packed { dir } => self.read_packed_atlas(dir, files),
+        }
+    }
+
+    /// Reads the atlas index, and then uploads the atlas image, so a broken index leaves the
+    /// previous atlas alone.
+    fn read_texture_atlas(
+        &mut self,
+        image_path: &Path,
+        index_path: &Path,
+        files: &FileManager,
+    ) -> Result<()> {
+        let base_path = index_path.parent().unwrap();
         let index_bytes = files
             .read(index_path)
             .map_err(|e| anyhow!("unable to open texture atlas index {:?}: {}", index_path, e))?;
+        let mut entries = Vec::new();
         let mut r = BufReader::new(&index_bytes[..]);
         loop {
             let mut line = String::new();
@@ -97,16 +379,190 @@ where
             let w = parts[2].parse()?;
             let h = parts[3].parse()?;
             let area = Rect { x, y, w, h };
-            let sprite = base_sprite.subview(area);
+            entries.push((base_path.join(parts[4]), area));
+        }
 
-            let path = base_path.join(parts[4]);
+        let base_sprite = self.renderer.load_texture_atlas(image_path, files)?;
+        self.path_to_sprite
+            .insert(normalize_path(image_path)?, base_sprite);
+        self.atlases.clear();
+        self.atlas_problems.clear();
+        self.add_atlas(base_sprite, entries);
+        Ok(())
+    }
+
+    /// Decodes and packs the images in a directory, and then uploads the atlases, so a broken
+    /// image leaves the previous ones alone. Images that don't all fit in one atlas are split
+    /// across several.
+    ///
+    /// The images are read in order, but decoded in parallel.
+    fn read_packed_atlas(&mut self, dir: &Path, files: &FileManager) -> Result<()> {
+        let mut encoded = Vec::new();
+        for path in packable_images(dir, files)? {
+            let bytes = files.read(&path)?;
+            encoded.push((normalize_path(&path)?, bytes));
+        }
+        let mut images = DecodePool::new()
+            .decode_all(encoded, |(path, bytes)| {
+                let image = image::load_from_memory(&bytes)
+                    .map_err(|e| anyhow!("unable to load image from {:?}: {}", path, e))?;
+                Ok((path, image.to_rgba8()))
+            })
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+        let oversized = self.remove_oversized(&mut images)?;
+        let atlases = pack_images(images, MAX_ATLAS_SIZE)?;
+        if atlases.is_empty() {
+            bail!("no images to pack in {:?}", dir);
+        }
+        self.atlases.clear();
+        self.atlas_problems.clear();
+        for problem in oversized {
+            self.atlas_problem(problem);
+        }
+        for (i, atlas) in atlases.into_iter().enumerate() {
+            let (width, height) = atlas.image.dimensions();
+            let pixels = atlas.image.as_raw();
+            let base_sprite = if i == 0 {
+                self.renderer.create_texture_atlas(width, height, pixels)?
+            } else {
+                self.renderer.add_texture_atlas(width, height, pixels)?
+            };
+            self.add_atlas(base_sprite, atlas.entries);
+        }
+        Ok(())
+    }
+
+    /// Fails on the first image too big for any atlas, naming it. With diagnostics on, leaves
+    /// them all out instead, and returns a problem for each one.
+    fn remove_oversized(
+        &self,
+        images: &mut Vec<(PathBuf, RgbaImage)>,
+    ) -> Result<Vec<AtlasProblem>> {
+        let fits = |image: &RgbaImage| {
+            image.width() as i32 <= MAX_ATLAS_SIZE && image.height() as i32 <= MAX_ATLAS_SIZE
+        };
+        let mut oversized = Vec::new();
+        for (path, image) in images.iter() {
+            if fits(image) {
+                continue;
+            }
+            let (width, height) = (image.width() as i32, image.height() as i32);
+            if !self.diagnostics {
+                bail!(
+                    "image {:?} is {}x{}, which is too big for a {}x{} atlas",
+                    path,
+                    width,
+                    height,
+                    MAX_ATLAS_SIZE,
+                    MAX_ATLAS_SIZE
+                );
+            }
+            oversized.push(AtlasProblem::Oversized {
+                path: path.clone(),
+                width,
+                height,
+                max_size: MAX_ATLAS_SIZE,
+            });
+        }
+        images.retain(|(_, image)| fits(image));
+        Ok(oversized)
+    }
+
+    /// Hands out sprites for the images in an atlas, and keeps track of the space left in it.
+    fn add_atlas(&mut self, base_sprite: Sprite, entries: Vec<(PathBuf, Rect<i32>)>) {
+        let (width, height) = (base_sprite.area.w, base_sprite.area.h);
+        let mut atlas = Atlas {
+            sprite: base_sprite,
+            space: AtlasAllocator::new(width, height),
+            images: Vec::with_capacity(entries.len()),
+            regions: Vec::new(),
+        };
+        for (path, area) in entries {
             info!("loaded image from texture atlas: {:?} at {:?}", path, area);
+            if clip(area, width, height) != Some(area) {
+                self.atlas_problem(AtlasProblem::OutOfBounds {
+                    path: path.clone(),
+                    area,
+                });
+            }
+            atlas.space.reserve(area);
+            atlas.images.push(area);
+            self.path_to_sprite.insert(path, base_sprite.subview(area));
+        }
+        self.atlases.push(atlas);
+    }
 
-            self.path_to_sprite.insert(path, sprite);
+    /// Claims an unused width x height region of a texture atlas, e.g. for cached text, from
+    /// the first atlas with room for it.
+    ///
+    /// Unlike a dynamic texture, it's drawn along with the rest of the atlas, so it doesn't
+    /// split up the batch. Fill it with update_atlas_region.
+    pub fn allocate_atlas_region(&mut self, width: u32, height: u32) -> Result<Sprite> {
+        if self.atlases.is_empty() {
+            bail!("no texture atlas has been loaded");
         }
+        for atlas in self.atlases.iter_mut() {
+            if let Some(area) = atlas.space.allocate(width as i32, height as i32) {
+                atlas.regions.push(area);
+                return Ok(atlas.sprite.subview(area));
+            }
+        }
+        self.record_atlas_problem(AtlasProblem::NoRoom { width, height });
+        bail!("no room in any texture atlas for {}x{}", width, height)
+    }
 
-        self.locked = true;
-        Ok(())
+    /// Replaces the pixels of a region from allocate_atlas_region with width * height RGBA pixels.
+    pub fn update_atlas_region(&mut self, sprite: &Sprite, pixels: &[u8]) -> Result<()> {
+        self.renderer.update_texture_atlas(sprite, pixels)
+    }
+
+    /// Returns a region from allocate_atlas_region, so its space can be used again.
+    pub fn release_atlas_region(&mut self, sprite: &Sprite) {
+        let atlas = self
+            .atlases
+            .iter_mut()
+            .find(|atlas| atlas.sprite.id == sprite.id);
+        if let Some(atlas) = atlas {
+            atlas.space.release(sprite.area);
+            atlas.regions.retain(|area| *area != sprite.area);
+        }
+    }
+
+    /// Dynamic textures aren't part of the atlas, so they can be created even after it's locked.
+    pub fn create_dynamic_texture(&mut self, width: u32, height: u32) -> Result<Sprite> {
+        self.renderer.create_dynamic_texture(width, height)
+    }
+
+    pub fn update_dynamic_texture(&mut self, sprite: &Sprite, pixels: &[u8]) -> Result<()> {
+        self.renderer.update_dynamic_texture(sprite, pixels)
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.renderer.resize(width, height);
+    }
+
+    pub fn render(&mut self, context: &RenderContext) -> Result<()> {
+        profile_scope!("render");
+        self.renderer.render(context)
+    }
+}
+
+impl ImageManager<NullRenderer> {
+    /// An image manager that hands out placeholder sprites without loading any images.
+    pub fn null_manager() -> ImageManager<NullRenderer> {
+        ImageManager {
+            path_to_sprite: HashMap::new(),
+            moved: HashMap::new(),
+            renderer: NullRenderer::new(),
+            locked: false,
+            atlases: Vec::new(),
+            atlas_source: None,
+            hot_reload: false,
+            frames_since_check: 0,
+            diagnostics: false,
+            atlas_problems: Vec::new(),
+        }
     }
 }
 
@@ -115,6 +571,7 @@ where
     T: Renderer,
 {
     fn load_sprite(&mut self, path: &Path) -> Result<Sprite> {
+        profile_scope!("load_sprite", path = ?path);
         info!("loading sprite from path: {:?}", path);
         let path = normalize_path(path)?;
         info!("loading sprite from normalized path: {:?}", path);
@@ -123,7 +580,17 @@ where
             return Ok(*existing);
         }
         if self.locked {
-            bail!("image manager is locked while loading: {:?}", path);
+            let placeholder = match self.atlases.first() {
+                Some(atlas) if self.diagnostics => atlas.sprite.subview(Rect {
+                    x: 0,
+                    y: 0,
+                    w: 0,
+                    h: 0,
+                }),
+                _ => bail!("image {:?} isn't in the texture atlas", path),
+            };
+            self.atlas_problem(AtlasProblem::Missing { path });
+            return Ok(placeholder);
         }
         let sprite = self.renderer.load_sprite(&path)?;
         self.path_to_sprite.insert(path.to_owned(), sprite);
@@ -151,4 +618,251 @@ where
         Animation::new(sprite, sprite_width, sprite_height)
             .map_err(|e| anyhow!("unable to create animation {:?}: {}", path, e,))
     }
+
+    fn renderer_stats(&self) -> RendererStats {
+        self.renderer.stats()
+    }
+
+    fn resolve_sprites(&self, context: &mut RenderContext) {
+        if self.moved.is_empty() {
+            return;
+        }
+        for sprite in context.sprites_mut() {
+            if let Some(current) = self.moved.get(&(sprite.id, sprite.area)) {
+                *sprite = *current;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use image::{ImageOutputFormat, RgbaImage};
+
+    use super::*;
+    use crate::filemanager::{DirEntry, FileManagerImpl};
+    use crate::rendercontext::RenderLayer;
+
+    /// An index file whose text and change time can be changed after it's boxed.
+    struct ChangingIndex {
+        state: Rc<RefCell<(String, SystemTime)>>,
+    }
+
+    impl FileManagerImpl for ChangingIndex {
+        fn read(&self, path: &Path) -> Result<Vec<u8>> {
+            Ok(self.read_to_string(path)?.into_bytes())
+        }
+
+        fn read_to_string(&self, _path: &Path) -> Result<String> {
+            Ok(self.state.borrow().0.clone())
+        }
+
+        fn read_dir(&self, dir_path: &Path) -> Result<Vec<DirEntry>> {
+            bail!("no directory at {:?}", dir_path)
+        }
+
+        fn modified(&self, _path: &Path) -> Option<SystemTime> {
+            Some(self.state.borrow().1)
+        }
+    }
+
+    #[test]
+    fn hot_reload() {
+        let state = Rc::new(RefCell::new((
+            "0,0,64,64,a.png".to_string(),
+            SystemTime::UNIX_EPOCH,
+        )));
+        let files = FileManager::with_internal(Box::new(ChangingIndex {
+            state: state.clone(),
+        }));
+        let mut images = ImageManager::null_manager();
+        let (image, index) = (Path::new("assets/atlas.png"), Path::new("assets/index.txt"));
+        images.load_texture_atlas(image, index, &files).unwrap();
+        images.set_hot_reload(true);
+        let check = |images: &mut ImageManager<NullRenderer>| {
+            (0..HOT_RELOAD_CHECK_FRAMES).any(|_| images.reload_changed_atlas(&files))
+        };
+        // The sprite handed out at the start is drawn wherever the image is now.
+        let sprite = images.load_sprite(Path::new("assets/a.png")).unwrap();
+        let area = |images: &mut ImageManager<NullRenderer>| {
+            let handed_out = images.load_sprite(Path::new("assets/a.png")).unwrap();
+            assert_eq!(handed_out.area, sprite.area);
+            let mut context = RenderContext::new(64, 64, 0).unwrap();
+            context.draw(sprite, RenderLayer::Hud, sprite.area, sprite.area);
+            images.resolve_sprites(&mut context);
+            let drawn = context.sprites_mut().next().unwrap().area;
+            drawn
+        };
+
+        // Nothing happens until the files change.
+        state.borrow_mut().0 = "64,0,32,32,a.png".to_string();
+        assert!(!check(&mut images));
+        assert_eq!(area(&mut images).x, 0);
+
+        state.borrow_mut().1 += Duration::from_secs(1);
+        assert!(check(&mut images));
+        assert_eq!(area(&mut images).x, 64);
+
+        // A broken index leaves the atlas as it was.
+        *state.borrow_mut() = ("64,0".to_string(), SystemTime::UNIX_EPOCH);
+        assert!(!check(&mut images));
+        assert_eq!(area(&mut images).x, 64);
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        RgbaImage::new(width, height)
+            .write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn pack_directory() {
+        let mut map = HashMap::new();
+        map.insert(PathBuf::from("assets/wide.png"), png(40, 10));
+        map.insert(PathBuf::from("assets/tall.png"), png(8, 30));
+        map.insert(PathBuf::from("assets/notes.txt"), b"not an image".to_vec());
+        // An atlas packed ahead of time isn't packed again.
+        map.insert(PathBuf::from("assets/textures.png"), png(64, 64));
+        map.insert(PathBuf::from("assets/textures_index.txt"), Vec::new());
+        let files = FileManager::from_memory(map).unwrap();
+
+        let mut images = ImageManager::null_manager();
+        images
+            .pack_texture_atlas(Path::new("assets"), &files)
+            .unwrap();
+        let wide = images.load_sprite(Path::new("assets/wide.png")).unwrap();
+        let tall = images.load_sprite(Path::new("assets/tall.png")).unwrap();
+        assert_eq!((wide.area.w, wide.area.h), (40, 10));
+        assert_eq!((tall.area.w, tall.area.h), (8, 30));
+        assert_eq!(wide.id, tall.id);
+        let base_sprite = images.atlases[0].sprite;
+        assert_eq!((base_sprite.area.w, base_sprite.area.h), (64, 30));
+    }
+
+    #[test]
+    fn regions_in_any_atlas() {
+        let mut images = ImageManager::null_manager();
+        let area = Rect {
+            x: 0,
+            y: 0,
+            w: 16,
+            h: 16,
+        };
+        let full = images
+            .renderer
+            .create_texture_atlas(16, 16, &[0; 1024])
+            .unwrap();
+        images.add_atlas(full, vec![(PathBuf::from("assets/a.png"), area)]);
+        let empty = images
+            .renderer
+            .add_texture_atlas(16, 16, &[0; 1024])
+            .unwrap();
+        images.add_atlas(empty, Vec::new());
+
+        // The first atlas is full, so the region comes from the second.
+        let region = images.allocate_atlas_region(16, 8).unwrap();
+        assert_eq!(region.id, empty.id);
+        images.update_atlas_region(&region, &[0; 512]).unwrap();
+        assert!(images.allocate_atlas_region(16, 16).is_err());
+
+        images.release_atlas_region(&region);
+        assert!(images.allocate_atlas_region(16, 16).is_ok());
+    }
+
+    #[test]
+    fn diagnostics() {
+        let mut map = HashMap::new();
+        map.insert(PathBuf::from("assets/small.png"), png(16, 8));
+        map.insert(
+            PathBuf::from("assets/huge.png"),
+            png(MAX_ATLAS_SIZE as u32 + 1, 1),
+        );
+        let files = FileManager::from_memory(map).unwrap();
+
+        // Without diagnostics, the first problem fails, and says which image it was.
+        let mut images = ImageManager::null_manager();
+        let err = images
+            .pack_texture_atlas(Path::new("assets"), &files)
+            .unwrap_err();
+        assert!(err.to_string().contains("huge.png"));
+
+        // With them, loading carries on, and the report has everything that went wrong.
+        let mut images = ImageManager::null_manager();
+        images.set_atlas_diagnostics(true);
+        images
+            .pack_texture_atlas(Path::new("assets"), &files)
+            .unwrap();
+        assert_eq!(
+            images
+                .load_sprite(Path::new("assets/small.png"))
+                .unwrap()
+                .area
+                .w,
+            16
+        );
+        let missing = images.load_sprite(Path::new("assets/gone.png")).unwrap();
+        assert_eq!((missing.area.w, missing.area.h), (0, 0));
+        assert!(images.allocate_atlas_region(4, 4).is_err());
+        let outside = Rect {
+            x: 8,
+            y: 0,
+            w: 16,
+            h: 8,
+        };
+        let base_sprite = images.atlases[0].sprite;
+        images.add_atlas(
+            base_sprite,
+            vec![(PathBuf::from("assets/off.png"), outside)],
+        );
+
+        let report = images.atlas_report();
+        assert_eq!(
+            report.problems,
+            vec![
+                AtlasProblem::Oversized {
+                    path: PathBuf::from("assets/huge.png"),
+                    width: MAX_ATLAS_SIZE + 1,
+                    height: 1,
+                    max_size: MAX_ATLAS_SIZE,
+                },
+                AtlasProblem::Missing {
+                    path: PathBuf::from("assets/gone.png"),
+                },
+                AtlasProblem::NoRoom {
+                    width: 4,
+                    height: 4
+                },
+                AtlasProblem::OutOfBounds {
+                    path: PathBuf::from("assets/off.png"),
+                    area: outside,
+                },
+            ]
+        );
+        assert_eq!(report.atlases.len(), 2);
+        assert_eq!(report.atlases[0].utilization(), 1.0);
+        assert_eq!(report.atlases[1].used_pixels(), 8 * 8);
+        let text = report.to_string();
+        assert!(text.contains("atlas 0: 16x8, 1 images, 0 regions, 100.0% used"));
+        assert!(text.contains("missing: \"assets/gone.png\""));
+
+        // The atlases are drawn one above the other.
+        let image = report.to_image();
+        assert_eq!(image.dimensions(), (16, 8 * 2 + 4));
+        assert_ne!(image.get_pixel(0, 0), image.get_pixel(0, 12));
+
+        // Packing again starts the problems over, since they were about the old atlases.
+        let dir = PathBuf::from("assets");
+        images
+            .read_atlas(&AtlasFiles::Packed { dir }, &files)
+            .unwrap();
+        let problems = images.atlas_report().problems;
+        assert!(matches!(problems[..], [AtlasProblem::Oversized { .. }]));
+    }
 }
@@ -1,19 +1,24 @@
 use std::collections::VecDeque;
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Error, Result};
 use gilrs::Gilrs;
 use log::{debug, error, info};
 use num_traits::Zero;
 
+use crate::cvars::Cvars;
 use crate::filemanager::FileManager;
 use crate::geometry::Point;
+use crate::keybindings::{Action, Binding, KeyBindings};
 use crate::smallintmap::SmallIntMap;
-use crate::{RENDER_HEIGHT, RENDER_WIDTH};
+use crate::{FRAME_RATE, RENDER_HEIGHT, RENDER_WIDTH};
 
+/// A key on the keyboard that can be bound to an action.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum KeyboardKey {
+pub enum KeyboardKey {
     Escape,
     Space,
     Enter,
@@ -27,6 +32,29 @@ enum KeyboardKey {
     Down,
     Left,
     Right,
+    B,
+    C,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    R,
+    T,
+    U,
+    V,
+    X,
+    Y,
+    Z,
+    Tab,
+    Shift,
+    Control,
 }
 
 impl KeyboardKey {
@@ -47,6 +75,29 @@ impl KeyboardKey {
             Keycode::Down => KeyboardKey::Down,
             Keycode::Left => KeyboardKey::Left,
             Keycode::Right => KeyboardKey::Right,
+            Keycode::B => KeyboardKey::B,
+            Keycode::C => KeyboardKey::C,
+            Keycode::F => KeyboardKey::F,
+            Keycode::G => KeyboardKey::G,
+            Keycode::H => KeyboardKey::H,
+            Keycode::I => KeyboardKey::I,
+            Keycode::J => KeyboardKey::J,
+            Keycode::K => KeyboardKey::K,
+            Keycode::L => KeyboardKey::L,
+            Keycode::M => KeyboardKey::M,
+            Keycode::N => KeyboardKey::N,
+            Keycode::O => KeyboardKey::O,
+            Keycode::P => KeyboardKey::P,
+            Keycode::R => KeyboardKey::R,
+            Keycode::T => KeyboardKey::T,
+            Keycode::U => KeyboardKey::U,
+            Keycode::V => KeyboardKey::V,
+            Keycode::X => KeyboardKey::X,
+            Keycode::Y => KeyboardKey::Y,
+            Keycode::Z => KeyboardKey::Z,
+            Keycode::Tab => KeyboardKey::Tab,
+            Keycode::LShift => KeyboardKey::Shift,
+            Keycode::LCtrl => KeyboardKey::Control,
             _ => return None,
         })
     }
@@ -68,19 +119,132 @@ impl KeyboardKey {
             KeyCode::ArrowDown => KeyboardKey::Down,
             KeyCode::ArrowLeft => KeyboardKey::Left,
             KeyCode::ArrowRight => KeyboardKey::Right,
+            KeyCode::KeyB => KeyboardKey::B,
+            KeyCode::KeyC => KeyboardKey::C,
+            KeyCode::KeyF => KeyboardKey::F,
+            KeyCode::KeyG => KeyboardKey::G,
+            KeyCode::KeyH => KeyboardKey::H,
+            KeyCode::KeyI => KeyboardKey::I,
+            KeyCode::KeyJ => KeyboardKey::J,
+            KeyCode::KeyK => KeyboardKey::K,
+            KeyCode::KeyL => KeyboardKey::L,
+            KeyCode::KeyM => KeyboardKey::M,
+            KeyCode::KeyN => KeyboardKey::N,
+            KeyCode::KeyO => KeyboardKey::O,
+            KeyCode::KeyP => KeyboardKey::P,
+            KeyCode::KeyR => KeyboardKey::R,
+            KeyCode::KeyT => KeyboardKey::T,
+            KeyCode::KeyU => KeyboardKey::U,
+            KeyCode::KeyV => KeyboardKey::V,
+            KeyCode::KeyX => KeyboardKey::X,
+            KeyCode::KeyY => KeyboardKey::Y,
+            KeyCode::KeyZ => KeyboardKey::Z,
+            KeyCode::Tab => KeyboardKey::Tab,
+            KeyCode::ShiftLeft => KeyboardKey::Shift,
+            KeyCode::ControlLeft => KeyboardKey::Control,
             _ => return None,
         })
     }
 }
 
+impl FromStr for KeyboardKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "escape" => KeyboardKey::Escape,
+            "space" => KeyboardKey::Space,
+            "enter" => KeyboardKey::Enter,
+            "w" => KeyboardKey::W,
+            "a" => KeyboardKey::A,
+            "s" => KeyboardKey::S,
+            "d" => KeyboardKey::D,
+            "q" => KeyboardKey::Q,
+            "e" => KeyboardKey::E,
+            "up" => KeyboardKey::Up,
+            "down" => KeyboardKey::Down,
+            "left" => KeyboardKey::Left,
+            "right" => KeyboardKey::Right,
+            "b" => KeyboardKey::B,
+            "c" => KeyboardKey::C,
+            "f" => KeyboardKey::F,
+            "g" => KeyboardKey::G,
+            "h" => KeyboardKey::H,
+            "i" => KeyboardKey::I,
+            "j" => KeyboardKey::J,
+            "k" => KeyboardKey::K,
+            "l" => KeyboardKey::L,
+            "m" => KeyboardKey::M,
+            "n" => KeyboardKey::N,
+            "o" => KeyboardKey::O,
+            "p" => KeyboardKey::P,
+            "r" => KeyboardKey::R,
+            "t" => KeyboardKey::T,
+            "u" => KeyboardKey::U,
+            "v" => KeyboardKey::V,
+            "x" => KeyboardKey::X,
+            "y" => KeyboardKey::Y,
+            "z" => KeyboardKey::Z,
+            "tab" => KeyboardKey::Tab,
+            "shift" => KeyboardKey::Shift,
+            "control" => KeyboardKey::Control,
+            _ => bail!("invalid key: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for KeyboardKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            KeyboardKey::Escape => "escape",
+            KeyboardKey::Space => "space",
+            KeyboardKey::Enter => "enter",
+            KeyboardKey::W => "w",
+            KeyboardKey::A => "a",
+            KeyboardKey::S => "s",
+            KeyboardKey::D => "d",
+            KeyboardKey::Q => "q",
+            KeyboardKey::E => "e",
+            KeyboardKey::Up => "up",
+            KeyboardKey::Down => "down",
+            KeyboardKey::Left => "left",
+            KeyboardKey::Right => "right",
+            KeyboardKey::B => "b",
+            KeyboardKey::C => "c",
+            KeyboardKey::F => "f",
+            KeyboardKey::G => "g",
+            KeyboardKey::H => "h",
+            KeyboardKey::I => "i",
+            KeyboardKey::J => "j",
+            KeyboardKey::K => "k",
+            KeyboardKey::L => "l",
+            KeyboardKey::M => "m",
+            KeyboardKey::N => "n",
+            KeyboardKey::O => "o",
+            KeyboardKey::P => "p",
+            KeyboardKey::R => "r",
+            KeyboardKey::T => "t",
+            KeyboardKey::U => "u",
+            KeyboardKey::V => "v",
+            KeyboardKey::X => "x",
+            KeyboardKey::Y => "y",
+            KeyboardKey::Z => "z",
+            KeyboardKey::Tab => "tab",
+            KeyboardKey::Shift => "shift",
+            KeyboardKey::Control => "control",
+        })
+    }
+}
+
 impl From<KeyboardKey> for usize {
     fn from(value: KeyboardKey) -> Self {
         value as usize
     }
 }
 
+/// A button on a gamepad that can be bound to an action.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum JoystickButton {
+pub enum JoystickButton {
     Up = 0,
     Down,
     Left,
@@ -109,6 +273,39 @@ impl JoystickButton {
     }
 }
 
+impl FromStr for JoystickButton {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "dpad_up" => JoystickButton::Up,
+            "dpad_down" => JoystickButton::Down,
+            "dpad_left" => JoystickButton::Left,
+            "dpad_right" => JoystickButton::Right,
+            "north" => JoystickButton::North,
+            "south" => JoystickButton::South,
+            "east" => JoystickButton::East,
+            "west" => JoystickButton::West,
+            _ => bail!("invalid button: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for JoystickButton {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            JoystickButton::Up => "dpad_up",
+            JoystickButton::Down => "dpad_down",
+            JoystickButton::Left => "dpad_left",
+            JoystickButton::Right => "dpad_right",
+            JoystickButton::North => "north",
+            JoystickButton::South => "south",
+            JoystickButton::East => "east",
+            JoystickButton::West => "west",
+        })
+    }
+}
+
 impl From<JoystickButton> for usize {
     fn from(value: JoystickButton) -> Self {
         value as usize
@@ -158,11 +355,31 @@ impl TryInto<JoystickAxis> for u8 {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-enum MouseButton {
+/// A mouse button that can be bound to an action.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MouseButton {
     Left = 0,
 }
 
+impl FromStr for MouseButton {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "left" => MouseButton::Left,
+            _ => bail!("invalid mouse button: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for MouseButton {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MouseButton::Left => "left",
+        })
+    }
+}
+
 impl From<MouseButton> for usize {
     fn from(value: MouseButton) -> Self {
         value as usize
@@ -176,9 +393,13 @@ struct InputState {
     mouse_buttons_down: SmallIntMap<MouseButton, bool>,
 
     mouse_position: Point<i32>,
+    /// How far the mouse has moved since the last update, in the host's units.
+    mouse_delta: Point<i32>,
     adjust_mouse_position: bool,
     window_width: i32,
     window_height: i32,
+    /// The text edits typed since the last update, oldest first.
+    text_edits: VecDeque<TextEdit>,
 }
 
 impl InputState {
@@ -189,9 +410,11 @@ impl InputState {
             joy_axes: SmallIntMap::new(),
             mouse_buttons_down: SmallIntMap::new(),
             mouse_position: Point::zero(),
+            mouse_delta: Point::zero(),
             adjust_mouse_position,
             window_width,
             window_height,
+            text_edits: VecDeque::new(),
         }
     }
 
@@ -223,6 +446,10 @@ impl InputState {
         self.joy_axes.insert(axis, value);
     }
 
+    fn joy_axis(&self, axis: JoystickAxis) -> f32 {
+        *self.joy_axes.get(axis).unwrap_or(&0.0)
+    }
+
     fn set_mouse_button_down(&mut self, button: MouseButton) {
         self.mouse_buttons_down.insert(button, true);
     }
@@ -235,6 +462,21 @@ impl InputState {
         *self.mouse_buttons_down.get(button).unwrap_or(&false)
     }
 
+    fn add_mouse_delta(&mut self, dx: i32, dy: i32) {
+        self.mouse_delta = Point::new(self.mouse_delta.x + dx, self.mouse_delta.y + dy);
+    }
+
+    fn take_mouse_delta(&mut self) -> Point<i32> {
+        std::mem::replace(&mut self.mouse_delta, Point::zero())
+    }
+
+    /// Queues each character of typed text, skipping control characters like the ones some
+    /// platforms send for enter and backspace.
+    fn add_typed_text(&mut self, text: &str) {
+        let chars = text.chars().filter(|c| !c.is_control());
+        self.text_edits.extend(chars.map(TextEdit::Insert));
+    }
+
     fn set_window_size(&mut self, width: i32, height: i32) {
         self.window_width = width;
         self.window_height = height;
@@ -248,12 +490,23 @@ impl InputState {
         };
     }
 
+    /// Maps a position in the window to the render area.
+    ///
+    /// The window size has to be in the same units as the mouse events, which is logical points
+    /// for SDL and physical pixels for winit, even when the renderer is drawing at a higher dpi.
     fn get_adjusted_mouse_position(&mut self, pos_x: i32, pos_y: i32) -> Point<i32> {
+        if self.window_width <= 0 || self.window_height <= 0 {
+            // The window is minimized, so there's nowhere for the mouse to be.
+            return self.mouse_position;
+        }
         let x = (pos_x as f32) / (self.window_width as f32);
         let y = (pos_y as f32) / (self.window_height as f32);
         let x = x * (RENDER_WIDTH as f32);
         let y = y * (RENDER_HEIGHT as f32);
-        Point::new(x as i32, y as i32)
+        Point::new(
+            (x as i32).clamp(0, RENDER_WIDTH as i32 - 1),
+            (y as i32).clamp(0, RENDER_HEIGHT as i32 - 1),
+        )
     }
 }
 
@@ -458,6 +711,9 @@ enum BinaryInput {
     MenuLeft,
     MenuRight,
     MouseButtonLeft,
+    PlayerDash,
+    Inventory,
+    ItemDrop,
 }
 
 impl From<BinaryInput> for usize {
@@ -482,6 +738,9 @@ fn all_binary_inputs() -> Vec<BinaryInput> {
         BinaryInput::MenuLeft,
         BinaryInput::MenuRight,
         BinaryInput::MouseButtonLeft,
+        BinaryInput::PlayerDash,
+        BinaryInput::Inventory,
+        BinaryInput::ItemDrop,
     ]
 }
 
@@ -525,81 +784,264 @@ fn mouse_button_input(button: MouseButton) -> Box<CachedBinaryInput<MouseButtonI
     Box::new(CachedBinaryInput::from(MouseButtonInput::new(button)))
 }
 
-fn create_input(input: BinaryInput) -> AnyOfInput {
-    AnyOfInput(match input {
-        BinaryInput::OkTrigger => vec![
-            key_trigger(KeyboardKey::Enter),
-            joystick_button_trigger(JoystickButton::South),
-        ],
-        BinaryInput::OkDown => vec![
-            key_input(KeyboardKey::Enter),
-            joystick_button_input(JoystickButton::South),
-        ],
-        BinaryInput::Cancel => vec![
-            key_trigger(KeyboardKey::Escape),
-            joystick_button_trigger(JoystickButton::West),
-        ],
-        BinaryInput::PlayerMoveForward => vec![
-            key_input(KeyboardKey::Up),
-            key_input(KeyboardKey::W),
-            joystick_button_input(JoystickButton::Up),
-            joystick_threshold(JoystickAxis::PrimaryVertical, Some(-0.5), None),
-        ],
-        BinaryInput::PlayerMoveBackward => vec![
-            key_input(KeyboardKey::Down),
-            key_input(KeyboardKey::S),
-            joystick_button_input(JoystickButton::Down),
-            joystick_threshold(JoystickAxis::PrimaryVertical, None, Some(0.5)),
-        ],
-        BinaryInput::PlayerStrafeLeft => vec![
-            key_input(KeyboardKey::A),
-            joystick_button_input(JoystickButton::Left),
-            joystick_threshold(JoystickAxis::PrimaryHorizontal, Some(-0.5), None),
-        ],
-        BinaryInput::PlayerStrafeRight => vec![
-            key_input(KeyboardKey::D),
-            joystick_button_input(JoystickButton::Right),
-            joystick_threshold(JoystickAxis::PrimaryHorizontal, None, Some(0.5)),
-        ],
-        BinaryInput::PlayerTurnLeft => vec![
-            key_input(KeyboardKey::Left),
-            key_input(KeyboardKey::Q),
-            joystick_threshold(JoystickAxis::SecondaryHorizontal, Some(-0.5), None),
-        ],
-        BinaryInput::PlayerTurnRight => vec![
-            key_input(KeyboardKey::Right),
-            key_input(KeyboardKey::E),
-            joystick_threshold(JoystickAxis::SecondaryHorizontal, None, Some(0.5)),
-        ],
-        BinaryInput::MenuDown => vec![
-            key_trigger(KeyboardKey::Down),
-            key_trigger(KeyboardKey::S),
-            joystick_button_trigger(JoystickButton::Down),
-            joystick_trigger(JoystickAxis::PrimaryVertical, None, Some(0.5)),
-        ],
-        BinaryInput::MenuUp => vec![
-            key_trigger(KeyboardKey::W),
-            key_trigger(KeyboardKey::Up),
-            joystick_button_trigger(JoystickButton::Up),
-            joystick_trigger(JoystickAxis::PrimaryVertical, Some(-0.5), None),
-        ],
-        BinaryInput::MenuLeft => vec![
-            key_trigger(KeyboardKey::Left),
-            key_trigger(KeyboardKey::A),
-            joystick_button_trigger(JoystickButton::Left),
-            joystick_trigger(JoystickAxis::PrimaryHorizontal, Some(-0.5), None),
-        ],
-        BinaryInput::MenuRight => vec![
-            key_trigger(KeyboardKey::D),
-            key_trigger(KeyboardKey::Right),
-            joystick_button_trigger(JoystickButton::Right),
-            joystick_trigger(JoystickAxis::PrimaryHorizontal, None, Some(0.5)),
-        ],
-        BinaryInput::MouseButtonLeft => vec![mouse_button_input(MouseButton::Left)],
-    })
-}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+fn mouse_button_trigger(button: MouseButton) -> Box<TriggerInput<MouseButtonInput>> {
+    Box::new(TriggerInput::from(MouseButtonInput::new(button)))
+}
+
+/// A hook for a rebindable key or button, which is either on while it's held, or only on the
+/// frame it's pressed.
+fn binding_input(binding: Binding, trigger: bool) -> Box<dyn StatefulBinaryInput> {
+    match (binding, trigger) {
+        (Binding::Key(key), false) => key_input(key),
+        (Binding::Key(key), true) => key_trigger(key),
+        (Binding::Button(button), false) => joystick_button_input(button),
+        (Binding::Button(button), true) => joystick_button_trigger(button),
+        (Binding::Mouse(button), false) => mouse_button_input(button),
+        (Binding::Mouse(button), true) => mouse_button_trigger(button),
+    }
+}
+
+fn create_binary_hooks(
+    inputs: &[BinaryInput],
+    profile: InputProfile,
+    keys: &KeyBindings,
+) -> SmallIntMap<BinaryInput, AnyOfInput> {
+    let mut hooks = SmallIntMap::new();
+    for input in inputs {
+        hooks.insert(input.clone(), create_input(input.clone(), profile, keys));
+    }
+    hooks
+}
+
+fn create_input(input: BinaryInput, profile: InputProfile, keys: &KeyBindings) -> AnyOfInput {
+    let (action, trigger) = match input {
+        BinaryInput::OkTrigger => (Action::Ok, true),
+        BinaryInput::OkDown => (Action::Ok, false),
+        BinaryInput::Cancel => (Action::Cancel, true),
+        BinaryInput::PlayerMoveForward => (Action::Forward, false),
+        BinaryInput::PlayerMoveBackward => (Action::Backward, false),
+        BinaryInput::PlayerStrafeLeft => (Action::StrafeLeft, false),
+        BinaryInput::PlayerStrafeRight => (Action::StrafeRight, false),
+        BinaryInput::PlayerTurnLeft => (Action::TurnLeft, false),
+        BinaryInput::PlayerTurnRight => (Action::TurnRight, false),
+        BinaryInput::MenuDown => (Action::MenuDown, true),
+        BinaryInput::MenuUp => (Action::MenuUp, true),
+        BinaryInput::MenuLeft => (Action::MenuLeft, true),
+        BinaryInput::MenuRight => (Action::MenuRight, true),
+        BinaryInput::MouseButtonLeft => (Action::Fire, false),
+        BinaryInput::PlayerDash => (Action::Dash, true),
+        BinaryInput::Inventory => (Action::Inventory, true),
+        BinaryInput::ItemDrop => (Action::Drop, true),
+    };
+    let mut bindings: Vec<Box<dyn StatefulBinaryInput>> = keys
+        .get(action)
+        .iter()
+        .map(|binding| binding_input(*binding, trigger))
+        .collect();
+
+    // The sticks aren't rebindable.
+    let sticks: Vec<Box<dyn StatefulBinaryInput>> = match input {
+        BinaryInput::PlayerMoveForward => vec![joystick_threshold(
+            JoystickAxis::PrimaryVertical,
+            Some(-0.5),
+            None,
+        )],
+        BinaryInput::PlayerMoveBackward => vec![joystick_threshold(
+            JoystickAxis::PrimaryVertical,
+            None,
+            Some(0.5),
+        )],
+        BinaryInput::PlayerStrafeLeft => vec![joystick_threshold(
+            JoystickAxis::PrimaryHorizontal,
+            Some(-0.5),
+            None,
+        )],
+        BinaryInput::PlayerStrafeRight => vec![joystick_threshold(
+            JoystickAxis::PrimaryHorizontal,
+            None,
+            Some(0.5),
+        )],
+        BinaryInput::PlayerTurnLeft => vec![joystick_threshold(
+            JoystickAxis::SecondaryHorizontal,
+            Some(-0.5),
+            None,
+        )],
+        BinaryInput::PlayerTurnRight => vec![joystick_threshold(
+            JoystickAxis::SecondaryHorizontal,
+            None,
+            Some(0.5),
+        )],
+        BinaryInput::MenuDown => vec![joystick_trigger(
+            JoystickAxis::PrimaryVertical,
+            None,
+            Some(0.5),
+        )],
+        BinaryInput::MenuUp => vec![joystick_trigger(
+            JoystickAxis::PrimaryVertical,
+            Some(-0.5),
+            None,
+        )],
+        BinaryInput::MenuLeft => vec![joystick_trigger(
+            JoystickAxis::PrimaryHorizontal,
+            Some(-0.5),
+            None,
+        )],
+        BinaryInput::MenuRight => vec![joystick_trigger(
+            JoystickAxis::PrimaryHorizontal,
+            None,
+            Some(0.5),
+        )],
+        _ => Vec::new(),
+    };
+    bindings.extend(sticks);
+    bindings.extend(profile.extra_bindings(input));
+    AnyOfInput(bindings)
+}
+
+/// How far a stick has to be pushed before it counts, from 0 to 1.
+pub const DEAD_ZONE_CVAR: &str = "dead_zone";
+
+const DEFAULT_DEAD_ZONE: f32 = 0.15;
+/// The largest dead zone allowed, so the stick still has some range left.
+const MAX_DEAD_ZONE: f32 = 0.9;
+/// How many steps each analog axis has in each direction. Axes are rounded to these steps so
+/// recordings play back exactly.
+const AXIS_STEPS: f32 = 31.0;
+
+/// Scales a stick's position so nothing inside the dead zone counts, and the rest of the range
+/// still goes smoothly from 0 to 1. The dead zone is round, so diagonals work like any other
+/// direction.
+pub fn apply_dead_zone(x: f32, y: f32, dead_zone: f32) -> (f32, f32) {
+    let magnitude = (x * x + y * y).sqrt();
+    if magnitude <= dead_zone {
+        return (0.0, 0.0);
+    }
+    let scale = ((magnitude - dead_zone) / (1.0 - dead_zone)).min(1.0) / magnitude;
+    (quantize_axis(x * scale), quantize_axis(y * scale))
+}
+
+/// Locks the mouse to a winit window and hides it for mouse look, or lets it go and shows it
+/// again. Hosts call this when set_mouse_captured reports a change.
+#[cfg(feature = "winit")]
+pub fn capture_winit_mouse(window: &winit::window::Window, captured: bool) {
+    use winit::window::CursorGrabMode;
+
+    let result = if captured {
+        // Not every platform can lock the mouse in place, but keeping it in the window will do.
+        window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+    } else {
+        window.set_cursor_grab(CursorGrabMode::None)
+    };
+    if let Err(e) = result {
+        error!("unable to capture the mouse: {}", e);
+    }
+    window.set_cursor_visible(!captured);
+}
+
+fn quantize_axis(value: f32) -> f32 {
+    (value.clamp(-1.0, 1.0) * AXIS_STEPS).round() / AXIS_STEPS
+}
+
+#[inline]
+fn axis_to_bin(value: f32, n: u8) -> u64 {
+    let steps = (value * AXIS_STEPS).round() as i64;
+    ((steps as u64) & 0x3F) << n
+}
+
+#[inline]
+fn bin_to_axis(encoded: u64, n: u8) -> f32 {
+    // Sign extend the 6 bits.
+    let steps = (((encoded >> n) & 0x3F) << 58) as i64 >> 58;
+    steps as f32 / AXIS_STEPS
+}
+
+/// Picks an analog axis if the stick is pushed, or else -1, 0, or 1 from a pair of digital inputs.
+fn axis_or_digital(axis: f32, negative: bool, positive: bool) -> f32 {
+    if axis != 0.0 {
+        return axis;
+    }
+    match (negative, positive) {
+        (true, false) => -1.0,
+        (false, true) => 1.0,
+        _ => 0.0,
+    }
+}
+
+/// One change to text being typed, while the keyboard is typing text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEdit {
+    /// A character was typed.
+    Insert(char),
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+}
+
+impl TextEdit {
+    /// The edit for a key that edits text rather than typing it.
+    #[cfg(feature = "sdl2")]
+    fn from_sdl_key(key: sdl2::keyboard::Keycode) -> Option<Self> {
+        use sdl2::keyboard::Keycode;
+        Some(match key {
+            Keycode::Backspace => TextEdit::Backspace,
+            Keycode::Delete => TextEdit::Delete,
+            Keycode::Left => TextEdit::Left,
+            Keycode::Right => TextEdit::Right,
+            Keycode::Home => TextEdit::Home,
+            Keycode::End => TextEdit::End,
+            _ => return None,
+        })
+    }
+
+    #[cfg(feature = "winit")]
+    fn from_winit_key(key: winit::keyboard::NamedKey) -> Option<Self> {
+        use winit::keyboard::NamedKey;
+        Some(match key {
+            NamedKey::Backspace => TextEdit::Backspace,
+            NamedKey::Delete => TextEdit::Delete,
+            NamedKey::ArrowLeft => TextEdit::Left,
+            NamedKey::ArrowRight => TextEdit::Right,
+            NamedKey::Home => TextEdit::Home,
+            NamedKey::End => TextEdit::End,
+            _ => return None,
+        })
+    }
+
+    /// Packs an edit, or none, into 24 bits: which kind it is, and the character typed.
+    fn encode(edit: Option<TextEdit>) -> u32 {
+        let (kind, c) = match edit {
+            None => (0, '\0'),
+            Some(TextEdit::Insert(c)) => (1, c),
+            Some(TextEdit::Backspace) => (2, '\0'),
+            Some(TextEdit::Delete) => (3, '\0'),
+            Some(TextEdit::Left) => (4, '\0'),
+            Some(TextEdit::Right) => (5, '\0'),
+            Some(TextEdit::Home) => (6, '\0'),
+            Some(TextEdit::End) => (7, '\0'),
+        };
+        kind | (c as u32) << 3
+    }
+
+    fn decode(n: u32) -> Option<TextEdit> {
+        Some(match n & 0x7 {
+            1 => TextEdit::Insert(char::from_u32(n >> 3)?),
+            2 => TextEdit::Backspace,
+            3 => TextEdit::Delete,
+            4 => TextEdit::Left,
+            5 => TextEdit::Right,
+            6 => TextEdit::Home,
+            7 => TextEdit::End,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
 pub struct InputSnapshot {
     pub ok_clicked: bool,
     pub ok_down: bool,
@@ -611,15 +1053,32 @@ pub struct InputSnapshot {
     pub player_strafe_right_down: bool,
     pub player_turn_left_down: bool,
     pub player_turn_right_down: bool,
+    pub player_dash_clicked: bool,
+
+    /// How far the gamepad's sticks are pushed, from -1 to 1, after the dead zone. Forward,
+    /// right, and clockwise are positive.
+    pub move_axis: f32,
+    pub strafe_axis: f32,
+    pub turn_axis: f32,
 
     pub menu_down_clicked: bool,
     pub menu_up_clicked: bool,
     pub menu_left_clicked: bool,
     pub menu_right_clicked: bool,
 
+    pub inventory_clicked: bool,
+    pub drop_clicked: bool,
+
     pub mouse_button_left_down: bool,
 
     pub mouse_position: Point<i32>,
+    /// How far the mouse moved this frame while it was captured for mouse look, in the host's
+    /// units. Right and down are positive.
+    pub mouse_delta: Point<i32>,
+
+    /// The next change to the text being typed, while the keyboard is typing text. Edits come
+    /// one a frame, in the order they were typed, so recordings keep every one of them.
+    pub text_edit: Option<TextEdit>,
 }
 
 #[inline]
@@ -637,25 +1096,71 @@ fn bin_to_bool(encoded: u64, n: u8) -> bool {
 }
 
 impl InputSnapshot {
-    fn encode(&self) -> u64 {
-        let mut result = 0;
+    /// How fast the player wants to move forward, from -1 for full speed backward to 1.
+    pub fn forward(&self) -> f32 {
+        let (backward, forward) = (self.player_backward_down, self.player_forward_down);
+        axis_or_digital(self.move_axis, backward, forward)
+    }
+
+    /// How fast the player wants to strafe, from -1 for full speed left to 1 for right.
+    pub fn strafe(&self) -> f32 {
+        let (left, right) = (self.player_strafe_left_down, self.player_strafe_right_down);
+        axis_or_digital(self.strafe_axis, left, right)
+    }
+
+    /// How fast the player wants to turn, from -1 for full speed left to 1 for right.
+    pub fn turn(&self) -> f32 {
+        let (left, right) = (self.player_turn_left_down, self.player_turn_right_down);
+        axis_or_digital(self.turn_axis, left, right)
+    }
+
+    fn encode(&self) -> u128 {
+        let mut result: u64 = 0;
         result |= bool_to_bin(self.ok_clicked, 0);
         result |= bool_to_bin(self.ok_down, 1);
         result |= bool_to_bin(self.cancel_clicked, 2);
+        result |= bool_to_bin(self.player_forward_down, 3);
+        result |= bool_to_bin(self.player_backward_down, 4);
+        result |= bool_to_bin(self.player_strafe_left_down, 5);
+        result |= bool_to_bin(self.player_strafe_right_down, 6);
+        result |= bool_to_bin(self.player_turn_left_down, 7);
+        result |= bool_to_bin(self.player_turn_right_down, 13);
         result |= bool_to_bin(self.menu_down_clicked, 8);
         result |= bool_to_bin(self.menu_up_clicked, 9);
         result |= bool_to_bin(self.menu_left_clicked, 10);
         result |= bool_to_bin(self.menu_right_clicked, 11);
         result |= bool_to_bin(self.mouse_button_left_down, 12);
+        result |= axis_to_bin(self.move_axis, 14);
+        result |= axis_to_bin(self.strafe_axis, 20);
+        result |= axis_to_bin(self.turn_axis, 26);
 
         let mouse_x = self.mouse_position.x;
         let mouse_y = self.mouse_position.y;
         result |= ((mouse_x & 0x0000FFFF) as u64) << 32;
         result |= ((mouse_y & 0x0000FFFF) as u64) << 48;
+
+        // The mouse delta goes above everything else, so older recordings still decode.
+        let delta_x = self.mouse_delta.x.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        let delta_y = self.mouse_delta.y.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        let mut result = result as u128;
+        result |= (delta_x as u16 as u128) << 64;
+        result |= (delta_y as u16 as u128) << 80;
+        // Likewise for dashing and the item keys, which came after the mouse delta.
+        result |= (self.player_dash_clicked as u128) << 96;
+        result |= (self.inventory_clicked as u128) << 97;
+        result |= (self.drop_clicked as u128) << 98;
+        result |= (TextEdit::encode(self.text_edit) as u128) << 99;
         result
     }
 
-    fn decode(n: u64) -> InputSnapshot {
+    fn decode(n: u128) -> InputSnapshot {
+        let delta_x = ((n >> 64) & 0xFFFF) as u16 as i16 as i32;
+        let delta_y = ((n >> 80) & 0xFFFF) as u16 as i16 as i32;
+        let dash = (n >> 96) & 1 != 0;
+        let inventory = (n >> 97) & 1 != 0;
+        let drop = (n >> 98) & 1 != 0;
+        let text_edit = TextEdit::decode(((n >> 99) & 0xFFFFFF) as u32);
+        let n = n as u64;
         let mouse_x = ((n >> 32) & 0x0000FFFF) as i32;
         let mouse_y = ((n >> 48) & 0x0000FFFF) as i32;
 
@@ -663,41 +1168,51 @@ impl InputSnapshot {
             ok_clicked: bin_to_bool(n, 0),
             ok_down: bin_to_bool(n, 1),
             cancel_clicked: bin_to_bool(n, 2),
-            player_forward_down: false,
-            player_backward_down: false,
-            player_strafe_left_down: false,
-            player_strafe_right_down: false,
-            player_turn_left_down: false,
-            player_turn_right_down: false,
+            player_forward_down: bin_to_bool(n, 3),
+            player_backward_down: bin_to_bool(n, 4),
+            player_strafe_left_down: bin_to_bool(n, 5),
+            player_strafe_right_down: bin_to_bool(n, 6),
+            player_turn_left_down: bin_to_bool(n, 7),
+            player_turn_right_down: bin_to_bool(n, 13),
+            player_dash_clicked: dash,
+            move_axis: bin_to_axis(n, 14),
+            strafe_axis: bin_to_axis(n, 20),
+            turn_axis: bin_to_axis(n, 26),
             menu_down_clicked: bin_to_bool(n, 8),
             menu_up_clicked: bin_to_bool(n, 9),
             menu_left_clicked: bin_to_bool(n, 10),
             menu_right_clicked: bin_to_bool(n, 11),
+            inventory_clicked: inventory,
+            drop_clicked: drop,
             mouse_button_left_down: bin_to_bool(n, 12),
             mouse_position: Point::new(mouse_x, mouse_y),
+            mouse_delta: Point::new(delta_x, delta_y),
+            text_edit,
         }
     }
 }
 
+#[derive(Debug, Clone)]
 struct RecorderEntry {
     frame: u64,
-    snapshot: u64,
+    snapshot: u128,
 }
 
+/// A recording of inputs, kept as the frames where they changed.
+///
+/// Frames passed to `playback` have to count up one at a time from the first one recorded.
+#[derive(Debug, Clone, Default)]
 pub struct InputRecorder {
-    previous: u64,
+    previous: u128,
     queue: VecDeque<RecorderEntry>,
 }
 
 impl InputRecorder {
-    fn new() -> InputRecorder {
-        InputRecorder {
-            previous: 0,
-            queue: VecDeque::new(),
-        }
+    pub fn new() -> InputRecorder {
+        InputRecorder::default()
     }
 
-    fn record(&mut self, frame: u64, snapshot: &InputSnapshot) {
+    pub fn record(&mut self, frame: u64, snapshot: &InputSnapshot) {
         let snapshot = snapshot.encode();
         if self.previous == snapshot {
             return;
@@ -706,7 +1221,7 @@ impl InputRecorder {
         self.queue.push_back(RecorderEntry { frame, snapshot });
     }
 
-    fn playback(&mut self, frame: u64) -> InputSnapshot {
+    pub fn playback(&mut self, frame: u64) -> InputSnapshot {
         if let Some(next) = self.queue.front() {
             if next.frame == frame {
                 self.previous = next.snapshot;
@@ -716,24 +1231,31 @@ impl InputRecorder {
         InputSnapshot::decode(self.previous)
     }
 
-    fn save(&self, path: &Path) -> Result<()> {
+    /// The recording as text, with a "frame,snapshot" line for each change.
+    pub fn to_text(&self) -> String {
         let mut lines = Vec::new();
         for entry in self.queue.iter() {
             lines.push(format!("{},{}", entry.frame, entry.snapshot));
         }
-        let text = lines.join("\n");
-        fs::write(path, text)?;
+        lines.join("\n")
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_text())?;
         Ok(())
     }
 
     fn load(&mut self, path: &Path, files: &FileManager) -> Result<()> {
-        self.previous = 0;
-        self.queue.clear();
-
         let text = files
             .read_to_string(path)
             .map_err(|e| anyhow!("unable to load input snapshot record at {:?}: {}", path, e))?;
+        *self = InputRecorder::from_text(&text)?;
+        Ok(())
+    }
 
+    /// Reads a recording written by `to_text`, ready to play back from the start.
+    pub fn from_text(text: &str) -> Result<InputRecorder> {
+        let mut recorder = InputRecorder::new();
         for line in text.lines() {
             let line = line.trim();
             if line.is_empty() {
@@ -747,10 +1269,265 @@ impl InputRecorder {
             let frame = frame.parse()?;
             let snapshot = snapshot.parse()?;
 
-            self.queue.push_back(RecorderEntry { frame, snapshot });
+            recorder.queue.push_back(RecorderEntry { frame, snapshot });
         }
+        Ok(recorder)
+    }
+}
+
+/// Whether entering a cheat code does anything, "true" or "false". Defaults to on in debug builds.
+pub const CHEATS_CVAR: &str = "cheats";
+
+/// The longest pause allowed between the inputs of a cheat code.
+const CHEAT_TIMEOUT_FRAMES: u64 = FRAME_RATE as u64 * 2;
+
+/// One step of a cheat code. These work the same on a keyboard or a gamepad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatInput {
+    Up,
+    Down,
+    Left,
+    Right,
+    Ok,
+}
+
+impl CheatInput {
+    /// The cheat inputs that were pressed this frame.
+    fn pressed(inputs: &InputSnapshot) -> impl Iterator<Item = CheatInput> {
+        [
+            (inputs.menu_up_clicked, CheatInput::Up),
+            (inputs.menu_down_clicked, CheatInput::Down),
+            (inputs.menu_left_clicked, CheatInput::Left),
+            (inputs.menu_right_clicked, CheatInput::Right),
+            (inputs.ok_clicked, CheatInput::Ok),
+        ]
+        .into_iter()
+        .filter_map(|(pressed, input)| pressed.then_some(input))
+    }
+}
+
+impl FromStr for CheatInput {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "up" => CheatInput::Up,
+            "down" => CheatInput::Down,
+            "left" => CheatInput::Left,
+            "right" => CheatInput::Right,
+            "ok" => CheatInput::Ok,
+            _ => bail!("invalid cheat input: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for CheatInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CheatInput::Up => "up",
+            CheatInput::Down => "down",
+            CheatInput::Left => "left",
+            CheatInput::Right => "right",
+            CheatInput::Ok => "ok",
+        })
+    }
+}
+
+/// Recognizes registered sequences of inputs, like "up up down down left right left right".
+#[derive(Debug, Clone, Default)]
+pub struct CheatCodes {
+    codes: Vec<(String, Vec<CheatInput>)>,
+    /// The most recent inputs, as long as the longest code.
+    recent: VecDeque<CheatInput>,
+    /// The frame of the most recent input.
+    last_frame: u64,
+}
+
+impl CheatCodes {
+    pub fn new() -> CheatCodes {
+        CheatCodes::default()
+    }
+
+    /// Adds a code, written as inputs separated by spaces, which fires the named event.
+    pub fn register(&mut self, name: &str, sequence: &str) -> Result<()> {
+        let sequence = sequence
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<CheatInput>>>()?;
+        if sequence.is_empty() {
+            bail!("invalid cheat code: {}", name);
+        }
+        self.codes.retain(|(other, _)| other != name);
+        self.codes.push((name.to_string(), sequence));
         Ok(())
     }
+
+    /// The registered codes, by name.
+    pub fn codes(&self) -> impl Iterator<Item = (&str, &[CheatInput])> {
+        self.codes
+            .iter()
+            .map(|(name, sequence)| (name.as_str(), sequence.as_slice()))
+    }
+
+    /// Feeds in a frame's inputs, returning the names of any codes they finished.
+    pub fn update(&mut self, frame: u64, inputs: &InputSnapshot) -> Vec<String> {
+        let mut fired = Vec::new();
+        for input in CheatInput::pressed(inputs) {
+            if frame.saturating_sub(self.last_frame) > CHEAT_TIMEOUT_FRAMES {
+                self.recent.clear();
+            }
+            self.last_frame = frame;
+
+            let longest = self.codes.iter().map(|(_, code)| code.len()).max();
+            self.recent.push_back(input);
+            if self.recent.len() > longest.unwrap_or(0) {
+                self.recent.pop_front();
+            }
+
+            let recent = self.recent.make_contiguous();
+            if let Some((name, _)) = self.codes.iter().find(|(_, code)| recent.ends_with(code)) {
+                fired.push(name.clone());
+                self.recent.clear();
+            }
+        }
+        fired
+    }
+}
+
+/// Which controls the player uses: "standard", "keyboard", or "switch".
+pub const INPUT_PROFILE_CVAR: &str = "input_profile";
+
+/// How long single switch scanning stays on each menu item before moving to the next one.
+const SCAN_FRAMES: u64 = FRAME_RATE as u64 * 3 / 2;
+/// How long the switch has to be held to back out, like cancel.
+const SWITCH_HOLD_FRAMES: u64 = FRAME_RATE as u64;
+
+/// A set of controls for players who can't use the standard ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputProfile {
+    /// A keyboard and mouse, or a gamepad.
+    #[default]
+    Standard,
+    /// Only a keyboard. Space fires, and turning while walking forward also strafes into the
+    /// turn, so corners can be taken without a mouse.
+    KeyboardOnly,
+    /// A single switch, which can be space, enter, or the gamepad's south button. Menus move
+    /// through their items on their own, pressing the switch picks the focused one, and holding
+    /// it backs out.
+    SingleSwitch,
+}
+
+impl InputProfile {
+    /// Bindings the profile adds to an input, on top of the standard ones.
+    fn extra_bindings(&self, input: BinaryInput) -> Vec<Box<dyn StatefulBinaryInput>> {
+        match (self, input) {
+            (InputProfile::KeyboardOnly, BinaryInput::MouseButtonLeft) => {
+                vec![key_input(KeyboardKey::Space)]
+            }
+            (InputProfile::SingleSwitch, BinaryInput::OkTrigger) => {
+                vec![key_trigger(KeyboardKey::Space)]
+            }
+            (InputProfile::SingleSwitch, BinaryInput::OkDown) => {
+                vec![key_input(KeyboardKey::Space)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// The layers the profile rewrites each frame's inputs with, in order.
+    fn layers(&self) -> Vec<Box<dyn InputLayer>> {
+        match self {
+            InputProfile::Standard => Vec::new(),
+            InputProfile::KeyboardOnly => vec![Box::new(AutoStrafeLayer)],
+            InputProfile::SingleSwitch => vec![Box::new(SwitchScanLayer::default())],
+        }
+    }
+}
+
+impl FromStr for InputProfile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "standard" => InputProfile::Standard,
+            "keyboard" => InputProfile::KeyboardOnly,
+            "switch" => InputProfile::SingleSwitch,
+            _ => bail!("invalid input profile: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for InputProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InputProfile::Standard => "standard",
+            InputProfile::KeyboardOnly => "keyboard",
+            InputProfile::SingleSwitch => "switch",
+        })
+    }
+}
+
+/// Rewrites each frame's inputs after they're read from the bindings, before the game sees them.
+pub trait InputLayer {
+    fn apply(&mut self, frame: u64, inputs: InputSnapshot) -> InputSnapshot;
+}
+
+/// Strafes into a turn while walking forward, unless the player is already strafing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AutoStrafeLayer;
+
+impl InputLayer for AutoStrafeLayer {
+    fn apply(&mut self, _frame: u64, mut inputs: InputSnapshot) -> InputSnapshot {
+        let strafing = inputs.player_strafe_left_down || inputs.player_strafe_right_down;
+        if inputs.player_forward_down && !strafing {
+            match (inputs.player_turn_left_down, inputs.player_turn_right_down) {
+                (true, false) => inputs.player_strafe_left_down = true,
+                (false, true) => inputs.player_strafe_right_down = true,
+                _ => {}
+            }
+        }
+        inputs
+    }
+}
+
+/// Moves menu focus down on its own every so often, so a single switch can reach every item,
+/// and turns holding the switch into cancel.
+#[derive(Debug, Clone, Default)]
+pub struct SwitchScanLayer {
+    /// The frame to move focus on next, or None if scanning hasn't started.
+    next_scan: Option<u64>,
+    /// The frame the switch was pressed, if it's down.
+    held_since: Option<u64>,
+    /// Whether the switch has been held long enough to cancel, so letting go doesn't also pick
+    /// something.
+    cancelled: bool,
+}
+
+impl InputLayer for SwitchScanLayer {
+    fn apply(&mut self, frame: u64, mut inputs: InputSnapshot) -> InputSnapshot {
+        if inputs.ok_down {
+            // Wait a whole interval after the switch is used before moving on.
+            self.next_scan = Some(frame + SCAN_FRAMES);
+            let held_since = *self.held_since.get_or_insert(frame);
+            if !self.cancelled && frame - held_since >= SWITCH_HOLD_FRAMES {
+                inputs.cancel_clicked = true;
+                self.cancelled = true;
+            }
+            if self.cancelled {
+                inputs.ok_down = false;
+            }
+            return inputs;
+        }
+        self.held_since = None;
+        self.cancelled = false;
+
+        let next_scan = *self.next_scan.get_or_insert(frame + SCAN_FRAMES);
+        if frame >= next_scan {
+            inputs.menu_down_clicked = true;
+            self.next_scan = Some(frame + SCAN_FRAMES);
+        }
+        inputs
+    }
 }
 
 #[derive(Debug)]
@@ -769,6 +1546,14 @@ pub struct InputManager {
     current_gamepad: Option<gilrs::GamepadId>,
     record_option: RecordOption,
     recorder: InputRecorder,
+    profile: InputProfile,
+    layers: Vec<Box<dyn InputLayer>>,
+    dead_zone: f32,
+    keys: KeyBindings,
+    /// Whether the host has captured the mouse for mouse look, so its motion turns the player.
+    mouse_captured: bool,
+    /// Whether the keyboard is typing text, so typed characters show up in the snapshot.
+    text_input: bool,
 }
 
 impl InputManager {
@@ -785,11 +1570,10 @@ impl InputManager {
             recorder.load(Path::new(path), files)?;
         }
 
-        let mut binary_hooks = SmallIntMap::new();
+        let profile = InputProfile::default();
+        let keys = KeyBindings::load(files);
         let all_binary_hooks = all_binary_inputs();
-        for hook in all_binary_hooks.iter() {
-            binary_hooks.insert(hook.clone(), create_input(hook.clone()));
-        }
+        let binary_hooks = create_binary_hooks(&all_binary_hooks, profile, &keys);
 
         debug!("Initializing gamepads");
         let gilrs = Gilrs::new().map_err(|e| anyhow!("unable to load game library: {}", e))?;
@@ -815,9 +1599,110 @@ impl InputManager {
             current_gamepad,
             record_option,
             recorder,
+            profile,
+            layers: profile.layers(),
+            dead_zone: DEFAULT_DEAD_ZONE,
+            keys,
+            mouse_captured: false,
+            text_input: false,
         })
     }
 
+    pub fn profile(&self) -> InputProfile {
+        self.profile
+    }
+
+    /// Switches to another set of controls, starting over with nothing held down.
+    pub fn set_profile(&mut self, profile: InputProfile) {
+        info!("using the {} input profile", profile);
+        self.profile = profile;
+        self.binary_hooks = create_binary_hooks(&self.all_binary_hooks, profile, &self.keys);
+        self.layers = profile.layers();
+    }
+
+    /// The keys and buttons bound to each action.
+    pub fn key_bindings(&self) -> &KeyBindings {
+        &self.keys
+    }
+
+    /// Switches to a new set of key bindings, starting over with nothing held down.
+    pub fn set_key_bindings(&mut self, keys: KeyBindings) {
+        self.keys = keys;
+        self.binary_hooks = create_binary_hooks(&self.all_binary_hooks, self.profile, &self.keys);
+    }
+
+    /// Replaces everything bound to an action, and saves the bindings so they're used next time.
+    pub fn rebind(
+        &mut self,
+        action: Action,
+        bindings: Vec<Binding>,
+        files: &FileManager,
+    ) -> Result<()> {
+        let mut keys = self.keys.clone();
+        keys.rebind(action, bindings);
+        self.set_key_bindings(keys);
+        self.keys.save(files)
+    }
+
+    /// Switches to the input profile and stick dead zone in the cvars, if they've changed. Hosts
+    /// call this before each update.
+    pub fn apply_cvars(&mut self, cvars: &Cvars) {
+        let profile = cvars.get_parsed(INPUT_PROFILE_CVAR).unwrap_or_default();
+        if profile != self.profile {
+            self.set_profile(profile);
+        }
+        let dead_zone: f32 = cvars
+            .get_parsed(DEAD_ZONE_CVAR)
+            .unwrap_or(DEFAULT_DEAD_ZONE);
+        self.dead_zone = dead_zone.clamp(0.0, MAX_DEAD_ZONE);
+    }
+
+    /// Sets the size the mouse position is relative to, in the units of the host's mouse events.
+    ///
+    /// Resize events update this automatically, so hosts only need it for the initial size.
+    /// Sets whether the host has captured the mouse for mouse look, returning true if that's a
+    /// change, so the host knows to capture or release it. While the mouse isn't captured, its
+    /// motion doesn't show up in the snapshot.
+    pub fn set_mouse_captured(&mut self, captured: bool) -> bool {
+        if captured == self.mouse_captured {
+            return false;
+        }
+        info!(
+            "{} the mouse",
+            if captured { "capturing" } else { "releasing" }
+        );
+        self.mouse_captured = captured;
+        self.state.take_mouse_delta();
+        true
+    }
+
+    pub fn is_mouse_captured(&self) -> bool {
+        self.mouse_captured
+    }
+
+    /// Sets whether the keyboard is typing text, returning true if that's a change, so the host
+    /// knows to start or stop text input. While it isn't, typed text is dropped.
+    pub fn set_text_input(&mut self, text_input: bool) -> bool {
+        if text_input == self.text_input {
+            return false;
+        }
+        info!(
+            "{} text input",
+            if text_input { "starting" } else { "stopping" }
+        );
+        self.text_input = text_input;
+        self.state.text_edits.clear();
+        true
+    }
+
+    pub fn is_text_input(&self) -> bool {
+        self.text_input
+    }
+
+    pub fn set_window_size(&mut self, width: i32, height: i32) {
+        self.state.set_window_size(width, height);
+    }
+
     pub fn update(&mut self, frame: u64) -> InputSnapshot {
         if let RecordOption::Playback(_) = self.record_option {
             return self.recorder.playback(frame);
@@ -835,7 +1720,23 @@ impl InputManager {
                 .update(&self.state);
         }
 
-        let snapshot = InputSnapshot {
+        let (strafe_axis, move_axis) = apply_dead_zone(
+            self.state.joy_axis(JoystickAxis::PrimaryHorizontal),
+            -self.state.joy_axis(JoystickAxis::PrimaryVertical),
+            self.dead_zone,
+        );
+        let (turn_axis, _) = apply_dead_zone(
+            self.state.joy_axis(JoystickAxis::SecondaryHorizontal),
+            0.0,
+            self.dead_zone,
+        );
+        let mouse_delta = self.state.take_mouse_delta();
+        let mouse_delta = if self.mouse_captured {
+            mouse_delta
+        } else {
+            Point::zero()
+        };
+        let mut snapshot = InputSnapshot {
             ok_clicked: self.is_on(BinaryInput::OkTrigger),
             ok_down: self.is_on(BinaryInput::OkDown),
             cancel_clicked: self.is_on(BinaryInput::Cancel),
@@ -845,13 +1746,24 @@ impl InputManager {
             player_strafe_right_down: self.is_on(BinaryInput::PlayerStrafeRight),
             player_turn_left_down: self.is_on(BinaryInput::PlayerTurnLeft),
             player_turn_right_down: self.is_on(BinaryInput::PlayerTurnRight),
+            player_dash_clicked: self.is_on(BinaryInput::PlayerDash),
+            move_axis,
+            strafe_axis,
+            turn_axis,
             menu_down_clicked: self.is_on(BinaryInput::MenuDown),
             menu_up_clicked: self.is_on(BinaryInput::MenuUp),
             menu_left_clicked: self.is_on(BinaryInput::MenuLeft),
             menu_right_clicked: self.is_on(BinaryInput::MenuRight),
+            inventory_clicked: self.is_on(BinaryInput::Inventory),
+            drop_clicked: self.is_on(BinaryInput::ItemDrop),
             mouse_button_left_down: self.is_on(BinaryInput::MouseButtonLeft),
             mouse_position: self.state.mouse_position,
+            mouse_delta,
+            text_edit: self.state.text_edits.pop_front(),
         };
+        for layer in self.layers.iter_mut() {
+            snapshot = layer.apply(frame, snapshot);
+        }
         if Some(snapshot) != self.previous_snapshot {
             debug!("{:?}", snapshot);
             self.previous_snapshot = Some(snapshot);
@@ -929,10 +1841,16 @@ impl InputManager {
             Event::KeyDown {
                 keycode: Some(key), ..
             } => {
+                if self.text_input {
+                    self.state.text_edits.extend(TextEdit::from_sdl_key(*key));
+                }
                 if let Some(key) = KeyboardKey::from_sdl_key(*key) {
                     self.state.set_key_down(key);
                 }
             }
+            Event::TextInput { text, .. } if self.text_input => {
+                self.state.add_typed_text(text);
+            }
             Event::KeyUp {
                 keycode: Some(key), ..
             } => {
@@ -958,9 +1876,12 @@ impl InputManager {
                 self.state.set_mouse_position(*x, *y);
                 self.state.set_mouse_button_up(MouseButton::Left);
             }
-            Event::MouseMotion { x, y, .. } => {
+            Event::MouseMotion {
+                x, y, xrel, yrel, ..
+            } => {
                 // info!("mouse moved to {x}, {y}");
                 self.state.set_mouse_position(*x, *y);
+                self.state.add_mouse_delta(*xrel, *yrel);
             }
             _ => {}
         }
@@ -969,8 +1890,8 @@ impl InputManager {
     #[cfg(feature = "winit")]
     pub fn handle_winit_event(&mut self, event: &winit::event::WindowEvent) {
         use winit::dpi::{PhysicalPosition, PhysicalSize};
-        use winit::event::{ElementState, KeyEvent, WindowEvent};
-        use winit::keyboard::PhysicalKey;
+        use winit::event::{ElementState, Ime, KeyEvent, WindowEvent};
+        use winit::keyboard::{Key, PhysicalKey};
 
         match event {
             WindowEvent::Resized(new_size) => {
@@ -983,14 +1904,32 @@ impl InputManager {
                     KeyEvent {
                         state: ElementState::Pressed,
                         physical_key: PhysicalKey::Code(key_code),
+                        logical_key,
+                        text,
                         ..
                     },
                 ..
             } => {
+                if self.text_input {
+                    let edit = match logical_key {
+                        Key::Named(named) => TextEdit::from_winit_key(*named),
+                        _ => None,
+                    };
+                    match (edit, text) {
+                        (Some(edit), _) => self.state.text_edits.push_back(edit),
+                        (None, Some(text)) => self.state.add_typed_text(text),
+                        (None, None) => {}
+                    }
+                }
                 if let Some(key) = KeyboardKey::from_keycode(*key_code) {
                     self.state.set_key_down(key);
                 }
             }
+            // Text from an input method, like for accented or CJK characters, comes all at once
+            // when it's done being composed.
+            WindowEvent::Ime(Ime::Commit(text)) if self.text_input => {
+                self.state.add_typed_text(text);
+            }
             WindowEvent::KeyboardInput {
                 event:
                     KeyEvent {
@@ -1024,6 +1963,15 @@ impl InputManager {
             _ => {}
         }
     }
+
+    /// Picks up raw mouse motion, which winit only reports as a device event, for mouse look.
+    #[cfg(feature = "winit")]
+    pub fn handle_winit_device_event(&mut self, event: &winit::event::DeviceEvent) {
+        if let winit::event::DeviceEvent::MouseMotion { delta: (dx, dy) } = event {
+            self.state
+                .add_mouse_delta(dx.round() as i32, dy.round() as i32);
+        }
+    }
 }
 
 impl Drop for InputManager {
@@ -1036,3 +1984,211 @@ impl Drop for InputManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mouse_position() {
+        let mut state = InputState::new(RENDER_WIDTH as i32 * 2, RENDER_HEIGHT as i32 * 2, true);
+        state.set_mouse_position(100, 50);
+        assert_eq!(state.mouse_position, Point::new(50, 25));
+
+        // Dragging outside the window stays on the edge of the render area.
+        state.set_mouse_position(-10, RENDER_HEIGHT as i32 * 3);
+        assert_eq!(
+            state.mouse_position,
+            Point::new(0, RENDER_HEIGHT as i32 - 1)
+        );
+
+        // A minimized window keeps the last position instead of dividing by zero.
+        state.set_window_size(0, 0);
+        state.set_mouse_position(100, 50);
+        assert_eq!(
+            state.mouse_position,
+            Point::new(0, RENDER_HEIGHT as i32 - 1)
+        );
+    }
+
+    #[test]
+    fn recording_round_trip() {
+        let forward = InputSnapshot {
+            player_forward_down: true,
+            player_turn_right_down: true,
+            ..Default::default()
+        };
+        let mut recorder = InputRecorder::new();
+        recorder.record(0, &InputSnapshot::default());
+        recorder.record(1, &forward);
+        recorder.record(2, &forward);
+        recorder.record(3, &InputSnapshot::default());
+
+        // Only the changes are kept, and movement survives the trip through text.
+        let mut playback = InputRecorder::from_text(&recorder.to_text()).unwrap();
+        assert_eq!(playback.to_text().lines().count(), 2);
+        let played: Vec<InputSnapshot> = (0..4).map(|frame| playback.playback(frame)).collect();
+        assert_eq!(
+            played,
+            vec![
+                InputSnapshot::default(),
+                forward,
+                forward,
+                InputSnapshot::default()
+            ]
+        );
+    }
+
+    #[test]
+    fn text_edits() {
+        let mut state = InputState::new(RENDER_WIDTH as i32, RENDER_HEIGHT as i32, true);
+        state.add_typed_text("hé\r");
+        assert_eq!(
+            Vec::from(state.text_edits.clone()),
+            vec![TextEdit::Insert('h'), TextEdit::Insert('é')]
+        );
+
+        // Every kind of edit survives recording.
+        let edits = [
+            TextEdit::Insert('é'),
+            TextEdit::Insert('😀'),
+            TextEdit::Backspace,
+            TextEdit::Delete,
+            TextEdit::Left,
+            TextEdit::Right,
+            TextEdit::Home,
+            TextEdit::End,
+        ];
+        for edit in edits {
+            let snapshot = InputSnapshot {
+                text_edit: Some(edit),
+                drop_clicked: true,
+                ..Default::default()
+            };
+            assert_eq!(InputSnapshot::decode(snapshot.encode()), snapshot);
+        }
+        let none = InputSnapshot::default();
+        assert_eq!(InputSnapshot::decode(none.encode()).text_edit, None);
+    }
+
+    #[test]
+    fn analog_axes() {
+        assert_eq!(apply_dead_zone(0.1, -0.1, 0.15), (0.0, 0.0));
+        assert_eq!(apply_dead_zone(1.0, 0.0, 0.15), (1.0, 0.0));
+        let (x, _) = apply_dead_zone(0.575, 0.0, 0.15);
+        assert!((x - 0.5).abs() <= 1.0 / AXIS_STEPS);
+
+        // Half pushed sticks survive recording, and win over the digital thresholds.
+        let stick = InputSnapshot {
+            player_forward_down: true,
+            move_axis: quantize_axis(0.6),
+            strafe_axis: quantize_axis(-0.25),
+            turn_axis: -1.0,
+            ..Default::default()
+        };
+        assert_eq!(InputSnapshot::decode(stick.encode()), stick);
+        assert_eq!(stick.forward(), stick.move_axis);
+        assert_eq!(stick.turn(), -1.0);
+        let keys = InputSnapshot {
+            player_backward_down: true,
+            player_strafe_left_down: true,
+            player_strafe_right_down: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            (keys.forward(), keys.strafe(), keys.turn()),
+            (-1.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn mouse_delta() {
+        let look = InputSnapshot {
+            player_forward_down: true,
+            mouse_delta: Point::new(-40, 3),
+            player_dash_clicked: true,
+            drop_clicked: true,
+            ..Default::default()
+        };
+        assert_eq!(InputSnapshot::decode(look.encode()), look);
+
+        // Recordings from before the mouse delta was recorded play back without it.
+        let mut recorder = InputRecorder::from_text("0,8").unwrap();
+        let old = recorder.playback(0);
+        assert!(old.player_forward_down);
+        assert_eq!(old.mouse_delta, Point::zero());
+    }
+
+    #[test]
+    fn cheat_codes() {
+        let mut cheats = CheatCodes::new();
+        cheats.register("god", "up up down ok").unwrap();
+        assert!(cheats.register("bad", "up sideways").is_err());
+        let press = |input: &str| InputSnapshot {
+            menu_up_clicked: input == "up",
+            menu_down_clicked: input == "down",
+            ok_clicked: input == "ok",
+            ..Default::default()
+        };
+
+        // A wrong input partway through starts over, but a code can start right after it.
+        let mut fired = Vec::new();
+        for (frame, input) in ["up", "up", "up", "down", "ok"].iter().enumerate() {
+            fired.extend(cheats.update(frame as u64, &press(input)));
+        }
+        assert_eq!(fired, vec!["god"]);
+
+        // Waiting too long between inputs doesn't count.
+        let mut fired = Vec::new();
+        for (i, input) in ["up", "up", "down", "ok"].iter().enumerate() {
+            let frame = 100 + i as u64 * (CHEAT_TIMEOUT_FRAMES + 1);
+            fired.extend(cheats.update(frame, &press(input)));
+        }
+        assert!(fired.is_empty());
+    }
+
+    #[test]
+    fn input_profiles() {
+        for s in ["standard", "keyboard", "switch"] {
+            assert_eq!(s.parse::<InputProfile>().unwrap().to_string(), s);
+        }
+        assert!("mouse".parse::<InputProfile>().is_err());
+
+        let mut strafe = AutoStrafeLayer;
+        let turning = InputSnapshot {
+            player_forward_down: true,
+            player_turn_left_down: true,
+            ..Default::default()
+        };
+        assert!(strafe.apply(0, turning).player_strafe_left_down);
+        let standing = InputSnapshot {
+            player_turn_left_down: true,
+            ..Default::default()
+        };
+        assert!(!strafe.apply(0, standing).player_strafe_left_down);
+
+        let mut scan = SwitchScanLayer::default();
+        let idle = InputSnapshot::default();
+        let held = InputSnapshot {
+            ok_down: true,
+            ..Default::default()
+        };
+        let scans: Vec<u64> = (0..SCAN_FRAMES * 2 + 1)
+            .filter(|&frame| scan.apply(frame, idle).menu_down_clicked)
+            .collect();
+        assert_eq!(scans, vec![SCAN_FRAMES, SCAN_FRAMES * 2]);
+
+        // Holding the switch cancels once, and stops counting as ok until it's let go.
+        let start = SCAN_FRAMES * 3;
+        let frames: Vec<InputSnapshot> = (start..=start + SWITCH_HOLD_FRAMES * 2)
+            .map(|frame| scan.apply(frame, held))
+            .collect();
+        assert!(frames[0].ok_down);
+        assert_eq!(
+            frames.iter().filter(|inputs| inputs.cancel_clicked).count(),
+            1
+        );
+        assert!(!frames.last().unwrap().ok_down);
+        assert!(!frames.iter().any(|inputs| inputs.menu_down_clicked));
+    }
+}
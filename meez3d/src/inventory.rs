@@ -0,0 +1,324 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Error, Result};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::filemanager::FileManager;
+use crate::properties::PropertyMap;
+
+/// The data file describing every kind of item the player can carry.
+pub const ITEMS_PATH: &str = "assets/items.json";
+/// The width and height of each icon in an item's icon sheet.
+pub const ICON_SIZE: i32 = 64;
+
+/// Where an item's icon is in the texture atlas.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ItemIcon {
+    /// An image in the atlas, cut into ICON_SIZE squares.
+    pub path: String,
+    /// Which square of the image, counting across and then down.
+    #[serde(default)]
+    pub index: u32,
+}
+
+/// What one kind of item looks like and does.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ItemInfo {
+    /// Shown next to the item when it's selected in the inventory.
+    pub description: String,
+    pub icon: ItemIcon,
+    /// How much health using the item gives back.
+    #[serde(default)]
+    pub heal: f32,
+    /// Whether the item stays in the inventory after it's used on something, like a key that
+    /// goes back on the key ring.
+    #[serde(default)]
+    pub keep: bool,
+}
+
+/// Something in a level the player can use items on, like a socket a crank handle fits into.
+/// The player uses an item on it by facing it and using the item from the inventory.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ItemTarget {
+    /// What the target is called, for messages and script events.
+    pub name: String,
+    /// The items that still do something to it. Each only works once.
+    pub accepts: Vec<String>,
+}
+
+impl ItemTarget {
+    /// The target for an object with a "target" property, which is its name, and an "accepts"
+    /// property listing the items that work on it, separated by commas.
+    pub fn from_properties(properties: &PropertyMap) -> Result<Option<ItemTarget>> {
+        let Some(name) = properties.get_string("target")? else {
+            return Ok(None);
+        };
+        let accepts: Vec<String> = properties
+            .get_string("accepts")?
+            .unwrap_or("")
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect();
+        if accepts.is_empty() {
+            bail!("invalid target {}: it accepts no items", name);
+        }
+        Ok(Some(ItemTarget {
+            name: name.to_string(),
+            accepts,
+        }))
+    }
+
+    pub fn accepts(&self, item: &str) -> bool {
+        self.accepts.iter().any(|accepted| accepted == item)
+    }
+}
+
+/// What using an item does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ItemUse {
+    /// It's used on the target the player is facing, and used up unless it's kept.
+    OnTarget { kept: bool },
+    /// It's used up healing the player by this much.
+    Heal(f32),
+    /// There's nothing to use it on, so it stays in the inventory.
+    Nothing,
+}
+
+impl ItemUse {
+    /// Whether the item comes out of the inventory.
+    pub fn is_used_up(&self) -> bool {
+        match self {
+            ItemUse::OnTarget { kept } => !kept,
+            ItemUse::Heal(_) => true,
+            ItemUse::Nothing => false,
+        }
+    }
+}
+
+/// Every kind of item, by name, so new ones can be added without rebuilding the game. The file
+/// is checked for changes while the game runs.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ItemTable {
+    items: BTreeMap<String, ItemInfo>,
+    /// The text it was last loaded from, to tell when the file has changed.
+    source: String,
+}
+
+impl ItemTable {
+    /// Parses a JSON object with an entry for each item, like
+    /// `{"medkit": {"description": "...", "icon": {"path": "...", "index": 2}, "heal": 50}}`.
+    pub fn from_json(text: &str) -> Result<ItemTable> {
+        let items: BTreeMap<String, ItemInfo> = serde_json::from_str(text)
+            .map_err(|e| anyhow!("unable to deserialize items: {}", e))?;
+        for (name, info) in items.iter() {
+            if !info.heal.is_finite() || info.heal < 0.0 {
+                bail!("invalid heal for item {}: {}", name, info.heal);
+            }
+        }
+        Ok(ItemTable {
+            items,
+            source: text.to_string(),
+        })
+    }
+
+    /// Loads the items file, or falls back to no item info if it's missing or invalid.
+    pub fn load(files: &FileManager) -> ItemTable {
+        let table = files
+            .read_to_string(Path::new(ITEMS_PATH))
+            .and_then(|text| ItemTable::from_json(&text));
+        match table {
+            Ok(table) => table,
+            Err(e) => {
+                info!("not using item info: {}", e);
+                ItemTable::default()
+            }
+        }
+    }
+
+    /// Loads the items file again if it's changed, returning whether it did. If the new file is
+    /// invalid, the old items are kept.
+    pub fn reload(&mut self, files: &FileManager) -> bool {
+        let Ok(text) = files.read_to_string(Path::new(ITEMS_PATH)) else {
+            return false;
+        };
+        if text == self.source {
+            return false;
+        }
+        match ItemTable::from_json(&text) {
+            Ok(table) => {
+                info!("reloaded items");
+                *self = table;
+                true
+            }
+            Err(e) => {
+                error!("not reloading items: {}", e);
+                // Don't complain again until it changes.
+                self.source = text;
+                false
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ItemInfo> {
+        self.items.get(name)
+    }
+
+    /// What using an item does, with the player facing the given target, if any. An item works
+    /// on a target that accepts it before anything else.
+    pub fn use_of(&self, name: &str, target: Option<&ItemTarget>) -> ItemUse {
+        let info = self.get(name);
+        if target.is_some_and(|target| target.accepts(name)) {
+            let kept = info.is_some_and(|info| info.keep);
+            return ItemUse::OnTarget { kept };
+        }
+        match info {
+            Some(info) if info.heal > 0.0 => ItemUse::Heal(info.heal),
+            _ => ItemUse::Nothing,
+        }
+    }
+}
+
+/// Something the player can do with an item from the inventory screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ItemAction {
+    Use,
+    Drop,
+}
+
+impl FromStr for ItemAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "use" => ItemAction::Use,
+            "drop" => ItemAction::Drop,
+            _ => bail!("invalid item action: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for ItemAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ItemAction::Use => "use",
+            ItemAction::Drop => "drop",
+        })
+    }
+}
+
+/// The items the player is carrying, and how many of each, in the order they were first
+/// picked up.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Inventory {
+    slots: Vec<(String, u32)>,
+}
+
+impl Inventory {
+    pub fn add(&mut self, name: &str) {
+        match self.slots.iter_mut().find(|(slot, _)| slot == name) {
+            Some((_, count)) => *count += 1,
+            None => self.slots.push((name.to_string(), 1)),
+        }
+    }
+
+    /// Takes one of an item out, returning false if there weren't any. Its slot goes away with
+    /// the last one.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let Some(index) = self.slots.iter().position(|(slot, _)| slot == name) else {
+            return false;
+        };
+        self.slots[index].1 -= 1;
+        if self.slots[index].1 == 0 {
+            self.slots.remove(index);
+        }
+        true
+    }
+
+    pub fn count(&self, name: &str) -> u32 {
+        self.slots
+            .iter()
+            .find(|(slot, _)| slot == name)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    /// Each kind of item carried, with how many of it.
+    pub fn slots(&self) -> &[(String, u32)] {
+        &self.slots
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_and_remove() {
+        let mut inventory = Inventory::default();
+        inventory.add("medkit");
+        inventory.add("key");
+        inventory.add("medkit");
+        assert_eq!(inventory.count("medkit"), 2);
+        assert_eq!(inventory.slots()[1], ("key".to_string(), 1));
+
+        assert!(inventory.remove("key"));
+        assert!(!inventory.remove("key"));
+        assert_eq!(inventory.slots().len(), 1);
+        assert!(inventory.remove("medkit") && inventory.remove("medkit"));
+        assert!(inventory.is_empty());
+    }
+
+    #[test]
+    fn load_and_validate() {
+        let text = r#"{"medkit": {"description": "patches you up", "icon": {"path": "a.png"}, "heal": 50}}"#;
+        let table = ItemTable::from_json(text).unwrap();
+        let medkit = table.get("medkit").unwrap();
+        assert_eq!((medkit.heal, medkit.icon.index), (50.0, 0));
+        assert!(table.get("key").is_none());
+
+        assert!(ItemTable::from_json(&text.replace("50", "-5")).is_err());
+        assert!(ItemTable::from_json(&text.replace("heal", "hael")).is_err());
+        assert_eq!("drop".parse::<ItemAction>().unwrap(), ItemAction::Drop);
+        assert_eq!(ItemAction::Use.to_string(), "use");
+    }
+
+    #[test]
+    fn use_on_target() {
+        let text = r#"{
+            "medkit": {"description": "", "icon": {"path": "a.png"}, "heal": 50},
+            "crank": {"description": "", "icon": {"path": "a.png"}},
+            "key": {"description": "", "icon": {"path": "a.png"}, "keep": true}
+        }"#;
+        let table = ItemTable::from_json(text).unwrap();
+        let socket = ItemTarget {
+            name: "socket".to_string(),
+            accepts: vec!["crank".to_string(), "key".to_string()],
+        };
+        let socket = Some(&socket);
+
+        assert_eq!(
+            table.use_of("crank", socket),
+            ItemUse::OnTarget { kept: false }
+        );
+        assert_eq!(
+            table.use_of("key", socket),
+            ItemUse::OnTarget { kept: true }
+        );
+        assert!(!table.use_of("key", socket).is_used_up());
+        // Without a target that takes them, only items that heal do anything.
+        assert_eq!(table.use_of("medkit", socket), ItemUse::Heal(50.0));
+        assert_eq!(table.use_of("crank", None), ItemUse::Nothing);
+        assert!(!table.use_of("crank", None).is_used_up());
+    }
+}
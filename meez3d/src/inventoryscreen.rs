@@ -0,0 +1,448 @@
+use std::path::Path;
+
+use log::error;
+
+use crate::font::Font;
+use crate::geometry::{Point, Rect};
+use crate::imagemanager::ImageLoader;
+use crate::inputmanager::InputSnapshot;
+use crate::inventory::{Inventory, ItemAction, ItemTable, ItemTarget, ItemUse, ICON_SIZE};
+use crate::keybindings::KeyBindings;
+use crate::narration::Narrator;
+use crate::rendercontext::{PostprocessProfile, RenderContext, RenderLayer};
+use crate::scene::{Scene, SceneResult};
+use crate::soundmanager::{Sound, SoundManager};
+use crate::sprite::SpriteSheet;
+use crate::utils::Color;
+
+const MARGIN: i32 = 16;
+/// How many items are in each row of the grid.
+const GRID_COLUMNS: usize = 4;
+/// The width and height of each cell of the grid, including the gap around its icon.
+const CELL_SIZE: i32 = 128;
+const BACKGROUND_COLOR: Color = Color {
+    r: 0x11,
+    g: 0x11,
+    b: 0x22,
+    a: 0xee,
+};
+const CELL_COLOR: Color = Color {
+    r: 0x22,
+    g: 0x22,
+    b: 0x44,
+    a: 0xff,
+};
+const SELECTED_COLOR: Color = Color {
+    r: 0x66,
+    g: 0x66,
+    b: 0xaa,
+    a: 0xff,
+};
+/// Items the target the player is facing accepts.
+const ACCEPTED_COLOR: Color = Color {
+    r: 0x33,
+    g: 0x66,
+    b: 0x44,
+    a: 0xff,
+};
+const HINT_COLOR: Color = Color {
+    r: 0x88,
+    g: 0xcc,
+    b: 0xff,
+    a: 0xff,
+};
+/// What the hint under the grid says, with the keys filled in by KeyBindings::fill_in.
+const HINT: &str = "{ok} to use, {drop} to drop";
+
+/// One kind of item in the grid.
+struct Slot {
+    name: String,
+    count: u32,
+    description: String,
+    /// The item's icon sheet and which icon in it, or None if it has no icon.
+    icon: Option<(SpriteSheet, u32)>,
+}
+
+/// A grid of the items the player is carrying, opened from the pause menu or with the inventory
+/// key.
+///
+/// The arrows move around the grid, and the selected item's description is shown next to it.
+/// Ok uses the selected item and drop drops it, which the scene the inventory belongs to does
+/// the next time it updates. If the player is facing something they can use items on, the items
+/// it accepts are marked, and using one uses it on that. Cancel or the inventory key closes the
+/// screen.
+pub struct InventoryScreen {
+    slots: Vec<Slot>,
+    selected: usize,
+    items: ItemTable,
+    target: Option<ItemTarget>,
+    /// Why the last item couldn't be used, until the selection moves.
+    status: Option<String>,
+    /// What the player chose to do with their items, until the stage manager collects it.
+    pending_actions: Vec<(ItemAction, String)>,
+    /// The slot that was selected the last time the screen was narrated, or None if it hasn't
+    /// been narrated yet.
+    narrated: Option<usize>,
+    /// The hint, naming the keys that use and drop items.
+    hint: String,
+}
+
+impl InventoryScreen {
+    /// A screen showing a copy of the inventory, since the game is paused while it's open.
+    pub fn new(
+        inventory: &Inventory,
+        items: &ItemTable,
+        target: Option<&ItemTarget>,
+        keys: &KeyBindings,
+        images: &mut dyn ImageLoader,
+    ) -> InventoryScreen {
+        let slots = inventory
+            .slots()
+            .iter()
+            .map(|(name, count)| {
+                let info = items.get(name);
+                let icon = info.and_then(|info| {
+                    let path = Path::new(&info.icon.path);
+                    match images.load_spritesheet(path, ICON_SIZE, ICON_SIZE) {
+                        Ok(sheet) => Some((sheet, info.icon.index)),
+                        Err(e) => {
+                            error!("unable to load icon for {}: {}", name, e);
+                            None
+                        }
+                    }
+                });
+                Slot {
+                    name: name.clone(),
+                    count: *count,
+                    description: info
+                        .map(|info| info.description.clone())
+                        .unwrap_or_default(),
+                    icon,
+                }
+            })
+            .collect();
+        InventoryScreen {
+            slots,
+            selected: 0,
+            items: items.clone(),
+            target: target.cloned(),
+            status: None,
+            pending_actions: Vec::new(),
+            narrated: None,
+            hint: keys.fill_in(HINT),
+        }
+    }
+
+    /// Moves the selection around the grid, stopping at its edges.
+    fn move_selection(&mut self, dx: i32, dy: i32) {
+        let column = (self.selected % GRID_COLUMNS) as i32 + dx;
+        if column < 0 || column >= GRID_COLUMNS as i32 {
+            return;
+        }
+        let target = self.selected as i32 + dx + dy * GRID_COLUMNS as i32;
+        if target >= 0 && (target as usize) < self.slots.len() {
+            self.selected = target as usize;
+            self.status = None;
+        }
+    }
+
+    /// Uses or drops one of the selected item, keeping the copy of the inventory up to date with
+    /// what the scene will do.
+    fn act(&mut self, action: ItemAction, sounds: &mut SoundManager) {
+        let Some(slot) = self.slots.get_mut(self.selected) else {
+            return;
+        };
+        let item_use = self.items.use_of(&slot.name, self.target.as_ref());
+        if action == ItemAction::Use {
+            match (item_use, &mut self.target) {
+                (ItemUse::Nothing, _) => {
+                    self.status = Some(format!("nothing to use {} on", slot.name));
+                    return;
+                }
+                (ItemUse::OnTarget { .. }, Some(target)) => {
+                    target.accepts.retain(|item| *item != slot.name);
+                }
+                _ => {}
+            }
+        }
+        sounds.play(Sound::Click);
+        self.pending_actions.push((action, slot.name.clone()));
+        if action == ItemAction::Use && !item_use.is_used_up() {
+            return;
+        }
+        slot.count -= 1;
+        if slot.count == 0 {
+            self.slots.remove(self.selected);
+            self.selected = self.selected.min(self.slots.len().saturating_sub(1));
+            self.narrated = None;
+        }
+    }
+
+    /// Where the cell for the item at the given index is, in a grid starting at top.
+    fn cell_rect(index: usize, left: i32, top: i32) -> Rect<i32> {
+        let (row, column) = (index / GRID_COLUMNS, index % GRID_COLUMNS);
+        Rect {
+            x: left + column as i32 * CELL_SIZE,
+            y: top + row as i32 * CELL_SIZE,
+            w: CELL_SIZE - MARGIN,
+            h: CELL_SIZE - MARGIN,
+        }
+    }
+}
+
+impl Scene for InventoryScreen {
+    fn update(
+        &mut self,
+        _context: &RenderContext,
+        inputs: &InputSnapshot,
+        sounds: &mut SoundManager,
+    ) -> SceneResult {
+        if inputs.cancel_clicked || inputs.inventory_clicked {
+            return SceneResult::Pop;
+        }
+        if inputs.menu_up_clicked {
+            self.move_selection(0, -1);
+        }
+        if inputs.menu_down_clicked {
+            self.move_selection(0, 1);
+        }
+        if inputs.menu_left_clicked {
+            self.move_selection(-1, 0);
+        }
+        if inputs.menu_right_clicked {
+            self.move_selection(1, 0);
+        }
+        if inputs.ok_clicked {
+            self.act(ItemAction::Use, sounds);
+        } else if inputs.drop_clicked {
+            self.act(ItemAction::Drop, sounds);
+        }
+        SceneResult::Continue
+    }
+
+    fn name(&self) -> &str {
+        "inventory"
+    }
+
+    fn postprocess(&self) -> PostprocessProfile {
+        PostprocessProfile::CLEAN
+    }
+
+    fn take_item_actions(&mut self) -> Vec<(ItemAction, String)> {
+        std::mem::take(&mut self.pending_actions)
+    }
+
+    fn flush_narration(&mut self, narrator: &mut dyn Narrator) {
+        if self.narrated == Some(self.selected) {
+            return;
+        }
+        match self.slots.get(self.selected) {
+            Some(slot) if slot.description.is_empty() => narrator.narrate(&slot.name),
+            Some(slot) => narrator.narrate(&format!("{}, {}", slot.name, slot.description)),
+            None => narrator.narrate("no items"),
+        }
+        self.narrated = Some(self.selected);
+    }
+
+    fn draw(&self, context: &mut RenderContext, font: &Font, _previous: Option<&dyn Scene>) {
+        context.fill_rect(context.ui_area(), RenderLayer::Hud, BACKGROUND_COLOR);
+        let area = context.safe_area();
+        let title = match &self.target {
+            Some(target) => format!("items, facing {}", target.name),
+            None => "items".to_string(),
+        };
+        let left = area.x + MARGIN;
+        font.draw_string(
+            context,
+            RenderLayer::Hud,
+            Point::new(left, area.y + MARGIN),
+            &title,
+        );
+
+        let top = area.y + MARGIN * 2 + font.char_height;
+        if self.slots.is_empty() {
+            font.draw_string(context, RenderLayer::Hud, Point::new(left, top), "no items");
+            return;
+        }
+
+        for (i, slot) in self.slots.iter().enumerate() {
+            let cell = InventoryScreen::cell_rect(i, left, top);
+            let accepted = self
+                .target
+                .as_ref()
+                .is_some_and(|target| target.accepts(&slot.name));
+            let color = if i == self.selected {
+                SELECTED_COLOR
+            } else if accepted {
+                ACCEPTED_COLOR
+            } else {
+                CELL_COLOR
+            };
+            context.fill_rect(cell, RenderLayer::Hud, color);
+            if let Some((sheet, index)) = &slot.icon {
+                let offset = (cell.w - ICON_SIZE) / 2;
+                let icon = Rect {
+                    x: cell.x + offset,
+                    y: cell.y + offset,
+                    w: ICON_SIZE,
+                    h: ICON_SIZE,
+                };
+                sheet.blit(context, RenderLayer::Hud, icon, *index, 0, false);
+            }
+            if slot.count > 1 {
+                let count = slot.count.to_string();
+                let x = cell.right() - font.text_width(&count);
+                let y = cell.bottom() - font.char_height;
+                font.draw_string(context, RenderLayer::Hud, Point::new(x, y), &count);
+            }
+        }
+
+        // The details of the selected item go to the right of the grid.
+        let Some(slot) = self.slots.get(self.selected) else {
+            return;
+        };
+        let x = left + MARGIN + GRID_COLUMNS as i32 * CELL_SIZE;
+        let width = area.right() - x - MARGIN;
+        font.draw_string(context, RenderLayer::Hud, Point::new(x, top), &slot.name);
+        let description = Point::new(x, top + font.char_height + MARGIN);
+        font.draw_wrapped(
+            context,
+            RenderLayer::Hud,
+            description,
+            width,
+            &slot.description,
+        );
+        let hint = Point::new(left, area.bottom() - MARGIN - font.char_height);
+        if let Some(status) = &self.status {
+            let status_pos = Point::new(left, hint.y - font.char_height);
+            font.draw_string(context, RenderLayer::Hud, status_pos, status);
+        }
+        context.set_tint(RenderLayer::Hud, HINT_COLOR);
+        font.draw_string(context, RenderLayer::Hud, hint, &self.hint);
+        context.set_tint(RenderLayer::Hud, Color::WHITE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imagemanager::ImageManager;
+
+    const ITEMS: &str = r#"{
+        "medkit": {"description": "", "icon": {"path": "a.png"}, "heal": 50},
+        "crank": {"description": "", "icon": {"path": "a.png"}},
+        "key": {"description": "", "icon": {"path": "a.png"}, "keep": true}
+    }"#;
+
+    #[test]
+    fn navigate_and_use() {
+        let mut inventory = Inventory::default();
+        for name in ["medkit", "key", "map", "coin", "gem", "medkit"] {
+            inventory.add(name);
+        }
+        let mut images = ImageManager::null_manager();
+        let items = ItemTable::from_json(ITEMS).unwrap();
+        let keys = KeyBindings::default();
+        let mut screen = InventoryScreen::new(&inventory, &items, None, &keys, &mut images);
+        assert_eq!(screen.hint, "enter to use, x to drop");
+        let context = RenderContext::new(640, 400, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+        let mut press = |screen: &mut InventoryScreen, inputs: InputSnapshot| {
+            screen.update(&context, &inputs, &mut sounds)
+        };
+
+        // Down goes to the next row, and left stops at the edge of the grid.
+        let down = InputSnapshot {
+            menu_down_clicked: true,
+            ..Default::default()
+        };
+        let left = InputSnapshot {
+            menu_left_clicked: true,
+            ..Default::default()
+        };
+        press(&mut screen, down);
+        assert_eq!(screen.slots[screen.selected].name, "gem");
+        press(&mut screen, left);
+        assert_eq!(screen.selected, GRID_COLUMNS);
+        press(&mut screen, down);
+        assert_eq!(screen.selected, GRID_COLUMNS);
+
+        let drop = InputSnapshot {
+            drop_clicked: true,
+            ..Default::default()
+        };
+        press(&mut screen, drop);
+        assert_eq!(screen.slots.len(), GRID_COLUMNS);
+        assert_eq!(screen.selected, GRID_COLUMNS - 1);
+
+        // Using one of two medkits leaves the other.
+        let up = InputSnapshot {
+            menu_up_clicked: true,
+            ..Default::default()
+        };
+        for _ in 0..3 {
+            press(&mut screen, left);
+        }
+        press(&mut screen, up);
+        let ok = InputSnapshot {
+            ok_clicked: true,
+            ..Default::default()
+        };
+        press(&mut screen, ok);
+        assert_eq!(screen.slots[0].count, 1);
+        assert_eq!(
+            screen.take_item_actions(),
+            vec![
+                (ItemAction::Drop, "gem".to_string()),
+                (ItemAction::Use, "medkit".to_string())
+            ]
+        );
+        assert!(screen.take_item_actions().is_empty());
+
+        let cancel = InputSnapshot {
+            cancel_clicked: true,
+            ..Default::default()
+        };
+        assert!(matches!(press(&mut screen, cancel), SceneResult::Pop));
+    }
+
+    #[test]
+    fn use_on_target() {
+        let mut inventory = Inventory::default();
+        inventory.add("crank");
+        inventory.add("key");
+        let socket = ItemTarget {
+            name: "socket".to_string(),
+            accepts: vec!["crank".to_string(), "key".to_string()],
+        };
+        let items = ItemTable::from_json(ITEMS).unwrap();
+        let mut images = ImageManager::null_manager();
+        let keys = KeyBindings::default();
+        let mut screen =
+            InventoryScreen::new(&inventory, &items, Some(&socket), &keys, &mut images);
+        let context = RenderContext::new(640, 400, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+        let ok = InputSnapshot {
+            ok_clicked: true,
+            ..Default::default()
+        };
+
+        // The crank is used up, but the key goes back on the key ring.
+        screen.update(&context, &ok, &mut sounds);
+        assert_eq!(screen.slots[0].name, "key");
+        screen.update(&context, &ok, &mut sounds);
+        assert_eq!(screen.slots[0].count, 1);
+        assert!(screen.status.is_none());
+
+        // Each item only works on the socket once.
+        screen.update(&context, &ok, &mut sounds);
+        assert_eq!(screen.status.as_deref(), Some("nothing to use key on"));
+        assert_eq!(
+            screen.take_item_actions(),
+            vec![
+                (ItemAction::Use, "crank".to_string()),
+                (ItemAction::Use, "key".to_string())
+            ]
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Error, Result};
+use log::info;
+
+use crate::filemanager::FileManager;
+use crate::inputmanager::{JoystickButton, KeyboardKey, MouseButton};
+
+/// The user data file the player's bindings are kept in.
+pub const BINDINGS_PATH: &str = "bindings.txt";
+
+/// The bindings the game starts with, in the same format as the bindings file.
+const DEFAULT_BINDINGS: &str = "
+ok = key:enter button:south
+cancel = key:escape button:west
+forward = key:w key:up button:dpad_up
+backward = key:s key:down button:dpad_down
+strafe_left = key:a button:dpad_left
+strafe_right = key:d button:dpad_right
+turn_left = key:q key:left
+turn_right = key:e key:right
+menu_up = key:w key:up button:dpad_up
+menu_down = key:down key:s button:dpad_down
+menu_left = key:left key:a button:dpad_left
+menu_right = key:right key:d button:dpad_right
+fire = mouse:left
+dash = key:shift button:east
+inventory = key:i key:tab button:north
+# Every face button is taken, and east is already dash.
+drop = key:x
+";
+
+/// Something the player can do, which keys and buttons can be bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Action {
+    Ok,
+    Cancel,
+    Forward,
+    Backward,
+    StrafeLeft,
+    StrafeRight,
+    TurnLeft,
+    TurnRight,
+    MenuUp,
+    MenuDown,
+    MenuLeft,
+    MenuRight,
+    Fire,
+    Dash,
+    Inventory,
+    Drop,
+}
+
+impl FromStr for Action {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "ok" => Action::Ok,
+            "cancel" => Action::Cancel,
+            "forward" => Action::Forward,
+            "backward" => Action::Backward,
+            "strafe_left" => Action::StrafeLeft,
+            "strafe_right" => Action::StrafeRight,
+            "turn_left" => Action::TurnLeft,
+            "turn_right" => Action::TurnRight,
+            "menu_up" => Action::MenuUp,
+            "menu_down" => Action::MenuDown,
+            "menu_left" => Action::MenuLeft,
+            "menu_right" => Action::MenuRight,
+            "fire" => Action::Fire,
+            "dash" => Action::Dash,
+            "inventory" => Action::Inventory,
+            "drop" => Action::Drop,
+            _ => bail!("invalid action: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Ok => "ok",
+            Action::Cancel => "cancel",
+            Action::Forward => "forward",
+            Action::Backward => "backward",
+            Action::StrafeLeft => "strafe_left",
+            Action::StrafeRight => "strafe_right",
+            Action::TurnLeft => "turn_left",
+            Action::TurnRight => "turn_right",
+            Action::MenuUp => "menu_up",
+            Action::MenuDown => "menu_down",
+            Action::MenuLeft => "menu_left",
+            Action::MenuRight => "menu_right",
+            Action::Fire => "fire",
+            Action::Dash => "dash",
+            Action::Inventory => "inventory",
+            Action::Drop => "drop",
+        })
+    }
+}
+
+/// A physical key or button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(KeyboardKey),
+    Button(JoystickButton),
+    Mouse(MouseButton),
+}
+
+impl FromStr for Binding {
+    type Err = Error;
+
+    /// Parses a binding like "key:w", "button:south", or "mouse:left".
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((device, name)) = s.split_once(':') else {
+            bail!("invalid binding: {}", s);
+        };
+        Ok(match device {
+            "key" => Binding::Key(name.parse()?),
+            "button" => Binding::Button(name.parse()?),
+            "mouse" => Binding::Mouse(name.parse()?),
+            _ => bail!("invalid binding: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "key:{}", key),
+            Binding::Button(button) => write!(f, "button:{}", button),
+            Binding::Mouse(button) => write!(f, "mouse:{}", button),
+        }
+    }
+}
+
+/// Which keys and buttons do each action. Gamepad sticks aren't included, and always work.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBindings {
+    bindings: BTreeMap<Action, Vec<Binding>>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            bindings: parse_bindings(DEFAULT_BINDINGS).expect("default bindings should parse"),
+        }
+    }
+}
+
+fn parse_bindings(text: &str) -> Result<BTreeMap<Action, Vec<Binding>>> {
+    let mut bindings = BTreeMap::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((action, keys)) = line.split_once('=') else {
+            bail!("invalid binding line: {}", line);
+        };
+        let action: Action = action.trim().parse()?;
+        let keys = keys
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<Binding>>>()?;
+        bindings.insert(action, keys);
+    }
+    Ok(bindings)
+}
+
+impl KeyBindings {
+    /// Reads bindings written by `to_text`, with an "action = binding binding ..." line for each
+    /// action. Lines starting with "#" are comments. Actions that aren't listed keep their
+    /// default bindings.
+    pub fn from_text(text: &str) -> Result<KeyBindings> {
+        let mut bindings = KeyBindings::default();
+        bindings.bindings.extend(parse_bindings(text)?);
+        Ok(bindings)
+    }
+
+    pub fn to_text(&self) -> String {
+        let lines: Vec<String> = self
+            .bindings
+            .iter()
+            .map(|(action, keys)| {
+                let keys: Vec<String> = keys.iter().map(Binding::to_string).collect();
+                format!("{} = {}", action, keys.join(" "))
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    /// Loads the player's bindings, or the defaults if they haven't changed any.
+    pub fn load(files: &FileManager) -> KeyBindings {
+        match files
+            .read_user_data(BINDINGS_PATH)
+            .and_then(|text| KeyBindings::from_text(&text))
+        {
+            Ok(bindings) => bindings,
+            Err(e) => {
+                info!("using default bindings: {}", e);
+                KeyBindings::default()
+            }
+        }
+    }
+
+    pub fn save(&self, files: &FileManager) -> Result<()> {
+        files
+            .write_user_data(BINDINGS_PATH, self.to_text().as_bytes())
+            .map_err(|e| anyhow!("unable to save bindings: {}", e))
+    }
+
+    /// The keys and buttons bound to an action.
+    pub fn get(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    /// Replaces everything bound to an action.
+    pub fn rebind(&mut self, action: Action, bindings: Vec<Binding>) {
+        self.bindings.insert(action, bindings);
+    }
+
+    /// Adds a key or button to an action, if it isn't already bound to it.
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        let bindings = self.bindings.entry(action).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    /// What to call the key or button that does an action in text on the screen, like "i" or
+    /// "shift". Keys come first, since text can't know what's being played on, then the mouse,
+    /// then gamepad buttons. An action with nothing bound to it is called by its own name.
+    pub fn name_of(&self, action: Action) -> String {
+        let bindings = self.get(action);
+        let key = bindings.iter().find_map(|binding| match binding {
+            Binding::Key(key) => Some(key.to_string()),
+            _ => None,
+        });
+        let mouse = bindings.iter().find_map(|binding| match binding {
+            Binding::Mouse(MouseButton::Left) => Some("click".to_string()),
+            _ => None,
+        });
+        let button = bindings.iter().find_map(|binding| match binding {
+            Binding::Button(button) => Some(button.to_string()),
+            _ => None,
+        });
+        key.or(mouse)
+            .or(button)
+            .unwrap_or_else(|| action.to_string())
+    }
+
+    /// Replaces each action in braces in some text, like "{inventory}", with the name of what
+    /// does it, so hints stay right after keys are rebound. Anything else in braces is left as it
+    /// is.
+    pub fn fill_in(&self, text: &str) -> String {
+        let mut result = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                break;
+            };
+            result.push_str(&rest[..start]);
+            let braced = &rest[start..start + len + 1];
+            match braced[1..braced.len() - 1].parse::<Action>() {
+                Ok(action) => result.push_str(&self.name_of(action)),
+                Err(_) => result.push_str(braced),
+            }
+            rest = &rest[start + len + 1..];
+        }
+        result.push_str(rest);
+        result
+    }
+
+    /// Takes a key or button off an action.
+    pub fn unbind(&mut self, action: Action, binding: Binding) {
+        if let Some(bindings) = self.bindings.get_mut(&action) {
+            bindings.retain(|other| *other != binding);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_rebind() {
+        let defaults = KeyBindings::default();
+        assert_eq!(
+            defaults.get(Action::Forward),
+            &[
+                Binding::Key(KeyboardKey::W),
+                Binding::Key(KeyboardKey::Up),
+                Binding::Button(JoystickButton::Up)
+            ]
+        );
+        assert_eq!(
+            KeyBindings::from_text(&defaults.to_text()).unwrap(),
+            defaults
+        );
+
+        // Only the listed actions change.
+        let text = "# left handed\nforward = key:i\nfire = key:space mouse:left\n";
+        let mut bindings = KeyBindings::from_text(text).unwrap();
+        assert_eq!(
+            bindings.get(Action::Forward),
+            &[Binding::Key(KeyboardKey::I)]
+        );
+        assert_eq!(bindings.get(Action::Cancel), defaults.get(Action::Cancel));
+
+        bindings.unbind(Action::Fire, Binding::Mouse(MouseButton::Left));
+        bindings.bind(Action::Fire, Binding::Key(KeyboardKey::Space));
+        assert_eq!(
+            bindings.get(Action::Fire),
+            &[Binding::Key(KeyboardKey::Space)]
+        );
+        bindings.rebind(Action::Forward, Vec::new());
+        assert!(bindings.get(Action::Forward).is_empty());
+
+        assert!(KeyBindings::from_text("jump = key:space").is_err());
+        assert!(KeyBindings::from_text("fire = key:f13").is_err());
+        assert!(KeyBindings::from_text("fire key:f").is_err());
+        assert!("pedal:left".parse::<Binding>().is_err());
+    }
+
+    #[test]
+    fn fill_in() {
+        let mut bindings = KeyBindings::default();
+        assert_eq!(
+            bindings.fill_in("{menu_left} {menu_right} adjust"),
+            "left right adjust"
+        );
+        assert_eq!(bindings.fill_in("{fire} to fire"), "click to fire");
+        assert_eq!(bindings.fill_in("{jump} {ok"), "{jump} {ok");
+
+        // Gamepad buttons are only named when there's no key, and then the action is.
+        bindings.rebind(Action::Ok, vec![Binding::Button(JoystickButton::South)]);
+        bindings.rebind(Action::Cancel, Vec::new());
+        assert_eq!(
+            bindings.fill_in("{ok} save  {cancel} cancel"),
+            "south save  cancel cancel"
+        );
+    }
+}
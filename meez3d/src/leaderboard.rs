@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::FRAME_RATE;
+use crate::filemanager::FileManager;
+
+/// Who finished runs are credited to on the leaderboard.
+pub const PLAYER_NAME_CVAR: &str = "player_name";
+/// The name used when the player hasn't set one.
+pub const DEFAULT_PLAYER_NAME: &str = "player";
+
+/// The user data file the local leaderboard is kept in.
+const LEADERBOARD_PATH: &str = "leaderboard.json";
+/// How many entries each level keeps.
+pub const LEADERBOARD_SIZE: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    /// How many frames the run took.
+    pub frames: u64,
+    /// Points scored, for levels that keep score. Otherwise 0, so the time decides the rank.
+    #[serde(default)]
+    pub score: u64,
+}
+
+impl LeaderboardEntry {
+    /// How long the run took, like "1:05.25".
+    pub fn time(&self) -> String {
+        let hundredths = self.frames * 100 / FRAME_RATE as u64;
+        let seconds = hundredths / 100;
+        format!(
+            "{}:{:02}.{:02}",
+            seconds / 60,
+            seconds % 60,
+            hundredths % 100
+        )
+    }
+
+    /// Whether this entry ranks above another: more points first, then a faster time.
+    fn beats(&self, other: &LeaderboardEntry) -> bool {
+        (self.score, other.frames) > (other.score, self.frames)
+    }
+}
+
+/// Where leaderboards are kept, so hosts can swap the local one for an online service.
+pub trait LeaderboardBackend {
+    /// Every level with entries, in order by name.
+    fn levels(&self) -> Vec<String>;
+
+    /// The entries for a level, best first.
+    fn entries(&self, level: &str) -> Vec<LeaderboardEntry>;
+
+    /// Adds an entry for a level, returning its rank from 0, or None if it didn't make the board.
+    fn submit(&mut self, level: &str, entry: LeaderboardEntry) -> Option<usize>;
+
+    /// Saves anything submitted since the last flush.
+    fn flush(&mut self, files: &FileManager) -> Result<()>;
+}
+
+/// A leaderboard kept on this device, in the user data directory.
+#[derive(Debug, Default)]
+pub struct LocalLeaderboard {
+    boards: BTreeMap<String, Vec<LeaderboardEntry>>,
+    /// Whether there are entries that haven't been saved yet.
+    dirty: bool,
+}
+
+impl LocalLeaderboard {
+    pub fn new() -> LocalLeaderboard {
+        LocalLeaderboard::default()
+    }
+
+    /// Loads the saved leaderboard, or starts an empty one if there isn't one yet.
+    pub fn load(files: &FileManager) -> LocalLeaderboard {
+        match files
+            .read_user_data(LEADERBOARD_PATH)
+            .and_then(|text| LocalLeaderboard::from_json(&text))
+        {
+            Ok(leaderboard) => leaderboard,
+            Err(e) => {
+                info!("starting a new leaderboard: {}", e);
+                LocalLeaderboard::new()
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(&self.boards)
+            .map_err(|e| anyhow!("unable to serialize leaderboard: {}", e))
+    }
+
+    pub fn from_json(text: &str) -> Result<LocalLeaderboard> {
+        let boards = serde_json::from_str(text)
+            .map_err(|e| anyhow!("unable to deserialize leaderboard: {}", e))?;
+        Ok(LocalLeaderboard {
+            boards,
+            dirty: false,
+        })
+    }
+}
+
+impl LeaderboardBackend for LocalLeaderboard {
+    fn levels(&self) -> Vec<String> {
+        self.boards.keys().cloned().collect()
+    }
+
+    fn entries(&self, level: &str) -> Vec<LeaderboardEntry> {
+        self.boards.get(level).cloned().unwrap_or_default()
+    }
+
+    fn submit(&mut self, level: &str, entry: LeaderboardEntry) -> Option<usize> {
+        let board = self.boards.entry(level.to_string()).or_default();
+        // Ties go to whoever got there first.
+        let rank = board
+            .iter()
+            .position(|other| entry.beats(other))
+            .unwrap_or(board.len());
+        if rank >= LEADERBOARD_SIZE {
+            return None;
+        }
+        board.insert(rank, entry);
+        board.truncate(LEADERBOARD_SIZE);
+        self.dirty = true;
+        Some(rank)
+    }
+
+    fn flush(&mut self, files: &FileManager) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        // A failed save isn't retried until something else changes, since this is called every
+        // frame, and the files might be read-only.
+        self.dirty = false;
+        files.write_user_data(LEADERBOARD_PATH, self.to_json()?.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn entry(name: &str, frames: u64, score: u64) -> LeaderboardEntry {
+        LeaderboardEntry {
+            name: name.to_string(),
+            frames,
+            score,
+        }
+    }
+
+    #[test]
+    fn ranking() {
+        let mut leaderboard = LocalLeaderboard::new();
+        assert_eq!(leaderboard.submit("a", entry("slow", 600, 0)), Some(0));
+        assert_eq!(leaderboard.submit("a", entry("fast", 300, 0)), Some(0));
+        assert_eq!(leaderboard.submit("a", entry("tie", 300, 0)), Some(1));
+        assert_eq!(leaderboard.submit("a", entry("points", 900, 5)), Some(0));
+        let names: Vec<String> = leaderboard
+            .entries("a")
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, vec!["points", "fast", "tie", "slow"]);
+        assert_eq!(leaderboard.entries("a")[1].time(), "0:05.00");
+
+        for _ in 0..LEADERBOARD_SIZE {
+            leaderboard.submit("b", entry("someone", 60, 0));
+        }
+        assert_eq!(leaderboard.submit("b", entry("late", 61, 0)), None);
+        assert_eq!(leaderboard.entries("b").len(), LEADERBOARD_SIZE);
+
+        let copy = LocalLeaderboard::from_json(&leaderboard.to_json().unwrap()).unwrap();
+        assert_eq!(copy.levels(), vec!["a", "b"]);
+        assert_eq!(copy.entries("a"), leaderboard.entries("a"));
+    }
+
+    #[test]
+    fn failed_flush_not_retried() {
+        let files = FileManager::from_memory(HashMap::new()).unwrap();
+        let mut leaderboard = LocalLeaderboard::new();
+        leaderboard.submit("a", entry("someone", 60, 0));
+        assert!(leaderboard.flush(&files).is_err());
+        assert!(leaderboard.flush(&files).is_ok());
+    }
+}
@@ -0,0 +1,164 @@
+use crate::font::Font;
+use crate::geometry::Point;
+use crate::inputmanager::InputSnapshot;
+use crate::leaderboard::LeaderboardBackend;
+use crate::rendercontext::{PostprocessProfile, RenderContext, RenderLayer};
+use crate::scene::{Scene, SceneResult};
+use crate::soundmanager::SoundManager;
+use crate::utils::Color;
+
+const MARGIN: i32 = 16;
+const BACKGROUND_COLOR: Color = Color {
+    r: 0x11,
+    g: 0x11,
+    b: 0x22,
+    a: 0xee,
+};
+const LEVEL_COLOR: Color = Color {
+    r: 0xff,
+    g: 0xdd,
+    b: 0x44,
+    a: 0xff,
+};
+
+/// A scrollable list of the best runs on every level, opened from the pause menu.
+///
+/// Each level's name is followed by its entries, best first. Up and down scroll, and cancel goes
+/// back to the pause menu.
+pub struct LeaderboardScreen {
+    /// The lines to show, and whether each one is a level name, as they were when the screen
+    /// opened.
+    lines: Vec<(String, bool)>,
+    /// How many lines the view is scrolled down from the top.
+    scroll: usize,
+}
+
+impl LeaderboardScreen {
+    pub fn new(leaderboard: &dyn LeaderboardBackend) -> LeaderboardScreen {
+        let mut lines = Vec::new();
+        for level in leaderboard.levels() {
+            lines.push((level.clone(), true));
+            for (i, entry) in leaderboard.entries(&level).iter().enumerate() {
+                let mut line = format!("{:>2}. {} {}", i + 1, entry.time(), entry.name);
+                if entry.score > 0 {
+                    line = format!("{} {}", line, entry.score);
+                }
+                lines.push((line, false));
+            }
+        }
+        LeaderboardScreen { lines, scroll: 0 }
+    }
+
+    fn scroll_by(&mut self, delta: i32) {
+        let max = self.lines.len().saturating_sub(1) as i32;
+        self.scroll = (self.scroll as i32 + delta).clamp(0, max) as usize;
+    }
+}
+
+impl Scene for LeaderboardScreen {
+    fn update(
+        &mut self,
+        _context: &RenderContext,
+        inputs: &InputSnapshot,
+        _sounds: &mut SoundManager,
+    ) -> SceneResult {
+        if inputs.cancel_clicked {
+            return SceneResult::Pop;
+        }
+        if inputs.menu_up_clicked {
+            self.scroll_by(-1);
+        }
+        if inputs.menu_down_clicked {
+            self.scroll_by(1);
+        }
+        SceneResult::Continue
+    }
+
+    fn name(&self) -> &str {
+        "leaderboard"
+    }
+
+    fn postprocess(&self) -> PostprocessProfile {
+        PostprocessProfile::CLEAN
+    }
+
+    fn draw(&self, context: &mut RenderContext, font: &Font, _previous: Option<&dyn Scene>) {
+        context.fill_rect(context.ui_area(), RenderLayer::Hud, BACKGROUND_COLOR);
+        let area = context.safe_area();
+        font.draw_string(
+            context,
+            RenderLayer::Hud,
+            Point::new(area.x + MARGIN, area.y + MARGIN),
+            "leaderboard",
+        );
+
+        let mut y = area.y + MARGIN * 2 + font.char_height;
+        if self.lines.is_empty() {
+            font.draw_string(
+                context,
+                RenderLayer::Hud,
+                Point::new(area.x + MARGIN, y),
+                "no runs yet",
+            );
+            return;
+        }
+
+        for (line, is_level) in self.lines.iter().skip(self.scroll) {
+            if y + font.char_height > area.bottom() - MARGIN {
+                break;
+            }
+            let (x, color) = if *is_level {
+                (area.x + MARGIN, LEVEL_COLOR)
+            } else {
+                (area.x + MARGIN * 2, Color::WHITE)
+            };
+            context.set_tint(RenderLayer::Hud, color);
+            font.draw_string(context, RenderLayer::Hud, Point::new(x, y), line);
+            y += font.char_height;
+        }
+        context.set_tint(RenderLayer::Hud, Color::WHITE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::leaderboard::{LeaderboardEntry, LocalLeaderboard};
+
+    #[test]
+    fn scroll() {
+        let mut leaderboard = LocalLeaderboard::new();
+        for frames in [120, 60] {
+            let entry = LeaderboardEntry {
+                name: "someone".to_string(),
+                frames,
+                score: 0,
+            };
+            leaderboard.submit("sector 7", entry);
+        }
+        let mut screen = LeaderboardScreen::new(&leaderboard);
+        assert_eq!(screen.lines.len(), 3);
+        assert_eq!(screen.lines[0], ("sector 7".to_string(), true));
+        assert_eq!(screen.lines[1].0, " 1. 0:01.00 someone");
+
+        let context = RenderContext::new(640, 400, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+        let down = InputSnapshot {
+            menu_down_clicked: true,
+            ..Default::default()
+        };
+        for _ in 0..5 {
+            screen.update(&context, &down, &mut sounds);
+        }
+        assert_eq!(screen.scroll, 2);
+
+        let cancel = InputSnapshot {
+            cancel_clicked: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            screen.update(&context, &cancel, &mut sounds),
+            SceneResult::Pop
+        ));
+    }
+}
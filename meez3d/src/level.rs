@@ -1,97 +1,931 @@
-use crate::constants::{RENDER_HEIGHT, RENDER_WIDTH};
+use crate::ability::{Abilities, Ability, Dash, Pickup, PickupKind, DASH_SPEED};
+use crate::aimassist::AimAssist;
+use crate::billboard::{place, Billboard, DepthBuffer};
+use crate::breadcrumbs::{Breadcrumbs, RouteGuide, BREADCRUMBS_CVAR, ROUTE_GUIDE_CVAR};
+use crate::constants::{FRAME_RATE, RENDER_HEIGHT, RENDER_WIDTH};
+use crate::cvars::Cvars;
+use crate::debugflags::DebugFlags;
+use crate::ending::RunSummary;
+use crate::enemy::Enemy;
+use crate::enemystats::{Difficulty, EnemyStatsTable, DEFAULT_ENEMY_TYPE, DIFFICULTY_CVAR};
+use crate::explosion::Explosion;
+use crate::faction::{Faction, Hostility};
 use crate::filemanager::FileManager;
+use crate::framearena::FrameArena;
+use crate::gameclock::{AmbientLight, GameClock};
 use crate::geometry::{Point, Rect};
+use crate::ghost::{ghost_path, movement_inputs, Ghost, GhostRun, GHOST_CVAR};
+use crate::hearing::Noise;
+use crate::heatmap::{heat_color, Heatmap, DEATH_COLOR, HEATMAP_CVAR};
 use crate::imagemanager::ImageLoader;
-use crate::inputmanager::InputSnapshot;
+use crate::inputmanager::{InputRecorder, InputSnapshot};
+use crate::inventory::{Inventory, ItemAction, ItemTable, ItemTarget, ItemUse};
+use crate::leaderboard::{
+    LeaderboardBackend, LeaderboardEntry, DEFAULT_PLAYER_NAME, PLAYER_NAME_CVAR,
+};
+use crate::lightmap::{Lightmap, DEFAULT_LIGHTMAP_AMBIENT};
+use crate::messagelog::{MessageKind, MessageLog};
+use crate::narration::Narrator;
+use crate::pathfinder::find_path;
+use crate::raycaster::{Ray, Raycaster};
+use crate::rendercontext::{PostprocessProfile, RenderLayer};
+use crate::replay::StateHash;
+use crate::savestate::{CheckpointState, LevelState, PlayerState};
+use crate::scene::EntityInfo;
 use crate::scene::Scene;
 use crate::scene::SceneResult;
-use crate::sprite::Sprite;
+use crate::scheduler::Scheduler;
+#[cfg(feature = "rhai")]
+use crate::script::{DoorChange, Script, ScriptEnemy};
+use crate::smallintset::BitSet;
+use crate::soundmanager::{Listener, Sound};
+use crate::spawner::Spawner;
+use crate::sprite::{Sprite, SpriteSheet};
+use crate::streaming::{Chunk, ChunkCoord, ChunkSource, StreamingGrid};
+use crate::surface::{Surface, SurfaceSettings};
+use crate::tilemap::{MapObject, TileMap, TileMapProperties};
+use crate::tileset::TileProperties;
+use crate::titlecard::TitleCard;
+use crate::tutorial::Tutorial;
 use crate::utils::Color;
+use crate::weather::Weather;
+use crate::worldtext::{draw_world_text, WorldText};
+use crate::Font;
 use crate::RenderContext;
 use crate::SoundManager;
-use crate::{Font, FRAME_RATE};
-use anyhow::Result;
-use rand::random;
+use anyhow::{bail, Result};
+use log::{error, info};
+use rand::rngs::StdRng;
+use rand::{random, Rng, SeedableRng};
 use std::f32::consts::FRAC_PI_2;
 use std::f32::consts::PI;
 use std::f32::consts::TAU;
+use std::hash::Hasher;
+use std::mem;
 use std::path::Path;
 use std::str::FromStr;
+use std::time::Duration;
 
 const TOLERANCE: f32 = 0.0001;
 const PLAYER_SIZE: f32 = 0.8;
 const MOVE_SPEED: f32 = 0.05;
 const TURN_SPEED: f32 = 0.02;
+/// How far the player turns for each unit the mouse moves, at a sensitivity of 1.
+const MOUSE_TURN_SPEED: f32 = 0.0025;
+const MAX_MOUSE_SENSITIVITY: f32 = 10.0;
+#[cfg(feature = "rhai")]
+const LEVEL_SCRIPT_PATH: &str = "assets/scripts/level.rhai";
+#[cfg(feature = "rhai")]
+const MESSAGE_FRAMES: u32 = 3 * FRAME_RATE;
+const MAP_WIDTH: usize = 32;
+const MAP_HEIGHT: usize = 32;
+/// The map is loaded in chunks this many tiles across, as the player gets near them.
+const CHUNK_SIZE: usize = 16;
+/// How many chunks in each direction from the player stay loaded.
+const STREAM_RADIUS: usize = 2;
+/// How often the heatmap is saved while the level is being played, in frames.
+const HEATMAP_SAVE_FRAMES: u64 = 30 * FRAME_RATE as u64;
+/// How often the enemy stats and items files are checked for changes, in frames.
+const DATA_RELOAD_FRAMES: u64 = FRAME_RATE as u64;
+/// How opaque the heatmap is over the automap.
+const HEATMAP_ALPHA: u8 = 0xc0;
+/// How long background work, like finding the route guide's route, can take each frame.
+const BACKGROUND_BUDGET: Duration = Duration::from_millis(2);
+/// The Tiled map to play, like "assets/levels/corridors.tmx", or empty for a random one.
+pub const MAP_CVAR: &str = "map";
+/// Whether the mouse is captured to turn the player while playing, "true" or "false".
+pub const MOUSE_LOOK_CVAR: &str = "mouse_look";
+/// How fast the mouse turns the player, where 1 is the default and 2 is twice as fast.
+pub const MOUSE_SENSITIVITY_CVAR: &str = "mouse_sensitivity";
+/// The random map doesn't have properties to name it, so its title card always says this.
+const LEVEL_TITLE: &str = "sector 7";
+const LEVEL_SUBTITLE: &str = "deep space";
+/// The names of the tutorial prompts for things every level has, from assets/tutorial.json.
+pub const MOVE_PROMPT: &str = "move";
+const FIRE_PROMPT: &str = "fire";
+const CHECKPOINT_PROMPT: &str = "checkpoint";
+const DASH_PROMPT: &str = "dash";
+const INVENTORY_PROMPT: &str = "inventory";
+/// How close the player has to be to something to use items on it, in tiles.
+const TARGET_REACH: f32 = 1.5;
+/// How far off to either side of where the player is facing something can be for them to use
+/// items on it, in radians.
+const TARGET_FOCUS_ANGLE: f32 = 0.5;
+/// Where messages from the level's script are shown, on the HUD.
+const MESSAGE_TEXT_Y: i32 = 250;
+/// Where the items the target the player is facing accepts are listed, on the HUD.
+const TARGET_TEXT_Y: i32 = 330;
+/// How many mirrors and portals a ray can pass through before it's drawn as a plain wall.
+const MAX_RAY_BOUNCES: u32 = 4;
+/// How much light a mirror reflects, so it's a little darker than what it shows.
+const MIRROR_REFLECTANCE: f32 = 0.8;
+const MIRROR_COLOR: Color = Color {
+    r: 0xa0,
+    g: 0xc0,
+    b: 0xe0,
+    a: 0xff,
+};
+const PORTAL_COLOR: Color = Color {
+    r: 0xa0,
+    g: 0x40,
+    b: 0xff,
+    a: 0xff,
+};
+const STAIRS_COLOR: Color = Color {
+    r: 0xc0,
+    g: 0x80,
+    b: 0x30,
+    a: 0xff,
+};
+const DOOR_COLOR: Color = Color {
+    r: 0x80,
+    g: 0x60,
+    b: 0x40,
+    a: 0xff,
+};
+/// How many pairs of linked portals each floor of the random map has.
+const PORTAL_PAIRS: usize = 3;
+/// How many floors the random map has, connected by stairs.
+const FLOORS: usize = 2;
+/// How long the camera takes to move to a new floor.
+const FLOOR_TRANSITION_FRAMES: u32 = FRAME_RATE / 3;
+/// The width and height of each texture in the wall sheet.
+const WALL_TEXTURE_SIZE: i32 = 64;
+/// Roughly the color of each texture in the wall sheet, for the minimap.
+const WALL_TEXTURE_COLORS: [Color; 4] = [
+    // Brick.
+    Color {
+        r: 0xa8,
+        g: 0x3c,
+        b: 0x2d,
+        a: 0xff,
+    },
+    // Stone.
+    Color {
+        r: 0x80,
+        g: 0x80,
+        b: 0x88,
+        a: 0xff,
+    },
+    // Metal.
+    Color {
+        r: 0x60,
+        g: 0x6c,
+        b: 0x84,
+        a: 0xff,
+    },
+    // Wood.
+    Color {
+        r: 0x90,
+        g: 0x5c,
+        b: 0x2d,
+        a: 0xff,
+    },
+];
+const STONE_TEXTURE: u32 = 1;
+/// The random map is a space station, so it has metal floors.
+const METAL_TEXTURE: u32 = 2;
+/// How big each cell of a cast floor or ceiling is on screen. Every pixel in a cell is the same
+/// texel, which keeps the number of sprites drawn reasonable.
+const SURFACE_CELL_WIDTH: i32 = 16;
+const SURFACE_CELL_HEIGHT: i32 = 8;
+/// How far away, in tiles, cast floors and ceilings fade to their darkest.
+const SURFACE_FADE_DISTANCE: f32 = 12.0;
+/// How light cast floors and ceilings are, up close and at the fade distance.
+const SURFACE_LIGHT: (f32, f32) = (0.8, 0.3);
+const BARREL_COLOR: Color = Color {
+    r: 0xd0,
+    g: 0x30,
+    b: 0x20,
+    a: 0xff,
+};
+const PLAYER_MAX_HEALTH: f32 = 100.0;
+/// How fast the player wades through deep water, as a fraction of their usual speed.
+const WATER_SPEED: f32 = 0.5;
+const WATER_COLOR: Color = Color {
+    r: 0x30,
+    g: 0x70,
+    b: 0xd0,
+    a: 0xff,
+};
+/// How long the player can stay underwater before they start drowning, in seconds.
+const MAX_OXYGEN: f32 = 10.0;
+/// How many seconds of air the player gets back for each second out of the water.
+const OXYGEN_REFILL_RATE: f32 = 4.0;
+/// How much health the player loses each second they're underwater with no air left.
+const DROWNING_DAMAGE: f32 = 20.0;
+/// How big the HUD's meters, like the air and dash meters, are, in HUD units.
+const METER_WIDTH: i32 = 100;
+const METER_HEIGHT: i32 = 6;
+const DASH_COLOR: Color = Color {
+    r: 0xff,
+    g: 0xc0,
+    b: 0x40,
+    a: 0xff,
+};
+const METER_BACKGROUND: Color = Color {
+    r: 0x20,
+    g: 0x20,
+    b: 0x40,
+    a: 0xc0,
+};
+/// How far a rocket moves each frame, in tiles.
+const ROCKET_SPEED: f32 = 0.25;
+/// How far a rocket flies before it goes off on its own, in tiles.
+const ROCKET_RANGE: f32 = 20.0;
+/// How far a rocket flies before it can hit anyone, so it doesn't hit whoever fired it, in tiles.
+const ROCKET_ARMING_DISTANCE: f32 = 0.5;
+/// How close a rocket has to get to someone to hit them, in tiles.
+const ROCKET_HIT_RADIUS: f32 = 0.3;
+const ROCKET_BLAST_RADIUS: f32 = 2.0;
+const ROCKET_DAMAGE: f32 = 40.0;
+const BARREL_BLAST_RADIUS: f32 = 3.0;
+const BARREL_DAMAGE: f32 = 60.0;
+/// How far the sound of a rocket launch carries, in tiles.
+const ROCKET_NOISE_RADIUS: f32 = 8.0;
+/// How far the sound of an explosion carries, in blast radii.
+const EXPLOSION_NOISE_RADII: f32 = 6.0;
+const BREADCRUMB_COLOR: Color = Color {
+    r: 0xff,
+    g: 0xe0,
+    b: 0x40,
+    a: 0xe0,
+};
+const GHOST_COLOR: Color = Color {
+    r: 0xc0,
+    g: 0xe0,
+    b: 0xff,
+    a: 0x60,
+};
+/// How tall the ghost is drawn, in tiles, which is a little shorter than the walls.
+const GHOST_HEIGHT: f32 = 0.75;
+/// How wide the ghost is drawn, as a fraction of its height.
+const GHOST_WIDTH: f32 = 0.35;
+const ROUTE_COLOR: Color = Color {
+    r: 0x40,
+    g: 0xff,
+    b: 0xff,
+    a: 0xff,
+};
+/// How bright the screen gets from an explosion right next to the player.
+const EXPLOSION_FLASH_COLOR: Color = Color {
+    r: 0xff,
+    g: 0xd0,
+    b: 0x90,
+    a: 0xa0,
+};
 
+#[derive(Clone, Copy)]
 enum Tile {
     Empty,
     Solid(Color),
+    /// A wall drawn with the texture at this index in the wall sheet.
+    Textured(u32),
+    Checkpoint,
+    /// Blocks the player like a wall, but reflects rays.
+    Mirror,
+    /// Rays and the player that enter this tile come out of the linked one, which links back.
+    Portal {
+        row: usize,
+        column: usize,
+    },
+    /// Takes the player to the same tile on another floor, which should have stairs back.
+    /// Rays stop here, so it looks like a stairwell in the wall.
+    Stairs {
+        floor: usize,
+    },
+    /// Explodes when it's caught in another explosion.
+    Barrel,
+    /// A wall that explosions knock down.
+    Cracked(Color),
+    /// Deep water, which the player wades through slowly, and has to hold their breath in.
+    Water,
+    /// A wall the level script can open and close.
+    Door,
+}
+
+impl Tile {
+    fn is_solid(&self) -> bool {
+        matches!(
+            self,
+            Tile::Solid(_)
+                | Tile::Textured(_)
+                | Tile::Mirror
+                | Tile::Barrel
+                | Tile::Cracked(_)
+                | Tile::Door
+        )
+    }
+
+    /// Whether an explosion can destroy this tile.
+    fn is_destructible(&self) -> bool {
+        matches!(self, Tile::Barrel | Tile::Cracked(_))
+    }
+
+    /// Whether a ray stops in this tile, at least to be reflected or sent through a portal.
+    fn stops_ray(&self) -> bool {
+        !matches!(self, Tile::Empty | Tile::Checkpoint | Tile::Water)
+    }
+
+    /// The color a ray that ends in this tile is drawn with.
+    fn wall_color(&self) -> Color {
+        match self {
+            Tile::Solid(color) | Tile::Cracked(color) => *color,
+            Tile::Textured(index) => WALL_TEXTURE_COLORS
+                .get(*index as usize)
+                .copied()
+                .unwrap_or(Color::WHITE),
+            Tile::Mirror => MIRROR_COLOR,
+            Tile::Barrel => BARREL_COLOR,
+            Tile::Portal { .. } => PORTAL_COLOR,
+            Tile::Stairs { .. } => STAIRS_COLOR,
+            Tile::Door => DOOR_COLOR,
+            Tile::Empty | Tile::Checkpoint | Tile::Water => Color::WHITE,
+        }
+    }
 }
 
+/// Tiles that haven't been loaded yet act like walls, so the player can't walk into them.
+static UNLOADED_TILE: Tile = Tile::Solid(Color {
+    r: 0,
+    g: 0,
+    b: 0,
+    a: 255,
+});
+
+/// What's left where an explosion destroyed a tile.
+static DESTROYED_TILE: Tile = Tile::Empty;
+
+/// What a door is while it's open.
+static OPEN_DOOR_TILE: Tile = Tile::Empty;
+
 /// A tile-based map.
 ///
 /// Top-left is (0, 0).
-/// Indexing is (column, row).
+/// Indexing is (row, column).
 ///
 struct Map {
-    tiles: Vec<Vec<Tile>>,
+    grid: StreamingGrid<Tile, ()>,
     width: usize,
     height: usize,
+    /// Every cell any ray has passed through, indexed by row * width + column.
+    visited: BitSet,
+    /// Every cell an explosion has destroyed, indexed like visited, so they stay destroyed after
+    /// their chunk is reloaded.
+    destroyed: BitSet,
+    /// Every door the level script has opened, indexed like visited, so they stay open after
+    /// their chunk is reloaded.
+    opened: BitSet,
+    /// How lit each cell is by the map's static lights, if it has any. It's baked when the level
+    /// loads, so walls that are destroyed later don't change it.
+    lightmap: Option<Lightmap>,
+    /// Whether any cell is water, which means the floor has to be cast to show it.
+    has_water: bool,
+}
+
+impl Map {
+    fn tile(&self, row: usize, column: usize) -> &Tile {
+        if row < self.height && column < self.width {
+            let index = row * self.width + column;
+            if self.destroyed.contains(index) {
+                return &DESTROYED_TILE;
+            }
+            if self.opened.contains(index) {
+                return &OPEN_DOOR_TILE;
+            }
+        }
+        self.grid.get(row, column).unwrap_or(&UNLOADED_TILE)
+    }
+
+    /// Whether a point, in tiles, is in deep water.
+    fn is_water(&self, x: f32, y: f32) -> bool {
+        x >= 0.0 && y >= 0.0 && matches!(self.tile(y as usize, x as usize), Tile::Water)
+    }
+
+    #[allow(clippy::collapsible_if)]
+    fn can_move_to(&self, x: f32, y: f32) -> bool {
+        let lower_bound = PLAYER_SIZE / 2.0;
+        let upper_bound = 1.0 - (PLAYER_SIZE / 2.0);
+
+        let row = y as usize;
+        let col = x as usize;
+        let x_frac = x - col as f32;
+        let y_frac = y - row as f32;
+        if self.tile(row, col).is_solid() {
+            return false;
+        }
+        if x_frac < lower_bound {
+            if col == 0 || self.tile(row, col - 1).is_solid() {
+                return false;
+            }
+        }
+        if y_frac < lower_bound {
+            if row == 0 || self.tile(row - 1, col).is_solid() {
+                return false;
+            }
+        }
+        if x_frac > upper_bound {
+            if col >= self.width - 1 || self.tile(row, col + 1).is_solid() {
+                return false;
+            }
+        }
+        if y_frac > upper_bound {
+            if row >= self.height - 1 || self.tile(row + 1, col).is_solid() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Where a body ends up after a frame of the given movement inputs, sliding along walls.
+    ///
+    /// With noclip, the body goes straight through walls, but still stays inside the map.
+    fn move_body(
+        &self,
+        body: PlayerState,
+        inputs: &InputSnapshot,
+        mouse_sensitivity: f32,
+        noclip: bool,
+    ) -> PlayerState {
+        let look = MOUSE_TURN_SPEED * mouse_sensitivity * inputs.mouse_delta.x as f32;
+        let mut angle = body.angle + TURN_SPEED * inputs.turn() + look;
+        while angle >= TAU {
+            angle -= TAU;
+        }
+        while angle < 0.0 {
+            angle += TAU;
+        }
+
+        let x_component = angle.cos();
+        let y_component = angle.sin();
+        // Sticks move at any speed up to full, in proportion to how far they're pushed.
+        let (forward, strafe) = (inputs.forward(), inputs.strafe());
+        let speed = if self.is_water(body.x, body.y) {
+            MOVE_SPEED * WATER_SPEED
+        } else {
+            MOVE_SPEED
+        };
+        let dx = speed * (forward * x_component - strafe * y_component);
+        let dy = speed * (forward * y_component + strafe * x_component);
+        let (mut x, mut y) = (body.x, body.y);
+        if noclip {
+            let margin = PLAYER_SIZE / 2.0;
+            x = (x + dx).clamp(margin, self.width as f32 - margin);
+            y = (y + dy).clamp(margin, self.height as f32 - margin);
+            return PlayerState { x, y, angle };
+        }
+        if self.can_move_to(x, y + dy) {
+            y += dy;
+        }
+        if self.can_move_to(x + dx, y) {
+            x += dx;
+        }
+        PlayerState { x, y, angle }
+    }
+
+    /// Where a body ends up after a frame of dashing the way it's facing. It moves in steps no
+    /// bigger than a frame of walking, sliding along walls, so it can't skip through one.
+    fn dash_body(&self, body: PlayerState) -> PlayerState {
+        let steps = (DASH_SPEED / MOVE_SPEED).ceil();
+        let step = DASH_SPEED / steps;
+        let (dx, dy) = (step * body.angle.cos(), step * body.angle.sin());
+        let (mut x, mut y) = (body.x, body.y);
+        for _ in 0..steps as u32 {
+            if self.can_move_to(x, y + dy) {
+                y += dy;
+            }
+            if self.can_move_to(x + dx, y) {
+                x += dx;
+            }
+        }
+        PlayerState { x, y, ..body }
+    }
+
+    fn destroy(&mut self, row: usize, column: usize) {
+        self.destroyed.insert(row * self.width + column);
+        if let Some(tile) = self.grid.get_mut(row, column) {
+            *tile = Tile::Empty;
+        }
+    }
+
+    /// Opens or closes the door in a cell. Returns false if there's no door there, or it isn't
+    /// loaded.
+    #[cfg(feature = "rhai")]
+    fn set_door(&mut self, row: usize, column: usize, open: bool) -> bool {
+        if !matches!(self.grid.get(row, column), Some(Tile::Door)) {
+            return false;
+        }
+        let index = row * self.width + column;
+        if open {
+            self.opened.insert(index);
+        } else {
+            self.opened.remove(index);
+        }
+        true
+    }
 }
 
-fn uniform_random(min: f32, max: f32) -> f32 {
+fn uniform_random(rng: &mut impl Rng, min: f32, max: f32) -> f32 {
     let range = max - min;
-    min + random::<f32>() * range
+    min + rng.gen::<f32>() * range
+}
+
+fn create_random_tile(rng: &mut impl Rng) -> Tile {
+    if rng.gen::<f32>() < 0.025 {
+        Tile::Textured(rng.gen_range(0..WALL_TEXTURE_COLORS.len() as u32))
+    } else if rng.gen::<f32>() < 0.005 {
+        let r = uniform_random(rng, 0.0, 256.0) as u8;
+        let g = uniform_random(rng, 0.0, 256.0) as u8;
+        let b = uniform_random(rng, 0.0, 256.0) as u8;
+        let a = 255;
+        let color = Color { r, g, b, a };
+        Tile::Solid(color)
+    } else if rng.gen::<f32>() < 0.005 {
+        Tile::Checkpoint
+    } else if rng.gen::<f32>() < 0.005 {
+        Tile::Mirror
+    } else if rng.gen::<f32>() < 0.005 {
+        Tile::Barrel
+    } else if rng.gen::<f32>() < 0.01 {
+        Tile::Cracked(Color::from_str("#806050").unwrap())
+    } else {
+        Tile::Empty
+    }
+}
+
+/// Generates a random map with a border, one chunk at a time.
+///
+/// Each chunk is seeded by its position, so it comes out the same every time it's reloaded.
+struct RandomMapSource {
+    seed: u64,
+    width: usize,
+    height: usize,
+    /// Tiles at particular (row, column)s, like portals and stairs, that aren't random.
+    fixed: Vec<((usize, usize), Tile)>,
+}
+
+impl RandomMapSource {
+    fn fixed_tile(&self, row: usize, column: usize) -> Option<Tile> {
+        self.fixed
+            .iter()
+            .find(|(cell, _)| *cell == (row, column))
+            .map(|(_, tile)| *tile)
+    }
 }
 
-fn create_random_row(width: usize, border_color: Color) -> Vec<Tile> {
-    let mut row = Vec::new();
-    row.push(Tile::Solid(border_color));
-    row.extend(
-        std::iter::repeat_with(|| {
-            if random::<f32>() < 0.025 {
-                let r = uniform_random(0.0, 256.0) as u8;
-                let g = uniform_random(0.0, 256.0) as u8;
-                let b = uniform_random(0.0, 256.0) as u8;
-                let a = 255;
-                let color = Color { r, g, b, a };
-                Tile::Solid(color)
+impl ChunkSource<Tile, ()> for RandomMapSource {
+    fn load_chunk(&mut self, coord: ChunkCoord, chunk_size: usize) -> Result<Chunk<Tile, ()>> {
+        let mut rng =
+            StdRng::seed_from_u64(self.seed ^ ((coord.row as u64) << 32) ^ coord.column as u64);
+        let mut tiles = Vec::with_capacity(chunk_size * chunk_size);
+        for i in 0..chunk_size * chunk_size {
+            let row = coord.row * chunk_size + i / chunk_size;
+            let column = coord.column * chunk_size + i % chunk_size;
+            let border =
+                row == 0 || column == 0 || row + 1 >= self.height || column + 1 >= self.width;
+            tiles.push(if border {
+                Tile::Textured(STONE_TEXTURE)
+            } else if let Some(tile) = self.fixed_tile(row, column) {
+                tile
             } else {
-                Tile::Empty
-            }
+                create_random_tile(&mut rng)
+            });
+        }
+        Ok(Chunk {
+            tiles,
+            entities: Vec::new(),
+        })
+    }
+}
+
+/// A map whose tiles are all known up front, like one loaded from a file.
+struct FixedMapSource {
+    rows: Vec<Vec<Tile>>,
+}
+
+impl ChunkSource<Tile, ()> for FixedMapSource {
+    fn load_chunk(&mut self, coord: ChunkCoord, chunk_size: usize) -> Result<Chunk<Tile, ()>> {
+        let mut tiles = Vec::with_capacity(chunk_size * chunk_size);
+        for i in 0..chunk_size * chunk_size {
+            let row = coord.row * chunk_size + i / chunk_size;
+            let column = coord.column * chunk_size + i % chunk_size;
+            let tile = self.rows.get(row).and_then(|tiles| tiles.get(column));
+            tiles.push(tile.copied().unwrap_or(Tile::Empty));
+        }
+        Ok(Chunk {
+            tiles,
+            entities: Vec::new(),
         })
-        .take(width - 2),
-    );
-    row.push(Tile::Solid(border_color));
-    row
+    }
 }
 
-fn create_random_map(width: usize, height: usize) -> Map {
-    let border_color = Color::from_str("#ffffff").unwrap();
-    let full_row = || {
-        std::iter::repeat_with(|| Tile::Solid(border_color))
-            .take(width)
-            .collect()
+impl Map {
+    /// A map with the given rows of tiles, which should all be the same length.
+    fn from_rows(rows: Vec<Vec<Tile>>) -> Result<Map> {
+        let height = rows.len();
+        let width = rows.first().map(Vec::len).unwrap_or(0);
+        if width == 0 {
+            bail!("invalid map: no tiles");
+        }
+        let has_water = rows
+            .iter()
+            .flatten()
+            .any(|tile| matches!(tile, Tile::Water));
+        let source = FixedMapSource { rows };
+        Ok(Map {
+            grid: StreamingGrid::new(width, height, CHUNK_SIZE, STREAM_RADIUS, Box::new(source))?,
+            width,
+            height,
+            visited: BitSet::new(),
+            destroyed: BitSet::new(),
+            opened: BitSet::new(),
+            lightmap: None,
+            has_water,
+        })
+    }
+}
+
+/// The raycaster tile for a tile in a Tiled map, from its "solid", "checkpoint", "water",
+/// "door", "texture", and "color" properties. Tiles without properties are plain white walls.
+fn tile_from_properties(properties: Option<&TileProperties>) -> Result<Tile> {
+    let Some(properties) = properties else {
+        return Ok(Tile::Solid(Color::WHITE));
     };
+    if properties.raw.get_bool("checkpoint")?.unwrap_or(false) {
+        return Ok(Tile::Checkpoint);
+    }
+    if properties.raw.get_bool("water")?.unwrap_or(false) {
+        return Ok(Tile::Water);
+    }
+    if properties.raw.get_bool("door")?.unwrap_or(false) {
+        return Ok(Tile::Door);
+    }
+    if !properties.solid {
+        return Ok(Tile::Empty);
+    }
+    if let Some(texture) = properties.raw.get_int("texture")? {
+        if texture < 0 || texture as usize >= WALL_TEXTURE_COLORS.len() {
+            bail!("invalid wall texture: {}", texture);
+        }
+        return Ok(Tile::Textured(texture as u32));
+    }
+    if let Some(color) = properties.raw.get_string("color")? {
+        return Ok(Tile::Solid(color.parse()?));
+    }
+    Ok(Tile::Solid(Color::WHITE))
+}
+
+/// Where the player starts in a Tiled map, as (row, column): the object with a "spawn"
+/// property, or else the first open tile.
+fn tilemap_spawn(tilemap: &TileMap, rows: &[Vec<Tile>]) -> Result<(usize, usize)> {
+    if let Some(spawn) = tilemap.objects.iter().find(|obj| obj.properties.spawn) {
+        let x = (spawn.position.x + spawn.position.w / 2) / tilemap.tilewidth;
+        let y = (spawn.position.y + spawn.position.h / 2) / tilemap.tileheight;
+        if x < 0 || y < 0 || y >= tilemap.height || x >= tilemap.width {
+            bail!("invalid spawn point: {}, {}", x, y);
+        }
+        return Ok((y as usize, x as usize));
+    }
+    for (row, tiles) in rows.iter().enumerate() {
+        if let Some(column) = tiles.iter().position(|tile| matches!(tile, Tile::Empty)) {
+            return Ok((row, column));
+        }
+    }
+    bail!("invalid map: nowhere to start");
+}
+
+/// Generates the floors of a random map, with stairs between each floor and the next.
+fn create_random_floors(width: usize, height: usize, seed: u64) -> Result<Vec<Map>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let cell = |rng: &mut StdRng| (rng.gen_range(1..height - 1), rng.gen_range(1..width - 1));
+    // Each flight of stairs is in the same place on both of the floors it connects.
+    let stairs: Vec<(usize, usize)> = (1..FLOORS).map(|_| cell(&mut rng)).collect();
+    (0..FLOORS)
+        .map(|floor| {
+            let mut fixed = Vec::new();
+            if floor > 0 {
+                fixed.push((stairs[floor - 1], Tile::Stairs { floor: floor - 1 }));
+            }
+            if floor + 1 < FLOORS {
+                fixed.push((stairs[floor], Tile::Stairs { floor: floor + 1 }));
+            }
+            for _ in 0..PORTAL_PAIRS {
+                let (a, b) = (cell(&mut rng), cell(&mut rng));
+                fixed.push((
+                    a,
+                    Tile::Portal {
+                        row: b.0,
+                        column: b.1,
+                    },
+                ));
+                fixed.push((
+                    b,
+                    Tile::Portal {
+                        row: a.0,
+                        column: a.1,
+                    },
+                ));
+            }
+            let source = RandomMapSource {
+                seed: rng.gen(),
+                width,
+                height,
+                fixed,
+            };
+            Ok(Map {
+                grid: StreamingGrid::new(
+                    width,
+                    height,
+                    CHUNK_SIZE,
+                    STREAM_RADIUS,
+                    Box::new(source),
+                )?,
+                width,
+                height,
+                visited: BitSet::new(),
+                destroyed: BitSet::new(),
+                opened: BitSet::new(),
+                lightmap: None,
+                has_water: false,
+            })
+        })
+        .collect()
+}
 
-    let mut map = Vec::new();
-    map.push(full_row());
-    map.extend(std::iter::repeat_with(|| create_random_row(width, border_color)).take(height - 2));
-    map.push(full_row());
+/// Loads the optional script for the level, which handles events like "start" and "checkpoint".
+#[cfg(feature = "rhai")]
+fn load_script(files: &FileManager) -> Option<Script> {
+    match Script::from_file(Path::new(LEVEL_SCRIPT_PATH), files) {
+        Ok(script) => Some(script),
+        Err(e) => {
+            info!("not using a level script: {}", e);
+            None
+        }
+    }
+}
 
-    Map {
-        tiles: map,
-        width,
-        height,
+/// Loads the best previous run of the level with the given title, if there is one.
+fn load_ghost(files: &FileManager, title: &str) -> Option<Ghost> {
+    let path = ghost_path(title);
+    match files
+        .read_user_data(&path)
+        .and_then(|text| GhostRun::from_text(&text))
+    {
+        Ok(run) => Some(Ghost::new(run)),
+        Err(e) => {
+            info!("not using a ghost: {}", e);
+            None
+        }
     }
 }
 
 pub struct Level {
-    map: Map,
+    /// Every floor of the level, from the bottom up. Only the current one is streamed in.
+    floors: Vec<Map>,
+    floor: usize,
+    /// How many more frames the camera is moving to a new floor, and whether it's going up.
+    floor_transition: Option<(u32, bool)>,
     player_x: f32,
     player_y: f32,
     player_angle: f32,
+    checkpoint: Option<CheckpointState>,
     background: Sprite,
+    /// The textures for textured walls, or None if they couldn't be loaded, in which case
+    /// they're drawn flat.
+    walls: Option<SpriteSheet>,
+    #[cfg(feature = "rhai")]
+    script: Option<Script>,
+    started: bool,
+    /// A message to show on the HUD, and how many more frames to show it.
+    message: Option<(String, u32)>,
+    /// The level's name, which its ghost and leaderboard entries are saved under.
+    title: String,
+    /// The level's name, shown as it starts.
+    title_card: Option<TitleCard>,
+    /// The time in the level, which only advances while it's being played.
+    clock: GameClock,
+    /// How the time of day tints the level, if it does.
+    ambient_light: Option<AmbientLight>,
+    /// Rain or snow over the view, and lightning.
+    weather: Option<Weather>,
+    /// What the floor and ceiling look like.
+    surfaces: SurfaceSettings,
+    /// The sprites standing in the level, on every floor.
+    billboards: Vec<Billboard>,
+    /// Signs and other words standing in the level, on every floor.
+    world_texts: Vec<WorldText>,
+    /// Abilities and items lying around the level that the player hasn't picked up yet.
+    pickups: Vec<Pickup>,
+    /// The enemies hunting the player, which are drawn with the billboards.
+    enemies: Vec<Enemy>,
+    /// Noises on the player's floor since the enemies last listened.
+    pending_noises: Vec<Noise>,
+    /// Sounds to play where they happened, as (sound, x, y), once the player has moved this frame.
+    pending_sounds: Vec<(Sound, f32, f32)>,
+    spawners: Vec<Spawner>,
+    /// Messages for the message log, since the last time the stage manager collected them.
+    pending_messages: Vec<(MessageKind, String)>,
+    /// Text that's appeared since the last time the stage manager collected it for narration.
+    pending_narration: Vec<String>,
+    /// The names of the tutorial prompts triggered since the last time the stage manager collected them.
+    pending_prompts: Vec<String>,
+    /// Parts of the level that trigger a tutorial prompt the first time the player walks in.
+    prompt_areas: Vec<MapArea>,
+    /// Parts of the level that fire a script event each time the player walks in, and whether
+    /// the player is in them.
+    trigger_areas: Vec<(MapArea, bool)>,
+    /// What each column of the screen sees, cast once per update.
+    rays: Vec<Option<Projection>>,
+    /// How far away the wall each ray hit is, as it's drawn, for clipping things against walls.
+    depth: DepthBuffer,
+    /// The cells every ray passed through on the last update, in one arena reset each time.
+    ray_paths: FrameArena<PathIndex>,
+    /// Whether the player is standing on the portal or stairs they arrived by, so they don't go
+    /// straight back.
+    arrived: bool,
+    health: f32,
+    abilities: Abilities,
+    dash: Dash,
+    inventory: Inventory,
+    /// What each kind of item does.
+    items: ItemTable,
+    /// Things in the level the player can use items on, and where they are.
+    targets: Vec<(ItemTarget, Billboard)>,
+    /// The target the player was facing at the end of the last update, so it's only narrated
+    /// when they turn to a new one.
+    focused_target: Option<usize>,
+    /// What the player chose to do with their items on the inventory screen, which is done at
+    /// the start of the next update.
+    pending_item_actions: Vec<(ItemAction, String)>,
+    /// How many seconds of air the player has left, which only runs down underwater.
+    oxygen: f32,
+    /// Whether the fire button was down last frame, so holding it only fires once.
+    fire_was_down: bool,
+    rockets: Vec<Rocket>,
+    explosions: Vec<Explosion>,
+    /// Where the player started, as (floor, row, column), for the route guide.
+    start: (usize, usize, usize),
+    breadcrumbs: Breadcrumbs,
+    show_breadcrumbs: bool,
+    route_guide: RouteGuide,
+    /// The cells from the player to wherever the route guide points, on the current floor.
+    route: Vec<(usize, usize)>,
+    /// The guide, floor, and cell the route was last asked for, so it's only searched for again
+    /// when one of them changes.
+    route_key: Option<(RouteGuide, usize, (usize, usize))>,
+    /// Work that only affects what's shown, like finding routes, spread out over frames.
+    /// Nothing here can change the game's state, or replays would depend on how fast it ran.
+    background_work: Scheduler<Level>,
+    /// How many frames the player has been playing the level.
+    run_frames: u64,
+    /// Where the player was when the run started, to play the run back from.
+    run_start: PlayerState,
+    /// The mouse sensitivity the run is being played at, from its first frame.
+    run_sensitivity: Option<f32>,
+    /// The player's inputs this run, until it finishes, or stops counting because they respawned.
+    recording: Option<InputRecorder>,
+    /// The best previous run, played back as the player plays.
+    ghost: Option<Ghost>,
+    show_ghost: bool,
+    /// A new best run to save, the next time the stage manager asks for user data.
+    pending_ghost: Option<GhostRun>,
+    /// Who finished runs are credited to on the leaderboard.
+    player_name: String,
+    /// A finished run to submit, the next time the stage manager asks for scores.
+    pending_score: Option<LeaderboardEntry>,
+    debug: DebugFlags,
+    aim_assist: AimAssist,
+    /// Which factions fight which.
+    hostility: Hostility,
+    /// The stats for each type of enemy, and which difficulty to use them at.
+    enemy_stats: EnemyStatsTable,
+    difficulty: Difficulty,
+    /// Where players have walked and died in this level, over every session.
+    heatmap: Heatmap,
+    show_heatmap: bool,
+    /// Whether the heatmap should be saved the next time the stage manager asks for user data.
+    heatmap_due: bool,
+    /// How many enemies the player has killed, for picking an ending.
+    kills: u32,
+    /// How many times the player has gone back to a checkpoint, for picking an ending.
+    respawns: u32,
+    /// Whether the player has reached the final exit, so the epilogue is only shown once.
+    ended: bool,
+    mouse_look: bool,
+    mouse_sensitivity: f32,
+}
+
+/// A rocket the player fired, which explodes when it hits something.
+struct Rocket {
+    x: f32,
+    y: f32,
+    angle: f32,
+    /// How far it's flown, in tiles.
+    traveled: f32,
+    /// Who fired it, which decides who it can hit.
+    faction: Faction,
+    /// The damage its blast does at the center.
+    damage: f32,
 }
 
 struct Projection {
@@ -99,6 +933,50 @@ struct Projection {
     y: f32,
     color: Color,
     normal: f32,
+    /// How far the ray traveled, including any bounces off mirrors.
+    distance: f32,
+    /// The direction of the last leg of the ray.
+    angle: f32,
+    /// The wall texture, for textured walls.
+    texture: Option<u32>,
+    /// Where across the wall's face the ray hit, from 0 at its left edge to 1 at its right, as
+    /// seen from in front of it.
+    texture_x: f32,
+    /// How much light is left after bouncing off mirrors, times how lit the face of the wall is
+    /// in the lightmap, which is already applied to color.
+    light: f32,
+}
+
+/// A named part of a level, like one that triggers a tutorial prompt, in tiles.
+struct MapArea {
+    x: f32,
+    y: f32,
+    w: f32,
+    h: f32,
+    /// The name of the prompt or trigger.
+    name: String,
+}
+
+impl MapArea {
+    /// The area covered by an object in a Tiled map, if it has the given name.
+    fn from_object(
+        tilemap: &TileMap,
+        object: &MapObject,
+        name: Option<&String>,
+    ) -> Option<MapArea> {
+        let (tile_w, tile_h) = (tilemap.tilewidth as f32, tilemap.tileheight as f32);
+        Some(MapArea {
+            x: object.position.x as f32 / tile_w,
+            y: object.position.y as f32 / tile_h,
+            w: object.position.w as f32 / tile_w,
+            h: object.position.h as f32 / tile_h,
+            name: name?.clone(),
+        })
+    }
+
+    fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.x + self.w && y >= self.y && y < self.y + self.h
+    }
 }
 
 struct PathIndex {
@@ -106,273 +984,1851 @@ struct PathIndex {
     column: usize,
 }
 
-fn float_eq(f1: f32, f2: f32) -> bool {
-    (f2 - f1).abs() < TOLERANCE
-}
-
 impl Level {
-    pub fn new(_files: &FileManager, images: &mut dyn ImageLoader) -> Result<Level> {
-        Ok(Level {
-            map: create_random_map(32, 32),
-            player_x: 15.5,
-            player_y: 15.5,
-            player_angle: 0.0,
-            background: images.load_sprite(Path::new("assets/spacebg.png"))?,
-        })
+    /// A random level, which is the same every time for the same seed.
+    pub fn with_seed(
+        seed: u64,
+        files: &FileManager,
+        images: &mut dyn ImageLoader,
+    ) -> Result<Level> {
+        let floors = create_random_floors(MAP_WIDTH, MAP_HEIGHT, seed)?;
+        let mut level = Level::with_floors(floors, (0, 15, 15), 0.0, LEVEL_TITLE, files, images)?;
+        level.show_title_card(Some(TitleCard::new(LEVEL_TITLE, Some(LEVEL_SUBTITLE))));
+        level.surfaces.floor = Surface::Texture(METAL_TEXTURE);
+        level.stream_map();
+        level.cast_rays();
+        Ok(level)
     }
 
-    #[allow(clippy::collapsible_if)]
-    fn can_move_to(&self, x: f32, y: f32) -> bool {
-        let lower_bound = PLAYER_SIZE / 2.0;
-        let upper_bound = 1.0 - (PLAYER_SIZE / 2.0);
+    /// Loads a level from a Tiled map. Its tiles' properties say what kind of wall each one is,
+    /// an object with a "spawn" property marks where the player starts, and the map's
+    /// "start_angle" property says which way they face.
+    pub fn from_tilemap(
+        path: &Path,
+        files: &FileManager,
+        images: &mut dyn ImageLoader,
+    ) -> Result<Level> {
+        let tilemap = TileMap::from_file(path, files, images)?;
+        let rows = Level::tilemap_rows(&tilemap)?;
+        let (row, column) = tilemap_spawn(&tilemap, &rows)?;
+        let angle = (tilemap.properties.start_angle.unwrap_or(0) as f32).to_radians();
+        let properties: &TileMapProperties = &tilemap.properties;
+        let title = match &properties.title {
+            Some(title) => title.clone(),
+            None => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
 
-        let row = y as usize;
-        let col = x as usize;
-        let x_frac = x - col as f32;
-        let y_frac = y - row as f32;
-        if !matches!(self.map.tiles[row][col], Tile::Empty) {
-            return false;
-        }
-        if x_frac < lower_bound {
-            if col == 0 || !matches!(self.map.tiles[row][col - 1], Tile::Empty) {
-                return false;
+        let lights: Vec<_> = tilemap
+            .objects
+            .iter()
+            .filter_map(|obj| {
+                let light = obj.properties.light?;
+                let (tile_w, tile_h) = (tilemap.tilewidth as f32, tilemap.tileheight as f32);
+                let x = (obj.position.x as f32 + obj.position.w as f32 / 2.0) / tile_w;
+                let y = (obj.position.y as f32 + obj.position.h as f32 / 2.0) / tile_h;
+                Some((x, y, light))
+            })
+            .collect();
+        let lightmap = (!lights.is_empty()).then(|| {
+            let ambient = properties.lightmap_ambient;
+            Lightmap::bake(
+                rows[0].len(),
+                rows.len(),
+                ambient.unwrap_or(DEFAULT_LIGHTMAP_AMBIENT),
+                &lights,
+                |row, column| rows[row][column].stops_ray(),
+            )
+        });
+        let mut map = Map::from_rows(rows)?;
+        map.lightmap = lightmap;
+        let floors = vec![map];
+        let mut level = Level::with_floors(floors, (0, row, column), angle, &title, files, images)?;
+        level.show_title_card(TitleCard::from_properties(properties));
+        level.clock = GameClock::from_properties(properties);
+        level.ambient_light = properties.ambient_light;
+        level.surfaces = properties.surfaces.unwrap_or_default();
+        level.world_texts = WorldText::from_tilemap(&tilemap).collect();
+        for (obj, billboard) in Billboard::from_tilemap(&tilemap)? {
+            let kind = match (obj.properties.pickup, &obj.properties.item) {
+                (Some(ability), _) => Some(PickupKind::Ability(ability)),
+                (None, Some(item)) => Some(PickupKind::Item(item.clone())),
+                (None, None) => None,
+            };
+            if let Some(kind) = kind {
+                level.pickups.push(Pickup { kind, billboard });
+                continue;
             }
-        }
-        if y_frac < lower_bound {
-            if row == 0 || !matches!(self.map.tiles[row - 1][col], Tile::Empty) {
-                return false;
+            if let Some(target) = &obj.properties.target {
+                level.targets.push((target.clone(), billboard));
             }
-        }
-        if x_frac > upper_bound {
-            if col >= self.map.width - 1 || !matches!(self.map.tiles[row][col + 1], Tile::Empty) {
-                return false;
+            if billboard.faction.is_none() {
+                level.billboards.push(billboard);
+                continue;
             }
+            let kind = obj.properties.enemy_type.as_deref();
+            let kind = kind.unwrap_or(DEFAULT_ENEMY_TYPE);
+            let enemy = level.new_enemy(billboard, kind);
+            level.enemies.push(enemy);
         }
-        if y_frac > upper_bound {
-            if row >= self.map.height - 1 || !matches!(self.map.tiles[row + 1][col], Tile::Empty) {
-                return false;
-            }
+        for obj in tilemap.objects.iter() {
+            let Some(settings) = obj.properties.spawner else {
+                continue;
+            };
+            let Some(template) = Billboard::from_object(&tilemap, obj)? else {
+                continue;
+            };
+            let (tile_w, tile_h) = (tilemap.tilewidth as f32, tilemap.tileheight as f32);
+            let area = (
+                obj.position.x as f32 / tile_w,
+                obj.position.y as f32 / tile_h,
+                obj.position.w as f32 / tile_w,
+                obj.position.h as f32 / tile_h,
+            );
+            let spawner = Spawner::new(settings, template, area);
+            level.spawners.push(match &obj.properties.enemy_type {
+                Some(kind) => spawner.with_enemy_type(kind),
+                None => spawner,
+            });
         }
-        true
+        level.prompt_areas = tilemap
+            .objects
+            .iter()
+            .filter_map(|obj| MapArea::from_object(&tilemap, obj, obj.properties.tutorial.as_ref()))
+            .collect();
+        level.trigger_areas = tilemap
+            .objects
+            .iter()
+            .filter_map(|obj| MapArea::from_object(&tilemap, obj, obj.properties.trigger.as_ref()))
+            .map(|area| (area, false))
+            .collect();
+        level.weather = properties.weather.map(|settings| {
+            let area = Rect {
+                x: 0,
+                y: 0,
+                w: RENDER_WIDTH as i32,
+                h: RENDER_HEIGHT as i32,
+            };
+            Weather::new(settings, area, random())
+        });
+        level.stream_map();
+        level.cast_rays();
+        Ok(level)
     }
 
-    fn project(
-        &self,
-        angle: f32,
-        x: f32,
-        y: f32,
-        path: &mut Option<Vec<PathIndex>>,
-    ) -> Option<Projection> {
-        let column = x as usize;
-        let row = y as usize;
-        let x = x - column as f32;
-        let y = y - row as f32;
-        self.project2(angle, row, column, x, y, -angle, path)
+    /// The raycaster tiles for the layer of a Tiled map the player is on.
+    fn tilemap_rows(tilemap: &TileMap) -> Result<Vec<Vec<Tile>>> {
+        (0..tilemap.height as usize)
+            .map(|row| {
+                (0..tilemap.width as usize)
+                    .map(|column| match tilemap.player_tile(row, column) {
+                        Some(index) => tile_from_properties(tilemap.get_tile_properties(index)),
+                        None => Ok(Tile::Empty),
+                    })
+                    .collect()
+            })
+            .collect()
     }
 
-    /// Projects a line through the tile map.
-    ///
-    /// angle: the angle, with 0 being right, and positive being clockwise, in radians
-    /// row: the row of the map the user is in, where 0 is the top
-    /// column: the column of the map the user is in
-    /// x: where in the tile the user is, in the range [0.0, 1.0]
-    /// y: where in the tile the user is, in the range [0.0, 1.0], with 0 being the top
-    /// normal: the normal angle of the last cell boundary crossed, defined like angle
-    ///
-    #[allow(clippy::too_many_arguments)]
-    fn project2(
-        &self,
+    /// A level with the given floors, where the player starts at the center of the given
+    /// (floor, row, column), facing the given angle.
+    fn with_floors(
+        floors: Vec<Map>,
+        start: (usize, usize, usize),
         angle: f32,
-        row: usize,
-        column: usize,
-        x: f32,
-        y: f32,
-        normal: f32,
-        path: &mut Option<Vec<PathIndex>>,
-    ) -> Option<Projection> {
-        // Check out of bounds.
-        if row >= self.map.height || column >= self.map.width {
-            return None;
+        title: &str,
+        files: &FileManager,
+        images: &mut dyn ImageLoader,
+    ) -> Result<Level> {
+        let heatmap = Heatmap::load(
+            files,
+            title,
+            floors.len(),
+            floors[0].width,
+            floors[0].height,
+        );
+        let run_start = PlayerState {
+            x: start.2 as f32 + 0.5,
+            y: start.1 as f32 + 0.5,
+            angle,
+        };
+        Ok(Level {
+            floors,
+            floor: start.0,
+            floor_transition: None,
+            player_x: run_start.x,
+            player_y: run_start.y,
+            player_angle: angle,
+            checkpoint: None,
+            background: images.load_sprite(Path::new("assets/spacebg.png"))?,
+            walls: match images.load_spritesheet(
+                Path::new("assets/walls.png"),
+                WALL_TEXTURE_SIZE,
+                WALL_TEXTURE_SIZE,
+            ) {
+                Ok(walls) => Some(walls),
+                Err(e) => {
+                    error!("unable to load wall textures: {}", e);
+                    None
+                }
+            },
+            #[cfg(feature = "rhai")]
+            script: load_script(files),
+            started: false,
+            message: None,
+            title: title.to_string(),
+            title_card: None,
+            clock: GameClock::default(),
+            ambient_light: None,
+            weather: None,
+            surfaces: SurfaceSettings::default(),
+            billboards: Vec::new(),
+            world_texts: Vec::new(),
+            pickups: Vec::new(),
+            enemies: Vec::new(),
+            pending_noises: Vec::new(),
+            pending_sounds: Vec::new(),
+            spawners: Vec::new(),
+            pending_messages: Vec::new(),
+            pending_narration: Vec::new(),
+            pending_prompts: Vec::new(),
+            prompt_areas: Vec::new(),
+            trigger_areas: Vec::new(),
+            rays: Vec::new(),
+            depth: DepthBuffer::default(),
+            ray_paths: FrameArena::new(),
+            // The player might start on stairs, and shouldn't take them until they step back on.
+            arrived: true,
+            health: PLAYER_MAX_HEALTH,
+            abilities: Abilities::default(),
+            dash: Dash::default(),
+            inventory: Inventory::default(),
+            items: ItemTable::load(files),
+            targets: Vec::new(),
+            focused_target: None,
+            pending_item_actions: Vec::new(),
+            oxygen: MAX_OXYGEN,
+            fire_was_down: false,
+            rockets: Vec::new(),
+            explosions: Vec::new(),
+            start,
+            breadcrumbs: Breadcrumbs::default(),
+            show_breadcrumbs: true,
+            route_guide: RouteGuide::Off,
+            route: Vec::new(),
+            route_key: None,
+            background_work: Scheduler::new(BACKGROUND_BUDGET),
+            run_frames: 0,
+            run_start,
+            run_sensitivity: None,
+            recording: Some(InputRecorder::new()),
+            ghost: load_ghost(files, title),
+            show_ghost: true,
+            pending_ghost: None,
+            player_name: DEFAULT_PLAYER_NAME.to_string(),
+            pending_score: None,
+            debug: DebugFlags::default(),
+            aim_assist: AimAssist::default(),
+            hostility: Hostility::default(),
+            enemy_stats: EnemyStatsTable::load(files),
+            difficulty: Difficulty::default(),
+            heatmap,
+            show_heatmap: false,
+            heatmap_due: false,
+            kills: 0,
+            respawns: 0,
+            ended: false,
+            mouse_look: true,
+            mouse_sensitivity: 1.0,
+        })
+    }
+
+    /// Shows the level's title card, if it has one, and narrates it.
+    fn show_title_card(&mut self, card: Option<TitleCard>) {
+        if let Some(card) = &card {
+            self.pending_narration.push(card.title.clone());
+            if let Some(subtitle) = &card.subtitle {
+                self.pending_narration.push(subtitle.clone());
+            }
         }
+        self.title_card = card;
+    }
+
+    /// The floor the player is on.
+    fn map(&self) -> &Map {
+        &self.floors[self.floor]
+    }
 
-        if let Some(path) = path.as_mut() {
-            path.push(PathIndex { row, column });
+    /// Switches to another floor, unloading the one the player is leaving and streaming in the
+    /// new one around them.
+    fn set_floor(&mut self, floor: usize) {
+        let floor = floor.min(self.floors.len() - 1);
+        if floor != self.floor {
+            self.floors[self.floor].grid.unload_all();
+            self.floor = floor;
+            self.stream_map();
         }
+    }
 
-        // Check for collision.
-        if let Tile::Solid(color) = self.map.tiles[row][column] {
-            return Some(Projection {
-                x: column as f32 + x,
-                y: row as f32 + y,
-                color,
-                normal,
-            });
+    /// The angle of the ray for the given column of the screen.
+    fn ray_angle(&self, column: i32) -> f32 {
+        let angle = ((column as f32) / 640.0) * FRAC_PI_2;
+        let angle = angle - (PI / 4.0);
+        let mut angle = self.player_angle + angle;
+        while angle >= PI * 2.0 {
+            angle -= PI * 2.0;
+        }
+        while angle < 0.0 {
+            angle += PI * 2.0;
         }
+        angle
+    }
 
-        // Check the cardinal directions, since the math gets funky.
-        if float_eq(angle, 0.0) {
-            // Straight right.
-            return self.project2(angle, row, column + 1, 0.0, y, PI, path);
+    /// Casts a ray for each column of the screen, revealing every cell the rays pass through.
+    fn cast_rays(&mut self) {
+        let mut rays = mem::take(&mut self.rays);
+        rays.clear();
+        let mut paths = mem::take(&mut self.ray_paths);
+        paths.reset();
+        let mut paths = Some(paths);
+        for column in 0..640 {
+            let angle = self.ray_angle(column);
+            rays.push(self.project(angle, self.player_x, self.player_y, &mut paths));
         }
-        if float_eq(angle, PI) {
-            // Straight left.
-            return if column == 0 {
-                None
-            } else {
-                return self.project2(angle, row, column - 1, 1.0, y, 0.0, path);
-            };
+        let paths = paths.unwrap_or_default();
+        let map = &mut self.floors[self.floor];
+        for index in paths.items() {
+            map.visited.insert(index.row * map.width + index.column);
+        }
+        self.rays = rays;
+        self.ray_paths = paths;
+
+        // Remove the fisheye effect, the same way the walls are drawn.
+        let mut depth = mem::take(&mut self.depth);
+        depth.update(self.rays.iter().enumerate().map(|(column, ray)| {
+            let angle = self.ray_angle(column as i32);
+            ray.as_ref()
+                .map(|ray| ray.distance * (self.player_angle - angle).cos())
+        }));
+        self.depth = depth;
+    }
+
+    /// Loads the parts of the map near the player, and unloads the rest.
+    fn stream_map(&mut self) {
+        let row = self.player_y as usize;
+        let column = self.player_x as usize;
+        if let Err(e) = self.floors[self.floor].grid.update(row, column) {
+            error!("unable to stream map around ({column}, {row}): {}", e);
+        }
+    }
+
+    /// Sends the player through a portal, or up or down stairs, when they step onto one.
+    fn follow_links(&mut self) {
+        let row = self.player_y as usize;
+        let column = self.player_x as usize;
+        let tile = *self.map().tile(row, column);
+        if !matches!(tile, Tile::Portal { .. } | Tile::Stairs { .. }) {
+            self.arrived = false;
+            return;
         }
-        if float_eq(angle, FRAC_PI_2) {
-            // Straight down.
-            return self.project2(angle, row + 1, column, x, 0.0, 3.0 * FRAC_PI_2, path);
+        if self.arrived || self.spawners.iter().any(Spawner::is_locking) {
+            return;
         }
-        if float_eq(angle, 3.0 * FRAC_PI_2) {
-            // Straight up.
-            return if row == 0 {
-                None
-            } else {
-                self.project2(angle, row - 1, column, x, 1.0, FRAC_PI_2, path)
-            };
+        match tile {
+            Tile::Portal {
+                row: to_row,
+                column: to_column,
+            } => {
+                info!("taking portal from ({column}, {row}) to ({to_column}, {to_row})");
+                // Keep the same place within the tile, so the view doesn't jump.
+                self.player_x = to_column as f32 + (self.player_x - column as f32);
+                self.player_y = to_row as f32 + (self.player_y - row as f32);
+            }
+            Tile::Stairs { floor } => {
+                info!("taking stairs from floor {} to {}", self.floor, floor);
+                let going_up = floor > self.floor;
+                self.set_floor(floor);
+                self.floor_transition = Some((FLOOR_TRANSITION_FRAMES, going_up));
+                self.pending_messages
+                    .push((MessageKind::System, format!("floor {}", self.floor + 1)));
+            }
+            _ => {}
         }
+        self.arrived = true;
+    }
 
-        // TODO: Try to simplify this.
-
-        // Check the odd angles.
-        //
-        //        0 - PI/2: right and down
-        //       PI/2 - PI: left and down
-        //     PI - 3 PI/2: left and up
-        // 3 PI / 2 - 2 PI: right and up
-
-        if angle < PI {
-            // It's pointing downish.
-            /*
-             *      +------------+
-             *      |            |
-             *      |        dx  |
-             *      |       *--+-|
-             *      |  ny-y |\θ| |
-             *      |       | \| |
-             *      +------------+
-             */
-
-            let x_intercept = x + (1.0 - y) / angle.tan();
-            if x_intercept < 0.0 {
-                // it hit the left.
-                if column == 0 {
-                    None
-                } else {
-                    let y_intercept = 1.0 - ((1.0 - y) + x * angle.tan());
-                    self.project2(angle, row, column - 1, 1.0, y_intercept, 0.0, path)
-                }
-            } else if x_intercept < 1.0 {
-                // it hit the bottom.
-                self.project2(
-                    angle,
-                    row + 1,
-                    column,
-                    x_intercept,
-                    0.0,
-                    3.0 * FRAC_PI_2,
-                    path,
-                )
-            } else {
-                // it hit the right.
-                let y_intercept = y + (1.0 - x) * angle.tan();
-                self.project2(angle, row, column + 1, 0.0, y_intercept, PI, path)
+    /// Moves the ghost along its run by a frame, taking portals and stairs like the player does.
+    fn update_ghost(&mut self) {
+        let Some(ghost) = &mut self.ghost else {
+            return;
+        };
+        let Some(inputs) = ghost.next_inputs() else {
+            return;
+        };
+        let map = &mut self.floors[ghost.floor];
+        if ghost.floor != self.floor {
+            // Stream in the floor the ghost is on, so it has walls to run into.
+            let (row, column) = (ghost.body.y as usize, ghost.body.x as usize);
+            if let Err(e) = map.grid.update(row, column) {
+                error!("unable to stream map around ghost: {}", e);
             }
-        } else {
-            // It's pointing upish.
-            /*
-             *               dx
-             *      +------------+
-             *      |       | /  |
-             *      |     y |/θ  |
-             *      |       *--+-|
-             *      |            |
-             *      |            |
-             *      +------------+
-             */
-            let up_angle = TAU - angle;
-            let x_intercept = x + y / up_angle.tan();
-            if x_intercept < 0.0 {
-                // it hit the left.
-                if column == 0 {
-                    None
-                } else {
-                    let y_intercept = 1.0 - ((1.0 - y) - x * up_angle.tan());
-                    self.project2(angle, row, column - 1, 1.0, y_intercept, 0.0, path)
-                }
-            } else if x_intercept < 1.0 {
-                // it hit the top.
-                if row == 0 {
-                    None
-                } else {
-                    self.project2(angle, row - 1, column, x_intercept, 1.0, FRAC_PI_2, path)
+        }
+        if inputs.player_dash_clicked {
+            ghost.dash.start();
+        }
+        let mouse_sensitivity = ghost.run.mouse_sensitivity;
+        ghost.body = map.move_body(ghost.body, &inputs, mouse_sensitivity, false);
+        if ghost.dash.update() {
+            ghost.body = map.dash_body(ghost.body);
+        }
+        let (row, column) = (ghost.body.y as usize, ghost.body.x as usize);
+        match *map.tile(row, column) {
+            Tile::Portal { .. } | Tile::Stairs { .. } if ghost.arrived => {}
+            Tile::Portal {
+                row: to_row,
+                column: to_column,
+            } => {
+                ghost.body.x = to_column as f32 + (ghost.body.x - column as f32);
+                ghost.body.y = to_row as f32 + (ghost.body.y - row as f32);
+                ghost.arrived = true;
+            }
+            Tile::Stairs { floor } => {
+                ghost.floor = floor.min(self.floors.len() - 1);
+                ghost.arrived = true;
+            }
+            _ => ghost.arrived = false,
+        }
+    }
+
+    /// Ends the run when the player reaches the top floor, keeping it if it's a new best.
+    fn finish_run(&mut self) {
+        let Some(inputs) = self.recording.take() else {
+            return;
+        };
+        self.heatmap_due = true;
+        let frames = self.run_frames;
+        let seconds = frames / FRAME_RATE as u64;
+        let time = format!("{}:{:02}", seconds / 60, seconds % 60);
+        self.pending_score = Some(LeaderboardEntry {
+            name: self.player_name.clone(),
+            frames,
+            score: 0,
+        });
+        let best = self.ghost.as_ref().map(|ghost| ghost.run.frames);
+        if best.is_some_and(|best| best <= frames) {
+            self.pending_messages
+                .push((MessageKind::System, format!("finished in {}", time)));
+            return;
+        }
+        self.pending_messages
+            .push((MessageKind::System, format!("new best {}", time)));
+        self.pending_ghost = Some(GhostRun {
+            frames,
+            start: self.run_start,
+            mouse_sensitivity: self.run_sensitivity.unwrap_or(self.mouse_sensitivity),
+            inputs,
+        });
+    }
+
+    /// How the run went, for picking an ending.
+    fn run_summary(&self) -> RunSummary {
+        RunSummary {
+            seconds: self.run_frames / FRAME_RATE as u64,
+            health: self.health,
+            kills: self.kills,
+            respawns: self.respawns,
+        }
+    }
+
+    /// How far the view is shifted up or down, and how dark it is, while changing floors.
+    fn floor_transition_effect(&self) -> (i32, f32) {
+        let Some((frames, going_up)) = self.floor_transition else {
+            return (0, 0.0);
+        };
+        let t = frames as f32 / FLOOR_TRANSITION_FRAMES as f32;
+        // Going up, the camera comes from below, so the walls start out higher on the screen.
+        let direction = if going_up { -1.0 } else { 1.0 };
+        let shift = direction * t * RENDER_HEIGHT as f32 / 4.0;
+        (shift as i32, t)
+    }
+
+    /// Asks for the route guide's route to be found in the background, if the player has moved to
+    /// another cell since the last time.
+    fn update_route(&mut self) {
+        if self.route_guide == RouteGuide::Off {
+            self.route.clear();
+            self.route_key = None;
+            return;
+        }
+        let from = (self.player_y as usize, self.player_x as usize);
+        let key = (self.route_guide, self.floor, from);
+        if self.route_key == Some(key) {
+            return;
+        }
+        self.route_key = Some(key);
+        self.background_work
+            .schedule("route", move |level: &mut Level| level.find_route(key));
+    }
+
+    /// Finds the route for the route guide from a cell, unless the player has already moved on
+    /// and a newer search is waiting.
+    fn find_route(&mut self, key: (RouteGuide, usize, (usize, usize))) {
+        if self.route_key != Some(key) {
+            return;
+        }
+        let (guide, floor, from) = key;
+        let (start_floor, start_row, start_column) = self.start;
+        let map = self.map();
+        let is_goal = |row, column| match (guide, *map.tile(row, column)) {
+            (RouteGuide::Start, _) if floor == start_floor => {
+                (row, column) == (start_row, start_column)
+            }
+            // The start is on another floor, so head for the stairs toward it first.
+            (RouteGuide::Start, Tile::Stairs { floor: to }) => {
+                to.abs_diff(start_floor) < floor.abs_diff(start_floor)
+            }
+            (RouteGuide::Exit, Tile::Stairs { floor: to }) => to > floor,
+            _ => false,
+        };
+        let passable = |row, column| !map.tile(row, column).is_solid();
+        let route = find_path(map.width, map.height, from, is_goal, passable);
+        self.route = route.unwrap_or_default();
+    }
+
+    /// Runs as much background work as fits in this frame.
+    fn run_background(&mut self) {
+        let mut work = mem::replace(&mut self.background_work, Scheduler::new(BACKGROUND_BUDGET));
+        work.run(self);
+        self.background_work = work;
+    }
+
+    /// Fires a rocket in the direction the player is facing, or at an enemy they're almost
+    /// facing, with aim assist.
+    fn fire(&mut self) {
+        let from = (self.player_x, self.player_y);
+        let targets: Vec<(f32, f32)> = self
+            .enemies
+            .iter()
+            .filter(|enemy| enemy.billboard.floor == self.floor)
+            .filter(|enemy| self.hostility.is_hostile(Faction::Player, enemy.faction()))
+            .map(Enemy::position)
+            .filter(|target| self.line_of_sight(from, *target))
+            .collect();
+        let angle = self.aim_assist.aim(from, self.player_angle, targets);
+        self.rockets.push(Rocket {
+            x: self.player_x,
+            y: self.player_y,
+            angle,
+            traveled: 0.0,
+            faction: Faction::Player,
+            damage: ROCKET_DAMAGE,
+        });
+        self.pending_noises
+            .push(Noise::new(from.0, from.1, ROCKET_NOISE_RADIUS));
+    }
+
+    /// Moves the rockets, setting off the ones that hit something or flew too far.
+    fn update_rockets(&mut self) {
+        let mut rockets = mem::take(&mut self.rockets);
+        rockets.retain_mut(|rocket| {
+            let x = rocket.x + rocket.angle.cos() * ROCKET_SPEED;
+            let y = rocket.y + rocket.angle.sin() * ROCKET_SPEED;
+            // Go off just in front of whatever it hit, so the blast isn't inside the wall.
+            let hit = x < 0.0 || y < 0.0 || self.map().tile(y as usize, x as usize).stops_ray();
+            if hit || rocket.traveled >= ROCKET_RANGE || self.rocket_hits_someone(rocket) {
+                let blast = Explosion::new(rocket.x, rocket.y, ROCKET_BLAST_RADIUS, rocket.damage)
+                    .with_source(rocket.faction);
+                self.explode(blast);
+                return false;
+            }
+            (rocket.x, rocket.y) = (x, y);
+            rocket.traveled += ROCKET_SPEED;
+            true
+        });
+        self.rockets.append(&mut rockets);
+    }
+
+    /// Whether a rocket has flown into someone its faction is hostile to. It passes by anyone
+    /// else.
+    fn rocket_hits_someone(&self, rocket: &Rocket) -> bool {
+        if rocket.traveled < ROCKET_ARMING_DISTANCE {
+            return false;
+        }
+        let near = |(x, y): (f32, f32)| {
+            ((x - rocket.x).powi(2) + (y - rocket.y).powi(2)).sqrt() < ROCKET_HIT_RADIUS
+        };
+        let hostile = |faction| self.hostility.is_hostile(rocket.faction, faction);
+        (hostile(Faction::Player) && near((self.player_x, self.player_y)))
+            || self.enemies.iter().any(|enemy| {
+                enemy.billboard.floor == self.floor
+                    && hostile(enemy.faction())
+                    && near(enemy.position())
+            })
+    }
+
+    /// Starts the spawners the player has walked into, and adds whatever they spawn.
+    fn update_spawners(&mut self) {
+        let (x, y) = (self.player_x, self.player_y);
+        let mut spawners = mem::take(&mut self.spawners);
+        for (i, spawner) in spawners.iter_mut().enumerate() {
+            if spawner.check_trigger(self.floor, x, y) && spawner.is_locking() {
+                self.pending_messages
+                    .push((MessageKind::System, "exits locked".to_string()));
+            }
+            let (was_locking, wave) = (spawner.is_locking(), spawner.wave());
+            let alive = self
+                .billboards
+                .iter()
+                .chain(self.enemies.iter().map(|enemy| &enemy.billboard))
+                .filter(|billboard| billboard.spawner == Some(i))
+                .count();
+            if let Some(billboard) = spawner.update(alive) {
+                let billboard = Billboard {
+                    spawner: Some(i),
+                    ..billboard
+                };
+                if billboard.faction.is_some() {
+                    let mut enemy = self.new_enemy(billboard, spawner.enemy_type());
+                    enemy.fade_in();
+                    self.enemies.push(enemy);
+                } else {
+                    self.billboards.push(billboard);
+                }
+            }
+            if spawner.is_locking() && spawner.wave() != wave {
+                self.pending_messages
+                    .push((MessageKind::System, format!("wave {}", spawner.wave())));
+            }
+            if was_locking && !spawner.is_locking() {
+                self.pending_messages
+                    .push((MessageKind::System, "exits unlocked".to_string()));
+            }
+        }
+        self.spawners = spawners;
+    }
+
+    /// Moves the characters on the player's floor. Each one chases and fires at the nearest
+    /// thing it's hostile to that it can see, or investigates noises it hears, if it's hostile to
+    /// anybody. Returns whether any of them caught the player.
+    fn update_enemies(&mut self) -> bool {
+        let player = (self.player_x, self.player_y);
+        let mut enemies = mem::take(&mut self.enemies);
+        for noise in mem::take(&mut self.pending_noises) {
+            let map = self.map();
+            let field = noise.propagate(map.width, map.height, |row, column| {
+                map.tile(row, column).is_solid()
+            });
+            for enemy in enemies.iter_mut() {
+                let (row, column) = enemy.cell();
+                if enemy.billboard.floor == self.floor
+                    && self.hostility.is_aggressive(enemy.faction())
+                    && field.is_heard(row, column)
+                {
+                    enemy.alert(noise.cell());
+                }
+            }
+        }
+        let mut caught = false;
+        for i in 0..enemies.len() {
+            let enemy = &enemies[i];
+            if enemy.billboard.floor != self.floor {
+                continue;
+            }
+            let faction = enemy.faction();
+            let others = enemies
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| *j != i && other.billboard.floor == self.floor)
+                .map(|(_, other)| (other.faction(), other.position()));
+            let from = enemy.position();
+            let target = [(Faction::Player, player)]
+                .into_iter()
+                .chain(others)
+                .filter(|(other, _)| self.hostility.is_hostile(faction, *other))
+                .map(|(_, position)| position)
+                .filter(|position| enemy.in_sight_range(*position))
+                .filter(|position| self.line_of_sight(from, *position))
+                .min_by(|a, b| {
+                    let distance = |(x, y): (f32, f32)| (x - from.0).powi(2) + (y - from.1).powi(2);
+                    distance(*a).total_cmp(&distance(*b))
+                });
+            let map = self.map();
+            let passable = |row, column| !map.tile(row, column).is_solid();
+            let route =
+                |from, to| find_path(map.width, map.height, from, |r, c| (r, c) == to, passable);
+            let blocked =
+                |x: f32, y: f32| x < 0.0 || y < 0.0 || map.tile(y as usize, x as usize).is_solid();
+            let enemy = &mut enemies[i];
+            if let Some(angle) = enemy.update(target, route, blocked) {
+                let (x, y) = enemy.position();
+                self.rockets.push(Rocket {
+                    x,
+                    y,
+                    angle,
+                    traveled: 0.0,
+                    faction,
+                    damage: enemy.stats.damage,
+                });
+            }
+            caught |= self.hostility.is_hostile(faction, Faction::Player) && enemy.touches(player);
+        }
+        self.enemies = enemies;
+        caught
+    }
+
+    /// An enemy of the given type, with its stats for the difficulty, and its type's tint.
+    fn new_enemy(&self, billboard: Billboard, kind: &str) -> Enemy {
+        let stats = self.enemy_stats.get(kind, self.difficulty);
+        let mut enemy = Enemy::new(billboard, kind, stats);
+        enemy.set_type_tint(self.enemy_stats.tint(kind));
+        enemy
+    }
+
+    /// Looks up every enemy's stats and tint again, after the difficulty or the stats file
+    /// changes.
+    fn refresh_enemy_stats(&mut self) {
+        for enemy in self.enemies.iter_mut() {
+            let stats = self.enemy_stats.get(&enemy.kind, self.difficulty);
+            enemy.set_stats(stats);
+            enemy.set_type_tint(self.enemy_stats.tint(&enemy.kind));
+        }
+    }
+
+    /// Unlocks the ability of any pickup the player has walked into, or puts its item in their
+    /// inventory.
+    fn update_pickups(&mut self) {
+        let (floor, x, y) = (self.floor, self.player_x, self.player_y);
+        let (reached, pickups): (Vec<Pickup>, Vec<Pickup>) = mem::take(&mut self.pickups)
+            .into_iter()
+            .partition(|pickup| pickup.reached_by(floor, x, y));
+        self.pickups = pickups;
+        for pickup in reached {
+            let (x, y) = (pickup.billboard.x, pickup.billboard.y);
+            self.pending_sounds.push((Sound::Click, x, y));
+            let prompt = match &pickup.kind {
+                PickupKind::Ability(ability) => {
+                    if !self.abilities.unlock(*ability) {
+                        continue;
+                    }
+                    match ability {
+                        Ability::Dash => DASH_PROMPT,
+                    }
+                }
+                PickupKind::Item(name) => {
+                    self.inventory.add(name);
+                    INVENTORY_PROMPT
+                }
+            };
+            self.pending_messages
+                .push((MessageKind::Pickup, format!("picked up {}", pickup.kind)));
+            self.pending_prompts.push(prompt.to_string());
+        }
+    }
+
+    /// The target the player is facing and close enough to use items on, if any, and if it
+    /// still accepts any.
+    fn find_focused_target(&self) -> Option<usize> {
+        let (floor, x, y) = (self.floor, self.player_x, self.player_y);
+        let mut nearest: Option<(usize, f32)> = None;
+        for (i, (target, billboard)) in self.targets.iter().enumerate() {
+            if billboard.floor != floor || target.accepts.is_empty() {
+                continue;
+            }
+            let (dx, dy) = (billboard.x - x, billboard.y - y);
+            let distance = (dx * dx + dy * dy).sqrt();
+            let off = (dy.atan2(dx) - self.player_angle + PI).rem_euclid(TAU) - PI;
+            if distance > TARGET_REACH || off.abs() > TARGET_FOCUS_ANGLE {
+                continue;
+            }
+            if nearest.is_none_or(|(_, nearest)| distance < nearest) {
+                nearest = Some((i, distance));
+            }
+        }
+        nearest.map(|(i, _)| i)
+    }
+
+    /// Narrates which items the target the player is facing accepts, when they turn to it.
+    fn update_focused_target(&mut self) {
+        let focused = self.find_focused_target();
+        if focused != mem::replace(&mut self.focused_target, focused) {
+            if let Some(text) = self.target_text() {
+                self.pending_narration.push(text);
+            }
+        }
+    }
+
+    /// What the target the player is facing is called, and which items it accepts.
+    fn target_text(&self) -> Option<String> {
+        let (target, _) = &self.targets[self.focused_target?];
+        if target.accepts.is_empty() {
+            return None;
+        }
+        Some(format!("{}: {}", target.name, target.accepts.join(", ")))
+    }
+
+    /// Does what the player chose to do with their items on the inventory screen. An item is
+    /// used on the target the player is facing if it accepts it, and otherwise heals the player
+    /// if it can. The level script gets an event like "use_crank_on_socket", "use_medkit", or
+    /// "drop_medkit".
+    fn apply_item_actions(&mut self, sounds: &mut SoundManager) {
+        for (action, name) in mem::take(&mut self.pending_item_actions) {
+            if self.inventory.count(&name) == 0 {
+                continue;
+            }
+            let focused = self.find_focused_target();
+            let target = focused.map(|i| &self.targets[i].0);
+            let item_use = match action {
+                ItemAction::Use => self.items.use_of(&name, target),
+                ItemAction::Drop => ItemUse::Nothing,
+            };
+            if action == ItemAction::Drop || item_use.is_used_up() {
+                self.inventory.remove(&name);
+            }
+            let (text, event) = match (action, item_use, focused) {
+                (ItemAction::Drop, _, _) => (format!("dropped {}", name), format!("drop_{}", name)),
+                (ItemAction::Use, ItemUse::OnTarget { .. }, Some(i)) => {
+                    let target = &mut self.targets[i].0;
+                    target.accepts.retain(|item| *item != name);
+                    let text = format!("used {} on {}", name, target.name);
+                    (text, format!("use_{}_on_{}", name, target.name))
+                }
+                (ItemAction::Use, ItemUse::Heal(heal), _) => {
+                    self.health = (self.health + heal).min(PLAYER_MAX_HEALTH);
+                    (format!("used {}", name), format!("use_{}", name))
+                }
+                (ItemAction::Use, _, _) => {
+                    let text = format!("nothing to use {} on", name);
+                    self.pending_messages.push((MessageKind::System, text));
+                    continue;
+                }
+            };
+            self.pending_messages.push((MessageKind::Pickup, text));
+            self.fire_script_event(&event, sounds);
+        }
+    }
+
+    /// Whether the player is underwater.
+    fn is_submerged(&self) -> bool {
+        self.map().is_water(self.player_x, self.player_y)
+    }
+
+    /// Uses up the player's air while they're underwater, hurting them once it's gone, or
+    /// refills it while they're out of the water.
+    fn update_oxygen(&mut self) {
+        let seconds = 1.0 / FRAME_RATE as f32;
+        if !self.is_submerged() {
+            self.oxygen = (self.oxygen + OXYGEN_REFILL_RATE * seconds).min(MAX_OXYGEN);
+            return;
+        }
+        self.oxygen = (self.oxygen - seconds).max(0.0);
+        if self.oxygen == 0.0 && !self.debug.god {
+            self.health -= DROWNING_DAMAGE * seconds;
+        }
+    }
+
+    /// Draws a line of text centered across the screen at a height in HUD units, moved into the
+    /// safe area like a button.
+    fn draw_centered_text(context: &mut RenderContext, font: &Font, y: i32, text: &str) {
+        let width = font.text_width(text);
+        let position = Rect {
+            x: (context.ui_area().w - width) / 2,
+            y,
+            w: width,
+            h: font.char_height,
+        };
+        let position = context.to_safe_area(position).top_left();
+        font.draw_string(context, RenderLayer::Hud, position, text);
+    }
+
+    /// Draws a labeled bar across the top of the screen, filled to a fraction from 0 to 1, in
+    /// the given row of meters.
+    fn draw_meter(
+        context: &mut RenderContext,
+        font: &Font,
+        row: i32,
+        label: &str,
+        fraction: f32,
+        color: Color,
+    ) {
+        let area = context.safe_area();
+        let label_width = font.text_width(label) + font.char_width;
+        let x = area.x + (area.w - METER_WIDTH + label_width) / 2;
+        let y = area.y + 8 + row * font.char_height * 3 / 2;
+        font.draw_string(
+            context,
+            RenderLayer::Hud,
+            Point::new(x - label_width, y),
+            label,
+        );
+        let bar = Rect {
+            x,
+            y,
+            w: METER_WIDTH,
+            h: METER_HEIGHT,
+        };
+        context.fill_rect(bar, RenderLayer::Hud, METER_BACKGROUND);
+        let filled = Rect {
+            w: (METER_WIDTH as f32 * fraction.clamp(0.0, 1.0)) as i32,
+            ..bar
+        };
+        context.fill_rect(filled, RenderLayer::Hud, color);
+    }
+
+    /// Records the player's death and shows the kill screen with the cause.
+    fn die(&mut self, cause: &str) -> SceneResult {
+        self.heatmap
+            .record_death(self.floor, self.player_y as usize, self.player_x as usize);
+        self.heatmap_due = true;
+        self.pending_messages
+            .push((MessageKind::System, cause.to_string()));
+        SceneResult::PushKillScreen {
+            text: cause.to_string(),
+        }
+    }
+
+    /// Whether a straight line between two points is clear, other than the tile at the end.
+    fn line_of_sight(&self, from: (f32, f32), to: (f32, f32)) -> bool {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let distance = (dx * dx + dy * dy).sqrt();
+        if distance < TOLERANCE {
+            return true;
+        }
+        let map = self.map();
+        let ray = Ray::from_point(from.0, from.1, dy.atan2(dx).rem_euclid(TAU));
+        let raycaster = Raycaster::new(map.width, map.height);
+        match raycaster.cast(ray, true, |row, column| map.tile(row, column).stops_ray()) {
+            None => true,
+            Some(hit) => {
+                let target = (to.1 as usize, to.0 as usize);
+                let reached = ((hit.x - from.0).powi(2) + (hit.y - from.1).powi(2)).sqrt();
+                (hit.row, hit.column) == target || reached >= distance - TOLERANCE
+            }
+        }
+    }
+
+    /// Sets off an explosion, damaging the player and destroying tiles in range that it can see.
+    /// Barrels it destroys set off explosions of their own.
+    fn explode(&mut self, explosion: Explosion) {
+        let mut pending = vec![explosion];
+        while let Some(explosion) = pending.pop() {
+            let origin = (explosion.x, explosion.y);
+            let loudness = explosion.radius * EXPLOSION_NOISE_RADII;
+            self.pending_noises
+                .push(Noise::new(origin.0, origin.1, loudness));
+            self.pending_sounds
+                .push((Sound::Thunder, origin.0, origin.1));
+            let player = (self.player_x, self.player_y);
+            let damage = explosion.damage_at(explosion.distance_to(player.0, player.1));
+            if damage > 0.0
+                && !self.debug.god
+                && self.hostility.can_hurt(explosion.source, Faction::Player)
+                && self.line_of_sight(origin, player)
+            {
+                self.health -= damage;
+            }
+
+            let mut enemies = mem::take(&mut self.enemies);
+            for enemy in enemies.iter_mut() {
+                let (x, y) = enemy.position();
+                let damage = explosion.damage_at(explosion.distance_to(x, y));
+                if damage > 0.0
+                    && enemy.billboard.floor == self.floor
+                    && self.hostility.can_hurt(explosion.source, enemy.faction())
+                    && self.line_of_sight(origin, (x, y))
+                {
+                    enemy.hurt(damage);
+                }
+            }
+            let alive = enemies.len();
+            enemies.retain(|enemy| !enemy.is_dead());
+            if explosion.source.is_none() || explosion.source == Some(Faction::Player) {
+                self.kills += (alive - enemies.len()) as u32;
+            }
+            self.enemies = enemies;
+
+            let reach = explosion.radius.ceil() as usize + 1;
+            let (row, column) = (explosion.y as usize, explosion.x as usize);
+            for i in row.saturating_sub(reach)..(row + reach).min(self.map().height) {
+                for j in column.saturating_sub(reach)..(column + reach).min(self.map().width) {
+                    let tile = *self.map().tile(i, j);
+                    if !tile.is_destructible() {
+                        continue;
+                    }
+                    // Measure to the nearest part of the tile, since that's what the blast hits.
+                    let near_x = explosion.x.clamp(j as f32, j as f32 + 1.0);
+                    let near_y = explosion.y.clamp(i as f32, i as f32 + 1.0);
+                    if explosion.distance_to(near_x, near_y) >= explosion.radius {
+                        continue;
+                    }
+                    let center = (j as f32 + 0.5, i as f32 + 0.5);
+                    if !self.line_of_sight(origin, center) {
+                        continue;
+                    }
+                    self.floors[self.floor].destroy(i, j);
+                    // The way back might be shorter now.
+                    self.route_key = None;
+                    if let Tile::Barrel = tile {
+                        pending.push(Explosion::new(
+                            center.0,
+                            center.1,
+                            BARREL_BLAST_RADIUS,
+                            BARREL_DAMAGE,
+                        ));
+                    }
+                }
+            }
+            self.explosions.push(explosion);
+        }
+    }
+
+    /// How far to shake the view for all the explosions going on.
+    fn shake(&self) -> (i32, i32) {
+        self.explosions
+            .iter()
+            .map(|explosion| explosion.shake(explosion.distance_to(self.player_x, self.player_y)))
+            .max_by_key(|(dx, dy)| dx.abs() + dy.abs())
+            .unwrap_or((0, 0))
+    }
+
+    /// Where a shape standing at (x, y) shows up on screen, placed the same way as billboards,
+    /// and the column of its middle. Shapes can't be cut off column by column like sprites, so
+    /// this returns None if the middle is behind a nearer wall.
+    fn place_shape(
+        &self,
+        (x, y): (f32, f32),
+        size: f32,
+        aspect: f32,
+        horizon: i32,
+    ) -> Option<(Rect<i32>, i32)> {
+        let camera = (self.player_x, self.player_y, self.player_angle);
+        let (dest, distance) = place(x, y, size, aspect, camera, horizon)?;
+        let column = dest.x + dest.w / 2;
+        if self
+            .depth
+            .is_hidden(column.clamp(0, RENDER_WIDTH as i32 - 1), distance)
+        {
+            return None;
+        }
+        Some((dest, column))
+    }
+
+    /// Draws the ceiling above the horizon and the floor below it. Walls are drawn over them.
+    fn draw_surfaces(&self, context: &mut RenderContext, horizon: i32, shift: i32) {
+        let ceiling_area = Rect {
+            x: 0,
+            y: 0,
+            w: RENDER_WIDTH as i32,
+            h: horizon,
+        };
+        let floor_area = Rect {
+            x: 0,
+            y: horizon,
+            w: RENDER_WIDTH as i32,
+            h: RENDER_HEIGHT as i32 - horizon,
+        };
+        for (surface, area, ceiling) in [
+            (self.surfaces.ceiling, ceiling_area, true),
+            (self.surfaces.floor, floor_area, false),
+        ] {
+            match (surface, &self.walls) {
+                // The floor can't be sky, so it would be left as the background color.
+                (Surface::Sky, _) if ceiling => self.draw_sky(context),
+                (Surface::Sky, _) => {}
+                // A flat color only has to be cast if the lightmap or water makes parts of it
+                // look different.
+                (Surface::Color(color), _)
+                    if self.map().lightmap.is_none() && (ceiling || !self.map().has_water) =>
+                {
+                    context.player_batch.fill_rect(area, color)
+                }
+                (Surface::Color(_), _) | (Surface::Texture(_), Some(_)) => {
+                    self.cast_surface(context, surface, horizon, shift, ceiling)
+                }
+                (Surface::Texture(index), None) => {
+                    let color = WALL_TEXTURE_COLORS
+                        .get(index as usize)
+                        .unwrap_or(&Color::WHITE);
+                    context.player_batch.fill_rect(area, *color);
+                }
+            }
+        }
+    }
+
+    /// Draws the space background across the top half of the screen, scrolling as the player
+    /// turns.
+    fn draw_sky(&self, context: &mut RenderContext) {
+        let background_fraction = if self.player_angle < PI {
+            -1.0 * self.player_angle / PI
+        } else {
+            1.0 - (self.player_angle - PI) / PI
+        };
+        let background_offset = (RENDER_WIDTH as f32 * background_fraction) as i32;
+
+        let background_src = Rect {
+            x: 0,
+            y: 0,
+            w: 640,
+            h: (RENDER_HEIGHT as i32 / 2).max(400),
+        };
+        let background_dst = Rect {
+            x: background_offset,
+            y: 0,
+            w: RENDER_WIDTH as i32,
+            h: RENDER_HEIGHT as i32 / 2,
+        };
+        context
+            .player_batch
+            .draw(self.background, background_dst, background_src, false);
+
+        let background_dst = Rect {
+            x: if background_dst.x < 0 {
+                background_dst.x + RENDER_WIDTH as i32
+            } else {
+                background_dst.x - RENDER_WIDTH as i32
+            },
+            y: 0,
+            w: RENDER_WIDTH as i32,
+            h: RENDER_HEIGHT as i32 / 2,
+        };
+        context
+            .player_batch
+            .draw(self.background, background_dst, background_src, true);
+    }
+
+    /// Draws a textured floor, or a ceiling, a row of cells at a time. Each row is as far away
+    /// as a wall whose bottom, or top, would be at that row, and each cell shows whatever part of
+    /// the texture is on the ground where the ray for its column meets that distance. Textures
+    /// fade with distance, and each cell is as lit as the lightmap says that spot on the ground is.
+    fn cast_surface(
+        &self,
+        context: &mut RenderContext,
+        surface: Surface,
+        horizon: i32,
+        shift: i32,
+        ceiling: bool,
+    ) {
+        let rows = if ceiling {
+            horizon
+        } else {
+            RENDER_HEIGHT as i32 - horizon
+        };
+        for row in (0..rows.max(0)).step_by(SURFACE_CELL_HEIGHT as usize) {
+            // A wall at distance d is RENDER_HEIGHT / d tall, centered on the horizon.
+            let from_horizon = row as f32 + SURFACE_CELL_HEIGHT as f32 / 2.0;
+            let distance = RENDER_HEIGHT as f32 / 2.0 / from_horizon;
+            let fade = (distance / SURFACE_FADE_DISTANCE).min(1.0);
+            let fade_light = SURFACE_LIGHT.0 + (SURFACE_LIGHT.1 - SURFACE_LIGHT.0) * fade;
+            let y = if ceiling {
+                horizon - row - SURFACE_CELL_HEIGHT
+            } else {
+                horizon + row
+            };
+            for column in (0..RENDER_WIDTH as i32).step_by(SURFACE_CELL_WIDTH as usize) {
+                let angle = self.ray_angle(column + SURFACE_CELL_WIDTH / 2);
+                // Undo the fisheye correction, to get how far along the ray the ground is.
+                let along = distance / (angle - self.player_angle).cos();
+                let x = self.player_x + angle.cos() * along;
+                let y_in_map = self.player_y + angle.sin() * along;
+                let dest = Rect {
+                    x: column + shift,
+                    y,
+                    w: SURFACE_CELL_WIDTH,
+                    h: SURFACE_CELL_HEIGHT,
+                };
+                let light = self
+                    .map()
+                    .lightmap
+                    .as_ref()
+                    .map_or(1.0, |lightmap| lightmap.level_at(x, y_in_map));
+                // Water tints the floor under it.
+                let tint = if !ceiling && self.map().is_water(x, y_in_map) {
+                    WATER_COLOR
+                } else {
+                    Color::WHITE
+                };
+                match (surface, &self.walls) {
+                    (Surface::Texture(index), Some(walls)) => {
+                        let shade = 255.0 * fade_light * light;
+                        context.player_batch.tint = Color {
+                            r: (shade * tint.r as f32 / 255.0) as u8,
+                            g: (shade * tint.g as f32 / 255.0) as u8,
+                            b: (shade * tint.b as f32 / 255.0) as u8,
+                            a: 0xff,
+                        };
+                        walls.blit_texel(
+                            &mut context.player_batch,
+                            dest,
+                            index,
+                            x - x.floor(),
+                            y_in_map - y_in_map.floor(),
+                        );
+                    }
+                    (Surface::Color(color), _) => {
+                        let color = Color {
+                            r: (color.r as f32 * light * tint.r as f32 / 255.0) as u8,
+                            g: (color.g as f32 * light * tint.g as f32 / 255.0) as u8,
+                            b: (color.b as f32 * light * tint.b as f32 / 255.0) as u8,
+                            a: color.a,
+                        };
+                        context.player_batch.fill_rect(dest, color);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        context.player_batch.tint = Color::WHITE;
+    }
+
+    /// Draws the ghost as a translucent figure standing on the floor, if it's on this floor.
+    fn draw_ghost(&self, context: &mut RenderContext, horizon: i32, shift: i32) {
+        let Some(ghost) = &self.ghost else {
+            return;
+        };
+        if !self.show_ghost || ghost.is_done() || ghost.floor != self.floor {
+            return;
+        }
+        let position = (ghost.body.x, ghost.body.y);
+        let Some((dest, column)) = self.place_shape(position, GHOST_HEIGHT, GHOST_WIDTH, horizon)
+        else {
+            return;
+        };
+        // The head is a circle as wide as the body, on top of it.
+        let body = Rect {
+            x: dest.x + shift,
+            y: dest.y + dest.w,
+            w: dest.w,
+            h: dest.h - dest.w,
+        };
+        context.player_batch.fill_rect(body, GHOST_COLOR);
+        let head = Point::new(column + shift, dest.y + dest.w / 2);
+        context
+            .player_batch
+            .fill_circle(head, dest.w as f32 / 2.0, GHOST_COLOR);
+    }
+
+    /// Draws each explosion's fireball as a circle facing the camera, behind any nearer walls.
+    fn draw_explosions(&self, context: &mut RenderContext, horizon: i32, shift: i32) {
+        for explosion in self.explosions.iter() {
+            let (size, color) = explosion.fireball();
+            let diameter = explosion.radius * size * 2.0;
+            let position = (explosion.x, explosion.y);
+            let Some((dest, column)) = self.place_shape(position, diameter, 1.0, horizon) else {
+                continue;
+            };
+            // The fireball is centered halfway up the walls, which is on the horizon.
+            let radius = dest.h as f32 / 2.0;
+            let center = Point::new(column + shift, horizon);
+            context.player_batch.fill_circle(center, radius, color);
+            context.add_light(center, radius as i32 * 2);
+        }
+
+        let flash = self
+            .explosions
+            .iter()
+            .map(|explosion| explosion.flash(explosion.distance_to(self.player_x, self.player_y)))
+            .fold(0.0, f32::max);
+        if flash > 0.0 {
+            let color = Color {
+                a: (EXPLOSION_FLASH_COLOR.a as f32 * flash).round() as u8,
+                ..EXPLOSION_FLASH_COLOR
+            };
+            let screen = Rect {
+                x: 0,
+                y: 0,
+                w: RENDER_WIDTH as i32,
+                h: RENDER_HEIGHT as i32,
+            };
+            context.player_batch.fill_rect(screen, color);
+        }
+    }
+
+    /// Saves a checkpoint if the player is standing on a checkpoint tile they haven't already used.
+    /// Returns true if a new checkpoint was saved.
+    fn update_checkpoint(&mut self) -> bool {
+        let row = self.player_y as usize;
+        let column = self.player_x as usize;
+        if !matches!(self.map().tile(row, column), Tile::Checkpoint) {
+            return false;
+        }
+        if let Some(checkpoint) = &self.checkpoint {
+            if checkpoint.floor == self.floor
+                && checkpoint.row == row
+                && checkpoint.column == column
+            {
+                return false;
+            }
+        }
+        info!(
+            "reached checkpoint at ({column}, {row}) on floor {}",
+            self.floor
+        );
+        self.checkpoint = Some(CheckpointState {
+            floor: self.floor,
+            row,
+            column,
+            player: self.player_state(),
+        });
+        true
+    }
+
+    /// Plays the sounds from this frame where they happened, as the player hears them.
+    fn play_sounds(&mut self, sounds: &mut SoundManager) {
+        sounds.set_listener(Listener {
+            x: self.player_x,
+            y: self.player_y,
+            angle: self.player_angle,
+        });
+        for (sound, x, y) in self.pending_sounds.drain(..) {
+            sounds.play_at(sound, x, y);
+        }
+    }
+
+    /// Fires the script event for each trigger area the player just walked into.
+    fn update_triggers(&mut self, sounds: &mut SoundManager) {
+        let (x, y) = (self.player_x, self.player_y);
+        let mut events = Vec::new();
+        for (area, inside) in self.trigger_areas.iter_mut() {
+            let was_inside = mem::replace(inside, area.contains(x, y));
+            if *inside && !was_inside {
+                events.push(format!("trigger_{}", area.name));
+            }
+        }
+        for event in events {
+            self.fire_script_event(&event, sounds);
+        }
+    }
+
+    /// Runs the level script's handler for the given event, if there is one.
+    #[cfg(feature = "rhai")]
+    fn fire_script_event(&mut self, event: &str, sounds: &mut SoundManager) {
+        let player = self.player_state();
+        let enemies = self.enemies.iter().map(ScriptEnemy::from).collect();
+        let Some(script) = &mut self.script else {
+            return;
+        };
+        match script.call_event(event, player, enemies) {
+            Ok(output) => {
+                for door in output.doors {
+                    self.change_door(door);
+                }
+                for sound in output.sounds {
+                    sounds.play(sound);
+                }
+                for message in output.messages {
+                    self.pending_messages
+                        .push((MessageKind::Dialog, message.clone()));
+                    self.pending_narration.push(message.clone());
+                    self.message = Some((message, MESSAGE_FRAMES));
+                }
+            }
+            Err(e) => error!("{}", e),
+        }
+    }
+
+    #[cfg(not(feature = "rhai"))]
+    fn fire_script_event(&mut self, _event: &str, _sounds: &mut SoundManager) {}
+
+    /// Opens or closes a door on the player's floor for the level script. A door can't close on
+    /// the player.
+    #[cfg(feature = "rhai")]
+    fn change_door(&mut self, door: DoorChange) {
+        let DoorChange { row, column, open } = door;
+        if !open && (row, column) == (self.player_y as usize, self.player_x as usize) {
+            error!("script error: can't close the door at ({column}, {row}) on the player");
+            return;
+        }
+        if !self.floors[self.floor].set_door(row, column, open) {
+            error!("script error: no door at ({column}, {row})");
+        }
+    }
+
+    fn player_state(&self) -> PlayerState {
+        PlayerState {
+            x: self.player_x,
+            y: self.player_y,
+            angle: self.player_angle,
+        }
+    }
+
+    fn set_player_state(&mut self, player: PlayerState) {
+        self.player_x = player.x;
+        self.player_y = player.y;
+        self.player_angle = player.angle;
+        self.stream_map();
+        self.cast_rays();
+    }
+
+    /// Casts a ray from a point, following it off mirrors and through portals.
+    fn project(
+        &self,
+        angle: f32,
+        x: f32,
+        y: f32,
+        path: &mut Option<FrameArena<PathIndex>>,
+    ) -> Option<Projection> {
+        let map = self.map();
+        let raycaster = Raycaster::new(map.width, map.height);
+        let mut ray = Ray::from_point(x, y, angle);
+        let mut distance = 0.0;
+        let mut light = 1.0;
+        // The tile the ray starts in never stops it, so it can leave the far end of a portal.
+        let mut skip = true;
+        for bounce in 0..=MAX_RAY_BOUNCES {
+            let hit = raycaster.cast(ray, skip, |row, column| {
+                if let Some(path) = path.as_mut() {
+                    path.push(PathIndex { row, column });
+                }
+                map.tile(row, column).stops_ray()
+            })?;
+            let (start_x, start_y) = (ray.column as f32 + ray.x, ray.row as f32 + ray.y);
+            distance += ((hit.x - start_x).powi(2) + (hit.y - start_y).powi(2)).sqrt();
+
+            let tile = *map.tile(hit.row, hit.column);
+            match tile {
+                Tile::Mirror if bounce < MAX_RAY_BOUNCES => {
+                    // Reflect across the mirror's face, and start again just in front of it.
+                    let angle = (2.0 * hit.normal + PI - ray.angle).rem_euclid(TAU);
+                    let start_x = hit.x + hit.normal.cos() * TOLERANCE;
+                    let start_y = hit.y + hit.normal.sin() * TOLERANCE;
+                    ray = Ray::from_point(start_x, start_y, angle);
+                    light *= MIRROR_REFLECTANCE;
+                    skip = false;
+                }
+                Tile::Portal {
+                    row: to_row,
+                    column: to_column,
+                } if bounce < MAX_RAY_BOUNCES => {
+                    // Come out of the linked tile at the same place the ray went into this one.
+                    ray = Ray {
+                        row: to_row,
+                        column: to_column,
+                        x: hit.x - hit.column as f32,
+                        y: hit.y - hit.row as f32,
+                        angle: ray.angle,
+                    };
+                    skip = true;
+                }
+                _ => {
+                    // The face is lit like the cell in front of it, since the wall's own cell is
+                    // solid.
+                    if let Some(lightmap) = &map.lightmap {
+                        let front_x = hit.x + hit.normal.cos() * TOLERANCE;
+                        let front_y = hit.y + hit.normal.sin() * TOLERANCE;
+                        light *= lightmap.level_at(front_x, front_y);
+                    }
+                    let color = tile.wall_color();
+                    let texture = match tile {
+                        Tile::Textured(index) => Some(index),
+                        _ => None,
+                    };
+                    return Some(Projection {
+                        x: hit.x,
+                        y: hit.y,
+                        color: Color {
+                            r: (color.r as f32 * light) as u8,
+                            g: (color.g as f32 * light) as u8,
+                            b: (color.b as f32 * light) as u8,
+                            a: color.a,
+                        },
+                        normal: hit.normal,
+                        distance,
+                        angle: ray.angle,
+                        texture,
+                        texture_x: hit.texture_x(),
+                        light,
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Scene for Level {
+    fn update(
+        &mut self,
+        context: &RenderContext,
+        inputs: &InputSnapshot,
+        sounds: &mut SoundManager,
+    ) -> SceneResult {
+        if !self.started {
+            self.started = true;
+            self.pending_messages
+                .push((MessageKind::System, format!("entered {}", self.title)));
+            self.pending_prompts.push(MOVE_PROMPT.to_string());
+            self.pending_prompts.push(FIRE_PROMPT.to_string());
+            self.fire_script_event("start", sounds);
+        }
+
+        self.apply_item_actions(sounds);
+
+        if inputs.cancel_clicked {
+            return SceneResult::PushPause;
+        }
+        if inputs.inventory_clicked {
+            return SceneResult::PushInventory;
+        }
+        self.clock.tick();
+        if let Some(weather) = &mut self.weather {
+            weather.update(context.logical_area(), sounds);
+        }
+        if inputs.ok_clicked {
+            return SceneResult::PushKillScreen {
+                text: format!("hello world"),
+            };
+        }
+
+        // Only dashes that actually happen are recorded, so the ghost doesn't have to know what
+        // the player had unlocked.
+        let dashed =
+            inputs.player_dash_clicked && self.abilities.has(Ability::Dash) && self.dash.start();
+        if let Some(recording) = &mut self.recording {
+            let inputs = InputSnapshot {
+                player_dash_clicked: dashed,
+                ..movement_inputs(inputs)
+            };
+            recording.record(self.run_frames, &inputs);
+        }
+        // The recording only keeps one sensitivity, so changing it part way through would make
+        // the ghost turn differently than the player did.
+        let run_sensitivity = *self.run_sensitivity.get_or_insert(self.mouse_sensitivity);
+        if run_sensitivity != self.mouse_sensitivity {
+            self.recording = None;
+        }
+        self.run_frames += 1;
+        if self.debug.any() {
+            // Runs with debug flags on don't count as a best run.
+            self.recording = None;
+        }
+        let mut body = self.map().move_body(
+            self.player_state(),
+            inputs,
+            self.mouse_sensitivity,
+            self.debug.noclip,
+        );
+        if self.dash.update() {
+            body = self.map().dash_body(body);
+        }
+        (self.player_x, self.player_y, self.player_angle) = (body.x, body.y, body.angle);
+        self.follow_links();
+        self.update_pickups();
+        self.update_focused_target();
+        self.update_ghost();
+        if self.floors.len() > 1 && self.floor + 1 == self.floors.len() {
+            self.finish_run();
+            if !mem::replace(&mut self.ended, true) {
+                return SceneResult::PushEpilogue {
+                    run: self.run_summary(),
+                };
+            }
+        }
+        if self.show_breadcrumbs {
+            self.breadcrumbs
+                .record(self.floor, self.player_x, self.player_y);
+        }
+        // Walking through walls would only muddy the heatmap.
+        if !self.debug.any() {
+            self.heatmap
+                .record_walk(self.floor, self.player_y as usize, self.player_x as usize);
+        }
+        if self.run_frames.is_multiple_of(HEATMAP_SAVE_FRAMES) {
+            self.heatmap_due = true;
+        }
+
+        self.update_oxygen();
+        if self.health <= 0.0 {
+            return self.die("drowned");
+        }
+
+        if inputs.mouse_button_left_down && !self.fire_was_down {
+            self.fire();
+        }
+        self.fire_was_down = inputs.mouse_button_left_down;
+        self.update_rockets();
+        self.play_sounds(sounds);
+        self.update_spawners();
+        self.explosions.retain_mut(Explosion::update);
+        if self.health <= 0.0 {
+            return self.die("blown up");
+        }
+        if self.update_enemies() && !self.debug.god {
+            return self.die("caught");
+        }
+
+        if let Some((frames, _)) = &mut self.floor_transition {
+            *frames -= 1;
+            if *frames == 0 {
+                self.floor_transition = None;
+            }
+        }
+        self.stream_map();
+        self.cast_rays();
+        self.update_route();
+        self.run_background();
+
+        // Each area only prompts once per visit to the level, even if the tutorial is off.
+        let (x, y) = (self.player_x, self.player_y);
+        let pending_prompts = &mut self.pending_prompts;
+        self.prompt_areas.retain(|area| {
+            if area.contains(x, y) {
+                pending_prompts.push(area.name.clone());
+                return false;
+            }
+            true
+        });
+        self.update_triggers(sounds);
+
+        if self.update_checkpoint() {
+            self.pending_messages
+                .push((MessageKind::System, "checkpoint".to_string()));
+            self.pending_prompts.push(CHECKPOINT_PROMPT.to_string());
+            self.fire_script_event("checkpoint", sounds);
+        }
+
+        if let Some((_, frames)) = &mut self.message {
+            *frames -= 1;
+            if *frames == 0 {
+                self.message = None;
+            }
+        }
+        if let Some(card) = &mut self.title_card {
+            if !card.update() {
+                self.title_card = None;
+            }
+        }
+
+        SceneResult::Continue
+    }
+
+    fn name(&self) -> &str {
+        "level"
+    }
+
+    fn clock(&self) -> Option<&GameClock> {
+        Some(&self.clock)
+    }
+
+    fn postprocess(&self) -> PostprocessProfile {
+        if !self.is_submerged() {
+            return PostprocessProfile::RETRO;
+        }
+        PostprocessProfile {
+            underwater: 1.0,
+            ..PostprocessProfile::RETRO
+        }
+    }
+
+    fn captures_mouse(&self) -> bool {
+        self.mouse_look
+    }
+
+    fn muffles_sound(&self) -> bool {
+        self.is_submerged()
+    }
+
+    fn hash_state(&self, hasher: &mut dyn Hasher) {
+        hasher.hash_usize(self.floor);
+        hasher.hash_u64(self.run_frames);
+        hasher.hash_u32(self.kills);
+        self.abilities.hash_state(hasher);
+        self.dash.hash_state(hasher);
+        hasher.hash_usize(self.pickups.len());
+        hasher.hash_usize(self.inventory.slots().len());
+        for (name, count) in self.inventory.slots() {
+            hasher.hash_str(name);
+            hasher.hash_u32(*count);
+        }
+        hasher.hash_usize(self.targets.len());
+        for (target, _) in self.targets.iter() {
+            hasher.hash_str(&target.name);
+            hasher.hash_usize(target.accepts.len());
+            for item in target.accepts.iter() {
+                hasher.hash_str(item);
+            }
+        }
+        hasher.hash_f32s(&[
+            self.player_x,
+            self.player_y,
+            self.player_angle,
+            self.health,
+            self.oxygen,
+        ]);
+        hasher.hash_usize(self.enemies.len());
+        for enemy in self.enemies.iter() {
+            let billboard = &enemy.billboard;
+            hasher.hash_usize(billboard.floor);
+            hasher.hash_u32(enemy.state as u32);
+            hasher.hash_f32s(&[billboard.x, billboard.y, enemy.health]);
+        }
+        hasher.hash_usize(self.rockets.len());
+        for rocket in self.rockets.iter() {
+            hasher.hash_f32s(&[rocket.x, rocket.y, rocket.angle]);
+        }
+        hasher.hash_usize(self.explosions.len());
+        for explosion in self.explosions.iter() {
+            hasher.hash_f32s(&[explosion.x, explosion.y]);
+        }
+        for map in self.floors.iter() {
+            for cells in [&map.destroyed, &map.opened] {
+                let words = cells.words();
+                hasher.hash_usize(words.len());
+                for word in words {
+                    hasher.hash_u64(*word);
                 }
-            } else {
-                // it hit the right.
-                let y_intercept = y - (1.0 - x) * up_angle.tan();
-                self.project2(angle, row, column + 1, 0.0, y_intercept, PI, path)
             }
         }
+        hasher.hash_bool(self.checkpoint.is_some());
+        if let Some(checkpoint) = self.checkpoint {
+            hasher.hash_usize(checkpoint.floor);
+            hasher.hash_usize(checkpoint.row);
+            hasher.hash_usize(checkpoint.column);
+        }
     }
-}
 
-impl Scene for Level {
-    fn update(
-        &mut self,
-        context: &RenderContext,
-        inputs: &InputSnapshot,
-        sounds: &mut SoundManager,
-    ) -> SceneResult {
-        if inputs.ok_clicked {
-            return SceneResult::PushKillScreen {
-                text: format!("hello world"),
-            };
+    fn apply_cvars(&mut self, cvars: &Cvars) {
+        self.show_breadcrumbs = cvars.get_parsed(BREADCRUMBS_CVAR).unwrap_or(true);
+        self.route_guide = cvars.get_parsed(ROUTE_GUIDE_CVAR).unwrap_or_default();
+        self.show_ghost = cvars.get_parsed(GHOST_CVAR).unwrap_or(true);
+        self.show_heatmap = cvars.get_parsed(HEATMAP_CVAR).unwrap_or(false);
+        self.debug = DebugFlags::from_cvars(cvars);
+        self.aim_assist = AimAssist::from_cvars(cvars);
+        self.hostility = Hostility::from_cvars(cvars);
+        let difficulty = cvars.get_parsed(DIFFICULTY_CVAR).unwrap_or_default();
+        if difficulty != self.difficulty {
+            self.difficulty = difficulty;
+            self.refresh_enemy_stats();
         }
+        self.mouse_look = cvars.get_parsed(MOUSE_LOOK_CVAR).unwrap_or(true);
+        let mouse_sensitivity: f32 = cvars.get_parsed(MOUSE_SENSITIVITY_CVAR).unwrap_or(1.0);
+        self.mouse_sensitivity = mouse_sensitivity.clamp(0.0, MAX_MOUSE_SENSITIVITY);
+        self.player_name = cvars
+            .get(PLAYER_NAME_CVAR)
+            .unwrap_or(DEFAULT_PLAYER_NAME)
+            .to_string();
+    }
 
-        if inputs.player_turn_left_down {
-            self.player_angle -= TURN_SPEED;
-        }
-        if inputs.player_turn_right_down {
-            self.player_angle += TURN_SPEED;
+    fn reload_data(&mut self, files: &FileManager) {
+        if !self.run_frames.is_multiple_of(DATA_RELOAD_FRAMES) {
+            return;
         }
-        while self.player_angle >= TAU {
-            self.player_angle -= TAU;
-        }
-        while self.player_angle < 0.0 {
-            self.player_angle += TAU;
+        if self.enemy_stats.reload(files) {
+            self.refresh_enemy_stats();
         }
+        self.items.reload(files);
+    }
 
-        let x_component = self.player_angle.cos();
-        let y_component = self.player_angle.sin();
-        let mut dx = 0.0;
-        let mut dy = 0.0;
-        if inputs.player_forward_down {
-            dx += MOVE_SPEED * x_component;
-            dy += MOVE_SPEED * y_component;
+    fn flush_user_data(&mut self, files: &FileManager) {
+        if mem::take(&mut self.heatmap_due) {
+            if let Err(e) = self.heatmap.flush(files, &self.title) {
+                error!("unable to save heatmap: {}", e);
+            }
         }
-        if inputs.player_backward_down {
-            dx -= MOVE_SPEED * x_component;
-            dy -= MOVE_SPEED * y_component;
+        let Some(run) = self.pending_ghost.take() else {
+            return;
+        };
+        let path = ghost_path(&self.title);
+        match files.write_user_data(&path, run.to_text().as_bytes()) {
+            Ok(()) => info!("saved ghost to {}", path),
+            Err(e) => error!("unable to save ghost: {}", e),
         }
-        if inputs.player_strafe_left_down {
-            dx += MOVE_SPEED * y_component;
-            dy -= MOVE_SPEED * x_component;
+    }
+
+    fn submit_scores(&mut self, leaderboard: &mut dyn LeaderboardBackend) {
+        let Some(entry) = self.pending_score.take() else {
+            return;
+        };
+        if let Some(rank) = leaderboard.submit(&self.title, entry) {
+            self.pending_messages
+                .push((MessageKind::System, format!("leaderboard #{}", rank + 1)));
         }
-        if inputs.player_strafe_right_down {
-            dx -= MOVE_SPEED * y_component;
-            dy += MOVE_SPEED * x_component;
+    }
+
+    fn flush_messages(&mut self, log: &mut MessageLog, frame: u64) {
+        for (kind, text) in self.pending_messages.drain(..) {
+            log.push(frame, kind, &text);
         }
-        if self.can_move_to(self.player_x, self.player_y + dy) {
-            self.player_y += dy;
+    }
+
+    fn flush_narration(&mut self, narrator: &mut dyn Narrator) {
+        for text in self.pending_narration.drain(..) {
+            narrator.narrate(&text);
         }
-        if self.can_move_to(self.player_x + dx, self.player_y) {
-            self.player_x += dx;
+    }
+
+    fn flush_prompts(&mut self, tutorial: &mut Tutorial) {
+        for name in self.pending_prompts.drain(..) {
+            tutorial.prompt(&name);
         }
+    }
 
-        SceneResult::Continue
+    fn inventory(&self) -> Option<&Inventory> {
+        Some(&self.inventory)
+    }
+
+    fn item_target(&self) -> Option<&ItemTarget> {
+        Some(&self.targets[self.find_focused_target()?].0)
+    }
+
+    fn queue_item_action(&mut self, action: ItemAction, item: &str) {
+        self.pending_item_actions.push((action, item.to_string()));
+    }
+
+    fn respawn_at_checkpoint(&mut self) -> bool {
+        let Some(checkpoint) = self.checkpoint else {
+            return false;
+        };
+        self.set_floor(checkpoint.floor);
+        self.set_player_state(checkpoint.player);
+        self.health = PLAYER_MAX_HEALTH;
+        self.oxygen = MAX_OXYGEN;
+        self.respawns += 1;
+        // Jumping back to a checkpoint would make the run impossible to play back.
+        self.recording = None;
+        true
+    }
+
+    fn save_state(&self) -> Option<LevelState> {
+        let mut state = LevelState::new(self.player_state(), self.checkpoint);
+        state.floor = self.floor;
+        Some(state)
+    }
+
+    fn entities(&self) -> Option<Vec<EntityInfo>> {
+        let info = |category, name, billboard: &Billboard| EntityInfo {
+            category,
+            name,
+            floor: billboard.floor,
+            x: billboard.x,
+            y: billboard.y,
+            state: None,
+            health: None,
+        };
+        let enemies = self.enemies.iter().map(|enemy| EntityInfo {
+            state: Some(enemy.state.to_string()),
+            health: Some(enemy.health),
+            ..info("enemy", Some(enemy.kind.clone()), &enemy.billboard)
+        });
+        let pickups = self
+            .pickups
+            .iter()
+            .map(|pickup| info("pickup", Some(pickup.kind.to_string()), &pickup.billboard));
+        let billboards = self
+            .billboards
+            .iter()
+            .map(|billboard| info("billboard", None, billboard));
+        Some(enemies.chain(pickups).chain(billboards).collect())
+    }
+
+    fn restore_state(&mut self, state: &LevelState) -> bool {
+        self.set_floor(state.floor);
+        self.set_player_state(state.player);
+        self.health = PLAYER_MAX_HEALTH;
+        self.oxygen = MAX_OXYGEN;
+        self.recording = None;
+        self.checkpoint = state.checkpoint;
+        true
     }
 
     fn draw(&self, context: &mut RenderContext, font: &Font, previous: Option<&dyn Scene>) {
@@ -385,74 +2841,30 @@ impl Scene for Level {
         //let bgcolor = Color::from_str("#00333c").unwrap();
         let bgcolor = Color::from_str("#333333").unwrap();
         context.player_batch.fill_rect(screen, bgcolor);
-
-        // Draw the background.
-        let background_fraction = if self.player_angle < PI {
-            -1.0 * self.player_angle / PI
-        } else {
-            1.0 - (self.player_angle - PI) / PI
-        };
-        let background_offset = (RENDER_WIDTH as f32 * background_fraction) as i32;
-
-        let background_src = Rect {
-            x: 0,
-            y: 0,
-            w: 640,
-            h: (RENDER_HEIGHT as i32 / 2).max(400),
-        };
-        let background_dst = Rect {
-            x: background_offset,
-            y: 0,
-            w: RENDER_WIDTH as i32,
-            h: RENDER_HEIGHT as i32 / 2,
-        };
-        context
-            .player_batch
-            .draw(self.background, background_dst, background_src, false);
-
-        let background_dst = Rect {
-            x: if background_dst.x < 0 {
-                background_dst.x + RENDER_WIDTH as i32
-            } else {
-                background_dst.x - RENDER_WIDTH as i32
-            },
-            y: 0,
-            w: RENDER_WIDTH as i32,
-            h: RENDER_HEIGHT as i32 / 2,
-        };
-        context
-            .player_batch
-            .draw(self.background, background_dst, background_src, true);
+        if let Some(light) = &self.ambient_light {
+            context.ambient_light = light.color_at(self.clock.time_of_day());
+        }
 
         // draw the 3d version.
-        for column in 0..640 {
-            let angle = ((column as f32) / 640.0) * FRAC_PI_2;
-            let angle = angle - (PI / 4.0);
-            let mut angle = self.player_angle + angle;
-            while angle >= PI * 2.0 {
-                angle -= PI * 2.0;
-            }
-            while angle < 0.0 {
-                angle += PI * 2.0;
-            }
+        let (floor_shift, floor_fade) = self.floor_transition_effect();
+        let shake = self.shake();
+        let horizon = RENDER_HEIGHT as i32 / 2 + floor_shift + shake.1;
+        self.draw_surfaces(context, horizon, shake.0);
+        for (column, ray) in self.rays.iter().enumerate() {
+            let column = column as i32;
 
-            if let Some(projection) = self.project(angle, self.player_x, self.player_y, &mut None) {
-                // Scale for distance.
-                let distance = ((self.player_x - projection.x) * (self.player_x - projection.x)
-                    + (self.player_y - projection.y) * (self.player_y - projection.y))
-                    .sqrt();
-                // Remove fisheye effect.
-                let distance = distance * (self.player_angle - angle).cos();
+            if let Some(projection) = ray {
+                // Scale for distance, which is along the ray, in case it bounced off a mirror.
+                let distance = self.depth.depth(column);
 
                 // TODO: Use a numerator other than 1?
                 let scale = if distance < 1.0 { 1.0 } else { 1.0 / distance };
                 let height = (RENDER_HEIGHT as f32 * scale) as i32;
-                let offset = (RENDER_HEIGHT as i32 - height) / 2;
+                let offset = (RENDER_HEIGHT as i32 - height) / 2 + floor_shift + shake.1;
+                let column = column + shake.0;
 
-                // Compute factor for diffuse lighting.
-                let projection_dx = self.player_x - projection.x;
-                let projection_dy = self.player_y - projection.y;
-                let projection_angle = projection_dy.atan2(projection_dx);
+                // Compute factor for diffuse lighting, looking back along the ray's last leg.
+                let projection_angle = projection.angle + PI;
                 let angle_diff = (projection_angle - projection.normal).abs();
                 let diffusion = angle_diff.cos().clamp(0.5, 1.0);
 
@@ -469,18 +2881,44 @@ impl Scene for Level {
                     a: projection.color.a,
                 };
 
-                context.player_batch.draw_line(
-                    Point {
-                        x: column,
-                        y: offset,
-                    },
-                    Point {
-                        x: column,
-                        y: offset + height,
-                    },
-                    color,
-                    1,
-                );
+                match (projection.texture, &self.walls) {
+                    (Some(index), Some(walls)) => {
+                        let shade = (255.0 * light * projection.light) as u8;
+                        context.player_batch.tint = Color {
+                            r: shade,
+                            g: shade,
+                            b: shade,
+                            a: 0xff,
+                        };
+                        let dest = Rect {
+                            x: column,
+                            y: offset,
+                            w: 1,
+                            h: height,
+                        };
+                        walls.blit_column(
+                            &mut context.player_batch,
+                            dest,
+                            index,
+                            projection.texture_x,
+                        );
+                        context.player_batch.tint = Color::WHITE;
+                    }
+                    _ => {
+                        context.player_batch.draw_line(
+                            Point {
+                                x: column,
+                                y: offset,
+                            },
+                            Point {
+                                x: column,
+                                y: offset + height,
+                            },
+                            color,
+                            1,
+                        );
+                    }
+                }
 
                 let reflection_height = height / 3;
                 let mut reflection_color = color;
@@ -500,24 +2938,127 @@ impl Scene for Level {
             }
         }
 
+        let camera = (self.player_x, self.player_y, self.player_angle);
+        let texts = self
+            .world_texts
+            .iter()
+            .filter(|text| text.floor == self.floor);
+        let billboards = self
+            .billboards
+            .iter()
+            .chain(self.pickups.iter().map(|pickup| &pickup.billboard))
+            .chain(self.enemies.iter().map(|enemy| &enemy.billboard))
+            .filter(|billboard| billboard.floor == self.floor);
+        draw_world_text(
+            &mut context.player_batch,
+            texts,
+            billboards,
+            font,
+            camera,
+            horizon,
+            shake.0,
+            &self.depth,
+        );
+        self.draw_ghost(context, horizon, shake.0);
+        self.draw_explosions(context, horizon, shake.0);
+
+        if floor_fade > 0.0 {
+            let fade = Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: (255.0 * floor_fade).round() as u8,
+            };
+            context.player_batch.fill_rect(screen, fade);
+        }
+
+        if let Some(weather) = &self.weather {
+            weather.draw(context);
+        }
+
         // Draw the 2d version.
         let player_size = 1.0;
         let vision_distance = 15.0;
         let w = 2;
         let h = 2;
         let empty_color = Color::from_str("#000000").unwrap();
-        for (i, row) in self.map.tiles.iter().enumerate() {
+        let checkpoint_color = Color::from_str("#00ff00").unwrap();
+        let unexplored_color = Color::from_str("#202020").unwrap();
+        for (i, j, _) in self.map().grid.loaded_tiles() {
+            // Look it up through the map, in case an explosion destroyed it.
+            let tile = self.map().tile(i, j);
             let y = i as i32 * h;
-            for (j, tile) in row.iter().enumerate() {
-                let x = j as i32 * w;
-                let rect = Rect { x, y, w, h };
-                let color = match tile {
+            let x = j as i32 * w;
+            let rect = Rect { x, y, w, h };
+            // Only show the cells the player has actually seen.
+            let color = if !self.map().visited.contains(i * self.map().width + j) {
+                &unexplored_color
+            } else {
+                match tile {
                     Tile::Empty => &empty_color,
-                    Tile::Solid(color) => color,
+                    Tile::Solid(color) | Tile::Cracked(color) => color,
+                    Tile::Textured(index) => WALL_TEXTURE_COLORS
+                        .get(*index as usize)
+                        .unwrap_or(&Color::WHITE),
+                    Tile::Barrel => &BARREL_COLOR,
+                    Tile::Checkpoint => &checkpoint_color,
+                    Tile::Mirror => &MIRROR_COLOR,
+                    Tile::Portal { .. } => &PORTAL_COLOR,
+                    Tile::Stairs { .. } => &STAIRS_COLOR,
+                    Tile::Water => &WATER_COLOR,
+                    Tile::Door => &DOOR_COLOR,
+                }
+            };
+            context.player_batch.fill_rect(rect, *color);
+        }
+
+        if self.show_heatmap {
+            for (i, j, _) in self.map().grid.loaded_tiles() {
+                let rect = Rect {
+                    x: j as i32 * w,
+                    y: i as i32 * h,
+                    w,
+                    h,
+                };
+                if self.heatmap.deaths(self.floor, i, j) > 0 {
+                    context.player_batch.fill_rect(rect, DEATH_COLOR);
+                } else if self.heatmap.walks(self.floor, i, j) > 0 {
+                    let color = Color {
+                        a: HEATMAP_ALPHA,
+                        ..heat_color(self.heatmap.heat(self.floor, i, j))
+                    };
+                    context.player_batch.fill_rect(rect, color);
+                }
+            }
+        }
+
+        if self.show_breadcrumbs {
+            // Older crumbs fade out, so the trail shows which way the player went.
+            let count = self.breadcrumbs.len() as f32;
+            for (i, crumb) in self.breadcrumbs.crumbs().enumerate() {
+                if crumb.floor != self.floor {
+                    continue;
+                }
+                let age = 1.0 - i as f32 / count;
+                let color = Color {
+                    a: (BREADCRUMB_COLOR.a as f32 * (1.0 - 0.75 * age)) as u8,
+                    ..BREADCRUMB_COLOR
                 };
-                context.player_batch.fill_rect(rect, *color);
+                let x = (crumb.x * w as f32) as i32;
+                let y = (crumb.y * h as f32) as i32;
+                context
+                    .player_batch
+                    .fill_rect(Rect { x, y, w: 1, h: 1 }, color);
             }
         }
+        if self.route.len() > 1 {
+            let points: Vec<Point<i32>> = self
+                .route
+                .iter()
+                .map(|&(i, j)| Point::new(j as i32 * w + w / 2, i as i32 * h + h / 2))
+                .collect();
+            context.player_batch.draw_polyline(&points, ROUTE_COLOR, 1);
+        }
 
         let player_color = Color::from_str("#ffffff").unwrap();
         context.player_batch.fill_circle(
@@ -545,7 +3086,7 @@ impl Scene for Level {
 
         // draw a single line point.
         let looking_color = Color::from_str("#FFFFFF").unwrap();
-        let mut path = Some(Vec::new());
+        let mut path = Some(FrameArena::new());
         let maybe_projection =
             self.project(self.player_angle, self.player_x, self.player_y, &mut path);
         let path_color = Color::from_str("#44ffffff").unwrap();
@@ -569,5 +3110,747 @@ impl Scene for Level {
                 1,
             );
         }
+
+        if let Some((message, _)) = &self.message {
+            Level::draw_centered_text(context, font, MESSAGE_TEXT_Y, message);
+        }
+        if let Some(text) = self.target_text() {
+            Level::draw_centered_text(context, font, TARGET_TEXT_Y, &text);
+        }
+        // Meters are only shown while they aren't full.
+        let mut row = 0;
+        if self.oxygen < MAX_OXYGEN {
+            let fraction = self.oxygen / MAX_OXYGEN;
+            Level::draw_meter(context, font, row, "air", fraction, WATER_COLOR);
+            row += 1;
+        }
+        if self.abilities.has(Ability::Dash) && !self.dash.is_ready() {
+            let fraction = 1.0 - self.dash.cooldown_fraction();
+            Level::draw_meter(context, font, row, "dash", fraction, DASH_COLOR);
+        }
+        if let Some(card) = &self.title_card {
+            card.draw(context, font);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::debugflags::{GOD_CVAR, NOCLIP_CVAR};
+    use crate::imagemanager::ImageManager;
+    use crate::lightmap::StaticLight;
+    use crate::rendercontext::SpriteBatchEntry;
+    use crate::soundmanager::recording_sound_manager;
+
+    /// A map of walls (#), floors (.), mirrors (M), barrels (B), cracked walls (C), textured walls
+    /// (T), water (~), and stairs to the floor with the given digit, with the given portal pairs.
+    fn test_map(rows: &[&str], portals: &[[(usize, usize); 2]]) -> Map {
+        let mut rows: Vec<Vec<Tile>> = rows
+            .iter()
+            .map(|row| {
+                row.chars()
+                    .map(|c| match c {
+                        '#' => Tile::Solid(Color::WHITE),
+                        'M' => Tile::Mirror,
+                        'B' => Tile::Barrel,
+                        'C' => Tile::Cracked(Color::WHITE),
+                        'T' => Tile::Textured(0),
+                        '~' => Tile::Water,
+                        'D' => Tile::Door,
+                        c if c.is_ascii_digit() => Tile::Stairs {
+                            floor: c as usize - '0' as usize,
+                        },
+                        _ => Tile::Empty,
+                    })
+                    .collect()
+            })
+            .collect();
+        for [a, b] in portals {
+            rows[a.0][a.1] = Tile::Portal {
+                row: b.0,
+                column: b.1,
+            };
+            rows[b.0][b.1] = Tile::Portal {
+                row: a.0,
+                column: a.1,
+            };
+        }
+        Map::from_rows(rows).unwrap()
+    }
+
+    fn test_billboard(x: f32, y: f32) -> Billboard {
+        let area = Rect {
+            x: 0,
+            y: 0,
+            w: 64,
+            h: 64,
+        };
+        Billboard {
+            floor: 0,
+            x,
+            y,
+            size: 0.5,
+            sprite: Sprite { id: 0, area },
+            source: area,
+            reversed: false,
+            faction: None,
+            spawner: None,
+            tint: Color::WHITE,
+            flash: Color::TRANSPARENT,
+        }
+    }
+
+    fn test_pickup(kind: PickupKind, x: f32, y: f32) -> Pickup {
+        let billboard = test_billboard(x, y);
+        Pickup { kind, billboard }
+    }
+
+    /// A level with a single floor made by `test_map`, with the player at (1.5, 1.5).
+    fn test_level(rows: &[&str], portals: &[[(usize, usize); 2]]) -> Level {
+        let files = FileManager::from_memory(HashMap::new()).unwrap();
+        let mut images = ImageManager::null_manager();
+        let mut level = Level::with_seed(random(), &files, &mut images).unwrap();
+        level.floors = vec![test_map(rows, portals)];
+        level.floor = 0;
+        level.set_player_state(PlayerState {
+            x: 1.5,
+            y: 1.5,
+            angle: 0.0,
+        });
+        level
+    }
+
+    #[test]
+    fn from_tilemap() {
+        let mut map = HashMap::new();
+        map.insert(
+            PathBuf::from("assets/walls.tsx"),
+            include_bytes!("../../assets/walls.tsx").to_vec(),
+        );
+        map.insert(
+            PathBuf::from("assets/levels/corridors.tmx"),
+            include_bytes!("../../assets/levels/corridors.tmx").to_vec(),
+        );
+        let files = FileManager::from_memory(map).unwrap();
+        let mut images = ImageManager::null_manager();
+        let path = Path::new("assets/levels/corridors.tmx");
+        let level = Level::from_tilemap(path, &files, &mut images).unwrap();
+
+        assert_eq!(level.title, "corridors");
+        assert_eq!(level.start, (0, 1, 1));
+        assert_eq!((level.player_x, level.player_y), (1.5, 1.5));
+        assert!((level.player_angle - FRAC_PI_2).abs() < 0.01);
+        assert_eq!(level.surfaces.floor, Surface::Texture(3));
+        assert!(matches!(
+            level.map().tile(0, 0),
+            Tile::Textured(STONE_TEXTURE)
+        ));
+        assert!(matches!(level.map().tile(1, 7), Tile::Textured(0)));
+        assert!(matches!(level.map().tile(1, 1), Tile::Empty));
+        let lightmap = level.map().lightmap.as_ref().unwrap();
+        assert_eq!(lightmap.level_at(14.5, 1.5), DEFAULT_LIGHTMAP_AMBIENT);
+        assert!(lightmap.level_at(4.5, 8.5) > DEFAULT_LIGHTMAP_AMBIENT);
+        // The socket is drawn like any other billboard.
+        assert_eq!(level.billboards.len(), 2);
+        let crate_billboard = level.billboards[0];
+        assert_eq!(
+            (crate_billboard.x, crate_billboard.y, crate_billboard.size),
+            (8.5, 8.75, 0.5)
+        );
+        assert_eq!(level.pickups.len(), 3);
+        assert_eq!(level.pickups[0].kind, PickupKind::Ability(Ability::Dash));
+        assert_eq!(
+            level.pickups[1].kind,
+            PickupKind::Item("medkit".to_string())
+        );
+        assert_eq!(
+            (level.pickups[0].billboard.x, level.pickups[0].billboard.y),
+            (12.5, 4.75)
+        );
+        assert_eq!(level.targets.len(), 1);
+        let (socket, billboard) = &level.targets[0];
+        assert_eq!(socket.accepts, ["crank"]);
+        assert_eq!((billboard.x, billboard.y), (14.5, 8.75));
+    }
+
+    #[test]
+    fn mirrors_and_portals() {
+        // The ray bounces off the mirror and comes back to the wall behind the player.
+        let level = test_level(&["#####", "#..M#", "#####"], &[]);
+        let projection = level.project(0.0, 1.5, 1.5, &mut None).unwrap();
+        assert!((projection.distance - 3.5).abs() < 0.01);
+        assert!((projection.x - 1.0).abs() < 0.01);
+        assert_eq!(projection.color.r, (255.0 * MIRROR_REFLECTANCE) as u8);
+
+        // Two mirrors facing each other run out of bounces.
+        let level = test_level(&["######", "#M..M#", "######"], &[]);
+        let projection = level.project(0.0, 1.5, 1.5, &mut None).unwrap();
+        let dimmed = MIRROR_COLOR.b as f32 * MIRROR_REFLECTANCE.powi(MAX_RAY_BOUNCES as i32);
+        assert!((projection.color.b as f32 - dimmed).abs() <= 1.0);
+
+        // The ray goes into the portal and comes out the other one, past the wall between them.
+        let portals = [[(1, 2), (1, 4)]];
+        let mut level = test_level(&["#######", "#..#..#", "#######"], &portals);
+        let projection = level.project(0.0, 1.5, 1.5, &mut None).unwrap();
+        assert!((projection.distance - 2.5).abs() < 0.01);
+        assert!((projection.x - 6.0).abs() < 0.01);
+
+        // Stepping into the portal takes the player out the other side, and not straight back.
+        level.arrived = false;
+        level.player_x = 2.1;
+        level.follow_links();
+        assert!((level.player_x - 4.1).abs() < 0.001);
+        level.follow_links();
+        assert!((level.player_x - 4.1).abs() < 0.001);
+    }
+
+    #[test]
+    fn textured_walls() {
+        let level = test_level(&["#####", "#T..#", "#..T#", "#####"], &[]);
+
+        // Facing the wall to the right, its left edge is at the top.
+        let projection = level.project(0.0, 1.5, 2.25, &mut None).unwrap();
+        assert_eq!(projection.texture, Some(0));
+        assert!((projection.texture_x - 0.25).abs() < 0.01);
+
+        // Facing the wall to the left, its left edge is at the bottom.
+        let projection = level.project(PI, 3.5, 1.25, &mut None).unwrap();
+        assert_eq!(projection.texture, Some(0));
+        assert!((projection.texture_x - 0.75).abs() < 0.01);
+
+        // Flat walls aren't textured.
+        let projection = level.project(FRAC_PI_2, 2.5, 1.5, &mut None).unwrap();
+        assert_eq!(projection.texture, None);
+    }
+
+    #[test]
+    fn lightmap() {
+        let rows = ["#######", "#.....#", "#######"];
+        let mut level = test_level(&rows, &[]);
+        let light = StaticLight {
+            radius: 3.0,
+            intensity: 1.0,
+        };
+        level.floors[0].lightmap =
+            Some(Lightmap::bake(7, 3, 0.25, &[(1.5, 1.5, light)], |r, c| {
+                rows[r].as_bytes()[c] == b'#'
+            }));
+
+        // The wall by the light is lit, and the one across the room is only ambient.
+        let lit = level.project(PI, 3.5, 1.5, &mut None).unwrap();
+        let dark = level.project(0.0, 3.5, 1.5, &mut None).unwrap();
+        assert_eq!(lit.light, 1.0);
+        assert_eq!(dark.light, 0.25);
+        assert_eq!(dark.color.r, (lit.color.r as f32 * 0.25) as u8);
+    }
+
+    #[test]
+    fn floor_casting() {
+        let mut level = test_level(&["#####", "#...#", "#####"], &[]);
+        level.surfaces = SurfaceSettings {
+            floor: Surface::Texture(METAL_TEXTURE),
+            ceiling: Surface::Color(Color::WHITE),
+        };
+        let mut context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let horizon = RENDER_HEIGHT as i32 / 2;
+        level.draw_surfaces(&mut context, horizon, 0);
+
+        // The ceiling is one rect, and the floor is a grid of cells, each a texel of the texture.
+        let entries = &context.player_batch.entries;
+        assert!(matches!(
+            entries[0],
+            SpriteBatchEntry::FillRect { destination, .. } if destination.h == horizon
+        ));
+        let cells = (RENDER_WIDTH as i32 / SURFACE_CELL_WIDTH) * (horizon / SURFACE_CELL_HEIGHT);
+        assert_eq!(entries.len() as i32, 1 + cells);
+        for entry in &entries[1..] {
+            let SpriteBatchEntry::Sprite { source, .. } = entry else {
+                panic!("expected a sprite");
+            };
+            assert_eq!((source.w, source.h), (1, 1));
+        }
+    }
+
+    #[test]
+    fn depth_buffer() {
+        let mut level = test_level(&["#####", "#...#", "#####"], &[]);
+        level.cast_rays();
+
+        // The wall straight ahead is 2.5 tiles away, and the fisheye correction makes the wall
+        // the same depth all the way across.
+        let center = RENDER_WIDTH as i32 / 2;
+        assert!((level.depth.depth(center) - 2.5).abs() < 0.01);
+        assert!((level.depth.depth(center + 20) - 2.5).abs() < 0.01);
+        assert!(level.depth.is_hidden(center, 3.0));
+        assert!(!level.depth.is_hidden(center, 2.0));
+    }
+
+    #[test]
+    fn stairs() {
+        let mut level = test_level(&["#####", "#..1#", "#####"], &[]);
+        level
+            .floors
+            .push(test_map(&["#####", "#M.0#", "#####"], &[]));
+        level.arrived = false;
+
+        // Climbing the stairs moves the camera up to the next floor, where the mirror is.
+        level.player_x = 3.5;
+        level.follow_links();
+        assert_eq!(level.floor, 1);
+        assert!(matches!(level.floor_transition, Some((_, true))));
+        assert!(matches!(level.map().tile(1, 1), Tile::Mirror));
+        let state = level.save_state().unwrap();
+        assert_eq!(state.floor, 1);
+
+        // The player arrives on the stairs down, and has to step off them to go back.
+        level.follow_links();
+        assert_eq!(level.floor, 1);
+        level.player_x = 2.5;
+        level.follow_links();
+        level.player_x = 3.5;
+        level.follow_links();
+        assert_eq!(level.floor, 0);
+        assert!(matches!(level.floor_transition, Some((_, false))));
+
+        assert!(level.restore_state(&state));
+        assert_eq!(level.floor, 1);
+    }
+
+    #[test]
+    fn debug_flags() {
+        let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+        let forward = InputSnapshot {
+            player_forward_down: true,
+            ..Default::default()
+        };
+        let rows = ["#####", "#.#.#", "#####"];
+
+        let mut level = test_level(&rows, &[]);
+        for _ in 0..100 {
+            level.update(&context, &forward, &mut sounds);
+        }
+        assert!(level.player_x < 2.0);
+
+        // With noclip, the player goes through the wall, but not off the edge of the map.
+        let mut cvars = Cvars::new();
+        cvars.set(NOCLIP_CVAR, "true");
+        cvars.set(GOD_CVAR, "true");
+        let mut level = test_level(&rows, &[]);
+        level.apply_cvars(&cvars);
+        for _ in 0..100 {
+            level.update(&context, &forward, &mut sounds);
+        }
+        assert!(level.player_x > 3.0 && level.player_x < 5.0);
+        // The run doesn't count once a flag is on.
+        assert!(level.recording.is_none());
+
+        level.explode(Explosion::new(level.player_x, 1.5, 2.0, ROCKET_DAMAGE));
+        assert_eq!(level.health, PLAYER_MAX_HEALTH);
+    }
+
+    #[test]
+    fn explosions() {
+        let mut level = test_level(&["#########", "#..B.B#C#", "#.C.....#", "#########"], &[]);
+        level.explode(Explosion::new(2.5, 1.5, ROCKET_BLAST_RADIUS, ROCKET_DAMAGE));
+
+        // The first barrel is in range, and sets off the second, but the wall shields the crack.
+        assert!(matches!(level.map().tile(1, 3), Tile::Empty));
+        assert!(matches!(level.map().tile(1, 5), Tile::Empty));
+        assert!(matches!(level.map().tile(2, 2), Tile::Empty));
+        assert!(matches!(level.map().tile(1, 7), Tile::Cracked(_)));
+        assert_eq!(level.explosions.len(), 3);
+
+        // The player is hit by the rocket and the first barrel, but the second is too far away.
+        let expected = PLAYER_MAX_HEALTH - ROCKET_DAMAGE / 2.0 - BARREL_DAMAGE / 3.0;
+        assert!((level.health - expected).abs() < 0.01);
+        assert_ne!(level.shake(), (0, 0));
+
+        // Each explosion is heard where it happened.
+        let (mut sounds, played) = recording_sound_manager();
+        level.play_sounds(&mut sounds);
+        assert_eq!(*played.borrow(), vec!["thunder"; 3]);
+        assert_eq!(sounds.listener().x, level.player_x);
+
+        // Destroyed tiles stay destroyed after their chunk is unloaded.
+        level.floors[0].grid.unload_all();
+        level.stream_map();
+        assert!(matches!(level.map().tile(1, 3), Tile::Empty));
+    }
+
+    #[test]
+    fn underwater() {
+        let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+        let forward = InputSnapshot {
+            player_forward_down: true,
+            ..Default::default()
+        };
+        let mut level = test_level(&["#######", "#..~~~#", "#######"], &[]);
+        level.update(&context, &forward, &mut sounds);
+        assert!((level.player_x - 1.5 - MOVE_SPEED).abs() < TOLERANCE);
+        assert!(!level.muffles_sound());
+        assert_eq!(level.postprocess(), PostprocessProfile::RETRO);
+
+        // Water slows the player down, and muffles and tints everything.
+        level.player_x = 3.5;
+        level.update(&context, &forward, &mut sounds);
+        assert!((level.player_x - 3.5 - MOVE_SPEED * WATER_SPEED).abs() < TOLERANCE);
+        assert!(level.muffles_sound());
+        assert_eq!(level.postprocess().underwater, 1.0);
+        assert!(level.oxygen < MAX_OXYGEN);
+
+        // Once the air runs out, the player starts drowning.
+        let idle = InputSnapshot::default();
+        let mut frames = 0;
+        let text = loop {
+            frames += 1;
+            if let SceneResult::PushKillScreen { text } = level.update(&context, &idle, &mut sounds)
+            {
+                break text;
+            }
+        };
+        assert_eq!(text, "drowned");
+        // The player was already underwater for a frame before this.
+        let expected = (MAX_OXYGEN + PLAYER_MAX_HEALTH / DROWNING_DAMAGE) * FRAME_RATE as f32;
+        assert!(((frames + 1) as f32 - expected).abs() < 2.0);
+
+        // Air comes back out of the water.
+        level.player_x = 1.5;
+        level.update(&context, &idle, &mut sounds);
+        assert!(level.oxygen > 0.0 && !level.muffles_sound());
+    }
+
+    #[test]
+    fn dash() {
+        let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let (mut sounds, played) = recording_sound_manager();
+        let dash = InputSnapshot {
+            player_dash_clicked: true,
+            ..Default::default()
+        };
+        let idle = InputSnapshot::default();
+        let mut level = test_level(&["####", "#..#", "####"], &[]);
+        level.update(&context, &dash, &mut sounds);
+        assert_eq!(level.player_x, 1.5);
+
+        // Walking into the pickup unlocks the dash.
+        level
+            .pickups
+            .push(test_pickup(PickupKind::Ability(Ability::Dash), 1.7, 1.5));
+        level.update(&context, &idle, &mut sounds);
+        assert!(level.pickups.is_empty());
+        assert!(level.abilities.has(Ability::Dash));
+        assert_eq!(level.pending_prompts.last().unwrap(), DASH_PROMPT);
+        assert!(played.borrow().contains(&"click".to_string()));
+
+        // The dash carries the player up to the wall, but not through it.
+        level.update(&context, &dash, &mut sounds);
+        assert!((level.player_x - 1.5 - DASH_SPEED).abs() < TOLERANCE);
+        for _ in 0..10 {
+            level.update(&context, &idle, &mut sounds);
+        }
+        assert!(level.player_x > 2.5 && level.player_x < 3.0 - PLAYER_SIZE / 2.0 + TOLERANCE);
+
+        // It can't be used again until it's cooled down.
+        level.player_angle = PI;
+        let x = level.player_x;
+        level.update(&context, &dash, &mut sounds);
+        assert_eq!(level.player_x, x);
+        while !level.dash.is_ready() {
+            level.update(&context, &idle, &mut sounds);
+        }
+        level.update(&context, &dash, &mut sounds);
+        assert!(level.player_x < x);
+    }
+
+    #[test]
+    fn items() {
+        let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+        let idle = InputSnapshot::default();
+        let mut level = test_level(&["####", "#..#", "####"], &[]);
+        let items = r#"{"medkit": {"description": "", "icon": {"path": "a.png"}, "heal": 50}}"#;
+        level.items = ItemTable::from_json(items).unwrap();
+        let medkit = PickupKind::Item("medkit".to_string());
+        level.pickups.push(test_pickup(medkit.clone(), 1.5, 1.5));
+        level.pickups.push(test_pickup(medkit, 1.6, 1.5));
+        level.update(&context, &idle, &mut sounds);
+        assert_eq!(level.inventory.count("medkit"), 2);
+        assert_eq!(level.pending_prompts.last().unwrap(), INVENTORY_PROMPT);
+
+        let open = InputSnapshot {
+            inventory_clicked: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            level.update(&context, &open, &mut sounds),
+            SceneResult::PushInventory
+        ));
+
+        // Using a medkit heals, but not past full health, and dropping one just loses it.
+        level.health = 70.0;
+        level.queue_item_action(ItemAction::Use, "medkit");
+        level.queue_item_action(ItemAction::Drop, "medkit");
+        // There's nothing left to use.
+        level.queue_item_action(ItemAction::Use, "medkit");
+        level.update(&context, &idle, &mut sounds);
+        assert_eq!(level.health, PLAYER_MAX_HEALTH);
+        assert!(level.inventory.is_empty());
+        let messages: Vec<&str> = level
+            .pending_messages
+            .iter()
+            .map(|(_, text)| text.as_str())
+            .collect();
+        assert!(messages.ends_with(&["used medkit", "dropped medkit"]));
+    }
+
+    #[test]
+    fn item_targets() {
+        let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+        let idle = InputSnapshot::default();
+        let mut level = test_level(&["#####", "#...#", "#####"], &[]);
+        let items = r#"{
+            "crank": {"description": "", "icon": {"path": "a.png"}},
+            "key": {"description": "", "icon": {"path": "a.png"}, "keep": true}
+        }"#;
+        level.items = ItemTable::from_json(items).unwrap();
+        level.inventory.add("crank");
+        level.inventory.add("key");
+        let socket = ItemTarget {
+            name: "socket".to_string(),
+            accepts: vec!["crank".to_string(), "key".to_string()],
+        };
+        level.targets.push((socket, test_billboard(2.5, 1.5)));
+        level.update(&context, &idle, &mut sounds);
+        assert_eq!(level.item_target().unwrap().name, "socket");
+        assert_eq!(level.target_text().unwrap(), "socket: crank, key");
+
+        // Facing away from the socket, there's nothing to use the crank on.
+        level.player_angle = PI;
+        level.queue_item_action(ItemAction::Use, "crank");
+        level.update(&context, &idle, &mut sounds);
+        assert!(level.item_target().is_none());
+        assert_eq!(level.inventory.count("crank"), 1);
+        let (_, text) = level.pending_messages.last().unwrap();
+        assert_eq!(text, "nothing to use crank on");
+
+        // The crank is used up, but the key goes back on the key ring.
+        level.player_angle = 0.0;
+        level.queue_item_action(ItemAction::Use, "crank");
+        level.queue_item_action(ItemAction::Use, "key");
+        level.update(&context, &idle, &mut sounds);
+        assert_eq!(level.inventory.count("crank"), 0);
+        assert_eq!(level.inventory.count("key"), 1);
+        let messages: Vec<&str> = level
+            .pending_messages
+            .iter()
+            .map(|(_, text)| text.as_str())
+            .collect();
+        assert!(messages.ends_with(&["used crank on socket", "used key on socket"]));
+        // Once everything it accepts has been used on it, it's no longer a target.
+        assert!(level.item_target().is_none());
+    }
+
+    #[test]
+    fn route_guide() {
+        let mut level = test_level(&["#####", "#...#", "#.1.#", "#####"], &[]);
+        level
+            .floors
+            .push(test_map(&["#####", "#...#", "#.0.#", "#####"], &[]));
+        level.start = (0, 1, 3);
+
+        // Upstairs, the way back to the start is down the stairs.
+        level.set_floor(1);
+        level.route_guide = RouteGuide::Start;
+        level.update_route();
+        level.run_background();
+        assert_eq!(level.route, vec![(1, 1), (2, 1), (2, 2)]);
+
+        // Downstairs, it goes straight there.
+        level.set_floor(0);
+        level.update_route();
+        level.run_background();
+        assert_eq!(level.route, vec![(1, 1), (1, 2), (1, 3)]);
+
+        level.route_guide = RouteGuide::Exit;
+        level.update_route();
+        level.run_background();
+        assert_eq!(level.route.last(), Some(&(2, 2)));
+        level.set_floor(1);
+        level.update_route();
+        level.run_background();
+        assert!(level.route.is_empty());
+    }
+
+    #[cfg(feature = "rhai")]
+    #[test]
+    fn script_triggers_and_doors() {
+        let mut level = test_level(&["######", "#..D.#", "######"], &[]);
+        level.enemies.clear();
+        level.script = Some(
+            Script::new(
+                r#"
+                fn on_trigger_gate() {
+                    if enemy_count() == 0 {
+                        open_door(3, 1);
+                    }
+                }
+                fn on_trigger_shut() {
+                    close_door(3, 1);
+                }
+                "#,
+            )
+            .unwrap(),
+        );
+        let area = MapArea {
+            x: 2.0,
+            y: 1.0,
+            w: 1.0,
+            h: 1.0,
+            name: "gate".to_string(),
+        };
+        level.trigger_areas.push((area, false));
+        let mut sounds = SoundManager::noop_manager();
+        let door_open = |level: &Level| !level.map().tile(1, 3).is_solid();
+
+        level.update_triggers(&mut sounds);
+        assert!(!door_open(&level));
+
+        // Walking into the area opens the door.
+        level.player_x = 2.5;
+        level.update_triggers(&mut sounds);
+        assert!(door_open(&level));
+
+        // Standing in it doesn't fire the event again, but walking back in does.
+        level.floors[0].set_door(1, 3, false);
+        level.update_triggers(&mut sounds);
+        assert!(!door_open(&level));
+        level.player_x = 1.5;
+        level.update_triggers(&mut sounds);
+        level.player_x = 2.5;
+        level.update_triggers(&mut sounds);
+        assert!(door_open(&level));
+
+        // The door won't close on the player, and only doors open.
+        level.player_x = 3.5;
+        level.fire_script_event("trigger_shut", &mut sounds);
+        assert!(door_open(&level));
+        level.player_x = 4.5;
+        level.fire_script_event("trigger_shut", &mut sounds);
+        assert!(!door_open(&level));
+        assert!(!level.floors[0].set_door(1, 4, true));
+    }
+
+    #[test]
+    fn lists_entities() {
+        let mut level = test_level(&["#####", "#...#", "#####"], &[]);
+        level.enemies = vec![level.new_enemy(test_billboard(2.5, 1.5), DEFAULT_ENEMY_TYPE)];
+        level.enemies[0].alert((1, 1));
+        level.pickups = vec![test_pickup(PickupKind::Ability(Ability::Dash), 3.5, 1.5)];
+        level.billboards = vec![test_billboard(1.5, 1.5)];
+
+        let entities = level.entities().unwrap();
+        let summary: Vec<_> = entities
+            .iter()
+            .map(|e| (e.category, e.name.as_deref(), e.x, e.state.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("enemy", Some(DEFAULT_ENEMY_TYPE), 2.5, Some("chase")),
+                ("pickup", Some("dash"), 3.5, None),
+                ("billboard", None, 1.5, None),
+            ]
+        );
+        assert_eq!(entities[0].health, Some(level.enemies[0].health));
+    }
+
+    #[test]
+    fn route_is_found_in_the_background() {
+        let mut level = test_level(&["#####", "#...#", "#.1.#", "#####"], &[]);
+        level.background_work.max_tasks_per_frame = Some(1);
+        level.route_guide = RouteGuide::Exit;
+
+        // Asking for a route doesn't find it until the background work runs.
+        level.update_route();
+        assert!(level.route.is_empty());
+        assert_eq!(level.background_work.pending(), 1);
+        level.run_background();
+        assert_eq!(level.route.last(), Some(&(2, 2)));
+        let first = level.route.clone();
+
+        // Moving twice before the work runs leaves a search for each cell, which take a frame
+        // each, and only the newest one counts.
+        level.player_x = 2.5;
+        level.update_route();
+        level.player_x = 3.5;
+        level.update_route();
+        assert_eq!(level.background_work.pending(), 2);
+        level.run_background();
+        assert_eq!(level.route, first);
+        level.run_background();
+        assert_eq!(level.route.first(), Some(&(1, 3)));
+        assert_eq!(level.route.last(), Some(&(2, 2)));
+        assert_eq!(level.background_work.pending(), 0);
+    }
+
+    #[test]
+    fn ghost_retraces_run() {
+        let floors = [
+            ["#######", "#....1#", "#######"],
+            ["#######", "#....0#", "#######"],
+        ];
+        let new_level = || {
+            let mut level = test_level(&floors[0], &[]);
+            level.floors.push(test_map(&floors[1], &[]));
+            level.run_start = level.player_state();
+            level.ghost = None;
+            level
+        };
+        let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+
+        // Walk to the stairs up, which finishes the run.
+        let mut level = new_level();
+        let forward = InputSnapshot {
+            player_forward_down: true,
+            ..Default::default()
+        };
+        let mut path = Vec::new();
+        let mut result = SceneResult::Continue;
+        while level.floor == 0 {
+            result = level.update(&context, &forward, &mut sounds);
+            path.push(level.player_state());
+            assert!(path.len() < 100);
+        }
+        // The top floor is the final exit.
+        let SceneResult::PushEpilogue { run } = result else {
+            panic!("no epilogue");
+        };
+        assert_eq!(run.kills, 0);
+        assert!(matches!(
+            level.update(&context, &forward, &mut sounds),
+            SceneResult::Continue
+        ));
+        let run = level.pending_ghost.take().unwrap();
+        assert_eq!(run.frames, path.len() as u64);
+        assert_eq!(level.pending_score.as_ref().unwrap().frames, run.frames);
+
+        // The ghost takes the same steps, even while the player stands still.
+        let mut level = new_level();
+        level.ghost = Some(Ghost::new(run));
+        for expected in path {
+            level.update(&context, &InputSnapshot::default(), &mut sounds);
+            assert_eq!(level.ghost.as_ref().unwrap().body, expected);
+        }
+        let ghost = level.ghost.as_ref().unwrap();
+        assert!(ghost.is_done());
+        assert_eq!(ghost.floor, 1);
     }
 }
@@ -1,41 +1,199 @@
 #![allow(clippy::manual_range_contains, clippy::collapsible_else_if)]
 
+/// Enters a tracing span for the rest of the enclosing block, when the tracing feature is on.
+///
+/// This lets profilers like tracing-chrome or Tracy see where each frame's time goes.
+macro_rules! profile_scope {
+    ($name:literal $(, $($fields:tt)*)?) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!($name $(, $($fields)*)?).entered();
+    };
+}
+
+mod ability;
+mod aimassist;
+mod atlasallocator;
+mod atlaspacker;
+mod atlasreport;
+mod benchmark;
+mod billboard;
+mod breadcrumbs;
+mod cliprecorder;
 mod constants;
 mod cursor;
+mod cvars;
+mod debugflags;
+#[cfg(feature = "debug_server")]
+mod debugserver;
+mod decodepool;
+mod displaysettings;
+mod ending;
+mod enemy;
+mod enemystats;
+mod engine;
+mod epilogue;
+mod explosion;
+mod faction;
 mod filemanager;
 mod font;
+mod framearena;
+mod frameclock;
+mod gameclock;
 mod geometry;
+mod ghost;
+mod harness;
+mod hearing;
+mod heatmap;
 mod imagemanager;
 mod inputmanager;
+mod inventory;
+mod inventoryscreen;
+mod keybindings;
+mod leaderboard;
+mod leaderboardscreen;
 mod level;
+mod lightmap;
+mod logscreen;
 mod menu;
+mod messagelog;
+mod nameentryscreen;
+mod narration;
+mod pathfinder;
+mod perfcapture;
+pub mod prelude;
 mod properties;
+mod raycaster;
 mod rendercontext;
 mod renderer;
+mod replay;
+mod safeareascreen;
+mod savestate;
 mod scene;
+mod scheduler;
+#[cfg(feature = "rhai")]
+mod script;
+mod session;
 mod smallintmap;
 mod smallintset;
+mod softwarerenderer;
 mod soundmanager;
+mod spawner;
 mod sprite;
 mod stagemanager;
+mod streaming;
+mod surface;
 mod tilemap;
 mod tileset;
+mod titlecard;
+mod transition;
+mod tutorial;
 mod uibutton;
+mod uitextinput;
+mod uitoast;
 mod utils;
+mod weather;
+mod windowconfig;
+mod worldtext;
 
+pub use ability::{Abilities, Ability, Dash, Pickup, PickupKind};
+pub use aimassist::{AimAssist, AIM_ASSIST_CVAR};
+pub use atlasallocator::AtlasAllocator;
+pub use atlaspacker::{pack_images, PackedAtlas};
+pub use atlasreport::{AtlasProblem, AtlasReport, AtlasUsage};
+pub use benchmark::{Benchmark, BenchmarkReport, BENCHMARK_SEED, DEFAULT_BENCHMARK_FRAMES};
+pub use billboard::{Billboard, DepthBuffer};
+pub use breadcrumbs::{Breadcrumbs, Crumb, RouteGuide, BREADCRUMBS_CVAR, ROUTE_GUIDE_CVAR};
+pub use cliprecorder::{ClipRecorder, CLIP_SECONDS_CVAR, SAVE_CLIP_CVAR};
 pub use constants::{FRAME_RATE, RENDER_HEIGHT, RENDER_WIDTH};
 
-pub use filemanager::FileManager;
-pub use font::Font;
+pub use cvars::Cvars;
+pub use debugflags::{DebugFlags, GOD_CVAR, NOCLIP_CVAR};
+#[cfg(feature = "debug_server")]
+pub use debugserver::DebugServer;
+pub use decodepool::DecodePool;
+pub use displaysettings::{DisplaySettings, WindowMode, DISPLAY_CVAR, WINDOW_MODE_CVAR};
+pub use ending::{Ending, EndingConditions, EndingRecord, EndingTable, RunSummary, ENDINGS_PATH};
+pub use enemy::{Enemy, EnemyState};
+pub use enemystats::{
+    Difficulty, EnemyStats, EnemyStatsTable, DEFAULT_ENEMY_TYPE, DIFFICULTY_CVAR, ENEMY_STATS_PATH,
+};
+pub use engine::{Engine, EnginePlugin};
+pub use epilogue::Epilogue;
+pub use explosion::Explosion;
+pub use faction::{Faction, Hostility, HOSTILITY_CVAR};
+pub use filemanager::{DirEntry, DirEntryType, FileManager, FileManagerImpl, USER_DATA_DIR};
+pub use font::{wrap_text, Font};
+pub use frameclock::FrameClock;
+pub use gameclock::{AmbientLight, DayPhase, GameClock};
+pub use geometry::{Point, Rect};
+pub use ghost::{ghost_path, Ghost, GhostRun, GHOST_CVAR};
+pub use harness::TestHarness;
+pub use hearing::{Noise, NoiseField};
+pub use heatmap::{Heatmap, HEATMAP_CVAR};
 pub use imagemanager::{ImageLoader, ImageManager};
-pub use inputmanager::{InputManager, RecordOption};
-pub use rendercontext::RenderContext;
-pub use soundmanager::{Sound, SoundManager, SoundPlayer};
+#[cfg(feature = "winit")]
+pub use inputmanager::capture_winit_mouse;
+pub use inputmanager::{
+    apply_dead_zone, AutoStrafeLayer, CheatCodes, CheatInput, InputLayer, InputManager,
+    InputProfile, InputRecorder, InputSnapshot, JoystickButton, KeyboardKey, MouseButton,
+    RecordOption, SwitchScanLayer, TextEdit, CHEATS_CVAR, DEAD_ZONE_CVAR, INPUT_PROFILE_CVAR,
+};
+pub use inventory::{Inventory, ItemAction, ItemIcon, ItemInfo, ItemTable, ICON_SIZE, ITEMS_PATH};
+pub use inventoryscreen::InventoryScreen;
+pub use keybindings::{Action, Binding, KeyBindings, BINDINGS_PATH};
+pub use leaderboard::{
+    LeaderboardBackend, LeaderboardEntry, LocalLeaderboard, LEADERBOARD_SIZE, PLAYER_NAME_CVAR,
+};
+pub use leaderboardscreen::LeaderboardScreen;
+pub use level::{MAP_CVAR, MOUSE_LOOK_CVAR, MOUSE_SENSITIVITY_CVAR};
+pub use lightmap::{Lightmap, StaticLight};
+pub use logscreen::LogScreen;
+pub use messagelog::{LogEntry, MessageKind, MessageLog, MESSAGE_LOG_CAPACITY};
+pub use nameentryscreen::{NameEntryScreen, MAX_NAME_LENGTH};
+pub use narration::{LogNarrator, Narrator, RecordingNarrator};
+pub use pathfinder::find_path;
+pub use perfcapture::{
+    CaptureFormat, FrameSample, PerfCapture, PERF_CAPTURE_CVAR, PERF_CAPTURE_FORMAT_CVAR,
+};
+pub use raycaster::{Ray, RayHit, Raycaster, Side};
+pub use rendercontext::{
+    LineCap, RenderContext, RenderLayer, SpriteBatch, SpriteTransform, MAX_SAFE_AREA, MAX_UI_SCALE,
+    MIN_UI_SCALE, SAFE_AREA_CVAR, UI_SCALE_CVAR,
+};
+pub use renderer::{NullRenderer, Renderer, RendererStats};
+pub use replay::{Replay, StateHash, StateHasher, REPLAY_CVARS};
+pub use safeareascreen::SafeAreaScreen;
+pub use savestate::{CheckpointState, LevelState, PlayerState, SAVE_STATE_VERSION};
+pub use scene::{EntityInfo, Scene, SceneResult};
+pub use scheduler::Scheduler;
+pub use session::{Session, WindowGeometry, SESSION_CVARS};
+pub use smallintset::{BitSet, SmallIntSet};
+pub use softwarerenderer::SoftwareRenderer;
+pub use soundmanager::{
+    Listener, RecordingSoundPlayer, Sound, SoundClip, SoundFormat, SoundHandle, SoundManager,
+    SoundPlayer, SoundSettings, SOUND_MANIFEST_PATH, VOLUME_CVAR,
+};
+pub use spawner::{EntityKind, SpawnTrigger, Spawner, SpawnerSettings};
+pub use sprite::{Animation, Sprite, SpriteSheet};
 pub use stagemanager::StageManager;
+pub use streaming::{Chunk, ChunkCoord, ChunkSource, StreamingGrid};
+pub use surface::{Surface, SurfaceSettings};
+pub use titlecard::TitleCard;
+pub use transition::{
+    Transition, TransitionFrame, TransitionKind, TRANSITION_CVAR, TRANSITION_FRAMES_CVAR,
+};
+pub use tutorial::{Tutorial, TUTORIAL_CVAR, TUTORIAL_PROMPTS_PATH, TUTORIAL_RESET_CVAR};
+pub use utils::Color;
+pub use weather::{Weather, WeatherKind, WeatherSettings};
+pub use windowconfig::WindowConfig;
+pub use worldtext::{WorldText, DEFAULT_TEXT_SIZE};
 
 #[cfg(feature = "sdl2")]
 mod sdl;
 
+#[cfg(feature = "sdl2")]
+pub use sdl::sdlrenderer::SdlRenderer;
+
 #[cfg(feature = "wgpu")]
 mod wgpu;
 
@@ -0,0 +1,159 @@
+use std::f32::consts::TAU;
+
+use anyhow::{bail, Result};
+
+use crate::properties::PropertyMap;
+use crate::raycaster::{Ray, Raycaster};
+
+/// How lit cells no static light reaches are, in a level with static lights, unless the map
+/// says otherwise.
+pub const DEFAULT_LIGHTMAP_AMBIENT: f32 = 0.3;
+
+/// A light placed in a map, like a torch or a lamp. It never moves, so how it lights the level
+/// is worked out once, when the level loads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StaticLight {
+    /// How far the light reaches, in tiles.
+    pub radius: f32,
+    /// How much the light adds right next to it, where 1 is enough to fully light a cell.
+    pub intensity: f32,
+}
+
+impl StaticLight {
+    /// The light for an object with a "light" property, which is its radius in tiles, and an
+    /// optional "light_intensity" percentage.
+    pub fn from_properties(properties: &PropertyMap) -> Result<Option<StaticLight>> {
+        let Some(radius) = properties.get_int("light")? else {
+            return Ok(None);
+        };
+        if radius <= 0 {
+            bail!("invalid light radius: {}", radius);
+        }
+        let intensity = properties.get_int("light_intensity")?.unwrap_or(100);
+        if intensity < 0 {
+            bail!("invalid light intensity: {}", intensity);
+        }
+        Ok(Some(StaticLight {
+            radius: radius as f32,
+            intensity: intensity as f32 / 100.0,
+        }))
+    }
+}
+
+/// How lit each cell of a map is by its static lights, from 0 for black to 1 for fully lit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lightmap {
+    width: usize,
+    height: usize,
+    ambient: f32,
+    /// The light level of each cell, indexed by row * width + column.
+    levels: Vec<f32>,
+}
+
+impl Lightmap {
+    /// Works out how lit each cell is by lights at the given (x, y, light), in tiles. A light
+    /// reaches a cell if nothing blocks the line from it to the cell's center, and fades out
+    /// evenly to its radius. Light from every light that reaches a cell adds up, on top of the
+    /// ambient level.
+    pub fn bake(
+        width: usize,
+        height: usize,
+        ambient: f32,
+        lights: &[(f32, f32, StaticLight)],
+        blocks: impl Fn(usize, usize) -> bool,
+    ) -> Lightmap {
+        let raycaster = Raycaster::new(width, height);
+        let mut levels = vec![ambient; width * height];
+        for &(x, y, light) in lights {
+            let reach = light.radius.ceil() as usize;
+            let (light_row, light_column) = (y.max(0.0) as usize, x.max(0.0) as usize);
+            let rows = light_row.saturating_sub(reach)..(light_row + reach + 1).min(height);
+            let columns = light_column.saturating_sub(reach)..(light_column + reach + 1).min(width);
+            for row in rows {
+                for column in columns.clone() {
+                    if blocks(row, column) {
+                        continue;
+                    }
+                    let (to_x, to_y) = (column as f32 + 0.5, row as f32 + 0.5);
+                    let distance = ((to_x - x).powi(2) + (to_y - y).powi(2)).sqrt();
+                    if distance >= light.radius
+                        || !is_clear(&raycaster, (x, y), (to_x, to_y), &blocks)
+                    {
+                        continue;
+                    }
+                    levels[row * width + column] +=
+                        light.intensity * (1.0 - distance / light.radius);
+                }
+            }
+        }
+        for level in levels.iter_mut() {
+            *level = level.min(1.0);
+        }
+        Lightmap {
+            width,
+            height,
+            ambient,
+            levels,
+        }
+    }
+
+    /// How lit the cell a point in the map is, with the point in tiles. Points off the map are
+    /// at the ambient level.
+    pub fn level_at(&self, x: f32, y: f32) -> f32 {
+        if x < 0.0 || y < 0.0 {
+            return self.ambient;
+        }
+        let (row, column) = (y as usize, x as usize);
+        if row >= self.height || column >= self.width {
+            return self.ambient;
+        }
+        self.levels[row * self.width + column]
+    }
+}
+
+/// Whether nothing blocks a straight line between two points, in tiles. The cell the line
+/// starts in doesn't count, so lights can be set into walls.
+fn is_clear(
+    raycaster: &Raycaster,
+    from: (f32, f32),
+    to: (f32, f32),
+    blocks: &impl Fn(usize, usize) -> bool,
+) -> bool {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let distance = (dx * dx + dy * dy).sqrt();
+    if distance < 0.001 {
+        return true;
+    }
+    let ray = Ray::from_point(from.0, from.1, dy.atan2(dx).rem_euclid(TAU));
+    match raycaster.cast(ray, true, blocks) {
+        None => true,
+        Some(hit) => ((hit.x - from.0).powi(2) + (hit.y - from.1).powi(2)).sqrt() >= distance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bake() {
+        // A room with a wall down the middle, and a light in the left half.
+        let rows = ["#######", "#..#..#", "#..#..#", "#.....#", "#######"];
+        let blocks = |row: usize, column: usize| rows[row].as_bytes()[column] == b'#';
+        let light = StaticLight {
+            radius: 4.0,
+            intensity: 1.0,
+        };
+        let lightmap = Lightmap::bake(7, 5, 0.25, &[(1.5, 1.5, light)], blocks);
+
+        // The light's own cell would be over full with the ambient light added.
+        assert_eq!(lightmap.level_at(1.5, 1.5), 1.0);
+        let near = lightmap.level_at(2.5, 2.5);
+        let far = lightmap.level_at(1.5, 3.5);
+        assert!(near > far && far > 0.25);
+        // The wall hides the upper right from the light, and walls themselves aren't lit.
+        assert_eq!(lightmap.level_at(4.5, 1.5), 0.25);
+        assert_eq!(lightmap.level_at(3.5, 1.5), 0.25);
+        assert_eq!(lightmap.level_at(-1.0, 1.5), 0.25);
+    }
+}
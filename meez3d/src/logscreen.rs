@@ -0,0 +1,151 @@
+use crate::font::Font;
+use crate::geometry::Point;
+use crate::inputmanager::InputSnapshot;
+use crate::messagelog::{LogEntry, MessageKind, MessageLog};
+use crate::rendercontext::{PostprocessProfile, RenderContext, RenderLayer};
+use crate::scene::{Scene, SceneResult};
+use crate::soundmanager::SoundManager;
+use crate::utils::Color;
+
+const MARGIN: i32 = 16;
+const BACKGROUND_COLOR: Color = Color {
+    r: 0x11,
+    g: 0x11,
+    b: 0x22,
+    a: 0xee,
+};
+
+fn kind_color(kind: MessageKind) -> Color {
+    match kind {
+        MessageKind::Dialog => Color::WHITE,
+        MessageKind::Pickup => Color {
+            r: 0xff,
+            g: 0xdd,
+            b: 0x44,
+            a: 0xff,
+        },
+        MessageKind::System => Color {
+            r: 0x88,
+            g: 0xcc,
+            b: 0xff,
+            a: 0xff,
+        },
+    }
+}
+
+/// A scrollable list of the message log, opened from the pause menu.
+///
+/// The newest messages are at the bottom. Up and down scroll through older ones, and cancel goes
+/// back to the pause menu.
+pub struct LogScreen {
+    /// A copy of the log as it was when the screen opened, since the game is paused anyway.
+    entries: Vec<LogEntry>,
+    /// How many messages the view is scrolled up from the newest one.
+    scroll: usize,
+}
+
+impl LogScreen {
+    pub fn new(log: &MessageLog) -> LogScreen {
+        LogScreen {
+            entries: log.entries().cloned().collect(),
+            scroll: 0,
+        }
+    }
+
+    fn scroll_by(&mut self, delta: i32) {
+        let max = self.entries.len().saturating_sub(1) as i32;
+        self.scroll = (self.scroll as i32 + delta).clamp(0, max) as usize;
+    }
+}
+
+impl Scene for LogScreen {
+    fn update(
+        &mut self,
+        _context: &RenderContext,
+        inputs: &InputSnapshot,
+        _sounds: &mut SoundManager,
+    ) -> SceneResult {
+        if inputs.cancel_clicked {
+            return SceneResult::Pop;
+        }
+        if inputs.menu_up_clicked {
+            self.scroll_by(1);
+        }
+        if inputs.menu_down_clicked {
+            self.scroll_by(-1);
+        }
+        SceneResult::Continue
+    }
+
+    fn name(&self) -> &str {
+        "log"
+    }
+
+    fn postprocess(&self) -> PostprocessProfile {
+        PostprocessProfile::CLEAN
+    }
+
+    fn draw(&self, context: &mut RenderContext, font: &Font, _previous: Option<&dyn Scene>) {
+        context.fill_rect(context.ui_area(), RenderLayer::Hud, BACKGROUND_COLOR);
+        let area = context.safe_area();
+        let left = area.x + MARGIN;
+        font.draw_string(
+            context,
+            RenderLayer::Hud,
+            Point::new(left, area.y + MARGIN),
+            "log",
+        );
+
+        let top = area.y + MARGIN * 2 + font.char_height;
+        if self.entries.is_empty() {
+            font.draw_string(context, RenderLayer::Hud, Point::new(left, top), "empty");
+            return;
+        }
+
+        // Fill the screen upward from the bottom, starting at the scrolled-to message.
+        let mut y = area.bottom() - MARGIN - font.char_height;
+        for entry in self.entries.iter().rev().skip(self.scroll) {
+            if y < top {
+                break;
+            }
+            let line = format!("{} {}", entry.timestamp(), entry.text);
+            context.set_tint(RenderLayer::Hud, kind_color(entry.kind));
+            font.draw_string(context, RenderLayer::Hud, Point::new(left, y), &line);
+            y -= font.char_height;
+        }
+        context.set_tint(RenderLayer::Hud, Color::WHITE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scroll() {
+        let mut log = MessageLog::new(10);
+        for i in 0..3 {
+            log.push(i, MessageKind::Dialog, "hi");
+        }
+        let mut screen = LogScreen::new(&log);
+        let context = RenderContext::new(640, 400, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+        let up = InputSnapshot {
+            menu_up_clicked: true,
+            ..Default::default()
+        };
+        for _ in 0..5 {
+            screen.update(&context, &up, &mut sounds);
+        }
+        assert_eq!(screen.scroll, 2);
+
+        let cancel = InputSnapshot {
+            cancel_clicked: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            screen.update(&context, &cancel, &mut sounds),
+            SceneResult::Pop
+        ));
+    }
+}
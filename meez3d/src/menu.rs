@@ -1,29 +1,42 @@
+use std::mem;
 use std::path::Path;
 
-use anyhow::Result;
-use log::error;
+use anyhow::{bail, Result};
+use log::{error, info};
 
 use crate::cursor::Cursor;
+use crate::cvars::Cvars;
 use crate::filemanager::FileManager;
 use crate::font::Font;
 use crate::geometry::{Point, Rect};
 use crate::imagemanager::ImageLoader;
 use crate::inputmanager::InputSnapshot;
-use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::narration::Narrator;
+use crate::rendercontext::{PostprocessProfile, RenderContext, RenderLayer};
 use crate::scene::{Scene, SceneResult};
 use crate::soundmanager::SoundManager;
-use crate::sprite::Sprite;
+use crate::tilemap::TileMap;
+use crate::tutorial::TUTORIAL_RESET_CVAR;
 use crate::uibutton::UiButton;
 use crate::utils::Color;
-use crate::RENDER_WIDTH;
+
+/// Where the menu's text is drawn, in HUD units.
+const TEXT_Y: i32 = 250;
 
 pub struct Menu {
     cancel_action: String,
     cursor: Cursor,
-    background: Sprite,
+    /// The Tiled map the menu was made from, whose layers are drawn behind the buttons.
+    background: Box<TileMap>,
     buttons: Vec<UiButton>,
     selected: usize,
     text: Option<String>,
+    postprocess: PostprocessProfile,
+    /// The button that was focused the last time the menu was narrated, or None if it hasn't
+    /// been yet.
+    narrated: Option<usize>,
+    /// Whether the tutorial_reset button was pressed since the cvars were last flushed.
+    reset_tutorial: bool,
 }
 
 enum ButtonOrderDirection {
@@ -32,86 +45,99 @@ enum ButtonOrderDirection {
 }
 
 impl Menu {
-    pub fn new_splash(files: &FileManager, images: &mut dyn ImageLoader) -> Result<Self> {
-        let background_path = Path::new("assets/splash.png");
-        let cancel_action = "menu";
-        let mut menu = Menu::new(background_path, cancel_action, None, files, images)?;
-        let start = Rect {
-            x: 60,
-            y: 80,
-            w: 394,
-            h: 145,
-        };
-        menu.add_button(Path::new("assets/start_button.png"), start, "level", images)?;
-        Ok(menu)
-    }
-
-    pub fn new_kill_screen(
-        text: &str,
+    /// The title screen, which shows the title of the last ending the player reached, if any.
+    pub fn new_splash(
         files: &FileManager,
         images: &mut dyn ImageLoader,
+        ending: Option<&str>,
     ) -> Result<Self> {
-        let background_path = Path::new("assets/red.png");
-        let cancel_action = "level";
-        let text = Some(text.to_string());
-        let mut menu = Menu::new(background_path, cancel_action, text, files, images)?;
-        let retry = Rect {
-            x: 800 - 197,
-            y: 450,
-            w: 394,
-            h: 145,
-        };
-        let quit = Rect {
-            x: 800 - 197,
-            y: 650,
-            w: 394,
-            h: 145,
-        };
-        menu.add_button(Path::new("assets/retry_button.png"), retry, "level", images)?;
-        menu.add_button(Path::new("assets/quit_button.png"), quit, "menu", images)?;
+        let mut menu = Menu::from_tilemap(Path::new("assets/menus/start.tmx"), files, images)?;
+        if let Some(title) = ending {
+            menu.text = Some(format!("last ending: {}", title));
+        }
         Ok(menu)
     }
 
-    fn new(
-        background_path: &Path,
-        cancel_action: &str,
-        text: Option<String>,
-        _files: &FileManager,
+    /// The menu shown when the game is paused, with ways into the message log, leaderboard, and
+    /// safe area screen.
+    pub fn new_pause(files: &FileManager, images: &mut dyn ImageLoader) -> Result<Self> {
+        Menu::from_tilemap(Path::new("assets/menus/pause.tmx"), files, images)
+    }
+
+    /// A menu made in Tiled, so it can be laid out without changing any code.
+    ///
+    /// The map's layers are drawn behind the buttons, with its pixels as HUD units. Each object
+    /// with the uibutton property is a button, which does its action property when clicked. A
+    /// tile object is drawn as its tile, with its label as alt text, and anything else as a plain
+    /// button with its label on it. The map's cancel_action property is what cancel does, and
+    /// its text property is drawn across the middle. Its clean property turns off the retro
+    /// postprocessing.
+    pub fn from_tilemap(
+        path: &Path,
+        files: &FileManager,
         images: &mut dyn ImageLoader,
     ) -> Result<Self> {
-        let cancel_action = cancel_action.to_string();
-        let cursor = Cursor::new(images)?;
-        let background = images.load_sprite(background_path)?;
-        let buttons = Vec::new();
-        let selected = 0;
-
+        info!("loading menu from {:?}", path);
+        let map = TileMap::from_file(path, files, images)?;
+        let mut buttons = Vec::new();
+        for object in map.objects.iter() {
+            let properties = &object.properties;
+            if !properties.uibutton {
+                continue;
+            }
+            let Some(action) = &properties.action else {
+                bail!("button {} in menu {:?} has no action", object.id, path);
+            };
+            let button = match object.gid {
+                Some(gid) => {
+                    let (sprite, source) = map.get_tile_sprite(gid)?;
+                    let button = UiButton::from_sprite(sprite, source, object.position, action);
+                    if properties.label.is_empty() {
+                        button
+                    } else {
+                        button.with_alt_text(&properties.label)
+                    }
+                }
+                None => UiButton::with_label(&properties.label, object.position, action),
+            };
+            buttons.push(button);
+        }
+        let raw = &map.properties.raw;
+        let text = raw.get_string("text")?.map(str::to_string);
+        let postprocess = if raw.get_bool("clean")?.unwrap_or(false) {
+            PostprocessProfile::CLEAN
+        } else {
+            PostprocessProfile::RETRO
+        };
         Ok(Self {
-            cancel_action,
-            cursor,
-            background,
+            cancel_action: map.properties.cancel_action.clone(),
+            cursor: Cursor::new(images)?,
+            background: Box::new(map),
             buttons,
-            selected,
+            selected: 0,
             text,
+            postprocess,
+            narrated: None,
+            reset_tutorial: false,
         })
     }
 
-    fn add_button(
-        &mut self,
-        path: &Path,
-        position: Rect<i32>,
-        action: &str,
+    /// The menu shown when the player dies, with the given text about how.
+    pub fn new_kill_screen(
+        text: &str,
+        files: &FileManager,
         images: &mut dyn ImageLoader,
-    ) -> Result<()> {
-        let button = UiButton::new(path, position, action, images)?;
-        self.buttons.push(button);
-        Ok(())
+    ) -> Result<Self> {
+        let mut menu = Menu::from_tilemap(Path::new("assets/menus/kill.tmx"), files, images)?;
+        menu.text = Some(text.to_string());
+        Ok(menu)
     }
 
     fn next_button(&mut self, delta: i32, direction: ButtonOrderDirection) {
         self.selected = (self.selected + 1) % self.buttons.len();
     }
 
-    fn perform_action(&self, action: &str) -> Option<SceneResult> {
+    fn perform_action(&mut self, action: &str) -> Option<SceneResult> {
         Some(if action == "level" {
             SceneResult::PushLevel
         } else if action == "menu" {
@@ -122,6 +148,21 @@ impl Menu {
             SceneResult::PopTwo
         } else if action == "reload" {
             SceneResult::ReloadLevel
+        } else if action == "respawn" {
+            SceneResult::RespawnAtCheckpoint
+        } else if action == "log" {
+            SceneResult::PushMessageLog
+        } else if action == "leaderboard" {
+            SceneResult::PushLeaderboard
+        } else if action == "inventory" {
+            SceneResult::PushInventory
+        } else if action == "safearea" {
+            SceneResult::PushSafeArea
+        } else if action == "name" {
+            SceneResult::PushNameEntry
+        } else if action == "tutorial_reset" {
+            self.reset_tutorial = true;
+            SceneResult::Continue
         } else {
             error!("invalid button action: {action}");
             return None;
@@ -132,12 +173,12 @@ impl Menu {
 impl Scene for Menu {
     fn update(
         &mut self,
-        _context: &RenderContext,
+        context: &RenderContext,
         inputs: &InputSnapshot,
         sounds: &mut SoundManager,
     ) -> SceneResult {
         if inputs.cancel_clicked {
-            if let Some(result) = self.perform_action(&self.cancel_action) {
+            if let Some(result) = self.perform_action(&self.cancel_action.clone()) {
                 return result;
             }
         }
@@ -155,12 +196,12 @@ impl Scene for Menu {
             self.next_button(1, ButtonOrderDirection::Horizontal);
         }
 
-        self.cursor.update(inputs);
+        self.cursor.update(context, inputs);
 
         let mut clicked_action = None;
         for (i, button) in self.buttons.iter_mut().enumerate() {
             let selected = i == self.selected;
-            if let Some(action) = button.update(selected, inputs, sounds) {
+            if let Some(action) = button.update(context, selected, inputs, sounds) {
                 clicked_action = Some(action);
             }
         }
@@ -173,6 +214,34 @@ impl Scene for Menu {
         SceneResult::Continue
     }
 
+    fn name(&self) -> &str {
+        "menu"
+    }
+
+    fn postprocess(&self) -> PostprocessProfile {
+        self.postprocess
+    }
+
+    fn flush_cvars(&mut self, cvars: &mut Cvars) {
+        if mem::take(&mut self.reset_tutorial) {
+            cvars.set(TUTORIAL_RESET_CVAR, "true");
+        }
+    }
+
+    fn flush_narration(&mut self, narrator: &mut dyn Narrator) {
+        if self.narrated.is_none() {
+            if let Some(text) = &self.text {
+                narrator.narrate(text);
+            }
+        }
+        if self.narrated != Some(self.selected) {
+            if let Some(button) = self.buttons.get(self.selected) {
+                narrator.narrate(button.narration());
+            }
+            self.narrated = Some(self.selected);
+        }
+    }
+
     fn draw(&self, context: &mut RenderContext, font: &Font, previous: Option<&dyn Scene>) {
         context.player_batch.fill_rect(
             context.logical_area(),
@@ -188,25 +257,161 @@ impl Scene for Menu {
             background.draw(context, font, None);
         }
 
-        let src = Rect {
-            x: 0,
-            y: 0,
-            w: 1600,
-            h: 900,
-        };
-        context
-            .hud_batch
-            .draw(self.background, context.logical_area(), src, false);
+        // The menu goes on the overlay, so it stays readable over a dark or warped level.
+        let area = context.ui_area();
+        self.background
+            .draw_background(context, RenderLayer::Overlay, area, Point::new(0, 0));
 
         if let Some(text) = self.text.as_ref() {
-            let text_width = text.len() as i32 * font.char_width;
-            let text_pos = Point::new((RENDER_WIDTH as i32 - text_width) / 2, 250);
-            font.draw_string(context, RenderLayer::Hud, text_pos, text);
+            let text_width = font.text_width(text);
+            let text_pos = Rect {
+                x: (context.ui_area().w - text_width) / 2,
+                y: TEXT_Y,
+                w: text_width,
+                h: font.char_height,
+            };
+            let text_pos = context.to_safe_area(text_pos).top_left();
+            font.draw_string(context, RenderLayer::Overlay, text_pos, text);
         }
 
         for button in self.buttons.iter() {
-            button.draw(context, RenderLayer::Hud, font);
+            button.draw(context, RenderLayer::Overlay, font);
+        }
+        self.cursor.draw(context, RenderLayer::Overlay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::imagemanager::ImageManager;
+
+    const MENU: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.8" tiledversion="1.8.0" orientation="orthogonal" renderorder="right-down" width="80" height="50" tilewidth="8" tileheight="8" infinite="0" backgroundcolor="#330033" nextlayerid="3" nextobjectid="3">
+ <properties>
+  <property name="cancel_action" value="menu"/>
+  <property name="clean" type="bool" value="true"/>
+  <property name="text" value="paused"/>
+ </properties>
+ <tileset firstgid="1" source="../8bitfont.tsx"/>
+ <imagelayer id="1" name="background">
+  <image source="../red.png"/>
+ </imagelayer>
+ <objectgroup id="2" name="buttons">
+  <object id="1" x="96" y="24" width="448" height="72">
+   <properties>
+    <property name="action" value="pop"/>
+    <property name="label" value="resume"/>
+    <property name="uibutton" type="bool" value="true"/>
+   </properties>
+  </object>
+  <object id="2" gid="66" x="96" y="192" width="64" height="64">
+   <properties>
+    <property name="action" value="level"/>
+    <property name="label" value="start"/>
+    <property name="uibutton" type="bool" value="true"/>
+   </properties>
+  </object>
+ </objectgroup>
+</map>
+"##;
+
+    fn load(menu: &str) -> Result<Menu> {
+        let mut map = HashMap::new();
+        map.insert(
+            PathBuf::from("assets/menus/pause.tmx"),
+            menu.as_bytes().to_vec(),
+        );
+        map.insert(
+            PathBuf::from("assets/8bitfont.tsx"),
+            include_bytes!("../../assets/8bitfont.tsx").to_vec(),
+        );
+        for (path, tileset) in [
+            (
+                "assets/start_button.tsx",
+                include_str!("../../assets/start_button.tsx"),
+            ),
+            (
+                "assets/retry_button.tsx",
+                include_str!("../../assets/retry_button.tsx"),
+            ),
+            (
+                "assets/quit_button.tsx",
+                include_str!("../../assets/quit_button.tsx"),
+            ),
+        ] {
+            map.insert(PathBuf::from(path), tileset.as_bytes().to_vec());
         }
-        self.cursor.draw(context, RenderLayer::Hud);
+        let files = FileManager::from_memory(map)?;
+        let mut images = ImageManager::null_manager();
+        Menu::from_tilemap(Path::new("assets/menus/pause.tmx"), &files, &mut images)
+    }
+
+    #[test]
+    fn from_tilemap() {
+        let mut menu = load(MENU).unwrap();
+        assert_eq!(menu.cancel_action, "menu");
+        assert_eq!(menu.text.as_deref(), Some("paused"));
+        assert_eq!(menu.postprocess, PostprocessProfile::CLEAN);
+        let narrations: Vec<&str> = menu.buttons.iter().map(UiButton::narration).collect();
+        assert_eq!(narrations, vec!["resume", "start"]);
+        assert_eq!(menu.buttons[0].position.y, 24);
+        // Tile objects are anchored at the bottom in Tiled, but not once they're loaded.
+        assert_eq!(menu.buttons[1].position.y, 128);
+
+        // Pressing and letting go of ok does the selected button's action.
+        let context = RenderContext::new(640, 400, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+        let down = InputSnapshot {
+            menu_down_clicked: true,
+            ok_down: true,
+            ..Default::default()
+        };
+        menu.update(&context, &down, &mut sounds);
+        let up = InputSnapshot::default();
+        assert!(matches!(
+            menu.update(&context, &up, &mut sounds),
+            SceneResult::PushLevel
+        ));
+
+        // The pause menu is made this way, too.
+        let pause = load(include_str!("../../assets/menus/pause.tmx")).unwrap();
+        assert_eq!(pause.cancel_action, "pop");
+        assert_eq!(pause.buttons.len(), 8);
+        // Every button fits on the screen.
+        let bottom = pause.buttons.iter().map(|button| button.position.bottom());
+        assert!(bottom.max().unwrap() <= context.ui_area().h);
+
+        // Replaying the tutorial is done through its cvar, like it is from the console.
+        let mut pause = pause;
+        let mut cvars = Cvars::new();
+        assert!(pause.perform_action("tutorial_reset").is_some());
+        pause.flush_cvars(&mut cvars);
+        assert_eq!(cvars.get(TUTORIAL_RESET_CVAR), Some("true"));
+        cvars.set(TUTORIAL_RESET_CVAR, "false");
+        pause.flush_cvars(&mut cvars);
+        assert_eq!(cvars.get(TUTORIAL_RESET_CVAR), Some("false"));
+
+        // So are the title and kill screens, with every button on the screen.
+        let start = load(include_str!("../../assets/menus/start.tmx")).unwrap();
+        assert_eq!(start.cancel_action, "menu");
+        assert_eq!(start.postprocess, PostprocessProfile::CLEAN);
+        let kill = load(include_str!("../../assets/menus/kill.tmx")).unwrap();
+        assert_eq!(kill.cancel_action, "level");
+        let narrations: Vec<&str> = kill.buttons.iter().map(UiButton::narration).collect();
+        assert_eq!(narrations, vec!["retry", "quit"]);
+        for button in start.buttons.iter().chain(kill.buttons.iter()) {
+            assert!(button.position.bottom() <= context.ui_area().h);
+        }
+
+        // A tile that isn't in any of the map's tilesets is an error, not a panic later.
+        let unknown_gid = MENU.replace(r#"gid="66""#, r#"gid="9999""#);
+        assert!(load(&unknown_gid).is_err());
+
+        let no_action = MENU.replace(r#"<property name="action" value="pop"/>"#, "");
+        assert!(load(&no_action).is_err());
     }
 }
@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use crate::constants::FRAME_RATE;
+
+/// How many messages the log keeps before it starts dropping the oldest ones.
+pub const MESSAGE_LOG_CAPACITY: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    /// A line spoken by a character, e.g. from the level script.
+    Dialog,
+    /// Something the player picked up.
+    Pickup,
+    /// Something the game is telling the player, like reaching a checkpoint.
+    System,
+}
+
+impl FromStr for MessageKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "dialog" => MessageKind::Dialog,
+            "pickup" => MessageKind::Pickup,
+            "system" => MessageKind::System,
+            _ => bail!("invalid message kind: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for MessageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MessageKind::Dialog => "dialog",
+            MessageKind::Pickup => "pickup",
+            MessageKind::System => "system",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    /// The frame the message was recorded on.
+    pub frame: u64,
+    pub kind: MessageKind,
+    pub text: String,
+}
+
+impl LogEntry {
+    /// When the message was recorded, in game time, like "1:05".
+    pub fn timestamp(&self) -> String {
+        let seconds = self.frame / FRAME_RATE as u64;
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// Every message the player has seen this session, newest last, up to a fixed capacity.
+#[derive(Debug, Clone)]
+pub struct MessageLog {
+    entries: VecDeque<LogEntry>,
+    capacity: usize,
+}
+
+impl Default for MessageLog {
+    fn default() -> Self {
+        MessageLog::new(MESSAGE_LOG_CAPACITY)
+    }
+}
+
+impl MessageLog {
+    pub fn new(capacity: usize) -> MessageLog {
+        MessageLog {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Records a message, dropping the oldest one if the log is full.
+    pub fn push(&mut self, frame: u64, kind: MessageKind, text: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(LogEntry {
+            frame,
+            kind,
+            text: text.to_string(),
+        });
+    }
+
+    /// The messages, from oldest to newest.
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &LogEntry> + ExactSizeIterator {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded() {
+        let mut log = MessageLog::new(3);
+        for i in 0..5 {
+            log.push(i * 60, MessageKind::System, &format!("message {}", i));
+        }
+        assert_eq!(log.len(), 3);
+        let texts: Vec<&str> = log.entries().map(|entry| entry.text.as_str()).collect();
+        assert_eq!(texts, vec!["message 2", "message 3", "message 4"]);
+
+        log.push(65 * 60, MessageKind::Dialog, "hello");
+        assert_eq!(log.entries().last().unwrap().timestamp(), "1:05");
+        assert_eq!(log.entries().next().unwrap().text, "message 3");
+
+        assert_eq!(
+            "pickup".parse::<MessageKind>().unwrap(),
+            MessageKind::Pickup
+        );
+        assert!("shout".parse::<MessageKind>().is_err());
+    }
+}
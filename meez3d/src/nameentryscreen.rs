@@ -0,0 +1,170 @@
+use crate::cvars::Cvars;
+use crate::font::Font;
+use crate::geometry::{Point, Rect};
+use crate::inputmanager::InputSnapshot;
+use crate::leaderboard::{DEFAULT_PLAYER_NAME, PLAYER_NAME_CVAR};
+use crate::rendercontext::{PostprocessProfile, RenderContext, RenderLayer};
+use crate::scene::{Scene, SceneResult};
+use crate::soundmanager::SoundManager;
+use crate::uitextinput::UiTextInput;
+use crate::utils::Color;
+
+const MARGIN: i32 = 16;
+/// The most characters a name can have, so it fits on the leaderboard.
+pub const MAX_NAME_LENGTH: usize = 16;
+const LABEL: &str = "name";
+const HINT: &str = "enter save  esc cancel";
+
+const BACKGROUND_COLOR: Color = Color {
+    r: 0x11,
+    g: 0x11,
+    b: 0x22,
+    a: 0xee,
+};
+
+/// A screen for typing the name runs are put on the leaderboard under, opened from the pause
+/// menu.
+///
+/// Ok saves the name to the player_name cvar, and cancel goes back to the pause menu without
+/// changing it.
+pub struct NameEntryScreen {
+    input: UiTextInput,
+    /// Whether the input has been filled in with the current name yet.
+    loaded: bool,
+    /// The name the player saved, if they have, until it's written to the cvar.
+    saved: Option<String>,
+}
+
+impl NameEntryScreen {
+    pub fn new() -> NameEntryScreen {
+        // The same size as a button on the pause menu, a little above the middle.
+        let position = Rect {
+            x: 96,
+            y: 216,
+            w: 448,
+            h: 72,
+        };
+        NameEntryScreen {
+            input: UiTextInput::new(position, MAX_NAME_LENGTH),
+            loaded: false,
+            saved: None,
+        }
+    }
+}
+
+impl Default for NameEntryScreen {
+    fn default() -> Self {
+        NameEntryScreen::new()
+    }
+}
+
+impl Scene for NameEntryScreen {
+    fn update(
+        &mut self,
+        _context: &RenderContext,
+        inputs: &InputSnapshot,
+        sounds: &mut SoundManager,
+    ) -> SceneResult {
+        if inputs.cancel_clicked {
+            return SceneResult::Pop;
+        }
+        if let Some(name) = self.input.update(true, inputs, sounds) {
+            let name = name.trim();
+            let name = if name.is_empty() {
+                DEFAULT_PLAYER_NAME
+            } else {
+                name
+            };
+            self.saved = Some(name.to_string());
+            return SceneResult::Pop;
+        }
+        SceneResult::Continue
+    }
+
+    fn name(&self) -> &str {
+        "name entry"
+    }
+
+    fn postprocess(&self) -> PostprocessProfile {
+        PostprocessProfile::CLEAN
+    }
+
+    fn wants_text_input(&self) -> bool {
+        true
+    }
+
+    fn apply_cvars(&mut self, cvars: &Cvars) {
+        // Only the first time, so what's been typed isn't replaced every frame.
+        if !self.loaded {
+            let name = cvars.get(PLAYER_NAME_CVAR).unwrap_or(DEFAULT_PLAYER_NAME);
+            self.input.set_text(name);
+            self.loaded = true;
+        }
+    }
+
+    fn flush_cvars(&mut self, cvars: &mut Cvars) {
+        if let Some(name) = self.saved.take() {
+            cvars.set(PLAYER_NAME_CVAR, &name);
+        }
+    }
+
+    fn draw(&self, context: &mut RenderContext, font: &Font, _previous: Option<&dyn Scene>) {
+        context.fill_rect(context.ui_area(), RenderLayer::Hud, BACKGROUND_COLOR);
+        let area = self.input.area(context);
+        let centered = |text: &str| area.x + (area.w - font.text_width(text)) / 2;
+        let position = Point::new(centered(LABEL), area.y - font.char_height - MARGIN);
+        font.draw_string(context, RenderLayer::Hud, position, LABEL);
+        self.input.draw(context, RenderLayer::Hud, font, true);
+        let position = Point::new(centered(HINT), area.bottom() + MARGIN);
+        font.draw_string(context, RenderLayer::Hud, position, HINT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inputmanager::TextEdit;
+
+    /// Runs a frame the way the stage manager does, returning whether the screen closed.
+    fn frame(screen: &mut NameEntryScreen, cvars: &mut Cvars, inputs: InputSnapshot) -> bool {
+        let context = RenderContext::new(640, 400, 0).unwrap();
+        screen.apply_cvars(cvars);
+        let result = screen.update(&context, &inputs, &mut SoundManager::noop_manager());
+        screen.flush_cvars(cvars);
+        matches!(result, SceneResult::Pop)
+    }
+
+    #[test]
+    fn save_name() {
+        let mut screen = NameEntryScreen::new();
+        let mut cvars = Cvars::new();
+        cvars.set(PLAYER_NAME_CVAR, "ann");
+        for c in "ie ".chars() {
+            let typing = InputSnapshot {
+                text_edit: Some(TextEdit::Insert(c)),
+                ..Default::default()
+            };
+            assert!(!frame(&mut screen, &mut cvars, typing));
+        }
+        let ok = InputSnapshot {
+            ok_clicked: true,
+            ..Default::default()
+        };
+        assert!(frame(&mut screen, &mut cvars, ok));
+        assert_eq!(cvars.get(PLAYER_NAME_CVAR), Some("annie"));
+
+        // Cancel leaves the name alone.
+        let mut screen = NameEntryScreen::new();
+        let backspace = InputSnapshot {
+            text_edit: Some(TextEdit::Backspace),
+            ..Default::default()
+        };
+        frame(&mut screen, &mut cvars, backspace);
+        let cancel = InputSnapshot {
+            cancel_clicked: true,
+            ..Default::default()
+        };
+        assert!(frame(&mut screen, &mut cvars, cancel));
+        assert_eq!(cvars.get(PLAYER_NAME_CVAR), Some("annie"));
+    }
+}
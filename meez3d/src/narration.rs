@@ -0,0 +1,44 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use log::debug;
+
+/// Speaks text for players who can't see the screen, like menu items as they're focused, or
+/// text as it appears. Hosts implement this with their platform's text to speech.
+pub trait Narrator {
+    fn narrate(&mut self, text: &str);
+}
+
+/// A narrator that only logs what it would say, for hosts without text to speech.
+#[derive(Debug, Default)]
+pub struct LogNarrator;
+
+impl Narrator for LogNarrator {
+    fn narrate(&mut self, text: &str) {
+        debug!("narrating: {}", text);
+    }
+}
+
+/// A narrator that remembers what it was asked to say, for tests.
+#[derive(Default)]
+pub struct RecordingNarrator {
+    spoken: Rc<RefCell<Vec<String>>>,
+}
+
+impl RecordingNarrator {
+    pub fn new() -> RecordingNarrator {
+        RecordingNarrator::default()
+    }
+
+    /// Returns a handle to the list of narrated text, which stays valid after the narrator is
+    /// boxed.
+    pub fn spoken(&self) -> Rc<RefCell<Vec<String>>> {
+        self.spoken.clone()
+    }
+}
+
+impl Narrator for RecordingNarrator {
+    fn narrate(&mut self, text: &str) {
+        self.spoken.borrow_mut().push(text.to_string());
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::VecDeque;
+
+/// Finds a shortest route across a grid, moving between edge-adjacent cells.
+///
+/// The route goes from `from` to the nearest cell where `is_goal` is true, and includes both ends.
+/// Cells are (row, column). The goal cell doesn't have to be passable, so the route can lead up to
+/// something like stairs in a wall. Returns None if no goal can be reached.
+pub fn find_path<G, P>(
+    width: usize,
+    height: usize,
+    from: (usize, usize),
+    is_goal: G,
+    passable: P,
+) -> Option<Vec<(usize, usize)>>
+where
+    G: Fn(usize, usize) -> bool,
+    P: Fn(usize, usize) -> bool,
+{
+    if from.0 >= height || from.1 >= width {
+        return None;
+    }
+    // The cell each cell was first reached from, indexed by row * width + column.
+    let mut came_from: Vec<Option<usize>> = vec![None; width * height];
+    let start = from.0 * width + from.1;
+    came_from[start] = Some(start);
+
+    let mut queue = VecDeque::from([from]);
+    while let Some((row, column)) = queue.pop_front() {
+        if is_goal(row, column) {
+            let mut path = vec![(row, column)];
+            let mut index = row * width + column;
+            while index != start {
+                index = came_from[index].unwrap_or(start);
+                path.push((index / width, index % width));
+            }
+            path.reverse();
+            return Some(path);
+        }
+        if (row, column) != from && !passable(row, column) {
+            continue;
+        }
+        let neighbors = [
+            (row.wrapping_sub(1), column),
+            (row + 1, column),
+            (row, column.wrapping_sub(1)),
+            (row, column + 1),
+        ];
+        for (next_row, next_column) in neighbors {
+            if next_row >= height || next_column >= width {
+                continue;
+            }
+            let index = next_row * width + next_column;
+            if came_from[index].is_none() {
+                came_from[index] = Some(row * width + column);
+                queue.push_back((next_row, next_column));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn around_walls() {
+        let rows = ["....", ".##.", ".#G.", "...."];
+        let cell = |row: usize, column: usize| rows[row].as_bytes()[column];
+        let path = find_path(
+            4,
+            4,
+            (0, 0),
+            |row, column| cell(row, column) == b'G',
+            |row, column| cell(row, column) != b'#',
+        )
+        .unwrap();
+        assert_eq!(path.first(), Some(&(0, 0)));
+        assert_eq!(path.last(), Some(&(2, 2)));
+        // Around either side of the walls is 6 steps.
+        assert_eq!(path.len(), 7);
+        for pair in path.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!(a.0.abs_diff(b.0) + a.1.abs_diff(b.1), 1);
+        }
+
+        let walled_in = find_path(4, 4, (0, 0), |_, _| false, |_, _| true);
+        assert_eq!(walled_in, None);
+    }
+}
@@ -0,0 +1,336 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Instant;
+
+use anyhow::{bail, Error, Result};
+use log::{error, info};
+use serde::Serialize;
+
+use crate::engine::EnginePlugin;
+use crate::filemanager::FileManager;
+use crate::font::Font;
+use crate::rendercontext::RenderContext;
+use crate::stagemanager::StageManager;
+
+/// The cvar that starts a capture, set to the number of frames to record.
+pub const PERF_CAPTURE_CVAR: &str = "perf_capture";
+/// The cvar that picks the format of the next capture, csv or json.
+pub const PERF_CAPTURE_FORMAT_CVAR: &str = "perf_capture_format";
+
+/// The longest capture allowed, so a typo can't fill up memory. This is a minute at 60 fps.
+const MAX_CAPTURE_FRAMES: usize = 3600;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaptureFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl CaptureFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            CaptureFormat::Csv => "csv",
+            CaptureFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for CaptureFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "csv" => CaptureFormat::Csv,
+            "json" => CaptureFormat::Json,
+            _ => bail!("invalid capture format: {}", s),
+        })
+    }
+}
+
+/// The timings and draw stats for one frame of a capture.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FrameSample {
+    pub frame: u64,
+    pub update_millis: f64,
+    pub draw_millis: f64,
+    /// The wall time since the previous frame, including rendering and any sleep.
+    pub frame_millis: f64,
+    pub player_entries: usize,
+    pub hud_entries: usize,
+    pub lights: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct CaptureSummary {
+    frames: usize,
+    mean_frame_millis: f64,
+    max_frame_millis: f64,
+    mean_update_millis: f64,
+    mean_draw_millis: f64,
+}
+
+#[derive(Serialize)]
+struct CaptureFile<'a> {
+    summary: CaptureSummary,
+    samples: &'a [FrameSample],
+}
+
+fn summarize(samples: &[FrameSample]) -> CaptureSummary {
+    let n = samples.len().max(1) as f64;
+    CaptureSummary {
+        frames: samples.len(),
+        mean_frame_millis: samples.iter().map(|s| s.frame_millis).sum::<f64>() / n,
+        max_frame_millis: samples.iter().map(|s| s.frame_millis).fold(0.0, f64::max),
+        mean_update_millis: samples.iter().map(|s| s.update_millis).sum::<f64>() / n,
+        mean_draw_millis: samples.iter().map(|s| s.draw_millis).sum::<f64>() / n,
+    }
+}
+
+/// Formats the samples as a capture file, one row or object per frame.
+pub fn format_capture(samples: &[FrameSample], format: CaptureFormat) -> Result<String> {
+    match format {
+        CaptureFormat::Csv => {
+            let mut text = String::from(
+                "frame,update_millis,draw_millis,frame_millis,player_entries,hud_entries,lights\n",
+            );
+            for s in samples {
+                writeln!(
+                    text,
+                    "{},{:.3},{:.3},{:.3},{},{},{}",
+                    s.frame,
+                    s.update_millis,
+                    s.draw_millis,
+                    s.frame_millis,
+                    s.player_entries,
+                    s.hud_entries,
+                    s.lights
+                )?;
+            }
+            Ok(text)
+        }
+        CaptureFormat::Json => Ok(serde_json::to_string_pretty(&CaptureFile {
+            summary: summarize(samples),
+            samples,
+        })?),
+    }
+}
+
+struct ActiveCapture {
+    frames: usize,
+    format: CaptureFormat,
+    samples: Vec<FrameSample>,
+}
+
+/// Records per-frame timings into a file, for attaching to performance bug reports.
+///
+/// Setting the perf_capture cvar to a number of frames starts a capture, e.g. with
+/// `PUT /cvars/perf_capture` on the debug server. When it's done, the capture is written to
+/// the output directory as perf-<first frame>.csv, or .json if perf_capture_format is json.
+pub struct PerfCapture {
+    files: FileManager,
+    dir: PathBuf,
+    active: Option<ActiveCapture>,
+    update_start: Instant,
+    update_millis: f64,
+    draw_start: Instant,
+    last_frame: Option<Instant>,
+}
+
+impl PerfCapture {
+    pub fn new(files: FileManager, dir: &Path) -> PerfCapture {
+        PerfCapture {
+            files,
+            dir: dir.to_path_buf(),
+            active: None,
+            update_start: Instant::now(),
+            update_millis: 0.0,
+            draw_start: Instant::now(),
+            last_frame: None,
+        }
+    }
+
+    fn start(&mut self, game: &mut StageManager) {
+        let Some(frames) = game.cvars().get_parsed::<usize>(PERF_CAPTURE_CVAR) else {
+            return;
+        };
+        if frames == 0 {
+            return;
+        }
+        // Clear the cvar so the capture only runs once.
+        game.cvars_mut().set(PERF_CAPTURE_CVAR, "0");
+
+        let format = match game.cvars().get(PERF_CAPTURE_FORMAT_CVAR) {
+            Some(format) => format.parse().unwrap_or_else(|e| {
+                error!("{}", e);
+                CaptureFormat::default()
+            }),
+            None => CaptureFormat::default(),
+        };
+        let frames = frames.min(MAX_CAPTURE_FRAMES);
+        info!("starting {:?} perf capture of {} frames", format, frames);
+        self.active = Some(ActiveCapture {
+            frames,
+            format,
+            samples: Vec::with_capacity(frames),
+        });
+    }
+
+    fn finish(&self, capture: &ActiveCapture) -> Result<PathBuf> {
+        let first = capture.samples.first().map(|s| s.frame).unwrap_or(0);
+        let path = self
+            .dir
+            .join(format!("perf-{}.{}", first, capture.format.extension()));
+        let text = format_capture(&capture.samples, capture.format)?;
+        self.files.write(&path, text.as_bytes())?;
+        Ok(path)
+    }
+}
+
+impl EnginePlugin for PerfCapture {
+    fn pre_update(&mut self, _context: &RenderContext, game: &mut StageManager) {
+        if self.active.is_none() {
+            self.start(game);
+        }
+        self.update_start = Instant::now();
+    }
+
+    fn post_update(&mut self, _context: &RenderContext, _game: &mut StageManager) {
+        self.update_millis = self.update_start.elapsed().as_secs_f64() * 1000.0;
+        self.draw_start = Instant::now();
+    }
+
+    fn pre_render(&mut self, context: &mut RenderContext, _font: &Font, _game: &StageManager) {
+        let now = Instant::now();
+        let draw_millis = (now - self.draw_start).as_secs_f64() * 1000.0;
+        let frame_millis = self
+            .last_frame
+            .map(|last| (now - last).as_secs_f64() * 1000.0)
+            .unwrap_or(0.0);
+        self.last_frame = Some(now);
+
+        let Some(capture) = self.active.as_mut() else {
+            return;
+        };
+        capture.samples.push(FrameSample {
+            frame: context.frame,
+            update_millis: self.update_millis,
+            draw_millis,
+            frame_millis,
+            player_entries: context.player_batch.entries.len(),
+            hud_entries: context.hud_batch.entries.len(),
+            lights: context.lights.len(),
+        });
+        if capture.samples.len() < capture.frames {
+            return;
+        }
+
+        let Some(capture) = self.active.take() else {
+            return;
+        };
+        match self.finish(&capture) {
+            Ok(path) => info!(
+                "wrote perf capture to {:?}: {:?}",
+                path,
+                summarize(&capture.samples)
+            ),
+            Err(e) => error!("unable to save perf capture: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::filemanager::{DirEntry, FileManagerImpl};
+    use crate::harness::TestHarness;
+    use crate::inputmanager::InputSnapshot;
+
+    /// Keeps written files in memory, and serves the font the harness needs.
+    struct MemoryFiles {
+        written: Rc<RefCell<HashMap<PathBuf, Vec<u8>>>>,
+    }
+
+    impl FileManagerImpl for MemoryFiles {
+        fn read(&self, path: &Path) -> Result<Vec<u8>> {
+            if path == Path::new("assets/8bitfont.tsx") {
+                return Ok(include_bytes!("../../assets/8bitfont.tsx").to_vec());
+            }
+            bail!("file not found: {:?}", path);
+        }
+
+        fn read_to_string(&self, path: &Path) -> Result<String> {
+            Ok(String::from_utf8(self.read(path)?)?)
+        }
+
+        fn read_dir(&self, _dir_path: &Path) -> Result<Vec<DirEntry>> {
+            Ok(Vec::new())
+        }
+
+        fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+            self.written
+                .borrow_mut()
+                .insert(path.to_path_buf(), data.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn capture() {
+        let written = Rc::new(RefCell::new(HashMap::new()));
+        let files = || {
+            FileManager::with_internal(Box::new(MemoryFiles {
+                written: written.clone(),
+            }))
+        };
+        let mut harness = TestHarness::new(files()).unwrap();
+        harness
+            .engine_mut()
+            .add_plugin(Box::new(PerfCapture::new(files(), Path::new("captures"))));
+
+        let inputs = InputSnapshot::default();
+        harness.step_n(2, &inputs).unwrap();
+        assert!(written.borrow().is_empty());
+
+        let cvars = harness.engine_mut().stage_manager_mut().cvars_mut();
+        cvars.set(PERF_CAPTURE_CVAR, "3");
+        harness.step_n(5, &inputs).unwrap();
+
+        let written = written.borrow();
+        let csv = written.get(Path::new("captures/perf-2.csv")).unwrap();
+        let csv = String::from_utf8(csv.clone()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("frame,"));
+        assert!(lines[3].starts_with("4,"));
+        // The capture only runs once.
+        assert_eq!(written.len(), 1);
+    }
+
+    #[test]
+    fn json() {
+        let samples = vec![
+            FrameSample {
+                frame: 7,
+                frame_millis: 10.0,
+                ..Default::default()
+            },
+            FrameSample {
+                frame: 8,
+                frame_millis: 20.0,
+                ..Default::default()
+            },
+        ];
+        let text = format_capture(&samples, CaptureFormat::Json).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(value["summary"]["mean_frame_millis"], 15.0);
+        assert_eq!(value["summary"]["max_frame_millis"], 20.0);
+        assert_eq!(value["samples"][1]["frame"], 8);
+        assert!("xml".parse::<CaptureFormat>().is_err());
+    }
+}
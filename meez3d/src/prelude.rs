@@ -0,0 +1,13 @@
+//! Everything needed to write a Scene and draw it, for hosts that `use meez3d::prelude::*`.
+
+pub use crate::filemanager::FileManager;
+pub use crate::font::Font;
+pub use crate::geometry::{Point, Rect};
+pub use crate::imagemanager::ImageLoader;
+pub use crate::inputmanager::InputSnapshot;
+pub use crate::rendercontext::{RenderContext, RenderLayer, SpriteBatch};
+pub use crate::savestate::{LevelState, PlayerState};
+pub use crate::scene::{Scene, SceneResult};
+pub use crate::soundmanager::{Sound, SoundManager};
+pub use crate::sprite::{Animation, Sprite, SpriteSheet};
+pub use crate::utils::Color;
@@ -1,6 +1,8 @@
 use std::collections::HashMap;
+use std::fmt::Write;
 
 use anyhow::{anyhow, bail, Result};
+use quick_xml::escape::escape;
 use serde::Deserialize;
 
 fn default_type() -> String {
@@ -29,7 +31,7 @@ enum PropertyValue {
     Bool(bool),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PropertyMap(HashMap<String, PropertyValue>);
 
 impl PropertyMap {
@@ -74,6 +76,40 @@ impl PropertyMap {
             })
             .transpose()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Writes a Tiled <properties> element, or nothing if there are no properties.
+    pub fn write_xml(&self, out: &mut String, indent: &str) -> Result<()> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        let mut keys: Vec<&String> = self.0.keys().collect();
+        keys.sort();
+        writeln!(out, "{indent}<properties>")?;
+        for k in keys {
+            let name = escape(k);
+            match &self.0[k] {
+                PropertyValue::Int(n) => writeln!(
+                    out,
+                    r#"{indent} <property name="{name}" type="int" value="{n}"/>"#
+                )?,
+                PropertyValue::String(s) => writeln!(
+                    out,
+                    r#"{indent} <property name="{name}" value="{}"/>"#,
+                    escape(s)
+                )?,
+                PropertyValue::Bool(b) => writeln!(
+                    out,
+                    r#"{indent} <property name="{name}" type="bool" value="{b}"/>"#
+                )?,
+            }
+        }
+        writeln!(out, "{indent}</properties>")?;
+        Ok(())
+    }
 }
 
 impl Default for PropertyMap {
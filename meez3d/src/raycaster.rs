@@ -0,0 +1,258 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+/// A side of a cell in the map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl Side {
+    /// The direction the side faces, out of its cell, with 0 being right and positive being
+    /// clockwise, in radians.
+    pub fn normal(&self) -> f32 {
+        match self {
+            Side::Left => PI,
+            Side::Right => 0.0,
+            Side::Top => 3.0 * FRAC_PI_2,
+            Side::Bottom => FRAC_PI_2,
+        }
+    }
+}
+
+/// Where a ray starts, and which way it goes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub row: usize,
+    pub column: usize,
+    /// Where in the cell the ray starts, in the range [0.0, 1.0].
+    pub x: f32,
+    /// Where in the cell the ray starts, in the range [0.0, 1.0], with 0 being the top.
+    pub y: f32,
+    /// The direction, with 0 being right and positive being clockwise, in radians.
+    pub angle: f32,
+}
+
+impl Ray {
+    /// A ray starting at a point in the map, in tiles.
+    pub fn from_point(x: f32, y: f32, angle: f32) -> Ray {
+        let (row, column) = (y as usize, x as usize);
+        Ray {
+            row,
+            column,
+            x: x - column as f32,
+            y: y - row as f32,
+            angle,
+        }
+    }
+}
+
+/// Where a ray stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub row: usize,
+    pub column: usize,
+    /// Where the ray stopped, in tiles from the top left of the map.
+    pub x: f32,
+    pub y: f32,
+    /// The side of the cell the ray came in through, or None if it stopped in the cell it
+    /// started in.
+    pub side: Option<Side>,
+    /// The direction the surface the ray hit faces, defined like a ray's angle. If the ray
+    /// stopped where it started, this faces back along the ray.
+    pub normal: f32,
+}
+
+impl RayHit {
+    /// Where across the face of the tile the ray hit, from 0 at its left edge to 1 at its right,
+    /// as seen by someone facing it.
+    pub fn texture_x(&self) -> f32 {
+        // The face runs along y if the normal points left or right, and along x otherwise.
+        let (along, reversed) = if self.normal.cos().abs() > 0.5 {
+            (self.y, self.normal.cos() > 0.0)
+        } else {
+            (self.x, self.normal.sin() < 0.0)
+        };
+        let u = along - along.floor();
+        if reversed {
+            1.0 - u
+        } else {
+            u
+        }
+    }
+}
+
+/// Walks rays through a grid of cells, one cell boundary at a time, using the traversal from
+/// Amanatides and Woo's "A Fast Voxel Traversal Algorithm for Ray Tracing".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Raycaster {
+    width: usize,
+    height: usize,
+}
+
+impl Raycaster {
+    pub fn new(width: usize, height: usize) -> Raycaster {
+        Raycaster { width, height }
+    }
+
+    /// Casts a ray through the map, calling visit with the (row, column) of each cell it passes
+    /// through, in order, starting with the one it starts in. The ray stops in the first cell
+    /// visit returns true for, except that if skip_start is set, it can't stop where it starts.
+    /// Returns None if the ray leaves the map without stopping.
+    pub fn cast(
+        &self,
+        ray: Ray,
+        skip_start: bool,
+        mut visit: impl FnMut(usize, usize) -> bool,
+    ) -> Option<RayHit> {
+        let (mut row, mut column) = (ray.row, ray.column);
+        if row >= self.height || column >= self.width {
+            return None;
+        }
+        let (start_x, start_y) = (column as f32 + ray.x, row as f32 + ray.y);
+        if visit(row, column) && !skip_start {
+            return Some(RayHit {
+                row,
+                column,
+                x: start_x,
+                y: start_y,
+                side: None,
+                normal: ray.angle + PI,
+            });
+        }
+
+        let (dx, dy) = (ray.angle.cos(), ray.angle.sin());
+        // How far along the ray the next vertical and horizontal cell boundaries are, and how
+        // far it is from one boundary to the next.
+        let (mut next_x, step_x) = first_crossing(ray.x, dx);
+        let (mut next_y, step_y) = first_crossing(ray.y, dy);
+        loop {
+            let (t, side) = if next_x < next_y {
+                let t = next_x;
+                next_x += step_x;
+                if dx > 0.0 {
+                    column += 1;
+                    (t, Side::Left)
+                } else {
+                    column = column.checked_sub(1)?;
+                    (t, Side::Right)
+                }
+            } else {
+                let t = next_y;
+                next_y += step_y;
+                if dy > 0.0 {
+                    row += 1;
+                    (t, Side::Top)
+                } else {
+                    row = row.checked_sub(1)?;
+                    (t, Side::Bottom)
+                }
+            };
+            if row >= self.height || column >= self.width {
+                return None;
+            }
+            if visit(row, column) {
+                // Land exactly on the boundary that was crossed, so the hit is in the cell.
+                let (x, y) = match side {
+                    Side::Left => (column as f32, start_y + t * dy),
+                    Side::Right => ((column + 1) as f32, start_y + t * dy),
+                    Side::Top => (start_x + t * dx, row as f32),
+                    Side::Bottom => (start_x + t * dx, (row + 1) as f32),
+                };
+                return Some(RayHit {
+                    row,
+                    column,
+                    x,
+                    y,
+                    side: Some(side),
+                    normal: side.normal(),
+                });
+            }
+        }
+    }
+}
+
+/// For a ray starting at offset in a cell and moving direction per unit along the ray, returns
+/// how far along the ray it first crosses a cell boundary, and how far it goes between
+/// boundaries after that, both infinite if it never crosses one.
+fn first_crossing(offset: f32, direction: f32) -> (f32, f32) {
+    if direction > 0.0 {
+        ((1.0 - offset) / direction, 1.0 / direction)
+    } else if direction < 0.0 {
+        (offset / -direction, 1.0 / -direction)
+    } else {
+        (f32::INFINITY, f32::INFINITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A raycaster for a map, and whether each cell is a wall.
+    fn walls<'a>(rows: &'a [&'a str]) -> (Raycaster, impl Fn(usize, usize) -> bool + 'a) {
+        let raycaster = Raycaster::new(rows[0].len(), rows.len());
+        (raycaster, move |row: usize, column: usize| {
+            rows[row].as_bytes()[column] == b'#'
+        })
+    }
+
+    #[test]
+    fn straight_and_diagonal() {
+        let (raycaster, is_wall) = walls(&["#####", "#...#", "#...#", "#####"]);
+        let hit = raycaster
+            .cast(Ray::from_point(1.5, 1.5, 0.0), false, &is_wall)
+            .unwrap();
+        assert_eq!((hit.row, hit.column), (1, 4));
+        assert_eq!(hit.side, Some(Side::Left));
+        assert_eq!(hit.x, 4.0);
+        assert!((hit.y - 1.5).abs() < 1e-6);
+
+        // Straight up, where the tangent is infinite.
+        let hit = raycaster
+            .cast(Ray::from_point(2.5, 2.5, 3.0 * FRAC_PI_2), false, &is_wall)
+            .unwrap();
+        assert_eq!((hit.row, hit.column, hit.side), (0, 2, Some(Side::Bottom)));
+        assert_eq!(hit.y, 1.0);
+        assert_eq!(hit.normal, FRAC_PI_2);
+
+        // Down and to the right, into the bottom wall.
+        let angle = 1.5f32.atan2(1.0);
+        let hit = raycaster
+            .cast(Ray::from_point(1.5, 1.5, angle), false, &is_wall)
+            .unwrap();
+        assert_eq!((hit.row, hit.column, hit.side), (3, 2, Some(Side::Top)));
+        assert!((hit.x - 2.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn visits_and_bounds() {
+        let (raycaster, _) = walls(&["....", "....", "...."]);
+        let mut path = Vec::new();
+        let hit = raycaster.cast(Ray::from_point(0.5, 1.5, 0.1), false, |row, column| {
+            path.push((row, column));
+            false
+        });
+        assert_eq!(hit, None);
+        assert_eq!(path, vec![(1, 0), (1, 1), (1, 2), (1, 3)]);
+
+        // Leaving off the top or left of the map doesn't wrap around.
+        let hit = raycaster.cast(Ray::from_point(0.5, 0.5, PI + 0.5), false, |_, _| true);
+        assert_eq!(hit.map(|hit| hit.side), Some(None));
+        let hit = raycaster.cast(Ray::from_point(0.5, 0.5, PI + 0.5), true, |_, _| false);
+        assert_eq!(hit, None);
+
+        // A ray starting on the edge of its cell still starts in that cell.
+        let ray = Ray {
+            row: 1,
+            column: 1,
+            x: 1.0,
+            y: 0.5,
+            angle: 0.0,
+        };
+        let hit = raycaster.cast(ray, true, |_, _| true).unwrap();
+        assert_eq!((hit.row, hit.column, hit.x), (1, 2, 2.0));
+    }
+}
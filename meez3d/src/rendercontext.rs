@@ -3,17 +3,45 @@ use std::f32::consts::PI;
 use anyhow::Result;
 use log::warn;
 
-use crate::constants::{CIRCLE_STEPS, MAX_LIGHTS};
-use crate::geometry::{Point, Rect};
+use crate::constants::{MAX_CIRCLE_STEPS, MAX_LIGHTS};
+use crate::framearena::{ArenaSlice, FrameArena};
+use crate::geometry::{triangulate, Point, Rect};
+use crate::renderer::RendererStats;
 use crate::sprite::Sprite;
+use crate::transition::TransitionFrame;
 use crate::utils::Color;
 
+/// The cvar for how big the HUD is drawn, as a multiple of its normal size.
+pub const UI_SCALE_CVAR: &str = "ui_scale";
+/// The UI scale is clamped to this range, so the HUD can't vanish or swallow the screen.
+pub const MIN_UI_SCALE: f32 = 0.25;
+pub const MAX_UI_SCALE: f32 = 4.0;
+/// The cvar for how far in from each edge of the screen the HUD and menus are kept, as a
+/// percentage of its size, for TVs and projectors that crop the picture.
+pub const SAFE_AREA_CVAR: &str = "safe_area";
+/// The safe area inset is clamped to this percentage, so there's always room for the HUD.
+pub const MAX_SAFE_AREA: f32 = 10.0;
+
 pub enum SpriteBatchEntry {
     Sprite {
         sprite: Sprite,
         source: Rect<i32>,
         destination: Rect<i32>,
         reversed: bool,
+        /// Multiplied with the sprite's pixels, so white draws the sprite unchanged.
+        tint: Color,
+        /// Mixed over the tinted pixels by its alpha, e.g. to flash the sprite white when it's
+        /// hit, so transparent draws the sprite unchanged.
+        flash: Color,
+    },
+    /// A sprite turned, stretched, or flipped around a point in its destination.
+    TransformedSprite {
+        sprite: Sprite,
+        source: Rect<i32>,
+        destination: Rect<i32>,
+        transform: SpriteTransform,
+        tint: Color,
+        flash: Color,
     },
     FillRect {
         destination: Rect<i32>,
@@ -25,17 +53,219 @@ pub enum SpriteBatchEntry {
         p3: Point<i32>,
         color: Color,
     },
+    FillGradientRect {
+        destination: Rect<i32>,
+        /// The colors at the top left, top right, bottom right, and bottom left corners.
+        colors: [Color; 4],
+    },
+    FillGradientTriangle {
+        p1: Point<i32>,
+        p2: Point<i32>,
+        p3: Point<i32>,
+        /// The colors at p1, p2, and p3, blended across the triangle.
+        colors: [Color; 3],
+    },
     Line {
         start: Point<i32>,
         end: Point<i32>,
         color: Color,
         width: i32,
+        /// Round caps are added as separate circles, so renderers draw them like butt caps.
+        cap: LineCap,
     },
 }
 
+impl SpriteBatchEntry {
+    /// The smallest rect containing everything the entry draws, e.g. to tell whether two entries
+    /// can be drawn in either order.
+    pub fn bounds(&self) -> Rect<i32> {
+        match self {
+            SpriteBatchEntry::Sprite { destination, .. }
+            | SpriteBatchEntry::FillRect { destination, .. }
+            | SpriteBatchEntry::FillGradientRect { destination, .. } => *destination,
+            SpriteBatchEntry::TransformedSprite {
+                destination,
+                transform,
+                ..
+            } => enclosing_rect(&transform.corners(*destination)),
+            SpriteBatchEntry::FillTriangle { p1, p2, p3, .. }
+            | SpriteBatchEntry::FillGradientTriangle { p1, p2, p3, .. } => {
+                bounding_rect(&[*p1, *p2, *p3], 0)
+            }
+            SpriteBatchEntry::Line {
+                start, end, width, ..
+            } => bounding_rect(&[*start, *end], width / 2 + 1),
+        }
+    }
+}
+
+/// How a sprite is turned, stretched, and flipped as it's drawn, e.g. for weapon sway or a
+/// spinning pickup on the HUD.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpriteTransform {
+    /// How far the sprite is turned clockwise on screen, in radians.
+    pub angle: f32,
+    /// The point it's turned and stretched around, from the top left of its destination.
+    pub pivot: Point<f32>,
+    /// How much it's stretched across and down. A negative scale mirrors it across the pivot.
+    pub scale: Point<f32>,
+    /// Mirrors the sprite left to right within its destination.
+    pub flip_horizontal: bool,
+    /// Mirrors the sprite top to bottom within its destination.
+    pub flip_vertical: bool,
+}
+
+impl Default for SpriteTransform {
+    fn default() -> Self {
+        SpriteTransform {
+            angle: 0.0,
+            pivot: Point::new(0.0, 0.0),
+            scale: Point::new(1.0, 1.0),
+            flip_horizontal: false,
+            flip_vertical: false,
+        }
+    }
+}
+
+impl SpriteTransform {
+    /// Turns a sprite by angle around the middle of a destination, e.g. for a spinning icon.
+    pub fn rotation(angle: f32, destination: Rect<i32>) -> SpriteTransform {
+        SpriteTransform {
+            angle,
+            pivot: Point::new(destination.w as f32 / 2.0, destination.h as f32 / 2.0),
+            ..Default::default()
+        }
+    }
+
+    /// Where the top left, top right, bottom right, and bottom left corners of the destination
+    /// end up on screen.
+    pub fn corners(&self, destination: Rect<i32>) -> [Point<f32>; 4] {
+        let pivot = Point::new(destination.x as f32, destination.y as f32) + self.pivot;
+        let (w, h) = (destination.w as f32, destination.h as f32);
+        let (sin, cos) = self.angle.sin_cos();
+        [(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)].map(|(x, y)| {
+            let x = (x - self.pivot.x) * self.scale.x;
+            let y = (y - self.pivot.y) * self.scale.y;
+            pivot + Point::new(x * cos - y * sin, x * sin + y * cos)
+        })
+    }
+}
+
+/// How the ends of a line are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineCap {
+    /// The line stops exactly at its end points.
+    #[default]
+    Butt,
+    /// The line goes past its end points by half its width.
+    Square,
+    /// The line ends in a half circle around each end point.
+    Round,
+}
+
+/// The corners of a line drawn as a rectangle of the given width, in order around it.
+///
+/// A zero-length line has no direction, so it's empty unless it has square caps, in which case
+/// it's a square around the point.
+pub fn line_quad(start: Point<i32>, end: Point<i32>, width: i32, cap: LineCap) -> [Point<f32>; 4] {
+    let p1 = Point::new(start.x as f32, start.y as f32);
+    let p2 = Point::new(end.x as f32, end.y as f32);
+    let half_width = width.max(1) as f32 / 2.0;
+
+    let dx = p2.x - p1.x;
+    let dy = p2.y - p1.y;
+    let length = (dx * dx + dy * dy).sqrt();
+    let direction = if length > 0.0 {
+        Point::new(dx / length, dy / length)
+    } else if cap == LineCap::Square {
+        Point::new(1.0, 0.0)
+    } else {
+        return [p1; 4];
+    };
+
+    let (p1, p2) = if cap == LineCap::Square {
+        let extension = direction * half_width;
+        (p1 - extension, p2 + extension)
+    } else {
+        (p1, p2)
+    };
+    let side = Point::new(-direction.y, direction.x) * half_width;
+    [p1 - side, p1 + side, p2 + side, p2 - side]
+}
+
+/// The one-pixel-high rows that cover a convex polygon, for renderers that fill shapes a row at a
+/// time, like the SDL canvas.
+pub fn convex_spans(points: &[Point<f32>]) -> Vec<Rect<i32>> {
+    let mut spans = Vec::new();
+    let Some(top) = points.iter().map(|p| p.y).reduce(f32::min) else {
+        return spans;
+    };
+    let bottom = points.iter().map(|p| p.y).fold(top, f32::max);
+
+    // Each pixel is covered if its center is inside the polygon.
+    let first_row = (top - 0.5).ceil() as i32;
+    let last_row = (bottom - 0.5).floor() as i32;
+    for row in first_row..=last_row {
+        let y = row as f32 + 0.5;
+        let mut left = f32::MAX;
+        let mut right = f32::MIN;
+        for (i, a) in points.iter().enumerate() {
+            let b = points[(i + 1) % points.len()];
+            if a.y == b.y || y < a.y.min(b.y) || y > a.y.max(b.y) {
+                continue;
+            }
+            let x = a.x + (y - a.y) * (b.x - a.x) / (b.y - a.y);
+            left = left.min(x);
+            right = right.max(x);
+        }
+        let x0 = (left - 0.5).ceil() as i32;
+        let x1 = (right - 0.5).floor() as i32;
+        if x1 >= x0 {
+            spans.push(Rect {
+                x: x0,
+                y: row,
+                w: x1 - x0 + 1,
+                h: 1,
+            });
+        }
+    }
+    spans
+}
+
+/// The color at a point in a triangle, blended from the colors at its corners.
+pub fn triangle_color(points: &[Point<f32>; 3], colors: &[Color; 3], x: f32, y: f32) -> Color {
+    let [a, b, c] = points;
+    let area = (b.x - a.x) * (c.y - a.y) - (c.x - a.x) * (b.y - a.y);
+    if area == 0.0 {
+        return colors[0];
+    }
+    let wb = ((x - a.x) * (c.y - a.y) - (c.x - a.x) * (y - a.y)) / area;
+    let wc = ((b.x - a.x) * (y - a.y) - (x - a.x) * (b.y - a.y)) / area;
+    let ab = if wb + wc > 0.0 {
+        colors[1].lerp(colors[2], wc / (wb + wc))
+    } else {
+        colors[1]
+    };
+    colors[0].lerp(ab, wb + wc)
+}
+
 pub struct SpriteBatch {
     pub clear_color: Color,
+    /// The tint applied to every sprite drawn until it's changed.
+    pub tint: Color,
+    /// The color mixed over every sprite drawn until it's changed.
+    pub flash: Color,
     pub entries: Vec<SpriteBatchEntry>,
+    /// When set, entries that are entirely outside this area are dropped instead of added.
+    pub cull_area: Option<Rect<i32>>,
+    /// How many entries have been dropped by culling since the batch was reset.
+    pub culled: u32,
+    /// Multiplies the position and size of everything drawn, but not the sprite sources, e.g.
+    /// for the HUD's UI scale. Culling happens after scaling.
+    pub scale: f32,
+    /// Scratch space for the points and edges of paths and arcs, freed with the rest of the frame.
+    points: FrameArena<Point<f32>>,
+    sides: FrameArena<(Point<f32>, Point<f32>)>,
 }
 
 impl SpriteBatch {
@@ -48,20 +278,115 @@ impl SpriteBatch {
                 b: 0,
                 a: 0,
             },
+            tint: Color::WHITE,
+            flash: Color::TRANSPARENT,
             entries: Vec::new(),
+            cull_area: None,
+            culled: 0,
+            scale: 1.0,
+            points: FrameArena::new(),
+            sides: FrameArena::new(),
+        }
+    }
+
+    /// Empties the batch for a new frame, keeping the memory it's already allocated.
+    pub fn reset(&mut self) {
+        self.clear_color = Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        };
+        self.tint = Color::WHITE;
+        self.flash = Color::TRANSPARENT;
+        self.entries.clear();
+        self.points.reset();
+        self.sides.reset();
+        self.culled = 0;
+    }
+
+    fn scale_point(&self, p: Point<i32>) -> Point<i32> {
+        if self.scale == 1.0 {
+            return p;
+        }
+        Point::new(
+            (p.x as f32 * self.scale).round() as i32,
+            (p.y as f32 * self.scale).round() as i32,
+        )
+    }
+
+    /// Scales both corners, so rects that touch still touch after scaling.
+    fn scale_rect(&self, rect: Rect<i32>) -> Rect<i32> {
+        if self.scale == 1.0 {
+            return rect;
+        }
+        let top_left = self.scale_point(Point::new(rect.x, rect.y));
+        let bottom_right = self.scale_point(Point::new(rect.right(), rect.bottom()));
+        Rect {
+            x: top_left.x,
+            y: top_left.y,
+            w: bottom_right.x - top_left.x,
+            h: bottom_right.y - top_left.y,
+        }
+    }
+
+    /// Returns whether something with these bounds can be dropped, and counts it if so.
+    fn cull(&mut self, bounds: Rect<i32>) -> bool {
+        match self.cull_area {
+            Some(area) if !area.intersects(bounds) => {
+                self.culled += 1;
+                true
+            }
+            _ => false,
         }
     }
 
     pub fn draw(&mut self, sprite: Sprite, dst: Rect<i32>, src: Rect<i32>, reversed: bool) {
+        let dst = self.scale_rect(dst);
+        if self.cull(dst) {
+            return;
+        }
         self.entries.push(SpriteBatchEntry::Sprite {
             sprite,
             source: src,
             destination: dst,
             reversed,
+            tint: self.tint,
+            flash: self.flash,
+        });
+    }
+
+    /// Draws a sprite turned, stretched, or flipped around a point in its destination.
+    pub fn draw_transformed(
+        &mut self,
+        sprite: Sprite,
+        dst: Rect<i32>,
+        src: Rect<i32>,
+        transform: SpriteTransform,
+    ) {
+        let dst = self.scale_rect(dst);
+        let transform = SpriteTransform {
+            pivot: transform.pivot * self.scale,
+            ..transform
+        };
+        if self.cull(enclosing_rect(&transform.corners(dst))) {
+            return;
+        }
+        self.entries.push(SpriteBatchEntry::TransformedSprite {
+            sprite,
+            source: src,
+            destination: dst,
+            transform,
+            tint: self.tint,
+            flash: self.flash,
         });
     }
 
     pub fn fill_rect(&mut self, rect: Rect<i32>, color: Color) {
+        let rect = self.scale_rect(rect);
+        if self.cull(rect) {
+            return;
+        }
         self.entries.push(SpriteBatchEntry::FillRect {
             destination: rect,
             color,
@@ -69,54 +394,154 @@ impl SpriteBatch {
     }
 
     pub fn fill_triangle(&mut self, p1: Point<i32>, p2: Point<i32>, p3: Point<i32>, color: Color) {
+        let [p1, p2, p3] = [p1, p2, p3].map(|p| self.scale_point(p));
+        if self.cull(bounding_rect(&[p1, p2, p3], 0)) {
+            return;
+        }
         self.entries
             .push(SpriteBatchEntry::FillTriangle { p1, p2, p3, color });
     }
 
-    pub fn draw_line(&mut self, point1: Point<i32>, point2: Point<i32>, color: Color, width: i32) {
-        if point1.y == point2.y {
-            // horizontal
-            let rect = if point1.x > point2.x {
-                Rect {
-                    x: point2.x,
-                    y: point2.y - width / 2,
-                    w: point1.x - point2.x,
-                    h: width,
-                }
-            } else {
-                Rect {
-                    x: point1.x,
-                    y: point1.y - width / 2,
-                    w: point2.x - point1.x,
-                    h: width,
-                }
-            };
-            self.fill_rect(rect, color);
-        } else if point1.x == point2.x {
-            // vertical
-            let rect = if point1.y > point2.y {
-                Rect {
-                    x: point2.x - width / 2,
-                    y: point2.y,
-                    w: width,
-                    h: point1.y - point2.y,
-                }
-            } else {
-                Rect {
-                    x: point1.x - width / 2,
-                    y: point1.y,
-                    w: width,
-                    h: point2.y - point1.y,
-                }
-            };
-            self.fill_rect(rect, color);
+    /// Fills a rect with the colors at its corners blended across it.
+    ///
+    /// The corners are in clockwise order, starting from the top left.
+    pub fn fill_gradient_rect(&mut self, rect: Rect<i32>, colors: [Color; 4]) {
+        let rect = self.scale_rect(rect);
+        if self.cull(rect) {
+            return;
+        }
+        self.entries.push(SpriteBatchEntry::FillGradientRect {
+            destination: rect,
+            colors,
+        });
+    }
+
+    pub fn fill_vertical_gradient(&mut self, rect: Rect<i32>, top: Color, bottom: Color) {
+        self.fill_gradient_rect(rect, [top, top, bottom, bottom]);
+    }
+
+    pub fn fill_horizontal_gradient(&mut self, rect: Rect<i32>, left: Color, right: Color) {
+        self.fill_gradient_rect(rect, [left, right, right, left]);
+    }
+
+    pub fn fill_gradient_triangle(
+        &mut self,
+        p1: Point<i32>,
+        p2: Point<i32>,
+        p3: Point<i32>,
+        colors: [Color; 3],
+    ) {
+        let [p1, p2, p3] = [p1, p2, p3].map(|p| self.scale_point(p));
+        if self.cull(bounding_rect(&[p1, p2, p3], 0)) {
+            return;
+        }
+        self.entries
+            .push(SpriteBatchEntry::FillGradientTriangle { p1, p2, p3, colors });
+    }
+
+    /// Adds a triangle facing the camera, whichever order its points are in.
+    ///
+    /// The wgpu renderer culls triangles that are clockwise on screen, which a computed
+    /// triangle can easily be.
+    fn fill_triangle_any_winding(
+        &mut self,
+        p1: Point<i32>,
+        p2: Point<i32>,
+        p3: Point<i32>,
+        color: Color,
+    ) {
+        let cross = (p2.x - p1.x) as i64 * (p3.y - p1.y) as i64
+            - (p3.x - p1.x) as i64 * (p2.y - p1.y) as i64;
+        if cross > 0 {
+            self.fill_triangle(p1, p3, p2, color);
         } else {
-            self.entries.push(SpriteBatchEntry::Line {
-                start: point1,
-                end: point2,
-                color,
-                width,
-            })
+            self.fill_triangle(p1, p2, p3, color);
+        }
+    }
+
+    /// Fills a simple polygon, which may be concave but shouldn't cross itself.
+    pub fn fill_polygon(&mut self, points: &[Point<i32>], color: Color) {
+        for [p1, p2, p3] in triangulate(points) {
+            self.fill_triangle_any_winding(p1, p2, p3, color);
+        }
+    }
+
+    /// Draws connected line segments, with mitred corners where they meet.
+    pub fn draw_polyline(&mut self, points: &[Point<i32>], color: Color, width: i32) {
+        let path = self.points.alloc(to_f32_points(points));
+        self.draw_path(path, false, color, width);
+    }
+
+    /// Draws the outline of a polygon, with mitred corners.
+    pub fn draw_polygon(&mut self, points: &[Point<i32>], color: Color, width: i32) {
+        let path = self.points.alloc(to_f32_points(points));
+        self.draw_path(path, true, color, width);
+    }
+
+    fn draw_path(&mut self, path: ArenaSlice, closed: bool, color: Color, width: i32) {
+        let mut path = path.truncated(dedup_points(self.points.get_mut(path)));
+        let points = self.points.get(path);
+        if closed && points.len() > 1 && points.first() == points.last() {
+            path = path.truncated(points.len() - 1);
+        }
+        let n = path.len();
+        if n < 2 {
+            return;
+        }
+
+        let sides = path_sides(
+            self.points.get(path),
+            closed,
+            width.max(1) as f32 / 2.0,
+            &mut self.sides,
+        );
+        let segments = if closed { n } else { n - 1 };
+        for i in 0..segments {
+            let sides = self.sides.get(sides);
+            let (left1, right1) = sides[i];
+            let (left2, right2) = sides[(i + 1) % n];
+            let (left1, right1, left2, right2) = (
+                round_point(left1),
+                round_point(right1),
+                round_point(left2),
+                round_point(right2),
+            );
+            self.fill_triangle_any_winding(left1, left2, right2, color);
+            self.fill_triangle_any_winding(left1, right2, right1, color);
+        }
+    }
+
+    pub fn draw_line(&mut self, point1: Point<i32>, point2: Point<i32>, color: Color, width: i32) {
+        self.draw_line_with_cap(point1, point2, color, width, LineCap::Butt);
+    }
+
+    pub fn draw_line_with_cap(
+        &mut self,
+        point1: Point<i32>,
+        point2: Point<i32>,
+        color: Color,
+        width: i32,
+        cap: LineCap,
+    ) {
+        let (start, end) = (self.scale_point(point1), self.scale_point(point2));
+        let scaled_width = ((width.max(1) as f32 * self.scale).round() as i32).max(1);
+        // Half the width on either side covers square caps, too.
+        if self.cull(bounding_rect(&[start, end], scaled_width / 2 + 1)) {
+            return;
+        }
+        self.entries.push(SpriteBatchEntry::Line {
+            start,
+            end,
+            color,
+            width: scaled_width,
+            cap,
+        });
+        if cap == LineCap::Round {
+            let radius = width.max(1) as f32 / 2.0;
+            self.fill_circle(point1, radius, color);
+            if point2 != point1 {
+                self.fill_circle(point2, radius, color);
+            }
         }
     }
 
@@ -124,6 +549,7 @@ impl SpriteBatch {
         self.fill_arc(center, radius, 0.0, 2.0 * PI, color);
     }
 
+    /// Fills a pie slice from start_theta to end_theta, in radians clockwise from the right.
     pub fn fill_arc(
         &mut self,
         center: Point<i32>,
@@ -132,49 +558,228 @@ impl SpriteBatch {
         end_theta: f32,
         color: Color,
     ) {
-        let mut theta: f32 = start_theta;
-        let mut current = Point::new(theta.cos(), theta.sin());
-        let dtheta = (2.0 * PI) / (CIRCLE_STEPS as f32);
-        // TODO: Make the arc actually end at the right spot.
-        while theta <= end_theta {
-            let next_theta = theta + dtheta;
-            let next = Point::new(next_theta.cos(), next_theta.sin());
+        let points = arc_points(&mut self.points, center, radius, start_theta, end_theta);
+        for i in 1..points.len() {
+            let pair = &self.points.get(points)[i - 1..=i];
+            let (point1, point2) = (round_point(pair[0]), round_point(pair[1]));
+            self.fill_triangle_any_winding(center, point1, point2, color);
+        }
+    }
 
-            let p1 = current * radius;
-            let p2 = next * radius;
-            let p1 = Point::new(p1.x as i32, p1.y as i32);
-            let p2 = Point::new(p2.x as i32, p2.y as i32);
-            let p1 = p1 + center;
-            let p2 = p2 + center;
+    pub fn draw_circle(&mut self, center: Point<i32>, radius: f32, color: Color, width: i32) {
+        let points = arc_points(&mut self.points, center, radius, 0.0, 2.0 * PI);
+        self.draw_path(points.truncated(points.len() - 1), true, color, width);
+    }
+
+    /// Draws the outline of an arc from start_theta to end_theta, in radians clockwise from the right.
+    pub fn draw_arc(
+        &mut self,
+        center: Point<i32>,
+        radius: f32,
+        start_theta: f32,
+        end_theta: f32,
+        color: Color,
+        width: i32,
+    ) {
+        let points = arc_points(&mut self.points, center, radius, start_theta, end_theta);
+        self.draw_path(points, false, color, width);
+    }
 
-            self.fill_triangle(center, p2, p1, color);
+    /// Fills the space between two circles, like a donut.
+    pub fn fill_ring(
+        &mut self,
+        center: Point<i32>,
+        inner_radius: f32,
+        outer_radius: f32,
+        color: Color,
+    ) {
+        self.fill_ring_arc(center, inner_radius, outer_radius, 0.0, 2.0 * PI, color);
+    }
 
-            current = next;
-            theta = next_theta;
+    /// Fills part of a ring, like a cooldown dial.
+    pub fn fill_ring_arc(
+        &mut self,
+        center: Point<i32>,
+        inner_radius: f32,
+        outer_radius: f32,
+        start_theta: f32,
+        end_theta: f32,
+        color: Color,
+    ) {
+        let outer = arc_points(
+            &mut self.points,
+            center,
+            outer_radius,
+            start_theta,
+            end_theta,
+        );
+        // The inner edge uses the same angles as the outer one, so the quads line up.
+        let steps = outer.len() - 1;
+        let inner = arc_points_with_steps(
+            &mut self.points,
+            center,
+            inner_radius,
+            start_theta,
+            end_theta,
+            steps,
+        );
+        for i in 0..steps {
+            let (outer, inner) = (self.points.get(outer), self.points.get(inner));
+            let (outer1, outer2) = (round_point(outer[i]), round_point(outer[i + 1]));
+            let (inner1, inner2) = (round_point(inner[i]), round_point(inner[i + 1]));
+            self.fill_triangle_any_winding(outer1, outer2, inner2, color);
+            self.fill_triangle_any_winding(outer1, inner2, inner1, color);
         }
     }
+}
 
-    pub fn draw_circle(&mut self, center: Point<i32>, radius: f32, color: Color, width: i32) {
-        let mut theta: f32 = 0.0;
-        let mut current = Point::new(theta.cos(), theta.sin());
-        let dtheta = (2.0 * PI) / (CIRCLE_STEPS as f32);
-        while theta <= 2.0 * PI {
-            let next_theta = theta + dtheta;
-            let next = Point::new(next_theta.cos(), next_theta.sin());
+/// How far the straight segments of an arc may stray from the true curve, in pixels.
+const ARC_TOLERANCE: f32 = 0.25;
 
-            let p1 = current * radius;
-            let p2 = next * radius;
-            let p1 = Point::new(p1.x as i32, p1.y as i32);
-            let p2 = Point::new(p2.x as i32, p2.y as i32);
-            let p1 = p1 + center;
-            let p2 = p2 + center;
+/// How many segments an arc needs to look smooth, which grows with its radius. An arc can't go
+/// around more than once, so a huge or non-finite sweep can't make it allocate without bound.
+fn arc_steps(radius: f32, sweep: f32) -> usize {
+    if !radius.is_finite() || !sweep.is_finite() {
+        return 1;
+    }
+    let sweep = sweep.abs().min(2.0 * PI);
+    let full_circle_steps = if radius <= ARC_TOLERANCE {
+        4.0
+    } else {
+        let step = 2.0 * (1.0 - ARC_TOLERANCE / radius).acos();
+        (2.0 * PI / step).clamp(4.0, MAX_CIRCLE_STEPS as f32)
+    };
+    ((full_circle_steps * sweep / (2.0 * PI)).ceil() as usize).max(1)
+}
+
+/// Allocates points along an arc, including both ends exactly.
+fn arc_points(
+    arena: &mut FrameArena<Point<f32>>,
+    center: Point<i32>,
+    radius: f32,
+    start_theta: f32,
+    end_theta: f32,
+) -> ArenaSlice {
+    let steps = arc_steps(radius, end_theta - start_theta);
+    arc_points_with_steps(arena, center, radius, start_theta, end_theta, steps)
+}
 
-            self.draw_line(p1, p2, color, width);
+fn arc_points_with_steps(
+    arena: &mut FrameArena<Point<f32>>,
+    center: Point<i32>,
+    radius: f32,
+    start_theta: f32,
+    end_theta: f32,
+    steps: usize,
+) -> ArenaSlice {
+    let center = Point::new(center.x as f32, center.y as f32);
+    arena.alloc((0..=steps).map(|i| {
+        let theta = start_theta + (end_theta - start_theta) * (i as f32 / steps as f32);
+        center + Point::new(theta.cos(), theta.sin()) * radius
+    }))
+}
+
+fn to_f32_points(points: &[Point<i32>]) -> impl Iterator<Item = Point<f32>> + '_ {
+    points.iter().map(|p| Point::new(p.x as f32, p.y as f32))
+}
 
-            current = next;
-            theta = next_theta;
+/// Moves each run of repeated points down to one point, returning how many points are left.
+fn dedup_points(points: &mut [Point<f32>]) -> usize {
+    let mut len = 0;
+    for i in 0..points.len() {
+        if len == 0 || points[i] != points[len - 1] {
+            points[len] = points[i];
+            len += 1;
         }
     }
+    len
+}
+
+/// The smallest rect containing all of the points, grown by margin on every side.
+fn bounding_rect(points: &[Point<i32>], margin: i32) -> Rect<i32> {
+    let left = points.iter().map(|p| p.x).min().unwrap_or(0) - margin;
+    let right = points.iter().map(|p| p.x).max().unwrap_or(0) + margin;
+    let top = points.iter().map(|p| p.y).min().unwrap_or(0) - margin;
+    let bottom = points.iter().map(|p| p.y).max().unwrap_or(0) + margin;
+    Rect {
+        x: left,
+        y: top,
+        w: right - left,
+        h: bottom - top,
+    }
+}
+
+/// The smallest rect of whole pixels covering all of the points. Points within a thousandth of
+/// a pixel of an edge count as on it, so rounding errors don't grow the rect.
+fn enclosing_rect(points: &[Point<f32>]) -> Rect<i32> {
+    const SLOP: f32 = 0.001;
+    let min = |values: &mut dyn Iterator<Item = f32>| values.fold(f32::INFINITY, f32::min);
+    let max = |values: &mut dyn Iterator<Item = f32>| values.fold(f32::NEG_INFINITY, f32::max);
+    let left = (min(&mut points.iter().map(|p| p.x)) + SLOP).floor() as i32;
+    let right = (max(&mut points.iter().map(|p| p.x)) - SLOP).ceil() as i32;
+    let top = (min(&mut points.iter().map(|p| p.y)) + SLOP).floor() as i32;
+    let bottom = (max(&mut points.iter().map(|p| p.y)) - SLOP).ceil() as i32;
+    Rect {
+        x: left,
+        y: top,
+        w: right - left,
+        h: bottom - top,
+    }
+}
+
+fn round_point(p: Point<f32>) -> Point<i32> {
+    Point::new(p.x.round() as i32, p.y.round() as i32)
+}
+
+/// How far a mitred corner can stick out, in half line widths, before it's cut off.
+const MITER_LIMIT: f32 = 4.0;
+
+fn normal(from: Point<f32>, to: Point<f32>) -> Point<f32> {
+    let dx = to.x - from.x;
+    let dy = to.y - from.y;
+    let length = (dx * dx + dy * dy).sqrt();
+    Point::new(-dy / length, dx / length)
+}
+
+/// Allocates the points on either side of each point on a path, where the edges of a wide line
+/// meet.
+fn path_sides(
+    points: &[Point<f32>],
+    closed: bool,
+    half_width: f32,
+    sides: &mut FrameArena<(Point<f32>, Point<f32>)>,
+) -> ArenaSlice {
+    let n = points.len();
+    sides.alloc((0..n).map(|i| {
+        let point = points[i];
+        let before = if i > 0 || closed {
+            Some(normal(points[(i + n - 1) % n], point))
+        } else {
+            None
+        };
+        let after = if i + 1 < n || closed {
+            Some(normal(point, points[(i + 1) % n]))
+        } else {
+            None
+        };
+        let offset = match (before, after) {
+            (Some(before), Some(after)) => {
+                let miter = before + after;
+                let length = (miter.x * miter.x + miter.y * miter.y).sqrt();
+                if length < f32::EPSILON {
+                    // The path doubles back on itself.
+                    after * half_width
+                } else {
+                    let miter = miter * (1.0 / length);
+                    let cos = miter.x * after.x + miter.y * after.y;
+                    miter * (half_width / cos.max(1.0 / MITER_LIMIT))
+                }
+            }
+            (Some(normal), None) | (None, Some(normal)) => normal * half_width,
+            (None, None) => Point::new(0.0, 0.0),
+        };
+        (point + offset, point - offset)
+    }))
 }
 
 pub struct Light {
@@ -186,35 +791,238 @@ pub struct Light {
 pub enum RenderLayer {
     Player,
     Hud,
+    /// Drawn on top after postprocessing, so it's never darkened, warped, or blended away in a
+    /// transition, e.g. for pause menus and debug text.
+    Overlay,
+}
+
+/// Which of the retro postprocessing effects run over a frame, and how strongly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PostprocessProfile {
+    /// Curves the image like an old CRT tube.
+    pub tube_warp: bool,
+    /// How far the red and blue channels are shifted apart, as a fraction of the screen width.
+    pub color_split: f32,
+    /// Softens the edges of pixels as they're scaled up, instead of keeping them sharp.
+    pub fuzz: bool,
+    /// How much the rolling scanlines are mixed in, from 0 to 1.
+    pub scanlines: f32,
+    /// How much TV static is mixed in, from 0 to 1.
+    pub static_noise: f32,
+    /// How much the world wobbles and turns blue, like it's seen through water, from 0 to 1.
+    pub underwater: f32,
+}
+
+impl PostprocessProfile {
+    /// The full retro treatment, for gameplay.
+    pub const RETRO: PostprocessProfile = PostprocessProfile {
+        tube_warp: true,
+        color_split: 0.002,
+        fuzz: true,
+        scanlines: 0.015,
+        static_noise: 0.04,
+        underwater: 0.0,
+    };
+
+    /// No effects at all, for scenes like menus that should be crisp.
+    pub const CLEAN: PostprocessProfile = PostprocessProfile {
+        tube_warp: false,
+        color_split: 0.0,
+        fuzz: false,
+        scanlines: 0.0,
+        static_noise: 0.0,
+        underwater: 0.0,
+    };
+}
+
+impl Default for PostprocessProfile {
+    fn default() -> Self {
+        PostprocessProfile::RETRO
+    }
 }
 
 pub struct RenderContext {
     pub player_batch: SpriteBatch,
     pub hud_batch: SpriteBatch,
+    pub overlay_batch: SpriteBatch,
     pub width: u32,
     pub height: u32,
     pub frame: u64,
     pub lights: Vec<Light>,
     pub is_dark: bool,
+    /// The player layer's colors are multiplied by this, e.g. for the time of day.
+    pub ambient_light: Color,
+    /// Set by the stage manager from the current scene every frame.
+    pub postprocess: PostprocessProfile,
+    /// Stats from the previous frame's render, set by the engine, e.g. for a debug overlay.
+    pub renderer_stats: RendererStats,
+    /// Set by the stage manager while it's blending from the last scene into this one.
+    pub transition: Option<TransitionFrame>,
+    /// The last frame the previous scene drew, set by the engine while there's a transition.
+    pub outgoing: Option<Box<RenderContext>>,
+    /// How far in the safe area is from each edge, as a percentage, from the safe_area cvar.
+    safe_area_inset: f32,
 }
 
 impl RenderContext {
+    /// Every batch starts out culling anything outside the logical area, since it can't be seen.
     pub fn new(width: u32, height: u32, frame: u64) -> Result<RenderContext> {
-        let player_batch = SpriteBatch::new();
-        let hud_batch = SpriteBatch::new();
+        let area = Rect {
+            x: 0,
+            y: 0,
+            w: width as i32,
+            h: height as i32,
+        };
+        let mut player_batch = SpriteBatch::new();
+        player_batch.cull_area = Some(area);
+        let mut hud_batch = SpriteBatch::new();
+        hud_batch.cull_area = Some(area);
+        let mut overlay_batch = SpriteBatch::new();
+        overlay_batch.cull_area = Some(area);
         let lights = Vec::new();
         let is_dark = false;
         Ok(RenderContext {
             player_batch,
             hud_batch,
+            overlay_batch,
             width,
             height,
             frame,
             lights,
             is_dark,
+            ambient_light: Color::WHITE,
+            postprocess: PostprocessProfile::default(),
+            renderer_stats: RendererStats::default(),
+            transition: None,
+            outgoing: None,
+            safe_area_inset: 0.0,
+        })
+    }
+
+    /// Empties the context to draw a new frame, keeping the memory it's already allocated.
+    pub fn reset(&mut self, frame: u64) {
+        self.player_batch.reset();
+        self.hud_batch.reset();
+        self.overlay_batch.reset();
+        self.frame = frame;
+        self.lights.clear();
+        self.is_dark = false;
+        self.ambient_light = Color::WHITE;
+        self.postprocess = PostprocessProfile::default();
+        self.renderer_stats = RendererStats::default();
+        self.transition = None;
+        self.outgoing = None;
+    }
+
+    /// Every sprite drawn into the batches this frame, so they can be pointed somewhere else in
+    /// the atlas after it's reloaded. The outgoing frame isn't included, since it's already been
+    /// through this.
+    pub fn sprites_mut(&mut self) -> impl Iterator<Item = &mut Sprite> + '_ {
+        [
+            &mut self.player_batch,
+            &mut self.hud_batch,
+            &mut self.overlay_batch,
+        ]
+        .into_iter()
+        .flat_map(|batch| batch.entries.iter_mut())
+        .filter_map(|entry| match entry {
+            SpriteBatchEntry::Sprite { sprite, .. }
+            | SpriteBatchEntry::TransformedSprite { sprite, .. } => Some(sprite),
+            _ => None,
         })
     }
 
+    /// The outgoing scene's frame, if it can be seen in this one.
+    pub fn visible_outgoing(&self) -> Option<(TransitionFrame, &RenderContext)> {
+        let transition = self.transition.filter(TransitionFrame::shows_outgoing)?;
+        Some((transition, self.outgoing.as_deref()?))
+    }
+
+    /// The HUD's scale factor, from the ui_scale cvar.
+    pub fn ui_scale(&self) -> f32 {
+        self.hud_batch.scale
+    }
+
+    /// The whole screen in HUD units, which are scaled by the UI scale as they're drawn.
+    pub fn ui_area(&self) -> Rect<i32> {
+        let scale = self.ui_scale();
+        Rect {
+            x: 0,
+            y: 0,
+            w: (self.width as f32 / scale).round() as i32,
+            h: (self.height as f32 / scale).round() as i32,
+        }
+    }
+
+    /// How far in the safe area is from each edge of the screen, as a percentage of its size.
+    pub fn safe_area_inset(&self) -> f32 {
+        self.safe_area_inset
+    }
+
+    /// Sets how far in the safe area is, from the safe_area cvar.
+    pub fn set_safe_area_inset(&mut self, percent: f32) {
+        self.safe_area_inset = percent;
+    }
+
+    /// The part of the screen that's still seen on a TV that crops the picture, in HUD units.
+    /// Anything anchored to an edge of the screen is anchored to this instead.
+    pub fn safe_area(&self) -> Rect<i32> {
+        let area = self.ui_area();
+        let x = (area.w as f32 * self.safe_area_inset / 100.0).round() as i32;
+        let y = (area.h as f32 * self.safe_area_inset / 100.0).round() as i32;
+        Rect {
+            x,
+            y,
+            w: area.w - x * 2,
+            h: area.h - y * 2,
+        }
+    }
+
+    /// Moves and shrinks a rect laid out on the whole screen in HUD units, like a menu button,
+    /// into the same place in the safe area.
+    pub fn to_safe_area(&self, rect: Rect<i32>) -> Rect<i32> {
+        let area = self.ui_area();
+        let safe = self.safe_area();
+        let scale_x = safe.w as f32 / area.w.max(1) as f32;
+        let scale_y = safe.h as f32 / area.h.max(1) as f32;
+        let x = safe.x + (rect.x as f32 * scale_x).round() as i32;
+        let y = safe.y + (rect.y as f32 * scale_y).round() as i32;
+        Rect {
+            x,
+            y,
+            w: safe.x + (rect.right() as f32 * scale_x).round() as i32 - x,
+            h: safe.y + (rect.bottom() as f32 * scale_y).round() as i32 - y,
+        }
+    }
+
+    /// Converts a point on screen, like the mouse position, to HUD units.
+    pub fn to_ui_point(&self, point: Point<i32>) -> Point<i32> {
+        let scale = self.ui_scale();
+        Point::new(
+            (point.x as f32 / scale).round() as i32,
+            (point.y as f32 / scale).round() as i32,
+        )
+    }
+
+    /// How many entries every layer has culled this frame, e.g. for a debug overlay.
+    pub fn culled(&self) -> u32 {
+        self.player_batch.culled + self.hud_batch.culled + self.overlay_batch.culled
+    }
+
+    /// Sets the scale of the layers that use UI units, from the ui_scale cvar.
+    pub fn set_ui_scale(&mut self, scale: f32) {
+        self.hud_batch.scale = scale;
+        self.overlay_batch.scale = scale;
+    }
+
+    fn batch_mut(&mut self, layer: RenderLayer) -> &mut SpriteBatch {
+        match layer {
+            RenderLayer::Player => &mut self.player_batch,
+            RenderLayer::Hud => &mut self.hud_batch,
+            RenderLayer::Overlay => &mut self.overlay_batch,
+        }
+    }
+
     pub fn logical_area(&self) -> Rect<i32> {
         // TODO: This should be cacheable.
         Rect {
@@ -226,10 +1034,7 @@ impl RenderContext {
     }
 
     pub fn draw(&mut self, sprite: Sprite, layer: RenderLayer, dst: Rect<i32>, src: Rect<i32>) {
-        match layer {
-            RenderLayer::Player => self.player_batch.draw(sprite, dst, src, false),
-            RenderLayer::Hud => self.hud_batch.draw(sprite, dst, src, false),
-        }
+        self.batch_mut(layer).draw(sprite, dst, src, false);
     }
 
     pub fn draw_reversed(
@@ -239,22 +1044,47 @@ impl RenderContext {
         dst: Rect<i32>,
         src: Rect<i32>,
     ) {
-        match layer {
-            RenderLayer::Player => self.player_batch.draw(sprite, dst, src, true),
-            RenderLayer::Hud => self.hud_batch.draw(sprite, dst, src, true),
-        }
+        self.batch_mut(layer).draw(sprite, dst, src, true);
+    }
+
+    /// Draws a sprite turned, stretched, or flipped, e.g. a weapon swaying as the player walks.
+    pub fn draw_transformed(
+        &mut self,
+        sprite: Sprite,
+        layer: RenderLayer,
+        dst: Rect<i32>,
+        src: Rect<i32>,
+        transform: SpriteTransform,
+    ) {
+        self.batch_mut(layer)
+            .draw_transformed(sprite, dst, src, transform);
+    }
+
+    /// Tints every sprite drawn on the layer from now on, e.g. to fade out a map layer.
+    pub fn set_tint(&mut self, layer: RenderLayer, tint: Color) {
+        self.batch_mut(layer).tint = tint;
+    }
+
+    /// Mixes a color over every sprite drawn on the layer from now on, e.g. to fade the HUD to
+    /// white.
+    pub fn set_flash(&mut self, layer: RenderLayer, flash: Color) {
+        self.batch_mut(layer).flash = flash;
     }
 
     pub fn fill_rect(&mut self, rect: Rect<i32>, layer: RenderLayer, color: Color) {
-        match layer {
-            RenderLayer::Player => self.player_batch.fill_rect(rect, color),
-            RenderLayer::Hud => self.hud_batch.fill_rect(rect, color),
-        }
+        self.batch_mut(layer).fill_rect(rect, color);
     }
 
     pub fn clear(&mut self) {
         self.player_batch.entries.clear();
         self.hud_batch.entries.clear();
+        self.overlay_batch.entries.clear();
+        self.player_batch.tint = Color::WHITE;
+        self.hud_batch.tint = Color::WHITE;
+        self.overlay_batch.tint = Color::WHITE;
+        self.player_batch.flash = Color::TRANSPARENT;
+        self.hud_batch.flash = Color::TRANSPARENT;
+        self.overlay_batch.flash = Color::TRANSPARENT;
         self.player_batch.clear_color = Color {
             r: 0,
             g: 0,
@@ -266,6 +1096,12 @@ impl RenderContext {
             g: 0,
             b: 0,
             a: 0,
+        };
+        self.overlay_batch.clear_color = Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
         }
     }
 
@@ -277,3 +1113,405 @@ impl RenderContext {
         self.lights.push(Light { position, radius });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangles(batch: &SpriteBatch) -> Vec<[Point<i32>; 3]> {
+        batch
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                SpriteBatchEntry::FillTriangle { p1, p2, p3, .. } => Some([*p1, *p2, *p3]),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn polygons() {
+        let mut batch = SpriteBatch::new();
+        let l_shape = [
+            Point::new(0, 0),
+            Point::new(20, 0),
+            Point::new(20, 10),
+            Point::new(10, 10),
+            Point::new(10, 20),
+            Point::new(0, 20),
+        ];
+        batch.fill_polygon(&l_shape, Color::WHITE);
+        let filled = triangles(&batch);
+        assert_eq!(filled.len(), 4);
+        for [a, b, c] in filled {
+            // Clockwise on screen would be culled.
+            let cross = (b.x - a.x) * (c.y - a.y) - (c.x - a.x) * (b.y - a.y);
+            assert!(cross < 0);
+        }
+
+        let mut batch = SpriteBatch::new();
+        let corner = [Point::new(0, 0), Point::new(10, 0), Point::new(10, 10)];
+        batch.draw_polyline(&corner, Color::WHITE, 2);
+        let drawn = triangles(&batch);
+        assert_eq!(drawn.len(), 4);
+        // The outside of the corner is mitred to a point instead of being cut off.
+        assert!(drawn.iter().flatten().any(|p| *p == Point::new(11, -1)));
+
+        let mut batch = SpriteBatch::new();
+        batch.draw_polygon(&corner, Color::WHITE, 2);
+        assert_eq!(triangles(&batch).len(), 6);
+    }
+
+    #[test]
+    fn line_caps() {
+        let start = Point::new(0, 0);
+        let end = Point::new(10, 0);
+        let butt = line_quad(start, end, 2, LineCap::Butt);
+        assert_eq!(
+            butt,
+            [
+                Point::new(0.0, -1.0),
+                Point::new(0.0, 1.0),
+                Point::new(10.0, 1.0),
+                Point::new(10.0, -1.0),
+            ]
+        );
+        let square = line_quad(start, end, 2, LineCap::Square);
+        assert_eq!(square[0], Point::new(-1.0, -1.0));
+        assert_eq!(square[2], Point::new(11.0, 1.0));
+
+        // Vertical lines are just as wide as any other.
+        let vertical = line_quad(start, Point::new(0, 10), 4, LineCap::Butt);
+        assert_eq!(vertical[0], Point::new(2.0, 0.0));
+        assert_eq!(vertical[1], Point::new(-2.0, 0.0));
+
+        assert_eq!(
+            line_quad(start, start, 2, LineCap::Butt),
+            [Point::new(0.0, 0.0); 4]
+        );
+        assert_eq!(
+            line_quad(start, start, 2, LineCap::Square)[2],
+            Point::new(1.0, 1.0)
+        );
+
+        let mut batch = SpriteBatch::new();
+        batch.draw_line_with_cap(start, end, Color::WHITE, 2, LineCap::Round);
+        assert!(triangles(&batch).len() > 2);
+    }
+
+    #[test]
+    fn arcs() {
+        // The last point lands exactly on the end angle, however the steps divide up.
+        let mut arena = FrameArena::new();
+        let points = arc_points(&mut arena, Point::new(0, 0), 10.0, 0.0, 1.0);
+        let last = arena.get(points).last().unwrap();
+        assert!((last.x - 1.0f32.cos() * 10.0).abs() < 1e-4);
+        assert!((last.y - 1.0f32.sin() * 10.0).abs() < 1e-4);
+
+        // Bigger circles get more steps, up to a limit.
+        let small = arc_steps(2.0, 2.0 * PI);
+        let large = arc_steps(100.0, 2.0 * PI);
+        assert!(small >= 4);
+        assert!(large > small);
+        assert_eq!(arc_steps(100000.0, 2.0 * PI), MAX_CIRCLE_STEPS as usize);
+
+        // Sweeps past a full circle, or that aren't numbers, stay bounded.
+        assert_eq!(arc_steps(100.0, 1e30), arc_steps(100.0, 2.0 * PI));
+        assert_eq!(arc_steps(100.0, -1e30), arc_steps(100.0, 2.0 * PI));
+        assert_eq!(arc_steps(100.0, f32::INFINITY), 1);
+        assert_eq!(arc_steps(100.0, f32::NAN), 1);
+        assert_eq!(arc_steps(f32::INFINITY, PI), 1);
+
+        let mut batch = SpriteBatch::new();
+        batch.fill_ring_arc(Point::new(50, 50), 10.0, 20.0, 0.0, PI, Color::WHITE);
+        let drawn = triangles(&batch);
+        assert_eq!(drawn.len(), 2 * arc_steps(20.0, PI));
+        for [a, b, c] in drawn {
+            let cross = (b.x - a.x) * (c.y - a.y) - (c.x - a.x) * (b.y - a.y);
+            assert!(cross <= 0);
+        }
+    }
+
+    #[test]
+    fn reset() {
+        let mut context = RenderContext::new(640, 400, 0).unwrap();
+        context
+            .player_batch
+            .draw_circle(Point::new(50, 50), 20.0, Color::WHITE, 2);
+        context.set_tint(
+            RenderLayer::Hud,
+            Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 128,
+            },
+        );
+        context.set_flash(RenderLayer::Player, Color::WHITE);
+        context.add_light(Point::new(10, 10), 5);
+        context.is_dark = true;
+        let capacity = context.player_batch.entries.capacity();
+        assert!(capacity > 0);
+
+        context.reset(1);
+        assert_eq!(context.frame, 1);
+        assert!(context.player_batch.entries.is_empty());
+        assert!(context.lights.is_empty());
+        assert!(!context.is_dark);
+        assert!(context.player_batch.points.items().is_empty());
+        assert_eq!(context.hud_batch.tint, Color::WHITE);
+        assert_eq!(context.player_batch.flash, Color::TRANSPARENT);
+        assert_eq!(context.player_batch.entries.capacity(), capacity);
+    }
+
+    #[test]
+    fn culling() {
+        let mut context = RenderContext::new(640, 400, 0).unwrap();
+        let on_screen = Rect {
+            x: 630,
+            y: 390,
+            w: 20,
+            h: 20,
+        };
+        let off_screen = Rect {
+            x: 650,
+            y: 0,
+            w: 20,
+            h: 20,
+        };
+        context.fill_rect(on_screen, RenderLayer::Player, Color::WHITE);
+        context.fill_rect(off_screen, RenderLayer::Player, Color::WHITE);
+        context.player_batch.fill_triangle(
+            Point::new(-10, -10),
+            Point::new(-20, -10),
+            Point::new(-10, -20),
+            Color::WHITE,
+        );
+        // A wide line reaches the screen even though both of its ends are off of it.
+        context.player_batch.draw_line_with_cap(
+            Point::new(-2, 50),
+            Point::new(-2, 100),
+            Color::WHITE,
+            6,
+            LineCap::Butt,
+        );
+        assert_eq!(context.player_batch.entries.len(), 2);
+        assert_eq!(context.culled(), 2);
+
+        context.hud_batch.cull_area = None;
+        context.fill_rect(off_screen, RenderLayer::Hud, Color::WHITE);
+        assert_eq!(context.hud_batch.entries.len(), 1);
+
+        context.reset(1);
+        assert_eq!(context.culled(), 0);
+        assert!(context.player_batch.cull_area.is_some());
+    }
+
+    #[test]
+    fn transforms() {
+        let destination = Rect {
+            x: 100,
+            y: 50,
+            w: 20,
+            h: 10,
+        };
+        let round = |corners: [Point<f32>; 4]| corners.map(round_point);
+        assert_eq!(
+            round(SpriteTransform::default().corners(destination)),
+            [
+                Point::new(100, 50),
+                Point::new(120, 50),
+                Point::new(120, 60),
+                Point::new(100, 60)
+            ]
+        );
+
+        // A quarter turn clockwise around the middle stands it on its end.
+        let turned = SpriteTransform::rotation(PI / 2.0, destination);
+        assert_eq!(
+            round(turned.corners(destination)),
+            [
+                Point::new(115, 45),
+                Point::new(115, 65),
+                Point::new(105, 65),
+                Point::new(105, 45)
+            ]
+        );
+
+        // Doubling the height around the bottom edge stretches it upward.
+        let stretched = SpriteTransform {
+            pivot: Point::new(10.0, 10.0),
+            scale: Point::new(1.0, 2.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            round(stretched.corners(destination))[0],
+            Point::new(100, 40)
+        );
+
+        let mut context = RenderContext::new(640, 400, 0).unwrap();
+        let sprite = Sprite {
+            id: 0,
+            area: destination,
+        };
+        context.draw_transformed(
+            sprite,
+            RenderLayer::Player,
+            destination,
+            destination,
+            turned,
+        );
+        assert_eq!(
+            context.player_batch.entries[0].bounds(),
+            Rect {
+                x: 105,
+                y: 45,
+                w: 10,
+                h: 20,
+            }
+        );
+        // Turned, it reaches onto the screen from just above it.
+        let above = Rect {
+            y: -14,
+            ..destination
+        };
+        context.draw_transformed(sprite, RenderLayer::Player, above, destination, turned);
+        assert_eq!(context.player_batch.entries.len(), 2);
+        context.draw(sprite, RenderLayer::Player, above, destination);
+        assert_eq!(context.culled(), 1);
+    }
+
+    #[test]
+    fn ui_scale() {
+        let mut context = RenderContext::new(640, 400, 0).unwrap();
+        context.set_ui_scale(0.5);
+        assert_eq!(context.ui_area().w, 1280);
+        assert_eq!(
+            context.to_ui_point(Point::new(100, 50)),
+            Point::new(200, 100)
+        );
+
+        let button = Rect {
+            x: 200,
+            y: 100,
+            w: 394,
+            h: 145,
+        };
+        context.fill_rect(button, RenderLayer::Hud, Color::WHITE);
+        context.fill_rect(button, RenderLayer::Player, Color::WHITE);
+        context.fill_rect(button, RenderLayer::Overlay, Color::WHITE);
+        let destination = |batch: &SpriteBatch| match batch.entries[0] {
+            SpriteBatchEntry::FillRect { destination, .. } => destination,
+            _ => panic!("expected a rect"),
+        };
+        assert_eq!(
+            destination(&context.hud_batch),
+            Rect {
+                x: 100,
+                y: 50,
+                w: 197,
+                h: 73,
+            }
+        );
+        assert_eq!(destination(&context.player_batch), button);
+        // The overlay is in UI units, too.
+        assert_eq!(
+            destination(&context.overlay_batch),
+            destination(&context.hud_batch)
+        );
+
+        // Something just off the HUD's area is scaled onto the screen, so it isn't culled.
+        context.fill_rect(
+            Rect {
+                x: 700,
+                y: 0,
+                w: 10,
+                h: 10,
+            },
+            RenderLayer::Hud,
+            Color::WHITE,
+        );
+        assert_eq!(context.hud_batch.entries.len(), 2);
+    }
+
+    #[test]
+    fn safe_area() {
+        let mut context = RenderContext::new(640, 400, 0).unwrap();
+        assert_eq!(context.safe_area(), context.ui_area());
+        context.set_safe_area_inset(5.0);
+        let safe = Rect {
+            x: 32,
+            y: 20,
+            w: 576,
+            h: 360,
+        };
+        assert_eq!(context.safe_area(), safe);
+
+        // The bottom right quarter of the screen is the bottom right quarter of the safe area.
+        assert_eq!(context.to_safe_area(context.ui_area()), safe);
+        let quarter = Rect {
+            x: 320,
+            y: 200,
+            w: 320,
+            h: 200,
+        };
+        let moved = Rect {
+            x: 320,
+            y: 200,
+            w: 288,
+            h: 180,
+        };
+        assert_eq!(context.to_safe_area(quarter), moved);
+
+        // It's in HUD units, so it grows with them.
+        context.set_ui_scale(0.5);
+        assert_eq!(context.safe_area().top_left(), Point::new(64, 40));
+    }
+
+    #[test]
+    fn spans() {
+        let triangle = [
+            Point::new(0.0, 0.0),
+            Point::new(4.0, 0.0),
+            Point::new(0.0, 4.0),
+        ];
+        let widths: Vec<i32> = convex_spans(&triangle).iter().map(|s| s.w).collect();
+        assert_eq!(widths, vec![4, 3, 2, 1]);
+
+        // A horizontal line two pixels wide covers two rows.
+        let line = line_quad(Point::new(1, 5), Point::new(6, 5), 2, LineCap::Butt);
+        let spans = convex_spans(&line);
+        assert_eq!(spans.len(), 2);
+        assert_eq!((spans[0].x, spans[0].y, spans[0].w), (1, 4, 5));
+        assert_eq!((spans[1].x, spans[1].y, spans[1].w), (1, 5, 5));
+    }
+
+    #[test]
+    fn gradients() {
+        let points = [
+            Point::new(0.0, 0.0),
+            Point::new(10.0, 0.0),
+            Point::new(0.0, 10.0),
+        ];
+        let red = Color {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let blue = Color {
+            r: 0,
+            g: 0,
+            b: 255,
+            a: 255,
+        };
+        let colors = [red, blue, Color::WHITE];
+        assert_eq!(triangle_color(&points, &colors, 0.0, 0.0), red);
+        assert_eq!(triangle_color(&points, &colors, 10.0, 0.0), blue);
+        assert_eq!(triangle_color(&points, &colors, 0.0, 10.0), Color::WHITE);
+        assert_eq!(
+            triangle_color(&points, &colors, 5.0, 0.0),
+            red.lerp(blue, 0.5)
+        );
+    }
+}
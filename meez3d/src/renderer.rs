@@ -1,9 +1,209 @@
+use std::collections::HashMap;
 use std::path::Path;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use image::RgbaImage;
+use serde::Serialize;
 
+use crate::filemanager::FileManager;
+use crate::geometry::Rect;
+use crate::rendercontext::RenderContext;
 use crate::sprite::Sprite;
 
+/// What a renderer has allocated, and how much work its last frame took.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RendererStats {
+    /// The size of every vertex and uniform buffer.
+    pub buffer_bytes: u64,
+    /// An estimate of texture memory, assuming 4 bytes per pixel.
+    pub texture_bytes: u64,
+    /// How many times the last frame bound a render pipeline.
+    pub pipeline_switches: u32,
+    /// Each draw call in a layer uses a different texture from the one before.
+    pub draw_calls: u32,
+    pub player_vertices: u32,
+    pub hud_vertices: u32,
+    pub overlay_vertices: u32,
+}
+
+/// A graphics backend, so ImageManager and the hosts don't depend on any one of them.
 pub trait Renderer {
     fn load_sprite(&mut self, path: &Path) -> Result<Sprite>;
+
+    /// Loads the image sprites are cut from, replacing any previous atlases.
+    ///
+    /// Returns a sprite covering the whole atlas, which the texture atlas index subdivides.
+    fn load_texture_atlas(&mut self, path: &Path, files: &FileManager) -> Result<Sprite>;
+
+    /// Makes the atlas from width * height RGBA pixels instead, e.g. from images packed at
+    /// startup, replacing any previous atlases.
+    fn create_texture_atlas(&mut self, width: u32, height: u32, _pixels: &[u8]) -> Result<Sprite> {
+        bail!(
+            "this renderer can't create a {}x{} texture atlas",
+            width,
+            height
+        )
+    }
+
+    /// Adds another atlas alongside the ones already loaded, for art that doesn't fit in one.
+    /// Its sprites have a different id from the other atlases'.
+    fn add_texture_atlas(&mut self, width: u32, height: u32, _pixels: &[u8]) -> Result<Sprite> {
+        bail!(
+            "this renderer can't add a {}x{} texture atlas",
+            width,
+            height
+        )
+    }
+
+    /// Creates a blank RGBA texture that can be redrawn every frame, e.g. for a minimap.
+    fn create_dynamic_texture(&mut self, width: u32, height: u32) -> Result<Sprite>;
+
+    /// Replaces the contents of a dynamic texture with width * height RGBA pixels.
+    fn update_dynamic_texture(&mut self, sprite: &Sprite, pixels: &[u8]) -> Result<()>;
+
+    /// Overwrites the part of a texture atlas a sprite covers with area.w * area.h RGBA pixels,
+    /// e.g. to fill in a region added at runtime.
+    fn update_texture_atlas(&mut self, sprite: &Sprite, _pixels: &[u8]) -> Result<()> {
+        bail!(
+            "this renderer can't update the texture atlas at {:?}",
+            sprite.area
+        )
+    }
+
+    /// Called when the window has been resized, in physical pixels.
+    fn resize(&mut self, width: u32, height: u32);
+
+    fn render(&mut self, context: &RenderContext) -> Result<()>;
+
+    /// Starts or stops keeping a copy of each frame rendered, e.g. for recording clips. Reading
+    /// frames back can stall the renderer, so it's off until it's asked for, and hosts can turn
+    /// it on for only the frames they need.
+    fn set_capturing(&mut self, _capturing: bool) {}
+
+    /// The last frame rendered while capturing, unless it's already been taken. Renderers that
+    /// can't read frames back never have one.
+    fn take_captured_frame(&mut self) -> Option<RgbaImage> {
+        None
+    }
+
+    /// Renderers that don't track stats report all zeroes.
+    fn stats(&self) -> RendererStats {
+        RendererStats::default()
+    }
+}
+
+/// A renderer that doesn't load or draw anything, for running the game without a window.
+#[derive(Default)]
+pub struct NullRenderer {
+    next_id: usize,
+    /// The sizes of the dynamic textures, so updates can still be checked.
+    dynamic_textures: HashMap<usize, (u32, u32)>,
+}
+
+impl NullRenderer {
+    pub fn new() -> NullRenderer {
+        NullRenderer::default()
+    }
+
+    fn next_sprite(&mut self, w: i32, h: i32) -> Sprite {
+        let id = self.next_id;
+        self.next_id += 1;
+        Sprite {
+            id,
+            area: Rect { x: 0, y: 0, w, h },
+        }
+    }
+}
+
+impl Renderer for NullRenderer {
+    fn load_sprite(&mut self, _path: &Path) -> Result<Sprite> {
+        Ok(self.next_sprite(0, 0))
+    }
+
+    fn load_texture_atlas(&mut self, _path: &Path, _files: &FileManager) -> Result<Sprite> {
+        Ok(self.next_sprite(0, 0))
+    }
+
+    fn create_texture_atlas(&mut self, width: u32, height: u32, pixels: &[u8]) -> Result<Sprite> {
+        let expected = (width * height * 4) as usize;
+        if width == 0 || height == 0 || pixels.len() != expected {
+            bail!(
+                "invalid {}x{} texture atlas with {} bytes",
+                width,
+                height,
+                pixels.len()
+            );
+        }
+        Ok(self.next_sprite(width as i32, height as i32))
+    }
+
+    fn add_texture_atlas(&mut self, width: u32, height: u32, pixels: &[u8]) -> Result<Sprite> {
+        self.create_texture_atlas(width, height, pixels)
+    }
+
+    fn create_dynamic_texture(&mut self, width: u32, height: u32) -> Result<Sprite> {
+        if width == 0 || height == 0 {
+            bail!("invalid dynamic texture size: {}x{}", width, height);
+        }
+        let sprite = self.next_sprite(width as i32, height as i32);
+        self.dynamic_textures.insert(sprite.id, (width, height));
+        Ok(sprite)
+    }
+
+    fn update_dynamic_texture(&mut self, sprite: &Sprite, pixels: &[u8]) -> Result<()> {
+        let (width, height) = self
+            .dynamic_textures
+            .get(&sprite.id)
+            .ok_or_else(|| anyhow!("not a dynamic texture: {}", sprite.id))?;
+        let expected = (width * height * 4) as usize;
+        if pixels.len() != expected {
+            bail!(
+                "dynamic texture {} needs {} bytes, but got {}",
+                sprite.id,
+                expected,
+                pixels.len()
+            );
+        }
+        Ok(())
+    }
+
+    fn update_texture_atlas(&mut self, sprite: &Sprite, pixels: &[u8]) -> Result<()> {
+        let area = sprite.area;
+        let expected = (area.w.max(0) * area.h.max(0) * 4) as usize;
+        if pixels.len() != expected {
+            bail!(
+                "atlas region {:?} needs {} bytes, but got {}",
+                area,
+                expected,
+                pixels.len()
+            );
+        }
+        Ok(())
+    }
+
+    fn resize(&mut self, _width: u32, _height: u32) {}
+
+    fn render(&mut self, _context: &RenderContext) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dynamic_textures() {
+        let mut renderer = NullRenderer::new();
+        assert!(renderer.create_dynamic_texture(0, 4).is_err());
+
+        let sprite = renderer.create_dynamic_texture(2, 3).unwrap();
+        assert_eq!((sprite.area.w, sprite.area.h), (2, 3));
+        renderer.update_dynamic_texture(&sprite, &[0; 24]).unwrap();
+        assert!(renderer.update_dynamic_texture(&sprite, &[0; 12]).is_err());
+
+        let other = renderer.load_sprite(Path::new("a.png")).unwrap();
+        assert_ne!(other.id, sprite.id);
+        assert!(renderer.update_dynamic_texture(&other, &[]).is_err());
+    }
 }
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hasher;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::aimassist::AIM_ASSIST_CVAR;
+use crate::cvars::Cvars;
+use crate::debugflags::{GOD_CVAR, NOCLIP_CVAR};
+use crate::enemystats::DIFFICULTY_CVAR;
+use crate::faction::HOSTILITY_CVAR;
+use crate::filemanager::FileManager;
+use crate::harness::TestHarness;
+use crate::inputmanager::{InputRecorder, InputSnapshot, CHEATS_CVAR};
+use crate::level::{MOUSE_LOOK_CVAR, MOUSE_SENSITIVITY_CVAR};
+use crate::transition::{TRANSITION_CVAR, TRANSITION_FRAMES_CVAR};
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// The cvars that change how the game plays out, so a replay has to set them the same way.
+pub const REPLAY_CVARS: &[&str] = &[
+    DIFFICULTY_CVAR,
+    HOSTILITY_CVAR,
+    MOUSE_LOOK_CVAR,
+    MOUSE_SENSITIVITY_CVAR,
+    AIM_ASSIST_CVAR,
+    GOD_CVAR,
+    NOCLIP_CVAR,
+    CHEATS_CVAR,
+    TRANSITION_CVAR,
+    TRANSITION_FRAMES_CVAR,
+];
+
+/// An FNV-1a hasher for the state of the game, which, unlike the standard library's hasher, is
+/// guaranteed to give the same hashes on every build and platform, so replays stay valid.
+#[derive(Debug, Clone, Copy)]
+pub struct StateHasher(u64);
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        StateHasher(FNV_OFFSET)
+    }
+}
+
+impl StateHasher {
+    pub fn new() -> StateHasher {
+        StateHasher::default()
+    }
+}
+
+impl Hasher for StateHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Writes values to a hasher as fixed-size little-endian bytes. The derived Hash impls write
+/// usize and enum discriminants at the platform's size and byte order, so state hashes use these
+/// instead to come out the same everywhere.
+pub trait StateHash {
+    fn hash_u32(&mut self, value: u32);
+    fn hash_u64(&mut self, value: u64);
+
+    /// Sizes and indices are hashed as u64, whatever the platform's usize is.
+    fn hash_usize(&mut self, value: usize) {
+        self.hash_u64(value as u64);
+    }
+
+    fn hash_bool(&mut self, value: bool) {
+        self.hash_u32(value as u32);
+    }
+
+    /// Floats are hashed by their bits, since any difference at all means a replay diverged.
+    fn hash_f32s(&mut self, values: &[f32]) {
+        for value in values {
+            self.hash_u32(value.to_bits());
+        }
+    }
+
+    /// Strings are hashed with their length first, so "ab", "c" and "a", "bc" hash differently.
+    fn hash_str(&mut self, value: &str);
+}
+
+impl StateHash for dyn Hasher + '_ {
+    fn hash_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn hash_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn hash_str(&mut self, value: &str) {
+        self.hash_usize(value.len());
+        self.write(value.as_bytes());
+    }
+}
+
+/// A recording of a game that can be played back to check the simulation is deterministic: the
+/// seed it started from, the settings it was played with, the inputs for every frame, and a hash
+/// of the world after each one.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub seed: u64,
+    /// The hash of the world after each frame.
+    pub hashes: Vec<u64>,
+    /// Each time one of the replay cvars was set to something new, as the frame it was set
+    /// before, the cvar, and its value.
+    pub cvars: Vec<(u64, String, String)>,
+    pub inputs: InputRecorder,
+}
+
+impl Replay {
+    pub fn new(seed: u64) -> Replay {
+        Replay {
+            seed,
+            hashes: Vec::new(),
+            cvars: Vec::new(),
+            inputs: InputRecorder::new(),
+        }
+    }
+
+    /// Notes any replay cvars that have changed since the last frame, before the next frame runs
+    /// with them.
+    pub fn record_cvars(&mut self, cvars: &Cvars) {
+        let frame = self.hashes.len() as u64;
+        let mut current: HashMap<&str, &str> = HashMap::new();
+        for (_, name, value) in self.cvars.iter() {
+            current.insert(name, value);
+        }
+        let mut changed = Vec::new();
+        for name in REPLAY_CVARS {
+            let Some(value) = cvars.get(name) else {
+                continue;
+            };
+            if current.get(name) != Some(&value) {
+                changed.push((frame, name.to_string(), value.to_string()));
+            }
+        }
+        self.cvars.extend(changed);
+    }
+
+    /// Sets the replay cvars the way they were before the given frame.
+    fn apply_cvars(&self, frame: u64, cvars: &mut Cvars) {
+        for (_, name, value) in self.cvars.iter().filter(|(f, _, _)| *f == frame) {
+            cvars.set(name, value);
+        }
+    }
+
+    /// Adds the next frame, with the inputs it ran with and the hash of the world after it.
+    pub fn record_frame(&mut self, inputs: &InputSnapshot, hash: u64) {
+        self.inputs.record(self.hashes.len() as u64, inputs);
+        self.hashes.push(hash);
+    }
+
+    /// Plays the inputs headlessly from a new game started from the seed, recording the hashes.
+    pub fn record(files: FileManager, seed: u64, inputs: &[InputSnapshot]) -> Result<Replay> {
+        let mut harness = TestHarness::with_seed(files, seed)?;
+        let mut replay = Replay::new(seed);
+        for frame_inputs in inputs {
+            replay.record_cvars(harness.engine().stage_manager().cvars());
+            if !harness.step(frame_inputs)? {
+                break;
+            }
+            replay.record_frame(frame_inputs, harness.state_hash());
+        }
+        Ok(replay)
+    }
+
+    /// Plays the replay back headlessly, failing at the first frame whose hash doesn't match.
+    pub fn verify(&self, files: FileManager) -> Result<()> {
+        let mut harness = TestHarness::with_seed(files, self.seed)?;
+        let mut inputs = self.inputs.clone();
+        for (frame, expected) in self.hashes.iter().enumerate() {
+            let cvars = harness.engine_mut().stage_manager_mut().cvars_mut();
+            self.apply_cvars(frame as u64, cvars);
+            if !harness.step(&inputs.playback(frame as u64))? {
+                bail!("replay ended early at frame {}", frame);
+            }
+            let actual = harness.state_hash();
+            if actual != *expected {
+                bail!(
+                    "replay diverged at frame {}: expected {:016x}, got {:016x}",
+                    frame,
+                    expected,
+                    actual
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The replay as text, with a "seed,frames,cvars" line, a line of hashes, a "frame,name,value"
+    /// line for each cvar, and then the inputs.
+    pub fn to_text(&self) -> String {
+        let hashes: Vec<String> = self.hashes.iter().map(|h| format!("{:016x}", h)).collect();
+        let cvars: String = self
+            .cvars
+            .iter()
+            .map(|(frame, name, value)| format!("{},{},{}\n", frame, name, value))
+            .collect();
+        format!(
+            "{},{},{}\n{}\n{}{}",
+            self.seed,
+            self.hashes.len(),
+            self.cvars.len(),
+            hashes.join(","),
+            cvars,
+            self.inputs.to_text()
+        )
+    }
+
+    pub fn from_text(text: &str) -> Result<Replay> {
+        let mut rest = text;
+        let mut next_line = || {
+            let (line, after) = rest.split_once('\n').unwrap_or((rest, ""));
+            rest = after;
+            line.trim()
+        };
+        let header = next_line();
+        let fields: Vec<&str> = header.split(',').collect();
+        // Replays from before the cvars were kept have none.
+        let (seed, frames, cvar_count) = match fields[..] {
+            [seed, frames] => (seed, frames, "0"),
+            [seed, frames, cvar_count] => (seed, frames, cvar_count),
+            _ => bail!("invalid replay header: {}", header),
+        };
+        let seed = seed.parse().context("invalid replay seed")?;
+        let frames: usize = frames.parse().context("invalid replay frames")?;
+        let cvar_count: usize = cvar_count.parse().context("invalid replay cvar count")?;
+        let hashes = next_line();
+        let hashes = hashes
+            .split(',')
+            .filter(|hash| !hash.is_empty())
+            .map(|hash| u64::from_str_radix(hash, 16).context("invalid replay hash"))
+            .collect::<Result<Vec<u64>>>()?;
+        if hashes.len() != frames {
+            bail!(
+                "invalid replay: {} hashes for {} frames",
+                hashes.len(),
+                frames
+            );
+        }
+        let mut cvars = Vec::with_capacity(cvar_count);
+        for _ in 0..cvar_count {
+            let line = next_line();
+            let mut fields = line.splitn(3, ',');
+            let (Some(frame), Some(name), Some(value)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                bail!("invalid replay cvar: {}", line);
+            };
+            let frame = frame.parse().context("invalid replay cvar frame")?;
+            cvars.push((frame, name.to_string(), value.to_string()));
+        }
+        Ok(Replay {
+            seed,
+            hashes,
+            cvars,
+            inputs: InputRecorder::from_text(rest)?,
+        })
+    }
+
+    /// Writes the replay to a path on disk, like an input recording.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_text())
+            .with_context(|| format!("unable to save replay at {:?}", path))
+    }
+
+    pub fn load(path: &Path, files: &FileManager) -> Result<Replay> {
+        let text = files
+            .read_to_string(path)
+            .with_context(|| format!("unable to load replay at {:?}", path))?;
+        Replay::from_text(&text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Point;
+    use crate::harness::test_files;
+
+    #[test]
+    fn replay_is_deterministic() {
+        let walk = InputSnapshot {
+            player_forward_down: true,
+            player_turn_right_down: true,
+            ..Default::default()
+        };
+        let fire = InputSnapshot {
+            mouse_button_left_down: true,
+            ..walk
+        };
+        let mut inputs = vec![walk; 40];
+        inputs.extend([fire, InputSnapshot::default(), fire]);
+        inputs.extend(vec![InputSnapshot::default(); 40]);
+
+        let replay = Replay::record(test_files(), 7, &inputs).unwrap();
+        assert_eq!(replay.hashes.len(), inputs.len());
+        let replay = Replay::from_text(&replay.to_text()).unwrap();
+        replay.verify(test_files()).unwrap();
+
+        // Another seed makes another world.
+        let other = Replay::record(test_files(), 8, &inputs).unwrap();
+        assert_ne!(other.hashes, replay.hashes);
+
+        // Changing anything the simulation decides is caught at the frame it happens.
+        let mut broken = replay.clone();
+        broken.hashes[30] ^= 1;
+        let error = broken.verify(test_files()).unwrap_err().to_string();
+        assert!(
+            error.starts_with("replay diverged at frame 30"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn replay_keeps_cvars() {
+        let look = InputSnapshot {
+            player_forward_down: true,
+            mouse_delta: Point::new(7, 0),
+            ..Default::default()
+        };
+        let mut harness = TestHarness::with_seed(test_files(), 3).unwrap();
+        harness.engine_mut().record_replay().unwrap();
+        let set = |harness: &mut TestHarness, name: &str, value: &str| {
+            let cvars = harness.engine_mut().stage_manager_mut().cvars_mut();
+            cvars.set(name, value);
+        };
+        set(&mut harness, MOUSE_SENSITIVITY_CVAR, "2");
+        harness.step_n(10, &look).unwrap();
+        set(&mut harness, MOUSE_SENSITIVITY_CVAR, "0.5");
+        // Settings that don't change the game aren't kept.
+        set(&mut harness, "volume", "0.5");
+        harness.step_n(10, &look).unwrap();
+        assert!(harness.engine_mut().record_replay().is_err());
+
+        let replay = harness.engine().replay().unwrap().clone();
+        let cvar = |frame: u64, value: &str| (frame, MOUSE_SENSITIVITY_CVAR.into(), value.into());
+        assert_eq!(replay.cvars, vec![cvar(0, "2"), cvar(10, "0.5")]);
+        let replay = Replay::from_text(&replay.to_text()).unwrap();
+        replay.verify(test_files()).unwrap();
+
+        // Without them, the player turns a different amount.
+        let mut defaults = replay.clone();
+        defaults.cvars.clear();
+        assert!(defaults.verify(test_files()).is_err());
+    }
+}
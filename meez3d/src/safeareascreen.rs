@@ -0,0 +1,187 @@
+use crate::cvars::Cvars;
+use crate::font::Font;
+use crate::geometry::{Point, Rect};
+use crate::inputmanager::InputSnapshot;
+use crate::keybindings::KeyBindings;
+use crate::rendercontext::{
+    PostprocessProfile, RenderContext, RenderLayer, MAX_SAFE_AREA, SAFE_AREA_CVAR,
+};
+use crate::scene::{Scene, SceneResult};
+use crate::soundmanager::SoundManager;
+use crate::utils::Color;
+
+const MARGIN: i32 = 16;
+/// How wide the line around the safe area is, in HUD units.
+const BORDER: i32 = 4;
+/// How much one press of left or right moves the safe area in, as a percentage.
+const STEP: f32 = 0.5;
+/// What the hint under the percentage says, with the keys filled in by KeyBindings::fill_in.
+const HINT: &str = "{menu_left} {menu_right} adjust";
+
+const OUTSIDE_COLOR: Color = Color {
+    r: 0x44,
+    g: 0x11,
+    b: 0x11,
+    a: 0xff,
+};
+const BACKGROUND_COLOR: Color = Color {
+    r: 0x11,
+    g: 0x11,
+    b: 0x22,
+    a: 0xff,
+};
+const BORDER_COLOR: Color = Color {
+    r: 0xff,
+    g: 0xdd,
+    b: 0x44,
+    a: 0xff,
+};
+
+/// A screen for fitting the safe area to a TV or projector that crops the picture, opened from
+/// the pause menu.
+///
+/// It outlines where the safe area is now. Left and right move it in and out, and ok or cancel
+/// go back to the pause menu.
+pub struct SafeAreaScreen {
+    /// The safe area inset, as a percentage.
+    inset: f32,
+    /// Whether the inset has changed since it was last written to the safe_area cvar.
+    changed: bool,
+    /// The hint, naming the keys that adjust the inset.
+    hint: String,
+}
+
+impl SafeAreaScreen {
+    pub fn new(keys: &KeyBindings) -> SafeAreaScreen {
+        SafeAreaScreen {
+            inset: 0.0,
+            changed: false,
+            hint: keys.fill_in(HINT),
+        }
+    }
+
+    fn adjust(&mut self, delta: f32) {
+        let inset = (self.inset + delta).clamp(0.0, MAX_SAFE_AREA);
+        if inset != self.inset {
+            self.inset = inset;
+            self.changed = true;
+        }
+    }
+}
+
+impl Scene for SafeAreaScreen {
+    fn update(
+        &mut self,
+        _context: &RenderContext,
+        inputs: &InputSnapshot,
+        _sounds: &mut SoundManager,
+    ) -> SceneResult {
+        if inputs.cancel_clicked || inputs.ok_clicked {
+            return SceneResult::Pop;
+        }
+        if inputs.menu_left_clicked {
+            self.adjust(-STEP);
+        }
+        if inputs.menu_right_clicked {
+            self.adjust(STEP);
+        }
+        SceneResult::Continue
+    }
+
+    fn name(&self) -> &str {
+        "safe area"
+    }
+
+    fn postprocess(&self) -> PostprocessProfile {
+        PostprocessProfile::CLEAN
+    }
+
+    fn apply_cvars(&mut self, cvars: &Cvars) {
+        if let Some(inset) = cvars.get_parsed::<f32>(SAFE_AREA_CVAR) {
+            if inset.is_finite() {
+                self.inset = inset.clamp(0.0, MAX_SAFE_AREA);
+            }
+        }
+    }
+
+    fn flush_cvars(&mut self, cvars: &mut Cvars) {
+        if self.changed {
+            cvars.set(SAFE_AREA_CVAR, &self.inset.to_string());
+            self.changed = false;
+        }
+    }
+
+    fn draw(&self, context: &mut RenderContext, font: &Font, _previous: Option<&dyn Scene>) {
+        context.fill_rect(context.ui_area(), RenderLayer::Hud, OUTSIDE_COLOR);
+        let area = context.safe_area();
+        context.fill_rect(area, RenderLayer::Hud, BACKGROUND_COLOR);
+        let edges = [
+            Rect { h: BORDER, ..area },
+            Rect { w: BORDER, ..area },
+            Rect {
+                y: area.bottom() - BORDER,
+                h: BORDER,
+                ..area
+            },
+            Rect {
+                x: area.right() - BORDER,
+                w: BORDER,
+                ..area
+            },
+        ];
+        for edge in edges {
+            context.fill_rect(edge, RenderLayer::Hud, BORDER_COLOR);
+        }
+
+        // The text is centered, so it shows the middle of the screen hasn't moved.
+        let centered = |text: &str| area.x + (area.w - font.text_width(text)) / 2;
+        let label = format!("safe area {}%", self.inset);
+        let y = area.y + (area.h - font.char_height * 2 - MARGIN) / 2;
+        let position = Point::new(centered(&label), y);
+        font.draw_string(context, RenderLayer::Hud, position, &label);
+        let position = Point::new(centered(&self.hint), y + font.char_height + MARGIN);
+        font.draw_string(context, RenderLayer::Hud, position, &self.hint);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adjust() {
+        let mut screen = SafeAreaScreen::new(&KeyBindings::default());
+        assert_eq!(screen.hint, "left right adjust");
+        let mut cvars = Cvars::new();
+        cvars.set(SAFE_AREA_CVAR, "2");
+        let context = RenderContext::new(640, 400, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+        let right = InputSnapshot {
+            menu_right_clicked: true,
+            ..Default::default()
+        };
+        for _ in 0..30 {
+            screen.apply_cvars(&cvars);
+            screen.update(&context, &right, &mut sounds);
+            screen.flush_cvars(&mut cvars);
+        }
+        assert_eq!(cvars.get_parsed::<f32>(SAFE_AREA_CVAR), Some(MAX_SAFE_AREA));
+
+        let left = InputSnapshot {
+            menu_left_clicked: true,
+            ..Default::default()
+        };
+        screen.update(&context, &left, &mut sounds);
+        screen.flush_cvars(&mut cvars);
+        assert_eq!(cvars.get(SAFE_AREA_CVAR), Some("9.5"));
+
+        let cancel = InputSnapshot {
+            cancel_clicked: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            screen.update(&context, &cancel, &mut sounds),
+            SceneResult::Pop
+        ));
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// The newest version of the save state format this build knows how to read.
+///
+/// Bump this whenever a field changes meaning. Adding a new field with a default doesn't require a
+/// bump, since older builds keep unknown fields around and newer builds fill in the default.
+pub const SAVE_STATE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PlayerState {
+    pub x: f32,
+    pub y: f32,
+    pub angle: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CheckpointState {
+    /// The floor the checkpoint is on, for levels with more than one.
+    #[serde(default)]
+    pub floor: usize,
+    pub row: usize,
+    pub column: usize,
+    pub player: PlayerState,
+}
+
+/// The dynamic state of a level, used for saves, checkpoints, and replication.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LevelState {
+    pub version: u32,
+    pub player: PlayerState,
+    /// The floor the player is on, for levels with more than one.
+    #[serde(default)]
+    pub floor: usize,
+    #[serde(default)]
+    pub checkpoint: Option<CheckpointState>,
+
+    /// Fields written by a newer version, preserved so they survive a round trip.
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, serde_json::Value>,
+}
+
+impl LevelState {
+    pub fn new(player: PlayerState, checkpoint: Option<CheckpointState>) -> LevelState {
+        LevelState {
+            version: SAVE_STATE_VERSION,
+            player,
+            floor: 0,
+            checkpoint,
+            unknown: BTreeMap::new(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|e| anyhow!("unable to serialize level state: {}", e))
+    }
+
+    pub fn from_json(text: &str) -> Result<LevelState> {
+        let state: LevelState = serde_json::from_str(text)
+            .map_err(|e| anyhow!("unable to deserialize level state: {}", e))?;
+        if state.version > SAVE_STATE_VERSION {
+            bail!(
+                "level state version {} is newer than supported version {}",
+                state.version,
+                SAVE_STATE_VERSION
+            );
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player() -> PlayerState {
+        PlayerState {
+            x: 15.5,
+            y: 3.25,
+            angle: 1.5,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let checkpoint = CheckpointState {
+            floor: 1,
+            row: 3,
+            column: 15,
+            player: player(),
+        };
+        let mut state = LevelState::new(player(), Some(checkpoint));
+        state.floor = 1;
+        let text = state.to_json().unwrap();
+        let decoded = LevelState::from_json(&text).unwrap();
+        assert_eq!(state, decoded);
+    }
+
+    #[test]
+    fn unknown_fields_are_preserved() {
+        let text = r#"{"version":1,"player":{"x":1.0,"y":2.0,"angle":0.0},"doors":[1,2]}"#;
+        let state = LevelState::from_json(text).unwrap();
+        assert_eq!(state.checkpoint, None);
+        assert_eq!(state.floor, 0);
+        assert!(state.unknown.contains_key("doors"));
+
+        let decoded = LevelState::from_json(&state.to_json().unwrap()).unwrap();
+        assert_eq!(state, decoded);
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let text = r#"{"version":99,"player":{"x":1.0,"y":2.0,"angle":0.0}}"#;
+        assert!(LevelState::from_json(text).is_err());
+    }
+}
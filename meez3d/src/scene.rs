@@ -1,9 +1,38 @@
+use std::hash::Hasher;
 use std::path::PathBuf;
 
+use serde::Serialize;
+
+use crate::cvars::Cvars;
+use crate::ending::RunSummary;
+use crate::filemanager::FileManager;
 use crate::font::Font;
+use crate::gameclock::GameClock;
 use crate::inputmanager::InputSnapshot;
-use crate::rendercontext::RenderContext;
+use crate::inventory::{Inventory, ItemAction, ItemTarget};
+use crate::leaderboard::LeaderboardBackend;
+use crate::messagelog::MessageLog;
+use crate::narration::Narrator;
+use crate::rendercontext::{PostprocessProfile, RenderContext};
+use crate::savestate::LevelState;
 use crate::soundmanager::SoundManager;
+use crate::tutorial::Tutorial;
+
+/// Something in a scene besides the player, like an enemy or a pickup, as the debug server
+/// lists it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EntityInfo {
+    /// What sort of thing it is: "enemy", "pickup", or "billboard".
+    pub category: &'static str,
+    /// Which one of its sort it is, like an enemy's type or what a pickup gives.
+    pub name: Option<String>,
+    pub floor: usize,
+    pub x: f32,
+    pub y: f32,
+    /// What it's doing, like "chase" for an enemy.
+    pub state: Option<String>,
+    pub health: Option<f32>,
+}
 
 pub enum SceneResult {
     Continue,
@@ -12,8 +41,23 @@ pub enum SceneResult {
     PushMenu,
     PushLevel,
     ReloadLevel,
-    PushKillScreen { text: String },
+    PushKillScreen {
+        text: String,
+    },
     PushPause,
+    PushMessageLog,
+    PushLeaderboard,
+    /// Opens the screen for fitting the HUD to a TV that crops the picture.
+    PushSafeArea,
+    /// Opens the screen for typing the name runs are put on the leaderboard under.
+    PushNameEntry,
+    /// Opens the inventory of the topmost scene that has one.
+    PushInventory,
+    RespawnAtCheckpoint,
+    /// The player reached the final exit, so show the epilogue for the ending the run earned.
+    PushEpilogue {
+        run: RunSummary,
+    },
 }
 
 pub trait Scene {
@@ -25,4 +69,104 @@ pub trait Scene {
     ) -> SceneResult;
 
     fn draw(&self, context: &mut RenderContext, font: &Font, previous: Option<&dyn Scene>);
+
+    /// Which postprocessing effects run while this is the current scene.
+    fn postprocess(&self) -> PostprocessProfile {
+        PostprocessProfile::RETRO
+    }
+
+    /// A short name for the kind of scene, for logging and debugging.
+    fn name(&self) -> &str {
+        "scene"
+    }
+
+    /// The time in the scene's world, if it has one.
+    fn clock(&self) -> Option<&GameClock> {
+        None
+    }
+
+    /// Whether the host should capture the mouse for mouse look while this is the current scene.
+    fn captures_mouse(&self) -> bool {
+        false
+    }
+
+    /// Whether the keyboard should type text while this is the current scene, for a text box.
+    fn wants_text_input(&self) -> bool {
+        false
+    }
+
+    /// Whether every sound should be muffled while this is the current scene, like underwater.
+    fn muffles_sound(&self) -> bool {
+        false
+    }
+
+    /// Feeds everything the simulation decides about the scene into the hasher, to check that
+    /// replays are deterministic. Anything only drawn, like weather, can be left out.
+    fn hash_state(&self, _hasher: &mut dyn Hasher) {}
+
+    /// Picks up any settings the scene cares about, before each update.
+    fn apply_cvars(&mut self, _cvars: &Cvars) {}
+
+    /// Sets any cvars for settings the player changed in the scene since the last call.
+    fn flush_cvars(&mut self, _cvars: &mut Cvars) {}
+
+    /// Submits any runs finished since the last call to the leaderboard.
+    fn submit_scores(&mut self, _leaderboard: &mut dyn LeaderboardBackend) {}
+
+    /// Moves any messages recorded since the last call into the log, stamped with the frame.
+    fn flush_messages(&mut self, _log: &mut MessageLog, _frame: u64) {}
+
+    /// Narrates anything that's appeared or been focused since the last call.
+    fn flush_narration(&mut self, _narrator: &mut dyn Narrator) {}
+
+    /// Passes any tutorial prompts triggered since the last call to the tutorial, which decides
+    /// whether they still need to be shown.
+    fn flush_prompts(&mut self, _tutorial: &mut Tutorial) {}
+
+    /// Picks up changes to any game data files the scene uses, so they can be tweaked while the
+    /// game runs.
+    fn reload_data(&mut self, _files: &FileManager) {}
+
+    /// Writes anything the scene wants to keep for the player, like a new best run, to user data.
+    fn flush_user_data(&mut self, _files: &FileManager) {}
+
+    /// The items the player is carrying, if the scene has an inventory.
+    fn inventory(&self) -> Option<&Inventory> {
+        None
+    }
+
+    /// The thing the player is facing that they could use items on, if there is one.
+    fn item_target(&self) -> Option<&ItemTarget> {
+        None
+    }
+
+    /// Returns anything the player chose to do with their items since the last call, as
+    /// (action, item name).
+    fn take_item_actions(&mut self) -> Vec<(ItemAction, String)> {
+        Vec::new()
+    }
+
+    /// Does something the player chose to do with an item in the scene's inventory, the next
+    /// time the scene updates.
+    fn queue_item_action(&mut self, _action: ItemAction, _item: &str) {}
+
+    /// Restores the scene to its last checkpoint, returning false if there isn't one.
+    fn respawn_at_checkpoint(&mut self) -> bool {
+        false
+    }
+
+    /// Returns the dynamic state of the scene, if it has any worth saving.
+    fn save_state(&self) -> Option<LevelState> {
+        None
+    }
+
+    /// Returns everything in the scene besides the player, if it has a world to be in.
+    fn entities(&self) -> Option<Vec<EntityInfo>> {
+        None
+    }
+
+    /// Restores previously saved state, returning false if the scene doesn't support it.
+    fn restore_state(&mut self, _state: &LevelState) -> bool {
+        false
+    }
 }
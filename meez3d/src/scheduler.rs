@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use log::warn;
+
+struct Task<C> {
+    name: String,
+    run: Box<dyn FnOnce(&mut C)>,
+}
+
+/// Runs non-critical work, like pathfinding or spawning, a little at a time.
+///
+/// Each frame, tasks run in the order they were scheduled until the frame's budget is spent,
+/// and the rest wait for the next frame. At least one task runs every frame, so the queue always
+/// drains eventually, even if every task is over budget.
+pub struct Scheduler<C> {
+    queue: VecDeque<Task<C>>,
+    /// How much time tasks can take each frame.
+    pub budget: Duration,
+    /// How many tasks can run each frame, however quick they are, e.g. to spread out spawns.
+    pub max_tasks_per_frame: Option<usize>,
+}
+
+impl<C> Scheduler<C> {
+    pub fn new(budget: Duration) -> Scheduler<C> {
+        Scheduler {
+            queue: VecDeque::new(),
+            budget,
+            max_tasks_per_frame: None,
+        }
+    }
+
+    /// Adds a task to the end of the queue. The name is only used for logging.
+    pub fn schedule<F>(&mut self, name: &str, task: F)
+    where
+        F: FnOnce(&mut C) + 'static,
+    {
+        self.queue.push_back(Task {
+            name: name.to_string(),
+            run: Box::new(task),
+        });
+    }
+
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Runs tasks until this frame's budget is spent, returning how many ran.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn run(&mut self, context: &mut C) -> usize {
+        let start = std::time::Instant::now();
+        self.run_with_clock(context, || start.elapsed())
+    }
+
+    /// There's no clock in the browser, so only one task runs each frame.
+    #[cfg(target_arch = "wasm32")]
+    pub fn run(&mut self, context: &mut C) -> usize {
+        self.run_with_clock(context, || Duration::MAX)
+    }
+
+    /// Runs tasks until the budget is spent, where elapsed returns the time taken so far.
+    pub fn run_with_clock<F>(&mut self, context: &mut C, mut elapsed: F) -> usize
+    where
+        F: FnMut() -> Duration,
+    {
+        profile_scope!("scheduler", pending = self.queue.len());
+        let max_tasks = self.max_tasks_per_frame.unwrap_or(usize::MAX).max(1);
+        let mut count = 0;
+        let mut previous = Duration::ZERO;
+        while let Some(task) = self.queue.pop_front() {
+            profile_scope!("task", name = %task.name);
+            (task.run)(context);
+            count += 1;
+
+            let now = elapsed();
+            if now.saturating_sub(previous) > self.budget {
+                warn!(
+                    "task {} took {:?}, over the budget of {:?}",
+                    task.name,
+                    now.saturating_sub(previous),
+                    self.budget
+                );
+            }
+            previous = now;
+            if now >= self.budget || count >= max_tasks {
+                break;
+            }
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget() {
+        let mut scheduler = Scheduler::new(Duration::from_millis(2));
+        for i in 0..5 {
+            scheduler.schedule("push", move |spawned: &mut Vec<i32>| spawned.push(i));
+        }
+
+        // Every task takes a millisecond, so two fit in the budget.
+        let mut spawned = Vec::new();
+        let mut fake_time = Duration::ZERO;
+        let mut tick = || {
+            fake_time += Duration::from_millis(1);
+            fake_time
+        };
+        assert_eq!(scheduler.run_with_clock(&mut spawned, &mut tick), 2);
+        assert_eq!(spawned, vec![0, 1]);
+        assert_eq!(scheduler.pending(), 3);
+
+        // A slow task still runs on its own, so the queue can't get stuck.
+        let ran = scheduler.run_with_clock(&mut spawned, || Duration::from_millis(10));
+        assert_eq!(ran, 1);
+
+        scheduler.max_tasks_per_frame = Some(1);
+        assert_eq!(scheduler.run_with_clock(&mut spawned, || Duration::ZERO), 1);
+        assert_eq!(spawned, vec![0, 1, 2, 3]);
+
+        scheduler.max_tasks_per_frame = None;
+        assert_eq!(scheduler.run_with_clock(&mut spawned, || Duration::ZERO), 1);
+        assert_eq!(scheduler.run(&mut spawned), 0);
+        assert_eq!(spawned, vec![0, 1, 2, 3, 4]);
+    }
+}
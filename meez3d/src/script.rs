@@ -0,0 +1,286 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+
+use crate::enemy::Enemy;
+use crate::filemanager::FileManager;
+use crate::savestate::PlayerState;
+use crate::soundmanager::Sound;
+
+/// What a script can see of an enemy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptEnemy {
+    pub kind: String,
+    pub floor: usize,
+    pub x: f32,
+    pub y: f32,
+    /// What it's doing, like "patrol" or "chase".
+    pub state: String,
+    pub health: f32,
+}
+
+impl From<&Enemy> for ScriptEnemy {
+    fn from(enemy: &Enemy) -> Self {
+        ScriptEnemy {
+            kind: enemy.kind.clone(),
+            floor: enemy.billboard.floor,
+            x: enemy.billboard.x,
+            y: enemy.billboard.y,
+            state: enemy.state.to_string(),
+            health: enemy.health,
+        }
+    }
+}
+
+impl ScriptEnemy {
+    fn to_map(&self) -> Map {
+        let mut map = Map::new();
+        map.insert("kind".into(), self.kind.clone().into());
+        map.insert("floor".into(), (self.floor as i64).into());
+        map.insert("x".into(), (self.x as f64).into());
+        map.insert("y".into(), (self.y as f64).into());
+        map.insert("state".into(), self.state.clone().into());
+        map.insert("health".into(), (self.health as f64).into());
+        map
+    }
+}
+
+/// A door a script opened or closed, by the cell it's in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DoorChange {
+    pub row: usize,
+    pub column: usize,
+    pub open: bool,
+}
+
+/// State shared between the game and the functions exposed to scripts.
+struct ScriptState {
+    player: PlayerState,
+    enemies: Vec<ScriptEnemy>,
+    messages: Vec<String>,
+    sounds: Vec<Sound>,
+    doors: Vec<DoorChange>,
+}
+
+impl ScriptState {
+    fn change_door(&mut self, x: i64, y: i64, open: bool) {
+        match (usize::try_from(y), usize::try_from(x)) {
+            (Ok(row), Ok(column)) => self.doors.push(DoorChange { row, column, open }),
+            _ => error!("script error: no door at ({}, {})", x, y),
+        }
+    }
+}
+
+/// Everything a script asked the game to do while handling an event.
+#[derive(Debug, Default)]
+pub struct ScriptOutput {
+    pub messages: Vec<String>,
+    pub sounds: Vec<Sound>,
+    pub doors: Vec<DoorChange>,
+}
+
+/// A rhai script with event handlers, like `fn on_checkpoint() { ... }`, or
+/// `fn on_trigger_ambush() { ... }` for when the player walks into an area with a "trigger"
+/// property of "ambush".
+///
+/// Scripts can call these functions:
+///   player_x(), player_y(), player_angle(): query the player
+///   enemies(): every enemy, as maps with kind, floor, x, y, state, and health
+///   enemy_count(): how many enemies are left
+///   open_door(x, y), close_door(x, y): open or close the door tile at a cell on the player's
+///     floor
+///   show_message(text): show a message on the HUD
+///   play_sound(name): play a sound by name, e.g. "click"
+///
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    state: Rc<RefCell<ScriptState>>,
+}
+
+impl Script {
+    pub fn from_file(path: &Path, files: &FileManager) -> Result<Script> {
+        info!("loading script from {:?}", path);
+        let text = files
+            .read_to_string(path)
+            .map_err(|e| anyhow!("unable to load script at {:?}: {}", path, e))?;
+        Script::new(&text).map_err(|e| anyhow!("unable to compile script {:?}: {}", path, e))
+    }
+
+    pub fn new(text: &str) -> Result<Script> {
+        let state = Rc::new(RefCell::new(ScriptState {
+            player: PlayerState {
+                x: 0.0,
+                y: 0.0,
+                angle: 0.0,
+            },
+            enemies: Vec::new(),
+            messages: Vec::new(),
+            sounds: Vec::new(),
+            doors: Vec::new(),
+        }));
+
+        let mut engine = Engine::new();
+
+        let s = state.clone();
+        engine.register_fn("player_x", move || s.borrow().player.x as f64);
+        let s = state.clone();
+        engine.register_fn("player_y", move || s.borrow().player.y as f64);
+        let s = state.clone();
+        engine.register_fn("player_angle", move || s.borrow().player.angle as f64);
+        let s = state.clone();
+        engine.register_fn("enemies", move || -> Array {
+            let enemies = &s.borrow().enemies;
+            enemies
+                .iter()
+                .map(|e| Dynamic::from_map(e.to_map()))
+                .collect()
+        });
+        let s = state.clone();
+        engine.register_fn("enemy_count", move || s.borrow().enemies.len() as i64);
+        let s = state.clone();
+        engine.register_fn("open_door", move |x: i64, y: i64| {
+            s.borrow_mut().change_door(x, y, true);
+        });
+        let s = state.clone();
+        engine.register_fn("close_door", move |x: i64, y: i64| {
+            s.borrow_mut().change_door(x, y, false);
+        });
+        let s = state.clone();
+        engine.register_fn("show_message", move |text: &str| {
+            s.borrow_mut().messages.push(text.to_string());
+        });
+        let s = state.clone();
+        engine.register_fn("play_sound", move |name: &str| match name.parse() {
+            Ok(sound) => s.borrow_mut().sounds.push(sound),
+            Err(e) => error!("script error: {}", e),
+        });
+
+        let ast = engine
+            .compile(text)
+            .map_err(|e| anyhow!("syntax error: {}", e))?;
+
+        Ok(Script { engine, ast, state })
+    }
+
+    /// Calls the `on_<event>` function, if the script defines one.
+    pub fn call_event(
+        &mut self,
+        event: &str,
+        player: PlayerState,
+        enemies: Vec<ScriptEnemy>,
+    ) -> Result<ScriptOutput> {
+        let name = format!("on_{event}");
+        if !self.ast.iter_functions().any(|f| f.name == name) {
+            return Ok(ScriptOutput::default());
+        }
+
+        {
+            let mut state = self.state.borrow_mut();
+            state.player = player;
+            state.enemies = enemies;
+        }
+        let mut scope = Scope::new();
+        self.engine
+            .call_fn::<()>(&mut scope, &self.ast, &name, ())
+            .map_err(|e| anyhow!("error in script function {}: {}", name, e))?;
+
+        let mut state = self.state.borrow_mut();
+        Ok(ScriptOutput {
+            messages: std::mem::take(&mut state.messages),
+            sounds: std::mem::take(&mut state.sounds),
+            doors: std::mem::take(&mut state.doors),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player() -> PlayerState {
+        PlayerState {
+            x: 1.5,
+            y: 2.5,
+            angle: 0.0,
+        }
+    }
+
+    #[test]
+    fn event_handler() {
+        let mut script = Script::new(
+            r#"
+            fn on_checkpoint() {
+                play_sound("click");
+                show_message(`at ${player_x()}`);
+            }
+            "#,
+        )
+        .unwrap();
+        let output = script
+            .call_event("checkpoint", player(), Vec::new())
+            .unwrap();
+        assert_eq!(output.sounds, vec![Sound::Click]);
+        assert_eq!(output.messages, vec!["at 1.5".to_string()]);
+    }
+
+    #[test]
+    fn missing_handler() {
+        let mut script = Script::new("fn on_start() { show_message(\"hi\"); }").unwrap();
+        let output = script
+            .call_event("checkpoint", player(), Vec::new())
+            .unwrap();
+        assert!(output.messages.is_empty());
+        assert!(output.sounds.is_empty());
+    }
+
+    #[test]
+    fn enemies_and_doors() {
+        let mut script = Script::new(
+            r#"
+            fn on_trigger_ambush() {
+                let chasing = enemies().filter(|enemy| enemy.state == "chase");
+                show_message(`${chasing[0].kind} at ${chasing[0].x}`);
+                if enemy_count() == 2 {
+                    close_door(3, 4);
+                    open_door(5, 6);
+                }
+                open_door(-1, 0);
+            }
+            "#,
+        )
+        .unwrap();
+        let enemy = |kind: &str, state: &str| ScriptEnemy {
+            kind: kind.to_string(),
+            floor: 0,
+            x: 2.5,
+            y: 1.5,
+            state: state.to_string(),
+            health: 10.0,
+        };
+        let enemies = vec![enemy("grunt", "patrol"), enemy("boss", "chase")];
+        let output = script
+            .call_event("trigger_ambush", player(), enemies)
+            .unwrap();
+        assert_eq!(output.messages, vec!["boss at 2.5".to_string()]);
+        assert_eq!(
+            output.doors,
+            vec![
+                DoorChange {
+                    row: 4,
+                    column: 3,
+                    open: false
+                },
+                DoorChange {
+                    row: 6,
+                    column: 5,
+                    open: true
+                },
+            ]
+        );
+    }
+}
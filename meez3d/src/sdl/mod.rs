@@ -1 +1,2 @@
+pub mod sdlrenderer;
 pub mod sdlsoundmanager;
@@ -0,0 +1,754 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use image::RgbaImage;
+use log::{error, info};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+
+use crate::constants::{RENDER_HEIGHT, RENDER_WIDTH};
+use crate::filemanager::FileManager;
+use crate::geometry::{Point, Rect};
+use crate::rendercontext::{
+    convex_spans, line_quad, triangle_color, RenderContext, SpriteBatch, SpriteBatchEntry,
+    SpriteTransform,
+};
+use crate::renderer::Renderer;
+use crate::sprite::Sprite;
+use crate::transition::TransitionKind;
+use crate::utils::Color;
+
+/// Sprites from the texture atlas have this id, and dynamic textures count up from it.
+const TEXTURE_ATLAS_ID: usize = 0;
+
+/// The darkness mask is computed on the CPU, so it's done at a fraction of the resolution.
+const DARKNESS_SCALE: u32 = 4;
+const DARKNESS_WIDTH: u32 = RENDER_WIDTH / DARKNESS_SCALE;
+const DARKNESS_HEIGHT: u32 = RENDER_HEIGHT / DARKNESS_SCALE;
+
+/// How dark it is at the edge of a spotlight, matching the wgpu shader.
+const DARKNESS_ALPHA: f32 = 0.85;
+
+/// The canvas can't blend colors across a shape, so gradients are drawn in flat pieces this wide.
+const GRADIENT_STEP: i32 = 8;
+
+impl From<Color> for sdl2::pixels::Color {
+    fn from(value: Color) -> Self {
+        sdl2::pixels::Color::RGBA(value.r, value.g, value.b, value.a)
+    }
+}
+
+impl From<Rect<i32>> for sdl2::rect::Rect {
+    fn from(value: Rect<i32>) -> Self {
+        sdl2::rect::Rect::new(
+            value.x,
+            value.y,
+            value.w.max(0) as u32,
+            value.h.max(0) as u32,
+        )
+    }
+}
+
+/// A renderer that only uses the SDL canvas, for machines without Vulkan, Metal, or DX12.
+///
+/// It draws the same sprite batches as the wgpu renderer, and emulates the spotlights in dark
+/// levels and the ambient light, but skips the rest of the postprocessing.
+pub struct SdlRenderer<'a> {
+    canvas: Canvas<Window>,
+    texture_creator: &'a TextureCreator<WindowContext>,
+
+    texture_atlas: Option<Texture<'a>>,
+    dynamic_textures: Vec<Texture<'a>>,
+
+    player_framebuffer: Texture<'a>,
+    hud_framebuffer: Texture<'a>,
+    /// Copied over everything else, after the darkness and any transition.
+    overlay_framebuffer: Texture<'a>,
+    darkness: Texture<'a>,
+    darkness_pixels: Vec<u8>,
+    /// The outgoing scene's finished frame is drawn here during a transition.
+    outgoing_framebuffer: Texture<'a>,
+
+    /// Whether each frame is read back from the canvas before it's shown.
+    capturing: bool,
+    captured_frame: Option<RgbaImage>,
+}
+
+impl<'a> SdlRenderer<'a> {
+    pub fn new(
+        canvas: Canvas<Window>,
+        texture_creator: &'a TextureCreator<WindowContext>,
+    ) -> Result<Self> {
+        let info = canvas.info();
+        info!("using SDL renderer: {}", info.name);
+
+        let player_framebuffer = create_framebuffer(texture_creator, RENDER_WIDTH, RENDER_HEIGHT)?;
+        let hud_framebuffer = create_framebuffer(texture_creator, RENDER_WIDTH, RENDER_HEIGHT)?;
+        let overlay_framebuffer = create_framebuffer(texture_creator, RENDER_WIDTH, RENDER_HEIGHT)?;
+        let outgoing_framebuffer =
+            create_framebuffer(texture_creator, RENDER_WIDTH, RENDER_HEIGHT)?;
+
+        let mut darkness = texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGBA32, DARKNESS_WIDTH, DARKNESS_HEIGHT)
+            .map_err(|e| anyhow!("unable to create darkness texture: {}", e))?;
+        darkness.set_blend_mode(BlendMode::Blend);
+        let darkness_pixels = vec![0; (DARKNESS_WIDTH * DARKNESS_HEIGHT * 4) as usize];
+
+        Ok(SdlRenderer {
+            canvas,
+            texture_creator,
+            texture_atlas: None,
+            dynamic_textures: Vec::new(),
+            player_framebuffer,
+            hud_framebuffer,
+            overlay_framebuffer,
+            darkness,
+            darkness_pixels,
+            outgoing_framebuffer,
+            capturing: false,
+            captured_frame: None,
+        })
+    }
+
+    pub fn window(&self) -> &Window {
+        self.canvas.window()
+    }
+
+    fn texture_atlas_area(&self) -> Rect<i32> {
+        let (w, h) = match &self.texture_atlas {
+            Some(texture) => {
+                let query = texture.query();
+                (query.width as i32, query.height as i32)
+            }
+            None => (0, 0),
+        };
+        Rect { x: 0, y: 0, w, h }
+    }
+
+    /// Replaces the texture atlas with width * height RGBA pixels.
+    fn set_texture_atlas(&mut self, width: u32, height: u32, pixels: &[u8]) -> Result<Sprite> {
+        let mut texture = self
+            .texture_creator
+            .create_texture_static(PixelFormatEnum::RGBA32, width, height)
+            .map_err(|e| anyhow!("unable to create {}x{} texture atlas: {}", width, height, e))?;
+        texture
+            .update(None, pixels, (width * 4) as usize)
+            .map_err(|e| anyhow!("unable to upload texture atlas: {}", e))?;
+        texture.set_blend_mode(BlendMode::Blend);
+        self.texture_atlas = Some(texture);
+        Ok(Sprite {
+            id: TEXTURE_ATLAS_ID,
+            area: self.texture_atlas_area(),
+        })
+    }
+
+    /// Fills the darkness mask with the same falloff the wgpu shader uses around each light.
+    fn update_darkness(&mut self, context: &RenderContext) -> Result<()> {
+        for y in 0..DARKNESS_HEIGHT {
+            for x in 0..DARKNESS_WIDTH {
+                let position = Point::new(
+                    ((x * DARKNESS_SCALE) + DARKNESS_SCALE / 2) as f32,
+                    ((y * DARKNESS_SCALE) + DARKNESS_SCALE / 2) as f32,
+                );
+                let mut alpha: f32 = 1.0;
+                for light in context.lights.iter() {
+                    let dx = position.x - light.position.x as f32;
+                    let dy = position.y - light.position.y as f32;
+                    let d = (dx * dx + dy * dy).sqrt() / light.radius.max(1) as f32;
+                    alpha = alpha.min(smoothstep(d) * DARKNESS_ALPHA);
+                }
+                let i = ((y * DARKNESS_WIDTH + x) * 4) as usize;
+                self.darkness_pixels[i..i + 4].copy_from_slice(&[0, 0, 0, (alpha * 255.0) as u8]);
+            }
+        }
+        self.darkness
+            .update(None, &self.darkness_pixels, (DARKNESS_WIDTH * 4) as usize)
+            .map_err(|e| anyhow!("unable to update darkness texture: {}", e))
+    }
+
+    /// Draws the context's layers into their framebuffers, and gets the darkness mask ready,
+    /// returning whether it should be drawn.
+    fn draw_layers(&mut self, context: &RenderContext) -> Result<bool> {
+        let SdlRenderer {
+            canvas,
+            texture_atlas,
+            dynamic_textures,
+            player_framebuffer,
+            hud_framebuffer,
+            overlay_framebuffer,
+            ..
+        } = self;
+
+        for (framebuffer, batch) in [
+            (player_framebuffer, &context.player_batch),
+            (hud_framebuffer, &context.hud_batch),
+            (overlay_framebuffer, &context.overlay_batch),
+        ] {
+            let mut result = Ok(());
+            canvas
+                .with_texture_canvas(framebuffer, |canvas| {
+                    result = draw_batch(canvas, texture_atlas, dynamic_textures, batch);
+                })
+                .map_err(|e| anyhow!("unable to render to framebuffer: {}", e))?;
+            result?;
+        }
+
+        let is_dark = context.is_dark && !context.lights.is_empty();
+        if is_dark {
+            self.update_darkness(context)?;
+        }
+
+        let ambient = context.ambient_light;
+        self.player_framebuffer
+            .set_color_mod(ambient.r, ambient.g, ambient.b);
+        Ok(is_dark)
+    }
+
+    /// Shows the finished frame, reading it back first while capturing.
+    fn present(&mut self) -> Result<()> {
+        if self.capturing {
+            let (width, height) = self
+                .canvas
+                .output_size()
+                .map_err(|e| anyhow!("unable to get canvas size: {}", e))?;
+            let pixels = self
+                .canvas
+                .read_pixels(None, PixelFormatEnum::RGBA32)
+                .map_err(|e| anyhow!("unable to read frame back: {}", e))?;
+            self.captured_frame = RgbaImage::from_raw(width, height, pixels);
+        }
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+fn create_framebuffer<'a>(
+    texture_creator: &'a TextureCreator<WindowContext>,
+    width: u32,
+    height: u32,
+) -> Result<Texture<'a>> {
+    let mut texture = texture_creator
+        .create_texture_target(PixelFormatEnum::RGBA32, width, height)
+        .map_err(|e| anyhow!("unable to create framebuffer: {}", e))?;
+    texture.set_blend_mode(BlendMode::Blend);
+    Ok(texture)
+}
+
+/// Puts the layers from draw_layers together on the canvas, with the darkness mask if it's dark.
+fn draw_scene(
+    canvas: &mut Canvas<Window>,
+    player_framebuffer: &Texture,
+    darkness: Option<&Texture>,
+    hud_framebuffer: &Texture,
+) -> Result<()> {
+    canvas
+        .copy(player_framebuffer, None, None)
+        .map_err(|e| anyhow!("unable to draw player layer: {}", e))?;
+    if let Some(darkness) = darkness {
+        canvas
+            .copy(darkness, None, None)
+            .map_err(|e| anyhow!("unable to draw darkness: {}", e))?;
+    }
+    canvas
+        .copy(hud_framebuffer, None, None)
+        .map_err(|e| anyhow!("unable to draw hud layer: {}", e))
+}
+
+/// Copies the overlay layer on top of whatever's on the canvas.
+fn draw_overlay(canvas: &mut Canvas<Window>, overlay_framebuffer: &Texture) -> Result<()> {
+    canvas
+        .copy(overlay_framebuffer, None, None)
+        .map_err(|e| anyhow!("unable to draw overlay layer: {}", e))
+}
+
+fn smoothstep(x: f32) -> f32 {
+    let x = x.clamp(0.0, 1.0);
+    x * x * (3.0 - 2.0 * x)
+}
+
+fn fill_spans(canvas: &mut Canvas<Window>, spans: Vec<Rect<i32>>, color: Color) -> Result<()> {
+    let rects: Vec<sdl2::rect::Rect> = spans.into_iter().map(|span| span.into()).collect();
+    canvas.set_draw_color(color);
+    canvas
+        .fill_rects(&rects)
+        .map_err(|e| anyhow!("unable to fill shape: {}", e))
+}
+
+/// Fills the spans in short pieces, each colored by color_at at its center.
+fn fill_gradient_spans<F>(
+    canvas: &mut Canvas<Window>,
+    spans: Vec<Rect<i32>>,
+    color_at: F,
+) -> Result<()>
+where
+    F: Fn(f32, f32) -> Color,
+{
+    for span in spans {
+        let mut x = span.x;
+        while x < span.right() {
+            let w = GRADIENT_STEP.min(span.right() - x);
+            let piece = Rect {
+                x,
+                y: span.y,
+                w,
+                h: 1,
+            };
+            canvas.set_draw_color(color_at(x as f32 + w as f32 / 2.0, span.y as f32 + 0.5));
+            canvas
+                .fill_rect(Some(piece.into()))
+                .map_err(|e| anyhow!("unable to fill gradient: {}", e))?;
+            x += w;
+        }
+    }
+    Ok(())
+}
+
+/// Draws a sprite with copy, tinted, and then again on top of itself for its flash.
+///
+/// The canvas can only multiply a sprite's colors, so rather than mixing the flash in, the flash
+/// is added to the sprite's own colors, which brightens it toward the flash color.
+fn copy_flashed<F>(texture: &mut Texture, tint: Color, flash: Color, mut copy: F) -> Result<()>
+where
+    F: FnMut(&Texture) -> Result<(), String>,
+{
+    texture.set_color_mod(tint.r, tint.g, tint.b);
+    texture.set_alpha_mod(tint.a);
+    copy(texture).map_err(|e| anyhow!("unable to draw sprite: {}", e))?;
+    if flash.a == 0 {
+        return Ok(());
+    }
+    texture.set_blend_mode(BlendMode::Add);
+    texture.set_color_mod(flash.r, flash.g, flash.b);
+    texture.set_alpha_mod(((flash.a as u32 * tint.a as u32) / 255) as u8);
+    let result = copy(texture).map_err(|e| anyhow!("unable to draw sprite flash: {}", e));
+    texture.set_blend_mode(BlendMode::Blend);
+    result
+}
+
+/// Where to draw a transformed sprite before it's turned, the point in that rect it's turned
+/// around, and whether to flip it across and down. The canvas can only stretch a sprite by
+/// changing where it's drawn, so negative scales become flips.
+fn transformed_placement(
+    destination: Rect<i32>,
+    transform: &SpriteTransform,
+) -> (Rect<i32>, Point<i32>, bool, bool) {
+    let pivot = Point::new(destination.x as f32, destination.y as f32) + transform.pivot;
+    let (w, h) = (destination.w as f32, destination.h as f32);
+    let (scale_x, scale_y) = (transform.scale.x, transform.scale.y);
+    let left = pivot.x + (-transform.pivot.x * scale_x).min((w - transform.pivot.x) * scale_x);
+    let top = pivot.y + (-transform.pivot.y * scale_y).min((h - transform.pivot.y) * scale_y);
+    let area = Rect {
+        x: left.round() as i32,
+        y: top.round() as i32,
+        w: (w * scale_x.abs()).round() as i32,
+        h: (h * scale_y.abs()).round() as i32,
+    };
+    let center = Point::new(
+        (pivot.x - left).round() as i32,
+        (pivot.y - top).round() as i32,
+    );
+    (
+        area,
+        center,
+        transform.flip_horizontal != (scale_x < 0.0),
+        transform.flip_vertical != (scale_y < 0.0),
+    )
+}
+
+fn draw_batch<'a>(
+    canvas: &mut Canvas<Window>,
+    texture_atlas: &mut Option<Texture<'a>>,
+    dynamic_textures: &mut [Texture<'a>],
+    batch: &SpriteBatch,
+) -> Result<()> {
+    canvas.set_draw_color(batch.clear_color);
+    canvas.set_blend_mode(BlendMode::None);
+    canvas.clear();
+    canvas.set_blend_mode(BlendMode::Blend);
+
+    for entry in batch.entries.iter() {
+        match entry {
+            SpriteBatchEntry::Sprite {
+                sprite,
+                source,
+                destination,
+                reversed,
+                tint,
+                flash,
+            } => {
+                let texture = if sprite.id == TEXTURE_ATLAS_ID {
+                    texture_atlas.as_mut()
+                } else {
+                    dynamic_textures.get_mut(sprite.id - 1)
+                };
+                let Some(texture) = texture else {
+                    error!("unknown texture for sprite: {}", sprite.id);
+                    continue;
+                };
+                let source = Rect {
+                    x: sprite.area.x + source.x,
+                    y: sprite.area.y + source.y,
+                    w: source.w,
+                    h: source.h,
+                };
+                copy_flashed(texture, *tint, *flash, |texture| {
+                    canvas.copy_ex(
+                        texture,
+                        Some(source.into()),
+                        Some((*destination).into()),
+                        0.0,
+                        None,
+                        *reversed,
+                        false,
+                    )
+                })?;
+            }
+            SpriteBatchEntry::TransformedSprite {
+                sprite,
+                source,
+                destination,
+                transform,
+                tint,
+                flash,
+            } => {
+                let texture = if sprite.id == TEXTURE_ATLAS_ID {
+                    texture_atlas.as_mut()
+                } else {
+                    dynamic_textures.get_mut(sprite.id - 1)
+                };
+                let Some(texture) = texture else {
+                    error!("unknown texture for sprite: {}", sprite.id);
+                    continue;
+                };
+                let source = Rect {
+                    x: sprite.area.x + source.x,
+                    y: sprite.area.y + source.y,
+                    w: source.w,
+                    h: source.h,
+                };
+                let (area, center, flip_horizontal, flip_vertical) =
+                    transformed_placement(*destination, transform);
+                copy_flashed(texture, *tint, *flash, |texture| {
+                    canvas.copy_ex(
+                        texture,
+                        Some(source.into()),
+                        Some(area.into()),
+                        transform.angle.to_degrees() as f64,
+                        Some(sdl2::rect::Point::new(center.x, center.y)),
+                        flip_horizontal,
+                        flip_vertical,
+                    )
+                })?;
+            }
+            SpriteBatchEntry::FillRect { destination, color } => {
+                canvas.set_draw_color(*color);
+                canvas
+                    .fill_rect(Some((*destination).into()))
+                    .map_err(|e| anyhow!("unable to fill rect: {}", e))?;
+            }
+            SpriteBatchEntry::FillTriangle { p1, p2, p3, color } => {
+                let points = [p1, p2, p3].map(|p| Point::new(p.x as f32, p.y as f32));
+                fill_spans(canvas, convex_spans(&points), *color)?;
+            }
+            SpriteBatchEntry::FillGradientRect {
+                destination,
+                colors,
+            } => {
+                let [top_left, top_right, bottom_right, bottom_left] = *colors;
+                let rect = *destination;
+                let corners = [
+                    Point::new(rect.x as f32, rect.y as f32),
+                    Point::new(rect.right() as f32, rect.y as f32),
+                    Point::new(rect.right() as f32, rect.bottom() as f32),
+                    Point::new(rect.x as f32, rect.bottom() as f32),
+                ];
+                fill_gradient_spans(canvas, convex_spans(&corners), |x, y| {
+                    let tx = (x - rect.x as f32) / rect.w.max(1) as f32;
+                    let ty = (y - rect.y as f32) / rect.h.max(1) as f32;
+                    let top = top_left.lerp(top_right, tx);
+                    let bottom = bottom_left.lerp(bottom_right, tx);
+                    top.lerp(bottom, ty)
+                })?;
+            }
+            SpriteBatchEntry::FillGradientTriangle { p1, p2, p3, colors } => {
+                let points = [p1, p2, p3].map(|p| Point::new(p.x as f32, p.y as f32));
+                fill_gradient_spans(canvas, convex_spans(&points), |x, y| {
+                    triangle_color(&points, colors, x, y)
+                })?;
+            }
+            SpriteBatchEntry::Line {
+                start,
+                end,
+                color,
+                width,
+                cap,
+            } => {
+                let points = line_quad(*start, *end, *width, *cap);
+                fill_spans(canvas, convex_spans(&points), *color)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl<'a> Renderer for SdlRenderer<'a> {
+    fn load_sprite(&mut self, _path: &Path) -> Result<Sprite> {
+        Ok(Sprite {
+            id: TEXTURE_ATLAS_ID,
+            area: self.texture_atlas_area(),
+        })
+    }
+
+    fn load_texture_atlas(&mut self, path: &Path, files: &FileManager) -> Result<Sprite> {
+        info!("Reading texture atlas from {:?}", path);
+        let bytes = files.read(path)?;
+        let img = image::load_from_memory(&bytes)
+            .map_err(|e| anyhow!("unable to load image from {:?}: {}", path, e))?
+            .to_rgba8();
+        self.set_texture_atlas(img.width(), img.height(), &img)
+            .map_err(|e| anyhow!("unable to load texture atlas {:?}: {}", path, e))
+    }
+
+    fn create_texture_atlas(&mut self, width: u32, height: u32, pixels: &[u8]) -> Result<Sprite> {
+        info!("Creating {}x{} texture atlas", width, height);
+        let expected = (width * height * 4) as usize;
+        if pixels.len() != expected {
+            bail!(
+                "{}x{} texture atlas needs {} bytes, but got {}",
+                width,
+                height,
+                expected,
+                pixels.len()
+            );
+        }
+        self.set_texture_atlas(width, height, pixels)
+    }
+
+    fn create_dynamic_texture(&mut self, width: u32, height: u32) -> Result<Sprite> {
+        if width == 0 || height == 0 {
+            bail!("invalid dynamic texture size: {}x{}", width, height);
+        }
+        let mut texture = self
+            .texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGBA32, width, height)
+            .map_err(|e| anyhow!("unable to create dynamic texture: {}", e))?;
+        texture.set_blend_mode(BlendMode::Blend);
+        self.dynamic_textures.push(texture);
+        Ok(Sprite {
+            id: self.dynamic_textures.len(),
+            area: Rect {
+                x: 0,
+                y: 0,
+                w: width as i32,
+                h: height as i32,
+            },
+        })
+    }
+
+    fn update_dynamic_texture(&mut self, sprite: &Sprite, pixels: &[u8]) -> Result<()> {
+        let texture = self
+            .dynamic_textures
+            .get_mut(sprite.id.wrapping_sub(1))
+            .ok_or_else(|| anyhow!("not a dynamic texture: {}", sprite.id))?;
+        let query = texture.query();
+        let expected = (query.width * query.height * 4) as usize;
+        if pixels.len() != expected {
+            bail!(
+                "dynamic texture {} needs {} bytes, but got {}",
+                sprite.id,
+                expected,
+                pixels.len()
+            );
+        }
+        texture
+            .update(None, pixels, (query.width * 4) as usize)
+            .map_err(|e| anyhow!("unable to update dynamic texture {}: {}", sprite.id, e))
+    }
+
+    fn update_texture_atlas(&mut self, sprite: &Sprite, pixels: &[u8]) -> Result<()> {
+        if sprite.id != TEXTURE_ATLAS_ID {
+            bail!("not a texture atlas: {}", sprite.id);
+        }
+        let area = sprite.area;
+        let texture = self
+            .texture_atlas
+            .as_mut()
+            .ok_or_else(|| anyhow!("no texture atlas has been loaded"))?;
+        let expected = (area.w.max(0) * area.h.max(0) * 4) as usize;
+        if pixels.len() != expected {
+            bail!(
+                "atlas region {:?} needs {} bytes, but got {}",
+                area,
+                expected,
+                pixels.len()
+            );
+        }
+        let rect: sdl2::rect::Rect = area.into();
+        texture
+            .update(rect, pixels, (area.w * 4) as usize)
+            .map_err(|e| anyhow!("unable to update texture atlas at {:?}: {}", area, e))
+    }
+
+    /// The canvas is always stretched to fill the window, so there's nothing to resize.
+    fn resize(&mut self, _width: u32, _height: u32) {}
+
+    fn render(&mut self, context: &RenderContext) -> Result<()> {
+        let outgoing = context.visible_outgoing();
+        if let Some((_, outgoing)) = outgoing {
+            let is_dark = self.draw_layers(outgoing)?;
+            let SdlRenderer {
+                canvas,
+                player_framebuffer,
+                hud_framebuffer,
+                overlay_framebuffer,
+                darkness,
+                outgoing_framebuffer,
+                ..
+            } = self;
+            let darkness = is_dark.then_some(&*darkness);
+            let mut result = Ok(());
+            canvas
+                .with_texture_canvas(outgoing_framebuffer, |canvas| {
+                    canvas.set_draw_color(sdl2::pixels::Color::BLACK);
+                    canvas.clear();
+                    result = draw_scene(canvas, player_framebuffer, darkness, hud_framebuffer)
+                        .and_then(|_| draw_overlay(canvas, overlay_framebuffer));
+                })
+                .map_err(|e| anyhow!("unable to render to framebuffer: {}", e))?;
+            result?;
+        }
+
+        let is_dark = self.draw_layers(context)?;
+        let darkness = is_dark.then_some(&self.darkness);
+        let canvas = &mut self.canvas;
+        canvas.set_draw_color(sdl2::pixels::Color::BLACK);
+        canvas.clear();
+        let Some(transition) = context.transition else {
+            draw_scene(
+                canvas,
+                &self.player_framebuffer,
+                darkness,
+                &self.hud_framebuffer,
+            )?;
+            draw_overlay(canvas, &self.overlay_framebuffer)?;
+            return self.present();
+        };
+        match transition.kind {
+            TransitionKind::Cut => {
+                draw_scene(
+                    canvas,
+                    &self.player_framebuffer,
+                    darkness,
+                    &self.hud_framebuffer,
+                )?;
+            }
+            TransitionKind::Fade => {
+                if outgoing.is_some() {
+                    canvas
+                        .copy(&self.outgoing_framebuffer, None, None)
+                        .map_err(|e| anyhow!("unable to draw outgoing scene: {}", e))?;
+                } else {
+                    draw_scene(
+                        canvas,
+                        &self.player_framebuffer,
+                        darkness,
+                        &self.hud_framebuffer,
+                    )?;
+                }
+                let alpha = (transition.darkness() * 255.0).round() as u8;
+                canvas.set_blend_mode(BlendMode::Blend);
+                canvas.set_draw_color(sdl2::pixels::Color::RGBA(0, 0, 0, alpha));
+                canvas
+                    .fill_rect(None)
+                    .map_err(|e| anyhow!("unable to fade: {}", e))?;
+            }
+            TransitionKind::Crossfade => {
+                draw_scene(
+                    canvas,
+                    &self.player_framebuffer,
+                    darkness,
+                    &self.hud_framebuffer,
+                )?;
+                if outgoing.is_some() {
+                    let alpha = ((1.0 - transition.progress) * 255.0).round() as u8;
+                    self.outgoing_framebuffer.set_alpha_mod(alpha);
+                    canvas
+                        .copy(&self.outgoing_framebuffer, None, None)
+                        .map_err(|e| anyhow!("unable to draw outgoing scene: {}", e))?;
+                    self.outgoing_framebuffer.set_alpha_mod(255);
+                }
+            }
+            TransitionKind::Wipe => {
+                if outgoing.is_some() {
+                    canvas
+                        .copy(&self.outgoing_framebuffer, None, None)
+                        .map_err(|e| anyhow!("unable to draw outgoing scene: {}", e))?;
+                }
+                // The new scene is only drawn left of the edge of the wipe.
+                let (width, height) = canvas
+                    .output_size()
+                    .map_err(|e| anyhow!("unable to get canvas size: {}", e))?;
+                let edge = (width as f32 * transition.progress).round() as u32;
+                if edge > 0 {
+                    canvas.set_clip_rect(sdl2::rect::Rect::new(0, 0, edge, height));
+                    let result = draw_scene(
+                        canvas,
+                        &self.player_framebuffer,
+                        darkness,
+                        &self.hud_framebuffer,
+                    );
+                    canvas.set_clip_rect(None);
+                    result?;
+                }
+            }
+        }
+        draw_overlay(canvas, &self.overlay_framebuffer)?;
+        self.present()
+    }
+
+    fn set_capturing(&mut self, capturing: bool) {
+        self.capturing = capturing;
+        if !capturing {
+            self.captured_frame = None;
+        }
+    }
+
+    fn take_captured_frame(&mut self) -> Option<RgbaImage> {
+        self.captured_frame.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transformed() {
+        let destination = Rect {
+            x: 10,
+            y: 20,
+            w: 8,
+            h: 4,
+        };
+        let transform = SpriteTransform {
+            pivot: Point::new(2.0, 4.0),
+            scale: Point::new(-2.0, 0.5),
+            ..Default::default()
+        };
+        // Mirrored across the pivot at (12, 24), and squashed down toward it.
+        let (area, center, flip_horizontal, flip_vertical) =
+            transformed_placement(destination, &transform);
+        assert_eq!(
+            area,
+            Rect {
+                x: 0,
+                y: 22,
+                w: 16,
+                h: 2,
+            }
+        );
+        assert_eq!(center, Point::new(12, 2));
+        assert!(flip_horizontal && !flip_vertical);
+    }
+}
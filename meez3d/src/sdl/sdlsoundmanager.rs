@@ -1,34 +1,46 @@
 use std::mem;
 use std::ops::DerefMut;
-use std::path::Path;
 
 use anyhow::{anyhow, bail, Result};
 use log::debug;
 use sdl2::audio::{
     AudioCVT, AudioCallback, AudioDevice, AudioSpec, AudioSpecDesired, AudioSpecWAV,
 };
+use sdl2::rwops::RWops;
 use sdl2::AudioSubsystem;
 
-use crate::soundmanager::{Sound, SoundPlayer};
+use crate::soundmanager::{SoundClip, SoundFormat, SoundHandle, SoundPlayer};
 
 const MAX_SOUNDS: usize = 4;
+/// The value of a silent sample.
+const SILENCE: f32 = 127.0;
+/// How far each muffled sample moves toward the mixed one, which sets the low-pass filter's
+/// cutoff to a few hundred hertz.
+const MUFFLE_SMOOTHING: f32 = 0.1;
+
+/// A loaded sound, converted to the device's format.
+struct Clip {
+    samples: Vec<u8>,
+    looping: bool,
+}
 
-struct SoundCallback {
-    clips: Vec<Vec<u8>>,
-    playing: Vec<(Sound, usize)>,
+/// A sound that's playing, and how loud it is in each channel.
+struct Playing {
+    handle: SoundHandle,
+    offset: usize,
+    /// The volume in the left and right channels.
+    gains: [f32; 2],
 }
 
-impl SoundCallback {
-    fn load_wav(&mut self, sound: Sound, name: &str, spec: &AudioSpec) -> Result<()> {
-        let path_str = format!("./assets/sounds/{}.wav", name);
-        let path = Path::new(&path_str);
-        let wav = load_wav(path, spec)?;
-        if self.clips.len() != sound as usize {
-            bail!("sounds must be loaded in order");
-        }
-        self.clips.push(wav);
-        Ok(())
-    }
+struct SoundCallback {
+    /// The loaded sounds, indexed by handle.
+    clips: Vec<Option<Clip>>,
+    playing: Vec<Playing>,
+    /// How many channels the device has, which are interleaved in the buffer.
+    channels: u8,
+    muffled: bool,
+    /// The low-pass filter's last output in each channel, relative to silence.
+    filtered: [f32; 2],
 }
 
 impl AudioCallback for SoundCallback {
@@ -36,32 +48,64 @@ impl AudioCallback for SoundCallback {
 
     fn callback(&mut self, buffer: &mut [Self::Channel]) {
         for sample in buffer.iter_mut() {
-            *sample = 127;
+            *sample = SILENCE as u8;
         }
 
         let playing = mem::take(&mut self.playing);
-        for (sound, offset) in playing.into_iter() {
-            let clip = &self.clips[sound as usize];
+        for playing in playing.into_iter() {
+            let Some(Some(clip)) = self.clips.get(playing.handle.index()) else {
+                continue;
+            };
+            let samples = &clip.samples;
+            let offset = playing.offset;
 
             for (i, sample) in buffer.iter_mut().enumerate() {
-                if offset + i >= clip.len() {
+                let position = if clip.looping {
+                    (offset + i) % samples.len()
+                } else if offset + i < samples.len() {
+                    offset + i
+                } else {
                     break;
-                }
-                *sample -= 127 / (MAX_SOUNDS as u8);
-                *sample += clip[i + offset] / (MAX_SOUNDS as u8);
+                };
+                // A mono device gets the average of the two channels.
+                let gain = if self.channels >= 2 {
+                    playing
+                        .gains
+                        .get(i % self.channels as usize)
+                        .copied()
+                        .unwrap_or(0.0)
+                } else {
+                    (playing.gains[0] + playing.gains[1]) / 2.0
+                };
+                let value = (samples[position] as f32 - SILENCE) * gain / MAX_SOUNDS as f32;
+                *sample = (*sample as f32 + value).round().clamp(0.0, 255.0) as u8;
             }
 
             let next_offset = offset + buffer.len();
-            if next_offset < clip.len() {
-                self.playing.push((sound, next_offset));
+            if clip.looping || next_offset < samples.len() {
+                self.playing.push(Playing {
+                    offset: next_offset % samples.len(),
+                    ..playing
+                });
+            }
+        }
+
+        if self.muffled {
+            let channels = self.channels.max(1) as usize;
+            for (i, sample) in buffer.iter_mut().enumerate() {
+                let filtered = &mut self.filtered[(i % channels).min(1)];
+                *filtered += (*sample as f32 - SILENCE - *filtered) * MUFFLE_SMOOTHING;
+                *sample = (*filtered + SILENCE).round().clamp(0.0, 255.0) as u8;
             }
         }
     }
 }
 
-fn load_wav(path: &Path, spec: &AudioSpec) -> Result<Vec<u8>> {
-    let wav = AudioSpecWAV::load_wav(path)
-        .map_err(|s| anyhow!("unable to load wav {:?}: {}", path, s))?;
+/// Decodes a wav file and converts it to the device's format.
+fn load_wav(data: &[u8], spec: &AudioSpec) -> Result<Vec<u8>> {
+    let mut rwops = RWops::from_bytes(data).map_err(|s| anyhow!("unable to read wav: {}", s))?;
+    let wav =
+        AudioSpecWAV::load_wav_rw(&mut rwops).map_err(|s| anyhow!("unable to load wav: {}", s))?;
 
     let cvt = AudioCVT::new(
         wav.format,
@@ -78,6 +122,9 @@ fn load_wav(path: &Path, spec: &AudioSpec) -> Result<Vec<u8>> {
     if wav.buffer().len() % 2 != 0 {
         bail!("wav parity error");
     }
+    if buffer.is_empty() {
+        bail!("wav has no samples");
+    }
 
     Ok(buffer)
 }
@@ -90,39 +137,74 @@ impl SdlSoundManager {
     pub fn new(audio: &AudioSubsystem) -> Result<Self> {
         let desired_spec = AudioSpecDesired {
             freq: Some(44100),
-            channels: Some(1),
+            channels: Some(2),
             samples: Some(512),
         };
 
-        let mut device = audio
-            .open_playback(None, &desired_spec, |_spec| SoundCallback {
+        let device = audio
+            .open_playback(None, &desired_spec, |spec| SoundCallback {
                 clips: Vec::new(),
                 playing: Vec::new(),
+                channels: spec.channels,
+                muffled: false,
+                filtered: [0.0; 2],
             })
             .map_err(|s| anyhow!("error initializing audio device: {}", s))?;
 
-        SdlSoundManager::load_sounds(&mut device)?;
-
         device.resume();
         Ok(Self { device })
     }
+}
 
-    fn load_sounds(device: &mut AudioDevice<SoundCallback>) -> Result<()> {
-        let spec = *device.spec();
-        let mut lock = device.lock();
+impl SoundPlayer for SdlSoundManager {
+    fn load(&mut self, handle: SoundHandle, clip: &SoundClip) -> Result<()> {
+        let samples = match clip.format {
+            SoundFormat::Wav => load_wav(&clip.data, self.device.spec())?,
+            SoundFormat::Ogg => bail!("unsupported sound format: ogg"),
+        };
+        let mut lock = self.device.lock();
         let callback = lock.deref_mut();
-        callback.load_wav(Sound::Click, "click", &spec)?;
+        // Stop anything playing the old sound, since its offset might be past the new one's end.
+        callback.playing.retain(|playing| playing.handle != handle);
+        if callback.clips.len() <= handle.index() {
+            callback.clips.resize_with(handle.index() + 1, || None);
+        }
+        callback.clips[handle.index()] = Some(Clip {
+            samples,
+            looping: clip.looping,
+        });
         Ok(())
     }
-}
 
-impl SoundPlayer for SdlSoundManager {
-    fn play(&mut self, sound: Sound) {
-        debug!("playing sound {:?}", sound);
+    fn play(&mut self, handle: SoundHandle, volume: f32, pan: f32) {
+        debug!(
+            "playing sound {:?} at volume {} and pan {}",
+            handle, volume, pan
+        );
+        let pan = pan.clamp(-1.0, 1.0);
+        let gains = [volume * (1.0 - pan).min(1.0), volume * (1.0 + pan).min(1.0)];
         let mut lock = self.device.lock();
         let callback = lock.deref_mut();
         if callback.playing.len() < MAX_SOUNDS {
-            callback.playing.push((sound, 0));
+            callback.playing.push(Playing {
+                handle,
+                offset: 0,
+                gains,
+            });
         }
     }
+
+    fn stop(&mut self, handle: SoundHandle) {
+        let mut lock = self.device.lock();
+        lock.deref_mut()
+            .playing
+            .retain(|playing| playing.handle != handle);
+    }
+
+    fn set_muffled(&mut self, muffled: bool) {
+        let mut lock = self.device.lock();
+        let callback = lock.deref_mut();
+        callback.muffled = muffled;
+        callback.filtered = [0.0; 2];
+    }
 }
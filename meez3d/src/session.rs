@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::aimassist::AIM_ASSIST_CVAR;
+use crate::cvars::Cvars;
+use crate::displaysettings::{DISPLAY_CVAR, WINDOW_MODE_CVAR};
+use crate::enemystats::DIFFICULTY_CVAR;
+use crate::filemanager::FileManager;
+use crate::inputmanager::{DEAD_ZONE_CVAR, INPUT_PROFILE_CVAR};
+use crate::leaderboard::PLAYER_NAME_CVAR;
+use crate::level::{MOUSE_LOOK_CVAR, MOUSE_SENSITIVITY_CVAR};
+use crate::rendercontext::{SAFE_AREA_CVAR, UI_SCALE_CVAR};
+use crate::soundmanager::VOLUME_CVAR;
+use crate::transition::{TRANSITION_CVAR, TRANSITION_FRAMES_CVAR};
+use crate::tutorial::TUTORIAL_CVAR;
+
+/// The user data file the last session is kept in.
+const SESSION_PATH: &str = "session.json";
+
+/// The cvars that are player settings, which are kept from one run to the next.
+pub const SESSION_CVARS: &[&str] = &[
+    WINDOW_MODE_CVAR,
+    DISPLAY_CVAR,
+    UI_SCALE_CVAR,
+    SAFE_AREA_CVAR,
+    VOLUME_CVAR,
+    DIFFICULTY_CVAR,
+    MOUSE_LOOK_CVAR,
+    MOUSE_SENSITIVITY_CVAR,
+    AIM_ASSIST_CVAR,
+    DEAD_ZONE_CVAR,
+    INPUT_PROFILE_CVAR,
+    TRANSITION_CVAR,
+    TRANSITION_FRAMES_CVAR,
+    TUTORIAL_CVAR,
+    PLAYER_NAME_CVAR,
+];
+
+/// Where a window was and how big it was, in the units of the host's window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// What the player had set up when the game last exited, so it can start the same way next time.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    /// The last window, if it was windowed rather than fullscreen.
+    #[serde(default)]
+    pub window: Option<WindowGeometry>,
+    /// The values of the session cvars that were set.
+    #[serde(default)]
+    pub settings: BTreeMap<String, String>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// Loads the last session, or starts a new one if nothing's been saved yet.
+    pub fn load(files: &FileManager) -> Session {
+        match files
+            .read_user_data(SESSION_PATH)
+            .and_then(|text| Session::from_json(&text))
+        {
+            Ok(session) => session,
+            Err(e) => {
+                info!("starting a new session: {}", e);
+                Session::new()
+            }
+        }
+    }
+
+    pub fn from_json(text: &str) -> Result<Session> {
+        serde_json::from_str(text).map_err(|e| anyhow!("unable to parse session: {}", e))
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| anyhow!("unable to serialize session: {}", e))
+    }
+
+    pub fn save(&self, files: &FileManager) -> Result<()> {
+        files.write_user_data(SESSION_PATH, self.to_json()?.as_bytes())
+    }
+
+    /// Sets the cvars to the session's settings. Anything else in the session is ignored, so an
+    /// old session can't set cvars that aren't settings.
+    pub fn apply_cvars(&self, cvars: &mut Cvars) {
+        for (name, value) in self.settings.iter() {
+            if SESSION_CVARS.contains(&name.as_str()) {
+                cvars.set(name, value);
+            }
+        }
+    }
+
+    /// Keeps the current value of every session cvar that's set.
+    pub fn read_cvars(&mut self, cvars: &Cvars) {
+        for name in SESSION_CVARS {
+            if let Some(value) = cvars.get(name) {
+                self.settings.insert(name.to_string(), value.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut cvars = Cvars::new();
+        cvars.set(VOLUME_CVAR, "0.5");
+        cvars.set(WINDOW_MODE_CVAR, "borderless");
+        cvars.set("god", "true");
+        let mut session = Session::new();
+        session.window = Some(WindowGeometry {
+            x: 10,
+            y: 20,
+            width: 800,
+            height: 600,
+        });
+        session.read_cvars(&cvars);
+        assert_eq!(session.settings.len(), 2);
+
+        let loaded = Session::from_json(&session.to_json().unwrap()).unwrap();
+        assert_eq!(loaded, session);
+        let mut restored = Cvars::new();
+        loaded.apply_cvars(&mut restored);
+        assert_eq!(restored.get(VOLUME_CVAR), Some("0.5"));
+        assert_eq!(restored.get(WINDOW_MODE_CVAR), Some("borderless"));
+        assert_eq!(restored.get("god"), None);
+
+        // Cheats edited into the file aren't applied.
+        let edited = Session::from_json(r#"{"settings": {"god": "true"}}"#).unwrap();
+        edited.apply_cvars(&mut restored);
+        assert_eq!(restored.get("god"), None);
+        assert_eq!(edited.window, None);
+        assert!(Session::from_json("not json").is_err());
+    }
+}
@@ -36,4 +36,12 @@ where
         let k: usize = k.into();
         self.values.get_mut(k).and_then(|ov| ov.as_mut())
     }
+
+    /// Iterates over the entries in order of their keys.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &V)> {
+        self.values
+            .iter()
+            .enumerate()
+            .filter_map(|(k, ov)| ov.as_ref().map(|v| (k, v)))
+    }
 }
@@ -1,23 +1,69 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A set for a handful of small items, like the ids of opened doors, kept in insertion order.
+///
+/// Lookups are linear, which is faster than hashing for the few items these sets usually hold.
+#[derive(Debug, Clone)]
 pub struct SmallIntSet<T> {
     items: Vec<T>,
 }
 
+impl<T> Default for SmallIntSet<T> {
+    fn default() -> Self {
+        SmallIntSet { items: Vec::new() }
+    }
+}
+
 impl<T> SmallIntSet<T>
 where
     T: PartialEq,
 {
     pub fn new() -> Self {
-        SmallIntSet { items: Vec::new() }
+        Self::default()
     }
 
-    pub fn insert(&mut self, item: T) {
+    /// Creates an empty set with room for at least the given number of items.
+    pub fn with_capacity(capacity: usize) -> Self {
+        SmallIntSet {
+            items: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Makes room for at least the given number of additional items.
+    pub fn reserve(&mut self, additional: usize) {
+        self.items.reserve(additional);
+    }
+
+    /// Adds the item, returning true if it wasn't already in the set.
+    pub fn insert(&mut self, item: T) -> bool {
+        if self.items.contains(&item) {
+            return false;
+        }
         self.items.push(item);
+        true
+    }
+
+    /// Removes the item, returning true if it was in the set.
+    pub fn remove(&mut self, item: T) -> bool {
+        let Some(index) = self.items.iter().position(|x| *x == item) else {
+            return false;
+        };
+        self.items.remove(index);
+        true
     }
 
     pub fn contains(&self, item: T) -> bool {
         self.items.contains(&item)
     }
 
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
     pub fn clear(&mut self) {
         self.items.clear();
     }
@@ -26,3 +72,204 @@ where
         self.items.iter()
     }
 }
+
+impl<T> SmallIntSet<T>
+where
+    T: PartialEq + Clone,
+{
+    /// The items in either set.
+    pub fn union(&self, other: &SmallIntSet<T>) -> SmallIntSet<T> {
+        let mut result = self.clone();
+        result.extend(other.iter().cloned());
+        result
+    }
+
+    /// The items in both sets.
+    pub fn intersection(&self, other: &SmallIntSet<T>) -> SmallIntSet<T> {
+        self.iter()
+            .filter(|item| other.items.contains(item))
+            .cloned()
+            .collect()
+    }
+
+    /// The items in this set, but not the other.
+    pub fn difference(&self, other: &SmallIntSet<T>) -> SmallIntSet<T> {
+        self.iter()
+            .filter(|item| !other.items.contains(item))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Sets are equal if they have the same items, in any order.
+impl<T> PartialEq for SmallIntSet<T>
+where
+    T: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|item| other.items.contains(item))
+    }
+}
+
+impl<T> Eq for SmallIntSet<T> where T: Eq {}
+
+impl<T> Extend<T> for SmallIntSet<T>
+where
+    T: PartialEq,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.insert(item);
+        }
+    }
+}
+
+impl<T> FromIterator<T> for SmallIntSet<T>
+where
+    T: PartialEq,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = SmallIntSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<'a, T> IntoIterator for &'a SmallIntSet<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
+
+impl<T> IntoIterator for SmallIntSet<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+/// Sets are saved as a plain list of their items.
+impl<T> Serialize for SmallIntSet<T>
+where
+    T: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.items.serialize(serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for SmallIntSet<T>
+where
+    T: Deserialize<'de> + PartialEq,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Vec::<T>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
+/// A set of small non-negative integers, stored as one bit each, so it stays compact even with
+/// an entry for every cell of a large map.
+#[derive(Debug, Clone, Default, Hash, Serialize, Deserialize)]
+pub struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the item, returning true if it wasn't already in the set.
+    pub fn insert(&mut self, item: usize) -> bool {
+        let (word, bit) = (item / 64, 1u64 << (item % 64));
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        let added = self.words[word] & bit == 0;
+        self.words[word] |= bit;
+        added
+    }
+
+    pub fn contains(&self, item: usize) -> bool {
+        let (word, bit) = (item / 64, 1u64 << (item % 64));
+        self.words.get(word).is_some_and(|w| w & bit != 0)
+    }
+
+    /// Takes the item out, returning true if it was in the set.
+    pub fn remove(&mut self, item: usize) -> bool {
+        let (word, bit) = (item / 64, 1u64 << (item % 64));
+        let Some(w) = self.words.get_mut(word) else {
+            return false;
+        };
+        let removed = *w & bit != 0;
+        *w &= !bit;
+        removed
+    }
+
+    /// The bits of the set, 64 to a word, starting from 0.
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_operations() {
+        let mut a: SmallIntSet<u32> = [1, 2, 3].into_iter().collect();
+        let b: SmallIntSet<u32> = [3, 4].into_iter().collect();
+        assert!(!a.insert(2));
+        assert_eq!(a.len(), 3);
+
+        assert_eq!(a.union(&b), [4, 3, 2, 1].into_iter().collect());
+        assert_eq!(a.intersection(&b), [3].into_iter().collect());
+        assert_eq!(a.difference(&b), [1, 2].into_iter().collect());
+
+        assert!(a.remove(1));
+        assert!(!a.remove(1));
+        assert_eq!(a.iter().copied().collect::<Vec<_>>(), vec![2, 3]);
+        assert!(b.difference(&b).is_empty());
+    }
+
+    #[test]
+    fn serialization() {
+        let set: SmallIntSet<u32> = [5, 1, 9].into_iter().collect();
+        let json = serde_json::to_string(&set).unwrap();
+        assert_eq!(json, "[5,1,9]");
+
+        // Duplicates in a hand-edited save are dropped.
+        let copy: SmallIntSet<u32> = serde_json::from_str("[5,1,9,1]").unwrap();
+        assert_eq!(copy, set);
+        assert_eq!(copy.len(), 3);
+
+        let mut bits = BitSet::new();
+        bits.insert(70);
+        let copy: BitSet = serde_json::from_str(&serde_json::to_string(&bits).unwrap()).unwrap();
+        assert!(copy.contains(70));
+        assert!(!copy.contains(69));
+    }
+
+    #[test]
+    fn bitset() {
+        let mut set = BitSet::new();
+        assert!(!set.contains(0));
+        assert!(!set.contains(1000));
+        assert!(set.insert(3));
+        assert!(set.insert(64));
+        assert!(set.insert(1000));
+        assert!(!set.insert(64));
+        assert!(set.contains(3));
+        assert!(set.contains(64));
+        assert!(set.contains(1000));
+        assert!(!set.contains(4));
+        assert!(!set.contains(63));
+        assert!(!set.contains(999));
+    }
+}
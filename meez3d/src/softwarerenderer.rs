@@ -0,0 +1,742 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use image::{Rgba, RgbaImage};
+use log::{error, info};
+
+use crate::filemanager::FileManager;
+use crate::geometry::{Point, Rect};
+use crate::rendercontext::{
+    convex_spans, line_quad, triangle_color, RenderContext, SpriteBatch, SpriteBatchEntry,
+    SpriteTransform,
+};
+use crate::renderer::{Renderer, RendererStats};
+use crate::sprite::Sprite;
+use crate::transition::{TransitionFrame, TransitionKind};
+use crate::utils::Color;
+
+/// How dark it is at the edge of a spotlight, matching the wgpu shader.
+const DARKNESS_ALPHA: f32 = 0.85;
+
+/// Every frame starts out black, like the canvas the other renderers clear.
+const OPAQUE_BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// A renderer that draws on the CPU into an image instead of a window, so frames can be checked
+/// pixel by pixel, e.g. in tests. Like the SDL renderer, it skips the postprocessing effects.
+#[derive(Default)]
+pub struct SoftwareRenderer {
+    textures: HashMap<usize, RgbaImage>,
+    /// Which of the textures are atlases, rather than dynamic textures.
+    atlas_ids: Vec<usize>,
+    next_id: usize,
+
+    player_layer: RgbaImage,
+    hud_layer: RgbaImage,
+    /// Copied over everything else, after the darkness and any transition.
+    overlay_layer: RgbaImage,
+    darkness: RgbaImage,
+    /// The outgoing scene's finished frame is drawn here during a transition.
+    outgoing_frame: RgbaImage,
+    frame: RgbaImage,
+
+    capturing: bool,
+    captured_frame: Option<RgbaImage>,
+}
+
+impl SoftwareRenderer {
+    pub fn new() -> SoftwareRenderer {
+        SoftwareRenderer::default()
+    }
+
+    /// The last frame rendered, which is as big as the context it was rendered from.
+    pub fn frame(&self) -> &RgbaImage {
+        &self.frame
+    }
+
+    fn add_texture(&mut self, texture: RgbaImage) -> Sprite {
+        let id = self.next_id;
+        self.next_id += 1;
+        let area = Rect {
+            x: 0,
+            y: 0,
+            w: texture.width() as i32,
+            h: texture.height() as i32,
+        };
+        self.textures.insert(id, texture);
+        Sprite { id, area }
+    }
+
+    /// Replaces every atlas with one made from width * height RGBA pixels.
+    fn set_texture_atlas(&mut self, width: u32, height: u32, pixels: &[u8]) -> Result<Sprite> {
+        for id in self.atlas_ids.drain(..) {
+            self.textures.remove(&id);
+        }
+        self.add_texture_atlas(width, height, pixels)
+    }
+
+    /// Draws the context's layers into their images, and gets the darkness mask ready,
+    /// returning whether it should be drawn.
+    fn draw_layers(&mut self, context: &RenderContext) -> bool {
+        let (width, height) = (context.width, context.height);
+        for (layer, batch) in [
+            (&mut self.player_layer, &context.player_batch),
+            (&mut self.hud_layer, &context.hud_batch),
+            (&mut self.overlay_layer, &context.overlay_batch),
+        ] {
+            resize(layer, width, height);
+            draw_batch(layer, &self.textures, batch);
+        }
+
+        let is_dark = context.is_dark && !context.lights.is_empty();
+        if is_dark {
+            resize(&mut self.darkness, width, height);
+            update_darkness(&mut self.darkness, context);
+        }
+        is_dark
+    }
+
+    /// Puts the layers from draw_layers together on the target, with the darkness mask if it's
+    /// dark, but only left of the right edge, e.g. for a wipe.
+    fn draw_scene(
+        &self,
+        target: &mut RgbaImage,
+        context: &RenderContext,
+        is_dark: bool,
+        right: u32,
+    ) {
+        composite(target, &self.player_layer, context.ambient_light, right);
+        if is_dark {
+            composite(target, &self.darkness, Color::WHITE, right);
+        }
+        composite(target, &self.hud_layer, Color::WHITE, right);
+    }
+
+    /// Draws the scene blended with the outgoing scene's frame, if it has one.
+    fn draw_transition(
+        &self,
+        target: &mut RgbaImage,
+        context: &RenderContext,
+        transition: TransitionFrame,
+        has_outgoing: bool,
+        is_dark: bool,
+    ) {
+        let width = target.width();
+        match transition.kind {
+            TransitionKind::Cut => self.draw_scene(target, context, is_dark, width),
+            TransitionKind::Fade => {
+                if has_outgoing {
+                    composite(target, &self.outgoing_frame, Color::WHITE, width);
+                } else {
+                    self.draw_scene(target, context, is_dark, width);
+                }
+                let fade = Color {
+                    a: (transition.darkness() * 255.0).round() as u8,
+                    ..Color::BLACK
+                };
+                target.pixels_mut().for_each(|pixel| blend(pixel, fade));
+            }
+            TransitionKind::Crossfade => {
+                self.draw_scene(target, context, is_dark, width);
+                if has_outgoing {
+                    let tint = Color {
+                        a: ((1.0 - transition.progress) * 255.0).round() as u8,
+                        ..Color::WHITE
+                    };
+                    composite(target, &self.outgoing_frame, tint, width);
+                }
+            }
+            TransitionKind::Wipe => {
+                if has_outgoing {
+                    composite(target, &self.outgoing_frame, Color::WHITE, width);
+                }
+                // The new scene is only drawn left of the edge of the wipe.
+                let edge = (width as f32 * transition.progress).round() as u32;
+                self.draw_scene(target, context, is_dark, edge);
+            }
+        }
+    }
+}
+
+/// Makes the image the given size, clearing it if it changes.
+fn resize(image: &mut RgbaImage, width: u32, height: u32) {
+    if image.dimensions() != (width, height) {
+        *image = RgbaImage::new(width, height);
+    }
+}
+
+fn smoothstep(x: f32) -> f32 {
+    let x = x.clamp(0.0, 1.0);
+    x * x * (3.0 - 2.0 * x)
+}
+
+/// Fills the darkness mask with the same falloff the wgpu shader uses around each light.
+fn update_darkness(darkness: &mut RgbaImage, context: &RenderContext) {
+    for (x, y, pixel) in darkness.enumerate_pixels_mut() {
+        let position = Point::new(x as f32 + 0.5, y as f32 + 0.5);
+        let mut alpha: f32 = 1.0;
+        for light in context.lights.iter() {
+            let dx = position.x - light.position.x as f32;
+            let dy = position.y - light.position.y as f32;
+            let d = (dx * dx + dy * dy).sqrt() / light.radius.max(1) as f32;
+            alpha = alpha.min(smoothstep(d) * DARKNESS_ALPHA);
+        }
+        *pixel = Rgba([0, 0, 0, (alpha * 255.0) as u8]);
+    }
+}
+
+/// Mixes a color over a pixel by its alpha, the way the SDL canvas blends.
+fn blend(pixel: &mut Rgba<u8>, color: Color) {
+    if color.a == 0 {
+        return;
+    }
+    let a = color.a as u32;
+    let [r, g, b, dst_a] = pixel.0;
+    let mix = |src: u8, dst: u8| ((src as u32 * a + dst as u32 * (255 - a)) / 255) as u8;
+    *pixel = Rgba([
+        mix(color.r, r),
+        mix(color.g, g),
+        mix(color.b, b),
+        (a + dst_a as u32 * (255 - a) / 255) as u8,
+    ]);
+}
+
+fn blend_at(target: &mut RgbaImage, x: i32, y: i32, color: Color) {
+    if x < 0 || y < 0 || x >= target.width() as i32 || y >= target.height() as i32 {
+        return;
+    }
+    blend(target.get_pixel_mut(x as u32, y as u32), color);
+}
+
+/// Blends a layer over the target, with its colors multiplied by tint, left of the right edge.
+fn composite(target: &mut RgbaImage, layer: &RgbaImage, tint: Color, right: u32) {
+    let width = right.min(target.width()).min(layer.width());
+    let height = target.height().min(layer.height());
+    for y in 0..height {
+        for x in 0..width {
+            let [r, g, b, a] = layer.get_pixel(x, y).0;
+            let color = Color { r, g, b, a }.multiply(tint);
+            blend(target.get_pixel_mut(x, y), color);
+        }
+    }
+}
+
+/// A sprite's pixel, tinted and then flashed, or None if it's outside its texture.
+fn shade(texture: &RgbaImage, x: i32, y: i32, tint: Color, flash: Color) -> Option<Color> {
+    if x < 0 || y < 0 || x >= texture.width() as i32 || y >= texture.height() as i32 {
+        return None;
+    }
+    let [r, g, b, a] = texture.get_pixel(x as u32, y as u32).0;
+    let color = Color { r, g, b, a }.multiply(tint);
+    let flashed = color.lerp(flash, flash.a as f32 / 255.0);
+    Some(Color {
+        a: color.a,
+        ..flashed
+    })
+}
+
+fn fill_spans(target: &mut RgbaImage, spans: Vec<Rect<i32>>, color_at: impl Fn(f32, f32) -> Color) {
+    for span in spans {
+        for x in span.x..span.right() {
+            let color = color_at(x as f32 + 0.5, span.y as f32 + 0.5);
+            blend_at(target, x, span.y, color);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_sprite(
+    target: &mut RgbaImage,
+    texture: &RgbaImage,
+    sprite: &Sprite,
+    source: Rect<i32>,
+    destination: Rect<i32>,
+    reversed: bool,
+    tint: Color,
+    flash: Color,
+) {
+    if destination.w <= 0 || destination.h <= 0 {
+        return;
+    }
+    let (width, height) = (target.width() as i32, target.height() as i32);
+    for y in destination.y.max(0)..destination.bottom().min(height) {
+        let v = (y - destination.y) * source.h / destination.h;
+        for x in destination.x.max(0)..destination.right().min(width) {
+            let mut u = (x - destination.x) * source.w / destination.w;
+            if reversed {
+                u = source.w - 1 - u;
+            }
+            let texel = shade(
+                texture,
+                sprite.area.x + source.x + u,
+                sprite.area.y + source.y + v,
+                tint,
+                flash,
+            );
+            if let Some(color) = texel {
+                blend_at(target, x, y, color);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_transformed_sprite(
+    target: &mut RgbaImage,
+    texture: &RgbaImage,
+    sprite: &Sprite,
+    source: Rect<i32>,
+    destination: Rect<i32>,
+    transform: &SpriteTransform,
+    tint: Color,
+    flash: Color,
+) {
+    let (w, h) = (destination.w as f32, destination.h as f32);
+    if w <= 0.0 || h <= 0.0 || transform.scale.x == 0.0 || transform.scale.y == 0.0 {
+        return;
+    }
+    let corners = transform.corners(destination);
+    let left = corners.iter().map(|p| p.x).fold(f32::MAX, f32::min).floor() as i32;
+    let right = corners.iter().map(|p| p.x).fold(f32::MIN, f32::max).ceil() as i32;
+    let top = corners.iter().map(|p| p.y).fold(f32::MAX, f32::min).floor() as i32;
+    let bottom = corners.iter().map(|p| p.y).fold(f32::MIN, f32::max).ceil() as i32;
+    let pivot = Point::new(destination.x as f32, destination.y as f32) + transform.pivot;
+    let (sin, cos) = transform.angle.sin_cos();
+    for y in top.max(0)..bottom.min(target.height() as i32) {
+        for x in left.max(0)..right.min(target.width() as i32) {
+            // Undo the turn and the stretch to find where in the destination the pixel came from.
+            let dx = x as f32 + 0.5 - pivot.x;
+            let dy = y as f32 + 0.5 - pivot.y;
+            let local_x = (dx * cos + dy * sin) / transform.scale.x + transform.pivot.x;
+            let local_y = (dy * cos - dx * sin) / transform.scale.y + transform.pivot.y;
+            if local_x < 0.0 || local_y < 0.0 || local_x >= w || local_y >= h {
+                continue;
+            }
+            let mut u = (local_x / w * source.w as f32) as i32;
+            let mut v = (local_y / h * source.h as f32) as i32;
+            if transform.flip_horizontal {
+                u = source.w - 1 - u;
+            }
+            if transform.flip_vertical {
+                v = source.h - 1 - v;
+            }
+            let texel = shade(
+                texture,
+                sprite.area.x + source.x + u,
+                sprite.area.y + source.y + v,
+                tint,
+                flash,
+            );
+            if let Some(color) = texel {
+                blend_at(target, x, y, color);
+            }
+        }
+    }
+}
+
+fn draw_batch(target: &mut RgbaImage, textures: &HashMap<usize, RgbaImage>, batch: &SpriteBatch) {
+    let clear = batch.clear_color;
+    for pixel in target.pixels_mut() {
+        *pixel = Rgba([clear.r, clear.g, clear.b, clear.a]);
+    }
+
+    for entry in batch.entries.iter() {
+        match entry {
+            SpriteBatchEntry::Sprite {
+                sprite,
+                source,
+                destination,
+                reversed,
+                tint,
+                flash,
+            } => {
+                let Some(texture) = textures.get(&sprite.id) else {
+                    error!("unknown texture for sprite: {}", sprite.id);
+                    continue;
+                };
+                draw_sprite(
+                    target,
+                    texture,
+                    sprite,
+                    *source,
+                    *destination,
+                    *reversed,
+                    *tint,
+                    *flash,
+                );
+            }
+            SpriteBatchEntry::TransformedSprite {
+                sprite,
+                source,
+                destination,
+                transform,
+                tint,
+                flash,
+            } => {
+                let Some(texture) = textures.get(&sprite.id) else {
+                    error!("unknown texture for sprite: {}", sprite.id);
+                    continue;
+                };
+                draw_transformed_sprite(
+                    target,
+                    texture,
+                    sprite,
+                    *source,
+                    *destination,
+                    transform,
+                    *tint,
+                    *flash,
+                );
+            }
+            SpriteBatchEntry::FillRect { destination, color } => {
+                for y in destination.y..destination.bottom() {
+                    for x in destination.x..destination.right() {
+                        blend_at(target, x, y, *color);
+                    }
+                }
+            }
+            SpriteBatchEntry::FillTriangle { p1, p2, p3, color } => {
+                let points = [p1, p2, p3].map(|p| Point::new(p.x as f32, p.y as f32));
+                fill_spans(target, convex_spans(&points), |_, _| *color);
+            }
+            SpriteBatchEntry::FillGradientRect {
+                destination,
+                colors,
+            } => {
+                let [top_left, top_right, bottom_right, bottom_left] = *colors;
+                let rect = *destination;
+                for y in rect.y..rect.bottom() {
+                    let ty = (y as f32 + 0.5 - rect.y as f32) / rect.h.max(1) as f32;
+                    for x in rect.x..rect.right() {
+                        let tx = (x as f32 + 0.5 - rect.x as f32) / rect.w.max(1) as f32;
+                        let top = top_left.lerp(top_right, tx);
+                        let bottom = bottom_left.lerp(bottom_right, tx);
+                        blend_at(target, x, y, top.lerp(bottom, ty));
+                    }
+                }
+            }
+            SpriteBatchEntry::FillGradientTriangle { p1, p2, p3, colors } => {
+                let points = [p1, p2, p3].map(|p| Point::new(p.x as f32, p.y as f32));
+                fill_spans(target, convex_spans(&points), |x, y| {
+                    triangle_color(&points, colors, x, y)
+                });
+            }
+            SpriteBatchEntry::Line {
+                start,
+                end,
+                color,
+                width,
+                cap,
+            } => {
+                let points = line_quad(*start, *end, *width, *cap);
+                fill_spans(target, convex_spans(&points), |_, _| *color);
+            }
+        }
+    }
+}
+
+/// Checks that there are width * height RGBA pixels.
+fn check_pixels(what: &str, width: u32, height: u32, pixels: &[u8]) -> Result<()> {
+    let expected = (width * height * 4) as usize;
+    if width == 0 || height == 0 || pixels.len() != expected {
+        bail!(
+            "invalid {}x{} {} with {} bytes",
+            width,
+            height,
+            what,
+            pixels.len()
+        );
+    }
+    Ok(())
+}
+
+impl Renderer for SoftwareRenderer {
+    /// Sprites are cut from the atlas, so this is the first atlas.
+    fn load_sprite(&mut self, _path: &Path) -> Result<Sprite> {
+        let id = self.atlas_ids.first().copied().unwrap_or_default();
+        let (w, h) = self.textures.get(&id).map_or((0, 0), |atlas| {
+            (atlas.width() as i32, atlas.height() as i32)
+        });
+        Ok(Sprite {
+            id,
+            area: Rect { x: 0, y: 0, w, h },
+        })
+    }
+
+    fn load_texture_atlas(&mut self, path: &Path, files: &FileManager) -> Result<Sprite> {
+        info!("Reading texture atlas from {:?}", path);
+        let bytes = files.read(path)?;
+        let img = image::load_from_memory(&bytes)
+            .map_err(|e| anyhow!("unable to load image from {:?}: {}", path, e))?
+            .to_rgba8();
+        self.set_texture_atlas(img.width(), img.height(), &img)
+    }
+
+    fn create_texture_atlas(&mut self, width: u32, height: u32, pixels: &[u8]) -> Result<Sprite> {
+        self.set_texture_atlas(width, height, pixels)
+    }
+
+    fn add_texture_atlas(&mut self, width: u32, height: u32, pixels: &[u8]) -> Result<Sprite> {
+        check_pixels("texture atlas", width, height, pixels)?;
+        let texture = RgbaImage::from_raw(width, height, pixels.to_vec())
+            .ok_or_else(|| anyhow!("invalid {}x{} texture atlas", width, height))?;
+        let sprite = self.add_texture(texture);
+        self.atlas_ids.push(sprite.id);
+        Ok(sprite)
+    }
+
+    fn create_dynamic_texture(&mut self, width: u32, height: u32) -> Result<Sprite> {
+        if width == 0 || height == 0 {
+            bail!("invalid dynamic texture size: {}x{}", width, height);
+        }
+        Ok(self.add_texture(RgbaImage::new(width, height)))
+    }
+
+    fn update_dynamic_texture(&mut self, sprite: &Sprite, pixels: &[u8]) -> Result<()> {
+        let texture = self
+            .textures
+            .get_mut(&sprite.id)
+            .filter(|_| !self.atlas_ids.contains(&sprite.id))
+            .ok_or_else(|| anyhow!("not a dynamic texture: {}", sprite.id))?;
+        let expected = (texture.width() * texture.height() * 4) as usize;
+        if pixels.len() != expected {
+            bail!(
+                "dynamic texture {} needs {} bytes, but got {}",
+                sprite.id,
+                expected,
+                pixels.len()
+            );
+        }
+        texture.copy_from_slice(pixels);
+        Ok(())
+    }
+
+    fn update_texture_atlas(&mut self, sprite: &Sprite, pixels: &[u8]) -> Result<()> {
+        if !self.atlas_ids.contains(&sprite.id) {
+            bail!("not a texture atlas: {}", sprite.id);
+        }
+        let area = sprite.area;
+        let expected = (area.w.max(0) * area.h.max(0) * 4) as usize;
+        if pixels.len() != expected {
+            bail!(
+                "atlas region {:?} needs {} bytes, but got {}",
+                area,
+                expected,
+                pixels.len()
+            );
+        }
+        let texture = self
+            .textures
+            .get_mut(&sprite.id)
+            .ok_or_else(|| anyhow!("no texture atlas has been loaded"))?;
+        for (i, pixel) in pixels.chunks_exact(4).enumerate() {
+            let x = area.x + i as i32 % area.w;
+            let y = area.y + i as i32 / area.w;
+            if x >= 0 && y >= 0 && x < texture.width() as i32 && y < texture.height() as i32 {
+                texture.put_pixel(
+                    x as u32,
+                    y as u32,
+                    Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Frames are always as big as the context, so there's nothing to resize.
+    fn resize(&mut self, _width: u32, _height: u32) {}
+
+    fn render(&mut self, context: &RenderContext) -> Result<()> {
+        let (width, height) = (context.width, context.height);
+        let outgoing = context.visible_outgoing();
+        if let Some((_, outgoing)) = outgoing {
+            let is_dark = self.draw_layers(outgoing);
+            let mut frame = RgbaImage::from_pixel(width, height, OPAQUE_BLACK);
+            self.draw_scene(&mut frame, outgoing, is_dark, width);
+            composite(&mut frame, &self.overlay_layer, Color::WHITE, width);
+            self.outgoing_frame = frame;
+        }
+
+        let is_dark = self.draw_layers(context);
+        let mut frame = RgbaImage::from_pixel(width, height, OPAQUE_BLACK);
+        match context.transition {
+            Some(transition) => {
+                self.draw_transition(&mut frame, context, transition, outgoing.is_some(), is_dark)
+            }
+            None => self.draw_scene(&mut frame, context, is_dark, width),
+        }
+        composite(&mut frame, &self.overlay_layer, Color::WHITE, width);
+        if self.capturing {
+            self.captured_frame = Some(frame.clone());
+        }
+        self.frame = frame;
+        Ok(())
+    }
+
+    fn set_capturing(&mut self, capturing: bool) {
+        self.capturing = capturing;
+        if !capturing {
+            self.captured_frame = None;
+        }
+    }
+
+    fn take_captured_frame(&mut self) -> Option<RgbaImage> {
+        self.captured_frame.take()
+    }
+
+    fn stats(&self) -> RendererStats {
+        RendererStats {
+            texture_bytes: self
+                .textures
+                .values()
+                .map(|texture| texture.len() as u64)
+                .sum(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::imagemanager::ImageManager;
+    use crate::rendercontext::RenderLayer;
+
+    const RED: Color = Color {
+        r: 255,
+        g: 0,
+        b: 0,
+        a: 255,
+    };
+    const BLUE: Color = Color {
+        r: 0,
+        g: 0,
+        b: 255,
+        a: 255,
+    };
+
+    fn pixel(renderer: &SoftwareRenderer, x: u32, y: u32) -> [u8; 4] {
+        renderer.frame().get_pixel(x, y).0
+    }
+
+    #[test]
+    fn sprites_and_shapes() {
+        let mut renderer = SoftwareRenderer::new();
+        let atlas = renderer
+            .create_texture_atlas(2, 1, &[255, 0, 0, 255, 0, 0, 255, 255])
+            .unwrap();
+        let mut context = RenderContext::new(8, 4, 0).unwrap();
+        context.clear();
+        let dest = Rect {
+            x: 0,
+            y: 0,
+            w: 4,
+            h: 2,
+        };
+        context.draw_reversed(atlas, RenderLayer::Player, dest, atlas.area);
+        context.set_tint(RenderLayer::Player, Color { r: 128, ..BLUE });
+        context.draw(
+            atlas,
+            RenderLayer::Player,
+            Rect { y: 2, ..dest },
+            atlas.area,
+        );
+        let half_white = Color {
+            a: 128,
+            ..Color::WHITE
+        };
+        let square = Rect {
+            x: 6,
+            y: 0,
+            w: 2,
+            h: 2,
+        };
+        context.fill_rect(square, RenderLayer::Hud, half_white);
+        renderer.render(&context).unwrap();
+
+        assert_eq!(renderer.frame().dimensions(), (8, 4));
+        // Reversed, so the blue half is on the left.
+        assert_eq!(pixel(&renderer, 0, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(&renderer, 3, 1), [255, 0, 0, 255]);
+        // Tinted.
+        assert_eq!(pixel(&renderer, 0, 2), [128, 0, 0, 255]);
+        assert_eq!(pixel(&renderer, 3, 3), [0, 0, 255, 255]);
+        // The HUD is blended over the black behind the player layer.
+        assert_eq!(pixel(&renderer, 4, 0), [0, 0, 0, 255]);
+        let [r, g, b, a] = pixel(&renderer, 6, 0);
+        assert!(r == g && g == b && (60..=68).contains(&r) && a == 255);
+    }
+
+    #[test]
+    fn wipe() {
+        let mut renderer = SoftwareRenderer::new();
+        let mut outgoing = RenderContext::new(8, 2, 0).unwrap();
+        outgoing.player_batch.clear_color = RED;
+        let mut context = RenderContext::new(8, 2, 1).unwrap();
+        context.player_batch.clear_color = BLUE;
+        context.outgoing = Some(Box::new(outgoing));
+        context.transition = Some(TransitionFrame {
+            kind: TransitionKind::Wipe,
+            progress: 0.25,
+        });
+        renderer.set_capturing(true);
+        renderer.render(&context).unwrap();
+
+        let frame = renderer.take_captured_frame().unwrap();
+        let row: Vec<[u8; 4]> = (0..8).map(|x| frame.get_pixel(x, 1).0).collect();
+        assert_eq!(row[..2], [[0, 0, 255, 255]; 2]);
+        assert_eq!(row[2..], [[255, 0, 0, 255]; 6]);
+        assert!(renderer.take_captured_frame().is_none());
+    }
+
+    #[test]
+    fn font() {
+        let mut map = HashMap::new();
+        map.insert(
+            PathBuf::from("assets/8bitfont.tsx"),
+            include_bytes!("../../assets/8bitfont.tsx").to_vec(),
+        );
+        map.insert(
+            PathBuf::from("assets/8bitfont.png"),
+            include_bytes!("../../assets/8bitfont.png").to_vec(),
+        );
+        let files = FileManager::from_memory(map).unwrap();
+        let mut images = ImageManager::new(SoftwareRenderer::new()).unwrap();
+        images
+            .pack_texture_atlas(Path::new("assets"), &files)
+            .unwrap();
+        let font = images.load_font(&files).unwrap();
+
+        let mut context = RenderContext::new(64, 64, 0).unwrap();
+        context.clear();
+        font.draw_string(&mut context, RenderLayer::Player, Point::new(0, 0), "A");
+        images.render(&context).unwrap();
+
+        // Every pixel of the letter is copied from the font, scaled up, over black.
+        let (sprite, source) = font.glyph('A');
+        let renderer = images.renderer();
+        let atlas = &renderer.textures[&sprite.id];
+        let mut lit = 0;
+        for (x, y, pixel) in renderer.frame().enumerate_pixels() {
+            let [r, g, b, a] = atlas
+                .get_pixel(
+                    (sprite.area.x + source.x) as u32 + x * source.w as u32 / 64,
+                    (sprite.area.y + source.y) as u32 + y * source.h as u32 / 64,
+                )
+                .0;
+            let expected = match a {
+                0 => [0, 0, 0, 255],
+                255 => [r, g, b, 255],
+                _ => continue,
+            };
+            assert_eq!(pixel.0, expected, "at ({}, {})", x, y);
+            if a == 255 && (r, g, b) != (0, 0, 0) {
+                lit += 1;
+            }
+        }
+        assert!(lit > 0);
+    }
+}
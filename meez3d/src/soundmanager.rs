@@ -1,28 +1,245 @@
-#[cfg(feature = "sdl2")]
-use anyhow::Result;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
 
+use anyhow::{anyhow, bail, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::filemanager::{DirEntryType, FileManager};
+
+/// The data file listing every sound the game can play.
+pub const SOUND_MANIFEST_PATH: &str = "assets/sounds.json";
+
+/// The cvar for how loud every sound is, from 0 for muted to 1, the default, for full volume.
+pub const VOLUME_CVAR: &str = "volume";
+
+/// How far away a sound in the world can be heard, in tiles.
+const HEARING_DISTANCE: f32 = 24.0;
+
+/// The sounds the engine plays itself. Each one plays whatever sound is registered under its
+/// name.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Sound {
-    Click = 0,
+    Click,
+    Thunder,
+}
+
+impl Sound {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sound::Click => "click",
+            Sound::Thunder => "thunder",
+        }
+    }
+}
+
+impl FromStr for Sound {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "click" => Sound::Click,
+            "thunder" => Sound::Thunder,
+            _ => bail!("invalid sound: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for Sound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A sound registered with a SoundManager.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SoundHandle(usize);
+
+impl SoundHandle {
+    /// Handles are given out in order, starting from 0.
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+/// The kind of file a sound is loaded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundFormat {
+    Wav,
+    Ogg,
+}
+
+impl SoundFormat {
+    /// The format of a sound file, from its extension.
+    pub fn from_path(path: &Path) -> Result<SoundFormat> {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        Ok(match extension.as_deref() {
+            Some("wav") => SoundFormat::Wav,
+            Some("ogg") => SoundFormat::Ogg,
+            _ => bail!("invalid sound format: {}", path.display()),
+        })
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            SoundFormat::Wav => "audio/wav",
+            SoundFormat::Ogg => "audio/ogg",
+        }
+    }
+}
+
+fn full_volume() -> f32 {
+    1.0
+}
+
+/// Where a sound in the manifest is loaded from, and how it's played.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SoundSettings {
+    pub path: String,
+    /// How loud the sound is, from 0 to 1. Every time it's played, it's scaled by this.
+    #[serde(default = "full_volume")]
+    pub volume: f32,
+    /// Whether the sound repeats until it's stopped.
+    #[serde(default)]
+    pub looping: bool,
+}
+
+impl SoundSettings {
+    pub fn new(path: &str) -> SoundSettings {
+        SoundSettings {
+            path: path.to_string(),
+            volume: full_volume(),
+            looping: false,
+        }
+    }
+}
+
+/// A sound file, read and ready for a sound player to decode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoundClip {
+    pub name: String,
+    pub format: SoundFormat,
+    pub data: Vec<u8>,
+    pub looping: bool,
 }
 
 pub trait SoundPlayer {
-    fn play(&mut self, sound: Sound);
+    /// Loads a sound so it can be played with the given handle. Loading a handle that's already
+    /// loaded replaces its sound.
+    fn load(&mut self, handle: SoundHandle, clip: &SoundClip) -> Result<()>;
+
+    /// Plays a sound at a volume from 0 to 1, panned from -1 for all the way left to 1 for all
+    /// the way right.
+    fn play(&mut self, handle: SoundHandle, volume: f32, pan: f32);
+
+    /// Stops every copy of a sound that's playing, which is the only way looping sounds end.
+    fn stop(&mut self, handle: SoundHandle);
+
+    /// Muffles everything that's playing, like it's heard from underwater, or stops muffling it.
+    fn set_muffled(&mut self, _muffled: bool) {}
 }
 
 pub struct NoopSoundPlayer {}
 
 impl SoundPlayer for NoopSoundPlayer {
-    fn play(&mut self, _sound: Sound) {}
+    fn load(&mut self, _handle: SoundHandle, _clip: &SoundClip) -> Result<()> {
+        Ok(())
+    }
+
+    fn play(&mut self, _handle: SoundHandle, _volume: f32, _pan: f32) {}
+
+    fn stop(&mut self, _handle: SoundHandle) {}
+}
+
+/// A sound player that remembers the names of the sounds it was asked to play, for tests.
+#[derive(Default)]
+pub struct RecordingSoundPlayer {
+    names: Vec<String>,
+    played: Rc<RefCell<Vec<String>>>,
+}
+
+impl RecordingSoundPlayer {
+    pub fn new() -> RecordingSoundPlayer {
+        RecordingSoundPlayer::default()
+    }
+
+    /// Returns a handle to the list of played sounds, which stays valid after the player is boxed.
+    pub fn played(&self) -> Rc<RefCell<Vec<String>>> {
+        self.played.clone()
+    }
+}
+
+impl SoundPlayer for RecordingSoundPlayer {
+    fn load(&mut self, handle: SoundHandle, clip: &SoundClip) -> Result<()> {
+        if self.names.len() <= handle.0 {
+            self.names.resize(handle.0 + 1, String::new());
+        }
+        self.names[handle.0] = clip.name.clone();
+        Ok(())
+    }
+
+    fn play(&mut self, handle: SoundHandle, _volume: f32, _pan: f32) {
+        if let Some(name) = self.names.get(handle.0) {
+            self.played.borrow_mut().push(name.clone());
+        }
+    }
+
+    fn stop(&mut self, _handle: SoundHandle) {}
+}
+
+/// Where sounds in the world are heard from.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Listener {
+    /// The position, in tiles.
+    pub x: f32,
+    pub y: f32,
+    /// The direction the listener is facing, with 0 being right and positive being clockwise,
+    /// in radians.
+    pub angle: f32,
+}
+
+impl Listener {
+    /// How loud a sound at a position is, and how far it's panned, as passed to a sound player.
+    /// Sounds get quieter the farther away they are, and are panned toward the side they're on.
+    pub fn hear(&self, x: f32, y: f32) -> (f32, f32) {
+        let (dx, dy) = (x - self.x, y - self.y);
+        let distance = (dx * dx + dy * dy).sqrt();
+        let volume = (1.0 - distance / HEARING_DISTANCE).clamp(0.0, 1.0);
+        if distance < 0.001 {
+            return (volume, 0.0);
+        }
+        let pan = (dy.atan2(dx) - self.angle).sin();
+        (volume, pan)
+    }
 }
 
 pub struct SoundManager {
     internal: Box<dyn SoundPlayer>,
+    listener: Listener,
+    muffled: bool,
+    /// Multiplies every sound's volume, from the volume cvar.
+    master_volume: f32,
+    /// The volume of each registered sound, indexed by handle.
+    volumes: Vec<f32>,
+    handles: HashMap<String, SoundHandle>,
 }
 
 impl SoundManager {
     pub fn with_internal(internal: Box<dyn SoundPlayer>) -> SoundManager {
-        Self { internal }
+        Self {
+            internal,
+            listener: Listener::default(),
+            muffled: false,
+            master_volume: 1.0,
+            volumes: Vec::new(),
+            handles: HashMap::new(),
+        }
     }
 
     pub fn noop_manager() -> SoundManager {
@@ -36,7 +253,258 @@ impl SoundManager {
         )))
     }
 
+    /// Loads a sound file and registers it under a name. A name that's already registered keeps
+    /// its handle, with the new sound replacing the old one.
+    pub fn register(
+        &mut self,
+        name: &str,
+        settings: &SoundSettings,
+        files: &FileManager,
+    ) -> Result<SoundHandle> {
+        let path = Path::new(&settings.path);
+        let clip = SoundClip {
+            name: name.to_string(),
+            format: SoundFormat::from_path(path)?,
+            data: files.read(path)?,
+            looping: settings.looping,
+        };
+        let handle = match self.handles.get(name) {
+            Some(handle) => *handle,
+            None => SoundHandle(self.volumes.len()),
+        };
+        self.internal
+            .load(handle, &clip)
+            .map_err(|e| anyhow!("unable to load sound {}: {}", name, e))?;
+        let volume = settings.volume.clamp(0.0, 1.0);
+        if handle.0 < self.volumes.len() {
+            self.volumes[handle.0] = volume;
+        } else {
+            self.volumes.push(volume);
+            self.handles.insert(name.to_string(), handle);
+        }
+        Ok(handle)
+    }
+
+    /// Registers every sound in a manifest, which maps names to their settings, like
+    /// {"click": {"path": "assets/sounds/click.wav", "volume": 0.5}}.
+    ///
+    /// A bad entry is logged and skipped, so one broken sound doesn't silence the rest.
+    pub fn load_manifest(&mut self, files: &FileManager, path: &Path) -> Result<Vec<SoundHandle>> {
+        let text = files.read_to_string(path)?;
+        let manifest: BTreeMap<String, serde_json::Value> = serde_json::from_str(&text)
+            .map_err(|e| anyhow!("unable to deserialize sound manifest: {}", e))?;
+        let mut handles = Vec::new();
+        for (name, settings) in manifest {
+            let registered = serde_json::from_value(settings)
+                .map_err(|e| anyhow!("invalid settings: {}", e))
+                .and_then(|settings| self.register(&name, &settings, files));
+            match registered {
+                Ok(handle) => handles.push(handle),
+                Err(e) => warn!("skipping sound {:?} in {:?}: {}", name, path, e),
+            }
+        }
+        Ok(handles)
+    }
+
+    /// Registers every wav or ogg file in a directory under its name without the extension, at
+    /// full volume and without looping.
+    pub fn load_directory(&mut self, files: &FileManager, dir: &Path) -> Result<Vec<SoundHandle>> {
+        let mut entries = files.read_dir(dir)?;
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut handles = Vec::new();
+        for entry in entries {
+            if !matches!(entry.file_type, DirEntryType::File)
+                || SoundFormat::from_path(&entry.full_path).is_err()
+            {
+                continue;
+            }
+            let Some(name) = entry.full_path.file_stem() else {
+                continue;
+            };
+            let settings = SoundSettings::new(&entry.full_path.to_string_lossy());
+            handles.push(self.register(&name.to_string_lossy(), &settings, files)?);
+        }
+        Ok(handles)
+    }
+
+    /// The sound registered under a name, if there is one.
+    pub fn handle(&self, name: &str) -> Option<SoundHandle> {
+        self.handles.get(name).copied()
+    }
+
+    /// Plays one of the engine's own sounds, if a sound with its name has been registered.
     pub fn play(&mut self, sound: Sound) {
-        self.internal.play(sound)
+        match self.handle(sound.name()) {
+            Some(handle) => self.play_sound(handle),
+            None => debug!("sound not registered: {}", sound),
+        }
+    }
+
+    /// Plays a registered sound at its full volume, right in the middle.
+    pub fn play_sound(&mut self, handle: SoundHandle) {
+        self.play_panned(handle, 1.0, 0.0);
+    }
+
+    pub fn stop(&mut self, handle: SoundHandle) {
+        self.internal.stop(handle);
+    }
+
+    fn play_panned(&mut self, handle: SoundHandle, volume: f32, pan: f32) {
+        let Some(sound_volume) = self.volumes.get(handle.0) else {
+            return;
+        };
+        let volume = volume * sound_volume * self.master_volume;
+        if volume <= 0.0 {
+            return;
+        }
+        self.internal.play(handle, volume, pan);
+    }
+
+    /// Sets how loud every sound is, from 0 to 1.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.master_volume = if volume.is_finite() {
+            volume.clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.master_volume
+    }
+
+    /// Moves where sounds played with play_at are heard from.
+    pub fn set_listener(&mut self, listener: Listener) {
+        self.listener = listener;
+    }
+
+    pub fn listener(&self) -> Listener {
+        self.listener
+    }
+
+    /// Muffles every sound, or stops muffling them. The player is only told when it changes.
+    pub fn set_muffled(&mut self, muffled: bool) {
+        if muffled != self.muffled {
+            self.muffled = muffled;
+            self.internal.set_muffled(muffled);
+        }
+    }
+
+    pub fn is_muffled(&self) -> bool {
+        self.muffled
+    }
+
+    /// Plays one of the engine's own sounds at a position in the world, in tiles, as the
+    /// listener would hear it.
+    pub fn play_at(&mut self, sound: Sound, x: f32, y: f32) {
+        if let Some(handle) = self.handle(sound.name()) {
+            self.play_sound_at(handle, x, y);
+        }
+    }
+
+    /// Plays a registered sound at a position in the world, in tiles, as the listener would
+    /// hear it.
+    pub fn play_sound_at(&mut self, handle: SoundHandle, x: f32, y: f32) {
+        let (volume, pan) = self.listener.hear(x, y);
+        if volume <= 0.0 {
+            return;
+        }
+        self.play_panned(handle, volume, pan);
+    }
+}
+
+/// A sound manager with every engine sound registered, that records the names of the sounds it
+/// plays, for tests.
+#[cfg(test)]
+pub fn recording_sound_manager() -> (SoundManager, Rc<RefCell<Vec<String>>>) {
+    let mut map = HashMap::new();
+    map.insert(
+        std::path::PathBuf::from(SOUND_MANIFEST_PATH),
+        br#"{"click": {"path": "click.wav"}, "thunder": {"path": "thunder.wav"},
+            "broken": {"volume": 0.5}, "text": {"path": "notes.txt"}}"#
+            .to_vec(),
+    );
+    map.insert("click.wav".into(), Vec::new());
+    map.insert("thunder.wav".into(), Vec::new());
+    let files = FileManager::from_memory(map).unwrap();
+    let player = RecordingSoundPlayer::new();
+    let played = player.played();
+    let mut sounds = SoundManager::with_internal(Box::new(player));
+    sounds
+        .load_manifest(&files, Path::new(SOUND_MANIFEST_PATH))
+        .unwrap();
+    (sounds, played)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    #[test]
+    fn hear() {
+        // Facing down, which is south.
+        let listener = Listener {
+            x: 5.0,
+            y: 5.0,
+            angle: FRAC_PI_2,
+        };
+        let (volume, pan) = listener.hear(5.0, 5.0);
+        assert_eq!((volume, pan), (1.0, 0.0));
+        let (ahead, pan) = listener.hear(5.0, 8.0);
+        assert!(pan.abs() < 0.01);
+        // East is on the listener's left.
+        let (_, pan) = listener.hear(8.0, 5.0);
+        assert!((pan + 1.0).abs() < 0.01);
+        let (_, pan) = listener.hear(2.0, 5.0);
+        assert!((pan - 1.0).abs() < 0.01);
+        let (farther, _) = listener.hear(5.0, 12.0);
+        assert!(ahead > farther && farther > 0.0);
+        assert_eq!(listener.hear(5.0, 40.0).0, 0.0);
+
+        let (mut sounds, played) = recording_sound_manager();
+        sounds.set_listener(listener);
+        sounds.play_at(Sound::Thunder, 6.0, 5.0);
+        sounds.play_at(Sound::Click, 5.0, 40.0);
+        assert_eq!(*played.borrow(), vec!["thunder"]);
+    }
+
+    #[test]
+    fn register() {
+        let (mut sounds, played) = recording_sound_manager();
+        let click = sounds.handle("click").unwrap();
+        let thunder = sounds.handle("thunder").unwrap();
+        assert_ne!(click, thunder);
+        assert_eq!(sounds.handle("explosion"), None);
+        // Bad entries in the manifest are skipped.
+        assert_eq!(sounds.handle("broken"), None);
+        assert_eq!(sounds.handle("text"), None);
+
+        let mut map = HashMap::new();
+        map.insert("sounds/explosion.ogg".into(), Vec::new());
+        map.insert("sounds/click.wav".into(), Vec::new());
+        map.insert("sounds/notes.txt".into(), Vec::new());
+        let files = FileManager::from_memory(map).unwrap();
+        let handles = sounds.load_directory(&files, Path::new("sounds")).unwrap();
+        // Click was already registered, so it keeps its handle.
+        let explosion = sounds.handle("explosion").unwrap();
+        assert_eq!(handles, vec![click, explosion]);
+        assert_eq!(explosion.index(), 2);
+
+        sounds.play(Sound::Click);
+        sounds.play_sound(explosion);
+        assert_eq!(*played.borrow(), vec!["click", "explosion"]);
+        // Nothing plays while it's muted.
+        sounds.set_volume(-1.0);
+        assert_eq!(sounds.volume(), 0.0);
+        sounds.play(Sound::Click);
+        assert_eq!(played.borrow().len(), 2);
+
+        let settings = SoundSettings::new("sounds/notes.txt");
+        assert!(sounds.register("notes", &settings, &files).is_err());
+        let settings: SoundSettings =
+            serde_json::from_str(r#"{"path": "a.wav", "looping": true}"#).unwrap();
+        assert_eq!((settings.volume, settings.looping), (1.0, true));
     }
 }
@@ -0,0 +1,346 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use crate::billboard::Billboard;
+use crate::constants::FRAME_RATE;
+use crate::enemystats::DEFAULT_ENEMY_TYPE;
+use crate::faction::Faction;
+use crate::properties::PropertyMap;
+
+/// What a spawner makes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntityKind {
+    /// Something to shoot, which aim assist aims at and rockets destroy.
+    Enemy,
+    /// Something that just stands there.
+    Decoration,
+}
+
+impl FromStr for EntityKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "enemy" => EntityKind::Enemy,
+            "decoration" => EntityKind::Decoration,
+            _ => bail!("invalid entity kind: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for EntityKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EntityKind::Enemy => "enemy",
+            EntityKind::Decoration => "decoration",
+        })
+    }
+}
+
+/// When a spawner starts spawning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpawnTrigger {
+    /// As soon as the level starts.
+    #[default]
+    Start,
+    /// When the player walks into the spawner's area.
+    Enter,
+}
+
+impl FromStr for SpawnTrigger {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "start" => SpawnTrigger::Start,
+            "enter" => SpawnTrigger::Enter,
+            _ => bail!("invalid spawn trigger: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for SpawnTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SpawnTrigger::Start => "start",
+            SpawnTrigger::Enter => "enter",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnerSettings {
+    pub kind: EntityKind,
+    /// How many to spawn, in each wave if there are waves.
+    pub count: u32,
+    /// How many frames to wait between spawns.
+    pub interval: u32,
+    /// How many of the spawner's entities can be alive at once.
+    pub max_alive: u32,
+    /// How many waves to spawn, each once the last is cleared, or None to just spawn count.
+    /// Spawners with waves lock the level's exits until every wave is cleared.
+    pub waves: Option<u32>,
+    pub trigger: SpawnTrigger,
+}
+
+impl SpawnerSettings {
+    /// The settings for an object with a "spawner" property naming what it spawns, along with
+    /// "spawn_count", "spawn_interval" in seconds, "max_alive", "waves", and "spawn_trigger",
+    /// or None if it's not a spawner.
+    pub fn from_properties(properties: &PropertyMap) -> Result<Option<SpawnerSettings>> {
+        let Some(kind) = properties.get_string("spawner")? else {
+            return Ok(None);
+        };
+        let kind: EntityKind = kind.parse()?;
+        let waves = properties.get_int("waves")?.map(|n| n.max(1) as u32);
+        if kind == EntityKind::Decoration && waves.is_some() {
+            // Decorations can't be destroyed, so a wave of them would never be cleared.
+            bail!("decoration spawners can't have waves");
+        }
+        let count = properties
+            .get_int("spawn_count")?
+            .map_or(1, |n| n.max(1) as u32);
+        Ok(Some(SpawnerSettings {
+            kind,
+            count,
+            interval: properties
+                .get_int("spawn_interval")?
+                .map_or(FRAME_RATE, |n| n.max(0) as u32 * FRAME_RATE),
+            max_alive: properties
+                .get_int("max_alive")?
+                .map_or(count, |n| n.max(1) as u32),
+            waves,
+            trigger: properties
+                .get_string("spawn_trigger")?
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or_default(),
+        }))
+    }
+}
+
+/// Spawns entities into a level, in waves for arena rooms.
+#[derive(Debug, Clone)]
+pub struct Spawner {
+    pub settings: SpawnerSettings,
+    /// What each entity looks like and where it appears.
+    template: Billboard,
+    /// Which type of enemy it spawns, for spawners that spawn enemies.
+    enemy_type: String,
+    /// The area that sets it off, in tiles, as (x, y, w, h).
+    area: (f32, f32, f32, f32),
+    active: bool,
+    /// Which wave it's on, counting from 0.
+    wave: u32,
+    /// How many it's spawned in this wave.
+    spawned: u32,
+    /// How many more frames until it can spawn again.
+    cooldown: u32,
+    done: bool,
+}
+
+impl Spawner {
+    pub fn new(settings: SpawnerSettings, template: Billboard, area: (f32, f32, f32, f32)) -> Self {
+        let template = Billboard {
+            faction: match settings.kind {
+                EntityKind::Enemy => Some(template.faction.unwrap_or(Faction::Monsters)),
+                EntityKind::Decoration => None,
+            },
+            ..template
+        };
+        Spawner {
+            settings,
+            template,
+            enemy_type: DEFAULT_ENEMY_TYPE.to_string(),
+            area,
+            active: false,
+            wave: 0,
+            spawned: 0,
+            cooldown: 0,
+            done: false,
+        }
+    }
+
+    /// The same spawner, spawning a different type of enemy.
+    pub fn with_enemy_type(self, enemy_type: &str) -> Spawner {
+        Spawner {
+            enemy_type: enemy_type.to_string(),
+            ..self
+        }
+    }
+
+    pub fn floor(&self) -> usize {
+        self.template.floor
+    }
+
+    pub fn enemy_type(&self) -> &str {
+        &self.enemy_type
+    }
+
+    /// Starts spawning the first time it's checked, or for spawners that wait for the player to
+    /// walk into their area, once the player on the given floor is at (x, y) inside it. Returns
+    /// true if it just started.
+    pub fn check_trigger(&mut self, floor: usize, x: f32, y: f32) -> bool {
+        if self.active || self.done {
+            return false;
+        }
+        let triggered = match self.settings.trigger {
+            SpawnTrigger::Start => true,
+            SpawnTrigger::Enter => {
+                let (left, top, w, h) = self.area;
+                let inside = x >= left && x < left + w && y >= top && y < top + h;
+                floor == self.template.floor && inside
+            }
+        };
+        self.active = triggered;
+        triggered
+    }
+
+    /// Whether the level's exits are locked until the waves are cleared.
+    pub fn is_locking(&self) -> bool {
+        self.active && !self.done && self.settings.waves.is_some()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Which wave it's on, counting from 1.
+    pub fn wave(&self) -> u32 {
+        self.wave + 1
+    }
+
+    /// Advances the spawner by a frame, given how many of its entities are still alive. Returns
+    /// an entity to add to the level, if it's time for one.
+    pub fn update(&mut self, alive: usize) -> Option<Billboard> {
+        if !self.active || self.done {
+            return None;
+        }
+        self.cooldown = self.cooldown.saturating_sub(1);
+        if self.spawned >= self.settings.count {
+            // The wave is over once everything in it is gone.
+            if alive > 0 {
+                return None;
+            }
+            self.wave += 1;
+            if self.wave >= self.settings.waves.unwrap_or(1) {
+                self.done = true;
+                return None;
+            }
+            self.spawned = 0;
+        }
+        if self.cooldown > 0 || alive >= self.settings.max_alive as usize {
+            return None;
+        }
+        self.spawned += 1;
+        self.cooldown = self.settings.interval;
+        Some(self.template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Rect;
+    use crate::properties::PropertiesXml;
+    use crate::sprite::Sprite;
+    use crate::utils::Color;
+
+    #[test]
+    fn waves() {
+        let area = Rect {
+            x: 0,
+            y: 0,
+            w: 64,
+            h: 64,
+        };
+        let template = Billboard {
+            floor: 0,
+            x: 0.5,
+            y: 1.0,
+            size: 1.0,
+            sprite: Sprite { id: 0, area },
+            source: area,
+            reversed: false,
+            faction: None,
+            spawner: None,
+            tint: Color::WHITE,
+            flash: Color::TRANSPARENT,
+        };
+        let settings = SpawnerSettings {
+            kind: EntityKind::Enemy,
+            count: 3,
+            interval: 2,
+            max_alive: 2,
+            waves: Some(2),
+            trigger: SpawnTrigger::Enter,
+        };
+        let mut spawner = Spawner::new(settings, template, (0.0, 0.0, 1.0, 1.0));
+        assert_eq!(spawner.update(0).map(|b| b.faction), None);
+        assert!(!spawner.check_trigger(0, 2.0, 2.0));
+        assert!(!spawner.check_trigger(1, 0.5, 0.5));
+        assert!(spawner.check_trigger(0, 0.5, 0.5));
+        assert!(spawner.is_locking());
+        assert!(!spawner.check_trigger(0, 0.5, 0.5));
+
+        // Spawns every other frame, but never more than two at once.
+        let mut alive = 0;
+        let mut frames = 0;
+        while spawner.wave() == 1 && frames < 100 {
+            if let Some(billboard) = spawner.update(alive) {
+                assert_eq!(billboard.faction, Some(Faction::Monsters));
+                alive += 1;
+            }
+            assert!(alive <= 2);
+            // Everything dies after two spawns, so the third can come.
+            if alive == 2 || spawner.spawned == 3 {
+                alive = 0;
+            }
+            frames += 1;
+        }
+        assert_eq!(spawner.wave(), 2);
+
+        // The last wave's cleared, and the exits unlock.
+        while !spawner.is_done() && frames < 200 {
+            spawner.update(0);
+            frames += 1;
+        }
+        assert!(spawner.is_done());
+        assert!(!spawner.is_locking());
+
+        // A spawner that starts with the level starts the first time it's checked, wherever the
+        // player is, so the level can tell the player the exits are locked.
+        let start = SpawnerSettings {
+            trigger: SpawnTrigger::Start,
+            ..settings
+        };
+        let mut spawner = Spawner::new(start, template, (0.0, 0.0, 1.0, 1.0));
+        assert!(spawner.check_trigger(1, 5.0, 5.0));
+        assert!(spawner.is_locking());
+        assert!(!spawner.check_trigger(1, 5.0, 5.0));
+    }
+
+    fn settings(properties: &str) -> Result<Option<SpawnerSettings>> {
+        let xml = format!("<properties>{}</properties>", properties);
+        let xml: PropertiesXml = quick_xml::de::from_str(&xml)?;
+        SpawnerSettings::from_properties(&xml.try_into()?)
+    }
+
+    #[test]
+    fn from_properties() {
+        let count = r#"<property name="spawn_count" type="int" value="2"/>"#;
+        assert_eq!(settings(count).unwrap(), None);
+        let decoration = r#"<property name="spawner" value="decoration"/>"#;
+        let spawner = settings(&format!("{decoration}{count}")).unwrap().unwrap();
+        assert_eq!((spawner.kind, spawner.count), (EntityKind::Decoration, 2));
+
+        // Decorations can't be cleared, so waves of them would lock the exits for good.
+        let waves = r#"<property name="waves" type="int" value="2"/>"#;
+        assert!(settings(&format!("{decoration}{waves}")).is_err());
+        let enemy = r#"<property name="spawner" value="enemy"/>"#;
+        let spawner = settings(&format!("{enemy}{waves}")).unwrap().unwrap();
+        assert_eq!(spawner.waves, Some(2));
+    }
+}
@@ -6,7 +6,7 @@ use anyhow::{anyhow, bail, Context, Result};
 
 use crate::filemanager::FileManager;
 use crate::geometry::Rect;
-use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::rendercontext::{RenderContext, RenderLayer, SpriteBatch, SpriteTransform};
 
 #[derive(Clone, Copy, Debug)]
 pub struct Sprite {
@@ -34,7 +34,8 @@ pub struct SpriteSheet {
 impl SpriteSheet {
     pub fn new(sprite: Sprite, sprite_width: i32, sprite_height: i32) -> Result<SpriteSheet> {
         let w = sprite.area.w;
-        let columns = (w / sprite_width) as u32;
+        // Placeholder sprites have no size, but still need a column to index into.
+        let columns = ((w / sprite_width) as u32).max(1);
         Ok(SpriteSheet {
             sprite,
             sprite_width,
@@ -70,6 +71,29 @@ impl SpriteSheet {
             context.draw(self.sprite, layer, dest, source_area);
         }
     }
+
+    /// Draws a one pixel wide column of a sprite, stretched to fill dest, where u goes from 0 at
+    /// the sprite's left edge to 1 at its right. This is what raycasters draw walls with.
+    pub fn blit_column(&self, batch: &mut SpriteBatch, dest: Rect<i32>, index: u32, u: f32) {
+        let mut source_area = self.source_area(index, 0);
+        let column = (u * self.sprite_width as f32) as i32;
+        source_area.x += column.clamp(0, self.sprite_width - 1);
+        source_area.w = 1;
+        batch.draw(self.sprite, dest, source_area, false);
+    }
+
+    /// Draws a single pixel of a sprite, stretched to fill dest, where u and v go from 0 at the
+    /// sprite's top left to 1 at its bottom right. This is what raycasters draw floors with.
+    pub fn blit_texel(&self, batch: &mut SpriteBatch, dest: Rect<i32>, index: u32, u: f32, v: f32) {
+        let mut source_area = self.source_area(index, 0);
+        let column = (u * self.sprite_width as f32) as i32;
+        let row = (v * self.sprite_height as f32) as i32;
+        source_area.x += column.clamp(0, self.sprite_width - 1);
+        source_area.y += row.clamp(0, self.sprite_height - 1);
+        source_area.w = 1;
+        source_area.h = 1;
+        batch.draw(self.sprite, dest, source_area, false);
+    }
 }
 
 pub struct Animation {
@@ -109,6 +133,20 @@ impl Animation {
         self.spritesheet
             .blit(context, layer, dest, index, 0, reverse)
     }
+
+    /// Draws the current frame turned, stretched, or flipped, e.g. for a tile object flipped
+    /// upside down in Tiled.
+    pub fn blit_transformed(
+        &self,
+        context: &mut RenderContext,
+        layer: RenderLayer,
+        dest: Rect<i32>,
+        transform: SpriteTransform,
+    ) {
+        let index = ((context.frame / self.frames_per_frame as u64) % self.frames as u64) as u32;
+        let source_area = self.spritesheet.source_area(index, 0);
+        context.draw_transformed(self.spritesheet.sprite, layer, dest, source_area, transform);
+    }
 }
 
 enum NextFrame {
@@ -224,6 +262,7 @@ pub struct AnimationStateMachine {
 
 impl AnimationStateMachine {
     pub fn from_file(path: &Path, files: &FileManager) -> Result<AnimationStateMachine> {
+        profile_scope!("load_animation_state_machine", path = ?path);
         let s = files.read_to_string(path).map_err(|e| {
             anyhow!(
                 "unable to load animation state machine at {:?}: {}",
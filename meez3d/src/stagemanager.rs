@@ -1,32 +1,106 @@
+use std::hash::Hasher;
 use std::{mem, path::Path};
 
 use anyhow::Result;
+use log::error;
+use rand::rngs::StdRng;
+use rand::{random, Rng, SeedableRng};
 
 use crate::{
+    cvars::Cvars,
+    debugflags::{DebugFlags, DEFAULT_CHEAT_CODES},
+    ending::{EndingRecord, EndingTable},
+    epilogue::Epilogue,
     filemanager::FileManager,
     font::Font,
+    gameclock::GameClock,
+    geometry::Point,
     imagemanager::ImageLoader,
-    inputmanager::InputSnapshot,
-    level::Level,
+    inputmanager::{CheatCodes, InputSnapshot, CHEATS_CVAR},
+    inventory::ItemTable,
+    inventoryscreen::InventoryScreen,
+    keybindings::KeyBindings,
+    leaderboard::{LeaderboardBackend, LocalLeaderboard},
+    leaderboardscreen::LeaderboardScreen,
+    level::{Level, MAP_CVAR},
+    logscreen::LogScreen,
     menu::Menu,
-    rendercontext::RenderContext,
-    scene::{Scene, SceneResult},
+    messagelog::{MessageKind, MessageLog},
+    nameentryscreen::NameEntryScreen,
+    narration::{LogNarrator, Narrator},
+    rendercontext::{RenderContext, RenderLayer},
+    replay::StateHasher,
+    safeareascreen::SafeAreaScreen,
+    savestate::LevelState,
+    scene::{EntityInfo, Scene, SceneResult},
     soundmanager::SoundManager,
+    transition::Transition,
+    tutorial::Tutorial,
+    utils::Color,
+};
+
+const WATERMARK_MARGIN: i32 = 8;
+const WATERMARK_COLOR: Color = Color {
+    r: 0xff,
+    g: 0x40,
+    b: 0x40,
+    a: 0xc0,
 };
 
 pub struct StageManager {
     current: Box<dyn Scene>,
     stack: Vec<Box<dyn Scene>>,
+    cvars: Cvars,
+    messages: MessageLog,
+    leaderboard: Box<dyn LeaderboardBackend>,
+    narrator: Box<dyn Narrator>,
+    cheats: CheatCodes,
+    tutorial: Tutorial,
+    /// The player's key bindings, so text on the screen names the right keys.
+    keys: KeyBindings,
+    /// What each item is, for the inventory screen.
+    items: ItemTable,
+    endings: EndingTable,
+    /// Which endings the player has reached, for the title screen.
+    ending_record: EndingRecord,
+    /// Where each random level's seed comes from, so a whole game can be repeated from one seed.
+    rng: StdRng,
+    /// The transition from the last scene, while it's running.
+    transition: Option<Transition>,
 }
 
 impl StageManager {
     pub fn new(file_manager: &FileManager, images: &mut dyn ImageLoader) -> Result<StageManager> {
-        // let path = Path::new("assets/menus/start.tmx");
-        // let splash = Menu::new_splash(file_manager, images)?;
-        let level = Level::new(file_manager, images)?;
+        StageManager::with_seed(file_manager, images, random())
+    }
+
+    /// A stage manager whose random levels all come from the given seed.
+    pub fn with_seed(
+        file_manager: &FileManager,
+        images: &mut dyn ImageLoader,
+        seed: u64,
+    ) -> Result<StageManager> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let level = Level::with_seed(rng.gen(), file_manager, images)?;
+        let mut cheats = CheatCodes::new();
+        for (name, code) in DEFAULT_CHEAT_CODES {
+            cheats.register(name, code)?;
+        }
         Ok(StageManager {
             current: Box::new(level),
             stack: Vec::new(),
+            cvars: Cvars::new(),
+            messages: MessageLog::default(),
+            leaderboard: Box::new(LocalLeaderboard::load(file_manager)),
+            narrator: Box::new(LogNarrator),
+            cheats,
+            tutorial: Tutorial::load(file_manager),
+            keys: KeyBindings::load(file_manager),
+            items: ItemTable::load(file_manager),
+            endings: EndingTable::load(file_manager),
+            ending_record: EndingRecord::load(file_manager),
+            rng,
+            transition: None,
         })
     }
 
@@ -38,8 +112,40 @@ impl StageManager {
         images: &mut dyn ImageLoader,
         sounds: &mut SoundManager,
     ) -> Result<bool> {
+        profile_scope!("update");
+        if let Some(transition) = &mut self.transition {
+            if !transition.advance() {
+                self.transition = None;
+            }
+        }
+        self.apply_cheats(context.frame, inputs);
+        self.current.apply_cvars(&self.cvars);
+        self.tutorial.apply_cvars(&mut self.cvars);
+        self.current.reload_data(files);
         let result = self.current.update(context, inputs, sounds);
-        Ok(match result {
+        self.pass_item_actions();
+        self.current.flush_cvars(&mut self.cvars);
+        sounds.set_muffled(self.current.muffles_sound());
+        self.current.submit_scores(self.leaderboard.as_mut());
+        self.current
+            .flush_messages(&mut self.messages, context.frame);
+        self.current.flush_narration(self.narrator.as_mut());
+        self.current.flush_prompts(&mut self.tutorial);
+        if let Some(prompt) = self.tutorial.update(&self.keys) {
+            self.narrator.narrate(prompt);
+        }
+        self.current.flush_user_data(files);
+        if let Err(e) = self.leaderboard.flush(files) {
+            error!("unable to save leaderboard: {}", e);
+        }
+        if let Err(e) = self.tutorial.flush(files) {
+            error!("unable to save tutorial: {}", e);
+        }
+        if let Err(e) = self.ending_record.flush(files) {
+            error!("unable to save endings: {}", e);
+        }
+        let switching = !matches!(result, SceneResult::Continue);
+        let running = match result {
             SceneResult::Continue => true,
             SceneResult::Pop => {
                 if let Some(next) = self.stack.pop() {
@@ -59,7 +165,7 @@ impl StageManager {
                 }
             }
             SceneResult::PushLevel => {
-                let level = Level::new(files, images)?;
+                let level = self.new_level(files, images)?;
                 let level = Box::new(level);
                 let previous = mem::replace(&mut self.current, level);
                 self.stack.push(previous);
@@ -67,11 +173,33 @@ impl StageManager {
             }
             SceneResult::ReloadLevel => {
                 self.stack.pop();
-                self.current = Box::new(Level::new(files, images)?);
+                self.current = Box::new(self.new_level(files, images)?);
                 true
             }
+            SceneResult::RespawnAtCheckpoint => {
+                if let Some(mut previous) = self.stack.pop() {
+                    if previous.respawn_at_checkpoint() {
+                        self.messages.push(
+                            context.frame,
+                            MessageKind::System,
+                            "back at checkpoint",
+                        );
+                        self.current = previous;
+                    } else {
+                        self.current = Box::new(self.new_level(files, images)?);
+                    }
+                    true
+                } else {
+                    false
+                }
+            }
             SceneResult::PushMenu => {
-                let menu = Menu::new_splash(files, images)?;
+                let ending = self
+                    .ending_record
+                    .last()
+                    .and_then(|name| self.endings.get(name));
+                let ending = ending.map(|ending| ending.title.as_str());
+                let menu = Menu::new_splash(files, images, ending)?;
                 let menu = Box::new(menu);
                 let previous = mem::replace(&mut self.current, menu);
                 self.stack.push(previous);
@@ -85,17 +213,512 @@ impl StageManager {
                 true
             }
             SceneResult::PushPause => {
-                let pause_screen = Menu::new_splash(files, images)?;
+                let pause_screen = Menu::new_pause(files, images)?;
                 let pause_screen = Box::new(pause_screen);
                 let previous = mem::replace(&mut self.current, pause_screen);
                 self.stack.push(previous);
                 true
             }
-        })
+            SceneResult::PushMessageLog => {
+                let log_screen = Box::new(LogScreen::new(&self.messages));
+                let previous = mem::replace(&mut self.current, log_screen);
+                self.stack.push(previous);
+                true
+            }
+            SceneResult::PushLeaderboard => {
+                let scores = Box::new(LeaderboardScreen::new(self.leaderboard.as_ref()));
+                let previous = mem::replace(&mut self.current, scores);
+                self.stack.push(previous);
+                true
+            }
+            SceneResult::PushSafeArea => {
+                let screen = Box::new(SafeAreaScreen::new(&self.keys));
+                let previous = mem::replace(&mut self.current, screen);
+                self.stack.push(previous);
+                true
+            }
+            SceneResult::PushNameEntry => {
+                let screen = Box::new(NameEntryScreen::new());
+                let previous = mem::replace(&mut self.current, screen);
+                self.stack.push(previous);
+                true
+            }
+            SceneResult::PushInventory => {
+                let owner = std::iter::once(&self.current)
+                    .chain(self.stack.iter().rev())
+                    .find_map(|scene| Some((scene.inventory()?, scene.item_target())));
+                let owner = owner.map(|(inventory, target)| (inventory.clone(), target.cloned()));
+                if let Some((inventory, target)) = owner {
+                    let screen = InventoryScreen::new(
+                        &inventory,
+                        &self.items,
+                        target.as_ref(),
+                        &self.keys,
+                        images,
+                    );
+                    let screen = Box::new(screen);
+                    let previous = mem::replace(&mut self.current, screen);
+                    self.stack.push(previous);
+                } else {
+                    error!("no scene has an inventory");
+                }
+                true
+            }
+            SceneResult::PushEpilogue { run } => {
+                let ending = self.endings.choose(&run);
+                self.ending_record.record(&ending.name);
+                let epilogue = Box::new(Epilogue::new(ending));
+                let previous = mem::replace(&mut self.current, epilogue);
+                self.stack.push(previous);
+                true
+            }
+        };
+        if switching && running {
+            self.transition = Transition::from_cvars(&self.cvars);
+        }
+        Ok(running)
+    }
+
+    /// Replaces everything on the stack with the Tiled map at the path, which the map cvar is set
+    /// to, so it's also what comes back when the level is reloaded.
+    pub fn load_level(
+        &mut self,
+        path: &Path,
+        files: &FileManager,
+        images: &mut dyn ImageLoader,
+    ) -> Result<()> {
+        self.cvars.set(MAP_CVAR, &path.to_string_lossy());
+        self.current = Box::new(self.new_level(files, images)?);
+        self.stack.clear();
+        Ok(())
+    }
+
+    /// A new level from the Tiled map named by the map cvar, or a random one if it's not set.
+    fn new_level(&mut self, files: &FileManager, images: &mut dyn ImageLoader) -> Result<Level> {
+        match self.cvars.get(MAP_CVAR) {
+            Some(path) if !path.is_empty() => Level::from_tilemap(Path::new(path), files, images),
+            _ => Level::with_seed(self.rng.gen(), files, images),
+        }
+    }
+
+    /// Hands anything the player did on the inventory screen to the scene the inventory belongs
+    /// to, which is under it on the stack.
+    fn pass_item_actions(&mut self) {
+        let actions = self.current.take_item_actions();
+        if actions.is_empty() {
+            return;
+        }
+        let owner = self
+            .stack
+            .iter_mut()
+            .rev()
+            .find(|scene| scene.inventory().is_some());
+        let Some(owner) = owner else {
+            error!("no scene has an inventory");
+            return;
+        };
+        for (action, item) in actions {
+            owner.queue_item_action(action, &item);
+        }
+    }
+
+    /// Toggles the cvar named by each cheat code entered this frame, so that whatever reads it can
+    /// pick it up like any other setting.
+    fn apply_cheats(&mut self, frame: u64, inputs: &InputSnapshot) {
+        let fired = self.cheats.update(frame, inputs);
+        let enabled = self
+            .cvars
+            .get_parsed(CHEATS_CVAR)
+            .unwrap_or(cfg!(debug_assertions));
+        if !enabled {
+            return;
+        }
+        for name in fired {
+            let on = !self.cvars.get_parsed(&name).unwrap_or(false);
+            self.cvars.set(&name, &on.to_string());
+            let text = format!("cheat {} {}", name, if on { "on" } else { "off" });
+            self.messages.push(frame, MessageKind::System, &text);
+        }
+    }
+
+    /// A hash of the state of every scene on the stack, which is the same every time the same
+    /// inputs are played from the same seed.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StateHasher::new();
+        for scene in self.stack.iter().chain(std::iter::once(&self.current)) {
+            hasher.write(scene.name().as_bytes());
+            scene.hash_state(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Returns the names of the scenes on the stack, from the bottom up to the current scene.
+    pub fn scene_names(&self) -> Vec<&str> {
+        self.stack
+            .iter()
+            .chain(std::iter::once(&self.current))
+            .map(|scene| scene.name())
+            .collect()
+    }
+
+    pub fn cheats(&self) -> &CheatCodes {
+        &self.cheats
+    }
+
+    /// The registered cheat codes, each of which toggles the cvar with the same name.
+    pub fn cheats_mut(&mut self) -> &mut CheatCodes {
+        &mut self.cheats
+    }
+
+    pub fn cvars(&self) -> &Cvars {
+        &self.cvars
+    }
+
+    pub fn cvars_mut(&mut self) -> &mut Cvars {
+        &mut self.cvars
+    }
+
+    /// Dialog, pickups, and system messages from every scene, for the log screen.
+    pub fn messages(&self) -> &MessageLog {
+        &self.messages
+    }
+
+    pub fn messages_mut(&mut self) -> &mut MessageLog {
+        &mut self.messages
+    }
+
+    /// The transition between scenes that's running, if any.
+    pub fn transition(&self) -> Option<&Transition> {
+        self.transition.as_ref()
+    }
+
+    /// Whether the host should capture the mouse for mouse look, because the current scene turns
+    /// the player with it.
+    pub fn captures_mouse(&self) -> bool {
+        self.current.captures_mouse()
+    }
+
+    /// Whether the host should have the keyboard type text, because the current scene has a
+    /// text box.
+    pub fn wants_text_input(&self) -> bool {
+        self.current.wants_text_input()
+    }
+
+    /// The clock of the topmost scene that has one, so it can be read while the game is paused.
+    pub fn clock(&self) -> Option<&GameClock> {
+        std::iter::once(&self.current)
+            .chain(self.stack.iter().rev())
+            .find_map(|scene| scene.clock())
+    }
+
+    /// Which tutorial prompts have been shown, and the one on screen.
+    pub fn tutorial(&self) -> &Tutorial {
+        &self.tutorial
+    }
+
+    /// Which endings the player has reached.
+    pub fn ending_record(&self) -> &EndingRecord {
+        &self.ending_record
+    }
+
+    pub fn leaderboard(&self) -> &dyn LeaderboardBackend {
+        self.leaderboard.as_ref()
+    }
+
+    /// Replaces the local leaderboard, for hosts with an online service.
+    pub fn set_leaderboard(&mut self, leaderboard: Box<dyn LeaderboardBackend>) {
+        self.leaderboard = leaderboard;
+    }
+
+    /// Replaces the key bindings text on the screen is written for, for hosts that let the
+    /// player rebind keys.
+    pub fn set_key_bindings(&mut self, keys: KeyBindings) {
+        self.keys = keys;
+    }
+
+    /// Replaces the narrator, which only logs, for hosts with text to speech.
+    pub fn set_narrator(&mut self, narrator: Box<dyn Narrator>) {
+        self.narrator = narrator;
+    }
+
+    /// Returns the saved state of the topmost scene that has any.
+    pub fn save_state(&self) -> Option<LevelState> {
+        std::iter::once(&self.current)
+            .chain(self.stack.iter().rev())
+            .find_map(|scene| scene.save_state())
+    }
+
+    /// Returns the entities in the topmost scene that has any.
+    pub fn entities(&self) -> Option<Vec<EntityInfo>> {
+        std::iter::once(&self.current)
+            .chain(self.stack.iter().rev())
+            .find_map(|scene| scene.entities())
+    }
+
+    /// Restores the state into the topmost scene that supports it.
+    pub fn restore_state(&mut self, state: &LevelState) -> bool {
+        std::iter::once(&mut self.current)
+            .chain(self.stack.iter_mut().rev())
+            .any(|scene| scene.restore_state(state))
     }
 
     pub fn draw(&mut self, context: &mut RenderContext, font: &Font) {
+        profile_scope!("draw");
+        context.postprocess = self.current.postprocess();
+        context.transition = self.transition.as_ref().map(Transition::frame);
         self.current
             .draw(context, font, self.stack.last().map(Box::as_ref));
+        self.tutorial.draw(context, font);
+        self.draw_debug_watermark(context, font);
+    }
+
+    /// Marks the screen while any debug flags are on, so it's clear the game isn't being played
+    /// by the normal rules.
+    fn draw_debug_watermark(&self, context: &mut RenderContext, font: &Font) {
+        let Some(text) = DebugFlags::from_cvars(&self.cvars).watermark() else {
+            return;
+        };
+        let area = context.safe_area();
+        let width = font.text_width(&text);
+        let position = Point::new(
+            area.right() - width - WATERMARK_MARGIN,
+            area.y + WATERMARK_MARGIN,
+        );
+        context.set_tint(RenderLayer::Overlay, WATERMARK_COLOR);
+        font.draw_string(context, RenderLayer::Overlay, position, &text);
+        context.set_tint(RenderLayer::Overlay, Color::WHITE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use anyhow::bail;
+
+    use super::*;
+    use crate::constants::{RENDER_HEIGHT, RENDER_WIDTH};
+    use crate::filemanager::{DirEntry, FileManagerImpl};
+    use crate::imagemanager::ImageManager;
+    use crate::level::MOVE_PROMPT;
+    use crate::narration::RecordingNarrator;
+    use crate::rendercontext::PostprocessProfile;
+    use crate::savestate::PlayerState;
+    use crate::transition::{TransitionKind, TRANSITION_CVAR, TRANSITION_FRAMES_CVAR};
+    use crate::utils::normalize_path;
+
+    /// Only the menus, so the levels are all random and nothing is saved.
+    struct NoFiles {}
+
+    const MENU_FILES: &[(&str, &str)] = &[
+        (
+            "assets/menus/start.tmx",
+            include_str!("../../assets/menus/start.tmx"),
+        ),
+        (
+            "assets/menus/kill.tmx",
+            include_str!("../../assets/menus/kill.tmx"),
+        ),
+        (
+            "assets/start_button.tsx",
+            include_str!("../../assets/start_button.tsx"),
+        ),
+        (
+            "assets/retry_button.tsx",
+            include_str!("../../assets/retry_button.tsx"),
+        ),
+        (
+            "assets/quit_button.tsx",
+            include_str!("../../assets/quit_button.tsx"),
+        ),
+    ];
+
+    impl FileManagerImpl for NoFiles {
+        fn read(&self, path: &Path) -> Result<Vec<u8>> {
+            Ok(self.read_to_string(path)?.into_bytes())
+        }
+
+        fn read_to_string(&self, path: &Path) -> Result<String> {
+            let path = normalize_path(path)?;
+            match MENU_FILES.iter().find(|(name, _)| Path::new(name) == path) {
+                Some((_, contents)) => Ok(contents.to_string()),
+                None => bail!("no file at {:?}", path),
+            }
+        }
+
+        fn read_dir(&self, dir_path: &Path) -> Result<Vec<DirEntry>> {
+            bail!("no directory at {:?}", dir_path)
+        }
+    }
+
+    #[test]
+    fn restore_state_under_menu() {
+        let files = FileManager::with_internal(Box::new(NoFiles {}));
+        let mut images = ImageManager::null_manager();
+        let mut sounds = SoundManager::noop_manager();
+        let mut stage_manager = StageManager::new(&files, &mut images).unwrap();
+
+        let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let inputs = InputSnapshot {
+            ok_clicked: true,
+            ..Default::default()
+        };
+        assert!(stage_manager
+            .update(&context, &inputs, &files, &mut images, &mut sounds)
+            .unwrap());
+        assert_eq!(stage_manager.scene_names(), vec!["level", "menu"]);
+
+        let player = PlayerState {
+            x: 2.5,
+            y: 3.5,
+            angle: 1.0,
+        };
+        assert!(stage_manager.restore_state(&LevelState::new(player, None)));
+        assert_eq!(stage_manager.save_state().unwrap().player, player);
+    }
+
+    #[test]
+    fn cheat_toggles_cvar() {
+        let files = FileManager::with_internal(Box::new(NoFiles {}));
+        let mut images = ImageManager::null_manager();
+        let mut sounds = SoundManager::noop_manager();
+        let mut stage_manager = StageManager::new(&files, &mut images).unwrap();
+        stage_manager
+            .cheats_mut()
+            .register("god", "left right")
+            .unwrap();
+        let mut enter_code = |stage_manager: &mut StageManager| {
+            let left = InputSnapshot {
+                menu_left_clicked: true,
+                ..Default::default()
+            };
+            let right = InputSnapshot {
+                menu_right_clicked: true,
+                ..Default::default()
+            };
+            for inputs in [left, right] {
+                let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+                stage_manager
+                    .update(&context, &inputs, &files, &mut images, &mut sounds)
+                    .unwrap();
+            }
+        };
+
+        stage_manager.cvars_mut().set(CHEATS_CVAR, "true");
+        enter_code(&mut stage_manager);
+        assert_eq!(stage_manager.cvars().get("god"), Some("true"));
+        let message = stage_manager.messages().entries().last().unwrap();
+        assert_eq!(message.text, "cheat god on");
+
+        // Release builds turn cheats off, so the code does nothing.
+        stage_manager.cvars_mut().set(CHEATS_CVAR, "false");
+        enter_code(&mut stage_manager);
+        assert_eq!(stage_manager.cvars().get("god"), Some("true"));
+    }
+
+    #[test]
+    fn narration() {
+        let files = FileManager::with_internal(Box::new(NoFiles {}));
+        let mut images = ImageManager::null_manager();
+        let mut sounds = SoundManager::noop_manager();
+        let mut stage_manager = StageManager::new(&files, &mut images).unwrap();
+        let narrator = RecordingNarrator::new();
+        let spoken = narrator.spoken();
+        stage_manager.set_narrator(Box::new(narrator));
+        let prompts = [(MOVE_PROMPT.to_string(), "wasd to move".to_string())];
+        stage_manager.tutorial = Tutorial::new().with_prompts(prompts.into());
+
+        let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let mut update = |stage_manager: &mut StageManager, inputs: InputSnapshot| {
+            stage_manager
+                .update(&context, &inputs, &files, &mut images, &mut sounds)
+                .unwrap();
+        };
+        update(&mut stage_manager, InputSnapshot::default());
+        assert_eq!(
+            *spoken.borrow(),
+            vec!["sector 7", "deep space", "wasd to move"]
+        );
+        spoken.borrow_mut().clear();
+
+        // The kill screen reads its text and then whichever button is focused.
+        let ok = InputSnapshot {
+            ok_clicked: true,
+            ..Default::default()
+        };
+        update(&mut stage_manager, ok);
+        update(&mut stage_manager, InputSnapshot::default());
+        let down = InputSnapshot {
+            menu_down_clicked: true,
+            ..Default::default()
+        };
+        update(&mut stage_manager, down);
+        update(&mut stage_manager, InputSnapshot::default());
+        assert_eq!(*spoken.borrow(), vec!["hello world", "retry", "quit"]);
+    }
+
+    #[test]
+    fn menu_postprocess() {
+        let files = FileManager::with_internal(Box::new(NoFiles {}));
+        let mut images = ImageManager::null_manager();
+        let mut sounds = SoundManager::noop_manager();
+        let mut stage_manager = StageManager::new(&files, &mut images).unwrap();
+        assert_eq!(
+            stage_manager.current.postprocess(),
+            PostprocessProfile::RETRO
+        );
+
+        // The kill screen is drawn over the level, so it keeps the level's look.
+        let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let inputs = InputSnapshot {
+            ok_clicked: true,
+            ..Default::default()
+        };
+        stage_manager
+            .update(&context, &inputs, &files, &mut images, &mut sounds)
+            .unwrap();
+        assert_eq!(
+            stage_manager.current.postprocess(),
+            PostprocessProfile::RETRO
+        );
+
+        let splash = Menu::new_splash(&files, &mut images, None).unwrap();
+        assert_eq!(splash.postprocess(), PostprocessProfile::CLEAN);
+    }
+
+    #[test]
+    fn transitions() {
+        let files = FileManager::with_internal(Box::new(NoFiles {}));
+        let mut images = ImageManager::null_manager();
+        let mut sounds = SoundManager::noop_manager();
+        let mut stage_manager = StageManager::new(&files, &mut images).unwrap();
+        stage_manager.cvars_mut().set(TRANSITION_CVAR, "crossfade");
+        stage_manager.cvars_mut().set(TRANSITION_FRAMES_CVAR, "4");
+
+        let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let mut update = |stage_manager: &mut StageManager, inputs: InputSnapshot| {
+            stage_manager
+                .update(&context, &inputs, &files, &mut images, &mut sounds)
+                .unwrap();
+        };
+        update(&mut stage_manager, InputSnapshot::default());
+        assert_eq!(stage_manager.transition(), None);
+
+        // Switching to the kill screen starts one.
+        let ok = InputSnapshot {
+            ok_clicked: true,
+            ..Default::default()
+        };
+        update(&mut stage_manager, ok);
+        let transition = stage_manager.transition().unwrap();
+        assert_eq!(transition.kind(), TransitionKind::Crossfade);
+        assert!(transition.is_starting());
+
+        for _ in 0..3 {
+            update(&mut stage_manager, InputSnapshot::default());
+            assert!(!stage_manager.transition().unwrap().is_starting());
+        }
+        update(&mut stage_manager, InputSnapshot::default());
+        assert_eq!(stage_manager.transition(), None);
     }
 }
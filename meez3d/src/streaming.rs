@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use log::info;
+
+/// The position of a chunk in a streamed map, counted in chunks rather than tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChunkCoord {
+    pub row: usize,
+    pub column: usize,
+}
+
+/// A square piece of a streamed map, with the entities that live in it.
+pub struct Chunk<T, E> {
+    /// chunk_size * chunk_size tiles, in row-major order.
+    pub tiles: Vec<T>,
+    pub entities: Vec<E>,
+}
+
+/// Where a StreamingGrid gets its chunks from, e.g. a file per chunk or a seeded generator.
+pub trait ChunkSource<T, E> {
+    /// Loads or generates the chunk at the given position.
+    ///
+    /// Chunks along the right and bottom edges of the map still have a full chunk_size of tiles,
+    /// but the ones past the edge are never read.
+    fn load_chunk(&mut self, coord: ChunkCoord, chunk_size: usize) -> Result<Chunk<T, E>>;
+
+    /// Called with a chunk just before it's dropped.
+    ///
+    /// This is the hook for keeping state that shouldn't reset when the player walks away, like
+    /// opened doors or fired triggers, so the next load_chunk for the same position can restore it.
+    fn unload_chunk(&mut self, _coord: ChunkCoord, _chunk: Chunk<T, E>) {}
+}
+
+/// A tile grid too big to keep in memory, so only the chunks near the player are loaded.
+pub struct StreamingGrid<T, E> {
+    width: usize,
+    height: usize,
+    chunk_size: usize,
+    /// How many chunks in each direction from the player's chunk to keep loaded.
+    radius: usize,
+    source: Box<dyn ChunkSource<T, E>>,
+    chunks: HashMap<ChunkCoord, Chunk<T, E>>,
+    center: Option<ChunkCoord>,
+}
+
+impl<T, E> StreamingGrid<T, E> {
+    pub fn new(
+        width: usize,
+        height: usize,
+        chunk_size: usize,
+        radius: usize,
+        source: Box<dyn ChunkSource<T, E>>,
+    ) -> Result<StreamingGrid<T, E>> {
+        if chunk_size == 0 {
+            bail!("chunk size must be positive");
+        }
+        Ok(StreamingGrid {
+            width,
+            height,
+            chunk_size,
+            radius,
+            source,
+            chunks: HashMap::new(),
+            center: None,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn chunk_coord(&self, row: usize, column: usize) -> ChunkCoord {
+        ChunkCoord {
+            row: row / self.chunk_size,
+            column: column / self.chunk_size,
+        }
+    }
+
+    /// Loads the chunks within the radius of the given tile, and unloads the rest.
+    ///
+    /// This is cheap to call every frame, since it does nothing until the tile is in a new chunk.
+    pub fn update(&mut self, row: usize, column: usize) -> Result<()> {
+        let center = self.chunk_coord(
+            row.min(self.height.saturating_sub(1)),
+            column.min(self.width.saturating_sub(1)),
+        );
+        if self.center == Some(center) {
+            return Ok(());
+        }
+        self.center = Some(center);
+        profile_scope!("load_chunks", row = center.row, column = center.column);
+
+        let rows = self.height.div_ceil(self.chunk_size);
+        let columns = self.width.div_ceil(self.chunk_size);
+        let row_range = center.row.saturating_sub(self.radius)..(center.row + self.radius + 1);
+        let column_range =
+            center.column.saturating_sub(self.radius)..(center.column + self.radius + 1);
+
+        let distant: Vec<ChunkCoord> = self
+            .chunks
+            .keys()
+            .filter(|coord| {
+                !row_range.contains(&coord.row) || !column_range.contains(&coord.column)
+            })
+            .copied()
+            .collect();
+        for coord in distant {
+            if let Some(chunk) = self.chunks.remove(&coord) {
+                self.source.unload_chunk(coord, chunk);
+            }
+        }
+
+        for chunk_row in row_range.start..row_range.end.min(rows) {
+            for chunk_column in column_range.start..column_range.end.min(columns) {
+                let coord = ChunkCoord {
+                    row: chunk_row,
+                    column: chunk_column,
+                };
+                if self.chunks.contains_key(&coord) {
+                    continue;
+                }
+                let chunk = self.source.load_chunk(coord, self.chunk_size)?;
+                let expected = self.chunk_size * self.chunk_size;
+                if chunk.tiles.len() != expected {
+                    bail!(
+                        "chunk {:?} has {} tiles, but should have {}",
+                        coord,
+                        chunk.tiles.len(),
+                        expected
+                    );
+                }
+                self.chunks.insert(coord, chunk);
+            }
+        }
+        info!(
+            "streamed chunks around {:?}, {} loaded",
+            center,
+            self.chunks.len()
+        );
+        Ok(())
+    }
+
+    /// The tile at the given position, or None if it's off the map or not loaded.
+    pub fn get(&self, row: usize, column: usize) -> Option<&T> {
+        if row >= self.height || column >= self.width {
+            return None;
+        }
+        let chunk = self.chunks.get(&self.chunk_coord(row, column))?;
+        chunk
+            .tiles
+            .get((row % self.chunk_size) * self.chunk_size + column % self.chunk_size)
+    }
+
+    pub fn get_mut(&mut self, row: usize, column: usize) -> Option<&mut T> {
+        if row >= self.height || column >= self.width {
+            return None;
+        }
+        let coord = self.chunk_coord(row, column);
+        let chunk = self.chunks.get_mut(&coord)?;
+        chunk
+            .tiles
+            .get_mut((row % self.chunk_size) * self.chunk_size + column % self.chunk_size)
+    }
+
+    pub fn is_loaded(&self, coord: ChunkCoord) -> bool {
+        self.chunks.contains_key(&coord)
+    }
+
+    pub fn loaded_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Every loaded tile on the map, as (row, column, tile).
+    pub fn loaded_tiles(&self) -> impl Iterator<Item = (usize, usize, &T)> {
+        let chunk_size = self.chunk_size;
+        let (width, height) = (self.width, self.height);
+        self.chunks.iter().flat_map(move |(coord, chunk)| {
+            chunk
+                .tiles
+                .iter()
+                .enumerate()
+                .map(move |(i, tile)| {
+                    (
+                        coord.row * chunk_size + i / chunk_size,
+                        coord.column * chunk_size + i % chunk_size,
+                        tile,
+                    )
+                })
+                .filter(move |(row, column, _)| *row < height && *column < width)
+        })
+    }
+
+    /// The entities in every loaded chunk.
+    pub fn entities(&self) -> impl Iterator<Item = &E> {
+        self.chunks.values().flat_map(|chunk| chunk.entities.iter())
+    }
+
+    /// Unloads every chunk, giving the source a chance to save their state.
+    pub fn unload_all(&mut self) {
+        for (coord, chunk) in self.chunks.drain() {
+            self.source.unload_chunk(coord, chunk);
+        }
+        self.center = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    /// Tiles are their own positions, and the tile at (0, 0) is a door that can be opened.
+    #[derive(Default)]
+    struct DoorSource {
+        opened: bool,
+        loads: Rc<RefCell<Vec<ChunkCoord>>>,
+    }
+
+    impl ChunkSource<(usize, usize), &'static str> for DoorSource {
+        fn load_chunk(
+            &mut self,
+            coord: ChunkCoord,
+            chunk_size: usize,
+        ) -> Result<Chunk<(usize, usize), &'static str>> {
+            self.loads.borrow_mut().push(coord);
+            let mut tiles = Vec::new();
+            for i in 0..chunk_size * chunk_size {
+                tiles.push((
+                    coord.row * chunk_size + i / chunk_size,
+                    coord.column * chunk_size + i % chunk_size,
+                ));
+            }
+            let mut entities = Vec::new();
+            if coord.row == 0 && coord.column == 0 {
+                entities.push(if self.opened { "open door" } else { "door" });
+            }
+            Ok(Chunk { tiles, entities })
+        }
+
+        fn unload_chunk(&mut self, coord: ChunkCoord, chunk: Chunk<(usize, usize), &'static str>) {
+            if coord.row == 0 && coord.column == 0 {
+                self.opened = chunk.entities.contains(&"open door");
+            }
+        }
+    }
+
+    #[test]
+    fn streaming() {
+        let source = DoorSource::default();
+        let loads = source.loads.clone();
+        let mut grid = StreamingGrid::new(100, 50, 10, 1, Box::new(source)).unwrap();
+        assert_eq!(grid.get(0, 0), None);
+
+        grid.update(5, 5).unwrap();
+        assert_eq!(grid.loaded_chunks(), 4);
+        assert_eq!(grid.get(12, 17), Some(&(12, 17)));
+        assert_eq!(grid.get(25, 5), None);
+        assert_eq!(grid.entities().collect::<Vec<_>>(), vec![&"door"]);
+
+        // Staying in the same chunk doesn't load anything.
+        grid.update(9, 9).unwrap();
+        assert_eq!(loads.borrow().len(), 4);
+
+        // Open the door, then walk far enough away for its chunk to be unloaded.
+        grid.chunks
+            .get_mut(&ChunkCoord { row: 0, column: 0 })
+            .unwrap()
+            .entities[0] = "open door";
+        grid.update(45, 45).unwrap();
+        assert_eq!(grid.loaded_chunks(), 6);
+        assert!(!grid.is_loaded(ChunkCoord { row: 0, column: 0 }));
+        assert_eq!(grid.get(45, 45), Some(&(45, 45)));
+        assert_eq!(grid.loaded_tiles().count(), 6 * 100);
+
+        // Coming back restores the door's state.
+        grid.update(0, 0).unwrap();
+        assert_eq!(grid.entities().collect::<Vec<_>>(), vec![&"open door"]);
+    }
+}
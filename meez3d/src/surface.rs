@@ -0,0 +1,111 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use crate::properties::PropertyMap;
+use crate::utils::Color;
+
+const DEFAULT_FLOOR_COLOR: Color = Color {
+    r: 0x33,
+    g: 0x33,
+    b: 0x33,
+    a: 0xff,
+};
+
+/// What the floor or ceiling of a raycast level looks like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Surface {
+    /// The scrolling space background. Only ceilings can be sky.
+    Sky,
+    Color(Color),
+    /// One of the wall textures, repeated once per tile.
+    Texture(u32),
+}
+
+impl FromStr for Surface {
+    type Err = Error;
+
+    /// Parses "sky", a color like "#336699", or a wall texture like "texture 2".
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "sky" {
+            return Ok(Surface::Sky);
+        }
+        if let Some(index) = s.strip_prefix("texture ") {
+            let Ok(index) = index.trim().parse() else {
+                bail!("invalid surface: {}", s);
+            };
+            return Ok(Surface::Texture(index));
+        }
+        match s.parse() {
+            Ok(color) => Ok(Surface::Color(color)),
+            Err(_) => bail!("invalid surface: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for Surface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Surface::Sky => f.write_str("sky"),
+            Surface::Color(color) => write!(f, "{}", color),
+            Surface::Texture(index) => write!(f, "texture {}", index),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SurfaceSettings {
+    pub floor: Surface,
+    pub ceiling: Surface,
+}
+
+impl Default for SurfaceSettings {
+    fn default() -> Self {
+        SurfaceSettings {
+            floor: Surface::Color(DEFAULT_FLOOR_COLOR),
+            ceiling: Surface::Sky,
+        }
+    }
+}
+
+impl SurfaceSettings {
+    /// The floor and ceiling for a map with "floor" and "ceiling" properties, either of which
+    /// can be left out to use the default.
+    pub fn from_properties(properties: &PropertyMap) -> Result<Option<SurfaceSettings>> {
+        let floor: Option<Surface> = properties
+            .get_string("floor")?
+            .map(str::parse)
+            .transpose()?;
+        let ceiling = properties
+            .get_string("ceiling")?
+            .map(str::parse)
+            .transpose()?;
+        if floor.is_none() && ceiling.is_none() {
+            return Ok(None);
+        }
+        if floor == Some(Surface::Sky) {
+            bail!("invalid floor: sky");
+        }
+        let defaults = SurfaceSettings::default();
+        Ok(Some(SurfaceSettings {
+            floor: floor.unwrap_or(defaults.floor),
+            ceiling: ceiling.unwrap_or(defaults.ceiling),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        for s in ["sky", "texture 2", "#336699"] {
+            assert_eq!(s.parse::<Surface>().unwrap().to_string(), s);
+        }
+        assert!("texture".parse::<Surface>().is_err());
+        assert!("texture x".parse::<Surface>().is_err());
+        assert!("grass".parse::<Surface>().is_err());
+    }
+}
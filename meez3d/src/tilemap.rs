@@ -1,24 +1,36 @@
 use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
 use std::num::ParseIntError;
-use std::ops::{Index, IndexMut};
+use std::ops::{Index, IndexMut, Range};
 use std::path::Path;
 use std::str::FromStr;
 
+use crate::ability::Ability;
+use crate::decodepool::DecodePool;
+use crate::faction::Faction;
 use crate::filemanager::FileManager;
+use crate::gameclock::AmbientLight;
 use crate::geometry::{Point, Rect};
 use crate::imagemanager::ImageLoader;
+use crate::inventory::ItemTarget;
+use crate::lightmap::StaticLight;
 use crate::properties::{PropertiesXml, PropertyMap};
-use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::rendercontext::{RenderContext, RenderLayer, SpriteTransform};
+use crate::spawner::SpawnerSettings;
 use crate::sprite::{Animation, Sprite};
-use crate::tileset::{LocalTileIndex, TileProperties, TileSet};
-use crate::utils::Color;
+use crate::surface::SurfaceSettings;
+use crate::tileset::{LocalTileIndex, TileProperties, TileSet, TileSetXml};
+use crate::utils::{normalize_path, Color};
+use crate::weather::WeatherSettings;
 
 use anyhow::{anyhow, bail, Context, Result};
 use log::info;
 use num_traits::Zero;
+use quick_xml::escape::escape;
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 struct TileSetSourceXml {
     #[serde(rename = "@source")]
     source: String,
@@ -46,6 +58,12 @@ struct LayerXml {
     width: u32,
     #[serde(rename = "@height")]
     height: u32,
+    #[serde(rename = "@visible")]
+    visible: Option<u8>,
+    #[serde(rename = "@opacity")]
+    opacity: Option<f32>,
+    #[serde(rename = "@tintcolor")]
+    tintcolor: Option<String>,
 
     data: DataXml,
 
@@ -61,19 +79,35 @@ struct ImageXml {
 #[derive(Debug, Deserialize)]
 struct ImageLayerXml {
     #[serde(rename = "@id")]
-    _id: i32,
+    id: i32,
+    #[serde(rename = "@name")]
+    name: Option<String>,
+    #[serde(rename = "@visible")]
+    visible: Option<u8>,
+    #[serde(rename = "@opacity")]
+    opacity: Option<f32>,
+    #[serde(rename = "@tintcolor")]
+    tintcolor: Option<String>,
     #[serde(rename = "@offsetx")]
-    _offsetx: Option<String>,
+    offsetx: Option<String>,
     #[serde(rename = "@offsety")]
-    _offsety: Option<String>,
+    offsety: Option<String>,
+    #[serde(rename = "@repeatx")]
+    repeatx: Option<u8>,
+    #[serde(rename = "@repeaty")]
+    repeaty: Option<u8>,
 
     image: ImageXml,
+
+    properties: Option<PropertiesXml>,
 }
 
 #[derive(Debug, Deserialize)]
 struct ObjectXml {
     #[serde(rename = "@id")]
     id: i32,
+    #[serde(rename = "@template")]
+    template: Option<String>,
     #[serde(rename = "@x")]
     x: i32,
     #[serde(rename = "@y")]
@@ -88,12 +122,64 @@ struct ObjectXml {
     properties: Option<PropertiesXml>,
 }
 
+#[derive(Debug, Deserialize)]
+struct TemplateObjectXml {
+    #[serde(rename = "@width")]
+    width: Option<i32>,
+    #[serde(rename = "@height")]
+    height: Option<i32>,
+    #[serde(rename = "@gid")]
+    gid: Option<u32>,
+
+    properties: Option<PropertiesXml>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TemplateXml {
+    tileset: Option<TileSetSourceXml>,
+    object: TemplateObjectXml,
+}
+
 #[derive(Debug, Deserialize)]
 struct ObjectGroupXml {
+    #[serde(rename = "@id")]
+    id: Option<u32>,
+    #[serde(rename = "@name")]
+    name: Option<String>,
+    #[serde(rename = "@visible")]
+    visible: Option<u8>,
+    #[serde(rename = "@opacity")]
+    opacity: Option<f32>,
+    #[serde(rename = "@tintcolor")]
+    tintcolor: Option<String>,
+
     #[serde(default)]
     object: Vec<ObjectXml>,
 }
 
+#[derive(Debug, Deserialize)]
+struct GroupXml {
+    #[serde(rename = "@id")]
+    id: u32,
+    #[serde(rename = "@name")]
+    name: Option<String>,
+    #[serde(rename = "@visible")]
+    visible: Option<u8>,
+    #[serde(rename = "@opacity")]
+    opacity: Option<f32>,
+    #[serde(rename = "@tintcolor")]
+    tintcolor: Option<String>,
+    #[serde(rename = "@offsetx")]
+    offsetx: Option<f32>,
+    #[serde(rename = "@offsety")]
+    offsety: Option<f32>,
+
+    #[serde(rename = "$value", default)]
+    fields: Vec<TileMapXmlField>,
+
+    properties: Option<PropertiesXml>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "lowercase")]
 enum TileMapXmlField {
@@ -101,6 +187,7 @@ enum TileMapXmlField {
     ObjectGroup(ObjectGroupXml),
     Layer(LayerXml),
     ImageLayer(ImageLayerXml),
+    Group(GroupXml),
 }
 
 fn default_backgroundcolor() -> String {
@@ -109,6 +196,8 @@ fn default_backgroundcolor() -> String {
 
 #[derive(Debug, Deserialize)]
 struct TileMapXml {
+    #[serde(rename = "@orientation")]
+    orientation: Option<String>,
     #[serde(rename = "@width")]
     width: i32,
     #[serde(rename = "@height")]
@@ -126,6 +215,19 @@ struct TileMapXml {
     properties: Option<PropertiesXml>,
 }
 
+/// The bits Tiled sets in the high end of a gid to mark a tile as flipped or rotated.
+const FLIPPED_HORIZONTALLY_FLAG: u32 = 0x80000000;
+const FLIPPED_VERTICALLY_FLAG: u32 = 0x40000000;
+const GID_FLAGS_MASK: u32 = 0xF0000000;
+
+/// Splits a raw gid from a Tiled file into the tile index and its flip flags.
+fn split_gid(raw: u32) -> (TileIndex, u32) {
+    (
+        TileIndex((raw & !GID_FLAGS_MASK) as usize),
+        raw & GID_FLAGS_MASK,
+    )
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct TileIndex(usize);
 
@@ -149,7 +251,148 @@ impl FromStr for TileIndex {
     }
 }
 
+/// The visibility, opacity, and tint color that Tiled allows on every kind of layer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LayerStyle {
+    visible: bool,
+    opacity: f32,
+    tint: Option<Color>,
+}
+
+impl Default for LayerStyle {
+    fn default() -> Self {
+        LayerStyle {
+            visible: true,
+            opacity: 1.0,
+            tint: None,
+        }
+    }
+}
+
+impl LayerStyle {
+    fn from_xml(
+        visible: Option<u8>,
+        opacity: Option<f32>,
+        tintcolor: Option<&str>,
+    ) -> Result<LayerStyle> {
+        let tint = tintcolor
+            .map(|color| {
+                color
+                    .parse()
+                    .context(format!("parsing tint color {:?}", color))
+            })
+            .transpose()?;
+        Ok(LayerStyle {
+            visible: visible.unwrap_or(1) != 0,
+            opacity: opacity.unwrap_or(1.0).clamp(0.0, 1.0),
+            tint,
+        })
+    }
+
+    /// Combines a group's style with the style of a layer inside it, the way Tiled does.
+    fn inherit(&self, child: &LayerStyle) -> LayerStyle {
+        let tint = match (self.tint, child.tint) {
+            (Some(a), Some(b)) => Some(Color {
+                r: (a.r as u32 * b.r as u32 / 255) as u8,
+                g: (a.g as u32 * b.g as u32 / 255) as u8,
+                b: (a.b as u32 * b.b as u32 / 255) as u8,
+                a: (a.a as u32 * b.a as u32 / 255) as u8,
+            }),
+            (a, b) => b.or(a),
+        };
+        LayerStyle {
+            visible: self.visible && child.visible,
+            opacity: self.opacity * child.opacity,
+            tint,
+        }
+    }
+
+    /// The color to multiply the layer's sprites by, with the opacity folded into the alpha.
+    fn color(&self) -> Color {
+        let tint = self.tint.unwrap_or(Color::WHITE);
+        Color {
+            a: (tint.a as f32 * self.opacity).round() as u8,
+            ..tint
+        }
+    }
+
+    fn write_xml(&self, out: &mut String) -> Result<()> {
+        if !self.visible {
+            write!(out, r#" visible="0""#)?;
+        }
+        if self.opacity != 1.0 {
+            write!(out, r#" opacity="{}""#, self.opacity)?;
+        }
+        if let Some(tint) = self.tint {
+            write!(out, r#" tintcolor="{}""#, tint)?;
+        }
+        Ok(())
+    }
+}
+
+/// How a map's tiles are laid out on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Orthogonal,
+    /// Diamond-shaped tiles, with each row running down and to the left.
+    Isometric,
+}
+
+impl FromStr for Orientation {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "orthogonal" => Orientation::Orthogonal,
+            "isometric" => Orientation::Isometric,
+            "staggered" | "hexagonal" => bail!("unsupported map orientation: {}", s),
+            _ => bail!("invalid map orientation: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Orientation::Orthogonal => write!(f, "orthogonal"),
+            Orientation::Isometric => write!(f, "isometric"),
+        }
+    }
+}
+
+/// How an image layer is scaled to the area it's drawn in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFit {
+    /// Draw at the image's native size, at the map offset.
+    None,
+    /// Scale to fit entirely inside the area, centered.
+    Fit,
+    /// Scale to cover the entire area, centered.
+    Cover,
+}
+
+impl FromStr for ImageFit {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "none" => ImageFit::None,
+            "fit" => ImageFit::Fit,
+            "cover" => ImageFit::Cover,
+            _ => bail!("invalid image fit: {}", s),
+        })
+    }
+}
+
 struct ImageLayer {
+    id: i32,
+    name: Option<String>,
+    source: String,
+    offsetx: Option<String>,
+    offsety: Option<String>,
+    style: LayerStyle,
+    repeat_x: bool,
+    repeat_y: bool,
+    fit: ImageFit,
+    properties: PropertyMap,
     surface: Sprite,
 }
 
@@ -162,19 +405,126 @@ impl ImageLayer {
         let path = path
             .parent()
             .context("xml file is root")?
-            .join(xml.image.source);
+            .join(&xml.image.source);
         let surface = images.load_sprite(&path)?;
-        Ok(ImageLayer { surface })
+        let properties: PropertyMap = xml
+            .properties
+            .map(|x| x.try_into())
+            .transpose()?
+            .unwrap_or_default();
+        let fit = properties
+            .get_string("fit")?
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(ImageFit::None);
+        let style = LayerStyle::from_xml(xml.visible, xml.opacity, xml.tintcolor.as_deref())?;
+        Ok(ImageLayer {
+            id: xml.id,
+            name: xml.name,
+            source: xml.image.source,
+            offsetx: xml.offsetx,
+            offsety: xml.offsety,
+            style,
+            repeat_x: xml.repeatx.unwrap_or(0) != 0,
+            repeat_y: xml.repeaty.unwrap_or(0) != 0,
+            fit,
+            properties,
+            surface,
+        })
+    }
+
+    /// Returns where to draw each copy of the image, taking into account fit and repeat modes.
+    fn destinations(&self, dest: Rect<i32>, offset: Point<i32>) -> Vec<Rect<i32>> {
+        let image_w = self.surface.area.w;
+        let image_h = self.surface.area.h;
+        if image_w <= 0 || image_h <= 0 {
+            return Vec::new();
+        }
+
+        let scale_x = dest.w as f32 / image_w as f32;
+        let scale_y = dest.h as f32 / image_h as f32;
+        let scale = match self.fit {
+            ImageFit::None => 1.0,
+            ImageFit::Fit => scale_x.min(scale_y),
+            ImageFit::Cover => scale_x.max(scale_y),
+        };
+        let w = ((image_w as f32 * scale).round() as i32).max(1);
+        let h = ((image_h as f32 * scale).round() as i32).max(1);
+        let (x, y) = match self.fit {
+            ImageFit::None => (offset.x, offset.y),
+            ImageFit::Fit | ImageFit::Cover => {
+                (dest.x + (dest.w - w) / 2, dest.y + (dest.h - h) / 2)
+            }
+        };
+
+        // When repeating, back up to the first copy that's at least partly visible.
+        let (x, end_x) = if self.repeat_x {
+            (x - (x - dest.x).div_euclid(w) * w - w, dest.right())
+        } else {
+            (x, x + 1)
+        };
+        let (y, end_y) = if self.repeat_y {
+            (y - (y - dest.y).div_euclid(h) * h - h, dest.bottom())
+        } else {
+            (y, y + 1)
+        };
+
+        let mut rects = Vec::new();
+        for row_y in (y..end_y).step_by(h as usize) {
+            for col_x in (x..end_x).step_by(w as usize) {
+                let rect = Rect {
+                    x: col_x,
+                    y: row_y,
+                    w,
+                    h,
+                };
+                if (!self.repeat_x && !self.repeat_y) || rect.intersects(dest) {
+                    rects.push(rect);
+                }
+            }
+        }
+        rects
+    }
+
+    fn write_xml(&self, out: &mut String, indent: &str) -> Result<()> {
+        write!(out, r#"{indent}<imagelayer id="{}""#, self.id)?;
+        if let Some(name) = &self.name {
+            write!(out, r#" name="{}""#, escape(name))?;
+        }
+        self.style.write_xml(out)?;
+        if let Some(offsetx) = &self.offsetx {
+            write!(out, r#" offsetx="{}""#, escape(offsetx))?;
+        }
+        if let Some(offsety) = &self.offsety {
+            write!(out, r#" offsety="{}""#, escape(offsety))?;
+        }
+        if self.repeat_x {
+            write!(out, r#" repeatx="1""#)?;
+        }
+        if self.repeat_y {
+            write!(out, r#" repeaty="1""#)?;
+        }
+        writeln!(out, ">")?;
+        self.properties.write_xml(out, &format!("{indent} "))?;
+        writeln!(
+            out,
+            r#"{indent} <image source="{}"/>"#,
+            escape(&self.source)
+        )?;
+        writeln!(out, "{indent}</imagelayer>")?;
+        Ok(())
     }
 }
 
 struct TileLayer {
-    _id: u32,
-    _name: String,
-    _width: u32,
-    _height: u32,
+    id: u32,
+    name: String,
+    width: u32,
+    height: u32,
+    style: LayerStyle,
     data: Vec<Vec<TileIndex>>,
     player: bool,
+    properties: PropertyMap,
 }
 
 impl TileLayer {
@@ -183,6 +533,7 @@ impl TileLayer {
         let name = xml.name;
         let width = xml.width;
         let height = xml.height;
+        let style = LayerStyle::from_xml(xml.visible, xml.opacity, xml.tintcolor.as_deref())?;
 
         let props: Option<PropertyMap> = xml.properties.map(|x| x.try_into()).transpose()?;
         let props = props.unwrap_or_default();
@@ -211,15 +562,54 @@ impl TileLayer {
         }
 
         Ok(TileLayer {
-            _id: id,
-            _name: name,
-            _width: width,
-            _height: height,
+            id,
+            name,
+            width,
+            height,
+            style,
             data,
             player,
+            properties: props,
         })
     }
 
+    /// Fails if any tile isn't in one of the map's tilesets, so drawing doesn't have to check.
+    fn check_tiles(&self, tilesets: &TileSetList) -> Result<()> {
+        for (row, tiles) in self.data.iter().enumerate() {
+            for (col, index) in tiles.iter().enumerate() {
+                if index.0 != 0 {
+                    tilesets.lookup(*index).with_context(|| {
+                        anyhow!("in layer {:?} at row {}, col {}", self.name, row, col)
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_xml(&self, out: &mut String, indent: &str) -> Result<()> {
+        write!(
+            out,
+            r#"{indent}<layer id="{}" name="{}" width="{}" height="{}""#,
+            self.id,
+            escape(&self.name),
+            self.width,
+            self.height
+        )?;
+        self.style.write_xml(out)?;
+        writeln!(out, ">")?;
+        self.properties.write_xml(out, &format!("{indent} "))?;
+        writeln!(out, r#"{indent} <data encoding="csv">"#)?;
+        for (i, row) in self.data.iter().enumerate() {
+            let row: Vec<String> = row.iter().map(|index| index.0.to_string()).collect();
+            let separator = if i + 1 < self.data.len() { "," } else { "" };
+            writeln!(out, "{}{}", row.join(","), separator)?;
+        }
+        writeln!(out, "</data>")?;
+        writeln!(out, "{indent}</layer>")?;
+        Ok(())
+    }
+
     fn get(&self, row: usize, col: usize) -> Option<&TileIndex> {
         self.data.get(row).and_then(|r| r.get(col))
     }
@@ -246,9 +636,229 @@ impl IndexMut<(usize, usize)> for TileLayer {
     }
 }
 
+/// An object group, whose objects are stored in TileMap::objects.
+struct ObjectLayer {
+    id: Option<u32>,
+    name: Option<String>,
+    style: LayerStyle,
+    objects: Range<usize>,
+}
+
+/// A Tiled group, whose offset and style apply to every layer inside it.
+struct GroupLayer {
+    id: u32,
+    name: Option<String>,
+    offset: Point<i32>,
+    style: LayerStyle,
+    properties: PropertyMap,
+    layers: Vec<Layer>,
+}
+
 enum Layer {
     Tile(TileLayer),
     Image(ImageLayer),
+    Object(ObjectLayer),
+    Group(GroupLayer),
+}
+
+impl Layer {
+    fn id(&self) -> i32 {
+        match self {
+            Layer::Tile(layer) => layer.id as i32,
+            Layer::Image(layer) => layer.id,
+            Layer::Object(layer) => layer.id.unwrap_or(0) as i32,
+            Layer::Group(group) => group.id as i32,
+        }
+    }
+
+    fn name(&self) -> Option<&str> {
+        match self {
+            Layer::Tile(layer) => Some(&layer.name),
+            Layer::Image(layer) => layer.name.as_deref(),
+            Layer::Object(layer) => layer.name.as_deref(),
+            Layer::Group(group) => group.name.as_deref(),
+        }
+    }
+
+    fn style(&self) -> &LayerStyle {
+        match self {
+            Layer::Tile(layer) => &layer.style,
+            Layer::Image(layer) => &layer.style,
+            Layer::Object(layer) => &layer.style,
+            Layer::Group(group) => &group.style,
+        }
+    }
+
+    fn style_mut(&mut self) -> &mut LayerStyle {
+        match self {
+            Layer::Tile(layer) => &mut layer.style,
+            Layer::Image(layer) => &mut layer.style,
+            Layer::Object(layer) => &mut layer.style,
+            Layer::Group(group) => &mut group.style,
+        }
+    }
+
+    /// The largest layer id in the layers, including inside groups.
+    fn max_id(layers: &[Layer]) -> i32 {
+        layers
+            .iter()
+            .map(|layer| match layer {
+                Layer::Group(group) => group.id.max(Layer::max_id(&group.layers) as u32) as i32,
+                _ => layer.id(),
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Finds the first layer with the given name, searching inside groups.
+    fn find<'a>(layers: &'a [Layer], name: &str) -> Option<&'a Layer> {
+        for layer in layers {
+            if layer.name() == Some(name) {
+                return Some(layer);
+            }
+            if let Layer::Group(group) = layer {
+                if let Some(layer) = Layer::find(&group.layers, name) {
+                    return Some(layer);
+                }
+            }
+        }
+        None
+    }
+
+    /// Finds the tile layer the player is on, or the first tile layer if none is marked.
+    fn find_player_layer(layers: &[Layer]) -> Option<&TileLayer> {
+        let mut tile_layers = Vec::new();
+        Layer::collect_tile_layers(layers, &mut tile_layers);
+        tile_layers
+            .iter()
+            .find(|layer| layer.player)
+            .or(tile_layers.first())
+            .copied()
+    }
+
+    fn collect_tile_layers<'a>(layers: &'a [Layer], out: &mut Vec<&'a TileLayer>) {
+        for layer in layers {
+            match layer {
+                Layer::Tile(layer) => out.push(layer),
+                Layer::Group(group) => Layer::collect_tile_layers(&group.layers, out),
+                _ => {}
+            }
+        }
+    }
+
+    /// Sets the visibility of every layer with the given name, returning whether there were any.
+    fn set_visible(layers: &mut [Layer], name: &str, visible: bool) -> bool {
+        let mut found = false;
+        for layer in layers.iter_mut() {
+            if layer.name() == Some(name) {
+                layer.style_mut().visible = visible;
+                found = true;
+            }
+            if let Layer::Group(group) = layer {
+                found |= Layer::set_visible(&mut group.layers, name, visible);
+            }
+        }
+        found
+    }
+}
+
+/// Loads the layers of a map, recursing into groups and collecting the objects from every layer.
+struct LayerLoader<'a> {
+    path: &'a Path,
+    files: &'a FileManager,
+    images: &'a mut dyn ImageLoader,
+    tilesets: &'a TileSetList,
+    tileset_sources: &'a [TileSetSourceXml],
+    objects: Vec<MapObject>,
+    templates: HashMap<String, ObjectTemplate>,
+    /// The number of layers loaded so far, not counting groups.
+    layer_count: i32,
+    player_layer: Option<i32>,
+}
+
+impl LayerLoader<'_> {
+    fn load(&mut self, fields: Vec<TileMapXmlField>) -> Result<Vec<Layer>> {
+        let mut layers = Vec::new();
+        for field in fields {
+            let layer = match field {
+                TileMapXmlField::Layer(layer) => {
+                    let layer = TileLayer::from_xml(layer)?;
+                    layer.check_tiles(self.tilesets)?;
+                    if layer.player {
+                        if self.player_layer.is_some() {
+                            bail!("too many player layers");
+                        }
+                        self.player_layer = Some(self.layer_count);
+                    }
+                    Layer::Tile(layer)
+                }
+                TileMapXmlField::ImageLayer(layer) => {
+                    Layer::Image(ImageLayer::from_xml(layer, self.path, self.images)?)
+                }
+                TileMapXmlField::ObjectGroup(group) => Layer::Object(self.load_objects(group)?),
+                TileMapXmlField::Group(group) => Layer::Group(self.load_group(group)?),
+                TileMapXmlField::TileSet(_) => continue,
+            };
+            if !matches!(layer, Layer::Group(_)) {
+                self.layer_count += 1;
+            }
+            layers.push(layer);
+        }
+        Ok(layers)
+    }
+
+    fn load_group(&mut self, xml: GroupXml) -> Result<GroupLayer> {
+        Ok(GroupLayer {
+            id: xml.id,
+            name: xml.name,
+            offset: Point::new(
+                xml.offsetx.unwrap_or(0.0) as i32,
+                xml.offsety.unwrap_or(0.0) as i32,
+            ),
+            style: LayerStyle::from_xml(xml.visible, xml.opacity, xml.tintcolor.as_deref())?,
+            properties: xml
+                .properties
+                .map(|x| x.try_into())
+                .transpose()?
+                .unwrap_or_default(),
+            layers: self.load(xml.fields)?,
+        })
+    }
+
+    fn load_objects(&mut self, group: ObjectGroupXml) -> Result<ObjectLayer> {
+        let start = self.objects.len();
+        for object in group.object {
+            let template = match &object.template {
+                Some(source) => {
+                    if !self.templates.contains_key(source) {
+                        let template_path = self
+                            .path
+                            .parent()
+                            .context("cannot load root as map")?
+                            .join(source);
+                        let template = ObjectTemplate::from_file(
+                            &template_path,
+                            self.path,
+                            self.tileset_sources,
+                            self.files,
+                        )?;
+                        self.templates.insert(source.clone(), template);
+                    }
+                    self.templates.get(source)
+                }
+                None => None,
+            };
+            self.objects
+                .push(MapObject::new(object, self.tilesets, template)?);
+        }
+        let style = LayerStyle::from_xml(group.visible, group.opacity, group.tintcolor.as_deref())?;
+        Ok(ObjectLayer {
+            id: group.id,
+            name: group.name,
+            style,
+            objects: start..self.objects.len(),
+        })
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -315,6 +925,35 @@ pub struct MapObjectProperties {
     // Map Areas
     pub preferred_x: Option<i32>,
     pub preferred_y: Option<i32>,
+    /// Where the player starts.
+    pub spawn: bool,
+    /// Whether a tile object in a raycast level is an enemy, which aim assist aims at.
+    pub enemy: bool,
+    /// The side a character in a raycast level is on. Enemies are monsters unless it says
+    /// otherwise.
+    pub faction: Option<Faction>,
+    /// Which type of enemy a character is, or a spawner spawns, for looking up its stats.
+    pub enemy_type: Option<String>,
+    /// A color to tint a tile object's sprite with, like "#8080ff" for a team color. Enemies
+    /// get it on top of their type's tint.
+    pub tint: Option<Color>,
+    /// Words to show standing in the world, like on a sign, drawn in the tint color.
+    pub text: Option<String>,
+    /// The name of a tutorial prompt to show when the player walks into the object's area.
+    pub tutorial: Option<String>,
+    /// An event for the level script when the player walks into the object's area, like
+    /// "ambush" for `on_trigger_ambush()`.
+    pub trigger: Option<String>,
+    /// What a spawner object spawns, and when.
+    pub spawner: Option<SpawnerSettings>,
+    /// A static light, like a torch or a lamp, that's baked into the level's lightmap.
+    pub light: Option<StaticLight>,
+    /// The ability the player unlocks by picking up the object.
+    pub pickup: Option<Ability>,
+    /// The item the player puts in their inventory by picking up the object.
+    pub item: Option<String>,
+    /// Whether the player can use items on the object, and which.
+    pub target: Option<ItemTarget>,
     // UI elements
     pub uibutton: bool,
     pub action: Option<String>,
@@ -329,6 +968,25 @@ impl TryFrom<PropertyMap> for MapObjectProperties {
             solid: properties.get_bool("solid")?.unwrap_or(false),
             preferred_x: properties.get_int("preferred_x")?,
             preferred_y: properties.get_int("preferred_y")?,
+            spawn: properties.get_bool("spawn")?.unwrap_or(false),
+            enemy: properties.get_bool("enemy")?.unwrap_or(false),
+            faction: properties
+                .get_string("faction")?
+                .map(str::parse)
+                .transpose()?,
+            enemy_type: properties.get_string("enemy_type")?.map(str::to_string),
+            tint: properties.get_string("tint")?.map(str::parse).transpose()?,
+            text: properties.get_string("text")?.map(str::to_string),
+            tutorial: properties.get_string("tutorial")?.map(str::to_string),
+            trigger: properties.get_string("trigger")?.map(str::to_string),
+            spawner: SpawnerSettings::from_properties(&properties)?,
+            light: StaticLight::from_properties(&properties)?,
+            pickup: properties
+                .get_string("pickup")?
+                .map(str::parse)
+                .transpose()?,
+            item: properties.get_string("item")?.map(str::to_string),
+            target: ItemTarget::from_properties(&properties)?,
             uibutton: properties.get_bool("uibutton")?.unwrap_or(false),
             label: properties.get_string("label")?.unwrap_or("").to_string(),
             action: properties.get_string("action")?.map(str::to_string),
@@ -337,29 +995,129 @@ impl TryFrom<PropertyMap> for MapObjectProperties {
     }
 }
 
+/// A Tiled object template (.tx file) that objects in a map can inherit from.
+#[derive(Debug, Clone)]
+struct ObjectTemplate {
+    /// The template's tile, already translated into the gids of the map using it.
+    gid: Option<TileIndex>,
+    gid_flags: u32,
+    width: Option<i32>,
+    height: Option<i32>,
+    properties: PropertyMap,
+}
+
+impl ObjectTemplate {
+    fn from_file(
+        path: &Path,
+        map_path: &Path,
+        map_tilesets: &[TileSetSourceXml],
+        files: &FileManager,
+    ) -> Result<ObjectTemplate> {
+        info!("loading object template from {:?}", path);
+        let text = files
+            .read_to_string(path)
+            .map_err(|e| anyhow!("unable to open {:?}: {}", path, e))?;
+        let xml = quick_xml::de::from_str::<TemplateXml>(&text)?;
+
+        // The template's gid is relative to its own tileset, so find the same tileset in the map.
+        let (gid, gid_flags) = match xml.object.gid.map(split_gid) {
+            Some((gid, flags)) => (Some(gid), flags),
+            None => (None, 0),
+        };
+        let gid = match (gid, &xml.tileset) {
+            (Some(gid), Some(tileset)) => {
+                let tileset_path = normalize_path(
+                    &path
+                        .parent()
+                        .context("template is root")?
+                        .join(&tileset.source),
+                )?;
+                let map_dir = map_path.parent().context("map is root")?;
+                let mut map_gid = None;
+                for map_tileset in map_tilesets {
+                    if normalize_path(&map_dir.join(&map_tileset.source))? == tileset_path {
+                        map_gid = Some(map_tileset.firstgid + gid.0 - tileset.firstgid);
+                    }
+                }
+                let map_gid = map_gid.with_context(|| {
+                    anyhow!(
+                        "map {:?} is missing tileset {:?} for template {:?}",
+                        map_path,
+                        tileset_path,
+                        path
+                    )
+                })?;
+                Some(map_gid.into())
+            }
+            (Some(_), None) => bail!("template {:?} has a gid but no tileset", path),
+            (None, _) => None,
+        };
+
+        Ok(ObjectTemplate {
+            gid,
+            gid_flags,
+            width: xml.object.width,
+            height: xml.object.height,
+            properties: xml
+                .object
+                .properties
+                .map(|x| x.try_into())
+                .transpose()?
+                .unwrap_or_default(),
+        })
+    }
+}
+
 pub struct MapObject {
     pub id: i32,
     pub gid: Option<TileIndex>,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
     pub position: Rect<i32>,
     pub properties: MapObjectProperties,
+    gid_flags: u32,
+    /// The properties set on the object itself, without defaults from its template or tile.
+    own_properties: PropertyMap,
+    template: Option<(String, ObjectTemplate)>,
 }
 
 impl MapObject {
-    fn new(xml: ObjectXml, tilesets: &TileSetList) -> Result<MapObject> {
+    fn new(
+        xml: ObjectXml,
+        tilesets: &TileSetList,
+        template: Option<&ObjectTemplate>,
+    ) -> Result<MapObject> {
         let id = xml.id;
         let x = xml.x;
         let mut y = xml.y;
-        let width = xml.width.unwrap_or(0);
-        let height = xml.height.unwrap_or(0);
         let mut properties: PropertyMap = xml
             .properties
             .map(|x| x.try_into())
             .transpose()?
             .unwrap_or_default();
-        let gid = xml.gid.map(|index| (index as usize).into());
+        let own_properties = properties.clone();
+        let (mut gid, mut gid_flags) = match xml.gid.map(split_gid) {
+            Some((gid, flags)) => (Some(gid), flags),
+            None => (None, 0),
+        };
+        let mut width = xml.width;
+        let mut height = xml.height;
+
+        // Anything not set on the object itself is inherited from its template.
+        if let Some(template) = template {
+            properties.set_defaults(&template.properties);
+            if gid.is_none() {
+                gid = template.gid;
+                gid_flags = template.gid_flags;
+            }
+            width = width.or(template.width);
+            height = height.or(template.height);
+        }
+        let width = width.unwrap_or(0);
+        let height = height.unwrap_or(0);
 
         if let Some(gid) = gid {
-            let (tileset, tile_id) = tilesets.lookup(gid);
+            let (tileset, tile_id) = tilesets.lookup(gid)?;
             let defaults = tileset.get_tile_properties(tile_id);
             if let Some(props) = defaults {
                 properties.set_defaults(&props.raw);
@@ -380,10 +1138,63 @@ impl MapObject {
         Ok(MapObject {
             id,
             gid,
+            flip_horizontal: gid_flags & FLIPPED_HORIZONTALLY_FLAG != 0,
+            flip_vertical: gid_flags & FLIPPED_VERTICALLY_FLAG != 0,
+            gid_flags,
             position,
             properties,
+            own_properties,
+            template: xml
+                .template
+                .and_then(|source| template.map(|t| (source, t.clone()))),
         })
     }
+
+    fn write_xml(&self, out: &mut String, indent: &str) -> Result<()> {
+        write!(out, r#"{indent}<object id="{}""#, self.id)?;
+        // Only write the fields that differ from the template, so changes to it are inherited.
+        let template = self.template.as_ref().map(|(source, template)| {
+            (
+                source,
+                template.gid.map(|gid| (gid, template.gid_flags)),
+                template.width.unwrap_or(0),
+                template.height.unwrap_or(0),
+            )
+        });
+        let (inherits_gid, inherits_size) = match template {
+            Some((source, gid, width, height)) => {
+                write!(out, r#" template="{}""#, escape(source))?;
+                (
+                    gid == self.gid.map(|gid| (gid, self.gid_flags)),
+                    width == self.position.w && height == self.position.h,
+                )
+            }
+            None => (false, false),
+        };
+        let mut y = self.position.y;
+        if let Some(gid) = self.gid {
+            if !inherits_gid {
+                write!(out, r#" gid="{}""#, gid.0 as u32 | self.gid_flags)?;
+            }
+            y += self.position.h;
+        }
+        write!(out, r#" x="{}" y="{}""#, self.position.x, y)?;
+        if !inherits_size && (self.position.w != 0 || self.position.h != 0) {
+            write!(
+                out,
+                r#" width="{}" height="{}""#,
+                self.position.w, self.position.h
+            )?;
+        }
+        if self.own_properties.is_empty() {
+            writeln!(out, "/>")?;
+        } else {
+            writeln!(out, ">")?;
+            self.own_properties.write_xml(out, &format!("{indent} "))?;
+            writeln!(out, "{indent}</object>")?;
+        }
+        Ok(())
+    }
 }
 
 struct TileSetList {
@@ -402,13 +1213,13 @@ impl TileSetList {
         self.tilesets.sort_by_key(|tileset| tileset.gid_sort_key());
     }
 
-    fn lookup(&self, tile_gid: TileIndex) -> (&TileSet, LocalTileIndex) {
+    fn lookup(&self, tile_gid: TileIndex) -> Result<(&TileSet, LocalTileIndex)> {
         for tileset in self.tilesets.iter() {
             if let Some(tile_id) = tileset.get_local_tile_index(tile_gid) {
-                return (tileset, tile_id);
+                return Ok((tileset, tile_id));
             }
         }
-        panic!("invalid tile_gid {:?}", tile_gid);
+        bail!("no tileset has tile gid {}", tile_gid.0)
     }
 }
 
@@ -416,6 +1227,24 @@ pub struct TileMapProperties {
     pub dark: bool,
     pub gravity: Option<i32>,
     pub cancel_action: String,
+    /// The name shown on the map's title card, if it has one.
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    /// How long a day lasts in the map, in seconds.
+    pub day_length: Option<u32>,
+    /// The hour of the day the map starts at.
+    pub start_hour: Option<u32>,
+    /// How the lighting changes over the day, if it does.
+    pub ambient_light: Option<AmbientLight>,
+    pub weather: Option<WeatherSettings>,
+    /// The direction the player starts out facing in a raycast level, in degrees clockwise from
+    /// east.
+    pub start_angle: Option<i32>,
+    /// What the floor and ceiling look like in a raycast level.
+    pub surfaces: Option<SurfaceSettings>,
+    /// How lit cells none of a raycast level's static lights reach are, from 0 to 1.
+    pub lightmap_ambient: Option<f32>,
+    pub raw: PropertyMap,
 }
 
 impl TryFrom<PropertyMap> for TileMapProperties {
@@ -428,6 +1257,18 @@ impl TryFrom<PropertyMap> for TileMapProperties {
                 .get_string("cancel_action")?
                 .unwrap_or("pop")
                 .to_string(),
+            title: properties.get_string("title")?.map(str::to_string),
+            subtitle: properties.get_string("subtitle")?.map(str::to_string),
+            day_length: properties.get_int("day_length")?.map(|x| x.max(1) as u32),
+            start_hour: properties.get_int("start_hour")?.map(|x| x.max(0) as u32),
+            ambient_light: AmbientLight::from_properties(&properties)?,
+            weather: WeatherSettings::from_properties(&properties)?,
+            start_angle: properties.get_int("start_angle")?,
+            surfaces: SurfaceSettings::from_properties(&properties)?,
+            lightmap_ambient: properties
+                .get_int("lightmap_ambient")?
+                .map(|x| x.clamp(0, 100) as f32 / 100.0),
+            raw: properties,
         })
     }
 }
@@ -437,8 +1278,10 @@ pub struct TileMap {
     pub height: i32,
     pub tilewidth: i32,
     pub tileheight: i32,
+    pub orientation: Orientation,
     backgroundcolor: Color,
     tilesets: TileSetList,
+    tileset_sources: Vec<TileSetSourceXml>,
     layers: Vec<Layer>,
     player_layer: Option<i32>, // TODO: Should just be i32.
     pub objects: Vec<MapObject>,
@@ -451,6 +1294,7 @@ impl TileMap {
         files: &FileManager,
         images: &mut dyn ImageLoader,
     ) -> Result<TileMap> {
+        profile_scope!("load_tilemap", path = ?path);
         info!("loading tilemap from {:?}", path);
         let text = files
             .read_to_string(path)
@@ -469,53 +1313,63 @@ impl TileMap {
         let height = xml.height;
         let tilewidth = xml.tilewidth;
         let tileheight = xml.tileheight;
+        let orientation = match &xml.orientation {
+            Some(orientation) => orientation
+                .parse()
+                .with_context(|| anyhow!("loading map {:?}", path))?,
+            None => Orientation::Orthogonal,
+        };
         let backgroundcolor = xml.backgroundcolor.parse().context(format!(
             "parsing background color {:?}",
             &xml.backgroundcolor
         ))?;
 
-        let mut tilesets = TileSetList::new();
+        // The tilesets are read in order and parsed in parallel, and then their images are
+        // loaded in order again.
+        let mut tileset_sources = Vec::new();
+        let mut tileset_texts = Vec::new();
         for field in xml.fields.iter() {
             if let TileMapXmlField::TileSet(tileset) = field {
-                let firstgid = tileset.firstgid.into();
+                tileset_sources.push(tileset.clone());
                 let tileset_path = path
                     .parent()
                     .context("cannot load root as map")?
                     .join(tileset.source.clone());
-                let tileset = TileSet::from_file(&tileset_path, firstgid, files, images)?;
-                tilesets.add(tileset);
+                info!("loading tileset from {:?}", tileset_path);
+                let text = files
+                    .read_to_string(&tileset_path)
+                    .map_err(|e| anyhow!("unable to open {:?}: {}", tileset_path, e))?;
+                tileset_texts.push((tileset_path, text));
             }
         }
+        let parsed = DecodePool::new().decode_all(tileset_texts, |(tileset_path, text)| {
+            let xml = TileSetXml::parse(&text)
+                .with_context(|| anyhow!("parsing tileset {:?}", tileset_path));
+            (tileset_path, xml)
+        });
+        let mut tilesets = TileSetList::new();
+        for (source, (tileset_path, xml)) in tileset_sources.iter().zip(parsed) {
+            let firstgid = source.firstgid.into();
+            tilesets.add(TileSet::from_xml(xml?, &tileset_path, firstgid, images)?);
+        }
         if tilesets.tilesets.is_empty() {
             bail!("at least one tileset must be present");
         }
 
-        let mut player_layer: Option<i32> = None;
-        let mut layers = Vec::new();
-        let mut objects: Vec<MapObject> = Vec::new();
-        for field in xml.fields {
-            match field {
-                TileMapXmlField::Layer(layer) => {
-                    let layer = TileLayer::from_xml(layer)?;
-                    if layer.player {
-                        if player_layer.is_some() {
-                            bail!("too many player layers");
-                        }
-                        player_layer = Some(layers.len() as i32);
-                    }
-                    layers.push(Layer::Tile(layer));
-                }
-                TileMapXmlField::ImageLayer(layer) => {
-                    layers.push(Layer::Image(ImageLayer::from_xml(layer, path, images)?));
-                }
-                TileMapXmlField::ObjectGroup(group) => {
-                    for object in group.object {
-                        objects.push(MapObject::new(object, &tilesets)?);
-                    }
-                }
-                _ => {}
-            }
-        }
+        let mut loader = LayerLoader {
+            path,
+            files,
+            images,
+            tilesets: &tilesets,
+            tileset_sources: &tileset_sources,
+            objects: Vec::new(),
+            templates: HashMap::new(),
+            layer_count: 0,
+            player_layer: None,
+        };
+        let layers = loader.load(xml.fields)?;
+        let objects = loader.objects;
+        let player_layer = loader.player_layer;
 
         let properties = if let Some(props) = xml.properties {
             props.try_into()?
@@ -530,8 +1384,10 @@ impl TileMap {
             height,
             tilewidth,
             tileheight,
+            orientation,
             backgroundcolor,
             tilesets,
+            tileset_sources,
             layers,
             player_layer,
             objects,
@@ -544,22 +1400,18 @@ impl TileMap {
         layer: &ImageLayer,
         context: &mut RenderContext,
         render_layer: RenderLayer,
-        _dest: Rect<i32>,
+        dest: Rect<i32>,
         offset: Point<i32>,
     ) {
-        let dest = Rect {
-            x: offset.x,
-            y: offset.y,
-            w: layer.surface.area.w,
-            h: layer.surface.area.h,
-        };
         let source = Rect {
             x: 0,
             y: 0,
             w: layer.surface.area.w,
             h: layer.surface.area.h,
         };
-        context.draw(layer.surface, render_layer, dest, source);
+        for destination in layer.destinations(dest, offset) {
+            context.draw(layer.surface, render_layer, destination, source);
+        }
     }
 
     fn draw_tile_layer(
@@ -569,6 +1421,76 @@ impl TileMap {
         render_layer: RenderLayer,
         dest: Rect<i32>,
         offset: Point<i32>,
+    ) {
+        match self.orientation {
+            Orientation::Orthogonal => {
+                self.draw_orthogonal_tile_layer(layer, context, render_layer, dest, offset)
+            }
+            Orientation::Isometric => {
+                self.draw_isometric_tile_layer(layer, context, render_layer, dest, offset)
+            }
+        }
+    }
+
+    /// The top left of the box around an isometric tile's diamond, relative to the map.
+    ///
+    /// Row 0, col 0 is the top corner of the map, centered horizontally in the map's bounds.
+    fn isometric_tile_position(&self, row: i32, col: i32) -> Point<i32> {
+        Point::new(
+            (col - row + self.height - 1) * self.tilewidth / 2,
+            (col + row) * self.tileheight / 2,
+        )
+    }
+
+    fn draw_isometric_tile_layer(
+        &self,
+        layer: &TileLayer,
+        context: &mut RenderContext,
+        render_layer: RenderLayer,
+        dest: Rect<i32>,
+        offset: Point<i32>,
+    ) {
+        // Tiles are drawn in row order, so the ones in front overlap the ones behind them.
+        // They don't line up with the edges of dest, so they're culled rather than trimmed.
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let index = layer.data[row as usize][col as usize];
+                if index.0 == 0 {
+                    continue;
+                }
+                let (tileset, tile_id) = self
+                    .tilesets
+                    .lookup(index)
+                    .expect("tiles were checked at load");
+                let source = tileset.get_source_rect(tile_id);
+
+                // Tiles taller than the grid stick up, since their bottoms line up with it.
+                let position = self.isometric_tile_position(row, col);
+                let destination = Rect {
+                    x: position.x + dest.x + offset.x,
+                    y: position.y + self.tileheight - source.h + dest.y + offset.y,
+                    w: source.w,
+                    h: source.h,
+                };
+                if !destination.intersects(dest) {
+                    continue;
+                }
+                if let Some(animation) = self.get_animation(index) {
+                    animation.blit(context, render_layer, destination, false);
+                } else {
+                    context.draw(tileset.sprite, render_layer, destination, source);
+                }
+            }
+        }
+    }
+
+    fn draw_orthogonal_tile_layer(
+        &self,
+        layer: &TileLayer,
+        context: &mut RenderContext,
+        render_layer: RenderLayer,
+        dest: Rect<i32>,
+        offset: Point<i32>,
     ) {
         let offset_x = offset.x;
         let offset_y = offset.y;
@@ -603,7 +1525,10 @@ impl TileMap {
                     continue;
                 }
 
-                let (tileset, tile_id) = self.tilesets.lookup(index);
+                let (tileset, tile_id) = self
+                    .tilesets
+                    .lookup(index)
+                    .expect("tiles were checked at load");
 
                 let mut source = tileset.get_source_rect(tile_id);
                 let mut pos_x = tilewidth * col + dest.x + offset_x;
@@ -660,38 +1585,184 @@ impl TileMap {
         }
     }
 
+    /// Draws the tile objects in the layer, like decorations placed in Tiled.
+    fn draw_object_layer(
+        &self,
+        layer: &ObjectLayer,
+        context: &mut RenderContext,
+        render_layer: RenderLayer,
+        dest: Rect<i32>,
+        offset: Point<i32>,
+    ) {
+        for object in self.objects[layer.objects.clone()].iter() {
+            let Some(gid) = object.gid else {
+                continue;
+            };
+            // Buttons draw themselves.
+            if object.properties.uibutton {
+                continue;
+            }
+            let destination = Rect {
+                x: object.position.x + dest.x + offset.x,
+                y: object.position.y + dest.y + offset.y,
+                w: object.position.w,
+                h: object.position.h,
+            };
+            if !destination.intersects(dest) {
+                continue;
+            }
+            if object.flip_vertical {
+                // Only transformed sprites can be flipped top to bottom.
+                let transform = SpriteTransform {
+                    flip_horizontal: object.flip_horizontal,
+                    flip_vertical: true,
+                    ..Default::default()
+                };
+                if let Some(animation) = self.get_animation(gid) {
+                    animation.blit_transformed(context, render_layer, destination, transform);
+                } else {
+                    let (tileset, tile_id) = self
+                        .tilesets
+                        .lookup(gid)
+                        .expect("objects were checked at load");
+                    let source = tileset.get_source_rect(tile_id);
+                    context.draw_transformed(
+                        tileset.sprite,
+                        render_layer,
+                        destination,
+                        source,
+                        transform,
+                    );
+                }
+            } else if let Some(animation) = self.get_animation(gid) {
+                animation.blit(context, render_layer, destination, object.flip_horizontal);
+            } else {
+                let (tileset, tile_id) = self
+                    .tilesets
+                    .lookup(gid)
+                    .expect("objects were checked at load");
+                let source = tileset.get_source_rect(tile_id);
+                if object.flip_horizontal {
+                    context.draw_reversed(tileset.sprite, render_layer, destination, source);
+                } else {
+                    context.draw(tileset.sprite, render_layer, destination, source);
+                }
+            }
+        }
+    }
+
     fn draw_layer(
         &self,
         layer: &Layer,
+        style: &LayerStyle,
         context: &mut RenderContext,
         render_layer: RenderLayer,
         dest: Rect<i32>,
         offset: Point<i32>,
     ) {
+        if !style.visible {
+            return;
+        }
+        context.set_tint(render_layer, style.color());
         match layer {
             Layer::Image(layer) => {
                 self.draw_image_layer(layer, context, render_layer, dest, offset)
             }
             Layer::Tile(layer) => self.draw_tile_layer(layer, context, render_layer, dest, offset),
+            Layer::Object(layer) => {
+                self.draw_object_layer(layer, context, render_layer, dest, offset)
+            }
+            Layer::Group(_) => {}
         }
+        context.set_tint(render_layer, Color::WHITE);
     }
 
-    pub fn draw_background(
+    /// Draws the layers on one side of the player layer, recursing into groups.
+    ///
+    /// `passed_player` is set once the player layer is reached, so drawing the background stops
+    /// there, while drawing the foreground starts there.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_layers(
         &self,
+        layers: &[Layer],
+        parent: &LayerStyle,
+        foreground: bool,
+        passed_player: &mut bool,
         context: &mut RenderContext,
         render_layer: RenderLayer,
         dest: Rect<i32>,
         offset: Point<i32>,
     ) {
-        context.fill_rect(dest, render_layer, self.backgroundcolor);
-        for layer in self.layers.iter() {
-            self.draw_layer(layer, context, render_layer, dest, offset);
-            if let Layer::Tile(TileLayer { player: true, .. }) = layer {
+        for layer in layers.iter() {
+            if !foreground && *passed_player {
                 return;
             }
+            let style = parent.inherit(layer.style());
+            match layer {
+                Layer::Group(group) => {
+                    let offset = offset + group.offset;
+                    self.draw_layers(
+                        &group.layers,
+                        &style,
+                        foreground,
+                        passed_player,
+                        context,
+                        render_layer,
+                        dest,
+                        offset,
+                    );
+                }
+                Layer::Tile(TileLayer { player: true, .. }) => {
+                    if !foreground {
+                        self.draw_layer(layer, &style, context, render_layer, dest, offset);
+                    }
+                    *passed_player = true;
+                }
+                _ => {
+                    if foreground == *passed_player {
+                        self.draw_layer(layer, &style, context, render_layer, dest, offset);
+                    }
+                }
+            }
         }
     }
 
+    /// Whether the layer with the given name is drawn, or None if there's no such layer.
+    ///
+    /// This is just the layer's own setting, so a visible layer in a hidden group is still visible.
+    pub fn is_layer_visible(&self, name: &str) -> Option<bool> {
+        Layer::find(&self.layers, name).map(|layer| layer.style().visible)
+    }
+
+    /// Shows or hides every layer or group with the given name, e.g. to reveal a secret area.
+    pub fn set_layer_visible(&mut self, name: &str, visible: bool) -> Result<()> {
+        if !Layer::set_visible(&mut self.layers, name, visible) {
+            bail!("no layer named {:?}", name);
+        }
+        Ok(())
+    }
+
+    pub fn draw_background(
+        &self,
+        context: &mut RenderContext,
+        render_layer: RenderLayer,
+        dest: Rect<i32>,
+        offset: Point<i32>,
+    ) {
+        context.fill_rect(dest, render_layer, self.backgroundcolor);
+        let mut passed_player = false;
+        self.draw_layers(
+            &self.layers,
+            &LayerStyle::default(),
+            false,
+            &mut passed_player,
+            context,
+            render_layer,
+            dest,
+            offset,
+        );
+    }
+
     pub fn draw_foreground(
         &self,
         context: &mut RenderContext,
@@ -702,15 +1773,17 @@ impl TileMap {
         if self.player_layer.is_none() {
             return;
         }
-        let mut drawing = false;
-        for layer in self.layers.iter() {
-            if drawing {
-                self.draw_layer(layer, context, render_layer, dest, offset);
-            }
-            if let Layer::Tile(TileLayer { player: true, .. }) = layer {
-                drawing = true;
-            }
-        }
+        let mut passed_player = false;
+        self.draw_layers(
+            &self.layers,
+            &LayerStyle::default(),
+            true,
+            &mut passed_player,
+            context,
+            render_layer,
+            dest,
+            offset,
+        );
     }
 
     /*
@@ -758,15 +1831,677 @@ impl TileMap {
     }
     */
 
+    /// Writes the map back out as a Tiled tmx file.
+    ///
+    /// Only what the loader understands is written, so anything else in the original file, like
+    /// wang sets or editor settings, is lost.
+    pub fn to_xml(&self) -> Result<String> {
+        let mut out = String::new();
+        let next_layer_id = Layer::max_id(&self.layers) + 1;
+        let next_object_id = self.objects.iter().map(|obj| obj.id).max().unwrap_or(0) + 1;
+
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            out,
+            r#"<map version="1.8" tiledversion="1.8.0" orientation="{}" renderorder="right-down" width="{}" height="{}" tilewidth="{}" tileheight="{}" infinite="0" backgroundcolor="{}" nextlayerid="{}" nextobjectid="{}">"#,
+            self.orientation,
+            self.width,
+            self.height,
+            self.tilewidth,
+            self.tileheight,
+            self.backgroundcolor,
+            next_layer_id,
+            next_object_id
+        )?;
+        self.properties.raw.write_xml(&mut out, " ")?;
+        for tileset in self.tileset_sources.iter() {
+            writeln!(
+                out,
+                r#" <tileset firstgid="{}" source="{}"/>"#,
+                tileset.firstgid,
+                escape(&tileset.source)
+            )?;
+        }
+        self.write_layers(&self.layers, &mut out, " ")?;
+        writeln!(out, "</map>")?;
+        Ok(out)
+    }
+
+    /// Writes the map to a tmx file, so a generated or edited map opens in Tiled. The tilesets it
+    /// refers to are left alone, since they're usually shared with other maps.
+    pub fn save(&self, path: &Path, files: &FileManager) -> Result<()> {
+        files.write(path, self.to_xml()?.as_bytes())
+    }
+
+    /// Writes each of the map's tilesets to the tsx file the map at path says it is in. This
+    /// overwrites tilesets other maps share, so only call it when they've been changed.
+    pub fn save_tilesets(&self, path: &Path, files: &FileManager) -> Result<()> {
+        let dir = path.parent().context("map is root")?;
+        for source in self.tileset_sources.iter() {
+            let firstgid: TileIndex = source.firstgid.into();
+            let tileset = self
+                .tilesets
+                .tilesets
+                .iter()
+                .find(|tileset| tileset.get_global_tile_index(0.into()) == firstgid)
+                .with_context(|| anyhow!("map is missing tileset {:?}", source.source))?;
+            tileset.save(&normalize_path(&dir.join(&source.source))?, files)?;
+        }
+        Ok(())
+    }
+
+    fn write_layers(&self, layers: &[Layer], out: &mut String, indent: &str) -> Result<()> {
+        for layer in layers.iter() {
+            match layer {
+                Layer::Tile(layer) => layer.write_xml(out, indent)?,
+                Layer::Image(layer) => layer.write_xml(out, indent)?,
+                Layer::Object(layer) => {
+                    write!(out, "{indent}<objectgroup")?;
+                    if let Some(id) = layer.id {
+                        write!(out, r#" id="{}""#, id)?;
+                    }
+                    if let Some(name) = &layer.name {
+                        write!(out, r#" name="{}""#, escape(name))?;
+                    }
+                    layer.style.write_xml(out)?;
+                    writeln!(out, ">")?;
+                    for object in self.objects[layer.objects.clone()].iter() {
+                        object.write_xml(out, &format!("{indent} "))?;
+                    }
+                    writeln!(out, "{indent}</objectgroup>")?;
+                }
+                Layer::Group(group) => {
+                    write!(out, r#"{indent}<group id="{}""#, group.id)?;
+                    if let Some(name) = &group.name {
+                        write!(out, r#" name="{}""#, escape(name))?;
+                    }
+                    group.style.write_xml(out)?;
+                    if group.offset.x != 0 {
+                        write!(out, r#" offsetx="{}""#, group.offset.x)?;
+                    }
+                    if group.offset.y != 0 {
+                        write!(out, r#" offsety="{}""#, group.offset.y)?;
+                    }
+                    writeln!(out, ">")?;
+                    let inner = format!("{indent} ");
+                    group.properties.write_xml(out, &inner)?;
+                    self.write_layers(&group.layers, out, &inner)?;
+                    writeln!(out, "{indent}</group>")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_animation(&self, tile_gid: TileIndex) -> Option<&Animation> {
-        let (tileset, tile_id) = self.tilesets.lookup(tile_gid);
+        let (tileset, tile_id) = self.tilesets.lookup(tile_gid).ok()?;
         tileset.animations.get(tile_id)
     }
 
-    /*
     pub fn get_tile_properties(&self, tile_gid: TileIndex) -> Option<&TileProperties> {
-        let (tileset, tile_id) = self.tilesets.lookup(tile_gid);
+        let (tileset, tile_id) = self.tilesets.lookup(tile_gid).ok()?;
         tileset.get_tile_properties(tile_id)
     }
-    */
+
+    /// The sprite a tile is in, and where in the sprite it is.
+    pub fn get_tile_sprite(&self, tile_gid: TileIndex) -> Result<(Sprite, Rect<i32>)> {
+        let (tileset, tile_id) = self.tilesets.lookup(tile_gid)?;
+        Ok((tileset.sprite, tileset.get_source_rect(tile_id)))
+    }
+
+    /// The tile at (row, col) in the layer the player is on, or None if there isn't one there.
+    pub fn player_tile(&self, row: usize, col: usize) -> Option<TileIndex> {
+        let layer = Layer::find_player_layer(&self.layers)?;
+        layer.get(row, col).copied().filter(|index| index.0 != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::imagemanager::ImageManager;
+
+    const MAP: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.8" tiledversion="1.8.0" orientation="orthogonal" renderorder="right-down" width="3" height="2" tilewidth="8" tileheight="8" infinite="0" backgroundcolor="#336699" nextlayerid="4" nextobjectid="4">
+ <properties>
+  <property name="cancel_action" value="quit"/>
+ </properties>
+ <tileset firstgid="1" source="8bitfont.tsx"/>
+ <imagelayer id="1" name="sky" opacity="0.5" tintcolor="#ff0000">
+  <image source="spacebg.png"/>
+ </imagelayer>
+ <layer id="2" name="tiles" width="3" height="2">
+  <properties>
+   <property name="player" type="bool" value="true"/>
+  </properties>
+  <data encoding="csv">
+1,2,3,
+0,0,4
+</data>
+ </layer>
+ <objectgroup id="3" name="objects" visible="0">
+  <object id="1" gid="5" x="8" y="16" width="8" height="8"/>
+  <object id="3" gid="2147483654" x="16" y="16" width="8" height="8"/>
+  <object id="2" x="0" y="0" width="16" height="8">
+   <properties>
+    <property name="label" value="a &amp; b"/>
+    <property name="uibutton" type="bool" value="true"/>
+   </properties>
+  </object>
+ </objectgroup>
+</map>
+"##;
+
+    const TEMPLATE: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<template>
+ <tileset firstgid="1" source="../8bitfont.tsx"/>
+ <object name="enemy" gid="3" width="8" height="8">
+  <properties>
+   <property name="label" value="enemy"/>
+   <property name="solid" type="bool" value="true"/>
+  </properties>
+ </object>
+</template>
+"##;
+
+    const TEMPLATE_MAP: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.8" tiledversion="1.8.0" orientation="orthogonal" renderorder="right-down" width="1" height="1" tilewidth="8" tileheight="8" infinite="0" backgroundcolor="#000000" nextlayerid="3" nextobjectid="3">
+ <tileset firstgid="1" source="other.tsx"/>
+ <tileset firstgid="145" source="8bitfont.tsx"/>
+ <layer id="1" name="tiles" width="1" height="1">
+  <data encoding="csv">
+0
+</data>
+ </layer>
+ <objectgroup id="2" name="objects">
+  <object id="1" template="templates/enemy.tx" x="8" y="16"/>
+  <object id="2" template="templates/enemy.tx" x="16" y="16" width="16" height="16">
+   <properties>
+    <property name="label" value="boss"/>
+   </properties>
+  </object>
+ </objectgroup>
+</map>
+"##;
+
+    const GROUP_MAP: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.8" tiledversion="1.8.0" orientation="orthogonal" renderorder="right-down" width="2" height="1" tilewidth="8" tileheight="8" infinite="0" backgroundcolor="#000000" nextlayerid="5" nextobjectid="2">
+ <tileset firstgid="1" source="8bitfont.tsx"/>
+ <group id="1" name="world" opacity="0.5" offsetx="16" offsety="8">
+  <layer id="2" name="tiles" width="2" height="1">
+   <properties>
+    <property name="player" type="bool" value="true"/>
+   </properties>
+   <data encoding="csv">
+1,2
+</data>
+  </layer>
+  <group id="3" name="decorations" tintcolor="#00ff00">
+   <objectgroup id="4" name="objects">
+    <object id="1" gid="5" x="0" y="8" width="8" height="8"/>
+   </objectgroup>
+  </group>
+ </group>
+</map>
+"##;
+
+    fn load(path: &str, text: &str) -> TileMap {
+        try_load(path, text).unwrap()
+    }
+
+    fn try_load(path: &str, text: &str) -> Result<TileMap> {
+        let mut map = HashMap::new();
+        map.insert(PathBuf::from(path), text.as_bytes().to_vec());
+        map.insert(
+            PathBuf::from("assets/templates/enemy.tx"),
+            TEMPLATE.as_bytes().to_vec(),
+        );
+        map.insert(
+            PathBuf::from("assets/other.tsx"),
+            include_bytes!("../../assets/8bitfont.tsx").to_vec(),
+        );
+        map.insert(
+            PathBuf::from("assets/8bitfont.tsx"),
+            include_bytes!("../../assets/8bitfont.tsx").to_vec(),
+        );
+        let files = FileManager::from_memory(map).unwrap();
+        let mut images = ImageManager::null_manager();
+        TileMap::from_file(Path::new(path), &files, &mut images)
+    }
+
+    #[test]
+    fn round_trip() {
+        let tilemap = load("assets/test.tmx", MAP);
+        let xml = tilemap.to_xml().unwrap();
+        assert_eq!(xml, MAP);
+
+        let copy = load("assets/copy.tmx", &xml);
+        assert_eq!(copy.objects.len(), 3);
+        assert_eq!(copy.objects[0].position.y, 8);
+        assert_eq!(copy.objects[1].gid, Some(6.into()));
+        assert!(copy.objects[1].flip_horizontal);
+        assert_eq!(copy.objects[2].properties.label, "a & b");
+        assert!(copy.objects[2].properties.uibutton);
+        assert_eq!(copy.properties.cancel_action, "quit");
+        assert_eq!(copy.player_layer, Some(1));
+        assert_eq!(copy.to_xml().unwrap(), xml);
+    }
+
+    #[test]
+    fn unknown_gids() {
+        // The font only has 144 tiles, so a map can't use a tile past them.
+        let tile = MAP.replace("0,0,4", "0,0,999");
+        let Err(err) = try_load("assets/test.tmx", &tile) else {
+            panic!("loaded a map with an unknown tile");
+        };
+        assert!(format!("{:#}", err).contains("row 1, col 2"));
+        let object = MAP.replace(r#"gid="5""#, r#"gid="999""#);
+        assert!(try_load("assets/test.tmx", &object).is_err());
+
+        let tilemap = load("assets/test.tmx", MAP);
+        assert!(tilemap.get_tile_sprite(4.into()).is_ok());
+        assert!(tilemap.get_tile_sprite(999.into()).is_err());
+    }
+
+    #[test]
+    fn templates() {
+        let tilemap = load("assets/test.tmx", TEMPLATE_MAP);
+        let enemy = &tilemap.objects[0];
+        assert_eq!(enemy.gid, Some(147.into()));
+        assert_eq!(
+            enemy.position,
+            Rect {
+                x: 8,
+                y: 8,
+                w: 8,
+                h: 8
+            }
+        );
+        assert_eq!(enemy.properties.label, "enemy");
+        assert!(enemy.properties.solid);
+
+        let boss = &tilemap.objects[1];
+        assert_eq!(
+            boss.position,
+            Rect {
+                x: 16,
+                y: 0,
+                w: 16,
+                h: 16
+            }
+        );
+        assert_eq!(boss.properties.label, "boss");
+        assert!(boss.properties.solid);
+
+        assert_eq!(tilemap.to_xml().unwrap(), TEMPLATE_MAP);
+    }
+
+    #[test]
+    fn draw_tile_objects() {
+        use crate::rendercontext::SpriteBatchEntry;
+
+        let mut tilemap = load("assets/test.tmx", MAP);
+        let mut context = RenderContext::new(640, 400, 0).unwrap();
+        let dest = context.logical_area();
+        tilemap.draw_foreground(&mut context, RenderLayer::Player, dest, Point::new(0, 0));
+        assert!(context.player_batch.entries.is_empty());
+
+        assert_eq!(tilemap.is_layer_visible("objects"), Some(false));
+        tilemap.set_layer_visible("objects", true).unwrap();
+        tilemap.draw_foreground(&mut context, RenderLayer::Player, dest, Point::new(0, 0));
+
+        let sprites: Vec<(Rect<i32>, Rect<i32>, bool)> = context
+            .player_batch
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                SpriteBatchEntry::Sprite {
+                    source,
+                    destination,
+                    reversed,
+                    ..
+                } => Some((*source, *destination, *reversed)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            sprites,
+            vec![
+                (
+                    Rect {
+                        x: 32,
+                        y: 0,
+                        w: 8,
+                        h: 8
+                    },
+                    Rect {
+                        x: 8,
+                        y: 8,
+                        w: 8,
+                        h: 8
+                    },
+                    false
+                ),
+                (
+                    Rect {
+                        x: 40,
+                        y: 0,
+                        w: 8,
+                        h: 8
+                    },
+                    Rect {
+                        x: 16,
+                        y: 8,
+                        w: 8,
+                        h: 8
+                    },
+                    true
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn draw_flipped_tile_objects() {
+        use crate::rendercontext::SpriteBatchEntry;
+
+        let mut tilemap = load("assets/test.tmx", MAP);
+        tilemap.set_layer_visible("objects", true).unwrap();
+        let flipped = tilemap
+            .objects
+            .iter_mut()
+            .find(|object| object.gid.is_some())
+            .unwrap();
+        flipped.flip_vertical = true;
+
+        let mut context = RenderContext::new(640, 400, 0).unwrap();
+        let dest = context.logical_area();
+        tilemap.draw_foreground(&mut context, RenderLayer::Player, dest, Point::new(0, 0));
+
+        let transformed: Vec<(Rect<i32>, SpriteTransform)> = context
+            .player_batch
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                SpriteBatchEntry::TransformedSprite {
+                    destination,
+                    transform,
+                    ..
+                } => Some((*destination, *transform)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(transformed.len(), 1);
+        let (destination, transform) = transformed[0];
+        assert_eq!(
+            destination,
+            Rect {
+                x: 8,
+                y: 8,
+                w: 8,
+                h: 8
+            }
+        );
+        assert!(transform.flip_vertical);
+        assert!(!transform.flip_horizontal);
+    }
+
+    fn image_layer(repeat_x: bool, repeat_y: bool, fit: ImageFit) -> ImageLayer {
+        ImageLayer {
+            id: 1,
+            name: None,
+            source: "bg.png".to_string(),
+            offsetx: None,
+            offsety: None,
+            style: LayerStyle::default(),
+            repeat_x,
+            repeat_y,
+            fit,
+            properties: PropertyMap::new(),
+            surface: Sprite {
+                id: 0,
+                area: Rect {
+                    x: 0,
+                    y: 0,
+                    w: 100,
+                    h: 50,
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn image_layer_fit() {
+        let dest = Rect {
+            x: 0,
+            y: 0,
+            w: 400,
+            h: 100,
+        };
+        let offset = Point::new(-30, 10);
+
+        let layer = image_layer(false, false, ImageFit::None);
+        assert_eq!(
+            layer.destinations(dest, offset),
+            vec![Rect {
+                x: -30,
+                y: 10,
+                w: 100,
+                h: 50
+            }]
+        );
+
+        let layer = image_layer(false, false, ImageFit::Fit);
+        assert_eq!(
+            layer.destinations(dest, offset),
+            vec![Rect {
+                x: 100,
+                y: 0,
+                w: 200,
+                h: 100
+            }]
+        );
+
+        let layer = image_layer(false, false, ImageFit::Cover);
+        assert_eq!(
+            layer.destinations(dest, offset),
+            vec![Rect {
+                x: 0,
+                y: -50,
+                w: 400,
+                h: 200
+            }]
+        );
+    }
+
+    #[test]
+    fn image_layer_repeat() {
+        let dest = Rect {
+            x: 0,
+            y: 0,
+            w: 250,
+            h: 100,
+        };
+        let offset = Point::new(-30, 10);
+
+        let layer = image_layer(true, false, ImageFit::None);
+        let xs: Vec<i32> = layer
+            .destinations(dest, offset)
+            .iter()
+            .map(|rect| rect.x)
+            .collect();
+        assert_eq!(xs, vec![-30, 70, 170]);
+
+        let layer = image_layer(true, true, ImageFit::None);
+        assert_eq!(layer.destinations(dest, offset).len(), 9);
+    }
+
+    #[test]
+    fn layer_style() {
+        use crate::rendercontext::SpriteBatchEntry;
+
+        let mut tilemap = load("assets/test.tmx", MAP);
+        // The null image manager loads every image as empty, so give the sky a size.
+        if let Layer::Image(sky) = &mut tilemap.layers[0] {
+            sky.surface.area.w = 100;
+            sky.surface.area.h = 50;
+        }
+        let mut context = RenderContext::new(640, 400, 0).unwrap();
+        let dest = context.logical_area();
+        tilemap.draw_background(&mut context, RenderLayer::Player, dest, Point::new(0, 0));
+
+        let tints: Vec<Color> = context
+            .player_batch
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                SpriteBatchEntry::Sprite { tint, .. } => Some(*tint),
+                _ => None,
+            })
+            .collect();
+        let sky = Color {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 128,
+        };
+        assert_eq!(
+            tints,
+            vec![sky, Color::WHITE, Color::WHITE, Color::WHITE, Color::WHITE]
+        );
+        assert_eq!(context.player_batch.tint, Color::WHITE);
+
+        tilemap.set_layer_visible("sky", false).unwrap();
+        assert!(tilemap.set_layer_visible("clouds", false).is_err());
+        context.clear();
+        tilemap.draw_background(&mut context, RenderLayer::Player, dest, Point::new(0, 0));
+        let sprites = context
+            .player_batch
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry, SpriteBatchEntry::Sprite { .. }))
+            .count();
+        assert_eq!(sprites, 4);
+    }
+
+    #[test]
+    fn groups() {
+        use crate::rendercontext::SpriteBatchEntry;
+
+        fn sprites(context: &RenderContext) -> Vec<(Rect<i32>, Color)> {
+            context
+                .player_batch
+                .entries
+                .iter()
+                .filter_map(|entry| match entry {
+                    SpriteBatchEntry::Sprite {
+                        destination, tint, ..
+                    } => Some((*destination, *tint)),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        let mut tilemap = load("assets/test.tmx", GROUP_MAP);
+        assert_eq!(tilemap.to_xml().unwrap(), GROUP_MAP);
+        assert_eq!(tilemap.player_layer, Some(0));
+        assert_eq!(tilemap.objects.len(), 1);
+
+        let mut context = RenderContext::new(640, 400, 0).unwrap();
+        let dest = context.logical_area();
+        let half = Color {
+            a: 128,
+            ..Color::WHITE
+        };
+        tilemap.draw_background(&mut context, RenderLayer::Player, dest, Point::new(0, 0));
+        assert_eq!(
+            sprites(&context),
+            vec![
+                (
+                    Rect {
+                        x: 16,
+                        y: 8,
+                        w: 8,
+                        h: 8
+                    },
+                    half
+                ),
+                (
+                    Rect {
+                        x: 24,
+                        y: 8,
+                        w: 8,
+                        h: 8
+                    },
+                    half
+                ),
+            ]
+        );
+
+        context.clear();
+        tilemap.draw_foreground(&mut context, RenderLayer::Player, dest, Point::new(0, 0));
+        let green = Color {
+            r: 0,
+            g: 255,
+            b: 0,
+            a: 128,
+        };
+        assert_eq!(
+            sprites(&context),
+            vec![(
+                Rect {
+                    x: 16,
+                    y: 8,
+                    w: 8,
+                    h: 8
+                },
+                green
+            )]
+        );
+
+        tilemap.set_layer_visible("world", false).unwrap();
+        assert_eq!(tilemap.is_layer_visible("objects"), Some(true));
+        context.clear();
+        tilemap.draw_foreground(&mut context, RenderLayer::Player, dest, Point::new(0, 0));
+        assert!(sprites(&context).is_empty());
+    }
+
+    #[test]
+    fn isometric() {
+        use crate::rendercontext::SpriteBatchEntry;
+
+        let text = GROUP_MAP.replace("orthogonal", "isometric");
+        let text = text.replace(
+            r#"width="2" height="1" tilewidth="8""#,
+            r#"width="2" height="2" tilewidth="16""#,
+        );
+        let text = text.replace(r#"width="2" height="1">"#, r#"width="2" height="2">"#);
+        let text = text.replace("1,2\n", "1,2,\n3,0\n");
+        let tilemap = load("assets/test.tmx", &text);
+        assert_eq!(tilemap.orientation, Orientation::Isometric);
+        assert_eq!(tilemap.to_xml().unwrap(), text);
+
+        let mut context = RenderContext::new(640, 400, 0).unwrap();
+        let dest = context.logical_area();
+        tilemap.draw_background(&mut context, RenderLayer::Player, dest, Point::new(-16, -8));
+        let positions: Vec<(i32, i32)> = context
+            .player_batch
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                SpriteBatchEntry::Sprite { destination, .. } => {
+                    Some((destination.x, destination.y))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(positions, vec![(8, 0), (16, 4), (0, 4)]);
+
+        let text = MAP.replace("orthogonal", "hexagonal");
+        let error = try_load("assets/test.tmx", &text).err().unwrap();
+        assert!(format!("{:#}", error).contains("unsupported map orientation: hexagonal"));
+    }
 }
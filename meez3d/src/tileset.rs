@@ -1,9 +1,11 @@
+use std::fmt::Write;
 use std::num::ParseIntError;
 use std::path::Path;
 use std::str::FromStr;
 
 use anyhow::{anyhow, Context, Error, Result};
 use log::info;
+use quick_xml::escape::escape;
 use serde::Deserialize;
 
 use crate::filemanager::FileManager;
@@ -42,9 +44,9 @@ struct ImageXml {
     #[serde(rename = "@source")]
     source: String,
     #[serde(rename = "@width")]
-    _width: i32,
+    width: i32,
     #[serde(rename = "@height")]
-    _height: i32,
+    height: i32,
 }
 
 #[derive(Debug, Deserialize)]
@@ -94,6 +96,13 @@ pub struct TileSetXml {
     fields: Vec<TileSetXmlField>,
 }
 
+impl TileSetXml {
+    /// Parses the text of a tsx file, which is safe to do on any thread.
+    pub fn parse(text: &str) -> Result<TileSetXml> {
+        Ok(quick_xml::de::from_str::<TileSetXml>(text)?)
+    }
+}
+
 pub struct TileProperties {
     pub solid: bool,
     pub animation: Option<String>,
@@ -112,24 +121,34 @@ impl TryFrom<PropertyMap> for TileProperties {
     }
 }
 
-pub struct TileSetProperties {}
+pub struct TileSetProperties {
+    pub raw: PropertyMap,
+}
 
 impl TryFrom<PropertyMap> for TileSetProperties {
     type Error = Error;
 
-    fn try_from(_value: PropertyMap) -> Result<Self, Self::Error> {
-        Ok(TileSetProperties {})
+    fn try_from(value: PropertyMap) -> Result<Self, Self::Error> {
+        Ok(TileSetProperties { raw: value })
     }
 }
 
+/// The image for a tileset, as it was written in the tsx file.
+struct TileSetImage {
+    source: String,
+    width: i32,
+    height: i32,
+}
+
 pub struct TileSet {
-    _name: String,
+    name: String,
     firstgid: TileIndex,
     pub tilewidth: i32,
     pub tileheight: i32,
     tilecount: i32,
     columns: i32,
     pub sprite: Sprite,
+    image: TileSetImage,
     pub animations: SmallIntMap<LocalTileIndex, Animation>,
     pub properties: TileSetProperties,
     tile_properties: SmallIntMap<LocalTileIndex, TileProperties>,
@@ -142,15 +161,16 @@ impl TileSet {
         files: &FileManager,
         images: &mut dyn ImageLoader,
     ) -> Result<TileSet> {
+        profile_scope!("load_tileset", path = ?path);
         info!("loading tileset from {:?}", path);
         let text = files
             .read_to_string(path)
             .map_err(|e| anyhow!("unable to open {:?}: {}", path, e))?;
-        let xml = quick_xml::de::from_str::<TileSetXml>(&text)?;
-        Self::from_xml(xml, path, firstgid, images)
+        Self::from_xml(TileSetXml::parse(&text)?, path, firstgid, images)
     }
 
-    fn from_xml(
+    /// Makes a tileset from a tsx file that's already been parsed, e.g. on another thread.
+    pub(crate) fn from_xml(
         xml: TileSetXml,
         path: &Path,
         firstgid: TileIndex,
@@ -163,6 +183,7 @@ impl TileSet {
         let columns = xml.columns;
 
         let mut sprite: Option<Sprite> = None;
+        let mut image: Option<TileSetImage> = None;
         let mut properties = PropertyMap::new();
         let mut animations = SmallIntMap::new();
         let mut tile_properties = SmallIntMap::new();
@@ -173,8 +194,13 @@ impl TileSet {
                     let img_path = path
                         .parent()
                         .context(anyhow!("tileset path is root"))?
-                        .join(img_xml.source);
+                        .join(&img_xml.source);
                     sprite = Some(images.load_sprite(&img_path)?);
+                    image = Some(TileSetImage {
+                        source: img_xml.source,
+                        width: img_xml.width,
+                        height: img_xml.height,
+                    });
                 }
                 TileSetXmlField::Properties(props_xml) => {
                     properties = props_xml.try_into()?;
@@ -207,16 +233,18 @@ impl TileSet {
         //println!("tile properties: {:?}", tile_properties);
 
         let sprite = sprite.context("missing image")?;
+        let image = image.context("missing image")?;
         let properties: TileSetProperties = properties.try_into()?;
 
         Ok(TileSet {
-            _name: name,
+            name,
             firstgid,
             tilewidth,
             tileheight,
             tilecount,
             columns,
             sprite,
+            image,
             animations,
             properties,
             tile_properties,
@@ -226,7 +254,7 @@ impl TileSet {
     pub fn get_local_tile_index(&self, tile_gid: TileIndex) -> Option<LocalTileIndex> {
         let tile_gid: usize = tile_gid.into();
         let firstgid: usize = self.firstgid.into();
-        if tile_gid >= firstgid {
+        if tile_gid >= firstgid && tile_gid - firstgid < self.tilecount.max(0) as usize {
             Some((tile_gid - firstgid).into())
         } else {
             None
@@ -245,6 +273,10 @@ impl TileSet {
         -key
     }
 
+    pub fn tilecount(&self) -> i32 {
+        self.tilecount
+    }
+
     fn _rows(&self) -> i32 {
         (self.tilecount as f32 / self.columns as f32).ceil() as i32
     }
@@ -269,4 +301,76 @@ impl TileSet {
     pub fn get_tile_properties(&self, tile_id: LocalTileIndex) -> Option<&TileProperties> {
         self.tile_properties.get(tile_id)
     }
+
+    /// Writes the tileset to a tsx file, e.g. after generating one for a new map.
+    pub fn save(&self, path: &Path, files: &FileManager) -> Result<()> {
+        files.write(path, self.to_xml()?.as_bytes())
+    }
+
+    /// Writes the tileset back out as a Tiled tsx file.
+    pub fn to_xml(&self) -> Result<String> {
+        let mut out = String::new();
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            out,
+            r#"<tileset version="1.8" tiledversion="1.8.0" name="{}" tilewidth="{}" tileheight="{}" tilecount="{}" columns="{}">"#,
+            escape(&self.name),
+            self.tilewidth,
+            self.tileheight,
+            self.tilecount,
+            self.columns
+        )?;
+        self.properties.raw.write_xml(&mut out, " ")?;
+        writeln!(
+            out,
+            r#" <image source="{}" width="{}" height="{}"/>"#,
+            escape(&self.image.source),
+            self.image.width,
+            self.image.height
+        )?;
+        for (id, props) in self.tile_properties.iter() {
+            if props.raw.is_empty() {
+                continue;
+            }
+            writeln!(out, r#" <tile id="{}">"#, id)?;
+            props.raw.write_xml(&mut out, "  ")?;
+            writeln!(out, " </tile>")?;
+        }
+        writeln!(out, "</tileset>")?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::imagemanager::ImageManager;
+
+    #[test]
+    fn round_trip() {
+        let path = Path::new("assets/8bitfont.tsx");
+        let mut map = HashMap::new();
+        map.insert(
+            path.to_owned(),
+            include_bytes!("../../assets/8bitfont.tsx").to_vec(),
+        );
+        let files = FileManager::from_memory(map).unwrap();
+        let mut images = ImageManager::null_manager();
+        let tileset = TileSet::from_file(path, 1.into(), &files, &mut images).unwrap();
+        let xml = tileset.to_xml().unwrap();
+
+        let mut map = HashMap::new();
+        map.insert(PathBuf::from("assets/copy.tsx"), xml.as_bytes().to_vec());
+        let files = FileManager::from_memory(map).unwrap();
+        let copy = TileSet::from_file(Path::new("assets/copy.tsx"), 1.into(), &files, &mut images)
+            .unwrap();
+        assert_eq!(copy.name, "8bitfont");
+        assert_eq!(copy.tilecount, 144);
+        assert_eq!(copy.columns, 12);
+        assert_eq!(copy.image.source, "8bitfont.png");
+        assert_eq!(copy.to_xml().unwrap(), xml);
+    }
 }
@@ -0,0 +1,139 @@
+use crate::constants::FRAME_RATE;
+use crate::font::Font;
+use crate::geometry::{Point, Rect};
+use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::tilemap::TileMapProperties;
+use crate::utils::Color;
+
+/// How long a title card takes to fade in, and again to fade out.
+const FADE_FRAMES: u32 = FRAME_RATE / 2;
+/// How long a title card stays fully visible.
+const HOLD_FRAMES: u32 = 2 * FRAME_RATE;
+/// The band behind the text is this opaque, at the card's full opacity.
+const BAND_ALPHA: f32 = 160.0;
+const LINE_SPACING: i32 = 8;
+
+/// A level name and subtitle shown over the gameplay for a few seconds, fading in and out.
+pub struct TitleCard {
+    pub title: String,
+    pub subtitle: Option<String>,
+    frame: u32,
+}
+
+impl TitleCard {
+    pub fn new(title: &str, subtitle: Option<&str>) -> TitleCard {
+        TitleCard {
+            title: title.to_string(),
+            subtitle: subtitle.map(str::to_string),
+            frame: 0,
+        }
+    }
+
+    /// The card for a map with a "title" property, and optionally a "subtitle" one.
+    pub fn from_properties(properties: &TileMapProperties) -> Option<TitleCard> {
+        let title = properties.title.as_deref()?;
+        Some(TitleCard::new(title, properties.subtitle.as_deref()))
+    }
+
+    /// Advances the card by a frame, returning false once it has faded out.
+    pub fn update(&mut self) -> bool {
+        self.frame = (self.frame + 1).min(self.total_frames());
+        !self.is_done()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.frame >= self.total_frames()
+    }
+
+    fn total_frames(&self) -> u32 {
+        2 * FADE_FRAMES + HOLD_FRAMES
+    }
+
+    /// How visible the card is, from 0 to 1, easing in and out of the fades.
+    pub fn opacity(&self) -> f32 {
+        let fade_out_start = FADE_FRAMES + HOLD_FRAMES;
+        let t = if self.frame < FADE_FRAMES {
+            self.frame as f32 / FADE_FRAMES as f32
+        } else if self.frame < fade_out_start {
+            1.0
+        } else {
+            1.0 - (self.frame - fade_out_start) as f32 / FADE_FRAMES as f32
+        };
+        let t = t.clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    /// Draws the card centered on the HUD.
+    pub fn draw(&self, context: &mut RenderContext, font: &Font) {
+        let opacity = self.opacity();
+        if opacity <= 0.0 {
+            return;
+        }
+        let area = context.ui_area();
+        let lines = if self.subtitle.is_some() { 2 } else { 1 };
+        let band_height = lines * font.char_height + (lines + 1) * LINE_SPACING;
+        let band = Rect {
+            x: 0,
+            y: (area.h - band_height) / 2,
+            w: area.w,
+            h: band_height,
+        };
+        let band_color = Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: (BAND_ALPHA * opacity).round() as u8,
+        };
+        context.fill_rect(band, RenderLayer::Hud, band_color);
+
+        let text_color = Color {
+            a: (255.0 * opacity).round() as u8,
+            ..Color::WHITE
+        };
+        context.set_tint(RenderLayer::Hud, text_color);
+        let mut y = band.y + LINE_SPACING;
+        for line in [Some(&self.title), self.subtitle.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            let width = font.text_width(line);
+            font.draw_string(
+                context,
+                RenderLayer::Hud,
+                Point::new((area.w - width) / 2, y),
+                line,
+            );
+            y += font.char_height + LINE_SPACING;
+        }
+        context.set_tint(RenderLayer::Hud, Color::WHITE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fades() {
+        let mut card = TitleCard::new("sector 7", Some("deep space"));
+        assert_eq!(card.opacity(), 0.0);
+
+        for _ in 0..FADE_FRAMES / 2 {
+            assert!(card.update());
+        }
+        assert!(card.opacity() > 0.0 && card.opacity() < 1.0);
+
+        for _ in 0..FADE_FRAMES {
+            assert!(card.update());
+        }
+        assert_eq!(card.opacity(), 1.0);
+
+        let mut frames = 0;
+        while card.update() {
+            frames += 1;
+        }
+        assert!(frames > FADE_FRAMES);
+        assert!(card.is_done());
+        assert_eq!(card.opacity(), 0.0);
+    }
+}
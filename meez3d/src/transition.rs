@@ -0,0 +1,191 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+use log::error;
+
+use crate::constants::FRAME_RATE;
+use crate::cvars::Cvars;
+
+/// The cvar for how scenes change: cut, fade, crossfade, or wipe. Defaults to cut.
+pub const TRANSITION_CVAR: &str = "transition";
+/// The cvar for how many frames a transition between scenes takes.
+pub const TRANSITION_FRAMES_CVAR: &str = "transition_frames";
+/// How many frames a transition takes if the cvar isn't set.
+pub const DEFAULT_TRANSITION_FRAMES: u32 = FRAME_RATE / 3;
+/// The longest a transition can take, so a typo can't leave the game stuck in one.
+const MAX_TRANSITION_FRAMES: u32 = FRAME_RATE * 5;
+
+/// How the stage manager goes from one scene to the next.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TransitionKind {
+    /// Switches straight to the new scene.
+    #[default]
+    Cut,
+    /// Fades the old scene out to black, and then the new one in.
+    Fade,
+    /// Blends from the old scene into the new one.
+    Crossfade,
+    /// Slides the new scene in over the old one, from left to right.
+    Wipe,
+}
+
+impl FromStr for TransitionKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "cut" => TransitionKind::Cut,
+            "fade" => TransitionKind::Fade,
+            "crossfade" => TransitionKind::Crossfade,
+            "wipe" => TransitionKind::Wipe,
+            _ => bail!("invalid transition: {}", s),
+        })
+    }
+}
+
+impl Display for TransitionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TransitionKind::Cut => "cut",
+            TransitionKind::Fade => "fade",
+            TransitionKind::Crossfade => "crossfade",
+            TransitionKind::Wipe => "wipe",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// How far along a transition is for the frame being drawn, which renderers use to blend the
+/// outgoing scene's frame with the new one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransitionFrame {
+    pub kind: TransitionKind,
+    /// From 0, showing only the outgoing scene, up towards 1, showing only the new one.
+    pub progress: f32,
+}
+
+impl TransitionFrame {
+    /// Whether any of the outgoing scene can be seen, since a fade only shows it for the first
+    /// half.
+    pub fn shows_outgoing(&self) -> bool {
+        match self.kind {
+            TransitionKind::Cut => false,
+            TransitionKind::Fade => self.progress < 0.5,
+            TransitionKind::Crossfade | TransitionKind::Wipe => true,
+        }
+    }
+
+    /// How much a fade has darkened the screen, from 0 to 1, peaking halfway through.
+    pub fn darkness(&self) -> f32 {
+        match self.kind {
+            TransitionKind::Fade => 1.0 - (self.progress * 2.0 - 1.0).abs(),
+            _ => 0.0,
+        }
+    }
+}
+
+/// A transition the stage manager is running, counted in updates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transition {
+    kind: TransitionKind,
+    frames: u32,
+    elapsed: u32,
+}
+
+impl Transition {
+    /// A transition that takes the given number of frames, or None if it wouldn't be seen.
+    pub fn new(kind: TransitionKind, frames: u32) -> Option<Transition> {
+        if kind == TransitionKind::Cut || frames == 0 {
+            return None;
+        }
+        Some(Transition {
+            kind,
+            frames: frames.min(MAX_TRANSITION_FRAMES),
+            elapsed: 0,
+        })
+    }
+
+    /// The transition the cvars ask for, if any.
+    pub fn from_cvars(cvars: &Cvars) -> Option<Transition> {
+        let kind = match cvars.get(TRANSITION_CVAR) {
+            Some(kind) => kind.parse().unwrap_or_else(|e| {
+                error!("{}", e);
+                TransitionKind::Cut
+            }),
+            None => TransitionKind::Cut,
+        };
+        let frames = cvars
+            .get_parsed(TRANSITION_FRAMES_CVAR)
+            .unwrap_or(DEFAULT_TRANSITION_FRAMES);
+        Transition::new(kind, frames)
+    }
+
+    pub fn kind(&self) -> TransitionKind {
+        self.kind
+    }
+
+    /// Whether the transition started this frame, so the outgoing scene's frame should be kept.
+    pub fn is_starting(&self) -> bool {
+        self.elapsed == 0
+    }
+
+    /// Moves on by a frame, returning false once the transition is over.
+    pub fn advance(&mut self) -> bool {
+        self.elapsed += 1;
+        self.elapsed < self.frames
+    }
+
+    pub fn frame(&self) -> TransitionFrame {
+        TransitionFrame {
+            kind: self.kind,
+            progress: self.elapsed as f32 / self.frames as f32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        for kind in ["cut", "fade", "crossfade", "wipe"] {
+            assert_eq!(kind.parse::<TransitionKind>().unwrap().to_string(), kind);
+        }
+        assert!("dissolve".parse::<TransitionKind>().is_err());
+    }
+
+    #[test]
+    fn runs_for_frames() {
+        let mut cvars = Cvars::new();
+        assert_eq!(Transition::from_cvars(&cvars), None);
+        cvars.set(TRANSITION_CVAR, "crossfade");
+        cvars.set(TRANSITION_FRAMES_CVAR, "4");
+        let mut transition = Transition::from_cvars(&cvars).unwrap();
+        assert!(transition.is_starting());
+        let mut progress = vec![transition.frame().progress];
+        while transition.advance() {
+            assert!(!transition.is_starting());
+            progress.push(transition.frame().progress);
+        }
+        assert_eq!(progress, [0.0, 0.25, 0.5, 0.75]);
+
+        cvars.set(TRANSITION_FRAMES_CVAR, "0");
+        assert_eq!(Transition::from_cvars(&cvars), None);
+    }
+
+    #[test]
+    fn fades_through_black() {
+        let at = |progress| TransitionFrame {
+            kind: TransitionKind::Fade,
+            progress,
+        };
+        assert_eq!(at(0.0).darkness(), 0.0);
+        assert_eq!(at(0.25).darkness(), 0.5);
+        assert_eq!(at(0.5).darkness(), 1.0);
+        assert_eq!(at(0.75).darkness(), 0.5);
+        assert!(at(0.25).shows_outgoing());
+        assert!(!at(0.5).shows_outgoing());
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::mem;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+
+use crate::constants::FRAME_RATE;
+use crate::cvars::Cvars;
+use crate::filemanager::FileManager;
+use crate::font::Font;
+use crate::keybindings::KeyBindings;
+use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::uitoast::UiToast;
+
+/// Whether tutorial prompts are shown, "true" or "false".
+pub const TUTORIAL_CVAR: &str = "tutorial";
+/// Set to "true" to show every tutorial prompt again. It goes back to "false" once it's done.
+pub const TUTORIAL_RESET_CVAR: &str = "tutorial_reset";
+
+/// The game data file with the text of each prompt, by name. Actions in braces, like "{dash}",
+/// are replaced with the keys bound to them.
+pub const TUTORIAL_PROMPTS_PATH: &str = "assets/tutorial.json";
+/// The user data file the prompts that have been shown are kept in.
+const TUTORIAL_PATH: &str = "tutorial.json";
+/// How long each prompt stays on screen.
+const PROMPT_FRAMES: u32 = 4 * FRAME_RATE;
+
+/// Prompts that teach the player how to play, like how to move, as they become relevant. Each
+/// prompt is only ever shown once, even across sessions, until the tutorial is reset.
+pub struct Tutorial {
+    /// The text of each prompt, by name. Levels ask for prompts by name.
+    prompts: BTreeMap<String, String>,
+    /// The names of the prompts that have been shown all the way through.
+    shown: BTreeSet<String>,
+    enabled: bool,
+    /// The names of the prompts waiting for the one on screen to go away.
+    queue: VecDeque<String>,
+    /// The name of the prompt on screen, and the toast showing it.
+    current: Option<(String, UiToast)>,
+    /// Whether shown has changed since it was last saved.
+    dirty: bool,
+}
+
+impl Default for Tutorial {
+    fn default() -> Self {
+        Tutorial {
+            prompts: BTreeMap::new(),
+            shown: BTreeSet::new(),
+            enabled: true,
+            queue: VecDeque::new(),
+            current: None,
+            dirty: false,
+        }
+    }
+}
+
+impl Tutorial {
+    pub fn new() -> Tutorial {
+        Tutorial::default()
+    }
+
+    /// Loads the prompts, and which of them have been shown, or starts over if nothing's been
+    /// saved yet.
+    pub fn load(files: &FileManager) -> Tutorial {
+        let tutorial = match files
+            .read_user_data(TUTORIAL_PATH)
+            .and_then(|text| Tutorial::from_json(&text))
+        {
+            Ok(tutorial) => tutorial,
+            Err(e) => {
+                info!("starting the tutorial over: {}", e);
+                Tutorial::new()
+            }
+        };
+        match files
+            .read_to_string(Path::new(TUTORIAL_PROMPTS_PATH))
+            .and_then(|text| Tutorial::prompts_from_json(&text))
+        {
+            Ok(prompts) => tutorial.with_prompts(prompts),
+            Err(e) => {
+                warn!("no tutorial prompts: {}", e);
+                tutorial
+            }
+        }
+    }
+
+    /// Uses the given text for each prompt, by name.
+    pub fn with_prompts(mut self, prompts: BTreeMap<String, String>) -> Tutorial {
+        self.prompts = prompts;
+        self
+    }
+
+    pub fn prompts_from_json(text: &str) -> Result<BTreeMap<String, String>> {
+        serde_json::from_str(text).map_err(|e| anyhow!("unable to deserialize prompts: {}", e))
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(&self.shown)
+            .map_err(|e| anyhow!("unable to serialize tutorial: {}", e))
+    }
+
+    pub fn from_json(text: &str) -> Result<Tutorial> {
+        let shown = serde_json::from_str(text)
+            .map_err(|e| anyhow!("unable to deserialize tutorial: {}", e))?;
+        Ok(Tutorial {
+            shown,
+            ..Tutorial::default()
+        })
+    }
+
+    /// Picks up whether prompts are on, and resets the tutorial if it's been asked to.
+    pub fn apply_cvars(&mut self, cvars: &mut Cvars) {
+        self.enabled = cvars.get_parsed(TUTORIAL_CVAR).unwrap_or(true);
+        if cvars.get_parsed(TUTORIAL_RESET_CVAR).unwrap_or(false) {
+            self.reset();
+            cvars.set(TUTORIAL_RESET_CVAR, "false");
+        }
+    }
+
+    /// Forgets which prompts have been shown, so they'll all be shown again.
+    pub fn reset(&mut self) {
+        info!("resetting the tutorial");
+        self.shown.clear();
+        self.dirty = true;
+    }
+
+    pub fn has_shown(&self, name: &str) -> bool {
+        self.shown.contains(name)
+    }
+
+    /// Shows the prompt with the given name, once the ones before it are done, unless it's been
+    /// shown before or is already waiting.
+    pub fn prompt(&mut self, name: &str) {
+        if !self.enabled
+            || self.shown.contains(name)
+            || self
+                .current
+                .as_ref()
+                .is_some_and(|(current, _)| current == name)
+            || self.queue.iter().any(|queued| queued == name)
+        {
+            return;
+        }
+        if !self.prompts.contains_key(name) {
+            warn!("no tutorial prompt named {:?}", name);
+            return;
+        }
+        self.queue.push_back(name.to_string());
+    }
+
+    /// The text of the prompt on screen, if there is one.
+    pub fn current(&self) -> Option<&str> {
+        self.current.as_ref().map(|(_, toast)| toast.text())
+    }
+
+    /// Advances the prompt on screen by a frame. Returns the next prompt if it just came up, with
+    /// the keys it mentions named from the given bindings.
+    pub fn update(&mut self, keys: &KeyBindings) -> Option<&str> {
+        if let Some((name, toast)) = &mut self.current {
+            if toast.update() {
+                return None;
+            }
+            // A prompt only counts as shown once it's been on screen the whole time, so one
+            // that's cut off by quitting comes back next time.
+            self.shown.insert(mem::take(name));
+            self.dirty = true;
+        }
+        self.current = self.queue.pop_front().map(|name| {
+            let toast = UiToast::new(&keys.fill_in(&self.prompts[&name]), PROMPT_FRAMES);
+            (name, toast)
+        });
+        self.current()
+    }
+
+    /// Draws the prompt on screen as a toast near the bottom of the screen.
+    pub fn draw(&self, context: &mut RenderContext, font: &Font) {
+        if let Some((_, toast)) = &self.current {
+            toast.draw(context, RenderLayer::Hud, font);
+        }
+    }
+
+    /// Saves which prompts have been shown, if it's changed since the last time.
+    pub fn flush(&mut self, files: &FileManager) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        // A failed save isn't retried until another prompt is shown, since this is called every
+        // frame, and the files might be read-only.
+        self.dirty = false;
+        files.write_user_data(TUTORIAL_PATH, self.to_json()?.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn prompts() -> BTreeMap<String, String> {
+        Tutorial::prompts_from_json(r#"{"move": "wasd to move", "fire": "{fire} to fire"}"#)
+            .unwrap()
+    }
+
+    #[test]
+    fn prompts_show_once() {
+        let keys = KeyBindings::default();
+        let mut tutorial = Tutorial::new().with_prompts(prompts());
+        tutorial.prompt("move");
+        tutorial.prompt("fire");
+        tutorial.prompt("move");
+        tutorial.prompt("unknown");
+        assert_eq!(tutorial.update(&keys), Some("wasd to move"));
+        assert!(!tutorial.has_shown("move"));
+        for _ in 1..PROMPT_FRAMES {
+            assert_eq!(tutorial.update(&keys), None);
+        }
+        assert_eq!(tutorial.update(&keys), Some("click to fire"));
+        assert!(tutorial.has_shown("move"));
+        for _ in 0..PROMPT_FRAMES {
+            tutorial.update(&keys);
+        }
+        assert_eq!(tutorial.current(), None);
+
+        let shipped = Tutorial::prompts_from_json(include_str!("../../assets/tutorial.json"));
+        let shipped = shipped.unwrap();
+        assert_eq!(
+            keys.fill_in(&shipped["move"]),
+            "w a s d to move, q and e to turn"
+        );
+        assert_eq!(keys.fill_in(&shipped["inventory"]), "i to see your items");
+
+        // A failed save isn't tried again until there's something new to save.
+        let files = FileManager::from_memory(HashMap::new()).unwrap();
+        assert!(tutorial.flush(&files).is_err());
+        assert!(tutorial.flush(&files).is_ok());
+
+        // What's been shown is saved, and resetting it shows everything again.
+        let copy = Tutorial::from_json(&tutorial.to_json().unwrap()).unwrap();
+        let mut copy = copy.with_prompts(prompts());
+        copy.prompt("move");
+        assert!(copy.has_shown("move"));
+        assert_eq!(copy.update(&keys), None);
+        let mut cvars = Cvars::new();
+        cvars.set(TUTORIAL_RESET_CVAR, "true");
+        copy.apply_cvars(&mut cvars);
+        assert_eq!(cvars.get(TUTORIAL_RESET_CVAR), Some("false"));
+        copy.prompt("move");
+        assert_eq!(copy.update(&keys), Some("wasd to move"));
+
+        // Turning the tutorial off stops new prompts.
+        cvars.set(TUTORIAL_CVAR, "false");
+        copy.apply_cvars(&mut cvars);
+        copy.prompt("fire");
+        assert!(!copy.has_shown("fire"));
+    }
+}
@@ -1,18 +1,28 @@
-use std::path::Path;
-
-use anyhow::Result;
 use log::info;
 
 use crate::font::Font;
 use crate::geometry::Point;
 use crate::geometry::Rect;
-use crate::imagemanager::ImageLoader;
 use crate::inputmanager::InputSnapshot;
 use crate::rendercontext::RenderContext;
 use crate::rendercontext::RenderLayer;
 use crate::soundmanager::Sound;
 use crate::soundmanager::SoundManager;
 use crate::sprite::Sprite;
+use crate::utils::Color;
+
+const LABEL_COLOR: Color = Color {
+    r: 0x33,
+    g: 0x22,
+    b: 0x55,
+    a: 0xff,
+};
+const LABEL_HOVER_COLOR: Color = Color {
+    r: 0x66,
+    g: 0x44,
+    b: 0xaa,
+    a: 0xff,
+};
 
 #[derive(Debug, Clone, Copy)]
 enum UiButtonState {
@@ -24,37 +34,79 @@ enum UiButtonState {
 
 pub struct UiButton {
     pub position: Rect<i32>,
-    sprite: Sprite,
+    /// The image the button is drawn with, and the part of it that's the button.
+    sprite: Option<(Sprite, Rect<i32>)>,
+    /// Text drawn over the button, for buttons that don't have their own art.
+    label: Option<String>,
+    /// What the button says in its art, for narration.
+    alt_text: Option<String>,
     state: UiButtonState,
     action: String,
 }
 
 impl UiButton {
-    pub fn new(
-        sprite_path: &Path,
+    /// A button drawn as part of a sprite that's already been loaded, like a tile.
+    pub fn from_sprite(
+        sprite: Sprite,
+        source: Rect<i32>,
         position: Rect<i32>,
         action: &str,
-        images: &mut dyn ImageLoader,
-    ) -> Result<Self> {
-        let sprite = images.load_sprite(sprite_path)?;
-        let state = UiButtonState::Normal;
-        let action = action.to_string();
-        Ok(UiButton {
+    ) -> Self {
+        UiButton {
             position,
-            sprite,
-            state,
-            action,
-        })
+            sprite: Some((sprite, source)),
+            label: None,
+            alt_text: None,
+            state: UiButtonState::Normal,
+            action: action.to_string(),
+        }
+    }
+
+    /// A plain button with text on it, instead of an image.
+    pub fn with_label(label: &str, position: Rect<i32>, action: &str) -> Self {
+        UiButton {
+            position,
+            sprite: None,
+            label: Some(label.to_string()),
+            alt_text: None,
+            state: UiButtonState::Normal,
+            action: action.to_string(),
+        }
+    }
+
+    /// Sets what the button says in its art, for narration.
+    pub fn with_alt_text(mut self, alt_text: &str) -> Self {
+        self.alt_text = Some(alt_text.to_string());
+        self
     }
 
+    /// What to narrate when the button is focused: its label, or its alt text, or else its
+    /// action.
+    pub fn narration(&self) -> &str {
+        self.label
+            .as_deref()
+            .or(self.alt_text.as_deref())
+            .unwrap_or(&self.action)
+    }
+
+    /// Where the button is on screen, in HUD units. Its position is laid out on the whole screen,
+    /// so it's moved into the safe area, to keep it from being cropped on a TV.
+    pub fn area(&self, context: &RenderContext) -> Rect<i32> {
+        context.to_safe_area(self.position)
+    }
+
+    /// The button's position is in HUD units, so the mouse is converted to them to hit-test it.
     pub fn update(
         &mut self,
+        context: &RenderContext,
         selected: bool,
         inputs: &InputSnapshot,
         sounds: &mut SoundManager,
     ) -> Option<String> {
         let mut clicked = false;
-        let mouse_inside = self.position.contains(inputs.mouse_position.into());
+        let mouse_inside = self
+            .area(context)
+            .contains(context.to_ui_point(inputs.mouse_position));
 
         self.state = if matches!(self.state, UiButtonState::MouseClick) {
             if inputs.mouse_button_left_down {
@@ -93,20 +145,32 @@ impl UiButton {
     }
 
     pub fn draw(&self, context: &mut RenderContext, layer: RenderLayer, font: &Font) {
-        let src = Rect {
-            x: 0,
-            y: 0,
-            w: self.sprite.area.w,
-            h: self.sprite.area.h,
-        };
+        let area = self.area(context);
         let dst = if matches!(
             self.state,
             UiButtonState::MouseClick | UiButtonState::GamepadClick
         ) {
-            self.position + Point::new(16, 16)
+            area + Point::new(16, 16)
         } else {
-            self.position
+            area
         };
-        context.draw(self.sprite, layer, dst, src);
+        if let Some((sprite, source)) = self.sprite {
+            context.draw(sprite, layer, dst, source);
+        } else {
+            let color = if matches!(self.state, UiButtonState::Normal) {
+                LABEL_COLOR
+            } else {
+                LABEL_HOVER_COLOR
+            };
+            context.fill_rect(dst, layer, color);
+        }
+        if let Some(label) = self.label.as_ref() {
+            let width = font.text_width(label);
+            let pos = Point::new(
+                dst.x + (dst.w - width) / 2,
+                dst.y + (dst.h - font.char_height) / 2,
+            );
+            font.draw_string(context, layer, pos, label);
+        }
     }
 }
@@ -0,0 +1,208 @@
+use crate::font::Font;
+use crate::geometry::{Point, Rect};
+use crate::inputmanager::{InputSnapshot, TextEdit};
+use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::soundmanager::{Sound, SoundManager};
+use crate::utils::Color;
+
+/// How far the text is from the left edge of the box, in HUD units.
+const PADDING: i32 = 8;
+/// How wide the cursor is, in HUD units.
+const CURSOR_WIDTH: i32 = 2;
+
+const BOX_COLOR: Color = Color {
+    r: 0x33,
+    g: 0x22,
+    b: 0x55,
+    a: 0xff,
+};
+const FOCUSED_BOX_COLOR: Color = Color {
+    r: 0x66,
+    g: 0x44,
+    b: 0xaa,
+    a: 0xff,
+};
+const CURSOR_COLOR: Color = Color {
+    r: 0xff,
+    g: 0xdd,
+    b: 0x44,
+    a: 0xff,
+};
+
+/// A box the player can type a line of text into, like a name or a seed.
+///
+/// It only takes typing while it's focused, which the scene that owns it decides. That scene
+/// should also have wants_text_input return true, so the keyboard types text, and should ignore
+/// the menu inputs while it's focused, since they're bound to letters.
+pub struct UiTextInput {
+    pub position: Rect<i32>,
+    /// The text, a character at a time, so the cursor moves by characters.
+    text: Vec<char>,
+    /// Where the next character goes, from 0 before the first one to the length after the last.
+    cursor: usize,
+    /// The most characters the text can have.
+    max_len: usize,
+}
+
+impl UiTextInput {
+    pub fn new(position: Rect<i32>, max_len: usize) -> Self {
+        UiTextInput {
+            position,
+            text: Vec::new(),
+            cursor: 0,
+            max_len,
+        }
+    }
+
+    /// The text that's been entered so far.
+    pub fn text(&self) -> String {
+        self.text.iter().collect()
+    }
+
+    /// Replaces the text, cut down to the most characters it can have, with the cursor at the end.
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.chars().take(self.max_len).collect();
+        self.cursor = self.text.len();
+    }
+
+    /// Where the box is on screen, in HUD units, moved into the safe area like a button.
+    pub fn area(&self, context: &RenderContext) -> Rect<i32> {
+        context.to_safe_area(self.position)
+    }
+
+    /// Applies the frame's typing, if it's focused. Returns the text when ok is pressed, so the
+    /// scene can use it.
+    pub fn update(
+        &mut self,
+        focused: bool,
+        inputs: &InputSnapshot,
+        sounds: &mut SoundManager,
+    ) -> Option<String> {
+        if !focused {
+            return None;
+        }
+        if let Some(edit) = inputs.text_edit {
+            self.edit(edit);
+        }
+        if inputs.ok_clicked {
+            sounds.play(Sound::Click);
+            return Some(self.text());
+        }
+        None
+    }
+
+    fn edit(&mut self, edit: TextEdit) {
+        match edit {
+            TextEdit::Insert(c) => {
+                if self.text.len() < self.max_len {
+                    self.text.insert(self.cursor, c);
+                    self.cursor += 1;
+                }
+            }
+            TextEdit::Backspace => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.text.remove(self.cursor);
+                }
+            }
+            TextEdit::Delete => {
+                if self.cursor < self.text.len() {
+                    self.text.remove(self.cursor);
+                }
+            }
+            TextEdit::Left => self.cursor = self.cursor.saturating_sub(1),
+            TextEdit::Right => self.cursor = (self.cursor + 1).min(self.text.len()),
+            TextEdit::Home => self.cursor = 0,
+            TextEdit::End => self.cursor = self.text.len(),
+        }
+    }
+
+    /// Draws the box and the text in it, and the cursor if it's focused. Text too long for the
+    /// box scrolls so the cursor stays in view.
+    pub fn draw(
+        &self,
+        context: &mut RenderContext,
+        layer: RenderLayer,
+        font: &Font,
+        focused: bool,
+    ) {
+        let area = self.area(context);
+        let color = if focused {
+            FOCUSED_BOX_COLOR
+        } else {
+            BOX_COLOR
+        };
+        context.fill_rect(area, layer, color);
+
+        // The last column is left for the cursor when it's after the last character.
+        let columns = ((area.w - PADDING * 2) / font.char_width.max(1)).max(1) as usize;
+        let first = (self.cursor + 1).saturating_sub(columns);
+        let visible: String = self.text.iter().skip(first).take(columns).collect();
+        let y = area.y + (area.h - font.char_height) / 2;
+        font.draw_string(context, layer, Point::new(area.x + PADDING, y), &visible);
+        if focused {
+            let cursor = Rect {
+                x: area.x + PADDING + (self.cursor - first) as i32 * font.char_width,
+                y,
+                w: CURSOR_WIDTH,
+                h: font.char_height,
+            };
+            context.fill_rect(cursor, layer, CURSOR_COLOR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typing(edit: TextEdit) -> InputSnapshot {
+        InputSnapshot {
+            text_edit: Some(edit),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn editing() {
+        let position = Rect {
+            x: 0,
+            y: 0,
+            w: 100,
+            h: 20,
+        };
+        let mut input = UiTextInput::new(position, 4);
+        let mut sounds = SoundManager::noop_manager();
+        for c in "seeds".chars() {
+            input.update(true, &typing(TextEdit::Insert(c)), &mut sounds);
+        }
+        // It stops at the most characters it can have.
+        assert_eq!(input.text(), "seed");
+
+        for edit in [TextEdit::Left, TextEdit::Left, TextEdit::Backspace] {
+            input.update(true, &typing(edit), &mut sounds);
+        }
+        assert_eq!((input.text().as_str(), input.cursor), ("sed", 1));
+        for edit in [TextEdit::Delete, TextEdit::Home, TextEdit::Insert('a')] {
+            input.update(true, &typing(edit), &mut sounds);
+        }
+        assert_eq!((input.text().as_str(), input.cursor), ("asd", 1));
+        input.update(true, &typing(TextEdit::End), &mut sounds);
+        assert_eq!(input.cursor, 3);
+
+        // Typing is ignored while it isn't focused, and ok hands over the text when it is.
+        let ok = InputSnapshot {
+            ok_clicked: true,
+            text_edit: Some(TextEdit::Insert('x')),
+            ..Default::default()
+        };
+        assert_eq!(input.update(false, &ok, &mut sounds), None);
+        assert_eq!(
+            input.update(true, &ok, &mut sounds),
+            Some("asdx".to_string())
+        );
+
+        input.set_text("longer");
+        assert_eq!((input.text().as_str(), input.cursor), ("long", 4));
+    }
+}
@@ -0,0 +1,74 @@
+use crate::font::Font;
+use crate::geometry::{Point, Rect};
+use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::utils::Color;
+
+/// How far the toast is from the bottom of the safe area, and the padding around its text.
+const MARGIN: i32 = 16;
+
+const BACKGROUND_COLOR: Color = Color {
+    r: 0,
+    g: 0,
+    b: 0,
+    a: 0xb0,
+};
+
+/// A short message in a box near the bottom of the screen, which goes away on its own after a
+/// while.
+pub struct UiToast {
+    text: String,
+    /// How many more frames to show it.
+    frames: u32,
+}
+
+impl UiToast {
+    pub fn new(text: &str, frames: u32) -> Self {
+        UiToast {
+            text: text.to_string(),
+            frames,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Advances the toast by a frame. Returns whether it's still showing.
+    pub fn update(&mut self) -> bool {
+        self.frames = self.frames.saturating_sub(1);
+        self.frames > 0
+    }
+
+    /// Draws the text in a box, centered near the bottom of the safe area.
+    pub fn draw(&self, context: &mut RenderContext, layer: RenderLayer, font: &Font) {
+        let area = context.safe_area();
+        let width = font.text_width(&self.text);
+        let position = Point::new(
+            area.x + (area.w - width) / 2,
+            area.bottom() - MARGIN * 2 - font.char_height,
+        );
+        let background = Rect {
+            x: position.x - MARGIN / 2,
+            y: position.y - MARGIN / 2,
+            w: width + MARGIN,
+            h: font.char_height + MARGIN,
+        };
+        context.fill_rect(background, layer, BACKGROUND_COLOR);
+        font.draw_string(context, layer, position, &self.text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn goes_away() {
+        let mut toast = UiToast::new("hello", 3);
+        assert_eq!(toast.text(), "hello");
+        assert!(toast.update());
+        assert!(toast.update());
+        assert!(!toast.update());
+        assert!(!toast.update());
+    }
+}
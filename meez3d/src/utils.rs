@@ -1,3 +1,4 @@
+use std::fmt;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
@@ -39,7 +40,7 @@ impl FromStr for Direction {
 }
 */
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -47,6 +48,53 @@ pub struct Color {
     pub a: u8,
 }
 
+impl Color {
+    pub const WHITE: Color = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+        a: 255,
+    };
+
+    pub const BLACK: Color = Color {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 255,
+    };
+
+    pub const TRANSPARENT: Color = Color {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 0,
+    };
+
+    /// Blends between two colors, where t of 0 is this color and 1 is the other.
+    pub fn lerp(self, other: Color, t: f32) -> Color {
+        let t = t.clamp(0.0, 1.0);
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Color {
+            r: mix(self.r, other.r),
+            g: mix(self.g, other.g),
+            b: mix(self.b, other.b),
+            a: mix(self.a, other.a),
+        }
+    }
+
+    /// Multiplies each channel by the other color's, the way a tint does, so white changes
+    /// nothing.
+    pub fn multiply(self, other: Color) -> Color {
+        let product = |a: u8, b: u8| ((a as u32 * b as u32 + 127) / 255) as u8;
+        Color {
+            r: product(self.r, other.r),
+            g: product(self.g, other.g),
+            b: product(self.b, other.b),
+            a: product(self.a, other.a),
+        }
+    }
+}
+
 impl FromStr for Color {
     type Err = Error;
 
@@ -69,6 +117,21 @@ impl FromStr for Color {
     }
 }
 
+impl fmt::Display for Color {
+    /// Formats the color the way Tiled does, with the alpha first if it's not opaque.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.a == 255 {
+            write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+        } else {
+            write!(
+                f,
+                "#{:02x}{:02x}{:02x}{:02x}",
+                self.a, self.r, self.g, self.b
+            )
+        }
+    }
+}
+
 #[cfg(feature = "wgpu")]
 impl From<Color> for wgpu::Color {
     fn from(value: Color) -> Self {
@@ -92,6 +155,21 @@ impl From<Color> for [f32; 4] {
     }
 }
 
+/// A file name for something about the given level, lowercase, with anything other than a
+/// letter or digit replaced with an underscore.
+pub fn level_file_name(level: &str) -> String {
+    level
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
 pub fn normalize_path(path: &Path) -> Result<PathBuf> {
     let mut output = PathBuf::new();
     for part in path.iter() {
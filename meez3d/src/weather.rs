@@ -0,0 +1,309 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::constants::FRAME_RATE;
+use crate::geometry::{Point, Rect};
+use crate::properties::PropertyMap;
+use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::soundmanager::{Sound, SoundManager};
+use crate::utils::Color;
+
+const DEFAULT_PARTICLES: u32 = 200;
+/// How fast rain falls, in pixels per frame, before each drop's own speed is applied.
+const RAIN_SPEED: f32 = 8.0;
+const RAIN_LENGTH: f32 = 10.0;
+const RAIN_COLOR: Color = Color {
+    r: 0xaa,
+    g: 0xbb,
+    b: 0xff,
+    a: 0x80,
+};
+const SNOW_SPEED: f32 = 1.0;
+/// How far snowflakes drift from side to side, in pixels per frame.
+const SNOW_SWAY: f32 = 0.5;
+const SNOW_SIZE: i32 = 2;
+const SNOW_COLOR: Color = Color {
+    r: 0xff,
+    g: 0xff,
+    b: 0xff,
+    a: 0xc0,
+};
+/// How long the screen stays lit after a lightning strike.
+const FLASH_FRAMES: u32 = FRAME_RATE / 4;
+const FLASH_ALPHA: f32 = 192.0;
+/// The range of time between lightning strikes.
+const LIGHTNING_FRAMES: (u32, u32) = (5 * FRAME_RATE, 15 * FRAME_RATE);
+/// The range of time between a lightning strike and its thunder.
+const THUNDER_DELAY_FRAMES: (u32, u32) = (FRAME_RATE / 2, 2 * FRAME_RATE);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeatherKind {
+    Clear,
+    Rain,
+    Snow,
+}
+
+impl FromStr for WeatherKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "clear" => WeatherKind::Clear,
+            "rain" => WeatherKind::Rain,
+            "snow" => WeatherKind::Snow,
+            _ => bail!("invalid weather: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for WeatherKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WeatherKind::Clear => "clear",
+            WeatherKind::Rain => "rain",
+            WeatherKind::Snow => "snow",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeatherSettings {
+    pub kind: WeatherKind,
+    /// How many raindrops or snowflakes are on screen at once.
+    pub particles: u32,
+    /// How fast the wind blows particles sideways, in pixels per frame. Positive is rightward.
+    pub wind: f32,
+    pub lightning: bool,
+}
+
+impl Default for WeatherSettings {
+    fn default() -> Self {
+        WeatherSettings {
+            kind: WeatherKind::Clear,
+            particles: DEFAULT_PARTICLES,
+            wind: 0.0,
+            lightning: false,
+        }
+    }
+}
+
+impl WeatherSettings {
+    /// The weather for a map with a "weather" property, which is "clear", "rain", or "snow".
+    ///
+    /// "weather_particles" sets how many particles there are, "wind" sets the wind speed in pixels
+    /// per second, and "lightning" turns on lightning and thunder.
+    pub fn from_properties(properties: &PropertyMap) -> Result<Option<WeatherSettings>> {
+        let kind = properties
+            .get_string("weather")?
+            .map(str::parse)
+            .transpose()?;
+        let lightning = properties.get_bool("lightning")?.unwrap_or(false);
+        let kind = match kind {
+            Some(kind) => kind,
+            None if lightning => WeatherKind::Clear,
+            None => return Ok(None),
+        };
+        let defaults = WeatherSettings::default();
+        Ok(Some(WeatherSettings {
+            kind,
+            particles: properties
+                .get_int("weather_particles")?
+                .map_or(defaults.particles, |n| n.max(0) as u32),
+            wind: properties
+                .get_int("wind")?
+                .map_or(defaults.wind, |n| n as f32 / FRAME_RATE as f32),
+            lightning,
+        }))
+    }
+}
+
+struct Particle {
+    position: Point<f32>,
+    /// Scales how fast the particle falls, so they don't all move together.
+    speed: f32,
+    /// Where the particle is in its side to side drift, for snow.
+    phase: f32,
+}
+
+/// Rain or snow falling over the player layer, and lightning with thunder.
+pub struct Weather {
+    pub settings: WeatherSettings,
+    particles: Vec<Particle>,
+    rng: StdRng,
+    /// How many more frames the lightning flash lasts.
+    flash_frames: u32,
+    /// How many frames until the next lightning strike.
+    lightning_in: u32,
+    /// How many frames until the thunder from the last strike, if it hasn't played yet.
+    thunder_in: Option<u32>,
+}
+
+impl Weather {
+    /// Scatters the particles across the area they'll fall through.
+    pub fn new(settings: WeatherSettings, area: Rect<i32>, seed: u64) -> Weather {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let count = match settings.kind {
+            WeatherKind::Clear => 0,
+            WeatherKind::Rain | WeatherKind::Snow => settings.particles as usize,
+        };
+        let particles = (0..count)
+            .map(|_| Particle {
+                position: Point::new(
+                    rng.gen_range(0.0..area.w.max(1) as f32),
+                    rng.gen_range(0.0..area.h.max(1) as f32),
+                ),
+                speed: rng.gen_range(0.75..1.25),
+                phase: rng.gen_range(0.0..std::f32::consts::TAU),
+            })
+            .collect();
+        let lightning_in = rng.gen_range(LIGHTNING_FRAMES.0..LIGHTNING_FRAMES.1);
+        Weather {
+            settings,
+            particles,
+            rng,
+            flash_frames: 0,
+            lightning_in,
+            thunder_in: None,
+        }
+    }
+
+    /// Moves the particles, wrapping them around the area, and plays any thunder that's due.
+    pub fn update(&mut self, area: Rect<i32>, sounds: &mut SoundManager) {
+        let (width, height) = (area.w.max(1) as f32, area.h.max(1) as f32);
+        for particle in self.particles.iter_mut() {
+            let (dx, dy) = match self.settings.kind {
+                WeatherKind::Clear => (0.0, 0.0),
+                WeatherKind::Rain => (self.settings.wind, RAIN_SPEED * particle.speed),
+                WeatherKind::Snow => {
+                    particle.phase += 0.05;
+                    (
+                        self.settings.wind + particle.phase.sin() * SNOW_SWAY,
+                        SNOW_SPEED * particle.speed,
+                    )
+                }
+            };
+            let position = &mut particle.position;
+            position.x = (position.x + dx).rem_euclid(width);
+            position.y += dy;
+            if position.y >= height {
+                position.y -= height;
+                position.x = self.rng.gen_range(0.0..width);
+            }
+        }
+
+        self.flash_frames = self.flash_frames.saturating_sub(1);
+        if let Some(frames) = &mut self.thunder_in {
+            if *frames == 0 {
+                sounds.play(Sound::Thunder);
+                self.thunder_in = None;
+            } else {
+                *frames -= 1;
+            }
+        }
+        if self.settings.lightning {
+            if self.lightning_in == 0 {
+                self.strike();
+            } else {
+                self.lightning_in -= 1;
+            }
+        }
+    }
+
+    /// Flashes the screen now, with thunder to follow.
+    pub fn strike(&mut self) {
+        self.flash_frames = FLASH_FRAMES;
+        self.thunder_in = Some(
+            self.rng
+                .gen_range(THUNDER_DELAY_FRAMES.0..THUNDER_DELAY_FRAMES.1),
+        );
+        self.lightning_in = self.rng.gen_range(LIGHTNING_FRAMES.0..LIGHTNING_FRAMES.1);
+    }
+
+    pub fn draw(&self, context: &mut RenderContext) {
+        for particle in self.particles.iter() {
+            let Point { x, y } = particle.position;
+            match self.settings.kind {
+                WeatherKind::Clear => {}
+                WeatherKind::Rain => {
+                    // Streak back along the direction the drop is falling.
+                    let fall = RAIN_SPEED * particle.speed;
+                    let tail =
+                        Point::new(x - self.settings.wind * RAIN_LENGTH / fall, y - RAIN_LENGTH);
+                    context.player_batch.draw_line(
+                        Point::new(x as i32, y as i32),
+                        Point::new(tail.x as i32, tail.y as i32),
+                        RAIN_COLOR,
+                        1,
+                    );
+                }
+                WeatherKind::Snow => {
+                    let flake = Rect {
+                        x: x as i32,
+                        y: y as i32,
+                        w: SNOW_SIZE,
+                        h: SNOW_SIZE,
+                    };
+                    context.fill_rect(flake, RenderLayer::Player, SNOW_COLOR);
+                }
+            }
+        }
+
+        if self.flash_frames > 0 {
+            let alpha = FLASH_ALPHA * self.flash_frames as f32 / FLASH_FRAMES as f32;
+            let flash = Color {
+                a: alpha.round() as u8,
+                ..Color::WHITE
+            };
+            context.fill_rect(context.logical_area(), RenderLayer::Player, flash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::soundmanager::recording_sound_manager;
+
+    #[test]
+    fn wind_and_thunder() {
+        let area = Rect {
+            x: 0,
+            y: 0,
+            w: 100,
+            h: 100,
+        };
+        let settings = WeatherSettings {
+            kind: WeatherKind::Snow,
+            particles: 10,
+            wind: 2.0,
+            lightning: true,
+        };
+        let mut weather = Weather::new(settings, area, 1);
+        let (mut sounds, played) = recording_sound_manager();
+
+        // The wind outpaces the sway, so every flake drifts right, and they all stay on screen.
+        let before: Vec<f32> = weather.particles.iter().map(|p| p.position.x).collect();
+        weather.update(area, &mut sounds);
+        for (particle, x) in weather.particles.iter().zip(before) {
+            let moved = (particle.position.x - x).rem_euclid(100.0);
+            assert!(moved > 1.0 && moved < 3.0);
+            assert!(particle.position.y >= 0.0 && particle.position.y < 100.0);
+        }
+
+        weather.strike();
+        assert!(weather.flash_frames > 0);
+        for _ in 0..THUNDER_DELAY_FRAMES.0 {
+            weather.update(area, &mut sounds);
+        }
+        assert!(played.borrow().is_empty());
+        for _ in THUNDER_DELAY_FRAMES.0..=THUNDER_DELAY_FRAMES.1 {
+            weather.update(area, &mut sounds);
+        }
+        assert_eq!(*played.borrow(), vec!["thunder"]);
+        assert_eq!(weather.flash_frames, 0);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::geometry::Rect;
+
+/// Entries from a sprite batch that can all be drawn at once, with the same texture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket {
+    /// The texture the entries are drawn from, or None if none of them needs one.
+    pub texture_id: Option<usize>,
+    /// Indexes of the entries, in the order they were added to the batch.
+    pub entries: Vec<usize>,
+    /// The smallest rect containing everything in the bucket.
+    bounds: Option<Rect<i32>>,
+}
+
+/// Whether two rects share any pixels, unlike Rect::intersects, which counts touching edges.
+fn overlaps(a: Rect<i32>, b: Rect<i32>) -> bool {
+    a.x < b.right() && b.x < a.right() && a.y < b.bottom() && b.y < a.bottom()
+}
+
+fn union(a: Rect<i32>, b: Rect<i32>) -> Rect<i32> {
+    let (x, y) = (a.x.min(b.x), a.y.min(b.y));
+    Rect {
+        x,
+        y,
+        w: a.right().max(b.right()) - x,
+        h: a.bottom().max(b.bottom()) - y,
+    }
+}
+
+/// Groups a batch's entries by texture, so each texture is drawn with as few draws as possible,
+/// given the texture each entry is drawn from, if any, and its bounds.
+///
+/// An entry only moves ahead of entries from other textures that it doesn't overlap, so
+/// everything still ends up on top of what it was drawn over. Entries without a texture go
+/// with whatever was drawn right before them.
+pub fn bucket_entries(entries: impl Iterator<Item = (Option<usize>, Rect<i32>)>) -> Vec<Bucket> {
+    let mut buckets: Vec<Bucket> = Vec::new();
+    for (i, (texture_id, bounds)) in entries.enumerate() {
+        let found = match texture_id {
+            None => buckets.len().checked_sub(1),
+            Some(id) => {
+                let mut found = None;
+                for (j, bucket) in buckets.iter().enumerate().rev() {
+                    if bucket.texture_id.is_none_or(|other| other == id) {
+                        found = Some(j);
+                        break;
+                    }
+                    if bucket.bounds.is_some_and(|other| overlaps(other, bounds)) {
+                        break;
+                    }
+                }
+                found
+            }
+        };
+        let Some(j) = found else {
+            buckets.push(Bucket {
+                texture_id,
+                entries: vec![i],
+                bounds: Some(bounds),
+            });
+            continue;
+        };
+        let bucket = &mut buckets[j];
+        bucket.texture_id = bucket.texture_id.or(texture_id);
+        bucket.entries.push(i);
+        bucket.bounds = Some(match bucket.bounds {
+            Some(other) => union(other, bounds),
+            None => bounds,
+        });
+    }
+    buckets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(x: i32, y: i32) -> Rect<i32> {
+        Rect { x, y, w: 10, h: 10 }
+    }
+
+    #[test]
+    fn buckets() {
+        let entries = [
+            (None, square(0, 0)),
+            (Some(1), square(0, 0)),
+            (Some(2), square(20, 0)),
+            // Apart from the last one, so it goes in the first bucket.
+            (Some(1), square(40, 0)),
+            // This is on top of the one from texture 2, so it has to be drawn after.
+            (Some(1), square(25, 5)),
+            (None, square(100, 100)),
+        ];
+        let buckets = bucket_entries(entries.into_iter());
+        let grouped: Vec<(Option<usize>, Vec<usize>)> = buckets
+            .into_iter()
+            .map(|bucket| (bucket.texture_id, bucket.entries))
+            .collect();
+        assert_eq!(
+            grouped,
+            [
+                (Some(1), vec![0, 1, 3]),
+                (Some(2), vec![2]),
+                (Some(1), vec![4, 5])
+            ]
+        );
+    }
+}
@@ -1,5 +1,7 @@
+mod batching;
 mod pipeline;
 mod shader;
 mod texture;
+mod vertexbuffer;
 
 pub mod renderer;
@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use anyhow::Result;
 use bytemuck::Pod;
 use wgpu::util::DeviceExt;
@@ -42,9 +44,41 @@ pub struct Pipeline {
     fragment_uniform_bind_group: wgpu::BindGroup,
     fragment_uniform_buffer: Option<wgpu::Buffer>,
 
+    texture_bind_group_layout: wgpu::BindGroupLayout,
     texture_bind_group: wgpu::BindGroup,
 }
 
+/// A range of vertices in a render, and the textures to draw them with, if not the pipeline's own.
+pub struct Draw<'a> {
+    pub vertices: Range<u32>,
+    pub textures: Option<&'a wgpu::BindGroup>,
+}
+
+fn create_texture_bind_group(
+    label: &str,
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    textures: &[&Texture],
+) -> wgpu::BindGroup {
+    let mut texture_bind_group_entries = Vec::new();
+    for (i, texture) in textures.iter().enumerate() {
+        texture_bind_group_entries.push(wgpu::BindGroupEntry {
+            binding: i as u32 * 2,
+            resource: wgpu::BindingResource::TextureView(&texture.view),
+        });
+        texture_bind_group_entries.push(wgpu::BindGroupEntry {
+            binding: i as u32 * 2 + 1,
+            resource: wgpu::BindingResource::Sampler(&texture.sampler),
+        });
+    }
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(format!("[{}] Texture Bind Group", label).as_str()),
+        layout,
+        entries: &texture_bind_group_entries,
+    })
+}
+
 impl Pipeline {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
@@ -177,23 +211,8 @@ impl Pipeline {
             multiview: None,
         });
 
-        let mut texture_bind_group_entries = Vec::new();
-        for (i, texture) in textures.iter().enumerate() {
-            texture_bind_group_entries.push(wgpu::BindGroupEntry {
-                binding: i as u32 * 2,
-                resource: wgpu::BindingResource::TextureView(&texture.view),
-            });
-            texture_bind_group_entries.push(wgpu::BindGroupEntry {
-                binding: i as u32 * 2 + 1,
-                resource: wgpu::BindingResource::Sampler(&texture.sampler),
-            });
-        }
-
-        let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some(format!("[{}] Texture Bind Group", label).as_str()),
-            layout: &texture_bind_group_layout,
-            entries: &texture_bind_group_entries,
-        });
+        let texture_bind_group =
+            create_texture_bind_group(label, device, &texture_bind_group_layout, textures);
 
         let label = label.to_owned();
 
@@ -207,6 +226,7 @@ impl Pipeline {
             fragment_uniform_bind_group_layout,
             fragment_uniform_bind_group,
             fragment_uniform_buffer,
+            texture_bind_group_layout,
             texture_bind_group,
         })
     }
@@ -270,6 +290,25 @@ impl Pipeline {
         );
     }
 
+    /// Creates a bind group for textures that can be swapped in for the pipeline's own in a Draw.
+    pub fn create_texture_bind_group(
+        &self,
+        device: &wgpu::Device,
+        textures: &[&Texture],
+    ) -> wgpu::BindGroup {
+        create_texture_bind_group(
+            &self.label,
+            device,
+            &self.texture_bind_group_layout,
+            textures,
+        )
+    }
+
+    /// Replaces the textures the pipeline was created with.
+    pub fn set_textures(&mut self, device: &wgpu::Device, textures: &[&Texture]) {
+        self.texture_bind_group = self.create_texture_bind_group(device, textures);
+    }
+
     pub fn render(
         &self,
         encoder: &mut wgpu::CommandEncoder,
@@ -277,6 +316,26 @@ impl Pipeline {
         clear_color: Color,
         vertex_buffer: wgpu::BufferSlice,
         vertex_count: u32,
+    ) {
+        self.render_draws(
+            encoder,
+            destination,
+            clear_color,
+            vertex_buffer,
+            &[Draw {
+                vertices: 0..vertex_count,
+                textures: None,
+            }],
+        );
+    }
+
+    pub fn render_draws(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        destination: &wgpu::TextureView,
+        clear_color: Color,
+        vertex_buffer: wgpu::BufferSlice,
+        draws: &[Draw],
     ) {
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
@@ -296,8 +355,11 @@ impl Pipeline {
         render_pass.set_pipeline(&self.render_pipeline);
         render_pass.set_bind_group(0, &self.vertex_uniform_bind_group, &[]);
         render_pass.set_bind_group(1, &self.fragment_uniform_bind_group, &[]);
-        render_pass.set_bind_group(2, &self.texture_bind_group, &[]);
         render_pass.set_vertex_buffer(0, vertex_buffer);
-        render_pass.draw(0..vertex_count, 0..1);
+        for draw in draws {
+            let textures = draw.textures.unwrap_or(&self.texture_bind_group);
+            render_pass.set_bind_group(2, textures, &[]);
+            render_pass.draw(draw.vertices.clone(), 0..1);
+        }
     }
 }
@@ -1,8 +1,9 @@
 use std::mem;
+use std::ops::Range;
 use std::path::Path;
 
-use anyhow::Result;
-use bytemuck::Zeroable;
+use anyhow::{anyhow, bail, Result};
+use image::RgbaImage;
 use log::{error, info};
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 use wgpu::util::DeviceExt;
@@ -11,20 +12,32 @@ use wgpu::SurfaceTargetUnsafe;
 use crate::constants::{FRAME_RATE, MAX_LIGHTS, RENDER_HEIGHT, RENDER_WIDTH};
 use crate::filemanager::FileManager;
 use crate::geometry::{Point, Rect};
-use crate::rendercontext::{RenderContext, RenderLayer, SpriteBatch, SpriteBatchEntry};
-use crate::renderer::Renderer;
+use crate::rendercontext::{
+    line_quad, LineCap, RenderContext, RenderLayer, SpriteBatch, SpriteBatchEntry,
+};
+use crate::renderer::{Renderer, RendererStats};
 use crate::sprite::Sprite;
+use crate::transition::TransitionKind;
 use crate::utils::Color;
-use crate::wgpu::pipeline::Pipeline;
+use crate::wgpu::batching::bucket_entries;
+use crate::wgpu::pipeline::{Draw, Pipeline};
 use crate::wgpu::shader::RenderVertexUniform;
 use crate::wgpu::shader::Vertex;
 use crate::wgpu::shader::{self, PostprocessVertex};
 use crate::wgpu::texture::Texture;
+use crate::wgpu::vertexbuffer::VertexBuffer;
 
 use super::shader::PostprocessFragmentUniform;
 
-const MAX_ENTRIES: usize = 4096;
-const MAX_VERTICES: usize = MAX_ENTRIES * 6;
+/// Every texture is RGBA or BGRA, at one byte per channel.
+const BYTES_PER_PIXEL: u64 = 4;
+
+/// Sprites from the first texture atlas have this id, and other textures count up from it.
+const TEXTURE_ATLAS_ID: usize = 0;
+
+/// Solid shapes aren't textured, so they're given a zero tint, which tells the shader to draw
+/// their color instead of sampling the texture. Sprites use their color to flash instead.
+const SOLID_TINT: [f32; 4] = [0.0, 0.0, 0.0, 0.0];
 
 const RECT_VERTICES: &[PostprocessVertex] = &[
     PostprocessVertex {
@@ -53,30 +66,43 @@ const RECT_VERTICES: &[PostprocessVertex] = &[
     },
 ];
 
+/// The corners of a rect, in the order add_rect_to_vertex_buffer takes them.
+fn rect_corners(rect: Rect<i32>) -> [Point<f32>; 4] {
+    let (l, t) = (rect.x as f32, rect.y as f32);
+    let (r, b) = (rect.right() as f32, rect.bottom() as f32);
+    [
+        Point::new(l, t),
+        Point::new(r, t),
+        Point::new(r, b),
+        Point::new(l, b),
+    ]
+}
+
+/// Adds a quad with its top left, top right, bottom right, and bottom left corners at the given
+/// points, which may be turned or stretched, showing the source area of the texture.
 #[allow(clippy::too_many_arguments)]
 fn add_rect_to_vertex_buffer(
     vertices: &mut Vec<Vertex>,
-    vertex_count: &mut usize,
-    destination: Rect<i32>,
+    corners: [Point<f32>; 4],
     source: Rect<i32>,
     color: Color,
-    reversed: bool,
+    tint: Color,
+    flip_horizontal: bool,
+    flip_vertical: bool,
     texture_atlas_width: u32,
     texture_atlas_height: u32,
 ) {
-    let dt = destination.y as f32;
-    let db = destination.bottom() as f32;
-    let dl = destination.x as f32;
-    let dr = destination.right() as f32;
-
-    let st = source.y as f32;
-    let sb = source.bottom() as f32;
+    let mut st = source.y as f32;
+    let mut sb = source.bottom() as f32;
     let mut sl = source.x as f32;
     let mut sr = source.right() as f32;
 
-    if reversed {
+    if flip_horizontal {
         mem::swap(&mut sl, &mut sr);
     }
+    if flip_vertical {
+        mem::swap(&mut st, &mut sb);
+    }
 
     // TODO: Consider moving this scaling into the shader.
     let xscale = texture_atlas_width as f32;
@@ -87,146 +113,131 @@ fn add_rect_to_vertex_buffer(
     let sr = sr / xscale;
 
     let color: [f32; 4] = color.into();
+    let tint: [f32; 4] = tint.into();
 
-    let i = *vertex_count;
-    *vertex_count += 6;
-
-    vertices[i] = Vertex {
-        position: [dl, dt],
+    let [tl, tr, br, bl] = corners;
+    let top_left = Vertex {
+        position: [tl.x, tl.y],
         tex_coords: [sl, st],
         color,
+        tint,
     };
-    vertices[i + 1] = Vertex {
-        position: [dl, db],
-        tex_coords: [sl, sb],
-        color,
-    };
-    vertices[i + 2] = Vertex {
-        position: [dr, dt],
+    let top_right = Vertex {
+        position: [tr.x, tr.y],
         tex_coords: [sr, st],
         color,
+        tint,
     };
-    vertices[i + 3] = Vertex {
-        position: [dr, dt],
-        tex_coords: [sr, st],
+    let bottom_right = Vertex {
+        position: [br.x, br.y],
+        tex_coords: [sr, sb],
         color,
+        tint,
     };
-    vertices[i + 4] = Vertex {
-        position: [dl, db],
+    let bottom_left = Vertex {
+        position: [bl.x, bl.y],
         tex_coords: [sl, sb],
         color,
+        tint,
     };
-    vertices[i + 5] = Vertex {
-        position: [dr, db],
-        tex_coords: [sr, sb],
-        color,
+
+    // Triangles that are clockwise on screen are culled, so a quad that's been mirrored by a
+    // negative scale has its triangles wound the other way.
+    let mirrored = (tr.x - tl.x) * (bl.y - tl.y) - (bl.x - tl.x) * (tr.y - tl.y) < 0.0;
+    let quad = if mirrored {
+        [
+            top_left,
+            top_right,
+            bottom_left,
+            top_right,
+            bottom_right,
+            bottom_left,
+        ]
+    } else {
+        [
+            top_left,
+            bottom_left,
+            top_right,
+            top_right,
+            bottom_left,
+            bottom_right,
+        ]
     };
+
+    vertices.extend_from_slice(&quad);
 }
 
 fn add_triangle_to_vertex_buffer(
     vertices: &mut Vec<Vertex>,
-    vertex_count: &mut usize,
     point1: Point<i32>,
     point2: Point<i32>,
     point3: Point<i32>,
     color: Color,
 ) {
     let color: [f32; 4] = color.into();
-
-    let i = *vertex_count;
-    *vertex_count += 3;
-
-    vertices[i] = Vertex {
-        position: [point1.x as f32, point1.y as f32],
-        tex_coords: [0.0, 0.0],
-        color,
-    };
-    vertices[i + 1] = Vertex {
-        position: [point2.x as f32, point2.y as f32],
+    vertices.extend([point1, point2, point3].map(|point| Vertex {
+        position: [point.x as f32, point.y as f32],
         tex_coords: [0.0, 0.0],
         color,
-    };
-    vertices[i + 2] = Vertex {
-        position: [point3.x as f32, point3.y as f32],
-        tex_coords: [0.0, 0.0],
-        color,
-    };
+        tint: SOLID_TINT,
+    }));
+}
+
+fn add_gradient_triangle_to_vertex_buffer(
+    vertices: &mut Vec<Vertex>,
+    points: [Point<f32>; 3],
+    colors: [Color; 3],
+) {
+    for (point, color) in points.into_iter().zip(colors) {
+        vertices.push(Vertex {
+            position: [point.x, point.y],
+            tex_coords: [0.0, 0.0],
+            color: color.into(),
+            tint: SOLID_TINT,
+        });
+    }
+}
+
+/// Adds a rect with the given colors at its top left, top right, bottom right, and bottom left.
+fn add_gradient_rect_to_vertex_buffer(
+    vertices: &mut Vec<Vertex>,
+    destination: Rect<i32>,
+    colors: [Color; 4],
+) {
+    let top_left = Point::new(destination.x as f32, destination.y as f32);
+    let top_right = Point::new(destination.right() as f32, destination.y as f32);
+    let bottom_right = Point::new(destination.right() as f32, destination.bottom() as f32);
+    let bottom_left = Point::new(destination.x as f32, destination.bottom() as f32);
+    let [top_left_color, top_right_color, bottom_right_color, bottom_left_color] = colors;
+
+    add_gradient_triangle_to_vertex_buffer(
+        vertices,
+        [top_left, bottom_left, top_right],
+        [top_left_color, bottom_left_color, top_right_color],
+    );
+    add_gradient_triangle_to_vertex_buffer(
+        vertices,
+        [top_right, bottom_left, bottom_right],
+        [top_right_color, bottom_left_color, bottom_right_color],
+    );
 }
 
 fn add_line_to_vertex_buffer(
     vertices: &mut Vec<Vertex>,
-    vertex_count: &mut usize,
     point1: Point<i32>,
     point2: Point<i32>,
     color: Color,
     width: i32,
+    cap: LineCap,
 ) {
-    if point1.x == point2.x || point1.y == point2.y {
-        panic!("a horizontal or vertical line was added to the line list");
-    }
-
-    if point1.x > point2.x {
-        add_line_to_vertex_buffer(vertices, vertex_count, point2, point1, color, width);
-        return;
-    }
-
-    let p1 = Point::new(point1.x as f32, point1.y as f32);
-    let p2 = Point::new(point2.x as f32, point2.y as f32);
-    let slope = (p2.y - p1.y) / (p2.x - p1.x);
-    let perp_slope = -1.0 / slope;
-    let theta = perp_slope.atan();
-
-    let half_width = width as f32 / 2.0;
-    let dx = half_width * theta.cos();
-    let dy = half_width * theta.sin();
-    let delta = Point::new(dx, dy);
-
-    let delta = if perp_slope < 0.0 {
-        delta * -1.0
-    } else {
-        delta
-    };
-
-    let q1 = p1 - delta;
-    let q2 = p1 + delta;
-    let q3 = p2 + delta;
-    let q4 = p2 - delta;
-
+    let [q1, q2, q3, q4] = line_quad(point1, point2, width, cap);
     let color: [f32; 4] = color.into();
-
-    let i = *vertex_count;
-    *vertex_count += 6;
-
-    vertices[i] = Vertex {
-        position: [q1.x, q1.y],
-        tex_coords: [0.0, 0.0],
-        color,
-    };
-    vertices[i + 1] = Vertex {
-        position: [q2.x, q2.y],
-        tex_coords: [0.0, 0.0],
-        color,
-    };
-    vertices[i + 2] = Vertex {
-        position: [q3.x, q3.y],
+    vertices.extend([q1, q2, q3, q3, q4, q1].map(|q| Vertex {
+        position: [q.x, q.y],
         tex_coords: [0.0, 0.0],
         color,
-    };
-    vertices[i + 3] = Vertex {
-        position: [q3.x, q3.y],
-        tex_coords: [0.0, 0.0],
-        color,
-    };
-    vertices[i + 4] = Vertex {
-        position: [q4.x, q4.y],
-        tex_coords: [0.0, 0.0],
-        color,
-    };
-    vertices[i + 5] = Vertex {
-        position: [q1.x, q1.y],
-        tex_coords: [0.0, 0.0],
-        color,
-    };
+        tint: SOLID_TINT,
+    }));
 }
 
 pub trait WindowHandle
@@ -252,19 +263,53 @@ pub struct WgpuRenderer<'window, T: WindowHandle> {
 
     render_pipeline: Pipeline,
 
-    texture_atlas_width: u32,
-    texture_atlas_height: u32,
+    /// Every texture sprites are drawn from, the texture atlases and dynamic textures, indexed by
+    /// sprite id. Ids aren't reused, so the slots of atlases that have been replaced are empty.
+    textures: Vec<Option<SpriteTexture>>,
+    /// Which textures are atlases, in the order they were loaded.
+    atlas_ids: Vec<usize>,
 
+    /// Each layer's vertices are built here every frame, and then copied to its buffer.
     player_vertices: Vec<Vertex>,
-    player_vertex_buffer: wgpu::Buffer,
+    player_vertex_buffer: VertexBuffer,
     hud_vertices: Vec<Vertex>,
-    hud_vertex_buffer: wgpu::Buffer,
+    hud_vertex_buffer: VertexBuffer,
+    overlay_vertices: Vec<Vertex>,
+    overlay_vertex_buffer: VertexBuffer,
 
     player_framebuffer: Texture,
     hud_framebuffer: Texture,
+    /// Mixed in after the postprocessing effects, at the HUD's scale.
+    overlay_framebuffer: Texture,
+    static_texture: Texture,
     postprocess_pipeline: Pipeline,
     postprocess_vertex_buffer: wgpu::Buffer,
     fragment_uniform: PostprocessFragmentUniform,
+    /// The outgoing scene's finished frame is drawn here when a transition starts.
+    transition_buffer: Texture,
+    /// The frame number of the outgoing context in the transition buffer, if it's been drawn,
+    /// so it's only drawn once for the whole transition.
+    transition_frame: Option<u64>,
+    /// The postprocess textures, but without the transition buffer, for drawing into it.
+    outgoing_textures: wgpu::BindGroup,
+
+    /// Whether each finished frame is read back.
+    capturing: bool,
+    /// While capturing, each finished frame is also drawn here, to be read back. It's made the
+    /// first time capturing starts, and kept, since capturing can be turned on and off every
+    /// few frames.
+    capture_buffer: Option<Texture>,
+    /// The last frame read back from the capture buffer.
+    captured_frame: Option<RgbaImage>,
+
+    /// The memory totals are kept up to date as things are allocated, and the rest per frame.
+    stats: RendererStats,
+}
+
+/// A texture, kept so it can be written to after it's loaded, and the bind group to draw it with.
+struct SpriteTexture {
+    texture: Texture,
+    bind_group: wgpu::BindGroup,
 }
 
 impl<'window, T> WgpuRenderer<'window, T>
@@ -277,8 +322,6 @@ where
         window_width: u32,
         window_height: u32,
         vsync: bool,
-        texture_atlas_path: &Path,
-        file_manager: &FileManager,
     ) -> Result<Self> {
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             backends: wgpu::Backends::all(),
@@ -318,10 +361,8 @@ where
             .await
             .unwrap();
 
-        info!("Reading texture atlas from {:?}", texture_atlas_path);
-        let texture_atlas = Texture::from_file(&device, &queue, texture_atlas_path, file_manager)?;
-        let texture_atlas_width = texture_atlas.width;
-        let texture_atlas_height = texture_atlas.height;
+        // The real atlas is swapped in by load_texture_atlas.
+        let texture_atlas = Texture::dynamic(&device, &queue, 1, 1)?;
 
         let surface_caps = surface.get_capabilities(&adapter);
 
@@ -359,21 +400,9 @@ where
             source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
         });
 
-        let mut player_vertices = Vec::new();
-        player_vertices.resize_with(MAX_VERTICES, Vertex::zeroed);
-        let player_vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&player_vertices),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let mut hud_vertices = Vec::new();
-        hud_vertices.resize_with(MAX_VERTICES, Vertex::zeroed);
-        let hud_vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&hud_vertices),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
+        let player_vertex_buffer = VertexBuffer::new(&device, "Player Vertex Buffer");
+        let hud_vertex_buffer = VertexBuffer::new(&device, "HUD Vertex Buffer");
+        let overlay_vertex_buffer = VertexBuffer::new(&device, "Overlay Vertex Buffer");
 
         let postprocess_vertex_buffer =
             device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -395,10 +424,14 @@ where
 
         let vertex_uniform = RenderVertexUniform::new(RENDER_WIDTH, RENDER_HEIGHT);
         render_pipeline.set_vertex_uniform(&device, vertex_uniform);
+        let bind_group = render_pipeline.create_texture_bind_group(&device, &[&texture_atlas]);
 
-        let player_framebuffer = Texture::frame_buffer(&device, config.format)?;
-        let hud_framebuffer = Texture::frame_buffer(&device, config.format)?;
+        let player_framebuffer = Texture::frame_buffer(&device, config.format, 1)?;
+        let hud_framebuffer = Texture::frame_buffer(&device, config.format, 1)?;
+        let overlay_framebuffer = Texture::frame_buffer(&device, config.format, 1)?;
         let static_texture = Texture::static_texture(&device, &queue, RENDER_WIDTH, RENDER_HEIGHT)?;
+        let transition_buffer =
+            Texture::transition_buffer(&device, config.format, window_width, window_height)?;
 
         let mut postprocess_pipeline = Pipeline::new(
             "Postprocess Pipeline",
@@ -407,9 +440,26 @@ where
             "vs_main2",
             "fs_main2",
             PostprocessVertex::desc(),
-            &[&player_framebuffer, &hud_framebuffer, &static_texture],
+            &[
+                &player_framebuffer,
+                &hud_framebuffer,
+                &static_texture,
+                &transition_buffer,
+                &overlay_framebuffer,
+            ],
             config.format,
         )?;
+        // The static texture stands in for the transition buffer, which isn't read from then.
+        let outgoing_textures = postprocess_pipeline.create_texture_bind_group(
+            &device,
+            &[
+                &player_framebuffer,
+                &hud_framebuffer,
+                &static_texture,
+                &static_texture,
+                &overlay_framebuffer,
+            ],
+        );
 
         let fragment_uniform = PostprocessFragmentUniform {
             texture_size: [RENDER_WIDTH as f32, RENDER_HEIGHT as f32],
@@ -417,7 +467,16 @@ where
             time_s: 0.0,
             is_dark: 0,
             spotlight_count: 0,
-            _padding: 0,
+            tube_warp: 0,
+            fuzz: 0,
+            color_split: 0.0,
+            scanlines: 0.0,
+            static_noise: 0.0,
+            hud_scale: 1.0,
+            underwater: 0.0,
+            transition: 0,
+            transition_progress: 0.0,
+            ambient_light: [1.0; 4],
             spotlight: [shader::Light {
                 position: [0.0, 0.0],
                 radius: 0.0,
@@ -426,6 +485,29 @@ where
         };
         postprocess_pipeline.set_fragment_uniform(&device, fragment_uniform);
 
+        let buffer_bytes = player_vertex_buffer.size()
+            + hud_vertex_buffer.size()
+            + overlay_vertex_buffer.size()
+            + postprocess_vertex_buffer.size()
+            + mem::size_of::<RenderVertexUniform>() as u64
+            + mem::size_of::<PostprocessFragmentUniform>() as u64;
+        let texture_bytes = [
+            &texture_atlas,
+            &player_framebuffer,
+            &hud_framebuffer,
+            &overlay_framebuffer,
+            &static_texture,
+            &transition_buffer,
+        ]
+        .iter()
+        .map(|texture| texture_size(texture))
+        .sum();
+        let stats = RendererStats {
+            buffer_bytes,
+            texture_bytes,
+            ..Default::default()
+        };
+
         Ok(Self {
             surface,
             device,
@@ -435,16 +517,30 @@ where
             window_height,
             render_pipeline,
             postprocess_pipeline,
-            player_vertices,
+            player_vertices: Vec::new(),
             player_vertex_buffer,
-            hud_vertices,
+            hud_vertices: Vec::new(),
             hud_vertex_buffer,
+            overlay_vertices: Vec::new(),
+            overlay_vertex_buffer,
             postprocess_vertex_buffer,
             fragment_uniform,
-            texture_atlas_width,
-            texture_atlas_height,
+            textures: vec![Some(SpriteTexture {
+                texture: texture_atlas,
+                bind_group,
+            })],
+            atlas_ids: vec![TEXTURE_ATLAS_ID],
             player_framebuffer,
             hud_framebuffer,
+            overlay_framebuffer,
+            static_texture,
+            transition_buffer,
+            transition_frame: None,
+            outgoing_textures,
+            capturing: false,
+            capture_buffer: None,
+            captured_frame: None,
+            stats,
             window,
         })
     }
@@ -453,153 +549,261 @@ where
         self.window
     }
 
-    pub fn resize(&mut self, new_width: u32, new_height: u32) {
-        if new_width > 0 && new_height > 0 {
-            self.window_width = new_width;
-            self.window_height = new_height;
-            self.config.width = new_width;
-            self.config.height = new_height;
-            self.surface.configure(&self.device, &self.config);
+    /// Renders the HUD and the overlay at 1, 2, or 4 times the render size, while the game view
+    /// stays chunky.
+    pub fn set_hud_scale(&mut self, scale: u32) -> Result<()> {
+        if ![1, 2, 4].contains(&scale) {
+            bail!("invalid hud scale: {}", scale);
         }
+        info!("using hud scale {}", scale);
+        let hud_framebuffer = Texture::frame_buffer(&self.device, self.config.format, scale)?;
+        let overlay_framebuffer = Texture::frame_buffer(&self.device, self.config.format, scale)?;
+        self.stats.texture_bytes -= texture_size(&self.hud_framebuffer);
+        self.stats.texture_bytes -= texture_size(&self.overlay_framebuffer);
+        self.stats.texture_bytes += texture_size(&hud_framebuffer);
+        self.stats.texture_bytes += texture_size(&overlay_framebuffer);
+        self.hud_framebuffer = hud_framebuffer;
+        self.overlay_framebuffer = overlay_framebuffer;
+        self.set_postprocess_textures();
+        self.fragment_uniform.hud_scale = scale as f32;
+        Ok(())
     }
 
-    fn fill_vertex_buffer(&mut self, layer: RenderLayer, batch: &SpriteBatch) -> u32 {
+    /// Points postprocessing at the current framebuffers, after any of them are replaced.
+    fn set_postprocess_textures(&mut self) {
+        self.postprocess_pipeline.set_textures(
+            &self.device,
+            &[
+                &self.player_framebuffer,
+                &self.hud_framebuffer,
+                &self.static_texture,
+                &self.transition_buffer,
+                &self.overlay_framebuffer,
+            ],
+        );
+        self.outgoing_textures = self.postprocess_pipeline.create_texture_bind_group(
+            &self.device,
+            &[
+                &self.player_framebuffer,
+                &self.hud_framebuffer,
+                &self.static_texture,
+                &self.static_texture,
+                &self.overlay_framebuffer,
+            ],
+        );
+    }
+
+    /// Fills the layer's vertex buffer, returning runs of vertices that share a texture id.
+    ///
+    /// Entries are grouped by texture as far as they can be without changing what's drawn on
+    /// top, so usually there's one run for each texture.
+    fn fill_vertex_buffer(
+        &mut self,
+        layer: RenderLayer,
+        batch: &SpriteBatch,
+    ) -> Vec<(Range<u32>, usize)> {
         let (vertex_buffer, vertices) = match layer {
-            RenderLayer::Player => (&self.player_vertex_buffer, &mut self.player_vertices),
-            RenderLayer::Hud => (&self.hud_vertex_buffer, &mut self.hud_vertices),
+            RenderLayer::Player => (&mut self.player_vertex_buffer, &mut self.player_vertices),
+            RenderLayer::Hud => (&mut self.hud_vertex_buffer, &mut self.hud_vertices),
+            RenderLayer::Overlay => (&mut self.overlay_vertex_buffer, &mut self.overlay_vertices),
         };
+        vertices.clear();
 
-        if batch.entries.len() > MAX_ENTRIES {
-            error!("sprite batch is too large: {}", batch.entries.len());
-        }
-
-        let mut vertex_count = 0;
-
-        for entry in batch.entries.iter() {
-            if vertex_count >= MAX_VERTICES {
-                break;
-            }
-
-            match entry {
-                SpriteBatchEntry::FillRect { destination, color } => {
-                    let source = Rect {
-                        x: 0,
-                        y: 0,
-                        w: 0,
-                        h: 0,
-                    };
-                    add_rect_to_vertex_buffer(
-                        vertices,
-                        &mut vertex_count,
-                        *destination,
+        let buckets = bucket_entries(batch.entries.iter().map(|entry| {
+            let texture_id = match entry {
+                SpriteBatchEntry::Sprite { sprite, .. }
+                | SpriteBatchEntry::TransformedSprite { sprite, .. } => Some(sprite.id),
+                _ => None,
+            };
+            (texture_id, entry.bounds())
+        }));
+
+        let mut runs = Vec::new();
+        for bucket in buckets {
+            let texture_id = bucket.texture_id.unwrap_or(TEXTURE_ATLAS_ID);
+            let Some(texture) = self.textures.get(texture_id).and_then(Option::as_ref) else {
+                error!("unknown texture for sprite: {}", texture_id);
+                continue;
+            };
+            let (texture_width, texture_height) = (texture.texture.width, texture.texture.height);
+            let run_start = vertices.len();
+            for &i in bucket.entries.iter() {
+                match &batch.entries[i] {
+                    SpriteBatchEntry::FillRect { destination, color } => {
+                        let source = Rect {
+                            x: 0,
+                            y: 0,
+                            w: 0,
+                            h: 0,
+                        };
+                        add_rect_to_vertex_buffer(
+                            vertices,
+                            rect_corners(*destination),
+                            source,
+                            *color,
+                            Color::TRANSPARENT,
+                            false,
+                            false,
+                            texture_width,
+                            texture_height,
+                        );
+                    }
+                    // A sprite with a zero tint would be drawn as a solid rect of its flash.
+                    SpriteBatchEntry::Sprite { tint, .. }
+                    | SpriteBatchEntry::TransformedSprite { tint, .. }
+                        if tint.a == 0 => {}
+                    SpriteBatchEntry::Sprite {
+                        sprite,
                         source,
-                        *color,
-                        false,
-                        self.texture_atlas_width,
-                        self.texture_atlas_height,
-                    );
-                }
-                SpriteBatchEntry::Sprite {
-                    sprite,
-                    source,
-                    destination,
-                    reversed,
-                } => {
-                    let source = Rect {
-                        x: sprite.area.x + source.x,
-                        y: sprite.area.y + source.y,
-                        w: source.w,
-                        h: source.h,
-                    };
-                    let color = Color {
-                        r: 0,
-                        g: 0,
-                        b: 0,
-                        a: 0,
-                    };
-                    add_rect_to_vertex_buffer(
-                        vertices,
-                        &mut vertex_count,
-                        *destination,
+                        destination,
+                        reversed,
+                        tint,
+                        flash,
+                    } => {
+                        let source = Rect {
+                            x: sprite.area.x + source.x,
+                            y: sprite.area.y + source.y,
+                            w: source.w,
+                            h: source.h,
+                        };
+                        add_rect_to_vertex_buffer(
+                            vertices,
+                            rect_corners(*destination),
+                            source,
+                            *flash,
+                            *tint,
+                            *reversed,
+                            false,
+                            texture_width,
+                            texture_height,
+                        );
+                    }
+                    SpriteBatchEntry::TransformedSprite {
+                        sprite,
                         source,
+                        destination,
+                        transform,
+                        tint,
+                        flash,
+                    } => {
+                        let source = Rect {
+                            x: sprite.area.x + source.x,
+                            y: sprite.area.y + source.y,
+                            w: source.w,
+                            h: source.h,
+                        };
+                        add_rect_to_vertex_buffer(
+                            vertices,
+                            transform.corners(*destination),
+                            source,
+                            *flash,
+                            *tint,
+                            transform.flip_horizontal,
+                            transform.flip_vertical,
+                            texture_width,
+                            texture_height,
+                        );
+                    }
+                    SpriteBatchEntry::FillTriangle { p1, p2, p3, color } => {
+                        add_triangle_to_vertex_buffer(vertices, *p1, *p2, *p3, *color);
+                    }
+                    SpriteBatchEntry::FillGradientRect {
+                        destination,
+                        colors,
+                    } => {
+                        add_gradient_rect_to_vertex_buffer(vertices, *destination, *colors);
+                    }
+                    SpriteBatchEntry::FillGradientTriangle { p1, p2, p3, colors } => {
+                        let points = [p1, p2, p3].map(|p| Point::new(p.x as f32, p.y as f32));
+                        add_gradient_triangle_to_vertex_buffer(vertices, points, *colors);
+                    }
+                    SpriteBatchEntry::Line {
+                        start,
+                        end,
                         color,
-                        *reversed,
-                        self.texture_atlas_width,
-                        self.texture_atlas_height,
-                    );
-                }
-                SpriteBatchEntry::FillTriangle { p1, p2, p3, color } => {
-                    add_triangle_to_vertex_buffer(
-                        vertices,
-                        &mut vertex_count,
-                        *p1,
-                        *p2,
-                        *p3,
-                        *color,
-                    );
-                }
-                SpriteBatchEntry::Line {
-                    start,
-                    end,
-                    color,
-                    width,
-                } => {
-                    add_line_to_vertex_buffer(
-                        vertices,
-                        &mut vertex_count,
-                        *start,
-                        *end,
-                        *color,
-                        *width,
-                    );
-                }
-            };
+                        width,
+                        cap,
+                    } => {
+                        add_line_to_vertex_buffer(vertices, *start, *end, *color, *width, *cap);
+                    }
+                };
+            }
+            if vertices.len() > run_start {
+                runs.push((run_start as u32..vertices.len() as u32, texture_id));
+            }
         }
-        //info!("created {} vertices", vertex_count);
 
-        self.queue.write_buffer(
-            vertex_buffer,
-            0,
-            bytemuck::cast_slice(&vertices[0..vertex_count]),
-        );
+        let old_size = vertex_buffer.size();
+        let written = vertex_buffer.write(&self.device, &self.queue, vertices) as u32;
+        self.stats.buffer_bytes += vertex_buffer.size() - old_size;
 
-        vertex_count as u32
+        // Only a buffer bigger than the device allows can come up short.
+        runs.retain_mut(|(range, _)| {
+            range.end = range.end.min(written);
+            range.start < range.end
+        });
+        runs
     }
 
-    pub fn render(&mut self, context: &RenderContext) -> Result<()> {
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
+    fn draws(&self, runs: &[(Range<u32>, usize)]) -> Vec<Draw<'_>> {
+        runs.iter()
+            .map(|(vertices, texture_id)| Draw {
+                vertices: vertices.clone(),
+                textures: self
+                    .textures
+                    .get(*texture_id)
+                    .and_then(Option::as_ref)
+                    .map(|texture| &texture.bind_group),
+            })
+            .collect()
+    }
 
-        let vertex_count = self.fill_vertex_buffer(RenderLayer::Player, &context.player_batch);
-        self.render_pipeline.render(
-            &mut encoder,
+    /// Draws each of the context's layers into its framebuffer, returning the number of draws
+    /// and vertices for each.
+    fn draw_layers(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        context: &RenderContext,
+    ) -> [(u32, u32); 3] {
+        let runs = self.fill_vertex_buffer(RenderLayer::Player, &context.player_batch);
+        let player_stats = run_stats(&runs);
+        self.render_pipeline.render_draws(
+            encoder,
             &self.player_framebuffer.view,
             context.player_batch.clear_color,
-            self.player_vertex_buffer.slice(..),
-            vertex_count,
+            self.player_vertex_buffer.slice(),
+            &self.draws(&runs),
         );
 
-        let vertex_count = self.fill_vertex_buffer(RenderLayer::Hud, &context.hud_batch);
-        self.render_pipeline.render(
-            &mut encoder,
+        let runs = self.fill_vertex_buffer(RenderLayer::Hud, &context.hud_batch);
+        let hud_stats = run_stats(&runs);
+        self.render_pipeline.render_draws(
+            encoder,
             &self.hud_framebuffer.view,
             context.hud_batch.clear_color,
-            self.hud_vertex_buffer.slice(..),
-            vertex_count,
+            self.hud_vertex_buffer.slice(),
+            &self.draws(&runs),
         );
 
-        let output = self.surface.get_current_texture()?;
-        let output_view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let runs = self.fill_vertex_buffer(RenderLayer::Overlay, &context.overlay_batch);
+        let overlay_stats = run_stats(&runs);
+        self.render_pipeline.render_draws(
+            encoder,
+            &self.overlay_framebuffer.view,
+            context.overlay_batch.clear_color,
+            self.overlay_vertex_buffer.slice(),
+            &self.draws(&runs),
+        );
+        [player_stats, hud_stats, overlay_stats]
+    }
 
+    /// Sets up postprocessing for the context, drawn into a target of the given size.
+    fn update_postprocess_uniform(&mut self, context: &RenderContext, render_size: [f32; 2]) {
         let time_s = (context.frame as f32) / (FRAME_RATE as f32);
         self.fragment_uniform.time_s = time_s;
 
         self.fragment_uniform.is_dark = if context.is_dark { 1 } else { 0 };
+        self.fragment_uniform.ambient_light = context.ambient_light.into();
         self.fragment_uniform.spotlight_count = context.lights.len() as i32;
         for (i, light) in context.lights.iter().enumerate() {
             let position = light.position;
@@ -607,30 +811,86 @@ where
             self.fragment_uniform.spotlight[i].radius = light.radius as f32;
         }
 
-        self.fragment_uniform.render_size = [self.window_width as f32, self.window_height as f32];
+        let postprocess = context.postprocess;
+        self.fragment_uniform.tube_warp = if postprocess.tube_warp { 1 } else { 0 };
+        self.fragment_uniform.fuzz = if postprocess.fuzz { 1 } else { 0 };
+        self.fragment_uniform.color_split = postprocess.color_split;
+        self.fragment_uniform.scanlines = postprocess.scanlines;
+        self.fragment_uniform.static_noise = postprocess.static_noise;
+        self.fragment_uniform.underwater = postprocess.underwater;
+
+        let (transition, progress) = transition_uniform(context);
+        self.fragment_uniform.transition = transition;
+        self.fragment_uniform.transition_progress = progress;
+
+        self.fragment_uniform.render_size = render_size;
 
         self.postprocess_pipeline
             .update_fragment_uniform(&self.queue, self.fragment_uniform);
+    }
 
-        let clear_color = Color {
-            r: 0,
-            b: 0,
-            g: 0,
-            a: 255,
-        };
-        self.postprocess_pipeline.render(
+    /// Draws the outgoing scene's finished frame into the transition buffer, where it stays for
+    /// the rest of the transition. It's done in its own submit, since the new frame reuses the
+    /// same framebuffers and uniform.
+    fn render_outgoing(&mut self, outgoing: &RenderContext) -> [(u32, u32); 3] {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Transition Encoder"),
+            });
+        let stats = self.draw_layers(&mut encoder, outgoing);
+        let render_size = [
+            self.transition_buffer.width as f32,
+            self.transition_buffer.height as f32,
+        ];
+        self.update_postprocess_uniform(outgoing, render_size);
+        self.postprocess_pipeline.render_draws(
             &mut encoder,
-            &output_view,
-            clear_color,
+            &self.transition_buffer.view,
+            Color::BLACK,
             self.postprocess_vertex_buffer.slice(..),
-            6,
+            &[Draw {
+                vertices: 0..6,
+                textures: Some(&self.outgoing_textures),
+            }],
         );
-
         self.queue.submit(std::iter::once(encoder.finish()));
+        stats
+    }
 
-        output.present();
+    fn sprite_texture(&self, id: usize) -> Option<&SpriteTexture> {
+        self.textures.get(id)?.as_ref()
+    }
 
-        Ok(())
+    fn new_sprite_texture(&mut self, texture: Texture) -> SpriteTexture {
+        let bind_group = self
+            .render_pipeline
+            .create_texture_bind_group(&self.device, &[&texture]);
+        self.stats.texture_bytes += texture_size(&texture);
+        SpriteTexture {
+            texture,
+            bind_group,
+        }
+    }
+
+    /// Adds a texture sprites can be drawn from, returning its sprite id.
+    fn add_texture(&mut self, texture: Texture) -> usize {
+        let texture = self.new_sprite_texture(texture);
+        self.textures.push(Some(texture));
+        self.textures.len() - 1
+    }
+
+    /// Replaces every texture atlas with this one, returning a sprite covering all of it.
+    fn set_texture_atlas(&mut self, texture_atlas: Texture) -> Sprite {
+        for id in mem::take(&mut self.atlas_ids) {
+            if let Some(old) = self.textures[id].take() {
+                self.stats.texture_bytes -= texture_size(&old.texture);
+            }
+        }
+        let sprite = whole_sprite(TEXTURE_ATLAS_ID, &texture_atlas);
+        self.textures[TEXTURE_ATLAS_ID] = Some(self.new_sprite_texture(texture_atlas));
+        self.atlas_ids.push(TEXTURE_ATLAS_ID);
+        sprite
     }
 }
 
@@ -640,14 +900,234 @@ where
 {
     fn load_sprite(&mut self, _path: &Path) -> Result<Sprite> {
         // TODO: Check that the path actually matches the texture_atlas_path.
+        let atlas = self
+            .sprite_texture(TEXTURE_ATLAS_ID)
+            .ok_or_else(|| anyhow!("no texture atlas has been loaded"))?;
+        Ok(whole_sprite(TEXTURE_ATLAS_ID, &atlas.texture))
+    }
+
+    fn load_texture_atlas(&mut self, path: &Path, files: &FileManager) -> Result<Sprite> {
+        info!("Reading texture atlas from {:?}", path);
+        let texture_atlas = Texture::from_file(&self.device, &self.queue, path, files)?;
+        Ok(self.set_texture_atlas(texture_atlas))
+    }
+
+    fn create_texture_atlas(&mut self, width: u32, height: u32, pixels: &[u8]) -> Result<Sprite> {
+        info!("Creating {}x{} texture atlas", width, height);
+        let texture_atlas = Texture::from_pixels(&self.device, &self.queue, width, height, pixels)?;
+        Ok(self.set_texture_atlas(texture_atlas))
+    }
+
+    fn add_texture_atlas(&mut self, width: u32, height: u32, pixels: &[u8]) -> Result<Sprite> {
+        info!("Adding {}x{} texture atlas", width, height);
+        let texture_atlas = Texture::from_pixels(&self.device, &self.queue, width, height, pixels)?;
+        let sprite = whole_sprite(self.textures.len(), &texture_atlas);
+        let id = self.add_texture(texture_atlas);
+        self.atlas_ids.push(id);
+        Ok(sprite)
+    }
+
+    fn create_dynamic_texture(&mut self, width: u32, height: u32) -> Result<Sprite> {
+        if width == 0 || height == 0 {
+            bail!("invalid dynamic texture size: {}x{}", width, height);
+        }
+        let texture = Texture::dynamic(&self.device, &self.queue, width, height)?;
+        let id = self.add_texture(texture);
         Ok(Sprite {
-            id: 0,
+            id,
             area: Rect {
                 x: 0,
                 y: 0,
-                w: self.texture_atlas_width as i32,
-                h: self.texture_atlas_height as i32,
+                w: width as i32,
+                h: height as i32,
             },
         })
     }
+
+    fn update_dynamic_texture(&mut self, sprite: &Sprite, pixels: &[u8]) -> Result<()> {
+        let dynamic = self
+            .sprite_texture(sprite.id)
+            .filter(|_| !self.atlas_ids.contains(&sprite.id))
+            .ok_or_else(|| anyhow!("not a dynamic texture: {}", sprite.id))?;
+        dynamic.texture.write(&self.queue, pixels)
+    }
+
+    fn update_texture_atlas(&mut self, sprite: &Sprite, pixels: &[u8]) -> Result<()> {
+        let atlas = self
+            .sprite_texture(sprite.id)
+            .filter(|_| self.atlas_ids.contains(&sprite.id))
+            .ok_or_else(|| anyhow!("not a texture atlas: {}", sprite.id))?;
+        atlas.texture.write_region(&self.queue, sprite.area, pixels)
+    }
+
+    fn resize(&mut self, new_width: u32, new_height: u32) {
+        if new_width > 0 && new_height > 0 {
+            self.window_width = new_width;
+            self.window_height = new_height;
+            self.config.width = new_width;
+            self.config.height = new_height;
+            self.surface.configure(&self.device, &self.config);
+            match Texture::transition_buffer(
+                &self.device,
+                self.config.format,
+                new_width,
+                new_height,
+            ) {
+                Ok(transition_buffer) => {
+                    self.stats.texture_bytes -= texture_size(&self.transition_buffer);
+                    self.stats.texture_bytes += texture_size(&transition_buffer);
+                    self.transition_buffer = transition_buffer;
+                    self.transition_frame = None;
+                    self.set_postprocess_textures();
+                }
+                Err(e) => error!("unable to resize transition buffer: {}", e),
+            }
+        }
+    }
+
+    fn render(&mut self, context: &RenderContext) -> Result<()> {
+        let mut outgoing_stats = [(0, 0); 3];
+        let mut outgoing_passes = 0;
+        if let Some((_, outgoing)) = context.visible_outgoing() {
+            // The outgoing frame doesn't change, so it's only drawn at the start of the
+            // transition, or again if the window is resized.
+            if self.transition_frame != Some(outgoing.frame) {
+                outgoing_stats = self.render_outgoing(outgoing);
+                outgoing_passes = 4;
+                self.transition_frame = Some(outgoing.frame);
+            }
+        }
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+        let layer_stats = self.draw_layers(&mut encoder, context);
+
+        let output = self.surface.get_current_texture()?;
+        let output_view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let render_size = [self.window_width as f32, self.window_height as f32];
+        self.update_postprocess_uniform(context, render_size);
+        self.postprocess_pipeline.render(
+            &mut encoder,
+            &output_view,
+            Color::BLACK,
+            self.postprocess_vertex_buffer.slice(..),
+            6,
+        );
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        output.present();
+
+        if let Some(capture_buffer) = self.capture_buffer.as_ref().filter(|_| self.capturing) {
+            // The capture buffer is smaller than the window, so it's drawn in its own submit,
+            // after the render size has been changed to match it.
+            self.fragment_uniform.render_size =
+                [capture_buffer.width as f32, capture_buffer.height as f32];
+            self.postprocess_pipeline
+                .update_fragment_uniform(&self.queue, self.fragment_uniform);
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Capture Encoder"),
+                });
+            self.postprocess_pipeline.render(
+                &mut encoder,
+                &capture_buffer.view,
+                Color::BLACK,
+                self.postprocess_vertex_buffer.slice(..),
+                6,
+            );
+            self.queue.submit(std::iter::once(encoder.finish()));
+            self.captured_frame = Some(capture_buffer.read_pixels(&self.device, &self.queue)?);
+        }
+
+        // There's one render pass each for the player, the hud, the overlay, and postprocessing,
+        // and again for the outgoing scene when a transition starts.
+        self.stats.pipeline_switches = 4 + outgoing_passes;
+        self.stats.draw_calls = 1;
+        self.stats.player_vertices = 0;
+        self.stats.hud_vertices = 0;
+        self.stats.overlay_vertices = 0;
+        if outgoing_passes > 0 {
+            self.stats.draw_calls += 1;
+        }
+        for [player_stats, hud_stats, overlay_stats] in [layer_stats, outgoing_stats] {
+            self.stats.draw_calls += player_stats.0 + hud_stats.0 + overlay_stats.0;
+            self.stats.player_vertices += player_stats.1;
+            self.stats.hud_vertices += hud_stats.1;
+            self.stats.overlay_vertices += overlay_stats.1;
+        }
+
+        Ok(())
+    }
+
+    fn set_capturing(&mut self, capturing: bool) {
+        self.capturing = capturing;
+        if !capturing {
+            self.captured_frame = None;
+            return;
+        }
+        if self.capture_buffer.is_some() {
+            return;
+        }
+        match Texture::capture_buffer(&self.device, self.config.format) {
+            Ok(capture_buffer) => self.capture_buffer = Some(capture_buffer),
+            Err(e) => error!("unable to capture frames: {}", e),
+        }
+    }
+
+    fn take_captured_frame(&mut self) -> Option<RgbaImage> {
+        self.captured_frame.take()
+    }
+
+    fn stats(&self) -> RendererStats {
+        self.stats
+    }
+}
+
+fn texture_size(texture: &Texture) -> u64 {
+    texture.width as u64 * texture.height as u64 * BYTES_PER_PIXEL
+}
+
+/// A sprite covering all of a texture.
+fn whole_sprite(id: usize, texture: &Texture) -> Sprite {
+    Sprite {
+        id,
+        area: Rect {
+            x: 0,
+            y: 0,
+            w: texture.width as i32,
+            h: texture.height as i32,
+        },
+    }
+}
+
+/// The shader's number for the context's transition, or 0 for none, and how far along it is.
+fn transition_uniform(context: &RenderContext) -> (i32, f32) {
+    let Some(transition) = context.transition else {
+        return (0, 0.0);
+    };
+    // Without the outgoing frame, there's nothing to blend with.
+    if transition.shows_outgoing() && context.outgoing.is_none() {
+        return (0, 0.0);
+    }
+    let kind = match transition.kind {
+        TransitionKind::Cut => 0,
+        TransitionKind::Fade => 1,
+        TransitionKind::Crossfade => 2,
+        TransitionKind::Wipe => 3,
+    };
+    (kind, transition.progress)
+}
+
+/// The number of draws and vertices in a layer's runs.
+fn run_stats(runs: &[(Range<u32>, usize)]) -> (u32, u32) {
+    let vertices = runs.iter().map(|(range, _)| range.len() as u32).sum();
+    (runs.len() as u32, vertices)
 }
@@ -22,6 +22,7 @@ pub struct Vertex {
     pub position: [f32; 2],
     pub tex_coords: [f32; 2],
     pub color: [f32; 4],
+    pub tint: [f32; 4],
 }
 
 impl Vertex {
@@ -46,6 +47,11 @@ impl Vertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
@@ -96,7 +102,19 @@ pub struct PostprocessFragmentUniform {
     pub time_s: f32,
     pub is_dark: i32,
     pub spotlight_count: i32,
-    pub _padding: u32,
+    pub tube_warp: i32,
+    pub fuzz: i32,
+    pub color_split: f32,
+    pub scanlines: f32,
+    pub static_noise: f32,
+    /// The HUD framebuffer's size relative to texture_size.
+    pub hud_scale: f32,
+    pub underwater: f32,
+    /// The TransitionKind being blended in, with 0 for none, and how far along it is.
+    pub transition: i32,
+    pub transition_progress: f32,
+    /// The player layer is multiplied by this color.
+    pub ambient_light: [f32; 4],
     pub spotlight: [Light; MAX_LIGHTS],
 }
 
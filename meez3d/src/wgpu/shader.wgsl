@@ -17,12 +17,14 @@ struct RenderVertexInput {
     @location(0) position: vec2<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) color: vec4<f32>,
+    @location(3) tint: vec4<f32>,
 }
 
 struct RenderVertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     @location(1) color: vec4<f32>,
+    @location(2) tint: vec4<f32>,
 }
 
 @vertex
@@ -32,6 +34,7 @@ fn vs_main(
     var out: RenderVertexOutput;
     out.tex_coords = model.tex_coords;
     out.color = model.color;
+    out.tint = model.tint;
 
     var x: f32 = model.position.x / render_vertex_uniform.logical_size.x;
     var y: f32 = model.position.y / render_vertex_uniform.logical_size.y;
@@ -54,12 +57,13 @@ var texture_atlas_sampler: sampler;
 
 @fragment
 fn fs_main(in: RenderVertexOutput) -> @location(0) vec4<f32> {
-    let col: vec4<f32> = in.color;
-    if col.a > 0.0 {
-        return col;
-    } else {
-        return textureSample(texture_atlas, texture_atlas_sampler, in.tex_coords);
+    // Solid shapes have no tint, and are just their color. Sprites are tinted, and then mixed
+    // with their color by its alpha, so a transparent color leaves them as they are.
+    let texel = textureSample(texture_atlas, texture_atlas_sampler, in.tex_coords) * in.tint;
+    if in.tint.a == 0.0 {
+        return in.color;
     }
+    return vec4<f32>(mix(texel.rgb, in.color.rgb, in.color.a), texel.a);
 }
 
 // Postprocessing Vertex
@@ -100,6 +104,21 @@ struct PostprocessFragmentUniform {
     // Lighting
     is_dark: i32,
     spotlight_count: i32,
+
+    // Effects
+    tube_warp: i32,
+    fuzz: i32,
+    color_split: f32,
+    scanlines: f32,
+    static_noise: f32,
+    hud_scale: f32,
+    underwater: f32,
+
+    // Transitions
+    transition: i32,
+    transition_progress: f32,
+    ambient_light: vec4<f32>,
+
     spotlight: array<Light, 32>,
 };
 @group(1) @binding(0)
@@ -120,6 +139,18 @@ var static_texture: texture_2d<f32>;
 @group(2) @binding(5)
 var static_sampler: sampler;
 
+// The outgoing scene's finished frame, while there's a transition.
+@group(2) @binding(6)
+var transition_texture: texture_2d<f32>;
+@group(2) @binding(7)
+var transition_sampler: sampler;
+
+// Drawn over everything else, after the effects, so nothing changes how it looks.
+@group(2) @binding(8)
+var overlay_framebuffer_texture: texture_2d<f32>;
+@group(2) @binding(9)
+var overlay_framebuffer_sampler: sampler;
+
 fn spotlight(position_: vec2<f32>) -> vec4<f32> {
     var position = position_;
 
@@ -162,7 +193,16 @@ fn fuzz_sample_uv(coord_: vec2<f32>) -> vec2<f32>{
     return coord;
 }
 
+// Snaps to the center of a texel, so LINEAR sampling acts like NEAREST.
+fn sharp_sample_uv(coord: vec2<f32>, size: vec2<f32>) -> vec2<f32> {
+    return (floor(coord * size) + 0.5) / size;
+}
+
 fn tube_warp(coord_: vec2<f32>, offset: vec2<f32>) -> vec2<f32> {
+    if (postprocessing_fragment_uniform.tube_warp == 0) {
+        return coord_ + offset;
+    }
+
     var coord = (coord_ * 2.0) - 1.0;
     coord *= 0.5;
 
@@ -185,26 +225,91 @@ fn scanline(y_: f32) -> vec4<f32> {
     return vec4<f32>(scanline_color, 1.0);
 }
 
+// How far the world is shifted at a point, to make it ripple like it's seen through water.
+fn underwater_wobble(uv: vec2<f32>) -> vec2<f32> {
+    let t = postprocessing_fragment_uniform.time_s;
+    let wobble = vec2<f32>(sin(uv.y * 30.0 + t * 3.0), cos(uv.x * 30.0 + t * 2.0));
+    return wobble * 0.004 * postprocessing_fragment_uniform.underwater;
+}
+
 fn get_scene_pixel(uv: vec2<f32>) -> vec4<f32> {
     let spot = spotlight(uv);
 
-    let fuzzed_sample_uv = fuzz_sample_uv(uv);
+    let texture_size = postprocessing_fragment_uniform.texture_size;
+    var fuzzed_sample_uv = sharp_sample_uv(uv, texture_size);
+    if (postprocessing_fragment_uniform.fuzz != 0) {
+        fuzzed_sample_uv = fuzz_sample_uv(uv);
+    }
+
+    // A supersampled HUD is kept sharp at its own resolution, instead of being fuzzed.
+    var hud_sample_uv = fuzzed_sample_uv;
+    if (postprocessing_fragment_uniform.hud_scale > 1.0) {
+        hud_sample_uv = sharp_sample_uv(uv, texture_size * postprocessing_fragment_uniform.hud_scale);
+    }
 
-    var player_color = textureSample(player_framebuffer_texture, player_framebuffer_sampler, fuzzed_sample_uv);
+    // Only the world is underwater, so the HUD stays readable.
+    let underwater = postprocessing_fragment_uniform.underwater;
+    let player_sample_uv = fuzzed_sample_uv + underwater_wobble(uv);
+    var player_color = textureSample(player_framebuffer_texture, player_framebuffer_sampler, player_sample_uv);
     player_color = vec4(mix(player_color.rgb, spot.rgb, spot.a), 1.0);
+    player_color = vec4(player_color.rgb * postprocessing_fragment_uniform.ambient_light.rgb, 1.0);
+    let water_color = player_color.rgb * vec3<f32>(0.4, 0.7, 1.0);
+    player_color = vec4(mix(player_color.rgb, water_color, underwater), 1.0);
 
-    let hud_color = textureSample(hud_framebuffer_texture, hud_framebuffer_sampler, fuzzed_sample_uv);
+    let hud_color = textureSample(hud_framebuffer_texture, hud_framebuffer_sampler, hud_sample_uv);
     let color = vec4<f32>(mix(hud_color.rgb, player_color.rgb, 1.0 - hud_color.a), 1.0);
 
     return color;
 }
 
+// Blends the outgoing scene's frame with this one, matching TransitionFrame.
+fn transition(uv: vec2<f32>, color: vec4<f32>) -> vec4<f32> {
+    let kind = postprocessing_fragment_uniform.transition;
+    let progress = postprocessing_fragment_uniform.transition_progress;
+    // This isn't in uniform control flow, so it can't use textureSample.
+    let outgoing = textureSampleLevel(transition_texture, transition_sampler, uv, 0.0);
+    if (kind == 1) {
+        // Fade
+        let brightness = abs(progress * 2.0 - 1.0);
+        if (progress < 0.5) {
+            return vec4<f32>(outgoing.rgb * brightness, 1.0);
+        }
+        return vec4<f32>(color.rgb * brightness, 1.0);
+    }
+    if (kind == 2) {
+        // Crossfade
+        return vec4<f32>(mix(outgoing.rgb, color.rgb, progress), 1.0);
+    }
+    if (kind == 3) {
+        // Wipe
+        if (uv.x < progress) {
+            return color;
+        }
+        return vec4<f32>(outgoing.rgb, 1.0);
+    }
+    return color;
+}
+
+// Puts the overlay on top of the finished frame, kept sharp at the HUD's resolution.
+fn overlay(uv: vec2<f32>, color: vec4<f32>) -> vec4<f32> {
+    let size = postprocessing_fragment_uniform.texture_size * postprocessing_fragment_uniform.hud_scale;
+    let overlay_uv = sharp_sample_uv(uv, size);
+    let overlay_color = textureSampleLevel(overlay_framebuffer_texture, overlay_framebuffer_sampler, overlay_uv, 0.0);
+    return vec4<f32>(mix(color.rgb, overlay_color.rgb, overlay_color.a), 1.0);
+}
+
 @fragment
 fn fs_main2(in: PostprocessVertexOutput) -> @location(0) vec4<f32> {
     let uv = in.clip_position.xy / postprocessing_fragment_uniform.render_size;
+    return overlay(uv, transition(uv, scene_color(uv)));
+}
+
+// The scene with every effect applied.
+fn scene_color(uv: vec2<f32>) -> vec4<f32> {
     let uv1 = tube_warp(uv, vec2<f32>(0.0, 0.0));
-    let uv2 = tube_warp(uv, vec2<f32>(0.002, 0.0));
-    let uv3 = tube_warp(uv, vec2<f32>(-0.002, 0.0));
+    let split = postprocessing_fragment_uniform.color_split;
+    let uv2 = tube_warp(uv, vec2<f32>(split, 0.0));
+    let uv3 = tube_warp(uv, vec2<f32>(-split, 0.0));
 
     if (uv1.x < 0.0 || uv1.y < 0.0 || uv1.x > 1.0 || uv1.y > 1.0) {
          return vec4<f32>(0.0, 0.0, 0.0, 1.0);
@@ -222,7 +327,10 @@ fn fs_main2(in: PostprocessVertexOutput) -> @location(0) vec4<f32> {
     random_pos = modf(random_pos).fract;
     let random = textureSample(static_texture, static_sampler, random_pos);
 
-    color = mix(mix(color, random, 0.04), scan, 0.015);
+    color = mix(
+        mix(color, random, postprocessing_fragment_uniform.static_noise),
+        scan,
+        postprocessing_fragment_uniform.scanlines);
 
     return color;
 }
@@ -1,12 +1,13 @@
 use std::path::Path;
 
 use anyhow::*;
-use image::GenericImageView;
+use image::{GenericImageView, RgbaImage};
 use log::info;
 use rand::random;
 
 use crate::constants::{RENDER_HEIGHT, RENDER_WIDTH};
 use crate::filemanager::FileManager;
+use crate::geometry::Rect;
 
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -29,9 +30,147 @@ impl Texture {
         Self::from_image(device, queue, &img, Some("texture atlas"))
     }
 
-    pub fn frame_buffer(device: &wgpu::Device, format: wgpu::TextureFormat) -> Result<Self> {
-        let width = RENDER_WIDTH;
-        let height = RENDER_HEIGHT;
+    /// A texture atlas made from width * height RGBA pixels.
+    pub fn from_pixels(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+    ) -> Result<Self> {
+        let img = image::RgbaImage::from_raw(width, height, pixels.to_vec()).ok_or_else(|| {
+            anyhow!(
+                "{}x{} texture needs {} bytes, but got {}",
+                width,
+                height,
+                width * height * 4,
+                pixels.len()
+            )
+        })?;
+        let img = image::DynamicImage::ImageRgba8(img);
+        Self::from_image(device, queue, &img, Some("texture atlas"))
+    }
+
+    /// A blank texture that can be overwritten with write.
+    pub fn dynamic(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let img = image::DynamicImage::new_rgba8(width, height);
+        Self::from_image(device, queue, &img, Some("Dynamic Texture"))
+    }
+
+    /// Replaces the whole texture with width * height RGBA pixels.
+    pub fn write(&self, queue: &wgpu::Queue, pixels: &[u8]) -> Result<()> {
+        let area = Rect {
+            x: 0,
+            y: 0,
+            w: self.width as i32,
+            h: self.height as i32,
+        };
+        self.write_region(queue, area, pixels)
+    }
+
+    /// Replaces part of the texture with area.w * area.h RGBA pixels.
+    pub fn write_region(&self, queue: &wgpu::Queue, area: Rect<i32>, pixels: &[u8]) -> Result<()> {
+        if area.x < 0
+            || area.y < 0
+            || area.w <= 0
+            || area.h <= 0
+            || area.right() > self.width as i32
+            || area.bottom() > self.height as i32
+        {
+            bail!(
+                "region {:?} is outside of the {}x{} texture",
+                area,
+                self.width,
+                self.height
+            );
+        }
+        let (width, height) = (area.w as u32, area.h as u32);
+        let expected = (width * height * 4) as usize;
+        if pixels.len() != expected {
+            bail!("texture needs {} bytes, but got {}", expected, pixels.len());
+        }
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: area.x as u32,
+                    y: area.y as u32,
+                    z: 0,
+                },
+            },
+            pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        Ok(())
+    }
+
+    /// A texture to render a layer into, at scale times the logical render size.
+    pub fn frame_buffer(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        scale: u32,
+    ) -> Result<Self> {
+        Self::render_target(
+            device,
+            format,
+            RENDER_WIDTH * scale,
+            RENDER_HEIGHT * scale,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        )
+    }
+
+    /// A texture to render finished frames into at the logical render size, so they can be
+    /// read back with read_pixels.
+    pub fn capture_buffer(device: &wgpu::Device, format: wgpu::TextureFormat) -> Result<Self> {
+        Self::render_target(
+            device,
+            format,
+            RENDER_WIDTH,
+            RENDER_HEIGHT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        )
+    }
+
+    /// A texture the size of the window, to draw the outgoing scene's finished frame into during
+    /// a transition, so it can be blended with the new one.
+    pub fn transition_buffer(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        Self::render_target(
+            device,
+            format,
+            width,
+            height,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        )
+    }
+
+    fn render_target(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        usage: wgpu::TextureUsages,
+    ) -> Result<Self> {
         let size = wgpu::Extent3d {
             width,
             height,
@@ -47,7 +186,7 @@ impl Texture {
             dimension: wgpu::TextureDimension::D2,
             //format: wgpu::TextureFormat::Bgra8Unorm,
             format,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage,
             view_formats: &[],
         });
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
@@ -71,6 +210,71 @@ impl Texture {
         })
     }
 
+    /// Copies the texture back from the GPU as RGBA pixels, waiting until it's done. The texture
+    /// has to have been made with COPY_SRC usage, like a capture buffer.
+    pub fn read_pixels(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<RgbaImage> {
+        // Rows of a copy have to start at multiples of the alignment.
+        let row_bytes = self.width * 4;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row_bytes = row_bytes.div_ceil(align) * align;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_row_bytes * self.height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .map_err(|e| anyhow!("texture readback was dropped: {}", e))?
+            .map_err(|e| anyhow!("unable to read texture back: {}", e))?;
+
+        let mut pixels = Vec::with_capacity((row_bytes * self.height) as usize);
+        {
+            let mapped = slice.get_mapped_range();
+            for row in mapped.chunks(padded_row_bytes as usize) {
+                pixels.extend_from_slice(&row[..row_bytes as usize]);
+            }
+        }
+        buffer.unmap();
+        if matches!(
+            self.texture.format(),
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        ) {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        RgbaImage::from_raw(self.width, self.height, pixels)
+            .ok_or_else(|| anyhow!("texture readback is the wrong size"))
+    }
+
     fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -0,0 +1,98 @@
+use std::mem;
+
+use log::error;
+
+use crate::wgpu::shader::Vertex;
+
+/// How many vertices a layer's buffer starts out holding, which is enough for 4096 sprites.
+const INITIAL_VERTICES: usize = 4096 * 6;
+const VERTEX_SIZE: u64 = mem::size_of::<Vertex>() as u64;
+
+/// How many vertices a buffer holding `capacity` has to grow to, to hold `needed`, doubling
+/// until it's big enough, but never past `max`.
+fn grown_capacity(capacity: usize, needed: usize, max: usize) -> usize {
+    let mut capacity = capacity.max(1);
+    while capacity < needed {
+        capacity *= 2;
+    }
+    capacity.min(max)
+}
+
+/// A vertex buffer for one layer, which is replaced with a bigger one whenever a frame has more
+/// vertices than it can hold, so complex scenes don't lose geometry.
+pub struct VertexBuffer {
+    label: &'static str,
+    buffer: wgpu::Buffer,
+    /// How many vertices the buffer holds.
+    capacity: usize,
+}
+
+impl VertexBuffer {
+    pub fn new(device: &wgpu::Device, label: &'static str) -> VertexBuffer {
+        VertexBuffer {
+            label,
+            buffer: create_buffer(device, label, INITIAL_VERTICES),
+            capacity: INITIAL_VERTICES,
+        }
+    }
+
+    /// The size of the buffer, in bytes.
+    pub fn size(&self) -> u64 {
+        self.buffer.size()
+    }
+
+    pub fn slice(&self) -> wgpu::BufferSlice<'_> {
+        self.buffer.slice(..)
+    }
+
+    /// Copies the vertices into the buffer, growing it first if they don't fit. Returns how many
+    /// were written, which is fewer than given only if the device can't hold a buffer that big.
+    pub fn write(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        vertices: &[Vertex],
+    ) -> usize {
+        if vertices.len() > self.capacity {
+            let max = (device.limits().max_buffer_size / VERTEX_SIZE) as usize;
+            let capacity = grown_capacity(self.capacity, vertices.len(), max);
+            if capacity < vertices.len() {
+                error!(
+                    "too many vertices in {}: {} of {} fit",
+                    self.label,
+                    capacity,
+                    vertices.len()
+                );
+            }
+            if capacity > self.capacity {
+                self.buffer = create_buffer(device, self.label, capacity);
+                self.capacity = capacity;
+            }
+        }
+        let count = vertices.len().min(self.capacity);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&vertices[..count]));
+        count
+    }
+}
+
+fn create_buffer(device: &wgpu::Device, label: &str, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: capacity as u64 * VERTEX_SIZE,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_by_doubling() {
+        assert_eq!(grown_capacity(6, 6, 1000), 6);
+        assert_eq!(grown_capacity(6, 7, 1000), 12);
+        assert_eq!(grown_capacity(6, 25, 1000), 48);
+        assert_eq!(grown_capacity(600, 1001, 1000), 1000);
+    }
+}
@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use image::RgbaImage;
+
+use crate::constants::{RENDER_HEIGHT, RENDER_WIDTH};
+use crate::filemanager::FileManager;
+
+/// How the game's window is presented, so hosts don't each have to set it up by hand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowConfig {
+    pub title: String,
+    /// An image to load through the FileManager, e.g. "assets/icon.png".
+    pub icon: Option<PathBuf>,
+    /// The smallest the window can be resized to, in the units of the host's resize events.
+    pub min_width: u32,
+    pub min_height: u32,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        WindowConfig {
+            title: "flywheel".to_string(),
+            icon: None,
+            min_width: RENDER_WIDTH / 2,
+            min_height: RENDER_HEIGHT / 2,
+        }
+    }
+}
+
+impl WindowConfig {
+    /// Loads the icon as RGBA pixels, or returns None if there isn't one.
+    pub fn load_icon(&self, files: &FileManager) -> Result<Option<RgbaImage>> {
+        let Some(path) = &self.icon else {
+            return Ok(None);
+        };
+        let bytes = files.read(path)?;
+        let icon = image::load_from_memory(&bytes)
+            .map_err(|e| anyhow!("unable to load icon {:?}: {}", path, e))?;
+        Ok(Some(icon.to_rgba8()))
+    }
+
+    #[cfg(feature = "sdl2")]
+    pub fn apply_to_sdl(
+        &self,
+        window: &mut sdl2::video::Window,
+        files: &FileManager,
+    ) -> Result<()> {
+        use sdl2::pixels::PixelFormatEnum;
+        use sdl2::surface::Surface;
+
+        window
+            .set_title(&self.title)
+            .map_err(|e| anyhow!("unable to set window title: {}", e))?;
+        window
+            .set_minimum_size(self.min_width, self.min_height)
+            .map_err(|e| anyhow!("unable to set minimum window size: {}", e))?;
+        if let Some(icon) = self.load_icon(files)? {
+            let (width, height) = icon.dimensions();
+            let mut pixels = icon.into_raw();
+            let surface = Surface::from_data(
+                &mut pixels,
+                width,
+                height,
+                width * 4,
+                PixelFormatEnum::RGBA32,
+            )
+            .map_err(|e| anyhow!("unable to create icon surface: {}", e))?;
+            window.set_icon(surface);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "winit")]
+    pub fn apply_to_winit(
+        &self,
+        window: &winit::window::Window,
+        files: &FileManager,
+    ) -> Result<()> {
+        use winit::dpi::PhysicalSize;
+        use winit::window::Icon;
+
+        window.set_title(&self.title);
+        window.set_min_inner_size(Some(PhysicalSize::new(self.min_width, self.min_height)));
+        if let Some(icon) = self.load_icon(files)? {
+            let (width, height) = icon.dimensions();
+            let icon = Icon::from_rgba(icon.into_raw(), width, height)
+                .map_err(|e| anyhow!("invalid window icon: {}", e))?;
+            window.set_window_icon(Some(icon));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn icon() {
+        let mut png = Vec::new();
+        RgbaImage::new(2, 3)
+            .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+            .unwrap();
+        let mut map = HashMap::new();
+        map.insert(PathBuf::from("assets/icon.png"), png);
+        let files = FileManager::from_memory(map).unwrap();
+
+        let mut config = WindowConfig::default();
+        assert!(config.load_icon(&files).unwrap().is_none());
+
+        config.icon = Some(PathBuf::from("assets/icon.png"));
+        let icon = config.load_icon(&files).unwrap().unwrap();
+        assert_eq!(icon.dimensions(), (2, 3));
+
+        config.icon = Some(PathBuf::from("assets/missing.png"));
+        assert!(config.load_icon(&files).is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use crate::billboard::{
+    draw_unoccluded, is_on_screen, place_billboards, project, Billboard, DepthBuffer,
+};
+use crate::constants::RENDER_HEIGHT;
+use crate::font::Font;
+use crate::geometry::Rect;
+use crate::rendercontext::SpriteBatch;
+use crate::tilemap::{MapObject, TileMap};
+use crate::utils::Color;
+
+/// How tall the letters of text from a point object are, in tiles.
+pub const DEFAULT_TEXT_SIZE: f32 = 0.25;
+
+/// Words standing in the raycast world, like a sign. Like a billboard, they always face the
+/// camera, get smaller with distance, and are hidden behind walls.
+#[derive(Debug, Clone)]
+pub struct WorldText {
+    pub floor: usize,
+    /// Where the middle of the text is, in tiles.
+    pub x: f32,
+    pub y: f32,
+    /// How tall the letters are, in tiles.
+    pub size: f32,
+    pub text: String,
+    pub color: Color,
+}
+
+impl WorldText {
+    /// The text for each object in a Tiled map that has a text property.
+    pub fn from_tilemap(tilemap: &TileMap) -> impl Iterator<Item = WorldText> + '_ {
+        tilemap
+            .objects
+            .iter()
+            .filter_map(|obj| WorldText::from_object(tilemap, obj))
+    }
+
+    /// The text for an object, centered on it, with letters as tall in tiles as it is in the
+    /// map, or DEFAULT_TEXT_SIZE for a point. Returns None if it has no text.
+    pub fn from_object(tilemap: &TileMap, obj: &MapObject) -> Option<WorldText> {
+        let text = obj.properties.text.as_ref()?;
+        let position = obj.position;
+        let (tile_w, tile_h) = (tilemap.tilewidth as f32, tilemap.tileheight as f32);
+        Some(WorldText {
+            floor: 0,
+            x: (position.x as f32 + position.w as f32 / 2.0) / tile_w,
+            y: (position.y as f32 + position.h as f32 / 2.0) / tile_h,
+            size: if position.h > 0 {
+                position.h as f32 / tile_h
+            } else {
+                DEFAULT_TEXT_SIZE
+            },
+            text: text.clone(),
+            color: obj.properties.tint.unwrap_or(Color::WHITE),
+        })
+    }
+}
+
+/// Where some text in the world shows up on the screen.
+struct PlacedText<'a> {
+    text: &'a WorldText,
+    /// The screen column the middle of the text is in.
+    column: i32,
+    distance: f32,
+}
+
+impl PlacedText<'_> {
+    /// Draws the text centered at eye level on the horizon, only where it isn't behind a wall.
+    fn draw(
+        &self,
+        batch: &mut SpriteBatch,
+        font: &Font,
+        horizon: i32,
+        shift: i32,
+        depth: &DepthBuffer,
+    ) {
+        let PlacedText {
+            text,
+            column,
+            distance,
+        } = *self;
+        let height = RENDER_HEIGHT as f32 * text.size / distance;
+        let letter_width = height * font.char_width as f32 / font.char_height.max(1) as f32;
+        let left = column as f32 - letter_width * text.text.chars().count() as f32 / 2.0;
+        let tint = batch.tint;
+        batch.tint = tint.multiply(text.color);
+        for (i, c) in text.text.chars().enumerate() {
+            // Each letter's edges are rounded on their own, so the gaps between them don't add up.
+            let x = (left + letter_width * i as f32) as i32;
+            let dest = Rect {
+                x,
+                y: horizon - (height / 2.0) as i32,
+                w: (left + letter_width * (i + 1) as f32) as i32 - x,
+                h: height as i32,
+            };
+            if dest.h <= 0 || !is_on_screen(dest.x, dest.w) {
+                continue;
+            }
+            let (sprite, source) = font.glyph(c);
+            draw_unoccluded(batch, sprite, source, dest, false, distance, shift, depth);
+        }
+        batch.tint = tint;
+    }
+}
+
+/// Draws text in the world along with the billboards, as seen from a camera at (x, y) facing
+/// angle, with the horizon at the given row. Both are drawn together from farthest to nearest,
+/// so a sign covers an enemy behind it and the other way around, and only where they aren't
+/// behind a wall.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_world_text<'a>(
+    batch: &mut SpriteBatch,
+    texts: impl IntoIterator<Item = &'a WorldText>,
+    billboards: impl IntoIterator<Item = &'a Billboard>,
+    font: &Font,
+    camera: (f32, f32, f32),
+    horizon: i32,
+    shift: i32,
+    depth: &DepthBuffer,
+) {
+    let mut placed: Vec<PlacedText> = texts
+        .into_iter()
+        .filter(|text| !text.text.is_empty())
+        .filter_map(|text| {
+            let (column, distance) = project(text.x, text.y, camera)?;
+            Some(PlacedText {
+                text,
+                column,
+                distance,
+            })
+        })
+        .collect();
+    placed.sort_by(|a, b| b.distance.total_cmp(&a.distance));
+
+    // Both lists are farthest first, so they're merged by taking the farther of their fronts.
+    let mut texts = placed.into_iter().peekable();
+    let mut billboards = place_billboards(billboards, camera, horizon)
+        .into_iter()
+        .peekable();
+    loop {
+        let text_is_farther = match (texts.peek(), billboards.peek()) {
+            (Some(text), Some(billboard)) => text.distance >= billboard.distance,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break,
+        };
+        if text_is_farther {
+            if let Some(text) = texts.next() {
+                text.draw(batch, font, horizon, shift, depth);
+            }
+        } else if let Some(billboard) = billboards.next() {
+            billboard.draw(batch, shift, depth);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::constants::RENDER_WIDTH;
+    use crate::filemanager::FileManager;
+    use crate::imagemanager::ImageManager;
+    use crate::rendercontext::{RenderContext, SpriteBatchEntry};
+    use crate::sprite::Sprite;
+
+    fn load_font() -> Font {
+        let mut map = HashMap::new();
+        map.insert(
+            PathBuf::from("assets/8bitfont.tsx"),
+            include_bytes!("../../assets/8bitfont.tsx").to_vec(),
+        );
+        let files = FileManager::from_memory(map).unwrap();
+        ImageManager::null_manager().load_font(&files).unwrap()
+    }
+
+    #[test]
+    fn draws_letters_at_eye_level() {
+        let font = load_font();
+        let mut context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let horizon = RENDER_HEIGHT as i32 / 2;
+        let center = RENDER_WIDTH as i32 / 2;
+        let red = Color {
+            g: 0,
+            b: 0,
+            ..Color::WHITE
+        };
+        let sign = WorldText {
+            floor: 0,
+            x: 4.0,
+            y: 0.0,
+            size: 0.5,
+            text: "EXIT".to_string(),
+            color: red,
+        };
+        // A wall 2 tiles away covers everything right of the middle.
+        let depth = DepthBuffer::new(
+            (0..RENDER_WIDTH as i32).map(|column| (column >= center).then_some(2.0)),
+        );
+        draw_world_text(
+            &mut context.player_batch,
+            [&sign],
+            [],
+            &font,
+            (0.0, 0.0, 0.0),
+            horizon,
+            0,
+            &depth,
+        );
+
+        // Only the two letters left of the middle are drawn, in the sign's color.
+        let drawn: Vec<(Rect<i32>, Rect<i32>, Color)> = context
+            .player_batch
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                SpriteBatchEntry::Sprite {
+                    source,
+                    destination,
+                    tint,
+                    ..
+                } => (*source, *destination, *tint),
+                _ => panic!("expected a sprite"),
+            })
+            .collect();
+        assert_eq!(drawn.len(), 2);
+        let height = RENDER_HEIGHT as i32 / 8;
+        for (_, dest, tint) in drawn.iter() {
+            assert_eq!((dest.y, dest.h), (horizon - height / 2, height));
+            assert_eq!(*tint, red);
+        }
+        assert_eq!(drawn[0].1.x, center - height * 2);
+        assert_eq!(drawn[1].1.x + drawn[1].1.w, center);
+        assert_eq!(drawn[0].0, font.glyph('E').1);
+        assert_eq!(drawn[1].0, font.glyph('X').1);
+        assert_eq!(context.player_batch.tint, Color::WHITE);
+    }
+
+    #[test]
+    fn sorted_with_billboards() {
+        let font = load_font();
+        let mut context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0).unwrap();
+        let sign = |x: f32| WorldText {
+            floor: 0,
+            x,
+            y: 0.0,
+            size: 0.5,
+            text: "A".to_string(),
+            color: Color::WHITE,
+        };
+        let billboard = |x: f32, id: usize| Billboard {
+            floor: 0,
+            x,
+            y: 0.0,
+            size: 1.0,
+            sprite: Sprite {
+                id,
+                area: Rect {
+                    x: 0,
+                    y: 0,
+                    w: 64,
+                    h: 64,
+                },
+            },
+            source: Rect {
+                x: 0,
+                y: 0,
+                w: 64,
+                h: 64,
+            },
+            reversed: false,
+            faction: None,
+            spawner: None,
+            tint: Color::WHITE,
+            flash: Color::TRANSPARENT,
+        };
+        // From far to near: a billboard, a sign, another billboard, and another sign.
+        let (far, near) = (billboard(5.0, 100), billboard(3.0, 101));
+        draw_world_text(
+            &mut context.player_batch,
+            [&sign(2.0), &sign(4.0)],
+            [&near, &far],
+            &font,
+            (0.0, 0.0, 0.0),
+            RENDER_HEIGHT as i32 / 2,
+            0,
+            &DepthBuffer::default(),
+        );
+        let ids: Vec<usize> = context
+            .player_batch
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                SpriteBatchEntry::Sprite { sprite, .. } => sprite.id,
+                _ => panic!("expected a sprite"),
+            })
+            .collect();
+        let letter = font.glyph('A').0.id;
+        assert_eq!(ids, vec![100, letter, 101, letter]);
+    }
+}
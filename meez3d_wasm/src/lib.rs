@@ -5,6 +5,7 @@ use websoundplayer::WebSoundPlayer;
 mod websoundplayer;
 
 use std::path::Path;
+use std::time::Duration;
 
 use anyhow::{bail, Result};
 use log::{error, info};
@@ -14,8 +15,8 @@ use winit::event_loop::EventLoop;
 use winit::window::{Window, WindowBuilder};
 
 use meez3d::{
-    FileManager, Font, ImageManager, InputManager, RecordOption, RenderContext, SoundManager,
-    StageManager, WgpuRenderer, RENDER_HEIGHT, RENDER_WIDTH,
+    capture_winit_mouse, Engine, FileManager, FrameClock, ImageManager, InputManager, RecordOption,
+    SoundManager, WgpuRenderer,
 };
 
 pub const CANVAS_WIDTH: u32 = 800;
@@ -23,14 +24,29 @@ pub const CANVAS_HEIGHT: u32 = 450;
 
 const ASSETS_ARCHIVE_BYTES: &[u8] = include_bytes!("../../assets.tar.gz");
 
+/// The time since the page loaded. Browsers don't have `Instant`, so this is read from
+/// `performance.now()` instead.
+#[cfg(target_arch = "wasm32")]
+fn now() -> Duration {
+    let millis = web_sys::window()
+        .and_then(|window| window.performance())
+        .map_or(0.0, |performance| performance.now());
+    Duration::from_secs_f64(millis / 1000.0)
+}
+
+/// The time since the game started, so the crate still builds for the host.
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> Duration {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    START.get_or_init(std::time::Instant::now).elapsed()
+}
+
 struct GameState<'window> {
-    stage_manager: StageManager,
-    file_manager: FileManager,
+    engine: Engine,
     images: ImageManager<WgpuRenderer<'window, Window>>,
-    sounds: SoundManager,
     inputs: InputManager,
-    font: Font,
-    frame: u64,
+    clock: FrameClock,
+    last_time: Duration,
 }
 
 impl<'window> GameState<'window> {
@@ -43,52 +59,59 @@ impl<'window> GameState<'window> {
         )?;
         let font = images.load_font(&file_manager)?;
 
+        let PhysicalSize { width, height } = images.renderer().window().inner_size();
         let inputs = InputManager::with_options(
-            CANVAS_WIDTH as i32,
-            CANVAS_HEIGHT as i32,
+            width as i32,
+            height as i32,
             true,
             RecordOption::None,
             &file_manager,
         )?;
-        let stage_manager = StageManager::new(&file_manager, &mut images)?;
-        let sounds = WebSoundPlayer::new(&file_manager)?;
+        let sounds = WebSoundPlayer::new()?;
         let sounds = SoundManager::with_internal(Box::new(sounds));
-
-        let frame = 0;
+        let engine = Engine::new(file_manager, &mut images, font, sounds)?;
 
         Ok(Self {
-            stage_manager,
-            file_manager,
+            engine,
             images,
-            sounds,
             inputs,
-            font,
-            frame,
+            clock: FrameClock::new(),
+            last_time: now(),
         })
     }
 
+    /// Runs as many updates as are due, and renders the last one. Browsers redraw at the
+    /// display's refresh rate, which is often faster or slower than the game's.
     fn run_one_frame(&mut self) -> Result<()> {
-        let width = RENDER_WIDTH;
-        let height = RENDER_HEIGHT;
-        let mut context = RenderContext::new(width, height, self.frame)?;
-
-        let inputs = self.inputs.update(self.frame);
-        let _ = self.stage_manager.update(
-            &context,
-            &inputs,
-            &self.file_manager,
-            &mut self.images,
-            &mut self.sounds,
-        )?;
+        let now = now();
+        let frames = self.clock.advance(now.saturating_sub(self.last_time));
+        self.last_time = now;
+
+        for _ in 0..frames {
+            self.inputs.apply_cvars(self.engine.stage_manager().cvars());
+            let inputs = self.inputs.update(self.engine.frame());
+            if !self.engine.run_one_frame(&inputs, &mut self.images)? {
+                return Ok(());
+            }
+        }
 
-        self.stage_manager.draw(&mut context, &self.font);
+        let captured = self.engine.stage_manager().captures_mouse();
+        if self.inputs.set_mouse_captured(captured) {
+            capture_winit_mouse(self.images.renderer().window(), captured);
+        }
+        let typing = self.engine.stage_manager().wants_text_input();
+        if self.inputs.set_text_input(typing) {
+            // Typed text comes with key presses either way, but this lets an IME compose it.
+            self.images.renderer().window().set_ime_allowed(typing);
+        }
 
-        match self.images.renderer_mut().render(&context) {
-            Ok(_) => {}
-            Err(e) => error!("{:?}", e),
+        if frames > 0 {
+            match self.images.render(self.engine.context()) {
+                Ok(_) => {}
+                Err(e) => error!("{:?}", e),
+            }
         }
 
-        self.frame += 1;
         Ok(())
     }
 }
@@ -129,17 +152,8 @@ pub async fn run() -> Result<()> {
     let width = if width == 0 { CANVAS_WIDTH } else { width };
     let height = if height == 0 { CANVAS_HEIGHT } else { height };
 
-    let texture_atlas_path = Path::new("assets/textures.png");
     let vsync = true;
-    let renderer = WgpuRenderer::new(
-        &window,
-        width,
-        height,
-        vsync,
-        texture_atlas_path,
-        &file_manager,
-    )
-    .await?;
+    let renderer = WgpuRenderer::new(&window, width, height, vsync).await?;
     let mut game = match GameState::new(file_manager, renderer) {
         Ok(game) => game,
         Err(e) => {
@@ -157,6 +171,7 @@ pub async fn run() -> Result<()> {
                 WindowEvent::Resized(new_size) => {
                     let PhysicalSize { width, height } = new_size;
                     info!("window resized to {width}, {height}");
+                    game.images.resize(*width, *height);
                 }
                 WindowEvent::RedrawRequested => {
                     if let Err(e) = game.run_one_frame() {
@@ -170,6 +185,7 @@ pub async fn run() -> Result<()> {
                 _ => {}
             }
         }
+        Event::DeviceEvent { ref event, .. } => game.inputs.handle_winit_device_event(event),
         Event::AboutToWait => game.images.renderer().window().request_redraw(),
         _ => {}
     })?;
@@ -1,38 +1,133 @@
-use std::path::Path;
-
 use base64::prelude::*;
 
 use anyhow::{anyhow, Result};
 use log::error;
-use meez3d::{FileManager, Sound, SoundPlayer};
-use web_sys::HtmlAudioElement;
+use meez3d::{SoundClip, SoundHandle, SoundPlayer};
+use web_sys::{
+    AudioContext, BiquadFilterNode, BiquadFilterType, GainNode, HtmlAudioElement, StereoPannerNode,
+};
+
+/// The low-pass filter's cutoff while sounds are muffled, and while they aren't, in hertz.
+const MUFFLED_FREQUENCY: f32 = 600.0;
+const CLEAR_FREQUENCY: f32 = 20000.0;
+
+/// A loaded sound, and the nodes it plays through, which set its volume and pan.
+struct WebSound {
+    element: HtmlAudioElement,
+    gain: GainNode,
+    panner: StereoPannerNode,
+}
 
 pub struct WebSoundPlayer {
-    click_sound: HtmlAudioElement,
+    context: AudioContext,
+    /// Every sound plays through this on its way out, so they can all be muffled at once.
+    filter: BiquadFilterNode,
+    /// The loaded sounds, indexed by handle.
+    sounds: Vec<Option<WebSound>>,
 }
 
-fn load_image(path: &Path, files: &FileManager) -> Result<HtmlAudioElement> {
-    let bytes = files.read(path)?;
-    let base64 = BASE64_STANDARD.encode(bytes);
-    let url = format!("data:audio/wav;base64,{}", base64);
+fn load_audio(clip: &SoundClip) -> Result<HtmlAudioElement> {
+    let base64 = BASE64_STANDARD.encode(&clip.data);
+    let url = format!("data:{};base64,{}", clip.format.mime_type(), base64);
     let element = HtmlAudioElement::new_with_src(&url)
         .map_err(|e| anyhow!("error creating html audio element: {:?}", e))?;
+    element.set_loop(clip.looping);
     Ok(element)
 }
 
+/// Loads a sound, and connects it to the output filter through a gain and a panner.
+fn load_sound(
+    clip: &SoundClip,
+    context: &AudioContext,
+    filter: &BiquadFilterNode,
+) -> Result<WebSound> {
+    let element = load_audio(clip)?;
+    let source = context
+        .create_media_element_source(&element)
+        .map_err(|e| anyhow!("error creating audio source: {:?}", e))?;
+    let gain = context
+        .create_gain()
+        .map_err(|e| anyhow!("error creating gain node: {:?}", e))?;
+    let panner = context
+        .create_stereo_panner()
+        .map_err(|e| anyhow!("error creating panner node: {:?}", e))?;
+    source
+        .connect_with_audio_node(&gain)
+        .and_then(|_| gain.connect_with_audio_node(&panner))
+        .and_then(|_| panner.connect_with_audio_node(filter))
+        .map_err(|e| anyhow!("error connecting audio nodes: {:?}", e))?;
+    Ok(WebSound {
+        element,
+        gain,
+        panner,
+    })
+}
+
 impl WebSoundPlayer {
-    pub fn new(files: &FileManager) -> Result<Self> {
-        let click_sound = load_image(Path::new("assets/sounds/click.wav"), files)?;
-        Ok(Self { click_sound })
+    pub fn new() -> Result<Self> {
+        let context =
+            AudioContext::new().map_err(|e| anyhow!("error creating audio context: {:?}", e))?;
+        let filter = context
+            .create_biquad_filter()
+            .map_err(|e| anyhow!("error creating filter node: {:?}", e))?;
+        filter.set_type(BiquadFilterType::Lowpass);
+        filter.frequency().set_value(CLEAR_FREQUENCY);
+        filter
+            .connect_with_audio_node(&context.destination())
+            .map_err(|e| anyhow!("error connecting audio nodes: {:?}", e))?;
+        Ok(Self {
+            context,
+            filter,
+            sounds: Vec::new(),
+        })
     }
 }
 
 impl SoundPlayer for WebSoundPlayer {
-    fn play(&mut self, sound: Sound) {
-        if let Err(e) = match sound {
-            Sound::Click => self.click_sound.play(),
-        } {
+    fn load(&mut self, handle: SoundHandle, clip: &SoundClip) -> Result<()> {
+        let sound = load_sound(clip, &self.context, &self.filter)?;
+        if self.sounds.len() <= handle.index() {
+            self.sounds.resize_with(handle.index() + 1, || None);
+        }
+        if let Some(old) = self.sounds[handle.index()].replace(sound) {
+            if let Err(e) = old.element.pause() {
+                error!("unable to stop sound: {:?}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn play(&mut self, handle: SoundHandle, volume: f32, pan: f32) {
+        let Some(Some(sound)) = self.sounds.get(handle.index()) else {
+            return;
+        };
+        // Browsers start the context suspended until the player interacts with the page.
+        if let Err(e) = self.context.resume() {
+            error!("unable to resume audio context: {:?}", e);
+        }
+        sound.gain.gain().set_value(volume);
+        sound.panner.pan().set_value(pan.clamp(-1.0, 1.0));
+        if let Err(e) = sound.element.play() {
             error!("unable to play sound: {:?}", e);
         }
     }
+
+    fn stop(&mut self, handle: SoundHandle) {
+        let Some(Some(sound)) = self.sounds.get(handle.index()) else {
+            return;
+        };
+        if let Err(e) = sound.element.pause() {
+            error!("unable to stop sound: {:?}", e);
+        }
+        sound.element.set_current_time(0.0);
+    }
+
+    fn set_muffled(&mut self, muffled: bool) {
+        let frequency = if muffled {
+            MUFFLED_FREQUENCY
+        } else {
+            CLEAR_FREQUENCY
+        };
+        self.filter.frequency().set_value(frequency);
+    }
 }
@@ -1,142 +1,514 @@
 use std::path::Path;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
 use anyhow::{anyhow, Result};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use log::{info, warn};
 use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::video::{FullscreenType, Window, WindowPos};
+use sdl2::{AudioSubsystem, Sdl, VideoSubsystem};
 
 use meez3d::{
-    FileManager, ImageManager, InputManager, RecordOption, RenderContext, SoundManager,
-    StageManager, WgpuRenderer, FRAME_RATE, RENDER_HEIGHT, RENDER_WIDTH,
+    Benchmark, DisplaySettings, Engine, FileManager, FrameClock, ImageManager, InputManager,
+    RecordOption, Renderer, Replay, SdlRenderer, SoundManager, WgpuRenderer, WindowConfig,
+    WindowGeometry, WindowMode, BENCHMARK_SEED, DEFAULT_BENCHMARK_FRAMES, UI_SCALE_CVAR,
 };
 
 pub const WINDOW_WIDTH: u32 = 1600;
 pub const WINDOW_HEIGHT: u32 = 900;
+/// Saves the last few seconds of gameplay as a clip, while clip_seconds is set.
+const SAVE_CLIP_KEY: Keycode = Keycode::F9;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// The same as --window-mode borderless.
     #[arg(long)]
     pub fullscreen: bool,
 
+    /// How to show the window: windowed, borderless, or exclusive.
+    #[arg(long)]
+    pub window_mode: Option<WindowMode>,
+
+    /// Which monitor to show the window on, as numbered by --list-displays. Without this or a
+    /// window mode, the window is shown the way it was when the game last exited.
+    #[arg(long)]
+    pub display: Option<usize>,
+
+    /// Lists the available monitors and exits.
+    #[arg(long)]
+    pub list_displays: bool,
+
     #[arg(long)]
     pub assets: Option<String>,
+
+    /// Which renderer to draw with. The sdl renderer works without Vulkan, Metal, or DX12.
+    #[arg(long, value_enum, default_value_t = RendererOption::Wgpu)]
+    pub renderer: RendererOption,
+
+    /// Renders the HUD at 1, 2, or 4 times the game's resolution, so text stays crisp.
+    /// Only the wgpu renderer supports this.
+    #[arg(long, default_value_t = 1)]
+    pub hud_scale: u32,
+
+    /// Draws the HUD at this multiple of its normal size, from 0.25 to 4. The ui_scale cvar
+    /// can change it while the game is running. Defaults to the scale from the last run.
+    #[arg(long)]
+    pub ui_scale: Option<f32>,
+
+    /// Records a replay of the game, and saves it to this path on exit, to check with
+    /// --verify-replay.
+    #[arg(long)]
+    pub record_replay: Option<String>,
+
+    /// Plays a replay back without a window or audio, checks that every frame comes out the
+    /// same as when it was recorded, and exits.
+    #[arg(long)]
+    pub verify_replay: Option<String>,
+
+    /// Plays the Tiled map at this path as fast as it can, prints a JSON report of how long the
+    /// frames took, and exits.
+    #[arg(long)]
+    pub benchmark: Option<String>,
+
+    /// The inputs to play during --benchmark, as recorded with --record.
+    #[arg(long)]
+    pub benchmark_inputs: Option<String>,
+
+    /// How many frames --benchmark runs.
+    #[arg(long, default_value_t = DEFAULT_BENCHMARK_FRAMES)]
+    pub benchmark_frames: u64,
+
+    /// Checks the texture atlas while the game runs. On exit, prints how full it is and any
+    /// images that were missing or didn't fit, and saves a picture of what's where in it to the
+    /// PNG at this path.
+    #[arg(long)]
+    pub atlas_report: Option<String>,
 }
 
-fn run(args: Args) -> Result<()> {
-    let sdl_context = sdl2::init().expect("failed to init SDL");
-    let video_subsystem = sdl_context.video().expect("failed to get video context");
-    let audio_subsystem = sdl_context.audio().expect("failed to get audio context");
+impl Args {
+    pub fn benchmark(&self) -> Option<Benchmark> {
+        Some(Benchmark {
+            level: self.benchmark.as_ref()?.into(),
+            inputs: self.benchmark_inputs.as_ref().map(Into::into),
+            frames: self.benchmark_frames,
+        })
+    }
+
+    /// Whether the command line says how to show the window, instead of showing it like last
+    /// time.
+    pub fn has_display_settings(&self) -> bool {
+        self.fullscreen || self.window_mode.is_some() || self.display.is_some()
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum RendererOption {
+    Wgpu,
+    Sdl,
+}
 
+fn run(args: Args) -> Result<()> {
     let file_manager = match &args.assets {
         Some(path) => FileManager::from_archive_file(Path::new(path)),
         None => FileManager::from_fs(),
     }?;
 
-    let title = "flywheel";
-    let mut window = video_subsystem.window(title, WINDOW_WIDTH, WINDOW_HEIGHT);
-    if args.fullscreen {
-        window.fullscreen_desktop();
+    if let Some(path) = &args.verify_replay {
+        let replay = Replay::load(Path::new(path), &file_manager)?;
+        replay.verify(file_manager)?;
+        println!("{}: {} frames match", path, replay.hashes.len());
+        return Ok(());
     }
-    let window = window.resizable().build().expect("failed to build window");
-    let (width, height) = window.size();
+
+    let sdl_context = sdl2::init().expect("failed to init SDL");
+    let video_subsystem = sdl_context.video().expect("failed to get video context");
+    let audio_subsystem = sdl_context.audio().expect("failed to get audio context");
+
+    if args.list_displays {
+        return list_displays(&video_subsystem);
+    }
+    let settings = DisplaySettings {
+        mode: match args.window_mode {
+            Some(mode) => mode,
+            None if args.fullscreen => WindowMode::Borderless,
+            None => WindowMode::Windowed,
+        },
+        display: args.display.unwrap_or(0),
+    };
+    let restore_display = !args.has_display_settings();
+
+    // The engine gives the window its configured title and icon once it's running, but it has
+    // the default title until then, so it's never shown untitled.
+    let mut window = video_subsystem
+        .window(&WindowConfig::default().title, WINDOW_WIDTH, WINDOW_HEIGHT)
+        .resizable()
+        .allow_highdpi()
+        .build()
+        .expect("failed to build window");
+    let settings = apply_display_settings(&mut window, settings)?;
+    // On HiDPI displays, the window is measured in points, but the surface needs pixels.
+    let (width, height) = window.drawable_size();
+    info!(
+        "window is {:?}, drawing at {}x{}",
+        window.size(),
+        width,
+        height
+    );
     sdl_context.mouse().show_cursor(false);
 
-    let texture_atlas_path = Path::new("assets/textures.png");
-    let future = WgpuRenderer::new(
-        &window,
+    // A handle to the same window, for changing its mode after the renderer has borrowed it.
+    let game_window = window.clone();
+
+    let from_disk = args.assets.is_none();
+    info!("using {:?} renderer", args.renderer);
+    match args.renderer {
+        RendererOption::Wgpu => {
+            let future = WgpuRenderer::new(&window, width, height, false);
+            let mut renderer = pollster::block_on(future)?;
+            renderer.set_hud_scale(args.hud_scale)?;
+            let image_manager = ImageManager::new(renderer)?;
+            run_game(
+                image_manager,
+                game_window,
+                settings,
+                restore_display,
+                args.ui_scale,
+                &sdl_context,
+                &audio_subsystem,
+                file_manager,
+                from_disk,
+                args.benchmark(),
+                args.atlas_report.as_deref(),
+                args.record_replay.as_deref(),
+            )
+        }
+        RendererOption::Sdl => {
+            if args.hud_scale != 1 {
+                warn!("the sdl renderer ignores --hud-scale");
+            }
+            let canvas = window
+                .into_canvas()
+                .build()
+                .map_err(|e| anyhow!("unable to create canvas: {}", e))?;
+            let texture_creator = canvas.texture_creator();
+            let renderer = SdlRenderer::new(canvas, &texture_creator)?;
+            let image_manager = ImageManager::new(renderer)?;
+            run_game(
+                image_manager,
+                game_window,
+                settings,
+                restore_display,
+                args.ui_scale,
+                &sdl_context,
+                &audio_subsystem,
+                file_manager,
+                from_disk,
+                args.benchmark(),
+                args.atlas_report.as_deref(),
+                args.record_replay.as_deref(),
+            )
+        }
+    }
+}
+
+fn list_displays(video_subsystem: &VideoSubsystem) -> Result<()> {
+    let count = video_subsystem
+        .num_video_displays()
+        .map_err(|e| anyhow!("unable to count displays: {}", e))?;
+    for i in 0..count {
+        let name = video_subsystem
+            .display_name(i)
+            .unwrap_or_else(|_| "unknown".to_string());
+        let bounds = video_subsystem
+            .display_bounds(i)
+            .map_err(|e| anyhow!("unable to get bounds of display {}: {}", i, e))?;
+        println!(
+            "{}: {} ({}x{} at {}, {})",
+            i,
+            name,
+            bounds.width(),
+            bounds.height(),
+            bounds.x(),
+            bounds.y()
+        );
+    }
+    Ok(())
+}
+
+/// Moves the window to the chosen display and switches it to the chosen mode.
+///
+/// Returns the settings that were actually applied, which fall back to the first display if the
+/// chosen one doesn't exist.
+fn apply_display_settings(
+    window: &mut Window,
+    settings: DisplaySettings,
+) -> Result<DisplaySettings> {
+    let video_subsystem = window.subsystem().clone();
+    let count = video_subsystem
+        .num_video_displays()
+        .map_err(|e| anyhow!("unable to count displays: {}", e))?;
+    let mut settings = settings;
+    if settings.display >= count as usize {
+        warn!(
+            "there is no display {}, so using display 0 instead",
+            settings.display
+        );
+        settings.display = 0;
+    }
+    let display = settings.display as i32;
+    info!("showing window {} on display {}", settings.mode, display);
+
+    // SDL only moves windows that aren't fullscreen.
+    window
+        .set_fullscreen(FullscreenType::Off)
+        .map_err(|e| anyhow!("unable to leave fullscreen: {}", e))?;
+    let bounds = video_subsystem
+        .display_bounds(display)
+        .map_err(|e| anyhow!("unable to get bounds of display {}: {}", display, e))?;
+    let (width, height) = window.size();
+    window.set_position(
+        WindowPos::Positioned(bounds.x() + (bounds.width() as i32 - width as i32) / 2),
+        WindowPos::Positioned(bounds.y() + (bounds.height() as i32 - height as i32) / 2),
+    );
+
+    match settings.mode {
+        WindowMode::Windowed => {}
+        WindowMode::Borderless => window
+            .set_fullscreen(FullscreenType::Desktop)
+            .map_err(|e| anyhow!("unable to go fullscreen: {}", e))?,
+        WindowMode::Exclusive => {
+            let mode = video_subsystem
+                .desktop_display_mode(display)
+                .map_err(|e| anyhow!("unable to get mode of display {}: {}", display, e))?;
+            window
+                .set_display_mode(mode)
+                .map_err(|e| anyhow!("unable to set display mode: {}", e))?;
+            window
+                .set_fullscreen(FullscreenType::True)
+                .map_err(|e| anyhow!("unable to go fullscreen: {}", e))?;
+        }
+    }
+    Ok(settings)
+}
+
+/// Where the window is and how big it is, in points.
+fn window_geometry(window: &Window) -> WindowGeometry {
+    let (x, y) = window.position();
+    let (width, height) = window.size();
+    WindowGeometry {
+        x,
+        y,
         width,
         height,
-        false,
-        texture_atlas_path,
-        &file_manager,
-    );
-    let renderer = pollster::block_on(future)?;
+    }
+}
 
-    let mut image_manager: ImageManager<WgpuRenderer<'_, sdl2::video::Window>> =
-        ImageManager::new(renderer)?;
-    image_manager.load_texture_atlas(
-        Path::new("assets/textures.png"),
-        Path::new("assets/textures_index.txt"),
-        &file_manager,
-    )?;
+/// Runs the game until it exits or the window is closed, and then saves the session.
+///
+/// The window is a handle to the one the renderer draws to, so its mode can be changed. Unless
+/// restore_display is false, it's shown the way it was at the end of the last session. With a
+/// benchmark, it runs that instead, and prints the report. With an atlas report path, it runs
+/// with atlas diagnostics on, and saves the report when it exits. With a replay path, it records
+/// a replay, and saves it when it exits.
+#[allow(clippy::too_many_arguments)]
+fn run_game<T: Renderer>(
+    mut image_manager: ImageManager<T>,
+    mut window: Window,
+    mut settings: DisplaySettings,
+    restore_display: bool,
+    ui_scale: Option<f32>,
+    sdl_context: &Sdl,
+    audio_subsystem: &AudioSubsystem,
+    file_manager: FileManager,
+    from_disk: bool,
+    benchmark: Option<Benchmark>,
+    atlas_report: Option<&str>,
+    record_replay: Option<&str>,
+) -> Result<()> {
+    image_manager.set_atlas_diagnostics(atlas_report.is_some());
+    if from_disk {
+        // Art read straight from the assets directory is packed as it's loaded, and reloaded as
+        // it changes.
+        image_manager.pack_texture_atlas(Path::new("assets"), &file_manager)?;
+        image_manager.set_hot_reload(true);
+    } else {
+        image_manager.load_texture_atlas(
+            Path::new("assets/textures.png"),
+            Path::new("assets/textures_index.txt"),
+            &file_manager,
+        )?;
+    }
     let font = image_manager.load_font(&file_manager)?;
 
+    // The window size is in points, like mouse events, while the renderer needs pixels.
+    let (width, height) = window.size();
     let mut input_manager = InputManager::with_options(
-        WINDOW_WIDTH as i32,
-        WINDOW_HEIGHT as i32,
+        width as i32,
+        height as i32,
         true,
         RecordOption::None,
         &file_manager,
     )?;
 
-    let mut stage_manager = StageManager::new(&file_manager, &mut image_manager)?;
-    let mut sound_manager = SoundManager::with_sdl(&audio_subsystem)?;
+    let mut engine = match &benchmark {
+        Some(_) => {
+            let sound_manager = SoundManager::noop_manager();
+            let images = &mut image_manager;
+            Engine::with_seed(file_manager, images, font, sound_manager, BENCHMARK_SEED)?
+        }
+        None => {
+            let sound_manager = SoundManager::with_sdl(audio_subsystem)?;
+            Engine::new(file_manager, &mut image_manager, font, sound_manager)?
+        }
+    };
+    engine.configure_sdl_window(&mut window)?;
+    if record_replay.is_some() {
+        engine.record_replay()?;
+    }
+    let cvars = engine.stage_manager_mut().cvars_mut();
+    if restore_display {
+        let restored = settings.with_cvars(cvars);
+        if restored != settings {
+            settings = apply_display_settings(&mut window, restored)?;
+        }
+    }
+    settings.write_cvars(cvars);
+    if let Some(ui_scale) = ui_scale {
+        cvars.set(UI_SCALE_CVAR, &ui_scale.to_string());
+    }
+    if let Some(benchmark) = &benchmark {
+        let report = engine.run_benchmark(&mut image_manager, benchmark)?;
+        println!("{}", report.to_json()?);
+        return Ok(());
+    }
+    if let Some(geometry) = engine.last_window() {
+        if restore_display && settings.mode == WindowMode::Windowed {
+            window
+                .set_size(geometry.width, geometry.height)
+                .map_err(|e| anyhow!("unable to restore window size: {}", e))?;
+            window.set_position(
+                WindowPos::Positioned(geometry.x),
+                WindowPos::Positioned(geometry.y),
+            );
+            let (width, height) = window.drawable_size();
+            image_manager.resize(width, height);
+            let (width, height) = window.size();
+            input_manager.set_window_size(width as i32, height as i32);
+        }
+    }
+    let window_id = window.id();
     let mut event_pump = sdl_context.event_pump().unwrap();
+    // SDL starts out typing text, but the game only wants it while there's a text box.
+    let text_input = sdl_context
+        .video()
+        .map_err(|e| anyhow!("unable to get video context: {}", e))?
+        .text_input();
+    text_input.stop();
 
-    let mut frame = 0;
     let speed_test_start_time: Instant = Instant::now();
+    let mut clock = FrameClock::new();
+    let mut last_time = Instant::now();
 
     'running: loop {
-        let start_time = Instant::now();
-
-        let width = RENDER_WIDTH;
-        let height = RENDER_HEIGHT;
-        let mut context = RenderContext::new(width, height, frame)?;
-
+        let mut save_clip = false;
         for event in event_pump.poll_iter() {
             input_manager.handle_sdl_event(&event);
             match event {
                 Event::Quit { .. } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(SAVE_CLIP_KEY),
+                    repeat: false,
+                    ..
+                } => save_clip = true,
                 Event::Window {
-                    win_event: WindowEvent::SizeChanged(new_width, new_height),
-                    window_id,
+                    win_event: WindowEvent::SizeChanged(..),
+                    window_id: event_window_id,
                     ..
-                } if window_id == window.id() => {
-                    image_manager
-                        .renderer_mut()
-                        .resize(new_width as u32, new_height as u32);
+                } if event_window_id == window_id => {
+                    let (width, height) = window.drawable_size();
+                    image_manager.resize(width, height);
                 }
                 _ => {}
             }
         }
 
-        let input_snapshot = input_manager.update(frame);
+        // After a slow frame, run the missed updates, but only render the last one.
+        let now = Instant::now();
+        let frames = clock.advance(now - last_time);
+        last_time = now;
+        // Reloaded before the frame is drawn, so its sprites are resolved against the new atlas.
+        if frames > 0 {
+            image_manager.reload_changed_atlas(engine.files());
+        }
+        for _ in 0..frames {
+            input_manager.apply_cvars(engine.stage_manager().cvars());
+            let input_snapshot = input_manager.update(engine.frame());
+            if !engine.run_one_frame(&input_snapshot, &mut image_manager)? {
+                break 'running;
+            }
+        }
+        let captured = engine.stage_manager().captures_mouse();
+        if input_manager.set_mouse_captured(captured) {
+            // Relative mode hides the cursor and reports motion even at the edge of the window.
+            sdl_context.mouse().set_relative_mouse_mode(captured);
+        }
+        let typing = engine.stage_manager().wants_text_input();
+        if input_manager.set_text_input(typing) {
+            if typing {
+                text_input.start();
+            } else {
+                text_input.stop();
+            }
+        }
 
-        if !stage_manager.update(
-            &context,
-            &input_snapshot,
-            &file_manager,
-            &mut image_manager,
-            &mut sound_manager,
-        )? {
-            break 'running;
+        if frames > 0 {
+            image_manager
+                .render(engine.context())
+                .map_err(|e| anyhow!("rendering error: {}", e))?;
+            engine.record_clip(image_manager.renderer_mut(), save_clip);
         }
 
-        context.clear();
-        stage_manager.draw(&mut context, &font);
-        image_manager
-            .renderer_mut()
-            .render(&context)
-            .map_err(|e| anyhow!("rendering error: {}", e))?;
-
-        frame += 1;
-        let target_duration = Duration::new(0, 1_000_000_000u32 / FRAME_RATE);
-        let actual_duration = start_time.elapsed();
-        if actual_duration > target_duration {
-            continue;
+        let wanted = settings.with_cvars(engine.stage_manager().cvars());
+        if wanted != settings {
+            settings = apply_display_settings(&mut window, wanted)?;
+            settings.write_cvars(engine.stage_manager_mut().cvars_mut());
+            // Don't wait for the resize event, since the next frame is drawn before it arrives.
+            let (width, height) = window.drawable_size();
+            image_manager.resize(width, height);
+            let (width, height) = window.size();
+            input_manager.set_window_size(width as i32, height as i32);
         }
-        let remaining = target_duration - actual_duration;
-        ::std::thread::sleep(remaining);
+
+        ::std::thread::sleep(clock.time_until_next().saturating_sub(last_time.elapsed()));
+    }
+
+    // A fullscreen window's size is the display's, so the last windowed size is kept instead.
+    let geometry = (settings.mode == WindowMode::Windowed).then(|| window_geometry(&window));
+    engine.save_session(geometry);
+    if let (Some(path), Some(replay)) = (record_replay, engine.replay()) {
+        replay.save(Path::new(path))?;
+        info!("saved {} frame replay to {:?}", replay.hashes.len(), path);
+    }
+    if let Some(path) = atlas_report {
+        save_atlas_report(&image_manager, Path::new(path))?;
     }
 
     let speed_test_end_time = Instant::now();
     let speed_test_duration = speed_test_end_time - speed_test_start_time;
-    let fps = frame as f64 / speed_test_duration.as_secs_f64();
+    let fps = engine.frame() as f64 / speed_test_duration.as_secs_f64();
 
     Ok(())
 }
 
+/// Prints the atlas report, and saves its picture of the atlas.
+fn save_atlas_report<T: Renderer>(image_manager: &ImageManager<T>, path: &Path) -> Result<()> {
+    let report = image_manager.atlas_report();
+    print!("{}", report);
+    report.save_image(path, &FileManager::from_fs()?)
+}
+
 fn main() {
     env_logger::init();
     let args = Args::parse();
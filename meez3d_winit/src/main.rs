@@ -3,27 +3,44 @@ use std::time::Instant;
 
 use anyhow::{bail, Result};
 use clap::Parser;
-use log::{error, info};
-use winit::dpi::{LogicalPosition, PhysicalSize, Position};
-use winit::event::{Event, WindowEvent};
+use log::{error, info, warn};
+use winit::dpi::{LogicalPosition, PhysicalPosition, PhysicalSize, Position};
+use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::{Window, WindowBuilder};
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::window::{Fullscreen, Window, WindowBuilder};
 
 use meez3d::{
-    FileManager, Font, ImageManager, InputManager, RecordOption, RenderContext, SoundManager,
-    StageManager, WgpuRenderer, RENDER_HEIGHT, RENDER_WIDTH,
+    capture_winit_mouse, Benchmark, DisplaySettings, Engine, FileManager, FrameClock, ImageManager,
+    InputManager, PerfCapture, RecordOption, Replay, SoundManager, WgpuRenderer, WindowConfig,
+    WindowGeometry, WindowMode, BENCHMARK_SEED, DEFAULT_BENCHMARK_FRAMES, UI_SCALE_CVAR,
 };
 
 pub const WINDOW_WIDTH: u32 = 1600;
 pub const WINDOW_HEIGHT: u32 = 1000;
+/// Saves the last few seconds of gameplay as a clip, while clip_seconds is set.
+const SAVE_CLIP_KEY: KeyCode = KeyCode::F9;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    // TODO: Use this or lose this.
+    /// The same as --window-mode borderless.
     #[arg(long)]
     pub fullscreen: bool,
 
+    /// How to show the window: windowed, borderless, or exclusive.
+    #[arg(long)]
+    pub window_mode: Option<WindowMode>,
+
+    /// Which monitor to show the window on, as numbered by --list-displays. Without this or a
+    /// window mode, the window is shown the way it was when the game last exited.
+    #[arg(long)]
+    pub display: Option<usize>,
+
+    /// Lists the available monitors and exits.
+    #[arg(long)]
+    pub list_displays: bool,
+
     #[arg(long)]
     pub record: Option<String>,
 
@@ -32,6 +49,53 @@ pub struct Args {
 
     #[arg(long)]
     pub speed_test: bool,
+
+    /// Renders the HUD at 1, 2, or 4 times the game's resolution, so text stays crisp.
+    #[arg(long, default_value_t = 1)]
+    pub hud_scale: u32,
+
+    /// Draws the HUD at this multiple of its normal size, from 0.25 to 4. The ui_scale cvar
+    /// can change it while the game is running. Defaults to the scale from the last run.
+    #[arg(long)]
+    pub ui_scale: Option<f32>,
+
+    /// Address to serve live game state on, like 127.0.0.1:7878.
+    #[cfg(feature = "debug_server")]
+    #[arg(long)]
+    pub debug_server: Option<String>,
+
+    /// Directory to write perf captures to, once the perf_capture cvar starts one.
+    #[arg(long)]
+    pub perf_captures: Option<String>,
+
+    /// Plays the Tiled map at this path as fast as it can, prints a JSON report of how long the
+    /// frames took, and exits.
+    #[arg(long)]
+    pub benchmark: Option<String>,
+
+    /// The inputs to play during --benchmark, as recorded with --record.
+    #[arg(long)]
+    pub benchmark_inputs: Option<String>,
+
+    /// How many frames --benchmark runs.
+    #[arg(long, default_value_t = DEFAULT_BENCHMARK_FRAMES)]
+    pub benchmark_frames: u64,
+
+    /// Checks the texture atlas while the game runs. On exit, prints how full it is and any
+    /// images that were missing or didn't fit, and saves a picture of what's where in it to the
+    /// PNG at this path.
+    #[arg(long)]
+    pub atlas_report: Option<String>,
+
+    /// Records a replay of the game, and saves it to this path on exit, to check with
+    /// --verify-replay.
+    #[arg(long)]
+    pub record_replay: Option<String>,
+
+    /// Plays a replay back without a window or audio, checks that every frame comes out the
+    /// same as when it was recorded, and exits.
+    #[arg(long)]
+    pub verify_replay: Option<String>,
 }
 
 impl Args {
@@ -47,18 +111,102 @@ impl Args {
             RecordOption::None
         })
     }
+
+    pub fn benchmark(&self) -> Option<Benchmark> {
+        Some(Benchmark {
+            level: self.benchmark.as_ref()?.into(),
+            inputs: self.benchmark_inputs.as_ref().map(Into::into),
+            frames: self.benchmark_frames,
+        })
+    }
+
+    pub fn display_settings(&self) -> DisplaySettings {
+        DisplaySettings {
+            mode: match self.window_mode {
+                Some(mode) => mode,
+                None if self.fullscreen => WindowMode::Borderless,
+                None => WindowMode::Windowed,
+            },
+            display: self.display.unwrap_or(0),
+        }
+    }
+
+    /// Whether the command line says how to show the window, instead of showing it like last
+    /// time.
+    pub fn has_display_settings(&self) -> bool {
+        self.fullscreen || self.window_mode.is_some() || self.display.is_some()
+    }
+}
+
+/// Moves the window to the chosen monitor and switches it to the chosen mode.
+///
+/// Returns the settings that were actually applied, which fall back to the first monitor if the
+/// chosen one doesn't exist. The renderer and inputs catch up when the window is resized.
+fn apply_display_settings(window: &Window, settings: DisplaySettings) -> DisplaySettings {
+    let mut settings = settings;
+    let monitors: Vec<_> = window.available_monitors().collect();
+    if settings.display >= monitors.len() {
+        warn!(
+            "there is no display {}, so using display 0 instead",
+            settings.display
+        );
+        settings.display = 0;
+    }
+    let Some(monitor) = monitors.get(settings.display).cloned() else {
+        warn!("there are no displays to show the window on");
+        return settings;
+    };
+    info!(
+        "showing window {} on display {}",
+        settings.mode, settings.display
+    );
+
+    match settings.mode {
+        WindowMode::Windowed => {
+            window.set_fullscreen(None);
+            let PhysicalPosition { x, y } = monitor.position();
+            let PhysicalSize { width, height } = monitor.size();
+            let size = window.outer_size();
+            window.set_outer_position(PhysicalPosition::new(
+                x + (width as i32 - size.width as i32) / 2,
+                y + (height as i32 - size.height as i32) / 2,
+            ));
+        }
+        WindowMode::Borderless => {
+            window.set_fullscreen(Some(Fullscreen::Borderless(Some(monitor))))
+        }
+        WindowMode::Exclusive => {
+            let mode = monitor.video_modes().max_by_key(|mode| {
+                let size = mode.size();
+                (size.width * size.height, mode.refresh_rate_millihertz())
+            });
+            match mode {
+                Some(mode) => window.set_fullscreen(Some(Fullscreen::Exclusive(mode))),
+                None => {
+                    warn!("display {} has no video modes", settings.display);
+                    window.set_fullscreen(Some(Fullscreen::Borderless(Some(monitor))));
+                }
+            }
+        }
+    }
+    settings
 }
 
 struct GameState<'window> {
-    stage_manager: StageManager,
-    file_manager: FileManager,
+    engine: Engine,
     images: ImageManager<WgpuRenderer<'window, Window>>,
-    sounds: SoundManager,
     inputs: InputManager,
-    font: Font,
-    frame: u64,
     start_time: Instant,
     speed_test: bool,
+    display_settings: DisplaySettings,
+    clock: FrameClock,
+    last_time: Instant,
+    /// Whether the clip hotkey was pressed since the last frame was rendered.
+    save_clip: bool,
+    /// Where to save the atlas report on exit, if atlas diagnostics are on.
+    atlas_report: Option<String>,
+    /// Where to save the replay on exit, if one is being recorded.
+    record_replay: Option<String>,
 }
 
 impl<'window> GameState<'window> {
@@ -68,85 +216,215 @@ impl<'window> GameState<'window> {
         renderer: WgpuRenderer<'window, Window>,
     ) -> Result<Self> {
         let mut images = ImageManager::new(renderer)?;
-        images.load_texture_atlas(
-            Path::new("assets/textures.png"),
-            Path::new("assets/textures_index.txt"),
-            &file_manager,
-        )?;
+        images.set_atlas_diagnostics(args.atlas_report.is_some());
+        // The assets are read straight from disk, so art is packed as it's loaded, and reloaded
+        // as it changes.
+        images.pack_texture_atlas(Path::new("assets"), &file_manager)?;
+        images.set_hot_reload(true);
         let font = images.load_font(&file_manager)?;
 
+        // Both the mouse and resize events are in physical pixels, so the input size is too.
+        let PhysicalSize { width, height } = images.renderer().window().inner_size();
         let inputs = InputManager::with_options(
-            WINDOW_WIDTH as i32,
-            WINDOW_HEIGHT as i32,
+            width as i32,
+            height as i32,
             true,
             args.record_option()?,
             &file_manager,
         )?;
 
-        let stage_manager = StageManager::new(&file_manager, &mut images)?;
         let sounds = SoundManager::noop_manager();
+        let mut engine = if args.benchmark.is_some() {
+            Engine::with_seed(file_manager, &mut images, font, sounds, BENCHMARK_SEED)?
+        } else {
+            Engine::new(file_manager, &mut images, font, sounds)?
+        };
+        if args.record_replay.is_some() {
+            engine.record_replay()?;
+        }
+
+        if let Some(dir) = &args.perf_captures {
+            let files = FileManager::from_fs()?;
+            engine.add_plugin(Box::new(PerfCapture::new(files, Path::new(dir))));
+        }
+
+        #[cfg(feature = "debug_server")]
+        if let Some(addr) = &args.debug_server {
+            engine.add_plugin(Box::new(meez3d::DebugServer::start(addr)?));
+        }
+
+        // Benchmarks start from the same window every time, like they do from the same seed.
+        let restore_display = args.benchmark.is_none() && !args.has_display_settings();
+        let mut display_settings = args.display_settings();
+        if restore_display {
+            display_settings = display_settings.with_cvars(engine.stage_manager().cvars());
+        }
+        let window = images.renderer().window();
+        engine.configure_winit_window(window)?;
+        let display_settings = apply_display_settings(window, display_settings);
+        if let Some(geometry) = engine.last_window() {
+            if restore_display && display_settings.mode == WindowMode::Windowed {
+                // The renderer and inputs catch up when the window is resized.
+                let _ =
+                    window.request_inner_size(PhysicalSize::new(geometry.width, geometry.height));
+                window.set_outer_position(PhysicalPosition::new(geometry.x, geometry.y));
+            }
+        }
+        let cvars = engine.stage_manager_mut().cvars_mut();
+        display_settings.write_cvars(cvars);
+        if let Some(ui_scale) = args.ui_scale {
+            cvars.set(UI_SCALE_CVAR, &ui_scale.to_string());
+        }
 
-        let frame = 0;
         let start_time = Instant::now();
         let speed_test = args.speed_test;
 
         Ok(Self {
-            stage_manager,
-            file_manager,
+            engine,
             images,
-            sounds,
             inputs,
-            font,
-            frame,
             start_time,
             speed_test,
+            display_settings,
+            clock: FrameClock::new(),
+            last_time: Instant::now(),
+            save_clip: false,
+            atlas_report: args.atlas_report,
+            record_replay: args.record_replay,
         })
     }
 
+    /// Runs as many updates as are due, and renders the last one.
     fn run_one_frame(&mut self) -> Result<bool> {
-        if self.frame == 0 {
-            self.start_time = Instant::now();
-        }
-
-        let width = RENDER_WIDTH;
-        let height = RENDER_HEIGHT;
-        let mut context = RenderContext::new(width, height, self.frame)?;
-
-        let inputs = self.inputs.update(self.frame);
-        if !self.stage_manager.update(
-            &context,
-            &inputs,
-            &self.file_manager,
-            &mut self.images,
-            &mut self.sounds,
-        )? {
-            let finish_time = Instant::now();
-            if self.speed_test {
-                let elapsed = finish_time - self.start_time;
-                let fps = self.frame as f64 / elapsed.as_secs_f64();
-                println!("{} fps: {} frames in {:?}", fps, self.frame, elapsed);
+        let now = Instant::now();
+        // The speed test runs every frame as fast as it can, instead of keeping time.
+        let frames = if self.speed_test {
+            1
+        } else {
+            self.clock.advance(now - self.last_time)
+        };
+        self.last_time = now;
+
+        // Reloaded before the frame is drawn, so its sprites are resolved against the new atlas.
+        if frames > 0 {
+            self.images.reload_changed_atlas(self.engine.files());
+        }
+        for _ in 0..frames {
+            let frame = self.engine.frame();
+            if frame == 0 {
+                self.start_time = Instant::now();
             }
-            return Ok(false);
+
+            self.inputs.apply_cvars(self.engine.stage_manager().cvars());
+            let inputs = self.inputs.update(frame);
+            if !self.engine.run_one_frame(&inputs, &mut self.images)? {
+                let finish_time = Instant::now();
+                if self.speed_test {
+                    let elapsed = finish_time - self.start_time;
+                    let fps = frame as f64 / elapsed.as_secs_f64();
+                    println!("{} fps: {} frames in {:?}", fps, frame, elapsed);
+                }
+                return Ok(false);
+            }
+        }
+
+        let captured = self.engine.stage_manager().captures_mouse();
+        if self.inputs.set_mouse_captured(captured) {
+            capture_winit_mouse(self.images.renderer().window(), captured);
+        }
+        let typing = self.engine.stage_manager().wants_text_input();
+        if self.inputs.set_text_input(typing) {
+            // Typed text comes with key presses either way, but this lets an IME compose it.
+            self.images.renderer().window().set_ime_allowed(typing);
         }
 
-        self.stage_manager.draw(&mut context, &self.font);
+        if frames > 0 {
+            match self.images.render(self.engine.context()) {
+                Ok(_) => {}
+                Err(e) => error!("{:?}", e),
+            }
+            let save_clip = std::mem::take(&mut self.save_clip);
+            self.engine
+                .record_clip(self.images.renderer_mut(), save_clip);
+        }
 
-        match self.images.renderer_mut().render(&context) {
-            Ok(_) => {}
-            Err(e) => error!("{:?}", e),
+        let wanted = self
+            .display_settings
+            .with_cvars(self.engine.stage_manager().cvars());
+        if wanted != self.display_settings {
+            let window = self.images.renderer().window();
+            self.display_settings = apply_display_settings(window, wanted);
+            self.display_settings
+                .write_cvars(self.engine.stage_manager_mut().cvars_mut());
         }
 
-        self.frame += 1;
         Ok(true)
     }
+
+    /// Saves the settings, and where the window is if it isn't fullscreen, for the next run.
+    fn save_session(&mut self) {
+        let window = self.images.renderer().window();
+        let geometry = match (self.display_settings.mode, window.outer_position()) {
+            (WindowMode::Windowed, Ok(PhysicalPosition { x, y })) => {
+                let PhysicalSize { width, height } = window.inner_size();
+                Some(WindowGeometry {
+                    x,
+                    y,
+                    width,
+                    height,
+                })
+            }
+            _ => None,
+        };
+        self.engine.save_session(geometry);
+    }
+
+    /// Prints the atlas report, and saves its picture of the atlas, if one was asked for.
+    fn save_atlas_report(&self) -> Result<()> {
+        let Some(path) = &self.atlas_report else {
+            return Ok(());
+        };
+        let report = self.images.atlas_report();
+        print!("{}", report);
+        report.save_image(Path::new(path), &FileManager::from_fs()?)
+    }
+
+    /// Saves the replay, if one is being recorded.
+    fn save_replay(&self) -> Result<()> {
+        let (Some(path), Some(replay)) = (&self.record_replay, self.engine.replay()) else {
+            return Ok(());
+        };
+        replay.save(Path::new(path))?;
+        info!("saved {} frame replay to {:?}", replay.hashes.len(), path);
+        Ok(())
+    }
 }
 
 pub async fn run(args: Args) -> Result<()> {
     let event_loop = EventLoop::new()?;
 
+    if args.list_displays {
+        for (i, monitor) in event_loop.available_monitors().enumerate() {
+            let name = monitor.name().unwrap_or_else(|| "unknown".to_string());
+            let PhysicalSize { width, height } = monitor.size();
+            let PhysicalPosition { x, y } = monitor.position();
+            println!("{}: {} ({}x{} at {}, {})", i, name, width, height, x, y);
+        }
+        return Ok(());
+    }
+
     let file_manager = FileManager::from_fs()?;
 
+    if let Some(path) = &args.verify_replay {
+        let replay = Replay::load(Path::new(path), &file_manager)?;
+        replay.verify(file_manager)?;
+        println!("{}: {} frames match", path, replay.hashes.len());
+        return Ok(());
+    }
+
+    // The engine applies the configured title once it's running.
     let window = WindowBuilder::new()
+        .with_title(WindowConfig::default().title)
         .with_position(Position::Logical(LogicalPosition::new(100.0, 100.0)))
         .build(&event_loop)
         .unwrap();
@@ -154,19 +432,11 @@ pub async fn run(args: Args) -> Result<()> {
     let PhysicalSize { width, height } = window.inner_size();
     let width = if width == 0 { WINDOW_WIDTH } else { width };
     let height = if height == 0 { WINDOW_HEIGHT } else { height };
-    window.set_cursor_visible(false);
-
-    let texture_atlas_path = Path::new("assets/textures.png");
-    let vsync = !args.speed_test;
-    let renderer = WgpuRenderer::new(
-        &window,
-        width,
-        height,
-        vsync,
-        texture_atlas_path,
-        &file_manager,
-    )
-    .await?;
+
+    let vsync = !args.speed_test && args.benchmark.is_none();
+    let benchmark = args.benchmark();
+    let mut renderer = WgpuRenderer::new(&window, width, height, vsync).await?;
+    renderer.set_hud_scale(args.hud_scale)?;
     let mut game = match GameState::new(args, file_manager, renderer) {
         Ok(game) => game,
         Err(e) => {
@@ -174,6 +444,12 @@ pub async fn run(args: Args) -> Result<()> {
         }
     };
 
+    if let Some(benchmark) = &benchmark {
+        let report = game.engine.run_benchmark(&mut game.images, benchmark)?;
+        println!("{}", report.to_json()?);
+        return Ok(());
+    }
+
     event_loop.set_control_flow(ControlFlow::Poll);
     event_loop.run(move |event, elwt| match event {
         Event::WindowEvent {
@@ -185,7 +461,7 @@ pub async fn run(args: Args) -> Result<()> {
                 WindowEvent::Resized(new_size) => {
                     let PhysicalSize { width, height } = new_size;
                     info!("window resized to {width}, {height}");
-                    game.images.renderer_mut().resize(*width, *height);
+                    game.images.resize(*width, *height);
                 }
                 WindowEvent::RedrawRequested => match game.run_one_frame() {
                     Ok(running) => {
@@ -198,13 +474,33 @@ pub async fn run(args: Args) -> Result<()> {
                         elwt.exit();
                     }
                 },
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            physical_key: PhysicalKey::Code(SAVE_CLIP_KEY),
+                            state: ElementState::Pressed,
+                            repeat: false,
+                            ..
+                        },
+                    ..
+                } => game.save_clip = true,
                 WindowEvent::CloseRequested => {
                     elwt.exit();
                 }
                 _ => {}
             }
         }
+        Event::DeviceEvent { ref event, .. } => game.inputs.handle_winit_device_event(event),
         Event::AboutToWait => game.images.renderer().window().request_redraw(),
+        Event::LoopExiting => {
+            game.save_session();
+            if let Err(e) = game.save_atlas_report() {
+                error!("unable to save atlas report: {}", e);
+            }
+            if let Err(e) = game.save_replay() {
+                error!("unable to save replay: {}", e);
+            }
+        }
         _ => {}
     })?;
 